@@ -49,8 +49,8 @@ fn main() {
         // The input header we would like to generate
         // bindings for.
         .raw_line(format!(
-            "pub unsafe fn x265_encoder_open(params: *mut x265_param) -> *mut x265_encoder {{
-                               unsafe {{ x265_encoder_open_{}(params) }}
+            "pub unsafe fn x265_api_get(bit_depth: i32) -> *const x265_api {{
+                               unsafe {{ x265_api_get_{}(bit_depth) }}
                           }}",
             apiver
         ))
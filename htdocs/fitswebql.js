@@ -54,7 +54,7 @@ function show_directory_contents(response) {
 	$("#filesystem").append($("<table></table>")
 		.attr("id", "files")
 		.attr("class", "table table-hover")
-		.html("<thead><tr style=\"color:inherit\"><th>name</th><th>size</th><th>last modified</th></tr></thead>"));
+		.html("<thead><tr style=\"color:inherit\"><th></th><th>name</th><th>object</th><th>telescope</th><th>dimensions</th><th>spectral range</th><th>size</th><th>last modified</th></tr></thead>"));
 	//class=\"danger\" style=\"color:black\"
 
 	//contents
@@ -79,7 +79,7 @@ function show_directory_contents(response) {
 		$("#tbody").append($("<tr></tr>")
 			.css("cursor", "pointer")
 			.attr("onclick", cmd)
-			.html("<td><span class=\"glyphicon glyphicon-level-up\"></span>&nbsp;&nbsp;" + ".." + "</td><td></td><td></td>"));
+			.html("<td></td><td><span class=\"glyphicon glyphicon-level-up\"></span>&nbsp;&nbsp;" + ".." + "</td><td></td><td></td><td></td><td></td><td></td><td></td>"));
 	}
 
 	//list directories first
@@ -96,7 +96,7 @@ function show_directory_contents(response) {
 			$("#tbody").append($("<tr></tr>")
 				.css("cursor", "pointer")
 				.attr("onclick", cmd)
-				.html("<td><span class=\"glyphicon glyphicon-folder-open\"></span>&nbsp;&nbsp;" + filelist[i].name + "</td><td></td><td>" + filelist[i].last_modified + "</td>"));
+				.html("<td></td><td><span class=\"glyphicon glyphicon-folder-open\"></span>&nbsp;&nbsp;" + filelist[i].name + "</td><td></td><td></td><td></td><td></td><td></td><td>" + filelist[i].last_modified + "</td>"));
 		}
 	}
 
@@ -166,7 +166,7 @@ function show_directory_contents(response) {
 				.attr("onclick", tmp)
 				//.attr("onmouseenter", cmd)
 				.attr('title', group_str)
-				.html("<td><p href=\"" + url + "\"><span class=\"glyphicon glyphicon-open-file\"></span>&nbsp;&nbsp;" + filelist[i].name + "</p></td><td>" + numeral(filelist[i].size).format('0.0 ib') + "</td><td>" + filelist[i].last_modified + "</td>"));
+				.html("<td>" + thumbnail_html(filelist[i]) + "</td><td><p href=\"" + url + "\"><span class=\"glyphicon glyphicon-open-file\"></span>&nbsp;&nbsp;" + filelist[i].name + "</p></td>" + fits_summary_html(filelist[i].fits) + "<td>" + numeral(filelist[i].size).format('0.0 ib') + "</td><td>" + filelist[i].last_modified + "</td>"));
 		}
 	}
 
@@ -175,6 +175,42 @@ function show_directory_contents(response) {
 	$("body").css("cursor", "default");
 }
 
+function escape_html(str) {
+	return String(str).replace(/&/g, "&amp;").replace(/</g, "&lt;").replace(/>/g, "&gt;").replace(/"/g, "&quot;");
+}
+
+//a lazily loaded preview, generated by the server on first access
+function thumbnail_html(file) {
+	if (file.thumbnail == null)
+		return "";
+
+	return "<img src=\"" + escape_html(file.thumbnail) + "\" loading=\"lazy\" alt=\"\" style=\"max-width: 64px; max-height: 64px\" onerror=\"this.style.display='none'\">";
+}
+
+//the object, telescope, dimensions and spectral range columns
+function fits_summary_html(fits) {
+	if (fits == null)
+		return "<td></td><td></td><td></td><td></td>";
+
+	let dimensions = fits.width + " x " + fits.height;
+
+	if (fits.depth > 1)
+		dimensions += " x " + fits.depth;
+
+	if (fits.polarisation > 1)
+		dimensions += " x " + fits.polarisation;
+
+	let spectral = "";
+
+	if (fits.freq_start != null && fits.freq_end != null)
+		spectral = fits.freq_start.toFixed(3) + " - " + fits.freq_end.toFixed(3) + " GHz";
+
+	if (fits.LINE != "")
+		spectral += (spectral == "" ? "" : " ") + "(" + fits.LINE + ")";
+
+	return "<td>" + escape_html(fits.OBJECT) + "</td><td>" + escape_html(fits.TELESCOP) + "</td><td>" + dimensions + "</td><td>" + escape_html(spectral) + "</td>";
+}
+
 function fetch_directory(dir) {
 	$("body").css("cursor", "wait");
 
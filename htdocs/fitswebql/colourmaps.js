@@ -279,25 +279,83 @@ function apply_colourmap(image, colourmap, bytes, w, h, stride, alpha) {
 	console.log("colourmap: time taken " + (end - start).toFixed(1) + " [ms]");
 }
 
-function add_composite_channel(bytes, w, h, stride, alpha, destImageData, channel) {
+//tints: [{colour: [r, g, b] in [0, 1], weight: w}, ...], one per channel
+function composite_gains(tints) {
+	//each colour component normalised so that equally bright channels add up to at most white
+	let norm = [1, 1, 1];
+
+	for (let c = 0; c < 3; c++) {
+		let sum = 0;
+
+		for (let i = 0; i < tints.length; i++)
+			sum += tints[i].colour[c];
+
+		norm[c] = Math.max(sum, 1);
+	}
+
+	return tints.map(tint => [0, 1, 2].map(c => tint.weight * tint.colour[c] / norm[c]));
+}
+
+function add_composite_channel(bytes, w, h, stride, alpha, destImageData, channel, tints) {
 	if (destImageData == null)
 		return;
 
-	if (channel >= 3)
-		return;
+	//keep every channel so that the colours can be mixed again later on
+	if (destImageData.channels == undefined)
+		destImageData.channels = [];
 
-	let src_offset = 0;
+	let buffer = new Uint8Array(w * h);
 	let dst_offset = 0;
 
 	for (var j = 0; j < h; j++) {
 		let offset = j * stride;
 
+		for (var i = 0; i < w; i++)
+			buffer[dst_offset++] = bytes[offset++];
+	}
+
+	destImageData.channels[channel] = { bytes: buffer, alpha: alpha, w: w, h: h };
+
+	mix_composite_channels(destImageData, tints);
+}
+
+//additive mixing of all the channels received so far
+function mix_composite_channels(destImageData, tints) {
+	if (destImageData == null || destImageData.channels == undefined)
+		return;
+
+	let channels = destImageData.channels;
+	let gains = composite_gains(tints);
+	let data = destImageData.data;
+
+	let w = Math.min(destImageData.width, channels.reduce((x, c) => Math.max(x, c.w), 0));
+	let h = Math.min(destImageData.height, channels.reduce((x, c) => Math.max(x, c.h), 0));
+
+	for (var j = 0; j < h; j++) {
 		for (var i = 0; i < w; i++) {
-			let pixel = bytes[offset++];
+			let r = 0, g = 0, b = 0, a = 0;
+
+			for (let k = 0; k < channels.length; k++) {
+				let c = channels[k];
+
+				if (c == undefined || k >= gains.length || i >= c.w || j >= c.h)
+					continue;
+
+				let src_offset = j * c.w + i;
+				let pixel = c.bytes[src_offset];
+
+				r += gains[k][0] * pixel;
+				g += gains[k][1] * pixel;
+				b += gains[k][2] * pixel;
+				a = Math.max(a, c.alpha[src_offset]);
+			}
 
-			destImageData.data[dst_offset + channel] = pixel;
-			destImageData.data[dst_offset + 3] = alpha[src_offset++];//the alpha channel
-			dst_offset += 4;//move to the next RGBA position
+			//a Uint8ClampedArray rounds and clamps
+			let dst_offset = 4 * (j * destImageData.width + i);
+			data[dst_offset] = r;
+			data[dst_offset + 1] = g;
+			data[dst_offset + 2] = b;
+			data[dst_offset + 3] = a;
 		}
 	}
 }
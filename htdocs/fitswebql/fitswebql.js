@@ -18,6 +18,46 @@ const wasm_supported = (() => {
 
 console.log(wasm_supported ? "WebAssembly is supported" : "WebAssembly is not supported");
 
+//AV1: Main profile, level 4.0, 8-bit, decoded natively via WebCodecs
+const AV1_CODEC_STRING = 'av01.0.08M.08';
+
+var av1_supported = false;
+
+//10-bit (Main profile) and 12-bit (Professional profile) luma
+const AV1_HBD_CODEC_STRINGS = { 10: 'av01.0.08M.10', 12: 'av01.2.08M.12' };
+
+var av1_hbd_supported = { 10: false, 12: false };
+
+//VP9 profile 2 (10/12-bit) still images and viewports, ogv.js only decodes the 8-bit profile 0
+const VP9_HBD_CODEC_STRINGS = { 10: 'vp09.02.50.10', 12: 'vp09.02.50.12' };
+
+var vp9_hbd_supported = { 10: false, 12: false };
+
+if (typeof VideoDecoder !== "undefined") {
+    VideoDecoder.isConfigSupported({ codec: AV1_CODEC_STRING }).then(function (support) {
+        av1_supported = support.supported;
+        console.log(av1_supported ? "AV1 decoding is supported" : "AV1 decoding is not supported");
+    }).catch(function (e) {
+        console.log("AV1 decoding is not supported:", e);
+    });
+
+    [10, 12].forEach(function (bits) {
+        VideoDecoder.isConfigSupported({ codec: AV1_HBD_CODEC_STRINGS[bits] }).then(function (support) {
+            av1_hbd_supported[bits] = support.supported;
+            console.log(bits + "-bit AV1 decoding is " + (support.supported ? "supported" : "not supported"));
+        }).catch(function (e) {
+            console.log(bits + "-bit AV1 decoding is not supported:", e);
+        });
+
+        VideoDecoder.isConfigSupported({ codec: VP9_HBD_CODEC_STRINGS[bits] }).then(function (support) {
+            vp9_hbd_supported[bits] = support.supported;
+            console.log(bits + "-bit VP9 decoding is " + (support.supported ? "supported" : "not supported"));
+        }).catch(function (e) {
+            console.log(bits + "-bit VP9 decoding is not supported:", e);
+        });
+    });
+}
+
 Array.prototype.rotate = function (n) {
     return this.slice(n, this.length).concat(this.slice(0, n));
 }
@@ -117,8 +157,25 @@ var ie = (function () {
 }());
 
 var colours = ["red", "green", "lightblue"];
+
+//the composite channel colours and weights [{colour: "#rrggbb", weight: w}, ...], empty for the default RGB
+var composite_channels = [];
+const COMPOSITE_MAX_CHANNELS = 16;
+const COMPOSITE_LAST_HUE = 270;
+
+//partial FITS download downsampling (1 = the native resolution)
+var cutout_binning = { spatial: 1, spectral: 1, mode: 'mean' };
+//the partial download file format: fits, hdf5, zarr or npy
+var cutout_format = 'fits';
+//the spectrum export file format: csv, votable or fits
+var spectrum_format = 'csv';
 var linedash = [[], [10, 5], [5, 5, 2, 2]];
 
+//"follow me" timings [ms]
+const FOLLOW_STATE_INTERVAL = 250;
+const FOLLOW_CURSOR_INTERVAL = 100;
+const FOLLOW_STATUS_TIMEOUT = 5000;
+
 function get_axes_range(width, height) {
     var xMin = /*0.005*width ;*/0.025 * width;
     var xMax = width - xMin - 1;
@@ -948,7 +1005,7 @@ function process_image(width, height, w, h, bytes, stride, alpha, index) {
                 compositeImageData = ctx.createImageData(compositeCanvas.width, compositeCanvas.height);
             }
 
-            add_composite_channel(bytes, w, h, stride, alpha, compositeImageData, index - 1);
+            add_composite_channel(bytes, w, h, stride, alpha, compositeImageData, index - 1, composite_tints());
         }
     }
 
@@ -1012,6 +1069,8 @@ function process_image(width, height, w, h, bytes, stride, alpha, index) {
 
         hide_hourglass();
 
+        apply_saved_view();
+
         try {
             display_cd_gridlines();
         }
@@ -1338,7 +1397,7 @@ function process_viewport(width, height, w, h, bytes, stride, alpha, index, swap
             compositeViewportImageData = ctx.createImageData(compositeViewportCanvas.width, compositeViewportCanvas.height);
         }
 
-        add_composite_channel(buffer, _w, _h, _stride, alpha, compositeViewportImageData, index - 1);
+        add_composite_channel(buffer, _w, _h, _stride, alpha, compositeViewportImageData, index - 1, composite_tints());
 
         if (viewport_count == va_count) {
             if (compositeViewportCanvas != null && compositeViewportImageData != null) {
@@ -1593,6 +1652,9 @@ function open_websocket_connection(_datasetId, index) {
                 let log = wasm_supported ? "WebAssembly is supported" : "WebAssembly is not supported";
                 ALMAWS.send('[debug] ' + log);
 
+                if (use_av1() || luma_bit_depth() > 8)
+                    ALMAWS.send(codec_request());
+
                 if (index == va_count) {
                     send_ping();
                 }
@@ -1698,7 +1760,14 @@ function open_websocket_connection(_datasetId, index) {
                         uncompressedSize = LZ4.decodeBlock(new Buffer(alpha), uncompressed);
                         alpha = uncompressed.slice(0, uncompressedSize);
 
-                        if (identifier == 'VP9') {
+                        //VP9 profile 2 (10/12-bit)
+                        if (identifier == 'VP9' && is_vp9_hbd(frames[0])) {
+                            decode_vp9_hbd_frame(frames[0], function (w, h, bytes, stride) {
+                                process_viewport(width, height, w, h, bytes, stride, alpha, index);
+                            });
+                        }
+
+                        if (identifier == 'VP9' && !is_vp9_hbd(frames[0])) {
                             var decoder = new OGVDecoderVideoVP9();
 
                             decoder.init(function () { console.log("init callback done"); });
@@ -1715,6 +1784,12 @@ function open_websocket_connection(_datasetId, index) {
                             }
                         }
 
+                        if (identifier == 'AV1') {
+                            decode_av1_frame(frames[0], function (w, h, bytes, stride) {
+                                process_viewport(width, height, w, h, bytes, stride, alpha, index);
+                            });
+                        }
+
                         if (identifier == 'HEVC') {
                             if (!composite_view) {
                                 let viewportCanvas = document.createElement('canvas');
@@ -1858,7 +1933,17 @@ function open_websocket_connection(_datasetId, index) {
                         uncompressedSize = LZ4.decodeBlock(new Buffer(alpha), uncompressed);
                         alpha = uncompressed.slice(0, uncompressedSize);
 
-                        if (identifier == 'VP9') {
+                        //VP9 profile 2 (10/12-bit)
+                        if (identifier == 'VP9' && is_vp9_hbd(frame)) {
+                            decode_vp9_hbd_frame(frame, function (w, h, bytes, stride) {
+                                process_image(width, height, w, h, bytes, stride, alpha, index);
+
+                                if (displayContours)
+                                    update_contours();
+                            });
+                        }
+
+                        if (identifier == 'VP9' && !is_vp9_hbd(frame)) {
                             var decoder = new OGVDecoderVideoVP9();
 
                             decoder.init(function () { console.log("init callback done"); });
@@ -1875,6 +1960,15 @@ function open_websocket_connection(_datasetId, index) {
                             });
                         }
 
+                        if (identifier == 'AV1') {
+                            decode_av1_frame(frame, function (w, h, bytes, stride) {
+                                process_image(width, height, w, h, bytes, stride, alpha, index);
+
+                                if (displayContours)
+                                    update_contours();
+                            });
+                        }
+
                         return;
 
                         //clear the Video Canvas
@@ -1972,8 +2066,8 @@ function open_websocket_connection(_datasetId, index) {
                         return;
                     }
 
-                    //video
-                    if (type == 5) {
+                    //video (5: HEVC/VP9, 8: AV1)
+                    if (type == 5 || type == 8) {
                         computed = dv.getFloat32(12, endianness);
 
                         var length = dv.getUint32(16, endianness);
@@ -2000,57 +2094,68 @@ function open_websocket_connection(_datasetId, index) {
                             let start = performance.now();
 
                             var len = frame.length;
-                            var ptr = Module._malloc(len);
 
-                            Module.HEAPU8.set(frame, ptr);
+                            if (type == 8) {
+                                //AV1 frames are decoded (asynchronously) by the browser
+                                decode_av1_video(frame, index, dv.getFloat32(0, endianness));
+                            } else {
+                                var ptr = Module._malloc(len);
 
-                            if (streaming && videoFrame[index - 1] != null && videoFrame[index - 1].img != null && videoFrame[index - 1].ptr != null && videoFrame[index - 1].alpha != null) {
-                                var img = videoFrame[index - 1].img;
+                                Module.HEAPU8.set(frame, ptr);
 
-                                try {
-                                    //VP9
-                                    api.vpx_decode_frame(ptr, len, videoFrame[index - 1].ptr, img.width, img.height, videoFrame[index - 1].alpha, colourmap);
-                                } catch (e) { };
+                                if (streaming && videoFrame[index - 1] != null && videoFrame[index - 1].img != null && videoFrame[index - 1].ptr != null && videoFrame[index - 1].alpha != null) {
+                                    var img = videoFrame[index - 1].img;
 
-                                try {
-                                    //HEVC
-                                    api.hevc_decode_nal_unit(index - 1, ptr, len, videoFrame[index - 1].ptr, img.width, img.height, videoFrame[index - 1].alpha, null, colourmap);
-                                } catch (e) { };
+                                    try {
+                                        //VP9
+                                        api.vpx_decode_frame(ptr, len, videoFrame[index - 1].ptr, img.width, img.height, videoFrame[index - 1].alpha, colourmap);
+                                    } catch (e) { };
+
+                                    try {
+                                        //HEVC
+                                        api.hevc_decode_nal_unit(index - 1, ptr, len, videoFrame[index - 1].ptr, img.width, img.height, videoFrame[index - 1].alpha, null, colourmap);
+                                    } catch (e) { };
+
+                                    if (img.data.length == 0) {
+                                        //detect detached data due to WASM memory growth
+                                        console.log("detached WASM buffer detected, refreshing videoFrame.ImageData");
 
-                                if (img.data.length == 0) {
-                                    //detect detached data due to WASM memory growth
-                                    console.log("detached WASM buffer detected, refreshing videoFrame.ImageData");
+                                        //WASM buffers have changed, need to refresh the ImageData.data buffer
+                                        var len = img.width * img.height * 4;
+                                        var data = new Uint8ClampedArray(Module.HEAPU8.buffer, videoFrame[index - 1].ptr, len);
+                                        var img = new ImageData(data, img.width, img.height);
 
-                                    //WASM buffers have changed, need to refresh the ImageData.data buffer
-                                    var len = img.width * img.height * 4;
-                                    var data = new Uint8ClampedArray(Module.HEAPU8.buffer, videoFrame[index - 1].ptr, len);
-                                    var img = new ImageData(data, img.width, img.height);
+                                        videoFrame[index - 1].img = img;
+                                    }
 
-                                    videoFrame[index - 1].img = img;
+                                    requestAnimationFrame(function () {
+                                        process_video(index)
+                                    });
                                 }
+                                else {
+                                    try {
+                                        //VP9
+                                        api.vpx_decode_frame(ptr, len, null, 0, 0, null, 'greyscale');
+                                    } catch (e) { };
 
-                                requestAnimationFrame(function () {
-                                    process_video(index)
-                                });
-                            }
-                            else {
-                                try {
-                                    //VP9
-                                    api.vpx_decode_frame(ptr, len, null, 0, 0, null, 'greyscale');
-                                } catch (e) { };
+                                    try {
+                                        //HEVC
+                                        api.hevc_decode_nal_unit(index - 1, ptr, len, null, 0, 0, null, null, 'greyscale');
+                                    } catch (e) { };
+                                }
 
-                                try {
-                                    //HEVC
-                                    api.hevc_decode_nal_unit(index - 1, ptr, len, null, 0, 0, null, null, 'greyscale');
-                                } catch (e) { };
+                                Module._free(ptr);
                             }
 
-                            Module._free(ptr);
-
                             let delta = performance.now() - start;
 
                             console.log('total decoding/processing/rendering time: ' + delta.toFixed() + ' [ms]');
 
+                            //acknowledge the frame (server-side adaptive bitrate control)
+                            try {
+                                wsConn[index - 1].send('[video_ack] seq_id=' + dv.getUint32(4, endianness) + '&decode=' + delta.toFixed(1));
+                            } catch (e) { };
+
                             let log = 'video frame length ' + len + ' bytes, decoding/processing/rendering time: ' + delta.toFixed() + ' [ms], bandwidth: ' + Math.round(bandwidth) + " [kbps], request latency: " + latency.toFixed() + ' [ms]';
 
                             if (video_fps_control == 'auto') {
@@ -2077,8 +2182,8 @@ function open_websocket_connection(_datasetId, index) {
                         return;
                     }
 
-                    //CSV spectrum
-                    if (type == 6) {
+                    //an exported spectrum: CSV, VOTable or FITS BINTABLE (or the region photometry CSV)
+                    if (type == 6 || type == 7 || type == 9 || type == 10) {
                         hide_hourglass();
 
                         var csv_len = dv.getUint32(12, endianness);
@@ -2092,17 +2197,39 @@ function open_websocket_connection(_datasetId, index) {
                         uncompressed = uncompressed.slice(0, uncompressedSize);
 
                         try {
-                            var csv = new TextDecoder().decode(uncompressed);
+                            var blob, extension;
+
+                            if (type == 6) {
+                                var csv = new TextDecoder().decode(uncompressed);
+
+                                // prepend the UTF-8 Byte Order Mark (BOM) 0xEF,0xBB,0xBF
+                                blob = new Blob([new Uint8Array([0xEF, 0xBB, 0xBF]), csv], { type: "data:text/csv;charset=utf-8" });
+                                extension = ".csv";
+                            }
+
+                            if (type == 7) {
+                                blob = new Blob([uncompressed], { type: "application/x-votable+xml" });
+                                extension = ".vot";
+                            }
+
+                            if (type == 9) {
+                                blob = new Blob([uncompressed], { type: "application/fits" });
+                                extension = ".fits";
+                            }
+
+                            if (type == 10) {
+                                var csv = new TextDecoder().decode(uncompressed);
 
-                            // prepend the UTF-8 Byte Order Mark (BOM) 0xEF,0xBB,0xBF
-                            var blob = new Blob([new Uint8Array([0xEF, 0xBB, 0xBF]), csv], { type: "data:text/csv;charset=utf-8" });
+                                blob = new Blob([new Uint8Array([0xEF, 0xBB, 0xBF]), csv], { type: "data:text/csv;charset=utf-8" });
+                                extension = "_photometry.csv";
+                            }
 
                             var filename;
 
                             if (va_count == 1) {
-                                filename = datasetId + ".csv";
+                                filename = datasetId + extension;
                             } else {
-                                filename = datasetId[index - 1] + ".csv";
+                                filename = datasetId[index - 1] + extension;
                             };
 
                             saveAs(blob, filename.replace('/', '_'));
@@ -2182,6 +2309,20 @@ function open_websocket_connection(_datasetId, index) {
                         if (data.type == "progress")
                             process_progress_event(data, index);
 
+                        if (data.type == "photometry")
+                            process_photometry(data);
+
+                        if (index == 1) {
+                            if (data.type == "follow")
+                                process_follow_event(data);
+
+                            if (data.type == "follow_state")
+                                apply_follow_state(data.data);
+
+                            if (data.type == "follow_cursor")
+                                display_follow_cursor(data.data);
+                        }
+
                         /*if (data.type == "image") {
                           if (data.message.indexOf("unavailable") >= 0) {
                             console.log("Server not ready, long-polling the image again after 100 ms.");
@@ -2224,14 +2365,23 @@ function open_websocket_connection(_datasetId, index) {
                                         img: img,
                                         ptr: ptr,
                                         alpha: alpha_ptr,
+                                        alpha_bytes: alpha,
                                         scaleX: imageFrame.w / width,
                                         scaleY: imageFrame.h / height,
                                         image_bounding_dims: image_bounding_dims,
                                     }
                                 }
                             }
+
+                            if (data.codec == "AV1")
+                                init_av1_video(index);
+                            else
+                                close_av1_video(index);
                         }
 
+                        if (data.type == "video_rescale")
+                            video_rescale(index, data.scale);
+
                         return;
                     }
                     catch (e) {
@@ -4537,7 +4687,18 @@ function change_spectrum_scale(index) {
 function change_tone_mapping(index, recursive) {
     var display;
 
-    if (document.getElementById('flux' + index).value == 'linear' || document.getElementById('flux' + index).value == 'log' || document.getElementById('flux' + index).value == 'square')
+    if (recursive && flux_name(document.getElementById('flux' + index).value) == 'power') {
+        var exponent = parseFloat(prompt("power-law exponent:", flux_exponent(document.getElementById('flux' + index).value)));
+
+        if (exponent > 0) {
+            for (let i = 1; i <= va_count; i++)
+                set_power_exponent(i, exponent);
+
+            document.getElementById('flux' + index).value = 'power:' + exponent;
+        }
+    }
+
+    if (flux_hides_sensitivity(document.getElementById('flux' + index).value))
         display = "none";
     else
         display = "block";
@@ -4611,6 +4772,10 @@ function image_refresh(index, refresh_histogram = true) {
     var strRequest = black + white + median + noise + flux + freq + hist;
     console.log(strRequest);
 
+    //the pyramid tiles were made with the default stretch
+    if (tile_pyramid != null)
+        tile_pyramid.valid = false;
+
     //send an [image] request to the server    
     wsConn[index - 1].send('[image]' + strRequest + '&timestamp=' + performance.now());
 }
@@ -4762,6 +4927,16 @@ function change_video_fps_control() {
     localStorage.setItem("video_fps_control", video_fps_control);
 }
 
+function change_luma_bits() {
+    luma_bits = document.getElementById('luma_bits').value;
+    localStorage.setItem("luma_bits", luma_bits);
+
+    //let the server know, new images, viewports and videos will use the new bit depth
+    for (let index = 0; index < va_count; index++)
+        if (wsConn[index].readyState == 1)
+            wsConn[index].send(codec_request());
+}
+
 function change_zoom_shape() {
     zoom_shape = document.getElementById('zoom_shape').value;
     localStorage.setItem("zoom_shape", zoom_shape);
@@ -4772,6 +4947,13 @@ function change_zoom_shape() {
     }
 }
 
+function change_spectrum_format() {
+    spectrum_format = document.getElementById('spectrum_format').value;
+    localStorage.setItem("spectrum_format", spectrum_format);
+
+    console.log("new spectrum export format:", spectrum_format);
+}
+
 function change_intensity_mode() {
     intensity_mode = document.getElementById('intensity_mode').value;
     localStorage.setItem("intensity_mode", intensity_mode);
@@ -5222,7 +5404,167 @@ function get_flux_value_legacy(value, black, white, multiplier) {
 }
 
 function get_flux_value_log(value, black, white) {
-    return black + (Math.exp(value) - 1) / (Math.E - 1) * (white - black);
+    return black + (Math.exp(value * Math.log(LOG_STRETCH_SCALE + 1)) - 1) / LOG_STRETCH_SCALE * (white - black);
+}
+
+//the stretches shared with the server (stretch.rs), value in [0, 1]
+const ASINH_SOFTENING = 0.1;
+const LOG_STRETCH_SCALE = 1000;
+const DEFAULT_POWER_EXPONENT = 1.5;
+
+function flux_name(flux) {
+    return flux.split(':')[0];
+}
+
+function flux_exponent(flux) {
+    var exponent = parseFloat(flux.split(':')[1]);
+
+    return (exponent > 0) ? exponent : DEFAULT_POWER_EXPONENT;
+}
+
+//the tone mappings without the noise sensitivity slider
+function flux_hides_sensitivity(flux) {
+    return ['linear', 'log', 'square', 'asinh', 'sqrt', 'power', 'zscale', 'histeq'].indexOf(flux_name(flux)) >= 0;
+}
+
+function stretch_curve(flux) {
+    switch (flux_name(flux)) {
+        case 'asinh':
+            return function (t) { return Math.asinh(t / ASINH_SOFTENING) / Math.asinh(1 / ASINH_SOFTENING); };
+        case 'sqrt':
+            return function (t) { return Math.sqrt(t); };
+        case 'log':
+            return function (t) { return Math.log(LOG_STRETCH_SCALE * t + 1) / Math.log(LOG_STRETCH_SCALE + 1); };
+        case 'power':
+            let exponent = flux_exponent(flux);
+            return function (t) { return Math.pow(t, exponent); };
+        default:
+            return function (t) { return t; };
+    }
+}
+
+function get_flux_value_stretch(value, flux, black, white) {
+    var t;
+
+    switch (flux_name(flux)) {
+        case 'asinh':
+            t = ASINH_SOFTENING * Math.sinh(value * Math.asinh(1 / ASINH_SOFTENING));
+            break;
+        case 'sqrt':
+            t = value * value;
+            break;
+        case 'power':
+            t = Math.pow(value, 1 / flux_exponent(flux));
+            break;
+        default:
+            t = value;
+            break;
+    }
+
+    return black + t * (white - black);
+}
+
+//the normalised cumulative image histogram, nbins + 1 knots over [min, max]
+function histogram_cdf(index) {
+    let fitsData = fitsContainer[index - 1];
+    var histogram = fitsData.histogram;
+    var total = 0;
+    var cdf = [0];
+
+    for (var i = 0; i < histogram.length; i++) {
+        total += histogram[i];
+        cdf.push(total);
+    }
+
+    return cdf.map(function (x) { return total > 0 ? x / total : 0; });
+}
+
+function get_flux_value_histeq(value, index) {
+    let fitsData = fitsContainer[index - 1];
+    var cdf = histogram_cdf(index);
+    var i = 1;
+
+    while (i < cdf.length - 1 && cdf[i] < value)
+        i++;
+
+    var frac = (cdf[i] > cdf[i - 1]) ? (value - cdf[i - 1]) / (cdf[i] - cdf[i - 1]) : 0;
+
+    return fitsData.min + (i - 1 + frac) / (cdf.length - 1) * (fitsData.max - fitsData.min);
+}
+
+//IRAF zscale limits (as on the server) from 1000 pixel values drawn from the image histogram
+function zscale_limits(index) {
+    let fitsData = fitsContainer[index - 1];
+    var nsamples = 1000;
+    var samples = [];
+
+    for (var k = 0; k < nsamples; k++)
+        samples.push(get_flux_value_histeq((k + 0.5) / nsamples, index));
+
+    var npix = samples.length;
+    var zmin = samples[0];
+    var zmax = samples[npix - 1];
+    var center = Math.floor(npix / 2);
+    var median = 0.5 * (samples[center - 1] + samples[center]);
+
+    var minpix = Math.max(5, Math.floor(0.5 * npix));
+    var ngrow = Math.max(1, Math.floor(0.01 * npix));
+    var badpix = new Array(npix).fill(false);
+    var ngoodpix = npix;
+    var last_ngoodpix = npix + 1;
+    var slope = 0;
+
+    for (var iter = 0; iter < 5; iter++) {
+        if (ngoodpix >= last_ngoodpix || ngoodpix < minpix)
+            break;
+
+        var sx = 0, sy = 0, sxx = 0, sxy = 0, n = 0;
+
+        for (var i = 0; i < npix; i++)
+            if (!badpix[i]) {
+                sx += i; sy += samples[i]; sxx += i * i; sxy += i * samples[i]; n++;
+            }
+
+        var det = n * sxx - sx * sx;
+
+        if (det == 0)
+            break;
+
+        slope = (n * sxy - sx * sy) / det;
+        var intercept = (sy - slope * sx) / n;
+
+        var flat = samples.map(function (y, i) { return y - (slope * i + intercept); });
+        var good = flat.filter(function (x, i) { return !badpix[i]; });
+        var mean = good.reduce(function (a, b) { return a + b; }, 0) / good.length;
+        var sigma = Math.sqrt(good.reduce(function (a, b) { return a + (b - mean) * (b - mean); }, 0) / good.length);
+        var threshold = 2.5 * sigma;
+
+        last_ngoodpix = ngoodpix;
+
+        for (var i = 0; i < npix; i++)
+            if (Math.abs(flat[i]) > threshold)
+                for (var j = Math.max(0, i - Math.floor(ngrow / 2)); j < Math.min(npix, i + ngrow - Math.floor(ngrow / 2)); j++)
+                    badpix[j] = true;
+
+        ngoodpix = badpix.filter(function (x) { return !x; }).length;
+    }
+
+    if (ngoodpix < minpix)
+        return [zmin, zmax];
+
+    slope /= 0.25;
+
+    return [Math.max(zmin, median - (center - 1) * slope), Math.min(zmax, median + (npix - center) * slope)];
+}
+
+function set_power_exponent(index, exponent) {
+    var select = document.getElementById('flux' + index);
+
+    for (var i = 0; i < select.options.length; i++)
+        if (flux_name(select.options[i].value) == 'power') {
+            select.options[i].value = 'power:' + exponent;
+            select.options[i].text = 'power (' + exponent + ')';
+        }
 }
 
 function get_flux_value_linear(value, black, white) {
@@ -5257,7 +5599,19 @@ function get_flux(value, flux, black, white, median, multiplier, index) {
     var min = fitsData.min;
     var max = fitsData.max;
 
-    switch (flux) {
+    switch (flux_name(flux)) {
+        case 'asinh':
+        case 'sqrt':
+        case 'power':
+            return get_flux_value_stretch(value, flux, black, white);
+            break;
+        case 'zscale':
+            let limits = zscale_limits(index);
+            return get_flux_value_linear(value, limits[0], limits[1]);
+            break;
+        case 'histeq':
+            return get_flux_value_histeq(value, index);
+            break;
         case 'linear':
             return get_flux_value_linear(value, black, white);
             break;
@@ -5370,7 +5724,7 @@ function get_flux_path_log(width, height, min, max, black, white, index) {
     var lower = min + black / width * (max - min);
     var upper = min + white / width * (max - min);
 
-    var sensitivity = 0.5 * (Math.E - 1) / (upper - lower);
+    var sensitivity = 1 / (upper - lower);
     var multiplier = sensitivity / fitsData.sensitivity;
     noise_sensitivity = get_noise_sensitivity_from_multiplier(multiplier);
 
@@ -5382,9 +5736,9 @@ function get_flux_path_log(width, height, min, max, black, white, index) {
     for (var x = black; x < white + dx; x += dx) {
         var y = height - 1;
         var tmp = min + x / width * (max - min);
-        tmp = (tmp - lower) * sensitivity;
+        tmp = Math.max(0.0, (tmp - lower) * sensitivity);
 
-        var pixel = (tmp > -0.5) ? Math.log(2.0 * tmp + 1.0) : 0.0;
+        var pixel = Math.log(LOG_STRETCH_SCALE * tmp + 1.0) / Math.log(LOG_STRETCH_SCALE + 1.0);
         pixel = Math.max(0.0, Math.min(1.0, pixel));
 
         y *= (1.0 - pixel);
@@ -5396,6 +5750,41 @@ function get_flux_path_log(width, height, min, max, black, white, index) {
     return path;
 }
 
+function get_flux_path_curve(width, height, black, white, curve) {
+    var path = "M0 " + (emStrokeWidth + height - 1) + " L" + black + " " + (emStrokeWidth + height - 1);
+
+    var segments = 100;
+    var dx = (white - black) / segments;
+
+    if (dx > 0)
+        for (var x = black; x < white + dx; x += dx) {
+            var y = height - 1;
+            var tmp = Math.max(0.0, Math.min(1.0, (x - black) / (white - black)));
+
+            var pixel = Math.max(0.0, Math.min(1.0, curve(tmp)));
+
+            y *= (1.0 - pixel);
+            path += " L" + x + " " + (emStrokeWidth + y);
+        }
+
+    path += " L" + width + " " + emStrokeWidth;
+
+    return path;
+}
+
+function get_flux_path_histeq(width, height, index) {
+    var cdf = histogram_cdf(index);
+    var path = "M0 " + (emStrokeWidth + height - 1);
+
+    for (var i = 1; i < cdf.length; i++) {
+        var x = i / (cdf.length - 1) * width;
+        var y = (height - 1) * (1.0 - cdf[i]);
+        path += " L" + x + " " + (emStrokeWidth + y);
+    }
+
+    return path;
+}
+
 function get_flux_path_legacy(width, height, black, white, multiplier) {
     var path = "M0 " + (emStrokeWidth + height - 1) + " L" + black + " " + (emStrokeWidth + height - 1);
 
@@ -5443,7 +5832,22 @@ function get_flux_path(width, height, flux, black, white, median, multiplier, in
     var white = (white - min) / (max - min) * width;
     var median = (median - min) / (max - min) * width;
 
-    switch (flux) {
+    switch (flux_name(flux)) {
+        case 'asinh':
+        case 'sqrt':
+        case 'power':
+            return get_flux_path_curve(width, height, black, white, stretch_curve(flux));
+            break;
+
+        case 'zscale':
+            let limits = zscale_limits(index);
+            return get_flux_path_curve(width, height, (limits[0] - min) / (max - min) * width, (limits[1] - min) / (max - min) * width, stretch_curve(flux));
+            break;
+
+        case 'histeq':
+            return get_flux_path_histeq(width, height, index);
+            break;
+
         case 'legacy':
             return get_flux_path_legacy(width, height, black, white, multiplier);
             break;
@@ -5532,7 +5936,19 @@ function setup_histogram_interaction(index) {
         .style("fill", "none")
         .attr("d", path);
 
-    switch (flux) {
+    switch (flux_name(flux)) {
+        case 'asinh':
+        case 'sqrt':
+        case 'power':
+            add_histogram_line(group, black, width, height, offset, 'black', 'right', false, index);
+            add_histogram_line(group, white, width, height, offset, 'white', 'top right', true, index);
+            break;
+
+        //the limits come from the data
+        case 'zscale':
+        case 'histeq':
+            break;
+
         case 'legacy':
             add_histogram_line(group, black, width, height, offset, 'black', 'right', false, index);
             add_histogram_line(group, white, width, height, offset, 'white', 'top left', true, index);
@@ -5811,6 +6227,34 @@ function display_preferences(index) {
         d3.select('#video_fps_control_li').style("display", "none");
     }
 
+    //luma bit depth, with AV1 or VP9 profile 2 (HEVC Main10/Main12 is decoded by WebAssembly)
+    if (hbd_supported(10) || hbd_supported(12)) {
+        tmpA = prefDropdown.append("li")
+            .attr("id", "luma_bits_li")
+            .append("a")
+            .style("class", "form-group")
+            .attr("class", "form-horizontal");
+
+        tmpA.append("label")
+            .attr("for", "luma_bits")
+            .attr("class", "control-label")
+            .html("luma bit depth:&nbsp; ");
+
+        var options = "<option value='8'>8-bit</option>";
+
+        [10, 12].forEach(function (bits) {
+            if (hbd_supported(bits))
+                options += "<option value='" + bits + "'>" + bits + "-bit</option>";
+        });
+
+        tmpA.append("select")
+            .attr("id", "luma_bits")
+            .attr("onchange", "javascript:change_luma_bits();")
+            .html(options);
+
+        document.getElementById('luma_bits').value = luma_bit_depth().toString();
+    }
+
     //ui_theme
     {
         tmpA = prefDropdown.append("li")
@@ -5919,6 +6363,23 @@ function display_preferences(index) {
             .html("<option>mean</option><option>integrated</option>");
 
         document.getElementById('intensity_mode').value = intensity_mode;
+
+        tmpA = prefDropdown.append("li")
+            .append("a")
+            .style("class", "form-group")
+            .attr("class", "form-horizontal");
+
+        tmpA.append("label")
+            .attr("for", "spectrum_format")
+            .attr("class", "control-label")
+            .html("spectrum export:&nbsp; ");
+
+        tmpA.append("select")
+            .attr("id", "spectrum_format")
+            .attr("onchange", "javascript:change_spectrum_format();")
+            .html("<option value='csv'>CSV</option><option value='votable'>VOTable</option><option value='fits'>FITS</option>");
+
+        document.getElementById('spectrum_format').value = spectrum_format;
     }
 
     tmpA = prefDropdown.append("li")
@@ -6045,14 +6506,17 @@ function display_histogram(index) {
         //.attr("class", "form-control")
         .attr("id", "flux" + index)
         .attr("onchange", "javascript:image_count=0;display_hourglass();change_tone_mapping(" + index + ",true);")
-        .html("<option value='linear'>linear</option><option value='legacy'>logarithmic</option><option value='logistic'>logistic</option><option value='ratio'>ratio</option><option value='square'>square</option>");//<option value='log'>log</option>
+        .html("<option value='linear'>linear</option><option value='legacy'>logarithmic</option><option value='logistic'>logistic</option><option value='ratio'>ratio</option><option value='square'>square</option><option value='asinh'>asinh</option><option value='sqrt'>sqrt</option><option value='log'>log</option><option value='power'>power</option><option value='zscale'>zscale</option><option value='histeq'>histogram equalisation</option>");
+
+    if (flux_name(fitsData.flux) == 'power')
+        set_power_exponent(index, flux_exponent(fitsData.flux));
 
     document.getElementById('flux' + index).value = fitsData.flux;
     //document.querySelectorAll('[value="' + fitsData.flux + '"]')[0].text = fitsData.flux + ' (default)' ;
 
     var display;
 
-    if (fitsData.flux == 'linear' || fitsData.flux == 'log' || fitsData.flux == 'square')
+    if (flux_hides_sensitivity(fitsData.flux))
         display = "none";
     else
         display = "block";
@@ -6578,6 +7042,7 @@ function setup_csv_export() {
                 ref_freq: RESTFRQ,
                 deltaV: 1000.0 * deltaV, // [m/s]
                 rest: rest,
+                format: spectrum_format,
                 seq_id: sent_seq_id,
                 timestamp: performance.now(),
             };
@@ -7079,10 +7544,12 @@ function x_axis_mouseenter(offset) {
         } catch (e) { };
 
         var freq = get_mouse_frequency(offset);
+        video_freq = freq;
 
         sent_vid_id++;
 
         if (composite_view) {
+            send_composite_colours();
             wsConn[0].send('[init_video] frame=' + freq + '&view=composite' + '&ref_freq=' + RESTFRQ + '&fps=' + vidFPS + '&seq_id=' + sent_vid_id + '&bitrate=' + Math.round(target_bitrate) + '&timestamp=' + performance.now());
             video_stack[0] = [];
         } else for (let index = 0; index < va_count; index++) {
@@ -7208,6 +7675,7 @@ function x_axis_mouseleave() {
 
             wsConn[0].send('[end_video]');
             video_stack[0] = [];
+            close_av1_video(1);
         } else for (let index = 0; index < va_count; index++) {
             Module._free(videoFrame[index].ptr);
             Module._free(videoFrame[index].alpha_ptr);
@@ -7218,6 +7686,7 @@ function x_axis_mouseleave() {
 
             wsConn[index].send('[end_video]');
             video_stack[index] = [];
+            close_av1_video(index + 1);
 
             if (va_count > 1)
                 refresh_tiles(index + 1);
@@ -7431,6 +7900,7 @@ function x_axis_move(offset) {
 
             //for each dataset request a video frame via WebSockets
             sent_vid_id++;
+            video_freq = freq;
 
             video_count = 0;
 
@@ -8174,9 +8644,6 @@ function display_composite_legend() {
     var strLegend = '<div class="container-fluid">';
 
     for (let index = 0; index < va_count; index++) {
-        if (index >= 3)
-            break;
-
         let fitsData = fitsContainer[index];
         let line = fitsData.LINE.trim();
         let filter = fitsData.FILTER.trim();
@@ -8198,7 +8665,9 @@ function display_composite_legend() {
             //strSelect += '<br><hr width="66%">' ;
             strSelect += '<br><br>';
 
-        strLegend += '<div><div id="DIV' + line + '" style="width:5em;height:1em;display:inline-block"><img id="IMG' + line + '" src="" alt="linedash" width="100%" height="100%"></div><span style="font-size:100%; font-family:Inconsolata; color:' + colours[index] + ';">&nbsp;■&nbsp;</span><span style="font-size:100%; font-family:Helvetica; font-weight:bold; nocolor:' + colours[index] + ';">' + plain2chem(line, false) + '</span>&nbsp;' + strSelect + '</div>';
+        let legend_colour = (composite_channels.length == 0 && va_count <= 3) ? colours[index] : rgb_to_hex(composite_tints()[index].colour);
+
+        strLegend += '<div><div id="DIV' + line + '" style="width:5em;height:1em;display:inline-block"><img id="IMG' + line + '" src="" alt="linedash" width="100%" height="100%"></div><span style="font-size:100%; font-family:Inconsolata; color:' + legend_colour + ';">&nbsp;■&nbsp;</span><span style="font-size:100%; font-family:Helvetica; font-weight:bold; nocolor:' + legend_colour + ';">' + plain2chem(line, false) + '</span>&nbsp;' + strSelect + '</div>';
     }
 
     strLegend += '</div>';
@@ -8762,7 +9231,10 @@ function setup_image_selection() {
                     ctx.fillStyle = "rgba(0,0,0,0.3)";
                     ctx.fillRect(data.px, data.py, data.zoomed_size, data.zoomed_size);
 
-                    ctx.drawImage(imageCanvas, data.x - data.clipSize, data.y - data.clipSize, 2 * data.clipSize + 1, 2 * data.clipSize + 1, data.px, data.py, data.zoomed_size, data.zoomed_size);
+                    if (data.pyramid != null)
+                        ctx.drawImage(data.pyramid, 0, 0, data.pyramid.width, data.pyramid.height, data.px, data.py, data.zoomed_size, data.zoomed_size);
+                    else
+                        ctx.drawImage(imageCanvas, data.x - data.clipSize, data.y - data.clipSize, 2 * data.clipSize + 1, 2 * data.clipSize + 1, data.px, data.py, data.zoomed_size, data.zoomed_size);
                 }
 
                 if (zoom_shape == "circle") {
@@ -8775,7 +9247,11 @@ function setup_image_selection() {
 
                     ctx.closePath();
                     ctx.clip();
-                    ctx.drawImage(imageCanvas, data.x - data.clipSize, data.y - data.clipSize, 2 * data.clipSize + 1, 2 * data.clipSize + 1, data.px, data.py, data.zoomed_size, data.zoomed_size);
+
+                    if (data.pyramid != null)
+                        ctx.drawImage(data.pyramid, 0, 0, data.pyramid.width, data.pyramid.height, data.px, data.py, data.zoomed_size, data.zoomed_size);
+                    else
+                        ctx.drawImage(imageCanvas, data.x - data.clipSize, data.y - data.clipSize, 2 * data.clipSize + 1, 2 * data.clipSize + 1, data.px, data.py, data.zoomed_size, data.zoomed_size);
                     ctx.restore();
                 }
             }
@@ -9064,6 +9540,8 @@ function setup_image_selection() {
             mouse_position = { x: offset[0], y: offset[1] };
             //updateKalman() ;
 
+            send_follow_cursor((mouse_position.x - d3.select(this).attr("x")) / d3.select(this).attr("width"), (mouse_position.y - d3.select(this).attr("y")) / d3.select(this).attr("height"));
+
             var image_bounding_dims = imageContainer[va_count - 1].image_bounding_dims;
             var imageCanvas = imageContainer[va_count - 1].imageCanvas;
             var x = image_bounding_dims.x1 + (mouse_position.x - d3.select(this).attr("x")) / d3.select(this).attr("width") * (image_bounding_dims.width - 1);
@@ -9326,7 +9804,9 @@ function setup_image_selection() {
 
                 //console.log(x-clipSize, y-clipSize, px, py, 2*clipSize, zoomed_size) ;		
 
-                image_stack.push({ x: x, y: y, clipSize: clipSize, px: px, py: py, zoomed_size: zoomed_size });
+                let pyramid = pyramid_canvas(fitsX - fitsSize, fitsY - fitsSize, 2 * fitsSize + 1, zoomed_size);
+
+                image_stack.push({ x: x, y: y, clipSize: clipSize, px: px, py: py, zoomed_size: zoomed_size, pyramid: pyramid });
                 viewport_zoom_settings = { x: x, y: y, clipSize: clipSize, zoomed_size: zoomed_size };
             }
 
@@ -9590,35 +10070,258 @@ function fetch_spectral_lines(datasetId, freq_start, freq_end) {
     xmlhttp.send();
 };
 
-function fetch_image(datasetId, index, add_timestamp) {
-    var xmlhttp = new XMLHttpRequest();
+function use_av1() {
+    return has_av1 && av1_supported;
+}
 
-    var url = 'get_image?datasetId=' + encodeURIComponent(datasetId) + '&' + encodeURIComponent(get_js_version());
+//HEVC Main10/Main12 need a WebAssembly decoder exporting hevc_max_bit_depth
+function hevc_bit_depth() {
+    if (typeof Module !== 'undefined' && typeof Module._hevc_max_bit_depth === 'function')
+        return Module._hevc_max_bit_depth();
 
-    if (add_timestamp)
-        url += '&timestamp=' + Date.now();
+    return 8;
+}
 
-    xmlhttp.onreadystatechange = function () {
-        if (xmlhttp.readyState == 4 && xmlhttp.status == 404) {
-            hide_hourglass();
-            show_not_found();
-        }
+//10/12-bit luma: AV1, or VP9 profile 2 still images with HEVC viewports and videos
+function hbd_supported(bits) {
+    if (use_av1())
+        return av1_hbd_supported[bits];
 
-        if (xmlhttp.readyState == 4 && xmlhttp.status == 415) {
-            hide_hourglass();
-            show_unsupported_media_type();
-        }
+    return vp9_hbd_supported[bits] && bits <= hevc_bit_depth();
+}
 
-        if (xmlhttp.readyState == 4 && xmlhttp.status == 500) {
-            hide_hourglass();
-            show_critical_error();
-        }
+//the luma bit depth requested from the server
+function luma_bit_depth() {
+    let bits = parseInt(luma_bits);
 
-        if (xmlhttp.readyState == 4 && xmlhttp.status == 502) {
-            console.log("Connection error, re-fetching image after 1 second.");
-            setTimeout(function () {
-                fetch_image(datasetId, index, true);
-            }, 1000);
+    if (hbd_supported(bits))
+        return bits;
+
+    return 8;
+}
+
+function codec_request() {
+    return '[codec] name=' + (use_av1() ? 'av1' : 'auto') + '&bits=' + luma_bit_depth();
+}
+
+function av1_codec_string() {
+    let bits = luma_bit_depth();
+
+    return bits > 8 ? AV1_HBD_CODEC_STRINGS[bits] : AV1_CODEC_STRING;
+}
+
+function vp9_hbd_codec_string() {
+    let bits = luma_bit_depth();
+
+    return VP9_HBD_CODEC_STRINGS[bits > 8 ? bits : 10];
+}
+
+//the 8-bit luma plane of a decoded AV1/VP9 frame; 10/12-bit samples are rounded to 8 bits for the colourmap
+function luma_plane(format, buffer, layout, w, h) {
+    if (format != "I420P10" && format != "I420P12")
+        return { bytes: buffer.subarray(layout[0].offset), stride: layout[0].stride };
+
+    let shift = (format == "I420P10") ? 2 : 4;
+    let round = 1 << (shift - 1);
+    let bytes = new Uint8Array(w * h);
+
+    for (let j = 0; j < h; j++) {
+        let offset = layout[0].offset + j * layout[0].stride;
+
+        for (let i = 0; i < w; i++) {
+            //little-endian 16-bit samples
+            let sample = buffer[offset + 2 * i] | (buffer[offset + 2 * i + 1] << 8);
+            bytes[j * w + i] = Math.min((sample + round) >> shift, 255);
+        }
+    }
+
+    return { bytes: bytes, stride: w };
+}
+
+//a keyframe packet starts with a temporal delimiter OBU followed by a sequence header OBU
+function is_av1_keyframe(frame) {
+    return frame.length > 2 && frame[0] == 0x12 && frame[1] == 0x00 && (frame[2] >> 3 & 0x0F) == 1;
+}
+
+//the VP9 uncompressed header starts with a frame marker (0b10), then the low and high profile bits
+function is_vp9_hbd(frame) {
+    return frame.length > 0 && (frame[0] >> 6) == 2 && (frame[0] >> 4 & 1) == 1;
+}
+
+//decode a single AV1 (key) frame, passing the luma plane to the callback
+function decode_av1_frame(frame, callback) {
+    decode_webcodecs_frame(av1_codec_string(), frame, callback);
+}
+
+//decode a single 10/12-bit VP9 (key) frame, passing the luma plane to the callback
+function decode_vp9_hbd_frame(frame, callback) {
+    decode_webcodecs_frame(vp9_hbd_codec_string(), frame, callback);
+}
+
+function decode_webcodecs_frame(codec, frame, callback) {
+    var decoder = new VideoDecoder({
+        output: function (videoFrame) {
+            var buffer = new Uint8Array(videoFrame.allocationSize());
+            var w = videoFrame.displayWidth;
+            var h = videoFrame.displayHeight;
+            var format = videoFrame.format;
+
+            videoFrame.copyTo(buffer).then(function (layout) {
+                videoFrame.close();
+
+                let luma = luma_plane(format, buffer, layout, w, h);
+                callback(w, h, luma.bytes, luma.stride);
+            });
+        },
+        error: function (e) {
+            console.error(codec + " decoder error:", e);
+        }
+    });
+
+    decoder.configure({ codec: codec, optimizeForLatency: true });
+    decoder.decode(new EncodedVideoChunk({ type: "key", timestamp: 0, data: frame }));
+    decoder.flush().then(function () {
+        decoder.close();
+    }).catch(function (e) {
+        console.error(e);
+    });
+}
+
+function init_av1_video(index) {
+    close_av1_video(index);
+    av1Keyframe[index - 1] = false;
+
+    av1Decoder[index - 1] = new VideoDecoder({
+        output: function (av1Frame) {
+            var buffer = new Uint8Array(av1Frame.allocationSize());
+            var w = av1Frame.displayWidth;
+            var h = av1Frame.displayHeight;
+            var format = av1Frame.format;
+
+            av1Frame.copyTo(buffer).then(function (layout) {
+                av1Frame.close();
+
+                if (!streaming || videoFrame[index - 1] == null || videoFrame[index - 1].img == null)
+                    return;
+
+                var img = videoFrame[index - 1].img;
+
+                if (img.data.length == 0) {
+                    //detect detached data due to WASM memory growth
+                    var len = img.width * img.height * 4;
+                    var data = new Uint8ClampedArray(Module.HEAPU8.buffer, videoFrame[index - 1].ptr, len);
+                    img = new ImageData(data, img.width, img.height);
+
+                    videoFrame[index - 1].img = img;
+                }
+
+                let luma = luma_plane(format, buffer, layout, w, h);
+                apply_colourmap(img, colourmap, luma.bytes, w, h, luma.stride, videoFrame[index - 1].alpha_bytes);
+
+                requestAnimationFrame(function () {
+                    process_video(index)
+                });
+            });
+        },
+        error: function (e) {
+            console.error("AV1 video decoder error:", e);
+            av1Decoder[index - 1] = null;
+        }
+    });
+
+    av1Decoder[index - 1].configure({ codec: av1_codec_string(), optimizeForLatency: true });
+}
+
+//restart the video stream at a resolution chosen by the server-side adaptive bitrate control
+function video_rescale(index, scale) {
+    if (!streaming || composite_view)
+        return;
+
+    console.log("adaptive bitrate: restarting the video stream at scale", scale);
+
+    if (videoFrame[index - 1] != null) {
+        Module._free(videoFrame[index - 1].ptr);
+        Module._free(videoFrame[index - 1].alpha);
+        videoFrame[index - 1].img = null;
+        videoFrame[index - 1] = null;
+    }
+
+    close_av1_video(index);
+
+    sent_vid_id++;
+
+    wsConn[index - 1].send('[end_video]');
+    wsConn[index - 1].send('[init_video] frame=' + video_freq + '&view=tile' + '&ref_freq=' + RESTFRQ + '&fps=' + vidFPS + '&seq_id=' + sent_vid_id + '&bitrate=' + Math.round(target_bitrate) + '&timestamp=' + performance.now() + '&scale=' + scale);
+}
+
+function close_av1_video(index) {
+    if (av1Decoder[index - 1] == null)
+        return;
+
+    try {
+        av1Decoder[index - 1].close();
+    } catch (e) { };
+
+    av1Decoder[index - 1] = null;
+}
+
+function decode_av1_video(frame, index, timestamp) {
+    var decoder = av1Decoder[index - 1];
+
+    if (decoder == null || decoder.state != "configured")
+        return;
+
+    var key = is_av1_keyframe(frame);
+
+    //the decoder needs a keyframe first
+    if (!key && decoder.decodeQueueSize == 0 && av1Keyframe[index - 1] != true)
+        return;
+
+    if (key)
+        av1Keyframe[index - 1] = true;
+
+    decoder.decode(new EncodedVideoChunk({ type: key ? "key" : "delta", timestamp: Math.round(1000 * timestamp), data: frame }));
+}
+
+function fetch_image(datasetId, index, add_timestamp) {
+    var xmlhttp = new XMLHttpRequest();
+
+    var url = 'get_image?datasetId=' + encodeURIComponent(datasetId) + '&' + encodeURIComponent(get_js_version());
+
+    if (use_av1())
+        url += '&codec=av1&bits=' + luma_bit_depth();
+    else if (luma_bit_depth() > 8)
+        url += '&codec=vp9&bits=' + luma_bit_depth();
+
+    if (add_timestamp)
+        url += '&timestamp=' + Date.now();
+
+    xmlhttp.onreadystatechange = function () {
+        if (xmlhttp.readyState == 4 && xmlhttp.status == 404) {
+            hide_hourglass();
+            show_not_found();
+        }
+
+        if (xmlhttp.readyState == 4 && xmlhttp.status == 415) {
+            hide_hourglass();
+            show_unsupported_media_type();
+        }
+
+        if (xmlhttp.readyState == 4 && xmlhttp.status == 500) {
+            hide_hourglass();
+            show_critical_error();
+        }
+
+        if (xmlhttp.readyState == 4 && xmlhttp.status == 504) {
+            hide_hourglass();
+            show_download_failed();
+        }
+
+        if (xmlhttp.readyState == 4 && xmlhttp.status == 502) {
+            console.log("Connection error, re-fetching image after 1 second.");
+            setTimeout(function () {
+                fetch_image(datasetId, index, true);
+            }, 1000);
         }
 
         if (xmlhttp.readyState == 4 && xmlhttp.status == 202) {
@@ -9631,6 +10334,9 @@ function fetch_image(datasetId, index, add_timestamp) {
         if (xmlhttp.readyState == 4 && xmlhttp.status == 200) {
             var received_msg = xmlhttp.response;
 
+            if (va_count == 1)
+                fetch_tile_pyramid(datasetId);
+
             if (received_msg instanceof ArrayBuffer) {
                 var dv = new DataView(received_msg);
                 console.log("FITSImage dataview byte length: ", dv.byteLength);
@@ -9670,7 +10376,14 @@ function fetch_image(datasetId, index, add_timestamp) {
 
                 //the decoder part
 
-                if (identifier == 'VP9') {
+                //VP9 profile 2 (10/12-bit)
+                if (identifier == 'VP9' && is_vp9_hbd(frame)) {
+                    decode_vp9_hbd_frame(frame, function (w, h, bytes, stride) {
+                        process_image(width, height, w, h, bytes, stride, alpha, index);
+                    });
+                }
+
+                if (identifier == 'VP9' && !is_vp9_hbd(frame)) {
                     var decoder = new OGVDecoderVideoVP9();
                     console.log(decoder);
 
@@ -9684,6 +10397,12 @@ function fetch_image(datasetId, index, add_timestamp) {
                             index);
                     });
                 }
+
+                if (identifier == 'AV1') {
+                    decode_av1_frame(frame, function (w, h, bytes, stride) {
+                        process_image(width, height, w, h, bytes, stride, alpha, index);
+                    });
+                }
             }
         }
     }
@@ -9871,6 +10590,8 @@ function fetch_spectrum(datasetId, index, add_timestamp) {
             if (spectrum_count == va_count) {
                 document.getElementById('welcome').style.display = "none";
                 console.log('hiding the loading progress, style =', document.getElementById('welcome').style.display);
+
+                apply_saved_view();
             }
 
             //setup_image_selection() ;
@@ -10142,6 +10863,7 @@ function videoTimeout(freq) {
     console.log("video inactive event");
 
     sent_vid_id++;
+    video_freq = freq;
 
     video_count = 0;
 
@@ -10366,12 +11088,12 @@ function imageTimeout() {
 
     sent_seq_id++;
 
+    _x1 = x1; _x2 = x2; _y1 = y1; _y2 = y2; // global variables (also used by the region photometry)
+
     // attach a CSV export handler
     if (has_velocity_info || has_frequency_info) {
         console.log("setting up an idle CSV handler");
 
-        _x1 = x1; _x2 = x2; _y1 = y1; _y2 = y2; // global variables
-
         var elem = document.getElementById('exportCSV');
 
         if (elem != null) {
@@ -10427,6 +11149,7 @@ function imageTimeout() {
                         ref_freq: RESTFRQ,
                         deltaV: 1000.0 * deltaV, // [m/s]
                         rest: rest,
+                        format: spectrum_format,
                         seq_id: sent_seq_id,
                         timestamp: performance.now(),
                     };
@@ -10638,153 +11361,1211 @@ function updateKalman() {
     mouse_position.y = predX.elements[1];*/
 }
 
-function change_noise_sensitivity(refresh, index) {
-    noise_sensitivity = document.getElementById('sensitivity' + index).value;
+function change_noise_sensitivity(refresh, index) {
+    noise_sensitivity = document.getElementById('sensitivity' + index).value;
+
+    var multiplier = get_noise_sensitivity(noise_sensitivity);
+    document.getElementById('sensitivityInput' + index).innerHTML = get_noise_sensitivity_string(noise_sensitivity, 2);
+
+    var c = document.getElementById("HistogramCanvas" + index);
+    var svg = d3.select("#HistogramSVG" + index);
+    var width = c.width;
+    var height = c.height;
+
+    var flux_elem = d3.select("#flux_path" + index);
+
+    var black, white, median;
+
+    try {
+        black = parseFloat(flux_elem.attr("black"));
+    }
+    catch (e) {
+    };
+
+    try {
+        white = parseFloat(flux_elem.attr("white"));
+    }
+    catch (e) {
+    };
+
+    try {
+        median = parseFloat(flux_elem.attr("median"));
+    }
+    catch (e) {
+    };
+
+    var path = get_flux_path(width, height, document.getElementById('flux' + index).value, black, white, median, multiplier, index);
+
+    flux_elem.attr("d", path);
+
+    if (refresh) {
+        display_hourglass();
+
+
+        if (!composite_view) {
+            image_count = va_count - 1;
+
+            image_refresh(index, false);
+        }
+        else {
+            image_count = 0;
+
+            for (let i = 1; i <= va_count; i++)
+                image_refresh(i, false);
+        }
+    }
+}
+
+function partial_fits_download(offsetx, offsety, width, height) {
+    mousedown = false;
+    d3.select("#region").attr("opacity", 0.0);
+
+    let fitsData = fitsContainer[va_count - 1];
+    var image_bounding_dims = imageContainer[va_count - 1].image_bounding_dims;
+    var imageCanvas = imageContainer[va_count - 1].imageCanvas;
+
+    var x1 = image_bounding_dims.x1 + (begin_x - offsetx) / width * (image_bounding_dims.width - 1);
+    var y1 = image_bounding_dims.y1 + (begin_y - offsety) / height * (image_bounding_dims.height - 1);
+
+    var orig_x1 = x1 * fitsData.width / imageCanvas.width;
+    var orig_y1 = fitsData.height - y1 * fitsData.height / imageCanvas.height;
+
+    var x2 = image_bounding_dims.x1 + (end_x - offsetx) / width * (image_bounding_dims.width - 1);
+    var y2 = image_bounding_dims.y1 + (end_y - offsety) / height * (image_bounding_dims.height - 1);
+
+    var orig_x2 = x2 * fitsData.width / imageCanvas.width;
+    var orig_y2 = fitsData.height - y2 * fitsData.height / imageCanvas.height;
+
+    selected_region = { x1: Math.round(orig_x1), y1: Math.round(orig_y2), x2: Math.round(orig_x2), y2: Math.round(orig_y1) };
+
+    var url = "get_fits?";
+
+    if (va_count == 1)
+        url += "datasetId=" + encodeURIComponent(datasetId) + "&";
+    else {
+        for (let index = 1; index <= va_count; index++)
+            url += "datasetId" + index + "=" + encodeURIComponent(datasetId[index - 1]) + "&";
+    }
+
+    url += "x1=" + Math.round(orig_x1) + "&y1=" + Math.round(orig_y2) + "&x2=" + Math.round(orig_x2) + "&y2=" + Math.round(orig_y1) + "&frame_start=" + data_band_lo + "&frame_end=" + data_band_hi + "&ref_freq=" + RESTFRQ;
+
+    if (cutout_binning.spatial > 1)
+        url += "&bin=" + cutout_binning.spatial;
+
+    if (cutout_binning.spectral > 1)
+        url += "&spectral_bin=" + cutout_binning.spectral + "&spectral_mode=" + cutout_binning.mode;
+
+    if (cutout_format != 'fits')
+        url += "&format=" + cutout_format;
+
+    //console.log(url) ;
+    //window.location.assign(url);
+    window.open(url, '_blank');
+    window.focus();
+}
+
+function ok_download() {
+    $('#downloadConfirmation').modal('hide');
+    d3.select("#downloadConfirmation").remove();
+
+    partial_fits_download(d3.select("#image_rectangle").attr("x"), d3.select("#image_rectangle").attr("y"), d3.select("#image_rectangle").attr("width"), d3.select("#image_rectangle").attr("height"));
+};
+
+function cancel_download() {
+    mousedown = false;
+    d3.select("#region").attr("opacity", 0.0);
+
+    $('#downloadConfirmation').modal('hide');
+    d3.select("#downloadConfirmation").remove();
+};
+
+function show_download_confirmation() {
+    var modal = document.getElementById('downloadConfirmation');
+    var span = document.getElementById('downloadconfirmationclose');
+
+    // When the user clicks on <span> (x), close the modal
+    span.onclick = function () {
+        $('#downloadConfirmation').modal('hide');
+        d3.select("#downloadConfirmation").remove();
+
+        mousedown = false;
+        d3.select("#region").attr("opacity", 0.0);
+    }
+    // When the user clicks a mouse, close it
+    window.onclick = function (event) {
+        if (event.target == modal) {
+            $('#downloadConfirmation').modal('hide');
+            d3.select("#downloadConfirmation").remove();
+
+            mousedown = false;
+            d3.select("#region").attr("opacity", 0.0);
+        }
+    }
+}
+
+function show_scaling_help() {
+    var modal = document.getElementById('scalingHelp');
+    var span = document.getElementById('scalingHeaderClose');
+
+    // When the user clicks on <span> (x), close the modal
+    span.onclick = function () {
+        $('#scalingHelp').modal('hide');
+        d3.select("#scalingHelp").remove();
+    }
+    // When the user moves a mouse, close it
+    window.onmousemove = function (event) {
+        if (event.target == modal) {
+            $('#scalingHelp').modal('hide');
+            d3.select("#scalingHelp").remove();
+        }
+    }
+}
+
+function get_view_state() {
+    var params = new URLSearchParams(window.location.search);
+
+    var state = {
+        datasets: (va_count == 1) ? [datasetId] : datasetId,
+        composite: composite_view,
+        flux: document.getElementById('flux1').value,
+        frame_start: data_band_lo,
+        frame_end: data_band_hi,
+        ref_freq: RESTFRQ,
+        user_selfrq: (typeof USER_SELFRQ !== 'undefined') ? USER_SELFRQ : null,
+        params: [],
+        region: selected_region
+    };
+
+    //where the dataset(s) came from
+    ['url', 'dir', 'ext', 'db', 'table'].forEach(function (key) {
+        if (params.get(key) != null)
+            state[key] = params.get(key);
+    });
+
+    for (let index = 1; index <= va_count; index++) {
+        var flux_elem = d3.select("#flux_path" + index);
+
+        state.params.push({
+            flux: document.getElementById('flux' + index).value,
+            black: parseFloat(flux_elem.attr("black")),
+            white: parseFloat(flux_elem.attr("white")),
+            median: parseFloat(flux_elem.attr("median")),
+            noise: noise_sensitivity
+        });
+    }
+
+    return state;
+}
+
+function save_view() {
+    var name = prompt("view name (optional):", "");
+
+    if (name == null)
+        return;
+
+    var state = get_view_state();
+    state.name = name;
+
+    var xmlhttp = new XMLHttpRequest();
+
+    xmlhttp.onreadystatechange = function () {
+        if (xmlhttp.readyState == 4) {
+            if (xmlhttp.status == 200) {
+                var response = JSON.parse(xmlhttp.responseText);
+                var link = window.location.protocol + "//" + window.location.host + response.url;
+
+                console.log("saved view:", response.id, link);
+                prompt("permalink to this view:", link);
+            }
+            else {
+                console.log("save_view error:", xmlhttp.status, xmlhttp.responseText);
+                alert("could not save the view (HTTP " + xmlhttp.status + ")");
+            }
+        }
+    };
+
+    xmlhttp.open("POST", "save_view", true);
+    xmlhttp.setRequestHeader("Content-Type", "application/json");
+    xmlhttp.send(JSON.stringify(state));
+}
+
+//a server-rendered PNG of the current image: colourmap, stretch, contours, colourbar and WCS grid
+//mode == 'snr': the integrated intensity divided by the propagated channel noise
+function save_snapshot(mode) {
+    let index = 1;
+    let dataId = va_count == 1 ? datasetId : datasetId[0];
+    let fitsData = fitsContainer[index - 1];
+
+    var width = prompt("snapshot width [pixels]:", fitsData != null ? fitsData.width : 1024);
+
+    if (width == null)
+        return;
+
+    var url = 'get_snapshot?datasetId=' + encodeURIComponent(dataId) + '&colourmap=' + colourmap;
+
+    if (parseInt(width) > 0)
+        url += '&width=' + parseInt(width);
+
+    var flux_elem = d3.select("#flux_path" + index);
+
+    //the SNR map has its own black and white points
+    if (mode == 'snr')
+        url += '&mode=snr';
+    else
+        ['black', 'white', 'median'].forEach(function (key) {
+            try {
+                let value = flux_elem.attr(key);
+
+                if (value != null)
+                    url += '&' + key + '=' + value;
+            }
+            catch (e) {
+            };
+        });
+
+    try {
+        url += '&flux=' + document.getElementById('flux' + index).value;
+    }
+    catch (e) {
+    };
+
+    url += '&noise=' + get_noise_sensitivity_string(noise_sensitivity, 3);
+    url += '&frame_start=' + data_band_lo + '&frame_end=' + data_band_hi + '&ref_freq=' + RESTFRQ;
+
+    if (displayContours)
+        url += '&contours=' + previous_contour_lines;
+
+    console.log("PNG snapshot:", url);
+    window.open(url, '_blank');
+}
+
+//download the per-channel noise statistics (median, RMS, NaN fraction, min/max) as CSV
+function download_noise_stats() {
+    let dataId = va_count == 1 ? datasetId : datasetId[0];
+    var url = 'get_noise_stats?datasetId=' + encodeURIComponent(dataId) + '&format=csv';
+
+    console.log("noise statistics:", url);
+    window.open(url, '_blank');
+}
+
+//region photometry of the current viewport region (circle or square), optionally
+//subtracting the median of a background annulus, exported as CSV
+function region_photometry() {
+    let fitsData = fitsContainer[0];
+
+    if (fitsData == null)
+        return;
+
+    var annulus = prompt("background annulus radii [pixels] 'inner,outer' (leave empty for none):", "");
+
+    if (annulus == null)
+        return;
+
+    var request = {
+        type: "photometry",
+        beam: zoom_shape,
+        frame_start: data_band_lo,
+        frame_end: data_band_hi,
+        ref_freq: RESTFRQ,
+        export: true,
+        timestamp: performance.now(),
+    };
+
+    if (typeof _x1 !== 'undefined') {
+        request.x1 = _x1;
+        request.y1 = _y2; // reversed Y-axis
+        request.x2 = _x2;
+        request.y2 = _y1; // reversed Y-axis
+    }
+
+    var radii = annulus.split(',').map(function (x) { return parseFloat(x); });
+
+    if (radii.length == 2 && radii[0] >= 0 && radii[1] > radii[0]) {
+        request.annulus_inner = radii[0];
+        request.annulus_outer = radii[1];
+    }
+
+    console.log("region photometry:", request);
+
+    display_hourglass();
+
+    if (wsConn[0].readyState == 1)
+        wsConn[0].send(JSON.stringify(request));
+}
+
+function process_photometry(data) {
+    hide_hourglass();
+
+    if (data.message != null) {
+        console.log("region photometry:", data.message);
+        return;
+    }
+
+    var summary = "region photometry (" + data.region + ", " + data.rows.length + " channel(s))";
+
+    if (data.rows.length == 1) {
+        let row = data.rows[0];
+        summary += "\nflux: " + row.flux + " +/- " + row.flux_error + " " + data.flux_unit;
+        summary += "\npeak: " + row.peak + " " + data.BUNIT;
+    }
+
+    if (data.integrated_flux != null)
+        summary += "\nintegrated flux: " + data.integrated_flux + " +/- " + data.integrated_flux_error + " " + data.flux_unit + " km/s";
+
+    console.log(summary, data);
+}
+
+//generate a HiPS tile set on the server, polling until it is ready
+function fetch_tile_pyramid(dataId) {
+    var xmlhttp = new XMLHttpRequest();
+    var url = 'get_tile_pyramid?datasetId=' + encodeURIComponent(dataId);
+
+    xmlhttp.onreadystatechange = function () {
+        if (xmlhttp.readyState != 4)
+            return;
+
+        if (xmlhttp.status == 202) {
+            //the pyramid is still being built
+            setTimeout(function () {
+                fetch_tile_pyramid(dataId);
+            }, 5000);
+            return;
+        }
+
+        if (xmlhttp.status != 200)
+            return;
+
+        try {
+            tile_pyramid = JSON.parse(xmlhttp.responseText);
+            tile_pyramid.datasetId = dataId;
+            tile_pyramid.valid = true;
+            console.log("tile pyramid:", tile_pyramid);
+        }
+        catch (e) {
+            console.log(e);
+        };
+    };
+
+    xmlhttp.open("GET", url, true);
+    xmlhttp.send();
+}
+
+//a cached tile {width, height, lum, alpha}, an empty tile or null when not yet available
+function get_pyramid_tile(level, x, y) {
+    let key = level + '/' + x + '_' + y;
+
+    if (pyramid_tiles.has(key))
+        return pyramid_tiles.get(key);
+
+    //a pending request
+    pyramid_tiles.set(key, null);
+
+    //keep the cache bounded
+    if (pyramid_tiles.size > 1024)
+        pyramid_tiles.delete(pyramid_tiles.keys().next().value);
+
+    var xmlhttp = new XMLHttpRequest();
+    var url = 'get_tile?datasetId=' + encodeURIComponent(tile_pyramid.datasetId) + '&level=' + level + '&x=' + x + '&y=' + y;
+
+    xmlhttp.responseType = 'blob';
+
+    xmlhttp.onreadystatechange = function () {
+        if (xmlhttp.readyState != 4)
+            return;
+
+        if (xmlhttp.status == 204) {
+            //a fully transparent tile
+            pyramid_tiles.set(key, { width: 0, height: 0, lum: null, alpha: null });
+            return;
+        }
+
+        if (xmlhttp.status != 200) {
+            pyramid_tiles.delete(key);
+            return;
+        }
+
+        createImageBitmap(xmlhttp.response).then(function (bitmap) {
+            var c = document.createElement('canvas');
+            c.width = bitmap.width;
+            c.height = bitmap.height;
+
+            var ctx = c.getContext('2d');
+            ctx.drawImage(bitmap, 0, 0);
+
+            let rgba = ctx.getImageData(0, 0, c.width, c.height).data;
+            let lum = new Uint8Array(c.width * c.height);
+            let alpha = new Uint8Array(c.width * c.height);
+
+            for (let i = 0; i < lum.length; i++) {
+                lum[i] = rgba[4 * i];
+                alpha[i] = rgba[4 * i + 3];
+            }
+
+            pyramid_tiles.set(key, { width: c.width, height: c.height, lum: lum, alpha: alpha });
+        }).catch(function (e) {
+            console.log(e);
+            pyramid_tiles.delete(key);
+        });
+    };
+
+    xmlhttp.open("GET", url, true);
+    xmlhttp.send();
+
+    return null;
+}
+
+//the zoom lens contents from the tile pyramid; (x0, y0) is the upper-left corner in FITS pixels (the first row at the top)
+function pyramid_canvas(x0, y0, size, zoomed_size) {
+    if (tile_pyramid == null || !tile_pyramid.valid || va_count > 1 || composite_view)
+        return null;
+
+    //pick the coarsest level that still matches the lens resolution
+    let level = tile_pyramid.levels - 1;
+    let factor = 1;
+
+    while (level > 0 && size / (2 * factor) >= zoomed_size) {
+        level--;
+        factor *= 2;
+    }
+
+    let dim = Math.max(Math.round(size / factor), 1);
+    let tx0 = Math.floor(x0 / factor);
+    let ty0 = Math.floor(y0 / factor);
+    let tile_size = tile_pyramid.tile_size;
+
+    let lum = new Uint8Array(dim * dim);
+    let alpha = new Uint8Array(dim * dim);
+    let complete = true;
+
+    for (let ty = Math.floor(ty0 / tile_size); ty <= Math.floor((ty0 + dim - 1) / tile_size); ty++) {
+        for (let tx = Math.floor(tx0 / tile_size); tx <= Math.floor((tx0 + dim - 1) / tile_size); tx++) {
+            if (tx < 0 || ty < 0 || tx * tile_size * factor >= tile_pyramid.width || ty * tile_size * factor >= tile_pyramid.height)
+                continue;
+
+            let tile = get_pyramid_tile(level, tx, ty);
+
+            if (tile == null) {
+                complete = false;
+                continue;
+            }
+
+            for (let j = 0; j < tile.height; j++) {
+                let dst_y = ty * tile_size + j - ty0;
+
+                if (dst_y < 0 || dst_y >= dim)
+                    continue;
+
+                for (let i = 0; i < tile.width; i++) {
+                    let dst_x = tx * tile_size + i - tx0;
+
+                    if (dst_x < 0 || dst_x >= dim)
+                        continue;
+
+                    lum[dst_y * dim + dst_x] = tile.lum[j * tile.width + i];
+                    alpha[dst_y * dim + dst_x] = tile.alpha[j * tile.width + i];
+                }
+            }
+        }
+    }
+
+    if (!complete)
+        return null;
+
+    var c = document.createElement('canvas');
+    c.width = dim;
+    c.height = dim;
+
+    var ctx = c.getContext('2d');
+    let imageData = ctx.createImageData(dim, dim);
+
+    apply_colourmap(imageData, colourmap, lum, dim, dim, dim, alpha);
+    ctx.putImageData(imageData, 0, 0);
+
+    return c;
+}
+
+function make_hips() {
+    let dataId = va_count == 1 ? datasetId : datasetId[0];
+
+    var xmlhttp = new XMLHttpRequest();
+    var url = 'make_hips?datasetId=' + encodeURIComponent(dataId);
+
+    xmlhttp.onreadystatechange = function () {
+        if (xmlhttp.readyState != 4)
+            return;
+
+        var response = null;
+
+        try {
+            response = JSON.parse(xmlhttp.responseText);
+        }
+        catch (e) {
+            console.log("make_hips:", xmlhttp.status, xmlhttp.responseText);
+            return;
+        };
+
+        if (xmlhttp.status == 200) {
+            var hips_url = window.location.protocol + '//' + window.location.host + response.url;
+            prompt("HiPS tiles are ready, the HiPS base URL:", hips_url);
+        } else if (xmlhttp.status == 202) {
+            console.log("HiPS generation in progress");
+            setTimeout(make_hips, 2000);
+        } else {
+            alert("HiPS generation failed: " + response.message);
+        }
+    };
+
+    xmlhttp.open("GET", url, true);
+    xmlhttp.send();
+}
+
+function export_video() {
+    let index = 1;
+    let dataId = va_count == 1 ? datasetId : datasetId[0];
+    let fitsData = fitsContainer[index - 1];
+
+    if (fitsData != null && fitsData.depth < 2) {
+        alert("video export requires a data cube");
+        return;
+    }
+
+    var format = prompt("video format (webm or mp4):", "webm");
+
+    if (format == null)
+        return;
+
+    var fps = prompt("frames per second:", Math.round(vidFPS));
+
+    if (fps == null)
+        return;
+
+    var url = 'export_video?datasetId=' + encodeURIComponent(dataId) + '&format=' + encodeURIComponent(format.trim().toLowerCase()) + '&fps=' + Math.max(1, parseInt(fps) || 10);
+
+    if (data_band_lo > 0 && data_band_hi > 0)
+        url += '&frame_start=' + data_band_lo + '&frame_end=' + data_band_hi + '&ref_freq=' + RESTFRQ;
+
+    try {
+        url += '&flux=' + document.getElementById('flux' + index).value;
+    }
+    catch (e) {
+    };
+
+    url += '&label=' + (confirm("overlay the frame labels?") ? 'true' : 'false');
+
+    var xmlhttp = new XMLHttpRequest();
+
+    xmlhttp.onreadystatechange = function () {
+        if (xmlhttp.readyState != 4)
+            return;
+
+        var response = null;
+
+        try {
+            response = JSON.parse(xmlhttp.responseText);
+        }
+        catch (e) {
+            alert("video export failed: " + xmlhttp.status);
+            return;
+        };
+
+        poll_video_export(response.job);
+    };
+
+    xmlhttp.open("GET", url, true);
+    xmlhttp.send();
+}
+
+function poll_video_export(job) {
+    var xmlhttp = new XMLHttpRequest();
+    var url = 'export_video_status?job=' + encodeURIComponent(job);
+
+    xmlhttp.onreadystatechange = function () {
+        if (xmlhttp.readyState != 4)
+            return;
+
+        var response = null;
+
+        try {
+            response = JSON.parse(xmlhttp.responseText);
+        }
+        catch (e) {
+            console.log("export_video_status:", xmlhttp.status, xmlhttp.responseText);
+            return;
+        };
+
+        if (xmlhttp.status == 200) {
+            d3.select("#fps").text('video export: done');
+            window.location.href = response.url.substring(response.url.lastIndexOf('/') + 1);
+        } else if (xmlhttp.status == 202) {
+            d3.select("#fps").text('video export: ' + Math.round(100 * response.progress) + '%');
+            setTimeout(function () { poll_video_export(job); }, 2000);
+        } else if (xmlhttp.status == 422) {
+            alert("video export failed: " + response.message);
+        } else {
+            alert("video export not found");
+        }
+    };
+
+    xmlhttp.open("GET", url, true);
+    xmlhttp.send();
+}
+
+//red, green and blue for up to three channels, evenly spaced hues from red to violet otherwise
+function default_composite_colour(index, count) {
+    if (count <= 3)
+        return [0, 1, 2].map(c => c == index % 3 ? 1 : 0);
+
+    let h = COMPOSITE_LAST_HUE * index / (count - 1) / 60;
+    let x = 1 - Math.abs((h % 2) - 1);
+
+    switch (Math.floor(h)) {
+        case 0: return [1, x, 0];
+        case 1: return [x, 1, 0];
+        case 2: return [0, 1, x];
+        case 3: return [0, x, 1];
+        case 4: return [x, 0, 1];
+        default: return [1, 0, x];
+    }
+}
+
+function hex_to_rgb(hex) {
+    let match = /^#?([0-9a-f]{2})([0-9a-f]{2})([0-9a-f]{2})$/i.exec(hex.trim());
+
+    if (match == null)
+        return null;
+
+    return [1, 2, 3].map(i => parseInt(match[i], 16) / 255);
+}
+
+function rgb_to_hex(rgb) {
+    return '#' + rgb.map(c => Math.round(255 * c).toString(16).padStart(2, '0')).join('');
+}
+
+//the colours of the live composite channels, one per dataset
+function composite_tints() {
+    let tints = [];
+
+    for (let index = 0; index < va_count; index++) {
+        let channel = composite_channels[index];
+        let colour = channel != undefined ? hex_to_rgb(channel.colour) : null;
+
+        tints.push({
+            colour: colour != null ? colour : default_composite_colour(index, va_count),
+            weight: channel != undefined ? channel.weight : 1
+        });
+    }
+
+    return tints;
+}
+
+//the legend colour of a composite channel pixel value (0-255)
+function composite_legend_colour(pixel, channel, colourmap) {
+    if (composite_channels.length == 0 && va_count <= 3)
+        return interpolate_colourmap(pixel, colourmap, 0.8);
+
+    let colour = composite_tints()[channel].colour;
+
+    return 'rgba(' + colour.map(c => Math.round(c * pixel)).join(',') + ',0.8)';
+}
+
+function send_composite_colours() {
+    if (composite_channels.length == 0 || wsConn == null || wsConn[0] == null)
+        return;
+
+    let channels = composite_tints().map(tint => ({ colour: rgb_to_hex(tint.colour), weight: tint.weight }));
+
+    wsConn[0].send('[composite] channels=' + JSON.stringify(channels));
+}
+
+//place the composite image onto the main canvas
+function redraw_composite_image() {
+    if (compositeCanvas == null || compositeImageData == null || imageContainer[va_count - 1] == null)
+        return;
+
+    compositeCanvas.getContext('2d').putImageData(compositeImageData, 0, 0);
+
+    let image_bounding_dims = imageContainer[va_count - 1].image_bounding_dims;
+    var c = document.getElementById('HTMLCanvas');
+    var width = c.width;
+    var height = c.height;
+    var ctx = c.getContext("2d");
+
+    ctx.imageSmoothingEnabled = false;
+
+    var scale = get_image_scale(width, height, image_bounding_dims.width, image_bounding_dims.height);
+    var img_width = scale * image_bounding_dims.width;
+    var img_height = scale * image_bounding_dims.height;
+
+    ctx.clearRect((width - img_width) / 2, (height - img_height) / 2, img_width, img_height);
+    ctx.drawImage(compositeCanvas, image_bounding_dims.x1, image_bounding_dims.y1, image_bounding_dims.width, image_bounding_dims.height, (width - img_width) / 2, (height - img_height) / 2, img_width, img_height);
+}
+
+//change the colour and the weight of each live composite channel
+function set_composite_colours() {
+    let tints = composite_tints();
+    let channels = [];
+
+    for (let index = 0; index < va_count; index++) {
+        let fitsData = fitsContainer[index];
+        let line = fitsData != null ? (fitsData.FILTER.trim() != "" ? fitsData.FILTER.trim() : fitsData.LINE.trim()) : "";
+
+        if (line == "")
+            line = "line-" + (index + 1);
+
+        let value = prompt(line + ": colour (#rrggbb) and weight", rgb_to_hex(tints[index].colour) + ' ' + tints[index].weight);
+
+        if (value == null)
+            return;
+
+        let parts = value.trim().split(/\s+/);
+        let colour = hex_to_rgb(parts[0]);
+        let weight = parts.length > 1 ? parseFloat(parts[1]) : 1;
+
+        if (colour == null || !(weight >= 0)) {
+            alert("invalid colour or weight: " + value);
+            return;
+        }
+
+        channels.push({ colour: rgb_to_hex(colour), weight: weight });
+    }
+
+    composite_channels = channels;
+
+    mix_composite_channels(compositeImageData, composite_tints());
+    mix_composite_channels(compositeViewportImageData, composite_tints());
+    redraw_composite_image();
+
+    try {
+        d3.select("#foreignRGBGroup").remove();
+        display_composite_legend();
+        display_rgb_legend();
+    }
+    catch (e) {
+    };
+
+    send_composite_colours();
+}
+
+//parse "dataset=1 range=lo:hi colour=#ff0000 weight=1 stretch=asinh"
+function parse_composite_channel(spec, channel) {
+    spec.trim().split(/\s+/).forEach(function (token) {
+        let pos = token.indexOf('=');
+
+        if (pos < 0)
+            return;
+
+        let key = token.substring(0, pos);
+        let value = token.substring(pos + 1);
+
+        switch (key) {
+            case 'dataset':
+                let index = parseInt(value);
+
+                if (index >= 1 && index <= va_count)
+                    channel.datasetId = va_count == 1 ? datasetId : datasetId[index - 1];
+                break;
+            case 'range':
+                let range = value.split(':').map(parseFloat);
+
+                if (range.length == 2 && range.every(x => isFinite(x))) {
+                    channel.frame_start = range[0];
+                    channel.frame_end = range[1];
+                }
+                break;
+            case 'colour':
+                if (hex_to_rgb(value) != null)
+                    channel.colour = value;
+                break;
+            case 'weight':
+                if (parseFloat(value) >= 0)
+                    channel.weight = parseFloat(value);
+                break;
+            case 'stretch':
+                channel.flux = value;
+                break;
+        }
+    });
+
+    return channel;
+}
+
+//an N-channel colour composite rendered on the server: several datasets (regridded onto the
+//first one) and/or several frame ranges of a single cube, each with its own colour and stretch
+function make_composite() {
+    let fitsData = fitsContainer[0];
+    let cube = fitsData != null && fitsData.depth > 1 && data_band_lo > 0 && data_band_hi > 0;
+
+    var count = prompt("number of channels (up to " + COMPOSITE_MAX_CHANNELS + "):", va_count > 1 ? va_count : 3);
+
+    if (count == null)
+        return;
+
+    count = Math.min(Math.max(parseInt(count) || 1, 1), COMPOSITE_MAX_CHANNELS);
+
+    let flux = 'linear';
+
+    try {
+        flux = document.getElementById('flux1').value;
+    }
+    catch (e) {
+    };
+
+    let channels = [];
+
+    for (let k = 0; k < count; k++) {
+        //one channel per dataset by default, a single cube gets split into equal frame ranges
+        let index = va_count > 1 ? k % va_count : 0;
+        let spec = 'dataset=' + (index + 1);
+
+        if (cube) {
+            let [lo, hi] = va_count > 1 ? [data_band_lo, data_band_hi] : [data_band_lo + (data_band_hi - data_band_lo) * k / count, data_band_lo + (data_band_hi - data_band_lo) * (k + 1) / count];
+            spec += ' range=' + lo + ':' + hi;
+        }
+
+        spec += ' colour=' + rgb_to_hex(default_composite_colour(k, count)) + ' weight=1 stretch=' + flux;
+
+        let value = prompt("channel " + (k + 1) + " of " + count + ":", spec);
+
+        if (value == null)
+            return;
+
+        let channel = parse_composite_channel(value, { datasetId: va_count == 1 ? datasetId : datasetId[index], ref_freq: RESTFRQ });
+        channels.push(channel);
+    }
+
+    var width = prompt("composite width [pixels]:", fitsData != null ? fitsData.width : 1024);
+
+    if (width == null)
+        return;
+
+    var url = 'get_composite?channels=' + encodeURIComponent(JSON.stringify(channels));
+
+    if (parseInt(width) > 0)
+        url += '&width=' + parseInt(width);
+
+    console.log("colour composite:", url);
+    window.open(url, '_blank');
+}
+
+function fetch_saved_view() {
+    var view_id = new URLSearchParams(window.location.search).get('view_id');
+
+    if (view_id == null)
+        return;
+
+    var xmlhttp = new XMLHttpRequest();
+
+    xmlhttp.onreadystatechange = function () {
+        if (xmlhttp.readyState == 4) {
+            if (xmlhttp.status == 200) {
+                saved_view = JSON.parse(xmlhttp.responseText).state;
+                console.log("saved view:", saved_view);
+
+                apply_saved_view();
+            }
+            else
+                console.log("get_view error:", xmlhttp.status, xmlhttp.responseText);
+        }
+    };
+
+    xmlhttp.open("GET", "get_view?id=" + encodeURIComponent(view_id), true);
+    xmlhttp.send();
+}
+
+//restore a saved view once the initial image(s) and histogram(s) are in place
+function apply_saved_view() {
+    if (saved_view == null || spectrum_count < va_count || imageContainer[va_count - 1] == null)
+        return;
+
+    var view = saved_view;
+    saved_view = null;
+
+    apply_view_state(view);
+}
+
+function apply_view_state(view) {
+    if (view.frame_start != null && view.frame_end != null) {
+        data_band_lo = view.frame_start;
+        data_band_hi = view.frame_end;
+    }
+
+    if (view.ref_freq != null)
+        RESTFRQ = view.ref_freq;
+
+    if (view.user_selfrq != null)
+        USER_SELFRQ = view.user_selfrq;
+
+    for (let index = 1; index <= va_count; index++) {
+        if (view.params == null || view.params[index - 1] == null)
+            continue;
+
+        var params = view.params[index - 1];
+
+        if (params.flux != null) {
+            document.getElementById('flux' + index).value = params.flux;
+            setup_histogram_interaction(index);
+        }
+
+        if (params.noise != null) {
+            noise_sensitivity = params.noise;
+            document.getElementById('sensitivity' + index).value = noise_sensitivity;
+            document.getElementById('sensitivityInput' + index).innerHTML = get_noise_sensitivity_string(noise_sensitivity, 2);
+        }
+
+        var flux_elem = d3.select("#flux_path" + index);
+
+        ['black', 'white', 'median'].forEach(function (key) {
+            if (params[key] != null && !isNaN(params[key]))
+                flux_elem.attr(key, params[key]);
+        });
+    }
+
+    display_hourglass();
+
+    image_count = 0;
+    viewport_count = 0;
+
+    for (let index = 1; index <= va_count; index++)
+        image_refresh(index, false);
+
+    display_molecules();
+
+    if (view.region != null)
+        display_saved_region(view.region);
+}
+
+//"follow me" collaborative sessions; all the requests go through the first WebSocket connection
+function follow_request(action, to) {
+    if (wsConn[0] == null)
+        return;
+
+    var msg = '[follow] action=' + action;
+
+    if (to != null)
+        msg += '&to=' + to;
+
+    wsConn[0].send(msg);
+}
+
+function follow_handover() {
+    if (follow_role != "presenter")
+        return;
+
+    if (follow_followers.length == 0) {
+        alert("there are no followers to hand the control over to");
+        return;
+    }
 
-    var multiplier = get_noise_sensitivity(noise_sensitivity);
-    document.getElementById('sensitivityInput' + index).innerHTML = get_noise_sensitivity_string(noise_sensitivity, 2);
+    var list = "";
 
-    var c = document.getElementById("HistogramCanvas" + index);
-    var svg = d3.select("#HistogramSVG" + index);
-    var width = c.width;
-    var height = c.height;
+    for (let i = 0; i < follow_followers.length; i++)
+        list += (i + 1) + ": " + follow_followers[i] + "\n";
 
-    var flux_elem = d3.select("#flux_path" + index);
+    var choice = prompt("hand the control over to follower no.:\n" + list, "1");
 
-    var black, white, median;
+    if (choice == null)
+        return;
 
-    try {
-        black = parseFloat(flux_elem.attr("black"));
+    var i = parseInt(choice) - 1;
+
+    if (isNaN(i) || i < 0 || i >= follow_followers.length)
+        return;
+
+    follow_request('handover', follow_followers[i]);
+}
+
+function process_follow_event(data) {
+    console.log("follow event:", data);
+
+    if (data.event == "rejected") {
+        display_follow_status(data.reason);
+        return;
     }
-    catch (e) {
-    };
 
-    try {
-        white = parseFloat(flux_elem.attr("white"));
+    var previous_role = follow_role;
+
+    follow_role = data.role;
+    follow_followers = data.followers;
+
+    if (follow_role == "presenter" && previous_role != "presenter") {
+        follow_last_state = "";
+        follow_interval = setInterval(send_follow_state, FOLLOW_STATE_INTERVAL);
     }
-    catch (e) {
-    };
 
-    try {
-        median = parseFloat(flux_elem.attr("median"));
+    if (follow_role != "presenter" && previous_role == "presenter") {
+        clearInterval(follow_interval);
+        follow_interval = -1;
     }
-    catch (e) {
-    };
 
-    var path = get_flux_path(width, height, document.getElementById('flux' + index).value, black, white, median, multiplier, index);
+    if (follow_role != "follower") {
+        follow_applied_key = "";
+        d3.select("#followCursor").remove();
+    }
 
-    flux_elem.attr("d", path);
+    var status = "";
 
-    if (refresh) {
-        display_hourglass();
+    switch (data.event) {
+        case "present":
+            status = (follow_role == "presenter") ? "you are presenting" : "a presentation has started, View/follow the presenter to join";
+            break;
+        case "join":
+            status = "a follower has joined";
+            break;
+        case "leave":
+            status = "a follower has left";
+            break;
+        case "handover":
+            status = (follow_role == "presenter") ? "you are now in control" : "the control has been handed over";
+            break;
+        case "end":
+            status = "the presentation has ended";
+            break;
+    }
 
+    if (follow_role == "presenter")
+        status += " (" + follow_followers.length + " follower" + (follow_followers.length == 1 ? ")" : "s)");
 
-        if (!composite_view) {
-            image_count = va_count - 1;
+    display_follow_status(status);
+}
 
-            image_refresh(index, false);
-        }
-        else {
-            image_count = 0;
+function display_follow_status(status) {
+    d3.select("#followStatus").remove();
 
-            for (let i = 1; i <= va_count; i++)
-                image_refresh(i, false);
-        }
-    }
+    d3.select("body")
+        .append("div")
+        .attr("id", "followStatus")
+        .style("position", "fixed")
+        .style("bottom", "1em")
+        .style("left", "1em")
+        .style("padding", "0.5em")
+        .style("z-index", "100")
+        .style("color", "white")
+        .style("background-color", "rgba(0, 0, 0, 0.6)")
+        .style("font-family", "Inconsolata")
+        .text(status)
+        .transition()
+        .delay(FOLLOW_STATUS_TIMEOUT)
+        .duration(500)
+        .style("opacity", 0.0)
+        .remove();
 }
 
-function partial_fits_download(offsetx, offsety, width, height) {
-    mousedown = false;
-    d3.select("#region").attr("opacity", 0.0);
+//the presenter sends its view state whenever it changes
+function send_follow_state() {
+    if (follow_role != "presenter" || wsConn[0] == null || imageContainer[va_count - 1] == null)
+        return;
 
-    let fitsData = fitsContainer[va_count - 1];
-    var image_bounding_dims = imageContainer[va_count - 1].image_bounding_dims;
-    var imageCanvas = imageContainer[va_count - 1].imageCanvas;
+    var state = JSON.stringify(get_view_state());
 
-    var x1 = image_bounding_dims.x1 + (begin_x - offsetx) / width * (image_bounding_dims.width - 1);
-    var y1 = image_bounding_dims.y1 + (begin_y - offsety) / height * (image_bounding_dims.height - 1);
+    if (state == follow_last_state)
+        return;
 
-    var orig_x1 = x1 * fitsData.width / imageCanvas.width;
-    var orig_y1 = fitsData.height - y1 * fitsData.height / imageCanvas.height;
+    follow_last_state = state;
+    wsConn[0].send('[follow_state] ' + state);
+}
 
-    var x2 = image_bounding_dims.x1 + (end_x - offsetx) / width * (image_bounding_dims.width - 1);
-    var y2 = image_bounding_dims.y1 + (end_y - offsety) / height * (image_bounding_dims.height - 1);
+//a cursor position relative to the image rectangle
+function send_follow_cursor(x, y) {
+    if (follow_role != "presenter" || wsConn[0] == null)
+        return;
 
-    var orig_x2 = x2 * fitsData.width / imageCanvas.width;
-    var orig_y2 = fitsData.height - y2 * fitsData.height / imageCanvas.height;
+    var now = performance.now();
 
-    var url = "get_fits?";
+    if (now - follow_cursor_timestamp < FOLLOW_CURSOR_INTERVAL)
+        return;
 
-    if (va_count == 1)
-        url += "datasetId=" + encodeURIComponent(datasetId) + "&";
-    else {
-        for (let index = 1; index <= va_count; index++)
-            url += "datasetId" + index + "=" + encodeURIComponent(datasetId[index - 1]) + "&";
+    follow_cursor_timestamp = now;
+    wsConn[0].send('[follow_cursor] ' + JSON.stringify({ x: x, y: y }));
+}
+
+function apply_follow_state(view) {
+    if (follow_role != "follower")
+        return;
+
+    //not ready yet, apply once the initial image is in place
+    if (spectrum_count < va_count || imageContainer[va_count - 1] == null) {
+        saved_view = view;
+        return;
     }
 
-    url += "x1=" + Math.round(orig_x1) + "&y1=" + Math.round(orig_y2) + "&x2=" + Math.round(orig_x2) + "&y2=" + Math.round(orig_y1) + "&frame_start=" + data_band_lo + "&frame_end=" + data_band_hi + "&ref_freq=" + RESTFRQ;
+    //only changes of the frame range or contrast require a new image
+    var key = JSON.stringify({ frame_start: view.frame_start, frame_end: view.frame_end, ref_freq: view.ref_freq, params: view.params });
 
-    //console.log(url) ;
-    //window.location.assign(url);
-    window.open(url, '_blank');
-    window.focus();
+    if (key != follow_applied_key) {
+        follow_applied_key = key;
+        apply_view_state(view);
+    }
+    else if (view.region != null)
+        display_saved_region(view.region);
+
+    if (view.region == null) {
+        selected_region = null;
+        d3.select("#region").attr("opacity", 0.0);
+    }
 }
 
-function ok_download() {
-    $('#downloadConfirmation').modal('hide');
-    d3.select("#downloadConfirmation").remove();
+function display_follow_cursor(cursor) {
+    if (follow_role != "follower")
+        return;
 
-    partial_fits_download(d3.select("#image_rectangle").attr("x"), d3.select("#image_rectangle").attr("y"), d3.select("#image_rectangle").attr("width"), d3.select("#image_rectangle").attr("height"));
-};
+    var elem = d3.select("#image_rectangle");
 
-function cancel_download() {
-    mousedown = false;
-    d3.select("#region").attr("opacity", 0.0);
+    if (elem.empty())
+        return;
 
-    $('#downloadConfirmation').modal('hide');
-    d3.select("#downloadConfirmation").remove();
-};
+    var x = parseFloat(elem.attr("x")) + cursor.x * parseFloat(elem.attr("width"));
+    var y = parseFloat(elem.attr("y")) + cursor.y * parseFloat(elem.attr("height"));
 
-function show_download_confirmation() {
-    var modal = document.getElementById('downloadConfirmation');
-    var span = document.getElementById('downloadconfirmationclose');
+    if (d3.select("#followCursor").empty())
+        d3.select("#FrontSVG")
+            .append("circle")
+            .attr("id", "followCursor")
+            .attr("r", emFontSize / 2)
+            .attr("fill", "none")
+            .attr("pointer-events", "none")
+            .style("stroke", "orange")
+            .style("stroke-width", emStrokeWidth);
 
-    // When the user clicks on <span> (x), close the modal
-    span.onclick = function () {
-        $('#downloadConfirmation').modal('hide');
-        d3.select("#downloadConfirmation").remove();
+    d3.select("#followCursor")
+        .attr("cx", x)
+        .attr("cy", y);
+}
 
-        mousedown = false;
-        d3.select("#region").attr("opacity", 0.0);
-    }
-    // When the user clicks a mouse, close it
-    window.onclick = function (event) {
-        if (event.target == modal) {
-            $('#downloadConfirmation').modal('hide');
-            d3.select("#downloadConfirmation").remove();
+function display_saved_region(region) {
+    let fitsData = fitsContainer[va_count - 1];
+    var image_bounding_dims = imageContainer[va_count - 1].image_bounding_dims;
+    var imageCanvas = imageContainer[va_count - 1].imageCanvas;
 
-            mousedown = false;
-            d3.select("#region").attr("opacity", 0.0);
-        }
-    }
-}
+    var elem = d3.select("#image_rectangle");
 
-function show_scaling_help() {
-    var modal = document.getElementById('scalingHelp');
-    var span = document.getElementById('scalingHeaderClose');
+    if (elem.empty())
+        return;
 
-    // When the user clicks on <span> (x), close the modal
-    span.onclick = function () {
-        $('#scalingHelp').modal('hide');
-        d3.select("#scalingHelp").remove();
-    }
-    // When the user moves a mouse, close it
-    window.onmousemove = function (event) {
-        if (event.target == modal) {
-            $('#scalingHelp').modal('hide');
-            d3.select("#scalingHelp").remove();
-        }
-    }
+    var offsetx = parseFloat(elem.attr("x"));
+    var offsety = parseFloat(elem.attr("y"));
+    var width = parseFloat(elem.attr("width"));
+    var height = parseFloat(elem.attr("height"));
+
+    //the inverse of the partial_fits_download() transformation
+    var to_x = function (x) {
+        return offsetx + (x * imageCanvas.width / fitsData.width - image_bounding_dims.x1) / (image_bounding_dims.width - 1) * width;
+    };
+
+    var to_y = function (y) {
+        return offsety + ((fitsData.height - y) * imageCanvas.height / fitsData.height - image_bounding_dims.y1) / (image_bounding_dims.height - 1) * height;
+    };
+
+    var x1 = to_x(region.x1), x2 = to_x(region.x2);
+    var y1 = to_y(region.y2), y2 = to_y(region.y1);
+
+    selected_region = region;
+
+    d3.select("#region")
+        .attr("x", Math.min(x1, x2))
+        .attr("y", Math.min(y1, y2))
+        .attr("width", Math.abs(x2 - x1))
+        .attr("height", Math.abs(y2 - y1))
+        .attr("opacity", 1.0);
 }
 
 function show_fits_header() {
@@ -10892,6 +12673,65 @@ function display_menu() {
         .on("click", show_fits_header)
         .html('display header');
 
+    fitsDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", save_view)
+        .html('save view (permalink) <span class="fas fa-link"></span>');
+
+    fitsDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", function () {
+            save_snapshot();
+        })
+        .html('save PNG snapshot <span class="fas fa-camera"></span>');
+
+    fitsDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", function () {
+            save_snapshot('snr');
+        })
+        .html('save SNR map (PNG) <span class="fas fa-camera"></span>');
+
+    fitsDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", download_noise_stats)
+        .html('channel noise statistics (CSV) <span class="fas fa-table"></span>');
+
+    fitsDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", region_photometry)
+        .html('region photometry (CSV) <span class="fas fa-bullseye"></span>');
+
+    fitsDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", make_hips)
+        .html('generate HiPS tiles <span class="fas fa-globe"></span>');
+
+    fitsDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", export_video)
+        .html('export video <span class="fas fa-film"></span>');
+
+    fitsDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", make_composite)
+        .html('colour composite <span class="fas fa-palette"></span>');
+
+    if (composite_view)
+        fitsDropdown.append("li")
+            .append("a")
+            .style('cursor', 'pointer')
+            .on("click", set_composite_colours)
+            .html('composite channel colours');
+
     if (!isLocal && va_count == 1 && (window.location.search.indexOf('ALMA') > 0 || window.location.search.indexOf('ALMB') > 0 || window.location.search.indexOf('FGN') > 0 || window.location.search.indexOf('CMG') > 0 || window.location.search.indexOf('SFP') > 0 || window.location.search.indexOf('NROA') > 0)) {
         var url = "";
 
@@ -11153,6 +12993,30 @@ function display_menu() {
         .attr("id", "viewDropdown")
         .attr("class", "dropdown-menu");
 
+    viewDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", function () { follow_request('present'); })
+        .html('<span class="fas fa-chalkboard-teacher"></span> present (follow me)');
+
+    viewDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", function () { follow_request('join'); })
+        .html('<span class="fas fa-users"></span> follow the presenter');
+
+    viewDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", follow_handover)
+        .html('<span class="fas fa-exchange-alt"></span> hand over the control');
+
+    viewDropdown.append("li")
+        .append("a")
+        .style('cursor', 'pointer')
+        .on("click", function () { follow_request('leave'); })
+        .html('<span class="fas fa-sign-out-alt"></span> stop presenting/following');
+
     if (has_webgl) {
         if (va_count == 1 || composite_view) {
             var htmlStr = '<i class="material-icons">3d_rotation</i> 3D surface';
@@ -11499,6 +13363,31 @@ function show_unsupported_media_type() {
         .text("FITSWEBQL SUPPORTS ONLY FITS DATA");
 }
 
+function show_download_failed() {
+    try {
+        $('#welcomeScreen').modal('hide');
+    }
+    catch (e) { };
+
+    var div = d3.select("body")
+        .append("div")
+        .attr("class", "container timeout");
+
+    var title = div.append("h1")
+        .style("margin-top", "25%")
+        .style("color", "red")
+        .attr("align", "center")
+        .text("DOWNLOAD FAILED");
+
+    div.append("h2")
+        .attr("align", "center")
+        .text("THE REMOTE SERVER DID NOT SEND THE COMPLETE FITS FILE");
+
+    div.append("h2")
+        .attr("align", "center")
+        .text("PLEASE RELOAD THE PAGE TO RESUME THE DOWNLOAD");
+}
+
 function show_not_found() {
     try {
         $('#welcomeScreen').modal('hide');
@@ -12080,6 +13969,63 @@ function download_confirmation() {
     bodyDiv.append("p")
         .html("Selecting a sub-region triggers a corresponding partial FITS file download.");
 
+    //optional downsampling of large cut-outs
+    var binning = bodyDiv.append("p")
+        .html("binning:&nbsp;spatial&nbsp;");
+
+    binning.append("select")
+        .attr("id", "spatial_binning")
+        .attr("onchange", "cutout_binning.spatial = parseInt(this.value)")
+        .selectAll("option")
+        .data([1, 2, 4, 8, 16])
+        .enter()
+        .append("option")
+        .attr("value", d => d)
+        .property("selected", d => d == cutout_binning.spatial)
+        .text(d => d == 1 ? "none" : d + "×" + d);
+
+    binning.append("span")
+        .html("&nbsp;spectral&nbsp;");
+
+    binning.append("select")
+        .attr("id", "spectral_binning")
+        .attr("onchange", "cutout_binning.spectral = parseInt(this.value)")
+        .selectAll("option")
+        .data([1, 2, 4, 8, 16, 32])
+        .enter()
+        .append("option")
+        .attr("value", d => d)
+        .property("selected", d => d == cutout_binning.spectral)
+        .text(d => d == 1 ? "none" : "×" + d);
+
+    binning.append("span")
+        .html("&nbsp;");
+
+    binning.append("select")
+        .attr("id", "spectral_binning_mode")
+        .attr("onchange", "cutout_binning.mode = this.value")
+        .selectAll("option")
+        .data(['mean', 'decimate'])
+        .enter()
+        .append("option")
+        .attr("value", d => d)
+        .property("selected", d => d == cutout_binning.mode)
+        .text(d => d == 'mean' ? "average channels" : "keep every N-th channel");
+
+    //the array formats keep the header values as attributes
+    bodyDiv.append("p")
+        .html("format:&nbsp;")
+        .append("select")
+        .attr("id", "cutout_format")
+        .attr("onchange", "cutout_format = this.value")
+        .selectAll("option")
+        .data([['fits', "FITS"], ['hdf5', "HDF5"], ['zarr', "Zarr (zip)"], ['npy', "NumPy (npz)"]])
+        .enter()
+        .append("option")
+        .attr("value", d => d[0])
+        .property("selected", d => d[0] == cutout_format)
+        .text(d => d[1]);
+
     var p = bodyDiv.append("p")
         .html("Proceed with the download?");
 
@@ -12118,7 +14064,7 @@ function download_confirmation() {
 function display_rgb_legend() {
     console.log("display_rgb_legend()");
 
-    if (va_count > 3)
+    if (va_count > COMPOSITE_MAX_CHANNELS)
         return;
 
     for (let index = 1; index <= va_count; index++) {
@@ -12223,7 +14169,7 @@ function display_rgb_legend() {
             //.attr("stroke-width", 0.1)
             .attr("stroke", "none")
             //.attr('fill', function(d, i) { return pixel2rgba(1.0*d, index-1, 0.8);});
-            .attr('fill', function (d, i) { return interpolate_colourmap(d, rgb[index - 1], 0.8); });
+            .attr('fill', function (d, i) { return composite_legend_colour(d, index - 1, rgb[index - 1]); });
 
         var colourScale = d3.scaleLinear()
             .range([0.8 * height, 0])
@@ -13051,6 +14997,13 @@ async*/ function mainRenderer() {
     else
         intensity_mode = localStorage.getItem("intensity_mode");
 
+    if (localStorage.getItem("spectrum_format") === null) {
+        spectrum_format = "csv";
+        localStorage.setItem("spectrum_format", spectrum_format);
+    }
+    else
+        spectrum_format = localStorage.getItem("spectrum_format");
+
     if (localStorage.getItem("colourmap") === null) {
         if (theme == 'bright')
             colourmap = "haxby";
@@ -13092,6 +15045,13 @@ async*/ function mainRenderer() {
     else
         video_fps_control = localStorage.getItem("video_fps_control");
 
+    if (localStorage.getItem("luma_bits") === null) {
+        luma_bits = "8";
+        localStorage.setItem("luma_bits", luma_bits);
+    }
+    else
+        luma_bits = localStorage.getItem("luma_bits");
+
     composite_view = (parseInt(votable.getAttribute('data-composite')) == 1) ? true : false;
     console.log("composite view:", composite_view);
 
@@ -13120,6 +15080,14 @@ async*/ function mainRenderer() {
         freqdrag = false;
         data_band_lo = 0;
         data_band_hi = 0;
+        saved_view = null;
+        selected_region = null;
+        follow_role = "none";
+        follow_followers = [];
+        follow_last_state = "";
+        follow_applied_key = "";
+        follow_interval = -1;
+        follow_cursor_timestamp = 0;
         latency = 0;
         ping_latency = 0;
         computed = 0;
@@ -13151,7 +15119,13 @@ async*/ function mainRenderer() {
         recv_vid_id = 0;
         sent_vid_id = 0;
         last_vid_id = 0;
+        video_freq = 0;
         videoFrame = [];
+        av1Decoder = [];
+        tile_pyramid = null;
+        pyramid_tiles = new Map();
+        av1Keyframe = [];
+        has_av1 = false;
 
         spectrum_stack = [];
         image_stack = [];
@@ -13286,12 +15260,17 @@ async*/ function mainRenderer() {
         spectrum_scale = new Array(va_count);
         videoFrame = new Array(va_count);
         video_stack = new Array(va_count);
+        av1Decoder = new Array(va_count);
+        av1Keyframe = new Array(va_count);
+        has_av1 = votable.getAttribute('data-has-av1') == 'true';
 
         for (let i = 0; i < va_count; i++) {
             spectrum_stack[i] = [];
             spectrum_scale[i] = 1;
             video_stack[i] = [];
             videoFrame[i] = null;
+            av1Decoder[i] = null;
+            av1Keyframe[i] = false;
         };
 
         if (va_count > 1) {
@@ -13515,6 +15494,8 @@ async*/ function mainRenderer() {
         viewport_count = 0;
         spectrum_count = 0;
 
+        fetch_saved_view();
+
         if (va_count == 1) {
             open_websocket_connection(datasetId, 1);
 
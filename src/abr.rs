@@ -0,0 +1,356 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+//the encoder bitrate limits [kilobits per second]
+pub const MIN_BITRATE: i32 = 100;
+pub const MAX_BITRATE: i32 = 10000;
+
+//video frames sent but not yet acknowledged by the client
+const MAX_IN_FLIGHT: usize = 3;
+const SKIP_IN_FLIGHT: usize = 6;
+
+//unacknowledged frames older than this are considered lost
+const FRAME_TIMEOUT: Duration = Duration::from_secs(2);
+
+//do not cut the bitrate more often than this
+const DECREASE_INTERVAL: Duration = Duration::from_millis(250);
+
+//how long the link needs to stay congested (or clear) before changing the video resolution
+const DOWNSCALE_DELAY: Duration = Duration::from_secs(2);
+const UPSCALE_DELAY: Duration = Duration::from_secs(10);
+
+//the available video resolution scales
+const SCALES: [f32; 3] = [1.0, 0.75, 0.5];
+
+//the smoothing factor for the exponentially-weighted moving averages
+const EWMA_ALPHA: f64 = 0.2;
+
+#[derive(Debug)]
+struct SentFrame {
+    seq_id: i32,
+    sent: Instant,
+    bytes: usize,
+}
+
+/// what to do with the next video frame
+#[derive(Debug)]
+pub struct Decision {
+    pub bitrate: i32,
+    pub skip: bool,
+    pub rescale: Option<f32>,
+}
+
+/// server-side adaptive bitrate control for a video stream
+///
+/// The bitrate follows an AIMD scheme driven by the number of unacknowledged frames
+/// and the decode time reported by the client in [video_ack]. The client-estimated
+/// bandwidth (bitrate= in [video]) remains the upper bound. A prolonged congestion
+/// lowers the video resolution, a prolonged clear link restores it.
+#[derive(Debug)]
+pub struct RateController {
+    bitrate: f64,
+    in_flight: VecDeque<SentFrame>,
+    decode_ms: f64,
+    rtt_ms: f64,
+    throughput: f64, //[kilobits per second]
+    acknowledged: bool,
+    last_decrease: Instant,
+    congested_since: Option<Instant>,
+    clear_since: Option<Instant>,
+    level: usize,
+}
+
+impl Default for RateController {
+    fn default() -> RateController {
+        RateController::new(1000, 1.0)
+    }
+}
+
+impl RateController {
+    pub fn new(bitrate: i32, scale: f32) -> RateController {
+        let level = SCALES
+            .iter()
+            .position(|&s| scale >= s)
+            .unwrap_or(SCALES.len() - 1);
+
+        RateController {
+            bitrate: num::clamp(bitrate, MIN_BITRATE, MAX_BITRATE) as f64,
+            in_flight: VecDeque::new(),
+            decode_ms: 0.0,
+            rtt_ms: 0.0,
+            throughput: 0.0,
+            acknowledged: false,
+            last_decrease: Instant::now(),
+            congested_since: None,
+            clear_since: None,
+            level: level,
+        }
+    }
+
+    pub fn scale(&self) -> f32 {
+        SCALES[self.level]
+    }
+
+    /// record the bytes sent for a video frame (called once per WebSocket message)
+    pub fn sent(&mut self, seq_id: i32, bytes: usize) {
+        if let Some(last) = self.in_flight.back_mut() {
+            if last.seq_id == seq_id {
+                last.bytes += bytes;
+                return;
+            }
+        }
+
+        self.in_flight.push_back(SentFrame {
+            seq_id: seq_id,
+            sent: Instant::now(),
+            bytes: bytes,
+        });
+    }
+
+    /// the client has decoded a video frame
+    pub fn ack(&mut self, seq_id: i32, decode_ms: f64) {
+        let now = Instant::now();
+
+        while let Some(frame) = self.in_flight.front() {
+            if frame.seq_id > seq_id {
+                break;
+            }
+
+            if frame.seq_id == seq_id {
+                let rtt = now.duration_since(frame.sent).as_secs_f64();
+                let kbps = (8 * frame.bytes) as f64 / 1000.0 / rtt.max(0.001);
+
+                self.rtt_ms = ewma(self.rtt_ms, 1000.0 * rtt, self.acknowledged);
+                self.throughput = ewma(self.throughput, kbps, self.acknowledged);
+            }
+
+            self.in_flight.pop_front();
+        }
+
+        if decode_ms.is_finite() && decode_ms >= 0.0 {
+            self.decode_ms = ewma(self.decode_ms, decode_ms, self.acknowledged);
+        }
+
+        self.acknowledged = true;
+    }
+
+    /// decide upon the bitrate for the next frame; client_bitrate is the client-side bandwidth estimate
+    pub fn next_frame(&mut self, client_bitrate: i32, fps: f64, keyframe: bool) -> Decision {
+        let now = Instant::now();
+        let client_bitrate = num::clamp(client_bitrate, MIN_BITRATE, MAX_BITRATE) as f64;
+
+        //older clients do not send [video_ack]; just follow their estimate
+        if !self.acknowledged {
+            self.in_flight.clear();
+            self.bitrate = client_bitrate;
+
+            return Decision {
+                bitrate: client_bitrate as i32,
+                skip: false,
+                rescale: None,
+            };
+        }
+
+        //forget the frames lost in transit
+        while let Some(frame) = self.in_flight.front() {
+            if now.duration_since(frame.sent) < FRAME_TIMEOUT {
+                break;
+            }
+
+            self.in_flight.pop_front();
+        }
+
+        let frame_interval = 1000.0 / fps.max(1.0); //[ms]
+        let in_flight = self.in_flight.len();
+
+        let congested = in_flight > MAX_IN_FLIGHT || self.decode_ms > 0.8 * frame_interval;
+
+        if congested {
+            if now.duration_since(self.last_decrease) > DECREASE_INTERVAL {
+                self.bitrate *= 0.75;
+                self.last_decrease = now;
+            }
+
+            //do not exceed the measured throughput either
+            if self.throughput > 0.0 {
+                self.bitrate = self.bitrate.min(self.throughput);
+            }
+
+            self.clear_since = None;
+            self.congested_since.get_or_insert(now);
+        } else {
+            self.bitrate += (0.05 * self.bitrate).max(25.0);
+
+            self.congested_since = None;
+            self.clear_since.get_or_insert(now);
+        }
+
+        self.bitrate = self.bitrate.max(MIN_BITRATE as f64).min(client_bitrate);
+
+        //change the resolution only after the bitrate has had a chance to settle
+        let mut rescale = None;
+
+        if let Some(since) = self.congested_since {
+            if now.duration_since(since) > DOWNSCALE_DELAY
+                && self.bitrate <= 1.5 * MIN_BITRATE as f64
+                && self.level + 1 < SCALES.len()
+            {
+                self.level += 1;
+                self.congested_since = None;
+                rescale = Some(SCALES[self.level]);
+            }
+        }
+
+        if let Some(since) = self.clear_since {
+            if now.duration_since(since) > UPSCALE_DELAY
+                && self.bitrate >= 0.9 * client_bitrate
+                && self.level > 0
+            {
+                self.level -= 1;
+                self.clear_since = None;
+                rescale = Some(SCALES[self.level]);
+            }
+        }
+
+        let decision = Decision {
+            bitrate: self.bitrate.round() as i32,
+            skip: !keyframe && in_flight >= SKIP_IN_FLIGHT,
+            rescale: rescale,
+        };
+
+        println!(
+            "ABR: {:?}; in flight: {}, decode: {:.1} ms, rtt: {:.1} ms, throughput: {:.0} kbps",
+            decision, in_flight, self.decode_ms, self.rtt_ms, self.throughput
+        );
+
+        decision
+    }
+}
+
+fn ewma(average: f64, value: f64, initialised: bool) -> f64 {
+    if initialised {
+        EWMA_ALPHA * value + (1.0 - EWMA_ALPHA) * average
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a controller that has seen a [video_ack]
+    fn acknowledged(bitrate: i32) -> RateController {
+        let mut controller = RateController::new(bitrate, 1.0);
+        controller.sent(0, 1000);
+        controller.ack(0, 1.0);
+        controller
+    }
+
+    #[test]
+    fn initial_state() {
+        assert_eq!(RateController::new(10, 1.0).bitrate, MIN_BITRATE as f64);
+        assert_eq!(
+            RateController::new(1 << 20, 1.0).bitrate,
+            MAX_BITRATE as f64
+        );
+        assert_eq!(RateController::new(1000, 1.0).scale(), 1.0);
+        assert_eq!(RateController::new(1000, 0.8).scale(), 0.75);
+        assert_eq!(RateController::new(1000, 0.1).scale(), 0.5);
+    }
+
+    #[test]
+    fn frames_in_flight() {
+        let mut controller = RateController::default();
+
+        controller.sent(1, 100);
+        controller.sent(1, 50);
+        controller.sent(2, 100);
+        controller.sent(3, 100);
+        assert_eq!(controller.in_flight.len(), 3);
+        assert_eq!(controller.in_flight[0].bytes, 150);
+
+        //an acknowledgement covers the earlier frames too
+        controller.ack(2, 5.0);
+        assert_eq!(controller.in_flight.len(), 1);
+        assert_eq!(controller.decode_ms, 5.0);
+        assert!(controller.throughput > 0.0);
+
+        //a bogus decode time is ignored
+        controller.ack(3, f64::NAN);
+        assert_eq!(controller.decode_ms, 5.0);
+        assert!(controller.in_flight.is_empty());
+    }
+
+    #[test]
+    fn legacy_clients_set_the_bitrate() {
+        let mut controller = RateController::new(1000, 1.0);
+        controller.sent(0, 1000);
+
+        let decision = controller.next_frame(500, 30.0, false);
+        assert_eq!(decision.bitrate, 500);
+        assert!(!decision.skip);
+        assert!(controller.in_flight.is_empty());
+    }
+
+    #[test]
+    fn additive_increase_up_to_the_client_estimate() {
+        let mut controller = acknowledged(1000);
+
+        assert_eq!(controller.next_frame(5000, 30.0, false).bitrate, 1050);
+        assert_eq!(controller.next_frame(5000, 30.0, false).bitrate, 1103);
+        assert_eq!(controller.next_frame(1080, 30.0, false).bitrate, 1080);
+    }
+
+    #[test]
+    fn multiplicative_decrease_and_skipping() {
+        let mut controller = acknowledged(1000);
+        controller.throughput = 0.0;
+
+        for seq_id in 1..=SKIP_IN_FLIGHT as i32 {
+            controller.sent(seq_id, 1000);
+        }
+
+        controller.last_decrease = Instant::now() - 2 * DECREASE_INTERVAL;
+        let decision = controller.next_frame(5000, 30.0, false);
+        assert_eq!(decision.bitrate, 750);
+        assert!(decision.skip);
+
+        //keyframes are never skipped, the bitrate is cut at most once per interval
+        let decision = controller.next_frame(5000, 30.0, true);
+        assert_eq!(decision.bitrate, 750);
+        assert!(!decision.skip);
+
+        //a slow decoder is a congestion too
+        let mut controller = acknowledged(1000);
+        controller.decode_ms = 30.0;
+        controller.last_decrease = Instant::now() - 2 * DECREASE_INTERVAL;
+        assert_eq!(controller.next_frame(5000, 30.0, false).bitrate, 750);
+    }
+
+    #[test]
+    fn resolution_changes() {
+        let mut controller = acknowledged(MIN_BITRATE);
+        controller.decode_ms = 1000.0;
+        controller.congested_since = Some(Instant::now() - 2 * DOWNSCALE_DELAY);
+
+        let decision = controller.next_frame(5000, 30.0, false);
+        assert_eq!(decision.rescale, Some(0.75));
+        assert_eq!(controller.scale(), 0.75);
+
+        //a clear link at the client bitrate for long enough
+        let mut controller = acknowledged(1000);
+        controller.level = 2;
+        controller.clear_since = Some(Instant::now() - 2 * UPSCALE_DELAY);
+
+        let decision = controller.next_frame(1000, 30.0, false);
+        assert_eq!(decision.rescale, Some(0.75));
+        assert_eq!(controller.next_frame(1000, 30.0, false).rescale, None);
+    }
+
+    #[test]
+    fn moving_averages() {
+        assert_eq!(ewma(10.0, 20.0, false), 20.0);
+        assert!((ewma(10.0, 20.0, true) - 12.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,563 @@
+//cube arithmetic: expressions over the physical values of loaded datasets (operands A, B, ...)
+
+use rayon::prelude::*;
+use std::io::Write;
+
+//the largest number of operands (A..Z)
+pub const MAX_OPERANDS: usize = 26;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Func {
+    Abs,
+    Sqrt,
+    Log10,
+    Ln,
+    Exp,
+    Min,
+    Max,
+    Mask, //mask(x, condition): x where the condition holds, NaN elsewhere
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<(Func, usize)> {
+        match name {
+            "abs" => Some((Func::Abs, 1)),
+            "sqrt" => Some((Func::Sqrt, 1)),
+            "log10" | "log" => Some((Func::Log10, 1)),
+            "ln" => Some((Func::Ln, 1)),
+            "exp" => Some((Func::Exp, 1)),
+            "min" => Some((Func::Min, 2)),
+            "max" => Some((Func::Max, 2)),
+            "mask" => Some((Func::Mask, 2)),
+            _ => None,
+        }
+    }
+}
+
+/// a parsed expression, operands are indices into the list of datasets
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number(f32),
+    Operand(usize),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f32),
+    Name(String),
+    Symbol(&'static str),
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    const SYMBOLS: [&str; 14] = [
+        "<=", ">=", "==", "!=", "<", ">", "+", "-", "*", "/", "^", "(", ")", ",",
+    ];
+
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap_or(' ');
+
+        if c.is_ascii_digit() || c == '.' {
+            //digits with an optional exponent, i.e. 1.5e-3
+            let mut end = 0;
+            let bytes = rest.as_bytes();
+
+            while end < bytes.len() {
+                let b = bytes[end];
+                let exponent_sign = (b == b'+' || b == b'-')
+                    && end > 0
+                    && (bytes[end - 1] == b'e' || bytes[end - 1] == b'E');
+
+                if b.is_ascii_digit() || b == b'.' || b == b'e' || b == b'E' || exponent_sign {
+                    end += 1;
+                } else {
+                    break;
+                }
+            }
+
+            match rest[..end].parse::<f32>() {
+                Ok(x) => tokens.push(Token::Number(x)),
+                Err(_) => return Err(format!("invalid number '{}'", &rest[..end])),
+            }
+
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+
+            tokens.push(Token::Name(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            match SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+                Some(symbol) => {
+                    tokens.push(Token::Symbol(symbol));
+                    rest = &rest[symbol.len()..];
+                }
+                None => return Err(format!("unexpected character '{}'", c)),
+            }
+        }
+
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    operands: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn accept(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.accept(symbol) {
+            Ok(())
+        } else {
+            Err(format!("'{}' expected", symbol))
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.additive()?;
+
+        let op = match self.peek() {
+            Some(Token::Symbol("<")) => Op::Lt,
+            Some(Token::Symbol("<=")) => Op::Le,
+            Some(Token::Symbol(">")) => Op::Gt,
+            Some(Token::Symbol(">=")) => Op::Ge,
+            Some(Token::Symbol("==")) => Op::Eq,
+            Some(Token::Symbol("!=")) => Op::Ne,
+            _ => return Ok(left),
+        };
+
+        self.position += 1;
+        let right = self.additive()?;
+
+        Ok(Expr::Binary(op, Box::new(left), Box::new(right)))
+    }
+
+    fn additive(&mut self) -> Result<Expr, String> {
+        let mut left = self.term()?;
+
+        loop {
+            let op = if self.accept("+") {
+                Op::Add
+            } else if self.accept("-") {
+                Op::Sub
+            } else {
+                return Ok(left);
+            };
+
+            let right = self.term()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+
+        loop {
+            let op = if self.accept("*") {
+                Op::Mul
+            } else if self.accept("/") {
+                Op::Div
+            } else {
+                return Ok(left);
+            };
+
+            let right = self.unary()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.accept("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+
+        if self.accept("+") {
+            return self.unary();
+        }
+
+        let base = self.primary()?;
+
+        if self.accept("^") {
+            let exponent = self.unary()?;
+            return Ok(Expr::Binary(Op::Pow, Box::new(base), Box::new(exponent)));
+        }
+
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(x)) => Ok(Expr::Number(x)),
+            Some(Token::Symbol("(")) => {
+                let expr = self.comparison()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Name(name)) => {
+                //a single capital letter is an operand
+                if name.len() == 1 && name.as_bytes()[0].is_ascii_uppercase() {
+                    let index = (name.as_bytes()[0] - b'A') as usize;
+
+                    if index >= self.operands {
+                        return Err(format!("operand {} has not been given", name));
+                    }
+
+                    return Ok(Expr::Operand(index));
+                }
+
+                let (func, arity) = match Func::from_name(&name.to_lowercase()) {
+                    Some(x) => x,
+                    None => return Err(format!("unknown function '{}'", name)),
+                };
+
+                self.expect("(")?;
+
+                let mut args = vec![self.comparison()?];
+
+                while self.accept(",") {
+                    args.push(self.comparison()?);
+                }
+
+                self.expect(")")?;
+
+                if args.len() != arity {
+                    return Err(format!("{} takes {} argument(s)", name, arity));
+                }
+
+                Ok(Expr::Call(func, args))
+            }
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err(String::from("unexpected end of the expression")),
+        }
+    }
+}
+
+/// parse an expression over the given number of operands, e.g. "(A - B) / B" or
+/// "mask(A, A > 0.01) * 1.5 + 2"
+pub fn parse(text: &str, operands: usize) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+        operands: operands.min(MAX_OPERANDS),
+    };
+
+    let expr = parser.comparison()?;
+
+    if parser.position < parser.tokens.len() {
+        return Err(format!(
+            "unexpected {:?}",
+            parser.tokens[parser.position].clone()
+        ));
+    }
+
+    Ok(expr)
+}
+
+fn truth(x: bool) -> f32 {
+    if x { 1.0 } else { 0.0 }
+}
+
+impl Expr {
+    /// the operands used by the expression
+    pub fn operands(&self, used: &mut Vec<usize>) {
+        match self {
+            Expr::Number(_) => {}
+            Expr::Operand(index) => {
+                if !used.contains(index) {
+                    used.push(*index);
+                }
+            }
+            Expr::Neg(x) => x.operands(used),
+            Expr::Binary(_, a, b) => {
+                a.operands(used);
+                b.operands(used);
+            }
+            Expr::Call(_, args) => args.iter().for_each(|x| x.operands(used)),
+        }
+    }
+
+    /// the value at a pixel, a NaN operand (an invalid pixel) yields NaN
+    pub fn eval(&self, values: &[f32]) -> f32 {
+        match self {
+            Expr::Number(x) => *x,
+            Expr::Operand(index) => values[*index],
+            Expr::Neg(x) => -x.eval(values),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (a.eval(values), b.eval(values));
+
+                if a.is_nan() || b.is_nan() {
+                    return f32::NAN;
+                }
+
+                match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                    Op::Pow => a.powf(b),
+                    Op::Lt => truth(a < b),
+                    Op::Le => truth(a <= b),
+                    Op::Gt => truth(a > b),
+                    Op::Ge => truth(a >= b),
+                    Op::Eq => truth(a == b),
+                    Op::Ne => truth(a != b),
+                }
+            }
+            Expr::Call(func, args) => {
+                let x = args[0].eval(values);
+
+                match func {
+                    Func::Abs => x.abs(),
+                    Func::Sqrt => x.sqrt(),
+                    Func::Log10 => x.log10(),
+                    Func::Ln => x.ln(),
+                    Func::Exp => x.exp(),
+                    Func::Min => x.min(args[1].eval(values)),
+                    Func::Max => x.max(args[1].eval(values)),
+                    Func::Mask => {
+                        let condition = args[1].eval(values);
+
+                        if condition.is_finite() && condition != 0.0 {
+                            x
+                        } else {
+                            f32::NAN
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// evaluate the expression frame by frame into big-endian float32 samples, returns the number
+/// of bytes written; <frame>(operand, frame) yields the physical values of an operand frame
+/// (NaN for invalid pixels), 2D operands (depths[operand] == 1) apply to every frame
+pub fn evaluate<F, W>(
+    expr: &Expr,
+    depths: &[usize],
+    size: usize,
+    depth: usize,
+    frame: F,
+    out: &mut W,
+) -> std::io::Result<usize>
+where
+    F: Fn(usize, usize) -> Option<Vec<f32>>,
+    W: Write,
+{
+    let mut used = Vec::new();
+    expr.operands(&mut used);
+
+    let mut written = 0;
+    let mut images: Vec<Option<Vec<f32>>> = vec![None; depths.len()];
+
+    for k in 0..depth {
+        for &index in &used {
+            //2D operands are read once
+            if depths[index] == 1 && images[index].is_some() {
+                continue;
+            }
+
+            let plane = if depths[index] == 1 { 0 } else { k };
+
+            images[index] = match frame(index, plane) {
+                Some(pixels) if pixels.len() == size => Some(pixels),
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("operand {} frame {} is not available", index, plane),
+                    ));
+                }
+            };
+        }
+
+        let samples: Vec<[u8; 4]> = (0..size)
+            .into_par_iter()
+            .map(|i| {
+                let mut values = [f32::NAN; MAX_OPERANDS];
+
+                for &index in &used {
+                    if let Some(ref pixels) = images[index] {
+                        values[index] = pixels[i];
+                    }
+                }
+
+                expr.eval(&values).to_be_bytes()
+            })
+            .collect();
+
+        let bytes = samples.concat();
+        out.write_all(&bytes)?;
+        written += bytes.len();
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(text: &str, values: &[f32]) -> f32 {
+        parse(text, values.len()).unwrap().eval(values)
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            tokenize(" 1.5e-3*A <= 2E+2 ").unwrap(),
+            vec![
+                Token::Number(1.5e-3),
+                Token::Symbol("*"),
+                Token::Name(String::from("A")),
+                Token::Symbol("<="),
+                Token::Number(200.0),
+            ]
+        );
+
+        //the minus binds as an operator after a number without an exponent
+        assert_eq!(
+            tokenize("2-1").unwrap(),
+            vec![Token::Number(2.0), Token::Symbol("-"), Token::Number(1.0)]
+        );
+
+        assert!(tokenize("1..2").is_err());
+        assert!(tokenize("A % B").is_err());
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(value("2 + 3 * 4", &[]), 14.0);
+        assert_eq!(value("(2 + 3) * 4", &[]), 20.0);
+        assert_eq!(value("8 / 4 / 2", &[]), 1.0);
+        assert_eq!(value("10 - 4 - 3", &[]), 3.0);
+        assert_eq!(value("2 ^ 3 ^ 2", &[]), 512.0);
+        assert_eq!(value("-2 ^ 2", &[]), -4.0);
+        assert_eq!(value("2 ^ -1", &[]), 0.5);
+        assert_eq!(value("1 + 2 > 2", &[]), 1.0);
+        assert_eq!(value("+3 == 3", &[]), 1.0);
+    }
+
+    #[test]
+    fn operands_and_functions() {
+        let values = [4.0, -2.0, 0.5];
+
+        assert_eq!(value("(A - B) / B", &values), -3.0);
+        assert_eq!(value("sqrt(A) + abs(B)", &values), 4.0);
+        assert_eq!(value("MAX(A, C) * min(B, C)", &values), -8.0);
+        assert_eq!(value("log(100) + ln(exp(C))", &values), 2.5);
+        assert_eq!(value("mask(A, A > 1)", &values), 4.0);
+        assert!(value("mask(A, B > 1)", &values).is_nan());
+
+        let mut used = Vec::new();
+        parse("C * A + mask(A, C != 0)", 3)
+            .unwrap()
+            .operands(&mut used);
+        assert_eq!(used, vec![2, 0]);
+    }
+
+    #[test]
+    fn invalid_pixels() {
+        assert!(value("A + 1", &[f32::NAN]).is_nan());
+        assert!(value("A > 0", &[f32::NAN]).is_nan());
+        assert!(value("-A", &[f32::NAN]).is_nan());
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(parse("A + B", 1).is_err());
+        assert!(parse("Z", MAX_OPERANDS + 10).is_ok());
+        assert!(parse("foo(A)", 1).is_err());
+        assert!(parse("min(A)", 1).is_err());
+        assert!(parse("sqrt(A, A)", 1).is_err());
+        assert!(parse("(A + 1", 1).is_err());
+        assert!(parse("A + 1)", 1).is_err());
+        assert!(parse("A 1", 1).is_err());
+        assert!(parse("A +", 1).is_err());
+        assert!(parse("", 1).is_err());
+    }
+
+    #[test]
+    fn frame_by_frame_evaluation() {
+        //a 2x1 cube of 3 frames and a 2x1 image
+        let expr = parse("A * B", 2).unwrap();
+        let depths = [3, 1];
+
+        let frame = |operand: usize, k: usize| -> Option<Vec<f32>> {
+            match operand {
+                0 => Some(vec![k as f32, f32::NAN]),
+                _ => {
+                    assert_eq!(k, 0);
+                    Some(vec![2.0, 3.0])
+                }
+            }
+        };
+
+        let mut out = Vec::new();
+        let written = evaluate(&expr, &depths, 2, 3, frame, &mut out).unwrap();
+
+        assert_eq!(written, 3 * 2 * 4);
+        assert_eq!(out.len(), written);
+
+        let samples: Vec<f32> = out
+            .chunks(4)
+            .map(|x| f32::from_be_bytes([x[0], x[1], x[2], x[3]]))
+            .collect();
+
+        for k in 0..3 {
+            assert_eq!(samples[2 * k], 2.0 * k as f32);
+            assert!(samples[2 * k + 1].is_nan());
+        }
+
+        //a missing or mis-sized frame aborts the evaluation
+        let missing = |_: usize, k: usize| if k < 2 { Some(vec![1.0, 1.0]) } else { None };
+        assert!(evaluate(&expr, &depths, 2, 3, missing, &mut Vec::new()).is_err());
+
+        let short = |_: usize, _: usize| Some(vec![1.0]);
+        assert!(evaluate(&expr, &depths, 2, 3, short, &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use rav1e::prelude::*;
+use std::time::Instant;
+
+use crate::fits::supported_bit_depth;
+
+//rav1e speed presets: 0 - slowest/best, 10 - fastest
+const AV1_IMAGE_SPEED: u8 = 6;
+const AV1_VIDEO_SPEED: u8 = 10;
+
+//a quantizer (0-255) for still images and viewports
+const AV1_IMAGE_QUANTIZER: usize = 80;
+
+//a running rav1e context cannot change its bitrate, the video encoder is re-created
+//(starting with a keyframe) when the adaptive bitrate moves by more than this fraction
+const AV1_BITRATE_HYSTERESIS: f64 = 0.2;
+
+fn encoder_config(width: u32, height: u32, speed: u8, bit_depth: u32) -> EncoderConfig {
+    let mut enc = EncoderConfig::with_speed_preset(speed);
+
+    enc.width = width as usize;
+    enc.height = height as usize;
+    enc.bit_depth = bit_depth as usize;
+    enc.chroma_sampling = ChromaSampling::Cs420;
+    enc.low_latency = true;
+
+    enc
+}
+
+fn new_context<T: Pixel>(enc: EncoderConfig) -> Option<Context<T>> {
+    let cfg = Config::new()
+        .with_encoder_config(enc)
+        .with_threads(num_cpus::get_physical().min(4)); //set the upper limit on the number of threads to 4
+
+    match cfg.new_context() {
+        Ok(ctx) => Some(ctx),
+        Err(err) => {
+            println!("AV1: invalid encoder configuration: {}", err);
+            None
+        }
+    }
+}
+
+//native-endian samples as raw bytes for Plane::copy_from_raw_u8
+fn u16_bytes(y: &[u16]) -> Vec<u8> {
+    y.iter().flat_map(|x| x.to_ne_bytes()).collect()
+}
+
+//a luma-only I420 frame from raw samples (1 or 2 bytes each); the chroma planes are mid-grey (no colour)
+fn make_frame<T: Pixel>(ctx: &Context<T>, width: u32, y: &[u8], bit_depth: u32) -> Frame<T> {
+    let mut frame = ctx.new_frame();
+    let bytewidth = if bit_depth > 8 { 2 } else { 1 };
+
+    frame.planes[0].copy_from_raw_u8(y, bytewidth * width as usize, bytewidth);
+
+    let neutral = 1u16 << (bit_depth - 1);
+
+    for plane in &mut frame.planes[1..] {
+        let stride = plane.cfg.width;
+        let count = plane.cfg.width * plane.cfg.height;
+
+        if bytewidth == 1 {
+            plane.copy_from_raw_u8(&vec![neutral as u8; count], stride, 1);
+        } else {
+            plane.copy_from_raw_u8(&u16_bytes(&vec![neutral; count]), 2 * stride, 2);
+        }
+    }
+
+    frame
+}
+
+/// encode a single (key) frame, i.e. a still image or a viewport
+pub fn encode_still(width: u32, height: u32, y: &[u8]) -> Option<Vec<u8>> {
+    encode_still_samples::<u8>(width, height, y, 8)
+}
+
+/// encode a single (key) frame with 10/12-bit luma samples
+pub fn encode_still_hbd(width: u32, height: u32, y: &[u16], bit_depth: u32) -> Option<Vec<u8>> {
+    encode_still_samples::<u16>(width, height, &u16_bytes(y), bit_depth)
+}
+
+fn encode_still_samples<T: Pixel>(
+    width: u32,
+    height: u32,
+    y: &[u8],
+    bit_depth: u32,
+) -> Option<Vec<u8>> {
+    let watch = Instant::now();
+
+    let mut enc = encoder_config(width, height, AV1_IMAGE_SPEED, bit_depth);
+    enc.still_picture = true;
+    enc.quantizer = AV1_IMAGE_QUANTIZER;
+
+    let mut ctx = new_context::<T>(enc)?;
+    let frame = make_frame(&ctx, width, y, bit_depth);
+
+    if let Err(err) = ctx.send_frame(frame) {
+        println!("AV1: error sending a frame: {:?}", err);
+        return None;
+    }
+
+    ctx.flush();
+
+    let mut image_frame: Vec<u8> = Vec::new();
+
+    loop {
+        match ctx.receive_packet() {
+            Ok(packet) => image_frame.extend_from_slice(&packet.data),
+            Err(EncoderStatus::Encoded) => continue,
+            Err(EncoderStatus::LimitReached) => break,
+            Err(err) => {
+                println!("AV1: encoder error: {:?}", err);
+                return None;
+            }
+        }
+    }
+
+    if image_frame.is_empty() {
+        println!("AV1 image frame error: no packet produced");
+        return None;
+    }
+
+    println!(
+        "AV1 {}x{} {}-bit image frame encode time: {:?}, {} bytes",
+        width,
+        height,
+        bit_depth,
+        watch.elapsed(),
+        image_frame.len()
+    );
+
+    Some(image_frame)
+}
+
+fn encode_video_frame<T: Pixel>(
+    ctx: &mut Context<T>,
+    width: u32,
+    y: &[u8],
+    bit_depth: u32,
+    keyframe: bool,
+) -> Vec<Vec<u8>> {
+    let frame = make_frame(ctx, width, y, bit_depth);
+
+    let params = FrameParameters {
+        frame_type_override: if keyframe {
+            FrameTypeOverride::Key
+        } else {
+            FrameTypeOverride::No
+        },
+        ..Default::default()
+    };
+
+    if let Err(err) = ctx.send_frame((frame, params)) {
+        println!("AV1: error sending a video frame: {:?}", err);
+        return Vec::new();
+    }
+
+    let mut packets: Vec<Vec<u8>> = Vec::new();
+
+    loop {
+        match ctx.receive_packet() {
+            Ok(packet) => packets.push(packet.data),
+            Err(EncoderStatus::Encoded) => continue,
+            Err(EncoderStatus::NeedMoreData) => break,
+            Err(err) => {
+                println!("AV1: video encoder error: {:?}", err);
+                break;
+            }
+        }
+    }
+
+    packets
+}
+
+enum VideoContext {
+    Low(Context<u8>),
+    High(Context<u16>),
+}
+
+fn video_context(
+    width: u32,
+    height: u32,
+    fps: f64,
+    bitrate: i32,
+    bit_depth: u32,
+) -> Option<VideoContext> {
+    let mut enc = encoder_config(width, height, AV1_VIDEO_SPEED, bit_depth);
+
+    enc.time_base = Rational::new(1, (fps.round() as u64).max(1));
+    enc.bitrate = bitrate * 1000; //[kilobits -> bits per second]
+    enc.speed_settings.rdo_lookahead_frames = 1; //no latency
+
+    if bit_depth > 8 {
+        Some(VideoContext::High(new_context(enc)?))
+    } else {
+        Some(VideoContext::Low(new_context(enc)?))
+    }
+}
+
+/// a real-time AV1 video stream encoder held by a WebSocket session
+pub struct VideoEncoder {
+    ctx: VideoContext,
+    width: u32,
+    height: u32,
+    fps: f64,
+    bitrate: i32,
+    bit_depth: u32,
+}
+
+impl VideoEncoder {
+    pub fn new(
+        width: u32,
+        height: u32,
+        fps: f64,
+        bitrate: i32,
+        bit_depth: u32,
+    ) -> Option<VideoEncoder> {
+        let bit_depth = supported_bit_depth(bit_depth);
+
+        Some(VideoEncoder {
+            ctx: video_context(width, height, fps, bitrate, bit_depth)?,
+            width: width,
+            height: height,
+            fps: fps,
+            bitrate: bitrate,
+            bit_depth: bit_depth,
+        })
+    }
+
+    /// follow the adaptive bitrate [kilobits per second]; true if the encoder has been re-created,
+    /// in which case the next frame is a keyframe
+    pub fn set_bitrate(&mut self, bitrate: i32) -> bool {
+        let change = ((bitrate - self.bitrate) as f64).abs() / (self.bitrate.max(1) as f64);
+
+        if change < AV1_BITRATE_HYSTERESIS {
+            return false;
+        }
+
+        match video_context(self.width, self.height, self.fps, bitrate, self.bit_depth) {
+            Some(ctx) => {
+                self.ctx = ctx;
+                self.bitrate = bitrate;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn bit_depth(&self) -> u32 {
+        self.bit_depth
+    }
+
+    /// encode an 8-bit luma plane (width x height), returning all the packets ready for sending
+    pub fn encode(&mut self, y: &[u8], keyframe: bool) -> Vec<Vec<u8>> {
+        match self.ctx {
+            VideoContext::Low(ref mut ctx) => encode_video_frame(ctx, self.width, y, 8, keyframe),
+            VideoContext::High(ref mut ctx) => {
+                //promote the samples to the stream bit depth
+                let shift = self.bit_depth - 8;
+                let y: Vec<u16> = y.iter().map(|x| (*x as u16) << shift).collect();
+                encode_video_frame(ctx, self.width, &u16_bytes(&y), self.bit_depth, keyframe)
+            }
+        }
+    }
+
+    /// encode a luma plane with samples at the stream bit depth (10 or 12 bits)
+    pub fn encode_hbd(&mut self, y: &[u16], keyframe: bool) -> Vec<Vec<u8>> {
+        match self.ctx {
+            VideoContext::High(ref mut ctx) => {
+                encode_video_frame(ctx, self.width, &u16_bytes(y), self.bit_depth, keyframe)
+            }
+            VideoContext::Low(_) => {
+                println!("AV1: 10/12-bit samples sent to an 8-bit video stream");
+                Vec::new()
+            }
+        }
+    }
+}
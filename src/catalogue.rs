@@ -0,0 +1,772 @@
+//an indexed catalogue of the local FITS files: the key header fields of every file under the
+//configured roots are kept in sqlite and searched by position, frequency and keywords; changes are
+//picked up by polling, the roots being re-scanned every SCAN_INTERVAL (there are no file system
+//notifications)
+
+use parking_lot::{Mutex, RwLock};
+use rayon::prelude::*;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::fits::FITS;
+
+//the catalogue sqlite store, created in the working directory on first use
+pub static CATALOGUE_DB: &'static str = "catalogue.db";
+
+const SCAN_INTERVAL: u64 = 5 * 60; //[s]; how often the roots are re-scanned for changes
+
+pub const DEFAULT_SEARCH_RADIUS: f64 = 0.1; //[deg]
+
+pub const DEFAULT_SEARCH_LIMIT: usize = 100;
+pub const MAX_SEARCH_LIMIT: usize = 1000;
+
+//the HeaderSummary columns of the files table, in order
+const SUMMARY_COLUMNS: &'static str = "object, telescope, line, bunit, obs_date, ra, dec, radius, freq_start, freq_end, bmaj, bmin, bpa, width, height, depth, polarisation";
+
+const SUMMARY_CACHE_SIZE: usize = 10000; //the header summaries of listed files kept in memory
+
+/// the searchable header fields of a FITS file
+#[derive(Debug, Clone)]
+pub struct HeaderSummary {
+    pub object: String,
+    pub telescope: String,
+    pub line: String,
+    pub bunit: String,
+    pub obs_date: String,
+    pub ra: Option<f64>, //the image centre [deg]
+    pub dec: Option<f64>,
+    pub radius: f64,             //half the image diagonal [deg]
+    pub freq_start: Option<f64>, //[GHz]
+    pub freq_end: Option<f64>,
+    pub bmaj: Option<f64>, //[deg]
+    pub bmin: Option<f64>,
+    pub bpa: Option<f64>,
+    pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+    pub polarisation: usize,
+}
+
+impl HeaderSummary {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "OBJECT" : self.object,
+            "TELESCOP" : self.telescope,
+            "LINE" : self.line,
+            "BUNIT" : self.bunit,
+            "DATE-OBS" : self.obs_date,
+            "ra" : self.ra,
+            "dec" : self.dec,
+            "radius" : self.radius,
+            "freq_start" : self.freq_start,
+            "freq_end" : self.freq_end,
+            "BMAJ" : self.bmaj,
+            "BMIN" : self.bmin,
+            "BPA" : self.bpa,
+            "width" : self.width,
+            "height" : self.height,
+            "depth" : self.depth,
+            "polarisation" : self.polarisation,
+        })
+    }
+}
+
+/// a catalogue row
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub path: String,
+    pub size: u64,
+    pub modified: i64, //[s] since the UNIX epoch
+    pub summary: HeaderSummary,
+    pub distance: Option<f64>, //from the cone search centre [deg]
+}
+
+impl Entry {
+    pub fn to_json(&self) -> serde_json::Value {
+        let path = Path::new(&self.path);
+        let (dir, filename, ext) = split_fits_path(path);
+
+        let mut json = self.summary.to_json();
+        json["path"] = json!(self.path);
+        json["dir"] = json!(dir);
+        json["filename"] = json!(filename);
+        json["ext"] = json!(ext);
+        json["size"] = json!(self.size);
+        json["last_modified"] = json!(self.modified);
+        json["distance"] = json!(self.distance);
+
+        json
+    }
+}
+
+/// the search criteria, all optional
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    pub cone: Option<(f64, f64, f64)>, //ra, dec, radius [deg]
+    pub freq: Option<(f64, f64)>,      //[GHz]; files whose frequency coverage overlaps
+    pub object: Option<String>,        //case-insensitive substrings
+    pub telescope: Option<String>,
+    pub line: Option<String>,
+    pub header: Option<String>, //any text within the FITS header
+    pub min_depth: Option<usize>,
+    pub limit: usize,
+}
+
+/// the indexer state, reported with the search results
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    pub roots: Vec<PathBuf>,
+    pub scanning: bool,
+    pub last_scan: Option<SystemTime>,
+    pub indexed: usize, //the number of files (re-)indexed by the last scan
+}
+
+impl Status {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "roots" : self.roots.iter().map(|x| x.to_string_lossy().into_owned()).collect::<Vec<String>>(),
+            "scanning" : self.scanning,
+            "last_scan" : self.last_scan.map(unix_time),
+            "indexed" : self.indexed,
+        })
+    }
+}
+
+lazy_static! {
+    static ref STATUS: RwLock<Status> = RwLock::new(Status::default());
+
+    //path -> (size, modified, summary) of the files read outside of the catalogue
+    static ref SUMMARIES: Mutex<HashMap<String, (u64, i64, Option<HeaderSummary>)>> =
+        Mutex::new(HashMap::new());
+}
+
+pub fn status() -> Status {
+    STATUS.read().clone()
+}
+
+fn unix_time(ts: SystemTime) -> i64 {
+    match ts.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(x) => x.as_secs() as i64,
+        Err(_) => 0,
+    }
+}
+
+/// .fits, .fits.gz and .fits.bz2 files (as listed by get_directory)
+pub fn is_fits_file(path: &Path) -> bool {
+    let name = match path.file_name().and_then(std::ffi::OsStr::to_str) {
+        Some(x) => x,
+        None => return false,
+    };
+
+    name.ends_with(".fits")
+        || name.ends_with(".FITS")
+        || name.ends_with(".fits.gz")
+        || name.ends_with(".FITS.GZ")
+        || name.ends_with(".fits.bz2")
+        || name.ends_with(".FITS.BZ2")
+}
+
+//the dir, filename and ext parameters of FITSWebQL.html
+fn split_fits_path(path: &Path) -> (String, String, String) {
+    let dir = match path.parent() {
+        Some(x) => x.to_string_lossy().into_owned(),
+        None => String::from("."),
+    };
+
+    let name = match path.file_name() {
+        Some(x) => x.to_string_lossy().into_owned(),
+        None => String::new(),
+    };
+
+    let lower = name.to_lowercase();
+
+    let ext_len = if lower.ends_with(".fits.gz") || lower.ends_with(".fits.bz2") {
+        lower
+            .rsplitn(3, '.')
+            .take(2)
+            .map(|x| x.len() + 1)
+            .sum::<usize>()
+    } else {
+        lower.rsplit('.').next().map_or(0, |x| x.len() + 1)
+    };
+
+    let (filename, ext) = name.split_at(name.len() - ext_len.min(name.len()));
+
+    (
+        dir,
+        filename.to_string(),
+        ext.trim_start_matches('.').to_string(),
+    )
+}
+
+fn open_db() -> rusqlite::Result<Connection> {
+    let conn = Connection::open(Path::new(CATALOGUE_DB))?;
+
+    //searches run concurrently with the indexer
+    conn.busy_timeout(Duration::from_secs(10))?;
+    conn.query_row("PRAGMA journal_mode=WAL;", [], |_| Ok(()))?;
+
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS files (path TEXT PRIMARY KEY, size INTEGER NOT NULL, modified INTEGER NOT NULL, valid INTEGER NOT NULL, object TEXT, telescope TEXT, line TEXT, bunit TEXT, obs_date TEXT, ra REAL, dec REAL, radius REAL, freq_start REAL, freq_end REAL, bmaj REAL, bmin REAL, bpa REAL, width INTEGER, height INTEGER, depth INTEGER, polarisation INTEGER, header TEXT);
+        CREATE INDEX IF NOT EXISTS files_dec ON files (dec);
+        CREATE INDEX IF NOT EXISTS files_freq ON files (freq_start, freq_end);",
+    )?;
+
+    Ok(conn)
+}
+
+//(re-)index a single file; files without a valid header are recorded too so that they are not
+//read again until they change
+fn index_file(conn: &Connection, path: &Path, size: u64, modified: i64) -> rusqlite::Result<()> {
+    let path_str = path.to_string_lossy().into_owned();
+
+    let fits = match FITS::from_header(path) {
+        Some(x) => x,
+        None => {
+            println!("[catalogue] {}: no valid FITS header", path_str);
+
+            conn.execute(
+                "INSERT OR REPLACE INTO files (path, size, modified, valid) VALUES (?1, ?2, ?3, 0);",
+                params![path_str, size as i64, modified],
+            )?;
+
+            return Ok(());
+        }
+    };
+
+    let s = fits.header_summary();
+
+    conn.execute(
+        "INSERT OR REPLACE INTO files (path, size, modified, valid, object, telescope, line, bunit, obs_date, ra, dec, radius, freq_start, freq_end, bmaj, bmin, bpa, width, height, depth, polarisation, header) VALUES (?1, ?2, ?3, 1, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21);",
+        params![
+            path_str,
+            size as i64,
+            modified,
+            s.object,
+            s.telescope,
+            s.line,
+            s.bunit,
+            s.obs_date,
+            s.ra,
+            s.dec,
+            s.radius,
+            s.freq_start,
+            s.freq_end,
+            s.bmaj,
+            s.bmin,
+            s.bpa,
+            s.width as i64,
+            s.height as i64,
+            s.depth as i64,
+            s.polarisation as i64,
+            fits.get_header()
+        ],
+    )?;
+
+    Ok(())
+}
+
+//walk a directory tree (skipping hidden entries), indexing new or modified FITS files;
+//returns the number of files (re-)indexed, <complete> is cleared if any (sub)directory cannot be read
+fn scan_directory(
+    conn: &Connection,
+    dir: &Path,
+    seen: &mut Vec<String>,
+    visited: &mut HashSet<PathBuf>,
+    complete: &mut bool,
+) -> usize {
+    //each directory once, a symbolic link cycle would recurse forever
+    match std::fs::canonicalize(dir) {
+        Ok(x) => {
+            if !visited.insert(x) {
+                return 0;
+            }
+        }
+        Err(err) => {
+            println!("[catalogue] cannot scan {:?}: {}", dir, err);
+            *complete = false;
+            return 0;
+        }
+    }
+
+    let entries = match dir.read_dir() {
+        Ok(x) => x,
+        Err(err) => {
+            println!("[catalogue] cannot scan {:?}: {}", dir, err);
+            *complete = false;
+            return 0;
+        }
+    };
+
+    let mut count = 0;
+
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let path = entry.path();
+
+        let metadata = match std::fs::metadata(&path) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+
+        if metadata.is_dir() {
+            count = count + scan_directory(conn, &path, seen, visited, complete);
+            continue;
+        }
+
+        if !metadata.is_file() || !is_fits_file(&path) {
+            continue;
+        }
+
+        let path_str = path.to_string_lossy().into_owned();
+        let size = metadata.len();
+        let modified = unix_time(metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH));
+
+        seen.push(path_str.clone());
+
+        //skip unchanged files
+        let known = conn
+            .query_row(
+                "SELECT size, modified FROM files WHERE path = ?1;",
+                params![path_str],
+                |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+            )
+            .optional();
+
+        match known {
+            Ok(Some((x, y))) if x == size as i64 && y == modified => continue,
+            _ => {}
+        }
+
+        match index_file(conn, &path, size, modified) {
+            Ok(_) => count = count + 1,
+            Err(err) => println!("[catalogue] cannot index {}: {}", path_str, err),
+        }
+    }
+
+    count
+}
+
+//a single pass over all the roots, forgetting the files that have disappeared
+fn scan(roots: &[PathBuf]) -> rusqlite::Result<usize> {
+    let conn = open_db()?;
+
+    let mut seen: Vec<String> = Vec::new();
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+    let mut count = 0;
+    let mut complete = true;
+
+    for root in roots {
+        count = count + scan_directory(&conn, root, &mut seen, &mut visited, &mut complete);
+    }
+
+    //an unreadable (i.e. unmounted) root or subdirectory does not mean that its files have been deleted
+    if !complete {
+        println!("[catalogue] not all the directories could be read, keeping the missing files");
+        return Ok(count);
+    }
+
+    seen.sort_unstable();
+
+    let known: Vec<String> = {
+        let mut stmt = conn.prepare("SELECT path FROM files;")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.filter_map(|x| x.ok()).collect()
+    };
+
+    for path in known {
+        if seen.binary_search(&path).is_err() {
+            println!("[catalogue] {} has been removed", path);
+            conn.execute("DELETE FROM files WHERE path = ?1;", params![path])?;
+        }
+    }
+
+    Ok(count)
+}
+
+/// start the background indexer of the <roots>, re-scanning them every SCAN_INTERVAL
+pub fn start(roots: Vec<PathBuf>) {
+    if roots.is_empty() {
+        return;
+    }
+
+    STATUS.write().roots = roots.clone();
+
+    std::thread::spawn(move || {
+        loop {
+            STATUS.write().scanning = true;
+
+            let watch = std::time::Instant::now();
+
+            let indexed = match scan(&roots) {
+                Ok(x) => x,
+                Err(err) => {
+                    println!("[catalogue] scan failed: {}", err);
+                    0
+                }
+            };
+
+            println!(
+                "[catalogue] {} file(s) indexed in {:?}",
+                indexed,
+                watch.elapsed()
+            );
+
+            {
+                let mut status = STATUS.write();
+                status.scanning = false;
+                status.last_scan = Some(SystemTime::now());
+                status.indexed = indexed;
+            }
+
+            std::thread::sleep(Duration::from_secs(SCAN_INTERVAL));
+        }
+    });
+}
+
+//the SUMMARY_COLUMNS starting at column <first>
+fn summary_from_row(row: &rusqlite::Row, first: usize) -> rusqlite::Result<HeaderSummary> {
+    Ok(HeaderSummary {
+        object: row.get::<_, Option<String>>(first)?.unwrap_or_default(),
+        telescope: row.get::<_, Option<String>>(first + 1)?.unwrap_or_default(),
+        line: row.get::<_, Option<String>>(first + 2)?.unwrap_or_default(),
+        bunit: row.get::<_, Option<String>>(first + 3)?.unwrap_or_default(),
+        obs_date: row.get::<_, Option<String>>(first + 4)?.unwrap_or_default(),
+        ra: row.get(first + 5)?,
+        dec: row.get(first + 6)?,
+        radius: row.get::<_, Option<f64>>(first + 7)?.unwrap_or(0.0),
+        freq_start: row.get(first + 8)?,
+        freq_end: row.get(first + 9)?,
+        bmaj: row.get(first + 10)?,
+        bmin: row.get(first + 11)?,
+        bpa: row.get(first + 12)?,
+        width: row.get::<_, Option<i64>>(first + 13)?.unwrap_or(0) as usize,
+        height: row.get::<_, Option<i64>>(first + 14)?.unwrap_or(0) as usize,
+        depth: row.get::<_, Option<i64>>(first + 15)?.unwrap_or(0) as usize,
+        polarisation: row.get::<_, Option<i64>>(first + 16)?.unwrap_or(0) as usize,
+    })
+}
+
+/// the header summaries of local FITS files (None without a valid header), i.e. for a directory
+/// listing: taken from the catalogue or a memory cache while a file is unchanged, otherwise read
+/// from the header only
+pub fn header_summaries(paths: &[PathBuf]) -> Vec<Option<HeaderSummary>> {
+    //only consult the database when the catalogue is enabled
+    let conn = if STATUS.read().roots.is_empty() {
+        None
+    } else {
+        open_db().ok()
+    };
+
+    let files: Vec<(String, u64, i64)> = paths
+        .iter()
+        .map(|path| {
+            let (size, modified) = match std::fs::metadata(path) {
+                Ok(metadata) => (
+                    metadata.len(),
+                    unix_time(metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH)),
+                ),
+                Err(_) => (0, 0),
+            };
+
+            (path.to_string_lossy().into_owned(), size, modified)
+        })
+        .collect();
+
+    let known: Vec<Option<Option<HeaderSummary>>> = files
+        .iter()
+        .map(|(path, size, modified)| {
+            if let Some((x, y, summary)) = SUMMARIES.lock().get(path) {
+                if x == size && y == modified {
+                    return Some(summary.clone());
+                }
+            }
+
+            let conn = conn.as_ref()?;
+
+            conn.query_row(
+                &format!(
+                    "SELECT valid, {} FROM files WHERE path = ?1 AND size = ?2 AND modified = ?3;",
+                    SUMMARY_COLUMNS
+                ),
+                params![path, *size as i64, modified],
+                |row| {
+                    if row.get::<_, i64>(0)? == 1 {
+                        Ok(Some(summary_from_row(row, 1)?))
+                    } else {
+                        Ok(None)
+                    }
+                },
+            )
+            .optional()
+            .ok()
+            .flatten()
+        })
+        .collect();
+
+    //read the remaining headers in parallel
+    files
+        .par_iter()
+        .zip(known.into_par_iter())
+        .map(|((path, size, modified), known)| {
+            if let Some(summary) = known {
+                return summary;
+            }
+
+            let summary = FITS::from_header(Path::new(path)).map(|fits| fits.header_summary());
+
+            let mut cache = SUMMARIES.lock();
+
+            if cache.len() >= SUMMARY_CACHE_SIZE {
+                cache.clear();
+            }
+
+            cache.insert(path.clone(), (*size, *modified, summary.clone()));
+
+            summary
+        })
+        .collect()
+}
+
+//the angular distance between two points on the sky [deg]
+fn separation(ra1: f64, dec1: f64, ra2: f64, dec2: f64) -> f64 {
+    let (ra1, dec1, ra2, dec2) = (
+        ra1.to_radians(),
+        dec1.to_radians(),
+        ra2.to_radians(),
+        dec2.to_radians(),
+    );
+
+    let a = ((dec2 - dec1) / 2.0).sin().powi(2)
+        + dec1.cos() * dec2.cos() * ((ra2 - ra1) / 2.0).sin().powi(2);
+
+    (2.0 * a.sqrt().min(1.0).asin()).to_degrees()
+}
+
+//escape the LIKE wildcards of a user-supplied substring
+fn like_pattern(text: &str) -> String {
+    let escaped = text
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+
+    format!("%{}%", escaped)
+}
+
+/// search the catalogue; a cone search returns the files whose footprint overlaps the cone,
+/// nearest first
+pub fn search(query: &Query) -> rusqlite::Result<Vec<Entry>> {
+    let conn = open_db()?;
+
+    let mut sql = format!(
+        "SELECT path, size, modified, {} FROM files WHERE valid = 1",
+        SUMMARY_COLUMNS
+    );
+
+    let mut values: Vec<rusqlite::types::Value> = Vec::new();
+
+    let mut add = |sql: &mut String, clause: &str, value: rusqlite::types::Value| {
+        values.push(value);
+        sql.push_str(&format!(
+            " AND {}",
+            clause.replace("?", &format!("?{}", values.len()))
+        ));
+    };
+
+    if let Some((_, dec, radius)) = query.cone {
+        //a declination band pre-selection, the exact separation is checked below
+        add(&mut sql, "dec + radius >= ?", (dec - radius).into());
+        add(&mut sql, "dec - radius <= ?", (dec + radius).into());
+    }
+
+    if let Some((fmin, fmax)) = query.freq {
+        add(&mut sql, "freq_end >= ?", fmin.into());
+        add(&mut sql, "freq_start <= ?", fmax.into());
+    }
+
+    for (column, text) in [
+        ("object", &query.object),
+        ("telescope", &query.telescope),
+        ("line", &query.line),
+        ("header", &query.header),
+    ] {
+        if let Some(text) = text {
+            add(
+                &mut sql,
+                &format!("{} LIKE ? ESCAPE '\\'", column),
+                like_pattern(text).into(),
+            );
+        }
+    }
+
+    if let Some(depth) = query.min_depth {
+        add(&mut sql, "depth >= ?", (depth as i64).into());
+    }
+
+    sql.push_str(" ORDER BY path;");
+
+    let mut stmt = conn.prepare(&sql)?;
+
+    let rows = stmt.query_map(rusqlite::params_from_iter(values.iter()), |row| {
+        Ok(Entry {
+            path: row.get(0)?,
+            size: row.get::<_, i64>(1)? as u64,
+            modified: row.get(2)?,
+            summary: summary_from_row(row, 3)?,
+            distance: None,
+        })
+    })?;
+
+    let mut entries: Vec<Entry> = rows.filter_map(|x| x.ok()).collect();
+
+    if let Some((ra, dec, radius)) = query.cone {
+        entries = entries
+            .into_iter()
+            .filter_map(|mut entry| {
+                let distance = match (entry.summary.ra, entry.summary.dec) {
+                    (Some(x), Some(y)) => separation(ra, dec, x, y),
+                    _ => return None,
+                };
+
+                if distance <= radius + entry.summary.radius {
+                    entry.distance = Some(distance);
+                    Some(entry)
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        entries.sort_by(|a, b| {
+            a.distance
+                .unwrap_or(0.0)
+                .total_cmp(&b.distance.unwrap_or(0.0))
+        });
+    }
+
+    entries.truncate(query.limit);
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(path: &str) -> (String, String, String) {
+        split_fits_path(Path::new(path))
+    }
+
+    fn owned(dir: &str, filename: &str, ext: &str) -> (String, String, String) {
+        (dir.to_string(), filename.to_string(), ext.to_string())
+    }
+
+    #[test]
+    fn fits_extensions() {
+        for name in [
+            "a.fits",
+            "a.FITS",
+            "a.fits.gz",
+            "a.FITS.GZ",
+            "/data/a.b.fits.bz2",
+        ] {
+            assert!(is_fits_file(Path::new(name)), "{}", name);
+        }
+
+        for name in ["a.fit", "a.fits.zip", "a.fits.Gz", "fits", "/"] {
+            assert!(!is_fits_file(Path::new(name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn fits_paths() {
+        assert_eq!(
+            split("/data/alma/cube.fits"),
+            owned("/data/alma", "cube", "fits")
+        );
+        assert_eq!(
+            split("/data/cube.v2.fits.gz"),
+            owned("/data", "cube.v2", "fits.gz")
+        );
+        assert_eq!(
+            split("/data/CUBE.FITS.BZ2"),
+            owned("/data", "CUBE", "FITS.BZ2")
+        );
+        assert_eq!(split("cube.fits"), owned("", "cube", "fits"));
+    }
+
+    #[test]
+    fn angular_separations() {
+        assert!(separation(10.0, 20.0, 10.0, 20.0).abs() < 1e-12);
+        assert!((separation(0.0, 0.0, 90.0, 0.0) - 90.0).abs() < 1e-9);
+        assert!((separation(0.0, -90.0, 0.0, 90.0) - 180.0).abs() < 1e-9);
+        //across RA = 0
+        assert!((separation(359.5, 0.0, 0.5, 0.0) - 1.0).abs() < 1e-9);
+        //RA converges towards the pole
+        assert!((separation(0.0, 89.0, 180.0, 89.0) - 2.0).abs() < 1e-9);
+        //a small offset at the catalogue search scale
+        let d = separation(150.0, 2.0, 150.0 + 0.1 / 2f64.to_radians().cos(), 2.0);
+        assert!((d - 0.1).abs() < 1e-6);
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(like_pattern("CO"), "%CO%");
+        assert_eq!(like_pattern("50%_a\\b"), "%50\\%\\_a\\\\b%");
+    }
+
+    #[test]
+    fn search_by_keyword() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute("CREATE TABLE t (name TEXT)", []).unwrap();
+
+        for name in ["CO_1-0", "CO 1-0", "HCO+ 50%", "HCO+ 500"] {
+            conn.execute("INSERT INTO t (name) VALUES (?1)", params![name])
+                .unwrap();
+        }
+
+        let count = |text: &str| -> i64 {
+            conn.query_row(
+                "SELECT COUNT(*) FROM t WHERE name LIKE ?1 ESCAPE '\\'",
+                params![like_pattern(text)],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(count("co"), 4);
+        assert_eq!(count("_"), 1);
+        assert_eq!(count("50%"), 1);
+        assert_eq!(count("50"), 2);
+    }
+
+    #[test]
+    fn unreadable_directories_are_incomplete() {
+        let conn = Connection::open_in_memory().unwrap();
+        let root = std::env::temp_dir().join(format!("catalogue-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+
+        let scan = |dir: &Path| -> bool {
+            let mut complete = true;
+            let count = scan_directory(
+                &conn,
+                dir,
+                &mut Vec::new(),
+                &mut HashSet::new(),
+                &mut complete,
+            );
+            assert_eq!(count, 0);
+            complete
+        };
+
+        assert!(scan(&root));
+        assert!(!scan(&root.join("missing")));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
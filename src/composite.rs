@@ -0,0 +1,612 @@
+use rayon::prelude::*;
+use std::time::Instant;
+
+use crate::hips::CelestialWcs;
+use crate::snapshot::SNAPSHOT_MAX_SIZE;
+
+//beyond that the channels become hard to tell apart
+pub const MAX_CHANNELS: usize = 16;
+
+//the hue of the last channel when spreading N > 3 channels over the colour wheel (violet)
+const LAST_HUE: f32 = 270.0;
+
+/// the colour of a channel (linear RGB in [0, 1]) and its weight
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tint {
+    pub colour: [f32; 3],
+    pub weight: f32,
+}
+
+/// a composite channel: a dataset integrated over a frame range, stretched and tinted
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub dataset_id: String,
+    pub frame_start: Option<f64>,
+    pub frame_end: Option<f64>,
+    pub ref_freq: f64,
+    pub flux: Option<String>,
+    pub tint: Tint,
+}
+
+/// a stretched channel image (FITS orientation, the first row at the bottom)
+pub struct ChannelImage {
+    pub luminance: Vec<u8>,
+    pub mask: Vec<u8>,
+    pub width: usize,
+    pub height: usize,
+    pub wcs: Option<CelestialWcs>,
+}
+
+impl ChannelImage {
+    fn is_valid(&self, x: i64, y: i64) -> bool {
+        x >= 0
+            && y >= 0
+            && (x as usize) < self.width
+            && (y as usize) < self.height
+            && self.mask[(y as usize) * self.width + (x as usize)] > 0
+    }
+
+    fn value(&self, x: i64, y: i64) -> f32 {
+        self.luminance[(y as usize) * self.width + (x as usize)] as f32
+    }
+
+    //bilinear interpolation, falling back on the nearest neighbour next to the masked pixels
+    fn sample(&self, x: f64, y: f64) -> Option<f32> {
+        let x0 = x.floor() as i64;
+        let y0 = y.floor() as i64;
+        let fx = (x - x0 as f64) as f32;
+        let fy = (y - y0 as f64) as f32;
+
+        if self.is_valid(x0, y0)
+            && self.is_valid(x0 + 1, y0)
+            && self.is_valid(x0, y0 + 1)
+            && self.is_valid(x0 + 1, y0 + 1)
+        {
+            return Some(
+                (1.0 - fx) * (1.0 - fy) * self.value(x0, y0)
+                    + fx * (1.0 - fy) * self.value(x0 + 1, y0)
+                    + (1.0 - fx) * fy * self.value(x0, y0 + 1)
+                    + fx * fy * self.value(x0 + 1, y0 + 1),
+            );
+        }
+
+        let (xn, yn) = (x.round() as i64, y.round() as i64);
+
+        if self.is_valid(xn, yn) {
+            Some(self.value(xn, yn))
+        } else {
+            None
+        }
+    }
+}
+
+//how the pixels of the reference (first) channel map onto another channel
+enum Mapping {
+    Identity,
+    Wcs(CelestialWcs, CelestialWcs), //reference -> world -> channel
+}
+
+impl Mapping {
+    fn map(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        match self {
+            Mapping::Identity => Some((x, y)),
+            Mapping::Wcs(reference, wcs) => reference
+                .pixel_to_world(x, y)
+                .and_then(|(lon, lat)| wcs.world_to_pixel(lon, lat)),
+        }
+    }
+}
+
+/// red, green and blue for up to three channels (the classic RGB composite),
+/// evenly spaced hues from red to violet otherwise
+pub fn default_colour(index: usize, count: usize) -> [f32; 3] {
+    if count <= 3 {
+        let mut colour = [0.0; 3];
+        colour[index % 3] = 1.0;
+        return colour;
+    }
+
+    let hue = LAST_HUE * (index as f32) / ((count - 1) as f32);
+    let h = hue / 60.0;
+    let x = 1.0 - ((h % 2.0) - 1.0).abs();
+
+    match h as u32 {
+        0 => [1.0, x, 0.0],
+        1 => [x, 1.0, 0.0],
+        2 => [0.0, 1.0, x],
+        3 => [0.0, x, 1.0],
+        4 => [x, 0.0, 1.0],
+        _ => [1.0, 0.0, x],
+    }
+}
+
+pub fn default_tint(index: usize, count: usize) -> Tint {
+    Tint {
+        colour: default_colour(index, count),
+        weight: 1.0,
+    }
+}
+
+/// "#rrggbb" (or "rrggbb") into linear RGB
+pub fn parse_colour(colour: &str) -> Option<[f32; 3]> {
+    let hex = colour.trim().trim_start_matches('#');
+
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut rgb = [0.0; 3];
+
+    for (i, c) in rgb.iter_mut().enumerate() {
+        *c = (u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()? as f32) / 255.0;
+    }
+
+    Some(rgb)
+}
+
+fn parse_tint(value: &serde_json::Value, index: usize, count: usize) -> Result<Tint, String> {
+    let colour = match value["colour"].as_str() {
+        Some(colour) => {
+            parse_colour(colour).ok_or_else(|| format!("channel {}: invalid colour", index))?
+        }
+        None => default_colour(index, count),
+    };
+
+    let weight = match value["weight"].as_f64() {
+        Some(x) if x.is_finite() && x >= 0.0 => x as f32,
+        Some(_) => return Err(format!("channel {}: invalid weight", index)),
+        None => 1.0,
+    };
+
+    Ok(Tint {
+        colour: colour,
+        weight: weight,
+    })
+}
+
+fn parse_array(json: &str) -> Result<Vec<serde_json::Value>, String> {
+    let values = match serde_json::from_str::<serde_json::Value>(json) {
+        Ok(serde_json::Value::Array(values)) => values,
+        Ok(_) => return Err(String::from("a JSON array of channels expected")),
+        Err(err) => return Err(format!("invalid channels: {}", err)),
+    };
+
+    if values.is_empty() || values.len() > MAX_CHANNELS {
+        return Err(format!("between 1 and {} channels expected", MAX_CHANNELS));
+    }
+
+    Ok(values)
+}
+
+/// the live composite colours, i.e. [{"colour":"#ff0000","weight":1.0}, ...]
+pub fn parse_tints(json: &str) -> Result<Vec<Tint>, String> {
+    let values = parse_array(json)?;
+    let count = values.len();
+
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| parse_tint(value, i, count))
+        .collect()
+}
+
+/// the channels of a rendered composite, i.e. [{"datasetId":"...","frame_start":...,
+/// "frame_end":...,"ref_freq":...,"flux":"asinh","colour":"#ff0000","weight":1.0}, ...];
+/// the same dataset may appear several times with different frame ranges
+pub fn parse_channels(json: &str) -> Result<Vec<Channel>, String> {
+    let values = parse_array(json)?;
+    let count = values.len();
+
+    values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| {
+            let dataset_id = match value["datasetId"].as_str() {
+                Some(x) if !x.is_empty() => x.to_string(),
+                _ => return Err(format!("channel {}: no datasetId", i)),
+            };
+
+            Ok(Channel {
+                dataset_id: dataset_id,
+                frame_start: value["frame_start"].as_f64(),
+                frame_end: value["frame_end"].as_f64(),
+                ref_freq: value["ref_freq"].as_f64().unwrap_or(0.0),
+                flux: value["flux"].as_str().map(|x| x.to_string()),
+                tint: parse_tint(value, i, count)?,
+            })
+        })
+        .collect()
+}
+
+//per-channel RGB gains: the colour times the weight, each colour component normalised
+//so that equally bright channels add up to at most white
+fn gains(tints: &[Tint], count: usize) -> Vec<[f32; 3]> {
+    let tints: Vec<Tint> = (0..count)
+        .map(|i| {
+            tints
+                .get(i)
+                .copied()
+                .unwrap_or_else(|| default_tint(i, count))
+        })
+        .collect();
+
+    let mut norm = [1.0f32; 3];
+
+    for (c, n) in norm.iter_mut().enumerate() {
+        *n = tints.iter().map(|t| t.colour[c]).sum::<f32>().max(1.0);
+    }
+
+    tints
+        .iter()
+        .map(|t| [0, 1, 2].map(|c| t.weight * t.colour[c] / norm[c]))
+        .collect()
+}
+
+/// mix N luminance planes into R, G and B planes (additive, clamped); the missing tints
+/// default to default_colour()
+pub fn mix_planes(planes: &[Vec<u8>], tints: &[Tint]) -> [Vec<u8>; 3] {
+    let len = planes.iter().map(|p| p.len()).min().unwrap_or(0);
+    let gains = gains(tints, planes.len());
+
+    [0, 1, 2].map(|c| {
+        (0..len)
+            .into_par_iter()
+            .map(|i| {
+                let sum: f32 = planes
+                    .iter()
+                    .zip(gains.iter())
+                    .map(|(plane, gain)| gain[c] * (plane[i] as f32))
+                    .sum();
+
+                sum.round().clamp(0.0, 255.0) as u8
+            })
+            .collect()
+    })
+}
+
+/// the same pixel grid up to rounding errors
+pub fn same_grid(a: &CelestialWcs, b: &CelestialWcs) -> bool {
+    let close = |x: f64, y: f64, eps: f64| (x - y).abs() <= eps;
+
+    a.projection == b.projection
+        && a.frame == b.frame
+        && close(a.crval1, b.crval1, 1e-9)
+        && close(a.crval2, b.crval2, 1e-9)
+        && close(a.crpix1, b.crpix1, 1e-6)
+        && close(a.crpix2, b.crpix2, 1e-6)
+        && (0..2).all(|i| (0..2).all(|j| close(a.cd[i][j], b.cd[i][j], 1e-12)))
+}
+
+//regrid the channels with a different pixel grid onto the first one
+fn mappings(channels: &[Channel], images: &[ChannelImage]) -> Result<Vec<Mapping>, String> {
+    let reference = &images[0];
+
+    channels
+        .iter()
+        .zip(images.iter())
+        .map(|(channel, image)| {
+            let same_size = image.width == reference.width && image.height == reference.height;
+
+            match (&reference.wcs, &image.wcs) {
+                (Some(ref_wcs), Some(wcs)) => {
+                    if same_size && same_grid(ref_wcs, wcs) {
+                        Ok(Mapping::Identity)
+                    } else if ref_wcs.frame != wcs.frame {
+                        Err(format!(
+                            "{}: cannot regrid between different celestial frames",
+                            channel.dataset_id
+                        ))
+                    } else {
+                        Ok(Mapping::Wcs(ref_wcs.clone(), wcs.clone()))
+                    }
+                }
+                //without a WCS the images of the same size are assumed to share the grid
+                _ if same_size => Ok(Mapping::Identity),
+                _ => Err(format!(
+                    "{}: cannot regrid an image without a celestial WCS",
+                    channel.dataset_id
+                )),
+            }
+        })
+        .collect()
+}
+
+/// an RGBA PNG of the composite on the pixel grid of the first channel (the top row first),
+/// optionally resized to the given width
+pub fn render(
+    channels: &[Channel],
+    images: &[ChannelImage],
+    width: Option<u32>,
+) -> Result<Vec<u8>, String> {
+    let watch = Instant::now();
+
+    if images.is_empty() || images.len() != channels.len() {
+        return Err(String::from("no composite channels"));
+    }
+
+    let reference = &images[0];
+
+    if reference.width == 0 || reference.height == 0 {
+        return Err(String::from("an empty reference image"));
+    }
+
+    let mappings = mappings(channels, images)?;
+    let tints: Vec<Tint> = channels.iter().map(|c| c.tint).collect();
+    let gains = gains(&tints, channels.len());
+
+    let out_w = width
+        .unwrap_or(reference.width as u32)
+        .clamp(16, SNAPSHOT_MAX_SIZE) as usize;
+    let out_h = (((out_w as f64) * (reference.height as f64) / (reference.width as f64)).round()
+        as usize)
+        .clamp(1, SNAPSHOT_MAX_SIZE as usize);
+
+    let sx = (reference.width as f64) / (out_w as f64);
+    let sy = (reference.height as f64) / (out_h as f64);
+
+    let rows: Vec<Vec<u8>> = (0..out_h)
+        .into_par_iter()
+        .map(|j| {
+            let mut row = vec![0u8; 4 * out_w];
+
+            //flip the image so that the top row comes first
+            let y = (reference.height as f64) - ((j as f64) + 0.5) * sy - 0.5;
+
+            for i in 0..out_w {
+                let x = ((i as f64) + 0.5) * sx - 0.5;
+                let mut rgb = [0.0f32; 3];
+                let mut valid = false;
+
+                for ((image, mapping), gain) in images.iter().zip(mappings.iter()).zip(gains.iter())
+                {
+                    let value = mapping.map(x, y).and_then(|(x, y)| image.sample(x, y));
+
+                    if let Some(value) = value {
+                        for (acc, g) in rgb.iter_mut().zip(gain.iter()) {
+                            *acc += g * value;
+                        }
+
+                        valid = true;
+                    }
+                }
+
+                if valid {
+                    let pixel = &mut row[4 * i..4 * i + 4];
+
+                    for (dst, x) in pixel.iter_mut().zip(rgb.iter()) {
+                        *dst = x.round().clamp(0.0, 255.0) as u8;
+                    }
+
+                    pixel[3] = 255;
+                }
+            }
+
+            row
+        })
+        .collect();
+
+    let data = rows.concat();
+    let mut buffer: Vec<u8> = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut buffer, out_w as u32, out_h as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| {
+            writer.write_image_data(&data)?;
+            writer.finish()
+        })
+        .map_err(|err| format!("composite PNG: {}", err))?;
+
+    println!(
+        "{}x{} composite of {} channel(s) rendered in {:?}",
+        out_w,
+        out_h,
+        channels.len(),
+        watch.elapsed()
+    );
+
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wcs(ctype1: &str, ctype2: &str, crpix: (f64, f64)) -> CelestialWcs {
+        let cd = [[-1e-3, 0.0], [0.0, 1e-3]];
+        CelestialWcs::new(ctype1, ctype2, (10.0, 20.0), crpix, cd).unwrap()
+    }
+
+    fn channel(dataset_id: &str) -> Channel {
+        Channel {
+            dataset_id: dataset_id.to_string(),
+            frame_start: None,
+            frame_end: None,
+            ref_freq: 0.0,
+            flux: None,
+            tint: default_tint(0, 1),
+        }
+    }
+
+    fn image(luminance: Vec<u8>, width: usize, wcs: Option<CelestialWcs>) -> ChannelImage {
+        ChannelImage {
+            mask: vec![255; luminance.len()],
+            height: luminance.len() / width,
+            luminance: luminance,
+            width: width,
+            wcs: wcs,
+        }
+    }
+
+    #[test]
+    fn default_colours() {
+        assert_eq!(default_colour(0, 3), [1.0, 0.0, 0.0]);
+        assert_eq!(default_colour(1, 2), [0.0, 1.0, 0.0]);
+        assert_eq!(default_colour(2, 3), [0.0, 0.0, 1.0]);
+
+        //hues from red to violet
+        assert_eq!(default_colour(0, 5), [1.0, 0.0, 0.0]);
+        assert_eq!(default_colour(2, 5), [0.0, 1.0, 0.25]);
+        assert_eq!(default_colour(4, 5), [0.5, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn colours() {
+        assert_eq!(parse_colour("#ff0080"), Some([1.0, 0.0, 128.0 / 255.0]));
+        assert_eq!(parse_colour(" 00FF00 "), Some([0.0, 1.0, 0.0]));
+        assert_eq!(parse_colour("#fff"), None);
+        assert_eq!(parse_colour("#gg0000"), None);
+        assert_eq!(parse_colour("#ffé000"), None);
+    }
+
+    #[test]
+    fn tints() {
+        let tints = parse_tints(r##"[{"colour":"#00ff00","weight":0.5},{}]"##).unwrap();
+
+        assert_eq!(
+            tints,
+            vec![
+                Tint {
+                    colour: [0.0, 1.0, 0.0],
+                    weight: 0.5
+                },
+                default_tint(1, 2)
+            ]
+        );
+
+        assert!(parse_tints("{}").is_err());
+        assert!(parse_tints("[]").is_err());
+        assert!(parse_tints("[").is_err());
+        assert!(parse_tints(&format!("[{}{{}}]", "{},".repeat(MAX_CHANNELS))).is_err());
+        assert!(parse_tints(r#"[{"weight":-1}]"#).is_err());
+        assert!(parse_tints(r#"[{"colour":"red"}]"#).is_err());
+    }
+
+    #[test]
+    fn channels() {
+        let channels = parse_channels(
+            r#"[{"datasetId":"A","frame_start":1.5,"frame_end":2.5,"ref_freq":1e9,"flux":"asinh"},
+                {"datasetId":"B"}]"#,
+        )
+        .unwrap();
+
+        assert_eq!(channels.len(), 2);
+        assert_eq!(channels[0].dataset_id, "A");
+        assert_eq!(channels[0].frame_start, Some(1.5));
+        assert_eq!(channels[0].frame_end, Some(2.5));
+        assert_eq!(channels[0].ref_freq, 1e9);
+        assert_eq!(channels[0].flux.as_deref(), Some("asinh"));
+        assert_eq!(channels[1].frame_start, None);
+        assert_eq!(channels[1].tint, default_tint(1, 2));
+
+        assert!(parse_channels(r#"[{"datasetId":""}]"#).is_err());
+        assert!(parse_channels(r#"[{"frame_start":1}]"#).is_err());
+    }
+
+    #[test]
+    fn additive_mixing() {
+        let planes = vec![vec![200, 0], vec![100, 255], vec![0, 50]];
+
+        //the classic RGB composite
+        let [r, g, b] = mix_planes(&planes, &[]);
+        assert_eq!(
+            (r, g, b),
+            (planes[0].clone(), planes[1].clone(), planes[2].clone())
+        );
+
+        //two red channels share the red component
+        let red = Tint {
+            colour: [1.0, 0.0, 0.0],
+            weight: 1.0,
+        };
+        let [r, g, _] = mix_planes(&planes[0..2], &[red, red]);
+        assert_eq!(r, vec![150, 128]);
+        assert_eq!(g, vec![0, 0]);
+
+        //the weights are not normalised, the sum is clamped
+        let bright = Tint { weight: 4.0, ..red };
+        let [r, _, _] = mix_planes(&planes[0..1], &[bright]);
+        assert_eq!(r, vec![255, 0]);
+    }
+
+    #[test]
+    fn bilinear_sampling() {
+        let mut image = image(vec![0, 100, 200, 100], 2, None);
+
+        assert_eq!(image.sample(0.5, 0.5), Some(100.0));
+        assert_eq!(image.sample(0.25, 0.0), Some(25.0));
+        assert_eq!(image.sample(1.2, 0.8), Some(100.0));
+        assert_eq!(image.sample(-2.0, 0.0), None);
+
+        //the nearest neighbour next to a masked pixel
+        image.mask[3] = 0;
+        assert_eq!(image.sample(0.6, 0.4), Some(100.0));
+        assert_eq!(image.sample(0.9, 0.9), None);
+    }
+
+    #[test]
+    fn grids() {
+        let a = wcs("RA---TAN", "DEC--TAN", (50.0, 50.0));
+
+        assert!(same_grid(
+            &a,
+            &wcs("RA---TAN", "DEC--TAN", (50.0, 50.0 + 1e-9))
+        ));
+        assert!(!same_grid(&a, &wcs("RA---TAN", "DEC--TAN", (51.0, 50.0))));
+        assert!(!same_grid(&a, &wcs("RA---SIN", "DEC--SIN", (50.0, 50.0))));
+
+        let channels = [channel("a"), channel("b")];
+
+        let images = [
+            image(vec![0; 4], 2, Some(a.clone())),
+            image(vec![0; 4], 2, None),
+        ];
+        assert!(matches!(
+            mappings(&channels, &images).unwrap()[1],
+            Mapping::Identity
+        ));
+
+        let images = [
+            image(vec![0; 4], 2, Some(a.clone())),
+            image(vec![0; 9], 3, None),
+        ];
+        assert!(mappings(&channels, &images).is_err());
+
+        let galactic = wcs("GLON-TAN", "GLAT-TAN", (50.0, 50.0));
+        let images = [
+            image(vec![0; 4], 2, Some(a.clone())),
+            image(vec![0; 4], 2, Some(galactic)),
+        ];
+        assert!(mappings(&channels, &images).is_err());
+
+        //a shifted grid maps pixel by pixel
+        let shifted = wcs("RA---TAN", "DEC--TAN", (51.0, 52.0));
+        let images = [
+            image(vec![0; 4], 2, Some(a)),
+            image(vec![0; 4], 2, Some(shifted)),
+        ];
+        let mapping = &mappings(&channels, &images).unwrap()[1];
+        let (x, y) = mapping.map(10.0, 10.0).unwrap();
+        assert!((x - 11.0).abs() < 1e-6 && (y - 12.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn rendered_png() {
+        let channels = [channel("a")];
+        let images = [image(vec![0, 255, 0, 255], 2, None)];
+
+        let png = render(&channels, &images, Some(32)).unwrap();
+        let decoder = png::Decoder::new(std::io::Cursor::new(png));
+        let reader = decoder.read_info().unwrap();
+        let info = reader.info();
+
+        assert_eq!((info.width, info.height), (32, 32));
+        assert_eq!(info.color_type, png::ColorType::Rgba);
+
+        assert!(render(&[], &[], None).is_err());
+        assert!(render(&channels, &[image(Vec::new(), 1, None)], None).is_err());
+    }
+}
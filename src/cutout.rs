@@ -0,0 +1,865 @@
+use byteorder::{BigEndian, ByteOrder};
+use rayon::prelude::*;
+use serde_json::{Map, Value};
+
+use crate::hdf5;
+use crate::zipstream::ZipStream;
+
+//the largest accepted binning factor along any axis
+pub const MAX_BINNING: usize = 1024;
+
+/// how the spectral axis of a cutout is reduced
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpectralBinning {
+    Mean,     //average every N frames
+    Decimate, //keep every N-th frame
+}
+
+/// cutout downsampling factors, 1 keeps the native resolution
+#[derive(Debug, Clone, Copy)]
+pub struct Binning {
+    pub x: usize,
+    pub y: usize,
+    pub spectral: usize,
+    pub mode: SpectralBinning,
+}
+
+impl Default for Binning {
+    fn default() -> Self {
+        Binning {
+            x: 1,
+            y: 1,
+            spectral: 1,
+            mode: SpectralBinning::Mean,
+        }
+    }
+}
+
+impl Binning {
+    pub fn new(x: usize, y: usize, spectral: usize, mode: SpectralBinning) -> Self {
+        Binning {
+            x: x.clamp(1, MAX_BINNING),
+            y: y.clamp(1, MAX_BINNING),
+            spectral: spectral.clamp(1, MAX_BINNING),
+            mode: mode,
+        }
+    }
+
+    pub fn is_native(&self) -> bool {
+        self.x == 1 && self.y == 1 && self.spectral == 1
+    }
+
+    /// the binned dimensions of a width x height x depth cutout (partial bins included)
+    pub fn dims(&self, width: usize, height: usize, depth: usize) -> (usize, usize, usize) {
+        (
+            width.div_ceil(self.x),
+            height.div_ceil(self.y),
+            depth.div_ceil(self.spectral),
+        )
+    }
+
+    /// the original frames to be read for the frame range [start, end]
+    pub fn frames(&self, start: usize, end: usize) -> Vec<usize> {
+        match self.mode {
+            SpectralBinning::Mean => (start..=end).collect(),
+            SpectralBinning::Decimate => (start..=end).step_by(self.spectral).collect(),
+        }
+    }
+
+    //the original frames averaged into one output frame
+    fn group(&self) -> usize {
+        match self.mode {
+            SpectralBinning::Mean => self.spectral,
+            SpectralBinning::Decimate => 1,
+        }
+    }
+
+    /// the pixel size multiplier of an axis (0-based), i.e. for CDELTn and the CD matrix
+    pub fn factor(&self, axis: usize) -> f64 {
+        match axis {
+            0 => self.x as f64,
+            1 => self.y as f64,
+            2 => self.spectral as f64,
+            _ => 1.0,
+        }
+    }
+
+    /// the new CRPIXn of an axis (0-based) of a cutout starting at the 0-based pixel offset;
+    /// a binned pixel k (1-based) covers the original pixels offset + f(k - 1) + 1 ..= offset + fk
+    pub fn crpix(&self, axis: usize, crpix: f64, offset: f64) -> f64 {
+        let factor = self.factor(axis);
+
+        //the position of the binned pixel centre within its bin (1-based)
+        let centre = if axis == 2 && self.mode == SpectralBinning::Decimate {
+            1.0
+        } else {
+            (factor + 1.0) / 2.0
+        };
+
+        (crpix - offset - centre) / factor + 1.0
+    }
+}
+
+/// the raw FITS data samples and the rules for the valid ones
+#[derive(Debug, Clone, Copy)]
+pub struct SampleFormat {
+    pub bitpix: i32,
+    pub bscale: f32,
+    pub bzero: f32,
+    pub datamin: f32,
+    pub datamax: f32,
+    pub ignrval: f32,
+    pub blank: Option<i64>, //the undefined value of the integer samples
+}
+
+impl SampleFormat {
+    fn value(&self, data: &[u8], index: usize) -> Option<f32> {
+        let raw = match self.bitpix {
+            8 => data[index] as f64,
+            16 => BigEndian::read_i16(&data[2 * index..]) as f64,
+            32 => BigEndian::read_i32(&data[4 * index..]) as f64,
+            -32 => BigEndian::read_f32(&data[4 * index..]) as f64,
+            -64 => BigEndian::read_f64(&data[8 * index..]),
+            _ => return None,
+        };
+
+        if self.bitpix > 0 && self.blank.map(|x| x as f64) == Some(raw) {
+            return None;
+        }
+
+        let tmp = self.bzero + self.bscale * raw as f32;
+
+        if tmp.is_finite() && tmp >= self.datamin && tmp <= self.datamax && tmp > self.ignrval {
+            Some(tmp)
+        } else {
+            None
+        }
+    }
+}
+
+/// averages the raw frames of a cutout region into binned big-endian float32 (BITPIX = -32) frames
+pub struct FrameBinner {
+    binning: Binning,
+    format: SampleFormat,
+    stride: usize, //the original image width
+    x1: usize,
+    y1: usize,
+    width: usize,
+    height: usize,
+    out_width: usize,
+    sum: Vec<f32>,
+    count: Vec<u32>,
+    frames: usize,
+}
+
+impl FrameBinner {
+    /// region: the (x1, y1, width, height) of the cutout within an image of stride pixels per row
+    pub fn new(
+        binning: Binning,
+        format: SampleFormat,
+        stride: usize,
+        region: (usize, usize, usize, usize),
+    ) -> Self {
+        let (x1, y1, width, height) = region;
+        let (out_width, out_height, _) = binning.dims(width, height, 1);
+
+        FrameBinner {
+            binning: binning,
+            format: format,
+            stride: stride,
+            x1: x1,
+            y1: y1,
+            width: width,
+            height: height,
+            out_width: out_width,
+            sum: vec![0.0; out_width * out_height],
+            count: vec![0; out_width * out_height],
+            frames: 0,
+        }
+    }
+
+    /// add an original frame, returns a binned frame once a spectral bin is complete
+    pub fn push(&mut self, data: &[u8]) -> Option<Vec<u8>> {
+        let out_width = self.out_width;
+        let (bin_x, bin_y) = (self.binning.x, self.binning.y);
+        let (x1, y1, width, height, stride) =
+            (self.x1, self.y1, self.width, self.height, self.stride);
+        let format = self.format;
+
+        //an empty cutout has no rows to bin
+        self.sum
+            .par_chunks_mut(out_width.max(1))
+            .zip(self.count.par_chunks_mut(out_width.max(1)))
+            .enumerate()
+            .for_each(|(row, (sum, count))| {
+                for y in (row * bin_y)..((row + 1) * bin_y).min(height) {
+                    let offset = (y1 + y) * stride + x1;
+
+                    for x in 0..width {
+                        if let Some(value) = format.value(data, offset + x) {
+                            sum[x / bin_x] += value;
+                            count[x / bin_x] += 1;
+                        }
+                    }
+                }
+            });
+
+        self.frames += 1;
+
+        if self.frames >= self.binning.group() {
+            Some(self.flush())
+        } else {
+            None
+        }
+    }
+
+    /// the last (partial) spectral bin, if any
+    pub fn finish(&mut self) -> Option<Vec<u8>> {
+        if self.frames > 0 {
+            Some(self.flush())
+        } else {
+            None
+        }
+    }
+
+    fn flush(&mut self) -> Vec<u8> {
+        let mut bytes = vec![0u8; 4 * self.sum.len()];
+
+        for (i, (sum, count)) in self.sum.iter().zip(self.count.iter()).enumerate() {
+            let mean = if *count > 0 {
+                sum / (*count as f32)
+            } else {
+                f32::NAN
+            };
+
+            BigEndian::write_f32(&mut bytes[4 * i..4 * i + 4], mean);
+        }
+
+        self.sum.iter_mut().for_each(|x| *x = 0.0);
+        self.count.iter_mut().for_each(|x| *x = 0);
+        self.frames = 0;
+
+        bytes
+    }
+}
+
+/// the file format of a cutout download
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CutoutFormat {
+    Fits,
+    Hdf5,
+    Zarr, //a Zarr v2 array in a ZIP store
+    Npy,  //a NumPy .npz archive
+}
+
+impl CutoutFormat {
+    pub fn from_name(name: &str) -> Option<CutoutFormat> {
+        match name.to_lowercase().as_str() {
+            "fits" => Some(CutoutFormat::Fits),
+            "hdf5" | "h5" => Some(CutoutFormat::Hdf5),
+            "zarr" => Some(CutoutFormat::Zarr),
+            "npy" | "npz" => Some(CutoutFormat::Npy),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CutoutFormat::Fits => "fits",
+            CutoutFormat::Hdf5 => "h5",
+            CutoutFormat::Zarr => "zarr.zip",
+            CutoutFormat::Npy => "npz",
+        }
+    }
+}
+
+/// the header cards of a (cut-out) FITS header as attributes: numbers, booleans and strings;
+/// COMMENT, HISTORY and blank cards are skipped
+pub fn header_attributes(header: &[u8]) -> Map<String, Value> {
+    let mut attributes = Map::new();
+
+    for card in header.chunks(80) {
+        let card = String::from_utf8_lossy(card);
+        let keyword = card.get(0..8).unwrap_or("").trim();
+
+        if keyword == "END" {
+            break;
+        }
+
+        if keyword.is_empty() || card.get(8..10) != Some("= ") {
+            continue;
+        }
+
+        if let Some(value) = card_value(card.get(10..).unwrap_or("")) {
+            attributes.insert(keyword.to_string(), value);
+        }
+    }
+
+    attributes
+}
+
+//the value of a header card, without the trailing comment
+fn card_value(text: &str) -> Option<Value> {
+    let text = text.trim_start();
+
+    if let Some(quoted) = text.strip_prefix('\'') {
+        //'' stands for a single quote within a string
+        let mut value = String::new();
+        let mut chars = quoted.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c == '\'' {
+                if chars.peek() == Some(&'\'') {
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+
+            value.push(c);
+        }
+
+        return Some(Value::String(value.trim_end().to_string()));
+    }
+
+    let text = text.split('/').next().unwrap_or("").trim();
+
+    match text {
+        "" => None,
+        "T" => Some(Value::Bool(true)),
+        "F" => Some(Value::Bool(false)),
+        _ => {
+            if let Ok(x) = text.parse::<i64>() {
+                return Some(json!(x));
+            }
+
+            match text.replace('D', "E").parse::<f64>() {
+                Ok(x) if x.is_finite() => Some(json!(x)),
+                _ => Some(Value::String(text.to_string())),
+            }
+        }
+    }
+}
+
+/// the shape of a cutout array; axes in the C order (NAXIS3, NAXIS2, NAXIS1) with the rows
+/// in the FITS order, samples are big-endian float32 physical values (NaN where undefined)
+pub struct ArrayInfo {
+    pub name: String,
+    pub depth: usize,
+    pub height: usize,
+    pub width: usize,
+    pub attributes: Map<String, Value>,
+}
+
+impl ArrayInfo {
+    fn frame_size(&self) -> usize {
+        self.height * self.width * 4
+    }
+
+    fn data_size(&self) -> usize {
+        self.depth * self.frame_size()
+    }
+
+    //the attributes including a note on the axis order, the scaling cards describe
+    //the raw FITS samples and are replaced by those of the float32 values
+    fn all_attributes(&self) -> Map<String, Value> {
+        let mut attributes = self.attributes.clone();
+
+        for keyword in ["BLANK", "DATAMIN", "DATAMAX"] {
+            attributes.remove(keyword);
+        }
+
+        attributes.insert("BITPIX".to_string(), json!(-32));
+        attributes.insert("BSCALE".to_string(), json!(1.0));
+        attributes.insert("BZERO".to_string(), json!(0.0));
+        attributes.insert(
+            "AXES".to_string(),
+            Value::String("NAXIS3,NAXIS2,NAXIS1".to_string()),
+        );
+        attributes
+    }
+}
+
+/// turns the cutout frames into a non-FITS file, one frame at a time
+pub trait ArrayWriter: Send {
+    /// the exact length of the file
+    fn length(&self) -> usize;
+    /// everything preceding the first frame
+    fn begin(&mut self) -> Vec<u8>;
+    /// a complete frame of height x width samples (rows in the FITS order)
+    fn frame(&mut self, data: &[u8]) -> Vec<u8>;
+    /// everything following the last frame
+    fn end(&mut self) -> Vec<u8>;
+}
+
+/// None for FITS, written by the caller
+pub fn array_writer(format: CutoutFormat, info: ArrayInfo) -> Option<Box<dyn ArrayWriter>> {
+    match format {
+        CutoutFormat::Fits => None,
+        CutoutFormat::Hdf5 => Some(Box::new(Hdf5Writer::new(info))),
+        CutoutFormat::Zarr => Some(Box::new(ZarrWriter::new(info))),
+        CutoutFormat::Npy => Some(Box::new(NpzWriter::new(info))),
+    }
+}
+
+struct Hdf5Writer {
+    header: Vec<u8>,
+    length: usize,
+}
+
+impl Hdf5Writer {
+    fn new(info: ArrayInfo) -> Self {
+        let attributes: Vec<(String, hdf5::Attribute)> = info
+            .all_attributes()
+            .into_iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    Value::Number(x) => hdf5::Attribute::Number(x.as_f64().unwrap_or(0.0)),
+                    Value::Bool(x) => hdf5::Attribute::Number(if x { 1.0 } else { 0.0 }),
+                    Value::String(x) => hdf5::Attribute::Text(x),
+                    x => hdf5::Attribute::Text(x.to_string()),
+                };
+
+                (key, value)
+            })
+            .collect();
+
+        let shape = [info.depth as u64, info.height as u64, info.width as u64];
+
+        let header = hdf5::file_header(&info.name, &shape, &attributes);
+
+        Hdf5Writer {
+            length: header.len() + info.data_size(),
+            header: header,
+        }
+    }
+}
+
+impl ArrayWriter for Hdf5Writer {
+    fn length(&self) -> usize {
+        self.length
+    }
+
+    fn begin(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.header)
+    }
+
+    fn frame(&mut self, data: &[u8]) -> Vec<u8> {
+        data.to_vec()
+    }
+
+    fn end(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+}
+
+//one chunk per frame, uncompressed
+struct ZarrWriter {
+    zip: ZipStream,
+    zarray: Vec<u8>,
+    zattrs: Vec<u8>,
+    frame_size: usize,
+    depth: usize,
+    frame: usize,
+}
+
+impl ZarrWriter {
+    fn new(info: ArrayInfo) -> Self {
+        let zarray = json!({
+            "zarr_format": 2,
+            "shape": [info.depth, info.height, info.width],
+            "chunks": [1, info.height.max(1), info.width.max(1)],
+            "dtype": ">f4",
+            "compressor": null,
+            "fill_value": "NaN",
+            "order": "C",
+            "filters": null,
+        });
+
+        ZarrWriter {
+            zip: ZipStream::new(),
+            zarray: zarray.to_string().into_bytes(),
+            zattrs: Value::Object(info.all_attributes())
+                .to_string()
+                .into_bytes(),
+            frame_size: info.frame_size(),
+            depth: info.depth,
+            frame: 0,
+        }
+    }
+
+    fn chunk_name(index: usize) -> String {
+        format!("{}.0.0", index)
+    }
+}
+
+impl ArrayWriter for ZarrWriter {
+    fn length(&self) -> usize {
+        let mut entries = vec![
+            (".zarray".to_string(), self.zarray.len() as u64),
+            (".zattrs".to_string(), self.zattrs.len() as u64),
+        ];
+
+        for index in 0..self.depth {
+            entries.push((ZarrWriter::chunk_name(index), self.frame_size as u64));
+        }
+
+        ZipStream::archive_length(&entries) as usize
+    }
+
+    fn begin(&mut self) -> Vec<u8> {
+        let mut buf = self.zip.entry(".zarray", &self.zarray);
+        buf.extend_from_slice(&self.zip.entry(".zattrs", &self.zattrs));
+        buf
+    }
+
+    fn frame(&mut self, data: &[u8]) -> Vec<u8> {
+        let name = ZarrWriter::chunk_name(self.frame);
+        self.frame += 1;
+
+        self.zip.entry(&name, data)
+    }
+
+    fn end(&mut self) -> Vec<u8> {
+        self.zip.finish()
+    }
+}
+
+//the cutout as data.npy, the attributes as a JSON string in header.npy
+struct NpzWriter {
+    zip: ZipStream,
+    header: Vec<u8>,
+    data_header: Vec<u8>,
+    data_size: usize,
+}
+
+//a version 1.0 .npy header, padded to a multiple of 64 bytes
+fn npy_header(descr: &str, shape: &str) -> Vec<u8> {
+    let dict = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+
+    let unpadded = 10 + dict.len() + 1;
+    let padding = (64 - unpadded % 64) % 64;
+    let header_len = dict.len() + padding + 1;
+
+    let mut buf = b"\x93NUMPY\x01\x00".to_vec();
+    buf.extend_from_slice(&(header_len as u16).to_le_bytes());
+    buf.extend_from_slice(dict.as_bytes());
+    buf.extend(std::iter::repeat_n(b' ', padding));
+    buf.push(b'\n');
+    buf
+}
+
+impl NpzWriter {
+    fn new(info: ArrayInfo) -> Self {
+        //a 0-d array of UTF-32 characters
+        let json: Vec<char> = Value::Object(info.all_attributes())
+            .to_string()
+            .chars()
+            .collect();
+
+        let mut header = npy_header(&format!("<U{}", json.len().max(1)), "()");
+
+        for c in &json {
+            header.extend_from_slice(&(*c as u32).to_le_bytes());
+        }
+
+        if json.is_empty() {
+            header.extend_from_slice(&0u32.to_le_bytes());
+        }
+
+        let shape = format!("({}, {}, {})", info.depth, info.height, info.width);
+
+        NpzWriter {
+            zip: ZipStream::new(),
+            header: header,
+            data_header: npy_header(">f4", &shape),
+            data_size: info.data_size(),
+        }
+    }
+}
+
+impl ArrayWriter for NpzWriter {
+    fn length(&self) -> usize {
+        let entries = [
+            ("header.npy".to_string(), self.header.len() as u64),
+            (
+                "data.npy".to_string(),
+                (self.data_header.len() + self.data_size) as u64,
+            ),
+        ];
+
+        ZipStream::archive_length(&entries) as usize
+    }
+
+    fn begin(&mut self) -> Vec<u8> {
+        let mut buf = self.zip.entry("header.npy", &self.header);
+
+        let size = (self.data_header.len() + self.data_size) as u64;
+        buf.extend_from_slice(&self.zip.begin_entry("data.npy", size));
+
+        self.zip.data(&self.data_header);
+        buf.extend_from_slice(&self.data_header);
+
+        buf
+    }
+
+    fn frame(&mut self, data: &[u8]) -> Vec<u8> {
+        self.zip.data(data);
+        data.to_vec()
+    }
+
+    fn end(&mut self) -> Vec<u8> {
+        let mut buf = self.zip.end_entry();
+        buf.extend_from_slice(&self.zip.finish());
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int16_format() -> SampleFormat {
+        SampleFormat {
+            bitpix: 16,
+            bscale: 2.0,
+            bzero: 10.0,
+            datamin: f32::MIN,
+            datamax: f32::MAX,
+            ignrval: f32::MIN,
+            blank: Some(-1),
+        }
+    }
+
+    fn int16_frame(values: &[i16]) -> Vec<u8> {
+        let mut data = vec![0u8; 2 * values.len()];
+        BigEndian::write_i16_into(values, &mut data);
+        data
+    }
+
+    fn float32_values(data: &[u8]) -> Vec<f32> {
+        data.chunks(4).map(BigEndian::read_f32).collect()
+    }
+
+    #[test]
+    fn binning_factors_are_clamped() {
+        let binning = Binning::new(0, 2 * MAX_BINNING, 3, SpectralBinning::Mean);
+
+        assert_eq!(
+            (binning.x, binning.y, binning.spectral),
+            (1, MAX_BINNING, 3)
+        );
+        assert!(Binning::default().is_native());
+        assert!(!binning.is_native());
+    }
+
+    #[test]
+    fn binned_dimensions_include_partial_bins() {
+        let binning = Binning::new(2, 3, 4, SpectralBinning::Mean);
+
+        assert_eq!(binning.dims(10, 10, 10), (5, 4, 3));
+        assert_eq!(binning.dims(1, 1, 1), (1, 1, 1));
+        assert_eq!(binning.dims(0, 0, 0), (0, 0, 0));
+    }
+
+    #[test]
+    fn spectral_frames() {
+        let mean = Binning::new(1, 1, 3, SpectralBinning::Mean);
+        let decimate = Binning::new(1, 1, 3, SpectralBinning::Decimate);
+
+        assert_eq!(mean.frames(2, 8), vec![2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(decimate.frames(2, 8), vec![2, 5, 8]);
+        assert_eq!(mean.group(), 3);
+        assert_eq!(decimate.group(), 1);
+    }
+
+    #[test]
+    fn reference_pixels() {
+        //the native grid is only shifted
+        assert_eq!(Binning::default().crpix(0, 100.0, 10.0), 90.0);
+
+        //binned pixel 1 spans the original pixels 1..=2, centred at 1.5
+        let binning = Binning::new(2, 4, 3, SpectralBinning::Mean);
+        assert_eq!(binning.crpix(0, 1.5, 0.0), 1.0);
+        assert_eq!(binning.crpix(1, 2.5, 0.0), 1.0);
+        assert_eq!(binning.crpix(0, 11.5, 10.0), 1.0);
+        assert_eq!(binning.crpix(2, 5.0, 0.0), 2.0);
+
+        //a decimated channel keeps the first frame of its bin
+        let binning = Binning::new(1, 1, 3, SpectralBinning::Decimate);
+        assert_eq!(binning.crpix(2, 4.0, 0.0), 2.0);
+        assert_eq!(binning.factor(2), 3.0);
+        assert_eq!(binning.factor(3), 1.0);
+    }
+
+    #[test]
+    fn physical_values_and_undefined_samples() {
+        let format = int16_format();
+        let data = int16_frame(&[0, 1, -1, 3]);
+
+        assert_eq!(format.value(&data, 0), Some(10.0));
+        assert_eq!(format.value(&data, 1), Some(12.0));
+        assert_eq!(format.value(&data, 2), None);
+
+        let format = SampleFormat {
+            datamax: 15.0,
+            ..format
+        };
+        assert_eq!(format.value(&data, 3), None);
+
+        //BLANK does not apply to floating-point data
+        let mut data = vec![0u8; 8];
+        BigEndian::write_f32_into(&[-1.0, f32::NAN], &mut data);
+        let format = SampleFormat {
+            bitpix: -32,
+            bscale: 1.0,
+            bzero: 0.0,
+            ..int16_format()
+        };
+        assert_eq!(format.value(&data, 0), Some(-1.0));
+        assert_eq!(format.value(&data, 1), None);
+    }
+
+    #[test]
+    fn spatial_binning_averages_the_valid_samples() {
+        //a 3x2 region at (1, 0) of a 4x2 image, binned 2x2
+        let binning = Binning::new(2, 2, 1, SpectralBinning::Mean);
+        let mut binner = FrameBinner::new(binning, int16_format(), 4, (1, 0, 3, 2));
+
+        let frame = int16_frame(&[100, 0, 1, 2, 100, 3, -1, 5]);
+        let binned = float32_values(&binner.push(&frame).unwrap());
+
+        //(0, 1, 3, BLANK) and the partial bin (2, 5)
+        assert_eq!(binned, vec![10.0 + 2.0 * 4.0 / 3.0, 10.0 + 2.0 * 3.5]);
+        assert_eq!(binner.finish(), None);
+    }
+
+    #[test]
+    fn spectral_binning_and_empty_bins() {
+        let binning = Binning::new(1, 1, 2, SpectralBinning::Mean);
+        let mut binner = FrameBinner::new(binning, int16_format(), 2, (0, 0, 2, 1));
+
+        assert_eq!(binner.push(&int16_frame(&[1, -1])), None);
+
+        let binned = float32_values(&binner.push(&int16_frame(&[3, -1])).unwrap());
+        assert_eq!(binned[0], 14.0);
+        assert!(binned[1].is_nan());
+
+        //the last, partial spectral bin
+        assert_eq!(binner.push(&int16_frame(&[5, 5])), None);
+        let binned = float32_values(&binner.finish().unwrap());
+        assert_eq!(binned, vec![20.0, 20.0]);
+    }
+
+    fn card(text: &str) -> String {
+        format!("{:<80}", text)
+    }
+
+    #[test]
+    fn header_cards_as_attributes() {
+        let header = [
+            card("SIMPLE  =                    T / conforms to FITS"),
+            card("BITPIX  =                   16"),
+            card("BSCALE  =        2.000000D+00"),
+            card("BUNIT   = 'Jy/beam '           / brightness unit"),
+            card("OBJECT  = 'O''Brien / field'"),
+            card("COMMENT a comment"),
+            card(""),
+            card("CTYPE1  = 'RA---SIN'"),
+            card("EQUINOX =               J2000."),
+            card("END"),
+            card("NAXIS   =                    3"),
+        ]
+        .concat();
+
+        let attributes = header_attributes(header.as_bytes());
+
+        assert_eq!(attributes["SIMPLE"], json!(true));
+        assert_eq!(attributes["BITPIX"], json!(16));
+        assert_eq!(attributes["BSCALE"], json!(2.0));
+        assert_eq!(attributes["BUNIT"], json!("Jy/beam"));
+        assert_eq!(attributes["OBJECT"], json!("O'Brien / field"));
+        assert_eq!(attributes["CTYPE1"], json!("RA---SIN"));
+        assert_eq!(attributes["EQUINOX"], json!("J2000."));
+        assert!(!attributes.contains_key("COMMENT"));
+        assert!(!attributes.contains_key("NAXIS"));
+    }
+
+    #[test]
+    fn format_names() {
+        assert_eq!(CutoutFormat::from_name("FITS"), Some(CutoutFormat::Fits));
+        assert_eq!(CutoutFormat::from_name("h5"), Some(CutoutFormat::Hdf5));
+        assert_eq!(CutoutFormat::from_name("npz"), Some(CutoutFormat::Npy));
+        assert_eq!(CutoutFormat::from_name("zarr"), Some(CutoutFormat::Zarr));
+        assert_eq!(CutoutFormat::from_name("votable"), None);
+        assert_eq!(CutoutFormat::Zarr.extension(), "zarr.zip");
+    }
+
+    #[test]
+    fn npy_headers_are_aligned() {
+        for shape in ["()", "(1, 2, 3)", "(1000, 2000, 3000)"] {
+            let header = npy_header(">f4", shape);
+
+            assert_eq!(header.len() % 64, 0);
+            assert_eq!(&header[0..8], b"\x93NUMPY\x01\x00");
+            assert_eq!(
+                u16::from_le_bytes([header[8], header[9]]) as usize,
+                header.len() - 10
+            );
+            assert_eq!(header.last(), Some(&b'\n'));
+        }
+    }
+
+    fn test_info() -> ArrayInfo {
+        let mut attributes = Map::new();
+        attributes.insert("BITPIX".to_string(), json!(16));
+        attributes.insert("BSCALE".to_string(), json!(2.0));
+        attributes.insert("BLANK".to_string(), json!(-1));
+        attributes.insert("BUNIT".to_string(), json!("Jy"));
+
+        ArrayInfo {
+            name: String::from("data"),
+            depth: 3,
+            height: 2,
+            width: 5,
+            attributes: attributes,
+        }
+    }
+
+    #[test]
+    fn float32_attributes() {
+        let attributes = test_info().all_attributes();
+
+        assert_eq!(attributes["BITPIX"], json!(-32));
+        assert_eq!(attributes["BSCALE"], json!(1.0));
+        assert_eq!(attributes["BZERO"], json!(0.0));
+        assert_eq!(attributes["BUNIT"], json!("Jy"));
+        assert_eq!(attributes["AXES"], json!("NAXIS3,NAXIS2,NAXIS1"));
+        assert!(!attributes.contains_key("BLANK"));
+    }
+
+    #[test]
+    fn array_writers_match_their_lengths() {
+        assert!(array_writer(CutoutFormat::Fits, test_info()).is_none());
+
+        for format in [CutoutFormat::Hdf5, CutoutFormat::Zarr, CutoutFormat::Npy] {
+            let info = test_info();
+            let frame = vec![0u8; info.frame_size()];
+            let depth = info.depth;
+
+            let mut writer = array_writer(format, info).unwrap();
+            let mut file = writer.begin();
+
+            for _ in 0..depth {
+                file.extend_from_slice(&writer.frame(&frame));
+            }
+
+            file.extend_from_slice(&writer.end());
+
+            assert_eq!(file.len(), writer.length(), "{:?}", format);
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//resumable HTTP(S) downloads: response headers, retries with backoff and checksums
+
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::time::Duration;
+
+/// how many times an interrupted download is resumed before giving up
+pub const DOWNLOAD_RETRIES: usize = 5;
+
+const BACKOFF_INITIAL: u64 = 2; //[s]; doubled after every failed attempt
+const BACKOFF_MAX: u64 = 60; //[s]
+
+/// abort a transfer receiving nothing for this long, it will be resumed
+pub const DOWNLOAD_STALL_TIMEOUT: u64 = 60; //[s]
+
+const HEAD_TIMEOUT: u64 = 30; //[s]
+
+/// the delay before the n-th (1-based) retry
+pub fn backoff(attempt: usize) -> Duration {
+    let shift = attempt.saturating_sub(1).min(16) as u32;
+    Duration::from_secs((BACKOFF_INITIAL << shift).min(BACKOFF_MAX))
+}
+
+/// the response headers of a (ranged) GET, as seen by curl's header_function
+#[derive(Debug, Default, Clone)]
+pub struct Response {
+    pub status: u32,
+    pub content_length: Option<u64>,
+    pub total_length: Option<u64>, //the N of 'Content-Range: bytes a-b/N'
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub sha256: Option<String>, //the advertised SHA-256 of the whole file (hex)
+}
+
+impl Response {
+    /// parse a header line; a status line starts a new response (i.e. after a redirection)
+    pub fn parse_line(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim();
+
+        if line.starts_with("HTTP/") {
+            *self = Response::default();
+            self.status = line
+                .split_whitespace()
+                .nth(1)
+                .and_then(|x| x.parse::<u32>().ok())
+                .unwrap_or(0);
+            return;
+        }
+
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), value.trim()),
+            None => return,
+        };
+
+        match name.as_str() {
+            "content-length" => self.content_length = value.parse::<u64>().ok(),
+            "content-range" => {
+                self.total_length = value
+                    .rsplit('/')
+                    .next()
+                    .and_then(|x| x.trim().parse::<u64>().ok())
+            }
+            "etag" => self.etag = Some(value.to_string()),
+            "last-modified" => self.last_modified = Some(value.to_string()),
+            //RFC 9530 'sha-256=:<base64>:' and RFC 3230 'SHA-256=<base64>'
+            "repr-digest" | "digest" => {
+                for item in value.split(',') {
+                    if let Some((algorithm, digest)) = item.split_once('=') {
+                        if algorithm.trim().eq_ignore_ascii_case("sha-256") {
+                            self.sha256 = base64_decode(digest.trim().trim_matches(':')).map(hex);
+                        }
+                    }
+                }
+            }
+            "x-checksum-sha256" => self.sha256 = Some(value.to_ascii_lowercase()),
+            "x-amz-checksum-sha256" => self.sha256 = base64_decode(value).map(hex),
+            _ => {}
+        }
+    }
+
+    /// the size of the whole file, if known
+    pub fn file_size(&self) -> Option<u64> {
+        match self.status {
+            200 => self.content_length,
+            206 | 416 => self.total_length,
+            _ => None,
+        }
+    }
+
+    /// a validator for If-Range: a strong ETag or the Last-Modified date
+    pub fn validator(&self) -> Option<String> {
+        match &self.etag {
+            Some(etag) if !etag.starts_with("W/") => Some(etag.clone()),
+            _ => self.last_modified.clone(),
+        }
+    }
+}
+
+/// can a failed transfer be resumed?
+pub fn is_transient(err: &curl::Error, status: u32) -> bool {
+    if err.is_http_returned_error() {
+        return status == 408 || status == 429 || status >= 500;
+    }
+
+    err.is_couldnt_connect()
+        || err.is_couldnt_resolve_host()
+        || err.is_operation_timedout()
+        || err.is_partial_file()
+        || err.is_recv_error()
+        || err.is_send_error()
+        || err.is_got_nothing()
+        || err.is_ssl_connect_error()
+}
+
+fn hex(bytes: Vec<u8>) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+
+    for c in text.bytes().filter(|c| *c != b'=') {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+
+    Some(bytes)
+}
+
+/// the size of a remote file from a HEAD request, i.e. to estimate the memory needed to load it
+pub fn content_length(url: &str) -> Option<u64> {
+    let mut easy = curl::easy::Easy::new();
+
+    easy.url(url).ok()?;
+    easy.nobody(true).ok()?;
+    easy.follow_location(true).ok()?;
+    easy.timeout(Duration::from_secs(HEAD_TIMEOUT)).ok()?;
+    easy.perform().ok()?;
+
+    match easy.response_code() {
+        Ok(200) => {}
+        _ => return None,
+    };
+
+    match easy.content_length_download() {
+        Ok(x) if x > 0.0 => Some(x as u64),
+        _ => None,
+    }
+}
+
+/// the SHA-256 (hex) of a file
+pub fn sha256_file(path: &str) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+
+    loop {
+        let len = file.read(&mut buffer)?;
+
+        if len == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..len]);
+    }
+
+    Ok(hex(hasher.finalize().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(lines: &[&str]) -> Response {
+        let mut response = Response::default();
+
+        for line in lines {
+            response.parse_line(format!("{}\r\n", line).as_bytes());
+        }
+
+        response
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_a_minute() {
+        let delays: Vec<u64> = (1..=7).map(|n| backoff(n).as_secs()).collect();
+        assert_eq!(delays, [2, 4, 8, 16, 32, 60, 60]);
+        assert_eq!(backoff(0).as_secs(), 2);
+        assert_eq!(backoff(usize::MAX).as_secs(), 60);
+    }
+
+    #[test]
+    fn base64_digests() {
+        assert_eq!(base64_decode("").unwrap(), b"");
+        assert_eq!(base64_decode("TWFu").unwrap(), b"Man");
+        assert_eq!(base64_decode("TWE=").unwrap(), b"Ma");
+        assert_eq!(base64_decode("TQ==").unwrap(), b"M");
+        //the URL-safe alphabet
+        assert_eq!(base64_decode("-_8=").unwrap(), [0xfb, 0xff]);
+        assert!(base64_decode("TW*u").is_none());
+        assert_eq!(hex(vec![0x00, 0x0f, 0xab]), "000fab");
+    }
+
+    #[test]
+    fn ranged_responses() {
+        let response = parse(&[
+            "HTTP/1.1 302 Found",
+            "Location: https://example.org/cube.fits",
+            "Content-Length: 0",
+            "HTTP/1.1 206 Partial Content",
+            "content-range: bytes 100-199/12345",
+            "Content-Length: 100",
+            "ETag: \"abc\"",
+            "Last-Modified: Tue, 15 Nov 1994 12:45:26 GMT",
+            "",
+        ]);
+
+        //a redirection starts afresh
+        assert_eq!(response.status, 206);
+        assert_eq!(response.content_length, Some(100));
+        assert_eq!(response.file_size(), Some(12345));
+        assert_eq!(response.validator().as_deref(), Some("\"abc\""));
+
+        let response = parse(&["HTTP/2 200", "content-length: 2880"]);
+        assert_eq!(response.file_size(), Some(2880));
+
+        let response = parse(&[
+            "HTTP/1.1 416 Range Not Satisfiable",
+            "Content-Range: bytes */2880",
+        ]);
+        assert_eq!(response.file_size(), Some(2880));
+
+        let response = parse(&["HTTP/1.1 404 Not Found", "Content-Length: 9"]);
+        assert_eq!(response.file_size(), None);
+    }
+
+    #[test]
+    fn weak_etags_fall_back_to_the_date() {
+        let response = parse(&[
+            "HTTP/1.1 200 OK",
+            "ETag: W/\"abc\"",
+            "Last-Modified: Tue, 15 Nov 1994 12:45:26 GMT",
+        ]);
+        assert_eq!(
+            response.validator().as_deref(),
+            Some("Tue, 15 Nov 1994 12:45:26 GMT")
+        );
+
+        let response = parse(&["HTTP/1.1 200 OK", "ETag: W/\"abc\""]);
+        assert_eq!(response.validator(), None);
+    }
+
+    #[test]
+    fn advertised_checksums() {
+        //SHA-256 of "abc"
+        let digest = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let encoded = "ungWv48Bz+pBQUDeXa4iI7ADYaOWF3qctBD/YfIAFa0=";
+
+        let response = parse(&[
+            "HTTP/1.1 200 OK",
+            &format!("Repr-Digest: md5=:AAAA:, sha-256=:{}:", encoded),
+        ]);
+        assert_eq!(response.sha256.as_deref(), Some(digest));
+
+        let response = parse(&["HTTP/1.1 200 OK", &format!("Digest: SHA-256={}", encoded)]);
+        assert_eq!(response.sha256.as_deref(), Some(digest));
+
+        let response = parse(&[
+            "HTTP/1.1 200 OK",
+            &format!("x-amz-checksum-sha256: {}", encoded),
+        ]);
+        assert_eq!(response.sha256.as_deref(), Some(digest));
+
+        let response = parse(&[
+            "HTTP/1.1 200 OK",
+            &format!("X-Checksum-Sha256: {}", digest.to_uppercase()),
+        ]);
+        assert_eq!(response.sha256.as_deref(), Some(digest));
+    }
+}
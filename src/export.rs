@@ -0,0 +1,1211 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{mem, ptr};
+use uuid::Uuid;
+use vpx_sys::*;
+
+use crate::fits::{IMAGECACHE, encode_frame, flush_frame};
+use crate::hevc;
+use crate::*; //x265 bindings
+
+//an upper limit on the exported video resolution (Full HD)
+pub const EXPORT_PIXEL_COUNT_LIMIT: u64 = 1920 * 1080;
+
+//the largest number of frames in a single export
+pub const EXPORT_MAX_FRAMES: usize = 10000;
+
+//a keyframe every two seconds to allow seeking in the presentation
+const KEYFRAME_INTERVAL: u32 = 2;
+
+//the x265 constant rate factor / libvpx target bitrate
+const HEVC_CRF: f64 = 20.0;
+const VP9_BITRATE: u32 = 8000; //[kilobits per second]
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    WebM, //VP9
+    Mp4,  //HEVC
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<ExportFormat> {
+        match name.to_lowercase().as_str() {
+            "webm" | "vp9" => Some(ExportFormat::WebM),
+            "mp4" | "hevc" => Some(ExportFormat::Mp4),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::WebM => "webm",
+            ExportFormat::Mp4 => "mp4",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ExportFormat::WebM => "video/webm",
+            ExportFormat::Mp4 => "video/mp4",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExportParams {
+    pub frame_start: f64,
+    pub frame_end: f64,
+    pub ref_freq: f64,
+    pub fps: u32,
+    pub flux: Option<String>,
+    pub label: bool,
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExportStatus {
+    Running(f32), //progress [0, 1]
+    Ready,
+    Failed(String),
+    Missing,
+}
+
+struct ExportJob {
+    dataset_id: String,
+    format: ExportFormat,
+    status: ExportStatus,
+}
+
+lazy_static! {
+    static ref EXPORT_JOBS: Mutex<HashMap<String, ExportJob>> = Mutex::new(HashMap::new());
+}
+
+/// IMAGECACHE/<dataset id>.exports/<job id>.webm|mp4
+pub fn export_directory(dataset_id: &str) -> PathBuf {
+    Path::new(IMAGECACHE).join(format!("{}.exports", dataset_id.replace("/", "_")))
+}
+
+pub fn export_path(dataset_id: &str, job: &str, format: ExportFormat) -> PathBuf {
+    export_directory(dataset_id).join(format!("{}.{}", job, format.extension()))
+}
+
+/// register a new export job, returns its id
+pub fn start_job(dataset_id: &str, format: ExportFormat) -> String {
+    let job = Uuid::new_v4().to_string();
+
+    EXPORT_JOBS.lock().insert(
+        job.clone(),
+        ExportJob {
+            dataset_id: dataset_id.to_string(),
+            format: format,
+            status: ExportStatus::Running(0.0),
+        },
+    );
+
+    job
+}
+
+pub fn set_progress(job: &str, progress: f32) {
+    if let Some(entry) = EXPORT_JOBS.lock().get_mut(job) {
+        entry.status = ExportStatus::Running(progress);
+    }
+}
+
+pub fn end_job(job: &str, result: Result<(), String>) {
+    if let Some(entry) = EXPORT_JOBS.lock().get_mut(job) {
+        entry.status = match result {
+            Ok(()) => ExportStatus::Ready,
+            Err(err) => {
+                println!("video export {}: {}", job, err);
+                ExportStatus::Failed(err)
+            }
+        };
+    }
+}
+
+pub fn status(job: &str) -> ExportStatus {
+    match EXPORT_JOBS.lock().get(job) {
+        Some(entry) => match entry.status {
+            //the file might have been removed together with the dataset cache
+            ExportStatus::Ready if !export_path(&entry.dataset_id, job, entry.format).exists() => {
+                ExportStatus::Missing
+            }
+            ref status => status.clone(),
+        },
+        None => ExportStatus::Missing,
+    }
+}
+
+/// the dataset id and the format of a finished export
+pub fn job_file(job: &str) -> Option<(PathBuf, ExportFormat, String)> {
+    let jobs = EXPORT_JOBS.lock();
+    let entry = jobs.get(job)?;
+
+    if entry.status != ExportStatus::Ready {
+        return None;
+    }
+
+    Some((
+        export_path(&entry.dataset_id, job, entry.format),
+        entry.format,
+        entry.dataset_id.clone(),
+    ))
+}
+
+/// an encoded frame and whether it can be decoded on its own
+pub struct EncodedFrame {
+    pub data: Vec<u8>,
+    pub keyframe: bool,
+}
+
+pub trait VideoFileEncoder {
+    fn encode(&mut self, y: &[u8], index: u32) -> Result<Option<EncodedFrame>, String>;
+    fn flush(&mut self) -> Result<Vec<EncodedFrame>, String>;
+    fn finish(&mut self, frames: &[EncodedFrame], fps: u32) -> Result<Vec<u8>, String>;
+}
+
+/// VP9 (libvpx) frames muxed into WebM
+pub struct Vp9FileEncoder {
+    ctx: vpx_codec_ctx_t,
+    raw: vpx_image,
+    width: u32,
+    height: u32,
+    keyframe_interval: u32,
+}
+
+impl Vp9FileEncoder {
+    pub fn new(width: u32, height: u32, fps: u32) -> Result<Vp9FileEncoder, String> {
+        let mut raw: vpx_image = vpx_image::default();
+        let mut ctx = vpx_codec_ctx_t {
+            name: ptr::null(),
+            iface: ptr::null_mut(),
+            err: VPX_CODEC_ERROR,
+            err_detail: ptr::null(),
+            init_flags: 0,
+            config: vpx_codec_ctx__bindgen_ty_1 { enc: ptr::null() },
+            priv_: ptr::null_mut(),
+        };
+
+        let ret =
+            unsafe { vpx_img_alloc(&mut raw, vpx_img_fmt::VPX_IMG_FMT_I420, width, height, 1) };
+
+        if ret.is_null() {
+            return Err(String::from("VP9: image allocation failed"));
+        }
+
+        let mut cfg = vpx_codec_enc_config_init();
+        let mut ret = unsafe { vpx_codec_enc_config_default(vpx_codec_vp9_cx(), &mut cfg, 0) };
+
+        if ret != VPX_CODEC_OK {
+            unsafe { vpx_img_free(&mut raw) };
+            return Err(String::from("VP9: default configuration failed"));
+        }
+
+        cfg.g_w = width;
+        cfg.g_h = height;
+        cfg.g_timebase.num = 1;
+        cfg.g_timebase.den = fps as i32;
+        cfg.g_lag_in_frames = 0; //one packet per frame
+        cfg.rc_min_quantizer = 4;
+        cfg.rc_max_quantizer = 42;
+        cfg.rc_target_bitrate = VP9_BITRATE;
+        cfg.g_pass = vpx_enc_pass::VPX_RC_ONE_PASS;
+        cfg.g_threads = num_cpus::get_physical().min(4) as u32; //set the upper limit on the number of threads to 4
+
+        ret = unsafe {
+            vpx_codec_enc_init_ver(
+                &mut ctx,
+                vpx_codec_vp9_cx(),
+                &mut cfg,
+                0,
+                VPX_ENCODER_ABI_VERSION as i32,
+            )
+        };
+
+        if ret != VPX_CODEC_OK {
+            unsafe { vpx_img_free(&mut raw) };
+            return Err(format!("VP9: codec init failed {:?}", ret));
+        }
+
+        ret = unsafe {
+            vpx_codec_control_(&mut ctx, vp8e_enc_control_id::VP8E_SET_CPUUSED as i32, 4)
+        };
+
+        if ret != VPX_CODEC_OK {
+            println!("VP9: error setting VP8E_SET_CPUUSED {:?}", ret);
+        }
+
+        Ok(Vp9FileEncoder {
+            ctx: ctx,
+            raw: raw,
+            width: width,
+            height: height,
+            keyframe_interval: KEYFRAME_INTERVAL * fps,
+        })
+    }
+}
+
+impl VideoFileEncoder for Vp9FileEncoder {
+    fn encode(&mut self, y: &[u8], index: u32) -> Result<Option<EncodedFrame>, String> {
+        //I420 with neutral chroma
+        let count = (self.raw.stride[1] as usize) * ((self.height as usize + 1) / 2);
+        let uv: Vec<u8> = vec![128; count];
+
+        self.raw.planes[0] = unsafe { mem::transmute(y.as_ptr()) };
+        self.raw.planes[1] = unsafe { mem::transmute(uv.as_ptr()) };
+        self.raw.planes[2] = unsafe { mem::transmute(uv.as_ptr()) };
+        self.raw.stride[0] = self.width as i32;
+
+        let keyframe = index % self.keyframe_interval == 0;
+        let flags = if keyframe { VPX_EFLAG_FORCE_KF } else { 0 };
+
+        let res = encode_frame(
+            self.ctx,
+            self.raw,
+            index as i64,
+            flags as i64,
+            VPX_DL_GOOD_QUALITY as u64,
+        );
+
+        //the planes fall out of scope
+        self.raw.planes[0] = ptr::null_mut();
+        self.raw.planes[1] = ptr::null_mut();
+        self.raw.planes[2] = ptr::null_mut();
+
+        match res {
+            Ok(Some(data)) => Ok(Some(EncodedFrame {
+                keyframe: keyframe || vp9_keyframe(&data),
+                data: data,
+            })),
+            Ok(None) => Ok(None),
+            Err(err) => Err(format!("VP9 codec error: {:?}", err)),
+        }
+    }
+
+    fn flush(&mut self) -> Result<Vec<EncodedFrame>, String> {
+        match flush_frame(self.ctx, VPX_DL_GOOD_QUALITY as u64) {
+            Ok(Some(data)) => Ok(vec![EncodedFrame {
+                keyframe: vp9_keyframe(&data),
+                data: data,
+            }]),
+            Ok(None) => Ok(Vec::new()),
+            Err(err) => Err(format!("VP9 codec error: {:?}", err)),
+        }
+    }
+
+    fn finish(&mut self, frames: &[EncodedFrame], fps: u32) -> Result<Vec<u8>, String> {
+        Ok(webm_file(self.width, self.height, fps, frames))
+    }
+}
+
+impl Drop for Vp9FileEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            vpx_img_free(&mut self.raw);
+            vpx_codec_destroy(&mut self.ctx);
+        }
+    }
+}
+
+//the uncompressed VP9 frame header: frame_marker, profile, show_existing_frame, frame_type
+fn vp9_keyframe(frame: &[u8]) -> bool {
+    match frame.first() {
+        Some(&b) => {
+            let profile = ((b >> 5) & 1) | (((b >> 4) & 1) << 1);
+            let shift = if profile == 3 { 2 } else { 3 };
+
+            (b >> 6) == 2 && (b >> shift) & 1 == 0 && (b >> (shift - 1)) & 1 == 0
+        }
+        None => false,
+    }
+}
+
+/// HEVC (x265) frames muxed into MP4
+pub struct HevcFileEncoder {
+    x265: hevc::Api,
+    param: *mut x265_param,
+    enc: *mut x265_encoder,
+    pic: *mut x265_picture,
+    width: u32,
+    height: u32,
+    parameter_sets: Vec<Vec<u8>>, //VPS, SPS, PPS without the length prefix
+}
+
+impl HevcFileEncoder {
+    pub fn new(width: u32, height: u32, fps: u32) -> Result<HevcFileEncoder, String> {
+        //8-bit I420 for the players
+        let x265 = hevc::Api::new(8);
+
+        //no B-frames nor lookahead: the decoding order is the presentation order
+        let param: *mut x265_param = x265.param_alloc("fast", "zerolatency");
+
+        if param.is_null() {
+            return Err(String::from("x265: parameter allocation failed"));
+        }
+
+        unsafe {
+            (*param).fpsNum = fps;
+            (*param).fpsDenom = 1;
+            (*param).bRepeatHeaders = 1;
+            (*param).bAnnexB = 0; //4-byte length prefixes as in MP4 samples
+            (*param).internalCsp = X265_CSP_I420 as i32; //monochrome HEVC is not widely supported by players
+            (*param).sourceWidth = width as i32;
+            (*param).sourceHeight = height as i32;
+            (*param).keyframeMax = (KEYFRAME_INTERVAL * fps) as i32;
+
+            //constant quality
+            (*param).rc.rateControlMode = X265_RC_METHODS_X265_RC_CRF as i32;
+            (*param).rc.rfConstant = HEVC_CRF;
+        };
+
+        let pic: *mut x265_picture = unsafe { x265.picture_alloc() };
+        let enc: *mut x265_encoder = unsafe { x265.encoder_open(param) };
+
+        if pic.is_null() || enc.is_null() {
+            unsafe {
+                if !pic.is_null() {
+                    x265.picture_free(pic);
+                }
+
+                x265.param_free(param);
+            }
+
+            return Err(String::from("x265: encoder initialisation failed"));
+        }
+
+        unsafe { x265.picture_init(param, pic) };
+
+        Ok(HevcFileEncoder {
+            x265: x265,
+            param: param,
+            enc: enc,
+            pic: pic,
+            width: width,
+            height: height,
+            parameter_sets: Vec::new(),
+        })
+    }
+
+    fn collect(&mut self, p_nal: *mut x265_nal, nal_count: u32) -> Option<EncodedFrame> {
+        if nal_count == 0 || p_nal.is_null() {
+            return None;
+        }
+
+        let nal_units = unsafe { std::slice::from_raw_parts(p_nal, nal_count as usize) };
+
+        let mut data: Vec<u8> = Vec::new();
+        let mut keyframe = false;
+
+        for unit in nal_units {
+            let payload =
+                unsafe { std::slice::from_raw_parts(unit.payload, unit.sizeBytes as usize) };
+
+            //VPS, SPS, PPS
+            if (32..=34).contains(&unit.type_) && self.parameter_sets.len() < 3 && payload.len() > 4
+            {
+                self.parameter_sets.push(payload[4..].to_vec());
+            }
+
+            //IRAP pictures
+            if (16..=23).contains(&unit.type_) {
+                keyframe = true;
+            }
+
+            data.extend_from_slice(payload);
+        }
+
+        Some(EncodedFrame {
+            data: data,
+            keyframe: keyframe,
+        })
+    }
+}
+
+impl VideoFileEncoder for HevcFileEncoder {
+    fn encode(&mut self, y: &[u8], _index: u32) -> Result<Option<EncodedFrame>, String> {
+        let uv: Vec<u8> =
+            vec![128; ((self.width as usize + 1) / 2) * ((self.height as usize + 1) / 2)];
+
+        unsafe {
+            (*self.pic).stride[0] = self.width as i32;
+            (*self.pic).stride[1] = ((self.width + 1) / 2) as i32;
+            (*self.pic).stride[2] = ((self.width + 1) / 2) as i32;
+            (*self.pic).planes[0] = y.as_ptr() as *mut std::os::raw::c_void;
+            (*self.pic).planes[1] = uv.as_ptr() as *mut std::os::raw::c_void;
+            (*self.pic).planes[2] = uv.as_ptr() as *mut std::os::raw::c_void;
+        }
+
+        let mut nal_count: u32 = 0;
+        let mut p_nal: *mut x265_nal = ptr::null_mut();
+        let p_out: *mut x265_picture = ptr::null_mut();
+
+        let ret = unsafe {
+            self.x265
+                .encoder_encode(self.enc, &mut p_nal, &mut nal_count, self.pic, p_out)
+        };
+
+        //the planes fall out of scope
+        unsafe {
+            for i in 0..3 {
+                (*self.pic).stride[i] = 0;
+                (*self.pic).planes[i] = ptr::null_mut();
+            }
+        }
+
+        if ret < 0 {
+            return Err(format!("x265 encoding error {}", ret));
+        }
+
+        Ok(self.collect(p_nal, nal_count))
+    }
+
+    fn flush(&mut self) -> Result<Vec<EncodedFrame>, String> {
+        let mut frames: Vec<EncodedFrame> = Vec::new();
+
+        loop {
+            let mut nal_count: u32 = 0;
+            let mut p_nal: *mut x265_nal = ptr::null_mut();
+            let p_out: *mut x265_picture = ptr::null_mut();
+
+            let ret = unsafe {
+                self.x265.encoder_encode(
+                    self.enc,
+                    &mut p_nal,
+                    &mut nal_count,
+                    ptr::null_mut(),
+                    p_out,
+                )
+            };
+
+            if ret <= 0 {
+                break;
+            }
+
+            if let Some(frame) = self.collect(p_nal, nal_count) {
+                frames.push(frame);
+            }
+        }
+
+        Ok(frames)
+    }
+
+    fn finish(&mut self, frames: &[EncodedFrame], fps: u32) -> Result<Vec<u8>, String> {
+        if self.parameter_sets.len() < 3 {
+            return Err(String::from("HEVC parameter sets not found"));
+        }
+
+        Ok(mp4_file(
+            self.width,
+            self.height,
+            fps,
+            frames,
+            &self.parameter_sets,
+        ))
+    }
+}
+
+impl Drop for HevcFileEncoder {
+    fn drop(&mut self) {
+        unsafe {
+            self.x265.encoder_close(self.enc);
+            self.x265.picture_free(self.pic);
+            self.x265.param_free(self.param);
+        }
+    }
+}
+
+//Matroska/WebM (EBML) elements
+fn ebml_element(id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut element: Vec<u8> = id
+        .to_be_bytes()
+        .iter()
+        .skip_while(|x| **x == 0)
+        .cloned()
+        .collect();
+
+    //an 8-byte size
+    element.push(0x01);
+    element.extend_from_slice(&(payload.len() as u64).to_be_bytes()[1..]);
+    element.extend_from_slice(payload);
+
+    element
+}
+
+fn ebml_uint(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let first = bytes.iter().position(|x| *x != 0).unwrap_or(7);
+
+    ebml_element(id, &bytes[first..])
+}
+
+fn ebml_float(id: u32, value: f64) -> Vec<u8> {
+    ebml_element(id, &value.to_be_bytes())
+}
+
+fn ebml_string(id: u32, value: &str) -> Vec<u8> {
+    ebml_element(id, value.as_bytes())
+}
+
+/// a WebM file with a single VP9 video track
+pub fn webm_file(width: u32, height: u32, fps: u32, frames: &[EncodedFrame]) -> Vec<u8> {
+    let frame_duration = 1000.0 / (fps as f64); //[ms]
+    let writing_app = format!("FITSWebQL {}", crate::VERSION_STRING);
+
+    let header = [
+        ebml_uint(0x4286, 1), //EBMLVersion
+        ebml_uint(0x42F7, 1), //EBMLReadVersion
+        ebml_uint(0x42F2, 4), //EBMLMaxIDLength
+        ebml_uint(0x42F3, 8), //EBMLMaxSizeLength
+        ebml_string(0x4282, "webm"),
+        ebml_uint(0x4287, 4), //DocTypeVersion
+        ebml_uint(0x4285, 2), //DocTypeReadVersion
+    ]
+    .concat();
+
+    let info = [
+        ebml_uint(0x2AD7B1, 1000000), //TimecodeScale: 1ms
+        ebml_float(0x4489, frames.len() as f64 * frame_duration),
+        ebml_string(0x4D80, &writing_app), //MuxingApp
+        ebml_string(0x5741, &writing_app),
+    ]
+    .concat();
+
+    let video = [
+        ebml_uint(0xB0, width as u64),
+        ebml_uint(0xBA, height as u64),
+    ]
+    .concat();
+
+    let track = [
+        ebml_uint(0xD7, 1),   //TrackNumber
+        ebml_uint(0x73C5, 1), //TrackUID
+        ebml_uint(0x83, 1),   //TrackType: video
+        ebml_uint(0x9C, 0),   //FlagLacing
+        ebml_string(0x86, "V_VP9"),
+        ebml_uint(0x23E383, (1.0e6 * frame_duration) as u64), //DefaultDuration [ns]
+        ebml_element(0xE0, &video),
+    ]
+    .concat();
+
+    let mut segment = [
+        ebml_element(0x1549A966, &info),
+        ebml_element(0x1654AE6B, &ebml_element(0xAE, &track)),
+    ]
+    .concat();
+
+    //a new cluster at every keyframe (the relative block timecodes are 16-bit)
+    let mut cluster: Vec<u8> = Vec::new();
+    let mut cluster_time: u64 = 0;
+
+    for (i, frame) in frames.iter().enumerate() {
+        let time = (i as f64 * frame_duration).round() as u64;
+
+        if frame.keyframe || time - cluster_time > 30000 || i == 0 {
+            if !cluster.is_empty() {
+                segment.extend(ebml_element(0x1F43B675, &cluster));
+            }
+
+            cluster = ebml_uint(0xE7, time); //Timecode
+            cluster_time = time;
+        }
+
+        let mut block: Vec<u8> = vec![0x81]; //the track number
+        block.extend_from_slice(&((time - cluster_time) as i16).to_be_bytes());
+        block.push(if frame.keyframe { 0x80 } else { 0x00 });
+        block.extend_from_slice(&frame.data);
+
+        cluster.extend(ebml_element(0xA3, &block)); //SimpleBlock
+    }
+
+    if !cluster.is_empty() {
+        segment.extend(ebml_element(0x1F43B675, &cluster));
+    }
+
+    [
+        ebml_element(0x1A45DFA3, &header),
+        ebml_element(0x18538067, &segment),
+    ]
+    .concat()
+}
+
+//ISO base media file format boxes
+fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+    let mut b: Vec<u8> = Vec::with_capacity(8 + payload.len());
+    b.extend_from_slice(&((8 + payload.len()) as u32).to_be_bytes());
+    b.extend_from_slice(kind);
+    b.extend_from_slice(payload);
+    b
+}
+
+fn mp4_full_box(kind: &[u8; 4], version: u8, flags: u32, payload: &[u8]) -> Vec<u8> {
+    let mut b: Vec<u8> = vec![version];
+    b.extend_from_slice(&flags.to_be_bytes()[1..]);
+    b.extend_from_slice(payload);
+    mp4_box(kind, &b)
+}
+
+const MP4_MATRIX: [u32; 9] = [0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000];
+
+fn mp4_matrix() -> Vec<u8> {
+    MP4_MATRIX.iter().flat_map(|x| x.to_be_bytes()).collect()
+}
+
+//remove the emulation prevention bytes (00 00 03) from a NAL unit
+fn nal_to_rbsp(nal: &[u8]) -> Vec<u8> {
+    let mut rbsp: Vec<u8> = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+
+    for &b in nal {
+        if zeros >= 2 && b == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if b == 0 { zeros + 1 } else { 0 };
+        rbsp.push(b);
+    }
+
+    rbsp
+}
+
+//the HEVC decoder configuration record
+fn hvcc_box(parameter_sets: &[Vec<u8>]) -> Vec<u8> {
+    //the general profile_tier_level follows the 2-byte NAL header and one byte of the SPS
+    let sps = nal_to_rbsp(&parameter_sets[1]);
+    let mut ptl = [0u8; 12];
+
+    if sps.len() >= 15 {
+        ptl.copy_from_slice(&sps[3..15]);
+    }
+
+    let mut b: Vec<u8> = vec![1]; //configurationVersion
+    b.extend_from_slice(&ptl[0..12]); //profile space/tier/idc, compatibility flags, constraint flags, level
+    b.extend_from_slice(&[0xF0, 0x00]); //min_spatial_segmentation_idc
+    b.push(0xFC); //parallelismType
+    b.push(0xFD); //chromaFormat: 4:2:0
+    b.push(0xF8); //bitDepthLumaMinus8
+    b.push(0xF8); //bitDepthChromaMinus8
+    b.extend_from_slice(&[0x00, 0x00]); //avgFrameRate
+    b.push(0x0F); //numTemporalLayers 1, temporalIdNested 1, lengthSizeMinusOne 3
+    b.push(parameter_sets.len() as u8);
+
+    for nal in parameter_sets {
+        let nal_type = (nal[0] >> 1) & 0x3F;
+        b.push(0x80 | nal_type); //array_completeness
+        b.extend_from_slice(&1u16.to_be_bytes());
+        b.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        b.extend_from_slice(nal);
+    }
+
+    mp4_box(b"hvcC", &b)
+}
+
+/// an MP4 file with a single HEVC video track, the index (moov) precedes the media data
+pub fn mp4_file(
+    width: u32,
+    height: u32,
+    fps: u32,
+    frames: &[EncodedFrame],
+    parameter_sets: &[Vec<u8>],
+) -> Vec<u8> {
+    let samples: Vec<(u32, bool)> = frames
+        .iter()
+        .map(|f| (f.data.len() as u32, f.keyframe))
+        .collect();
+
+    let mut file = mp4_header(width, height, fps, &samples, parameter_sets);
+    file.reserve(frames.iter().map(|f| f.data.len()).sum());
+
+    for frame in frames {
+        file.extend_from_slice(&frame.data);
+    }
+
+    file
+}
+
+//ftyp, moov and the mdat header for samples given as (size, keyframe)
+fn mp4_header(
+    width: u32,
+    height: u32,
+    fps: u32,
+    samples: &[(u32, bool)],
+    parameter_sets: &[Vec<u8>],
+) -> Vec<u8> {
+    let n = samples.len() as u32;
+    let movie_timescale: u32 = 1000;
+    let movie_duration = ((n as u64) * (movie_timescale as u64) / (fps as u64)) as u32;
+
+    let ftyp = mp4_box(
+        b"ftyp",
+        &[
+            &b"isom"[..],
+            &512u32.to_be_bytes(),
+            b"isom",
+            b"iso6",
+            b"mp41",
+        ]
+        .concat(),
+    );
+
+    let mvhd = mp4_full_box(
+        b"mvhd",
+        0,
+        0,
+        &[
+            &[0u8; 8][..], //creation/modification times
+            &movie_timescale.to_be_bytes(),
+            &movie_duration.to_be_bytes(),
+            &0x00010000u32.to_be_bytes(), //rate
+            &0x0100u16.to_be_bytes(),     //volume
+            &[0u8; 10],
+            &mp4_matrix(),
+            &[0u8; 24],
+            &2u32.to_be_bytes(), //next_track_ID
+        ]
+        .concat(),
+    );
+
+    let tkhd = mp4_full_box(
+        b"tkhd",
+        0,
+        3, //enabled, in movie
+        &[
+            &[0u8; 8][..],
+            &1u32.to_be_bytes(), //track_ID
+            &[0u8; 4],
+            &movie_duration.to_be_bytes(),
+            &[0u8; 8],
+            &[0u8; 8], //layer, alternate_group, volume, reserved
+            &mp4_matrix(),
+            &(width << 16).to_be_bytes(),
+            &(height << 16).to_be_bytes(),
+        ]
+        .concat(),
+    );
+
+    let mdhd = mp4_full_box(
+        b"mdhd",
+        0,
+        0,
+        &[
+            &[0u8; 8][..],
+            &fps.to_be_bytes(), //timescale: one tick per frame
+            &n.to_be_bytes(),
+            &0x55C4u16.to_be_bytes(), //'und'
+            &[0u8; 2],
+        ]
+        .concat(),
+    );
+
+    let hdlr = mp4_full_box(
+        b"hdlr",
+        0,
+        0,
+        &[&[0u8; 4][..], b"vide", &[0u8; 12], b"VideoHandler\0"].concat(),
+    );
+
+    let vmhd = mp4_full_box(b"vmhd", 0, 1, &[0u8; 8]);
+    let dref = mp4_full_box(
+        b"dref",
+        0,
+        0,
+        &[&1u32.to_be_bytes()[..], &mp4_full_box(b"url ", 0, 1, &[])].concat(),
+    );
+    let dinf = mp4_box(b"dinf", &dref);
+
+    let hev1 = mp4_box(
+        b"hev1",
+        &[
+            &[0u8; 6][..],
+            &1u16.to_be_bytes(), //data_reference_index
+            &[0u8; 16],
+            &(width as u16).to_be_bytes(),
+            &(height as u16).to_be_bytes(),
+            &0x00480000u32.to_be_bytes(), //72 dpi
+            &0x00480000u32.to_be_bytes(),
+            &[0u8; 4],
+            &1u16.to_be_bytes(), //frame_count
+            &[0u8; 32],          //compressorname
+            &0x0018u16.to_be_bytes(),
+            &0xFFFFu16.to_be_bytes(),
+            &hvcc_box(parameter_sets),
+        ]
+        .concat(),
+    );
+
+    let stsd = mp4_full_box(b"stsd", 0, 0, &[&1u32.to_be_bytes()[..], &hev1].concat());
+
+    //every frame lasts one tick
+    let stts = mp4_full_box(
+        b"stts",
+        0,
+        0,
+        &[1u32.to_be_bytes(), n.to_be_bytes(), 1u32.to_be_bytes()].concat(),
+    );
+
+    let keyframes: Vec<u32> = samples
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, keyframe))| *keyframe)
+        .map(|(i, _)| i as u32 + 1)
+        .collect();
+
+    let mut stss_payload = (keyframes.len() as u32).to_be_bytes().to_vec();
+    for k in &keyframes {
+        stss_payload.extend_from_slice(&k.to_be_bytes());
+    }
+    let stss = mp4_full_box(b"stss", 0, 0, &stss_payload);
+
+    //all the samples in a single chunk
+    let stsc = mp4_full_box(
+        b"stsc",
+        0,
+        0,
+        &[
+            1u32.to_be_bytes(),
+            1u32.to_be_bytes(),
+            n.to_be_bytes(),
+            1u32.to_be_bytes(),
+        ]
+        .concat(),
+    );
+
+    let mut stsz_payload = [0u32.to_be_bytes(), n.to_be_bytes()].concat();
+    for (size, _) in samples {
+        stsz_payload.extend_from_slice(&size.to_be_bytes());
+    }
+    let stsz = mp4_full_box(b"stsz", 0, 0, &stsz_payload);
+
+    let media_size: u64 = samples.iter().map(|(size, _)| *size as u64).sum();
+    let large_mdat = media_size + 8 > u32::MAX as u64;
+    let mdat_header_size: u64 = if large_mdat { 16 } else { 8 };
+
+    //the chunk offset depends on the size of moov, which does not depend on the offset value itself
+    let moov = |chunk_offset: u64| -> Vec<u8> {
+        let stco = mp4_full_box(
+            b"co64",
+            0,
+            0,
+            &[&1u32.to_be_bytes()[..], &chunk_offset.to_be_bytes()].concat(),
+        );
+
+        let stbl = mp4_box(
+            b"stbl",
+            &[&stsd[..], &stts, &stss, &stsc, &stsz, &stco].concat(),
+        );
+        let minf = mp4_box(b"minf", &[&vmhd[..], &dinf, &stbl].concat());
+        let mdia = mp4_box(b"mdia", &[&mdhd[..], &hdlr, &minf].concat());
+        let trak = mp4_box(b"trak", &[&tkhd[..], &mdia].concat());
+
+        mp4_box(b"moov", &[&mvhd[..], &trak].concat())
+    };
+
+    let moov_size = moov(0).len() as u64;
+    let chunk_offset = ftyp.len() as u64 + moov_size + mdat_header_size;
+
+    let mut file: Vec<u8> = Vec::with_capacity(chunk_offset as usize);
+    file.extend(ftyp);
+    file.extend(moov(chunk_offset));
+
+    if large_mdat {
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(b"mdat");
+        file.extend_from_slice(&(media_size + 16).to_be_bytes());
+    } else {
+        file.extend_from_slice(&((media_size + 8) as u32).to_be_bytes());
+        file.extend_from_slice(b"mdat");
+    }
+
+    file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //an EBML variable-length integer: (value, length), the marker bit removed
+    fn vint(b: &[u8]) -> (u64, usize) {
+        let len = b[0].leading_zeros() as usize + 1;
+        let mut value = (b[0] as u64) & (0xFF >> len);
+
+        for x in &b[1..len] {
+            value = (value << 8) | *x as u64;
+        }
+
+        (value, len)
+    }
+
+    //the (id, payload) children of an EBML element
+    fn ebml_children(mut b: &[u8]) -> Vec<(u32, &[u8])> {
+        let mut children = Vec::new();
+
+        while !b.is_empty() {
+            let id_len = b[0].leading_zeros() as usize + 1;
+            let id = b[..id_len]
+                .iter()
+                .fold(0u32, |acc, x| (acc << 8) | *x as u32);
+            let (size, size_len) = vint(&b[id_len..]);
+            let start = id_len + size_len;
+            let end = start + size as usize;
+
+            children.push((id, &b[start..end]));
+            b = &b[end..];
+        }
+
+        children
+    }
+
+    fn be_u32(b: &[u8]) -> u32 {
+        u32::from_be_bytes(b[..4].try_into().unwrap())
+    }
+
+    fn be_u64(b: &[u8]) -> u64 {
+        u64::from_be_bytes(b[..8].try_into().unwrap())
+    }
+
+    //the (type, header size, box size) of the top-level MP4 boxes, checking they tile the file
+    fn mp4_boxes(b: &[u8], file_size: u64) -> Vec<([u8; 4], u64, u64)> {
+        let mut boxes = Vec::new();
+        let mut offset: u64 = 0;
+
+        while offset < file_size {
+            let at = &b[offset as usize..];
+            let kind: [u8; 4] = at[4..8].try_into().unwrap();
+
+            let (header, size) = match be_u32(at) {
+                1 => (16, be_u64(&at[8..])),
+                size => (8, size as u64),
+            };
+
+            boxes.push((kind, header, size));
+            offset += size;
+        }
+
+        assert_eq!(offset, file_size);
+        boxes
+    }
+
+    //the child box sizes of a container add up to its payload
+    fn mp4_container_sizes(b: &[u8]) {
+        let mut offset = 0;
+
+        while offset < b.len() {
+            let size = be_u32(&b[offset..]) as usize;
+            let kind = &b[offset + 4..offset + 8];
+
+            assert!(size >= 8 && offset + size <= b.len());
+
+            if [&b"moov"[..], b"trak", b"mdia", b"minf", b"stbl", b"dinf"].contains(&kind) {
+                mp4_container_sizes(&b[offset + 8..offset + size]);
+            }
+
+            offset += size;
+        }
+
+        assert_eq!(offset, b.len());
+    }
+
+    //the single chunk offset
+    fn co64(b: &[u8]) -> u64 {
+        let at = b.windows(4).position(|w| w == b"co64").unwrap();
+        assert_eq!(be_u32(&b[at + 8..]), 1); //entry_count
+        be_u64(&b[at + 12..])
+    }
+
+    //VPS, SPS (with an emulation prevention byte in the profile_tier_level) and PPS
+    fn parameter_sets() -> Vec<Vec<u8>> {
+        vec![
+            vec![0x40, 0x01, 0x0C, 0x01],
+            vec![
+                0x42, 0x01, 0x01, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00,
+                0x00, 0x03, 0x00, 0x5D, 0xA0,
+            ],
+            vec![0x44, 0x01, 0xC1, 0x72],
+        ]
+    }
+
+    #[test]
+    fn ebml_sizes() {
+        //one-byte id, an 8-byte size
+        assert_eq!(
+            ebml_element(0xA3, &[1, 2, 3]),
+            vec![0xA3, 0x01, 0, 0, 0, 0, 0, 0, 3, 1, 2, 3]
+        );
+
+        //four-byte id
+        assert_eq!(
+            ebml_element(0x1A45DFA3, &[])[..5],
+            [0x1A, 0x45, 0xDF, 0xA3, 0x01]
+        );
+
+        //unsigned integers in the fewest bytes, zero in one
+        assert_eq!(ebml_uint(0xE7, 0)[9..], [0]);
+        assert_eq!(ebml_uint(0xE7, 300)[9..], [0x01, 0x2C]);
+        assert_eq!(vint(&ebml_uint(0xE7, 300)[1..]), (2, 8));
+    }
+
+    #[test]
+    fn webm_clusters_and_blocks() {
+        let frames = vec![
+            EncodedFrame {
+                data: vec![0x82, 0x49],
+                keyframe: true,
+            },
+            EncodedFrame {
+                data: vec![0x86],
+                keyframe: false,
+            },
+            EncodedFrame {
+                data: vec![0x82, 0x49, 0x83],
+                keyframe: true,
+            },
+        ];
+
+        let file = webm_file(64, 32, 10, &frames);
+        let top = ebml_children(&file);
+
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0].0, 0x1A45DFA3); //EBML
+        assert_eq!(top[1].0, 0x18538067); //Segment
+
+        let segment = ebml_children(top[1].1);
+        let ids: Vec<u32> = segment.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, vec![0x1549A966, 0x1654AE6B, 0x1F43B675, 0x1F43B675]);
+
+        //a cluster per keyframe: (cluster timecode, [(relative time, flags, data)])
+        type Block = (i16, u8, Vec<u8>);
+
+        let clusters: Vec<(u64, Vec<Block>)> = segment[2..]
+            .iter()
+            .map(|(_, cluster)| {
+                let children = ebml_children(cluster);
+                assert_eq!(children[0].0, 0xE7);
+
+                let timecode = children[0]
+                    .1
+                    .iter()
+                    .fold(0u64, |acc, x| (acc << 8) | *x as u64);
+                let blocks = children[1..]
+                    .iter()
+                    .map(|(id, block)| {
+                        assert_eq!(*id, 0xA3);
+                        assert_eq!(block[0], 0x81); //track 1
+                        (
+                            i16::from_be_bytes([block[1], block[2]]),
+                            block[3],
+                            block[4..].to_vec(),
+                        )
+                    })
+                    .collect();
+
+                (timecode, blocks)
+            })
+            .collect();
+
+        assert_eq!(
+            clusters,
+            vec![
+                (
+                    0,
+                    vec![(0, 0x80, vec![0x82, 0x49]), (100, 0x00, vec![0x86])]
+                ),
+                (200, vec![(0, 0x80, vec![0x82, 0x49, 0x83])]),
+            ]
+        );
+    }
+
+    #[test]
+    fn vp9_frame_types() {
+        assert!(vp9_keyframe(&[0x80])); //profile 0
+        assert!(!vp9_keyframe(&[0x84]));
+        assert!(vp9_keyframe(&[0x90])); //profile 2
+        assert!(!vp9_keyframe(&[0x94]));
+        assert!(vp9_keyframe(&[0xB0])); //profile 3, a reserved bit
+        assert!(!vp9_keyframe(&[0xB2]));
+        assert!(!vp9_keyframe(&[0x88])); //show_existing_frame
+        assert!(!vp9_keyframe(&[0x00])); //no frame marker
+        assert!(!vp9_keyframe(&[]));
+    }
+
+    #[test]
+    fn emulation_prevention_bytes() {
+        assert_eq!(nal_to_rbsp(&[0, 0, 3, 1]), vec![0, 0, 1]);
+        assert_eq!(nal_to_rbsp(&[0, 0, 3, 0, 0, 3, 2]), vec![0, 0, 0, 0, 2]);
+        assert_eq!(nal_to_rbsp(&[0, 0, 3, 3]), vec![0, 0, 3]);
+        assert_eq!(nal_to_rbsp(&[0, 3, 0, 3]), vec![0, 3, 0, 3]);
+    }
+
+    #[test]
+    fn hvcc_record() {
+        let hvcc = hvcc_box(&parameter_sets());
+
+        assert_eq!(be_u32(&hvcc) as usize, hvcc.len());
+        assert_eq!(&hvcc[4..8], b"hvcC");
+
+        //the profile_tier_level without the emulation prevention bytes
+        assert_eq!(
+            hvcc[9..21],
+            [
+                0x01, 0x60, 0x00, 0x00, 0x00, 0x90, 0x00, 0x00, 0x00, 0x00, 0x00, 0x5D
+            ]
+        );
+
+        //one array per parameter set: VPS, SPS, PPS
+        assert_eq!(hvcc[30], 3);
+        assert_eq!(hvcc[31], 0x80 | 32);
+        assert_eq!(&hvcc[32..36], &[0, 1, 0, 4]);
+        assert_eq!(hvcc[40], 0x80 | 33);
+    }
+
+    #[test]
+    fn mp4_layout() {
+        let frames = vec![
+            EncodedFrame {
+                data: vec![1; 100],
+                keyframe: true,
+            },
+            EncodedFrame {
+                data: vec![2; 50],
+                keyframe: false,
+            },
+        ];
+
+        let file = mp4_file(64, 32, 10, &frames, &parameter_sets());
+        let boxes = mp4_boxes(&file, file.len() as u64);
+
+        let kinds: Vec<&[u8; 4]> = boxes.iter().map(|(kind, _, _)| kind).collect();
+        assert_eq!(kinds, vec![b"ftyp", b"moov", b"mdat"]);
+        assert_eq!(boxes[2], (*b"mdat", 8, 158));
+
+        let moov_end = (boxes[0].2 + boxes[1].2) as usize;
+        mp4_container_sizes(&file[..moov_end]);
+
+        //the chunk offset points at the first byte of the first frame
+        let offset = co64(&file) as usize;
+        assert_eq!(offset, moov_end + 8);
+        assert_eq!(file[offset..offset + 100], [1; 100]);
+        assert_eq!(file[offset + 100..], [2; 50]);
+    }
+
+    #[test]
+    fn mp4_large_mdat() {
+        //over 4 GiB of media data, only the header is written
+        let samples = vec![(3_000_000_000u32, true), (2_000_000_000u32, false)];
+        let header = mp4_header(64, 32, 10, &samples, &parameter_sets());
+
+        let media_size: u64 = 5_000_000_000;
+        let file_size = header.len() as u64 + media_size;
+        let boxes = mp4_boxes(&header, file_size);
+
+        assert_eq!(boxes.len(), 3);
+        assert_eq!(boxes[2], (*b"mdat", 16, media_size + 16));
+
+        let moov_end = boxes[0].2 + boxes[1].2;
+        mp4_container_sizes(&header[..moov_end as usize]);
+
+        //past the 64-bit size, the media data follows the header
+        assert_eq!(co64(&header), moov_end + 16);
+        assert_eq!(co64(&header), header.len() as u64);
+    }
+}
@@ -6,15 +6,12 @@ use parking_lot::RwLock;
 use positioned_io::ReadAt;
 use regex::Regex;
 use std;
-use std::cell::RefCell;
-use std::ffi::CString;
 use std::fs::File;
 use std::io::BufWriter;
 use std::io::Cursor;
 use std::io::Seek;
 use std::io::SeekFrom;
 use std::io::{Read, Write};
-use std::rc::Rc;
 use std::slice;
 use std::sync::mpsc;
 use std::thread;
@@ -28,7 +25,24 @@ use wincode::config::Configuration;
 use wincode_derive::SchemaWrite;
 
 use crate::UserParams;
+#[cfg(feature = "av1")]
+use crate::av1;
+use crate::catalogue;
+use crate::composite;
+use crate::cutout;
+use crate::download;
+use crate::export;
+use crate::hevc;
+use crate::hips;
+use crate::noise;
+use crate::photometry;
+use crate::pyramid;
+use crate::registry;
+use crate::s3;
 use crate::server;
+use crate::snapshot;
+use crate::stretch;
+use crate::table;
 use ::actix::*;
 use rayon;
 use rayon::prelude::*;
@@ -77,7 +91,7 @@ use ocl::ProQue;
 #[cfg(feature = "opencl")]
 use rand::distributions::{Distribution, StandardNormal, Uniform};
 
-use curl::easy::Easy;
+use curl::easy::{Easy, List};
 
 use num;
 use num_integer::Integer;
@@ -140,6 +154,180 @@ pub fn encode_frame(
     }
 }
 
+/// the IMAGECACHE file of an AV1 still image, 8-bit images keep the original <id>.av1.img name
+pub fn av1_image_filename(dataset_id: &str, bit_depth: u32) -> String {
+    if bit_depth > 8 {
+        format!(
+            "{}/{}.av1.{}bit.img",
+            IMAGECACHE,
+            dataset_id.replace("/", "_"),
+            bit_depth
+        )
+    } else {
+        format!("{}/{}.av1.img", IMAGECACHE, dataset_id.replace("/", "_"))
+    }
+}
+
+/// the IMAGECACHE file of a 10/12-bit VP9 still image, 8-bit images keep the original <id>.img name
+pub fn vpx_image_filename(dataset_id: &str, bit_depth: u32) -> String {
+    if bit_depth > 8 {
+        format!(
+            "{}/{}.vp9.{}bit.img",
+            IMAGECACHE,
+            dataset_id.replace("/", "_"),
+            bit_depth
+        )
+    } else {
+        format!("{}/{}.img", IMAGECACHE, dataset_id.replace("/", "_"))
+    }
+}
+
+/// the luma bit depth requested by a client: 8 (default), 10 or 12
+pub fn supported_bit_depth(bits: u32) -> u32 {
+    match bits {
+        10 | 12 => bits,
+        _ => 8,
+    }
+}
+
+/// a VP9 profile 2 keyframe with 10/12-bit luma (I42016, 16-bit samples),
+/// None unless libvpx has been built with --enable-vp9-highbitdepth
+pub fn encode_vpx_still_hbd(width: u32, height: u32, y: &[u16], bit_depth: u32) -> Option<Vec<u8>> {
+    let watch = Instant::now();
+
+    let mut raw: vpx_image = vpx_image::default();
+    let mut ctx = vpx_codec_ctx_t {
+        name: ptr::null(),
+        iface: ptr::null_mut(),
+        err: VPX_CODEC_ERROR,
+        err_detail: ptr::null(),
+        init_flags: 0,
+        config: vpx_codec_ctx__bindgen_ty_1 { enc: ptr::null() },
+        priv_: ptr::null_mut(),
+    };
+
+    //a workaround around a bug in libvpx triggered when h > w, the samples stay in place
+    let (w, h) = if width > height {
+        (width, height)
+    } else {
+        (height, width)
+    };
+
+    let align = 1;
+    let ret = unsafe { vpx_img_alloc(&mut raw, vpx_img_fmt::VPX_IMG_FMT_I42016, w, h, align) };
+
+    if ret.is_null() {
+        println!(
+            "VP9 {}-bit image frame error: image allocation failed",
+            bit_depth
+        );
+        return None;
+    }
+
+    //I420 with mid-grey (no colour) chroma planes
+    let chroma_width = (w + 1) / 2;
+    let chroma_count = (chroma_width as usize) * (((h + 1) / 2) as usize);
+
+    let u: Vec<u16> = vec![1 << (bit_depth - 1); chroma_count];
+    let v: Vec<u16> = vec![1 << (bit_depth - 1); chroma_count];
+
+    //16-bit samples, the strides are in bytes
+    raw.bit_depth = bit_depth;
+    raw.planes[0] = y.as_ptr() as *mut u8;
+    raw.planes[1] = u.as_ptr() as *mut u8;
+    raw.planes[2] = v.as_ptr() as *mut u8;
+    raw.stride[0] = 2 * w as i32;
+    raw.stride[1] = 2 * chroma_width as i32;
+    raw.stride[2] = 2 * chroma_width as i32;
+
+    let mut cfg = vpx_codec_enc_config_init();
+    let mut ret = unsafe { vpx_codec_enc_config_default(vpx_codec_vp9_cx(), &mut cfg, 0) };
+
+    if ret != VPX_CODEC_OK {
+        println!("VP9 image frame error: default Configuration failed");
+
+        unsafe { vpx_img_free(&mut raw) };
+
+        return None;
+    }
+
+    cfg.g_w = w;
+    cfg.g_h = h;
+    cfg.g_profile = 2;
+    cfg.g_bit_depth = if bit_depth > 10 {
+        vpx_bit_depth::VPX_BITS_12
+    } else {
+        vpx_bit_depth::VPX_BITS_10
+    };
+    cfg.g_input_bit_depth = bit_depth;
+    cfg.rc_min_quantizer = 10;
+    cfg.rc_max_quantizer = 42;
+    cfg.rc_target_bitrate = 4096; // [kilobits per second]
+    cfg.g_pass = vpx_enc_pass::VPX_RC_ONE_PASS;
+    cfg.g_threads = num_cpus::get_physical().min(4) as u32; //set the upper limit on the number of threads to 4
+
+    ret = unsafe {
+        vpx_codec_enc_init_ver(
+            &mut ctx,
+            vpx_codec_vp9_cx(),
+            &mut cfg,
+            VPX_CODEC_USE_HIGHBITDEPTH as vpx_codec_flags_t,
+            VPX_ENCODER_ABI_VERSION as i32,
+        )
+    };
+
+    if ret != VPX_CODEC_OK {
+        println!(
+            "VP9 {}-bit image frame error: codec init failed {:?}",
+            bit_depth, ret
+        );
+
+        unsafe { vpx_img_free(&mut raw) };
+
+        return None;
+    }
+
+    ret = unsafe { vpx_codec_control_(&mut ctx, vp8e_enc_control_id::VP8E_SET_CPUUSED as i32, 8) };
+
+    if ret != VPX_CODEC_OK {
+        println!("VP9: error setting VP8E_SET_CPUUSED {:?}", ret);
+    }
+
+    let flags = VPX_EFLAG_FORCE_KF;
+
+    //call encode_frame with a valid image, then flush the encoder to signal the end
+    let result =
+        encode_frame(ctx, raw, 0, flags as i64, VPX_DL_BEST_QUALITY as u64).and_then(|frame| {
+            flush_frame(ctx, VPX_DL_BEST_QUALITY as u64).map(|residual| residual.or(frame))
+        });
+
+    unsafe { vpx_img_free(&mut raw) };
+    unsafe { vpx_codec_destroy(&mut ctx) };
+
+    let image_frame = match result {
+        Ok(Some(frame)) => frame,
+        Ok(None) => {
+            println!(
+                "VP9 {}-bit image frame error: no image packet produced",
+                bit_depth
+            );
+            return None;
+        }
+        Err(err) => {
+            println!("codec error: {:?}", err);
+            return None;
+        }
+    };
+
+    println!(
+        "VP9 {}-bit image frame encode time: {:?}",
+        bit_depth,
+        watch.elapsed()
+    );
+
+    Some(image_frame)
+}
+
 pub fn flush_frame(
     mut ctx: vpx_codec_ctx_t,
     deadline: u64,
@@ -152,6 +340,27 @@ pub fn flush_frame(
     }
 }
 
+/// quantise the [0, 1] luminance of the valid pixels, masked pixels are black
+fn quantise_luminance<T: Default + Send>(
+    pixels: &[f32],
+    mask: &[u8],
+    quantise: impl Fn(f32) -> T + Send + Sync,
+    pool: &Option<rayon::ThreadPool>,
+) -> Vec<T> {
+    let convert = || -> Vec<T> {
+        pixels
+            .par_iter()
+            .zip(mask.par_iter())
+            .map(|(x, m)| if *m > 0 { quantise(*x) } else { T::default() })
+            .collect()
+    };
+
+    match pool {
+        Some(pool) => pool.install(convert),
+        None => convert(),
+    }
+}
+
 #[cfg(feature = "zfp")]
 fn zfp_decompress_float_array2d(
     mut buffer: Vec<u8>,
@@ -257,16 +466,24 @@ pub const RAID_COUNT: usize = 8;
 pub const IMAGE_PIXEL_COUNT_LIMIT: u64 = 1280 * 720;
 pub const VIDEO_PIXEL_COUNT_LIMIT: u64 = 720 * 480;
 
-const FITS_CHUNK_LENGTH: usize = 2880;
+pub const FITS_CHUNK_LENGTH: usize = 2880;
 const FITS_LINE_LENGTH: usize = 80;
 
+//the maximum number of header blocks read by FITS::from_header
+const MAX_HEADER_CHUNKS: usize = 1000;
+
+//the header of an object store dataset is read 16 FITS blocks at a time
+const S3_HEADER_LENGTH: u64 = 16 * FITS_CHUNK_LENGTH as u64;
+
 const NBINS: usize = 1024;
 const NBINS2: usize = 16 * 1024;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Codec {
     HEVC,
     VPX,
+    #[cfg(feature = "av1")]
+    AV1,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -281,11 +498,45 @@ pub enum Intensity {
     Integrated,
 }
 
+//the state of a FITS file being parsed while it is downloaded
+struct FitsStream {
+    buffer: Vec<u8>,
+    header: Vec<u8>,
+    no_hdu: i32,
+    frame: usize,
+    frame_size: usize,
+    cdelt3: f64,
+    is_compressed: bool,
+    compression_checked: bool,
+    bz_decoder: Option<BzDecompressor<Vec<u8>>>,
+    gz_decoder: Option<GzDecompressor<Vec<u8>>>,
+    aborted: bool,
+}
+
+impl FitsStream {
+    fn new() -> FitsStream {
+        FitsStream {
+            buffer: Vec::new(),
+            header: Vec::new(),
+            no_hdu: 0,
+            frame: 0,
+            frame_size: 0,
+            cdelt3: 0.0,
+            is_compressed: false,
+            compression_checked: false,
+            bz_decoder: None,
+            gz_decoder: None,
+            aborted: false,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct FITS {
     created: Instant,
     pub dataset_id: String,
-    url: String,
+    pub url: String,
+    pub path: String,
     filesize: u64,
     //basic header/votable
     pub telescope: String,
@@ -355,6 +606,7 @@ pub struct FITS {
     data_mad: RwLock<f32>,
     data_mad_p: RwLock<f32>,
     data_mad_n: RwLock<f32>,
+    frame_stats: RwLock<Vec<Option<noise::ChannelStats>>>, //per-channel noise statistics
     pub pmin: f32,
     pub pmax: f32,
     pub lmin: f32,
@@ -378,6 +630,9 @@ pub struct FITS {
     pub is_optical: bool,
     pub is_xray: bool,
     pub is_dummy: bool,
+    cancelled: std::sync::Arc<std::sync::atomic::AtomicBool>, //the registry flag of this load
+    //the zscale/histeq stretch functions of the whole image
+    stretches: RwLock<std::collections::HashMap<String, std::sync::Arc<stretch::StretchFunction>>>,
     pub status_code: u16,
 }
 
@@ -390,6 +645,32 @@ struct FITSImage {
     alpha: Vec<u8>, //lz4-compressed alpha channel
 }
 
+//a spectrum export region: the first channel, the centre [px] and the size [px], [deg]
+struct SpectrumRegion {
+    start: usize,
+    cx: i32,
+    cy: i32,
+    dimx: i32,
+    dimy: i32,
+    lng: f64,
+    lat: f64,
+    width: f64,
+    height: f64,
+}
+
+//an image integrated over a frame range together with its tone-mapping statistics
+struct RangeImage<'a> {
+    pixels: std::borrow::Cow<'a, Vec<f32>>,
+    mask: std::borrow::Cow<'a, Vec<u8>>,
+    pmin: f32,
+    pmax: f32,
+    black: f32,
+    white: f32,
+    median: f32,
+    sensitivity: f32,
+    ratio_sensitivity: f32,
+}
+
 impl FITS {
     pub fn new(id: &String, url: &String, flux: &String) -> FITS {
         let obj_name = match Uuid::parse_str(id) {
@@ -401,6 +682,7 @@ impl FITS {
             created: Instant::now(),
             dataset_id: id.clone(),
             url: url.clone(),
+            path: String::from(""),
             filesize: 0,
             telescope: String::from(""),
             obj_name: obj_name,
@@ -468,6 +750,7 @@ impl FITS {
             data_mad: RwLock::new(0.0),
             data_mad_p: RwLock::new(0.0),
             data_mad_n: RwLock::new(0.0),
+            frame_stats: RwLock::new(Vec::new()),
             pmin: std::f32::MIN,
             pmax: std::f32::MAX,
             lmin: (0.5f32).ln(),
@@ -491,6 +774,8 @@ impl FITS {
             is_optical: true,
             is_xray: false,
             is_dummy: true,
+            cancelled: registry::cancel_flag(id),
+            stretches: RwLock::new(std::collections::HashMap::new()),
             status_code: 404,
         };
 
@@ -581,6 +866,11 @@ impl FITS {
             (0..self.depth)
                 .into_par_iter()
                 .map(|frame| {
+                    //skip the remaining frames of a cancelled load
+                    if self.is_cancelled() {
+                        return Vec::new();
+                    }
+
                     //frame is i32
                     let offset = header_offset + (frame as usize) * frame_size;
                     let mut data_u8: Vec<u8> = vec![0; frame_size];
@@ -757,6 +1047,11 @@ impl FITS {
                 .collect()
         });
 
+        if self.is_cancelled() {
+            println!("{}: loading has been cancelled", self.dataset_id);
+            return false;
+        }
+
         self.data_f16 = gather_f16;
 
         self.frame_min = thread_frame_min
@@ -1309,6 +1604,189 @@ impl FITS {
         return true;
     }
 
+    /// only the header of a local (optionally gzip/bzip2-compressed) FITS file, i.e. for the
+    /// catalogue; None without a valid FITS header
+    pub fn from_header(filepath: &std::path::Path) -> Option<FITS> {
+        let file = File::open(filepath).ok()?;
+        let (mut reader, _) = open_fits_reader(file);
+
+        let mut fits = FITS::header_only(filepath);
+        fits.read_header_only(&mut reader)?;
+
+        Some(fits)
+    }
+
+    fn header_only(filepath: &std::path::Path) -> FITS {
+        let id = filepath.to_string_lossy().into_owned();
+        let mut fits = FITS::new(&id, &String::from(""), &String::from(""));
+        fits.path = id.clone();
+        fits.obj_name = String::from(""); //not the file name
+
+        fits
+    }
+
+    //parse the header of the first HDU with data; returns the offset of the data [bytes]
+    fn read_header_only(&mut self, reader: &mut dyn Read) -> Option<u64> {
+        let mut header: Vec<u8> = Vec::new();
+        let mut chunk = [0u8; FITS_CHUNK_LENGTH];
+        let mut offset: u64 = 0;
+
+        for _ in 0..MAX_HEADER_CHUNKS {
+            reader.read_exact(&mut chunk).ok()?;
+            offset = offset + FITS_CHUNK_LENGTH as u64;
+
+            let end = self.parse_fits_header_chunk(&chunk).ok()?;
+            header.extend_from_slice(&chunk);
+
+            //try again, there may be an image extension
+            if end && self.naxis == 0 {
+                header = Vec::new();
+                continue;
+            }
+
+            if end {
+                //test for frequency/velocity
+                self.frame_reference_unit();
+                self.frame_reference_type();
+
+                if self.restfrq > 0.0 {
+                    self.has_frequency = true;
+                }
+
+                self.has_header = true;
+                self.header = String::from_utf8_lossy(&header).into_owned();
+
+                return Some(offset);
+            }
+        }
+
+        None
+    }
+
+    /// a preview of a local FITS file without loading the dataset: the middle channel (the
+    /// first one for compressed files, which would otherwise be decompressed in full) reduced
+    /// to at most <size> pixels across and zscale-stretched; returns interleaved 8-bit
+    /// luminance + alpha with the first row at the top, and the dimensions
+    pub fn thumbnail_from_path(
+        filepath: &std::path::Path,
+        size: usize,
+    ) -> Option<(Vec<u8>, usize, usize)> {
+        let file = File::open(filepath).ok()?;
+        let (mut reader, is_compressed) = open_fits_reader(file.try_clone().ok()?);
+
+        let mut fits = FITS::header_only(filepath);
+        let offset = fits.read_header_only(&mut reader)?;
+
+        let (width, height) = (fits.width, fits.height);
+
+        match fits.bitpix {
+            8 | 16 | 32 | -32 | -64 => {}
+            _ => return None,
+        };
+
+        if width == 0 || height == 0 || size == 0 {
+            return None;
+        }
+
+        let bytes_per_pixel = (fits.bitpix.abs() / 8) as usize;
+        let row_bytes = width * bytes_per_pixel;
+
+        let channel = if is_compressed { 0 } else { fits.depth / 2 };
+        let data_offset = offset + (channel * height * row_bytes) as u64;
+
+        //every <step>-th row, averaged over <step> pixels along the row
+        let step = ((width.max(height) + size - 1) / size).max(1);
+        let w = (width + step - 1) / step;
+        let h = (height + step - 1) / step;
+
+        let pixel = |buf: &[u8], x: usize| -> f32 {
+            let b = &buf[x * bytes_per_pixel..(x + 1) * bytes_per_pixel];
+
+            let raw = match fits.bitpix {
+                8 => b[0] as f32,
+                16 => i16::from_be_bytes([b[0], b[1]]) as f32,
+                32 => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f32,
+                -32 => f32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+                _ => f64::from_be_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]) as f32,
+            };
+
+            let tmp = fits.bzero + fits.bscale * raw;
+
+            if tmp.is_finite() && tmp >= fits.datamin && tmp <= fits.datamax && tmp > fits.ignrval {
+                tmp
+            } else {
+                std::f32::NAN
+            }
+        };
+
+        let mut values: Vec<f32> = Vec::with_capacity(w * h);
+        let mut buf = vec![0u8; row_bytes];
+        let mut next_row = 0;
+
+        for j in 0..h {
+            let y = j * step;
+
+            if is_compressed {
+                //skip the rows in-between
+                while next_row <= y {
+                    reader.read_exact(&mut buf).ok()?;
+                    next_row = next_row + 1;
+                }
+            } else {
+                file.read_exact_at(data_offset + (y * row_bytes) as u64, &mut buf)
+                    .ok()?;
+            }
+
+            for i in 0..w {
+                let mut sum = 0.0_f32;
+                let mut count = 0;
+
+                for x in (i * step)..((i + 1) * step).min(width) {
+                    let value = pixel(&buf, x);
+
+                    if value.is_finite() {
+                        sum += value;
+                        count += 1;
+                    }
+                }
+
+                values.push(if count > 0 {
+                    sum / (count as f32)
+                } else {
+                    std::f32::NAN
+                });
+            }
+        }
+
+        let (black, white) = match stretch::zscale(&values) {
+            Some((z1, z2)) if z2 > z1 => (z1, z2),
+            _ => {
+                let valid = values.iter().filter(|x| x.is_finite());
+                let lo = valid.clone().fold(std::f32::MAX, |a, b| a.min(*b));
+                let hi = valid.fold(std::f32::MIN, |a, b| a.max(*b));
+                (lo, hi.max(lo + std::f32::EPSILON))
+            }
+        };
+
+        //FITS images start with the bottom row
+        let mut data: Vec<u8> = Vec::with_capacity(2 * w * h);
+
+        for row in values.chunks(w).rev() {
+            for value in row {
+                if value.is_finite() {
+                    let x = ((value - black) / (white - black)).clamp(0.0, 1.0);
+                    data.push((255.0 * x) as u8);
+                    data.push(255);
+                } else {
+                    data.push(0);
+                    data.push(0);
+                }
+            }
+        }
+
+        Some((data, w, h))
+    }
+
     pub fn from_path(
         id: &String,
         flux: &String,
@@ -1318,6 +1796,7 @@ impl FITS {
     ) -> FITS {
         let mut fits = FITS::new(id, url, flux);
         fits.is_dummy = false;
+        fits.path = filepath.to_string_lossy().into_owned();
 
         //load data from filepath
         let mut f = match File::open(filepath) {
@@ -1510,6 +1989,10 @@ impl FITS {
                     cdelt3 as f32,
                     &server,
                 ) {
+                    if fits.is_cancelled() {
+                        return fits;
+                    }
+
                     println!("CRITICAL ERROR parallel reading from half-float cache");
                     fits.status_code = 500;
 
@@ -1576,6 +2059,11 @@ impl FITS {
                     let mut frame: usize = 0;
 
                     for data in rx {
+                        if fits.is_cancelled() {
+                            println!("{}: loading has been cancelled", id);
+                            return fits;
+                        }
+
                         fits.process_cube_frame(&data, cdelt3 as f32, frame);
                         frame = frame + 1;
                         fits.send_progress_notification(
@@ -1673,9 +2161,30 @@ impl FITS {
 
         println!("FITS::from_url({})", url);
 
+        //the dataset id identifies the URL, the registry runs one load per dataset
+        let filename = format!("{}/{}.fits", FITSCACHE, id.replace("/", "_"));
+
+        if std::path::Path::new(&filename).exists() {
+            println!("{} has already been downloaded to {}", url, filename);
+            return FITS::from_path(id, flux, std::path::Path::new(&filename), url, server);
+        }
+
+        //object storage has its own (ranged, parallel) download
+        if s3::is_s3_url(url) {
+            return FITS::from_s3(id, flux, url, server);
+        }
+
         let tmp = format!("{}/{}.fits.tmp", FITSCACHE, id.replace("/", "_"));
 
-        let mut cachefile = match File::create(&tmp) {
+        //the ETag/Last-Modified of a partial download, sent as If-Range when resuming
+        let validator_file = format!("{}.validator", tmp);
+        let mut validator = std::fs::read_to_string(&validator_file).ok();
+
+        let mut cachefile = match std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&tmp)
+        {
             Err(ref e) => {
                 println!("Could not create {} ({})!", tmp, e);
                 fits.status_code = 500;
@@ -1684,281 +2193,298 @@ impl FITS {
             Ok(file) => file,
         };
 
-        /*let mut easy = Easy2::new(fits);
-        easy.get(true).unwrap();
-        easy.url(url).unwrap();
-        easy.perform().unwrap();*/
+        let mut stream = FitsStream::new();
 
-        let mut buffer = Vec::new();
-        let mut easy = Easy::new();
+        //replay an interrupted download through the parser before resuming it
+        if let Ok(mut partial) = File::open(&tmp) {
+            let mut chunk = vec![0u8; 1024 * 1024];
 
-        //enable automatic URL relocations
-        match easy.follow_location(true) {
-            Ok(_) => {}
-            Err(err) => println!("curl::follow_location: {}", err),
-        }
+            loop {
+                match partial.read(&mut chunk) {
+                    Ok(0) => break,
+                    Ok(len) => {
+                        if !fits.stream_data(&mut stream, &chunk[..len], server) {
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        println!("cannot read the partial FITS file {}: {}", tmp, err);
+                        fits.status_code = 500;
+                        stream.aborted = true;
+                        break;
+                    }
+                }
+            }
 
-        match easy.fail_on_error(true) {
-            Ok(_) => {}
-            Err(err) => println!("curl::fail_on_error: {}", err),
+            if fits.filesize > 0 {
+                println!(
+                    "{}: resuming the download from {} bytes",
+                    url, fits.filesize
+                );
+            }
         }
 
-        let mut header: Vec<u8> = Vec::new();
-        let mut end: bool = false;
-        let mut no_hdu: i32 = 0;
-        let mut frame: usize = 0;
-        let mut frame_size: usize = 0;
-
-        let mut total = 0;
-        let mut cdelt3 = 0.0;
+        let mut response = download::Response::default();
+        let mut expected_size: Option<u64> = None;
+        let mut sha256: Option<String> = None;
+        let mut complete = false;
+        let mut changed = false;
+        let mut fatal = false;
+        let mut attempt = 0;
 
-        let mut is_compressed = false;
-        let mut compression_checked = false;
+        while !stream.aborted && !fits.is_cancelled() {
+            let offset = fits.filesize;
+            let resumed = offset > 0 && validator.is_some();
 
-        type BzStream = BzDecompressor<Vec<u8>>;
-        type GzStream = GzDecompressor<Vec<u8>>;
+            let mut easy = Easy::new();
 
-        let mut bz_decoder: Option<Rc<RefCell<BzStream>>> = None;
-        let mut gz_decoder: Option<Rc<RefCell<GzStream>>> = None;
+            //enable automatic URL relocations
+            match easy.follow_location(true) {
+                Ok(_) => {}
+                Err(err) => println!("curl::follow_location: {}", err),
+            }
 
-        {
-            easy.url(url).unwrap();
-            easy.progress(true).unwrap();
+            match easy.fail_on_error(true) {
+                Ok(_) => {}
+                Err(err) => println!("curl::fail_on_error: {}", err),
+            }
 
-            let mut transfer = easy.transfer();
+            //abort stalled transfers, they will be resumed
+            match easy.low_speed_limit(1) {
+                Ok(_) => {}
+                Err(err) => println!("curl::low_speed_limit: {}", err),
+            }
 
-            transfer
-                .write_function(|data| {
-                    //println!("curl received {} bytes", data.len());
-                    fits.filesize = fits.filesize + data.len() as u64;
+            match easy.low_speed_time(std::time::Duration::from_secs(
+                download::DOWNLOAD_STALL_TIMEOUT,
+            )) {
+                Ok(_) => {}
+                Err(err) => println!("curl::low_speed_time: {}", err),
+            }
 
-                    match cachefile.write_all(data) {
-                        Ok(_) => {}
-                        Err(err) => {
-                            println!("cannot append to the temporary FITS file: {}", err);
-                            fits.status_code = 500;
-                        }
-                    };
+            //a plain Range header: a server ignoring it replies 200 with the whole file
+            if offset > 0 {
+                match easy.range(&format!("{}-", offset)) {
+                    Ok(_) => {}
+                    Err(err) => println!("curl::range: {}", err),
+                }
 
-                    if !is_compressed {
-                        buffer.extend_from_slice(data);
-                    } else {
-                        match &bz_decoder {
-                            Some(decoder) => {
-                                let mut decoder = decoder.borrow_mut();
-                                match decoder.write_all(&data) {
-                                    Ok(_) => {
-                                        decoder.flush().unwrap();
-                                        let out = decoder.get_mut();
-                                        let len = out.len();
-                                        if len > 0 {
-                                            buffer.extend_from_slice(out);
-                                            out.drain(0..out.len());
-                                        }
-                                    }
-                                    Err(err) => {
-                                        println!("Decompress: {}", err);
-                                        fits.status_code = 500;
-                                    }
-                                }
-                            }
-                            None => {}
-                        }
+                if let Some(validator) = &validator {
+                    let mut list = List::new();
 
-                        match &gz_decoder {
-                            Some(decoder) => {
-                                let mut decoder = decoder.borrow_mut();
-                                match decoder.write_all(&data) {
-                                    Ok(_) => {
-                                        decoder.flush().unwrap();
-                                        let out = decoder.get_mut();
-                                        let len = out.len();
-                                        if len > 0 {
-                                            buffer.extend_from_slice(out);
-                                            out.drain(0..out.len());
-                                        }
-                                    }
-                                    Err(err) => {
-                                        println!("Decompress: {}", err);
-                                        fits.status_code = 500;
-                                    }
-                                }
-                            }
-                            None => {}
-                        }
+                    match list.append(&format!("If-Range: {}", validator)) {
+                        Ok(_) => {}
+                        Err(err) => println!("curl::List::append: {}", err),
                     }
 
-                    if !compression_checked && buffer.len() >= 10 {
-                        print!(
-                            "buffer length: {}, checking for compression...",
-                            buffer.len()
-                        );
-                        //test for magick numbers and the deflate compression type
-                        if buffer[0] == 0x1f && buffer[1] == 0x8b && buffer[2] == 0x08 {
-                            let mut decoder = GzDecompressor::new(Vec::new());
-                            is_compressed = true;
-                            println!("gzip found.");
-
-                            //decompress the incoming data
-                            match decoder.write_all(&buffer) {
-                                Ok(_) => {
-                                    buffer.drain(0..buffer.len());
-                                    decoder.flush().unwrap();
-                                    let out = decoder.get_mut();
-                                    let len = out.len();
-                                    if len > 0 {
-                                        buffer.extend_from_slice(out);
-                                        out.drain(0..out.len());
-                                    }
-                                }
-                                Err(err) => {
-                                    println!("Decompress: {}", err);
-                                    fits.status_code = 500;
-                                }
-                            }
+                    match easy.http_headers(list) {
+                        Ok(_) => {}
+                        Err(err) => println!("curl::http_headers: {}", err),
+                    }
+                }
+            }
 
-                            gz_decoder = Some(Rc::new(RefCell::new(decoder)));
-                        } else
-                        //test for magick numbers and the bzip2 compression type
-                        if buffer[0] == 0x42 && buffer[1] == 0x5a && buffer[2] == 0x68 {
-                            let mut decoder = BzDecompressor::new(Vec::new());
-                            is_compressed = true;
-                            println!("bzip2 found.");
+            easy.url(url).unwrap();
+            easy.progress(true).unwrap();
 
-                            //decompress the incoming data
-                            match decoder.write_all(&buffer) {
-                                Ok(_) => {
-                                    buffer.drain(0..buffer.len());
-                                    decoder.flush().unwrap();
-                                    let out = decoder.get_mut();
-                                    let len = out.len();
-                                    if len > 0 {
-                                        buffer.extend_from_slice(out);
-                                        out.drain(0..out.len());
-                                    }
-                                }
-                                Err(err) => {
-                                    println!("Decompress: {}", err);
-                                    fits.status_code = 500;
-                                }
-                            }
+            let mut first = true;
+            let mut skip: u64 = 0;
+            let status = std::cell::Cell::new(0);
 
-                            bz_decoder = Some(Rc::new(RefCell::new(decoder)));
-                        } else {
-                            println!("no compression found.");
-                        }
+            let result = {
+                let mut transfer = easy.transfer();
 
-                        compression_checked = true;
-                    }
+                transfer
+                    .header_function(|line| {
+                        response.parse_line(line);
+                        status.set(response.status);
+                        true
+                    })
+                    .unwrap();
 
-                    //handle the header first
-                    if !fits.has_header {
-                        while buffer.len() >= FITS_CHUNK_LENGTH && !fits.has_header {
-                            let chunk: Vec<u8> = buffer.drain(0..FITS_CHUNK_LENGTH).collect();
+                transfer
+                    .write_function(|data| {
+                        //abort the transfer of a cancelled load
+                        if fits.is_cancelled() {
+                            return Ok(0);
+                        }
 
-                            no_hdu = no_hdu + 1;
+                        //a full response to a ranged request
+                        if first {
+                            first = false;
 
-                            //parse a FITS header chunk
-                            match fits.parse_fits_header_chunk(&chunk) {
-                                Ok(x) => end = x,
-                                Err(err) => {
-                                    println!("CRITICAL ERROR parsing FITS header: {}", err);
-                                    fits.status_code = 415;
-                                    //terminate the transfer early
+                            if offset > 0 && status.get() == 200 {
+                                if resumed {
+                                    //If-Range did not match: the remote file has changed
+                                    changed = true;
                                     return Ok(0);
                                 }
-                            };
-
-                            header.extend_from_slice(&chunk);
 
-                            //try again, there may be an image extension
-                            if end && fits.naxis == 0 {
-                                header = Vec::new();
-                                end = false;
+                                skip = offset;
                             }
+                        }
 
-                            if end {
-                                //test for frequency/velocity
-                                fits.frame_reference_unit();
-                                fits.frame_reference_type();
+                        let len = data.len();
 
-                                if fits.restfrq > 0.0 {
-                                    fits.has_frequency = true;
-                                }
+                        //skip the bytes received before
+                        let data = if skip > 0 {
+                            let n = skip.min(len as u64) as usize;
+                            skip = skip - n as u64;
+                            &data[n..]
+                        } else {
+                            data
+                        };
 
-                                fits.has_header = true;
+                        if data.is_empty() {
+                            return Ok(len);
+                        }
 
-                                {
-                                    let fits = Arc::new(RwLock::new(Box::new(fits.clone())));
-                                    DATASETS.write().insert(id.clone(), fits.clone());
-                                }
+                        match cachefile.write_all(data) {
+                            Ok(_) => {}
+                            Err(err) => {
+                                println!("cannot append to the temporary FITS file: {}", err);
+                                fits.status_code = 500;
+                                stream.aborted = true;
+                                return Ok(0);
+                            }
+                        };
 
-                                println!("{}/#hdu = {}, {:?}", id, no_hdu, fits);
+                        if !fits.stream_data(&mut stream, data, server) {
+                            return Ok(0);
+                        }
 
-                                fits.header = match String::from_utf8(header.clone()) {
-                                    Ok(x) => x,
-                                    Err(err) => {
-                                        println!("FITS HEADER UTF8: {}", err);
-                                        fits.status_code = 500;
-                                        String::from("")
-                                    }
-                                };
+                        Ok(len)
+                    })
+                    .unwrap();
 
-                                //prepare for reading the data HUD(s)
-                                frame_size = fits.init_data_storage();
+                transfer.perform()
+            };
 
-                                println!("FITS cube frame size: {} bytes", frame_size);
+            if response.status == 200 || response.status == 206 {
+                if let Some(x) = response.validator() {
+                    let _ = std::fs::write(&validator_file, &x);
+                    validator = Some(x);
+                }
+            }
 
-                                total = fits.depth;
+            expected_size = response.file_size().or(expected_size);
+            sha256 = response.sha256.clone().or(sha256);
 
-                                cdelt3 = {
-                                    if fits.has_velocity && fits.depth > 1 {
-                                        fits.cdelt3 * fits.frame_multiplier / 1000.0
-                                    } else {
-                                        1.0
-                                    }
-                                }
-                            }
-                        }
-                    } else {
-                        //then the data part
-                        if !fits.has_data {
-                            //kB downloaded progress
-                            fits.send_progress_notification(
-                                &server,
-                                &"downloading FITS".to_owned(),
-                                (fits.depth * frame_size / 1024) as i32,
-                                ((frame * frame_size + buffer.len().min(frame_size)) / 1024) as i32,
-                            );
+            let err = match result {
+                Ok(_) => {
+                    complete = true;
+                    break;
+                }
+                Err(err) => err,
+            };
 
-                            while buffer.len() >= frame_size && !fits.has_data {
-                                let data: Vec<u8> = buffer.drain(0..frame_size).collect();
+            if changed {
+                println!(
+                    "{}: the remote file has changed, the download must start over",
+                    url
+                );
+                break;
+            }
 
-                                fits.process_cube_frame(&data, cdelt3 as f32, frame);
-                                frame = frame + 1;
+            if stream.aborted || fits.is_cancelled() {
+                break;
+            }
 
-                                if frame == fits.depth {
-                                    //all data frames have been received
-                                    fits.has_data = true;
-                                    fits.status_code = 200;
-                                }
-                            }
-                        }
-                    };
+            let status = easy.response_code().unwrap_or(0);
 
-                    Ok(data.len())
-                })
-                .unwrap();
+            //there was nothing left to download
+            if status == 416 && offset > 0 {
+                complete = true;
+                break;
+            }
+
+            if !download::is_transient(&err, status) {
+                println!("{}: {} (HTTP status {})", url, err, status);
+                fatal = true;
+                break;
+            }
+
+            //give up for now, the download remains resumable
+            if attempt >= download::DOWNLOAD_RETRIES {
+                println!(
+                    "{}: {} (HTTP status {}), giving up after {} attempts",
+                    url, err, status, attempt
+                );
+                break;
+            }
+
+            attempt = attempt + 1;
+            let delay = download::backoff(attempt);
+
+            println!(
+                "{}: {}, resuming from {} bytes in {:?} (attempt {}/{})",
+                url,
+                err,
+                fits.filesize,
+                delay,
+                attempt,
+                download::DOWNLOAD_RETRIES
+            );
 
-            let _ = transfer.perform();
+            fits.send_progress_notification(
+                &server,
+                &format!("download interrupted, retrying in {}s", delay.as_secs()),
+                0,
+                0,
+            );
+
+            let start = Instant::now();
+
+            while start.elapsed() < delay && !fits.is_cancelled() {
+                thread::sleep(std::time::Duration::from_millis(250));
+            }
         }
 
         println!(
             "{} bytes remaining in the libcurl download buffer; has_data: {}",
-            buffer.len(),
+            stream.buffer.len(),
             fits.has_data
         );
 
+        //verify the download before it goes into the FITSCACHE
+        let mut corrupted = changed;
+
+        if complete && fits.has_data {
+            if let Some(size) = expected_size {
+                if fits.filesize != size {
+                    println!(
+                        "{}: received {} bytes, expected {} bytes",
+                        url, fits.filesize, size
+                    );
+
+                    corrupted = fits.filesize > size;
+                    complete = false;
+                }
+            }
+
+            if let Some(sha256) = &sha256 {
+                match download::sha256_file(&tmp) {
+                    Ok(x) if x == *sha256 => println!("{}: SHA-256 verified", url),
+                    Ok(x) => {
+                        println!("{}: SHA-256 mismatch, {} instead of {}", url, x, sha256);
+                        corrupted = true;
+                    }
+                    Err(err) => {
+                        println!("cannot verify the SHA-256 of {}: {}", tmp, err);
+                        complete = false;
+                    }
+                }
+            }
+        }
+
+        let interrupted = !complete && !fatal && !stream.aborted && !fits.is_cancelled();
+
+        if corrupted || interrupted {
+            //a failed download, resumable unless the data is corrupted
+            fits.has_data = false;
+            fits.status_code = 504;
+        }
+
         if fits.has_data {
             if !fits.pixels.is_empty() && !fits.mask.is_empty() {
                 //apply std::f32::NAN to masked pixels
@@ -2007,22 +2533,448 @@ impl FITS {
             println!("{}: reading FITS data completed", id);
         };
 
-        if fits.filesize >= FITS_CHUNK_LENGTH as u64 {
+        if fits.status_code == 504 && !corrupted {
+            //keep the partial file and its validator for the next attempt
+            println!(
+                "{}: {} bytes kept for resuming the download",
+                url, fits.filesize
+            );
+            fits.send_progress_notification(&server, &"error downloading FITS".to_owned(), 0, 0);
+        } else if fits.filesize >= FITS_CHUNK_LENGTH as u64 {
             if fits.status_code == 200 {
-                let filename = format!("{}/{}.fits", FITSCACHE, id.replace("/", "_"));
-                let _ = std::fs::rename(tmp, filename);
+                let _ = std::fs::rename(&tmp, &filename);
+                let _ = std::fs::remove_file(&validator_file);
             } else {
-                let _ = std::fs::remove_file(tmp);
+                let _ = std::fs::remove_file(&tmp);
+                let _ = std::fs::remove_file(&validator_file);
             }
         } else {
             fits.status_code = 404;
             fits.send_progress_notification(&server, &"error downloading FITS".to_owned(), 0, 0);
-            let _ = std::fs::remove_file(tmp);
+            let _ = std::fs::remove_file(&tmp);
+            let _ = std::fs::remove_file(&validator_file);
         };
 
         fits
     }
 
+    //feed the downloaded bytes into the parser: the header first, then the data frames;
+    //false aborts the download
+    fn stream_data(
+        &mut self,
+        stream: &mut FitsStream,
+        data: &[u8],
+        server: &Addr<server::SessionServer>,
+    ) -> bool {
+        self.filesize = self.filesize + data.len() as u64;
+
+        if !stream.is_compressed {
+            stream.buffer.extend_from_slice(data);
+        } else {
+            if let Some(decoder) = &mut stream.bz_decoder {
+                match decoder.write_all(&data) {
+                    Ok(_) => {
+                        decoder.flush().unwrap();
+                        let out = decoder.get_mut();
+                        let len = out.len();
+                        if len > 0 {
+                            stream.buffer.extend_from_slice(out);
+                            out.drain(0..out.len());
+                        }
+                    }
+                    Err(err) => {
+                        println!("Decompress: {}", err);
+                        self.status_code = 500;
+                    }
+                }
+            }
+
+            if let Some(decoder) = &mut stream.gz_decoder {
+                match decoder.write_all(&data) {
+                    Ok(_) => {
+                        decoder.flush().unwrap();
+                        let out = decoder.get_mut();
+                        let len = out.len();
+                        if len > 0 {
+                            stream.buffer.extend_from_slice(out);
+                            out.drain(0..out.len());
+                        }
+                    }
+                    Err(err) => {
+                        println!("Decompress: {}", err);
+                        self.status_code = 500;
+                    }
+                }
+            }
+        }
+
+        if !stream.compression_checked && stream.buffer.len() >= 10 {
+            let buffer = &mut stream.buffer;
+
+            print!(
+                "buffer length: {}, checking for compression...",
+                buffer.len()
+            );
+            //test for magick numbers and the deflate compression type
+            if buffer[0] == 0x1f && buffer[1] == 0x8b && buffer[2] == 0x08 {
+                let mut decoder = GzDecompressor::new(Vec::new());
+                stream.is_compressed = true;
+                println!("gzip found.");
+
+                //decompress the incoming data
+                match decoder.write_all(&buffer) {
+                    Ok(_) => {
+                        buffer.drain(0..buffer.len());
+                        decoder.flush().unwrap();
+                        let out = decoder.get_mut();
+                        let len = out.len();
+                        if len > 0 {
+                            buffer.extend_from_slice(out);
+                            out.drain(0..out.len());
+                        }
+                    }
+                    Err(err) => {
+                        println!("Decompress: {}", err);
+                        self.status_code = 500;
+                    }
+                }
+
+                stream.gz_decoder = Some(decoder);
+            } else
+            //test for magick numbers and the bzip2 compression type
+            if buffer[0] == 0x42 && buffer[1] == 0x5a && buffer[2] == 0x68 {
+                let mut decoder = BzDecompressor::new(Vec::new());
+                stream.is_compressed = true;
+                println!("bzip2 found.");
+
+                //decompress the incoming data
+                match decoder.write_all(&buffer) {
+                    Ok(_) => {
+                        buffer.drain(0..buffer.len());
+                        decoder.flush().unwrap();
+                        let out = decoder.get_mut();
+                        let len = out.len();
+                        if len > 0 {
+                            buffer.extend_from_slice(out);
+                            out.drain(0..out.len());
+                        }
+                    }
+                    Err(err) => {
+                        println!("Decompress: {}", err);
+                        self.status_code = 500;
+                    }
+                }
+
+                stream.bz_decoder = Some(decoder);
+            } else {
+                println!("no compression found.");
+            }
+
+            stream.compression_checked = true;
+        }
+
+        //handle the header first
+        if !self.has_header {
+            while stream.buffer.len() >= FITS_CHUNK_LENGTH && !self.has_header {
+                let chunk: Vec<u8> = stream.buffer.drain(0..FITS_CHUNK_LENGTH).collect();
+
+                stream.no_hdu = stream.no_hdu + 1;
+
+                //parse a FITS header chunk
+                let end = match self.parse_fits_header_chunk(&chunk) {
+                    Ok(x) => x,
+                    Err(err) => {
+                        println!("CRITICAL ERROR parsing FITS header: {}", err);
+                        self.status_code = 415;
+                        //terminate the transfer early
+                        stream.aborted = true;
+                        return false;
+                    }
+                };
+
+                stream.header.extend_from_slice(&chunk);
+
+                //try again, there may be an image extension
+                if end && self.naxis == 0 {
+                    stream.header = Vec::new();
+                    continue;
+                }
+
+                if end {
+                    //test for frequency/velocity
+                    self.frame_reference_unit();
+                    self.frame_reference_type();
+
+                    if self.restfrq > 0.0 {
+                        self.has_frequency = true;
+                    }
+
+                    self.has_header = true;
+
+                    {
+                        let fits = Arc::new(RwLock::new(Box::new(self.clone())));
+                        DATASETS
+                            .write()
+                            .insert(self.dataset_id.clone(), fits.clone());
+                    }
+
+                    println!("{}/#hdu = {}, {:?}", self.dataset_id, stream.no_hdu, self);
+
+                    self.header = match String::from_utf8(stream.header.clone()) {
+                        Ok(x) => x,
+                        Err(err) => {
+                            println!("FITS HEADER UTF8: {}", err);
+                            self.status_code = 500;
+                            String::from("")
+                        }
+                    };
+
+                    //prepare for reading the data HUD(s)
+                    stream.frame_size = self.init_data_storage();
+
+                    println!("FITS cube frame size: {} bytes", stream.frame_size);
+
+                    stream.cdelt3 = {
+                        if self.has_velocity && self.depth > 1 {
+                            self.cdelt3 * self.frame_multiplier / 1000.0
+                        } else {
+                            1.0
+                        }
+                    }
+                }
+            }
+        }
+
+        //then the data part
+        if self.has_header && !self.has_data && stream.frame_size > 0 {
+            {
+                let frame_size = stream.frame_size;
+
+                //kB downloaded progress
+                self.send_progress_notification(
+                    &server,
+                    &"downloading FITS".to_owned(),
+                    (self.depth * frame_size / 1024) as i32,
+                    ((stream.frame * frame_size + stream.buffer.len().min(frame_size)) / 1024)
+                        as i32,
+                );
+
+                while stream.buffer.len() >= frame_size && !self.has_data {
+                    let data: Vec<u8> = stream.buffer.drain(0..frame_size).collect();
+
+                    self.process_cube_frame(&data, stream.cdelt3 as f32, stream.frame);
+                    stream.frame = stream.frame + 1;
+
+                    if stream.frame == self.depth {
+                        //all data frames have been received
+                        self.has_data = true;
+                        self.status_code = 200;
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    /// an s3://bucket/key object: the header is read with ranged GETs first, then the whole
+    /// object is downloaded in parallel chunks into the FITSCACHE and loaded from there
+    pub fn from_s3(
+        id: &String,
+        flux: &String,
+        url: &String,
+        server: &Addr<server::SessionServer>,
+    ) -> FITS {
+        let mut fits = FITS::new(id, url, flux);
+        fits.is_dummy = false;
+
+        println!("FITS::from_s3({})", url);
+
+        let object = match s3::S3Object::open(url) {
+            Ok(x) => x,
+            Err(err) => {
+                println!("CRITICAL ERROR {}", err);
+
+                //access denied is reported as such, no response or a server error as a timeout
+                fits.status_code = match err.status {
+                    401 | 403 => err.status as u16,
+                    0 | 500..=599 => 504,
+                    _ => 404,
+                };
+
+                fits.send_progress_notification(
+                    &server,
+                    &"error downloading FITS".to_owned(),
+                    0,
+                    0,
+                );
+                return fits;
+            }
+        };
+
+        fits.filesize = object.size;
+
+        println!("{}: {} bytes", url, object.size);
+
+        if object.size < FITS_CHUNK_LENGTH as u64 {
+            fits.status_code = 404;
+            fits.send_progress_notification(&server, &"error downloading FITS".to_owned(), 0, 0);
+            return fits;
+        }
+
+        //read the header ahead of the data, unless the object is compressed
+        let mut offset: u64 = 0;
+        let mut header: Vec<u8> = Vec::new();
+        let mut no_hdu: i32 = 0;
+
+        while !fits.has_header && offset < object.size {
+            let buffer = match object.read_range(offset, S3_HEADER_LENGTH) {
+                Ok(x) => x,
+                Err(err) => {
+                    //the retries have been exhausted, a page reload tries again
+                    println!("CRITICAL ERROR {}", err);
+                    fits.status_code = 504;
+                    fits.send_progress_notification(
+                        &server,
+                        &"error downloading FITS".to_owned(),
+                        0,
+                        0,
+                    );
+                    return fits;
+                }
+            };
+
+            if offset == 0
+                && buffer.len() >= 3
+                && ((buffer[0] == 0x1f && buffer[1] == 0x8b && buffer[2] == 0x08)
+                    || (buffer[0] == 0x42 && buffer[1] == 0x5a && buffer[2] == 0x68))
+            {
+                println!("{}: compressed FITS, skipping the ranged header", url);
+                break;
+            }
+
+            offset = offset + buffer.len() as u64;
+
+            for chunk in buffer.chunks_exact(FITS_CHUNK_LENGTH) {
+                no_hdu = no_hdu + 1;
+
+                let end = match fits.parse_fits_header_chunk(&chunk) {
+                    Ok(x) => x,
+                    Err(err) => {
+                        println!("CRITICAL ERROR parsing FITS header: {}", err);
+                        fits.status_code = 415;
+                        return fits;
+                    }
+                };
+
+                header.extend_from_slice(&chunk);
+
+                //try again, there may be an image extension
+                if end && fits.naxis == 0 {
+                    header = Vec::new();
+                    continue;
+                }
+
+                if end {
+                    //test for frequency/velocity
+                    fits.frame_reference_unit();
+                    fits.frame_reference_type();
+
+                    if fits.restfrq > 0.0 {
+                        fits.has_frequency = true;
+                    }
+
+                    fits.has_header = true;
+                    fits.header = String::from_utf8_lossy(&header).into_owned();
+
+                    {
+                        let fits = Arc::new(RwLock::new(Box::new(fits.clone())));
+                        DATASETS.write().insert(id.clone(), fits.clone());
+                    }
+
+                    println!("{}/#hdu = {}, {:?}", id, no_hdu, fits);
+                    break;
+                }
+            }
+        }
+
+        //then download the whole object in parallel chunks
+        let tmp = format!("{}/{}.fits.tmp", FITSCACHE, id.replace("/", "_"));
+
+        let cachefile = match File::create(&tmp) {
+            Err(ref e) => {
+                println!("Could not create {} ({})!", tmp, e);
+                fits.status_code = 500;
+                return fits;
+            }
+            Ok(file) => file,
+        };
+
+        if let Err(err) = cachefile.set_len(object.size) {
+            println!("Could not allocate {} ({})!", tmp, err);
+            fits.status_code = 500;
+            let _ = std::fs::remove_file(tmp);
+            return fits;
+        }
+
+        let watch = Instant::now();
+        let total = (object.size / 1024) as i32;
+
+        let result = object.download(
+            &cachefile,
+            |received| {
+                //kB downloaded progress
+                fits.send_progress_notification(
+                    &server,
+                    &"downloading FITS".to_owned(),
+                    total,
+                    (received / 1024) as i32,
+                )
+            },
+            || fits.is_cancelled(),
+        );
+
+        drop(cachefile);
+
+        match result {
+            Ok(_) => {
+                println!(
+                    "{}: downloaded {} bytes in {:?}",
+                    url,
+                    object.size,
+                    watch.elapsed()
+                );
+
+                fits.send_progress_notification(&server, &"downloading FITS done".to_owned(), 0, 0);
+            }
+            Err(err) => {
+                //a failed transfer can be retried (from scratch, the chunks are not kept)
+                println!("CRITICAL ERROR {}", err);
+                fits.status_code = if fits.is_cancelled() { 500 } else { 504 };
+                fits.send_progress_notification(
+                    &server,
+                    &"error downloading FITS".to_owned(),
+                    0,
+                    0,
+                );
+                let _ = std::fs::remove_file(tmp);
+                return fits;
+            }
+        }
+
+        //the same caching as from_url: a complete download becomes FITSCACHE/<id>.fits
+        let filename = format!("{}/{}.fits", FITSCACHE, id.replace("/", "_"));
+
+        if let Err(err) = std::fs::rename(&tmp, &filename) {
+            println!("Could not rename {} ({})!", tmp, err);
+            fits.status_code = 500;
+            let _ = std::fs::remove_file(tmp);
+            return fits;
+        }
+
+        FITS::from_path(id, flux, std::path::Path::new(&filename), url, server)
+    }
+
     fn send_progress_notification(
         &self,
         server: &Addr<server::SessionServer>,
@@ -2037,6 +2989,12 @@ impl FITS {
             elapsed: Instant::now().duration_since(self.created),
             dataset_id: self.dataset_id.clone(),
         });
+
+        registry::update_progress(&self.dataset_id, notification, total, running);
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(std::sync::atomic::Ordering::SeqCst)
     }
 
     fn init_data_storage(&mut self) -> usize {
@@ -2054,6 +3012,7 @@ impl FITS {
 
         self.frame_min.resize(self.depth as usize, std::f32::MAX);
         self.frame_max.resize(self.depth as usize, std::f32::MIN);
+        self.frame_stats.get_mut().resize(self.depth as usize, None);
 
         match self.bitpix {
             8 => self
@@ -2127,6 +3086,7 @@ impl FITS {
         x1: f64,
         y1: f64,
         start: f64,
+        binning: &cutout::Binning,
     ) -> bool {
         let mut offset: usize = 0;
 
@@ -2181,8 +3141,11 @@ impl FITS {
             }
 
             if line.contains("CRPIX1  = ") {
-                let new_value = format!("CRPIX1  = {} / modified by fits_web_ql", self.crpix1 - x1)
-                    .into_bytes();
+                let new_value = format!(
+                    "CRPIX1  = {} / modified by fits_web_ql",
+                    binning.crpix(0, self.crpix1, x1)
+                )
+                .into_bytes();
 
                 for i in 0..new_value.len().min(FITS_LINE_LENGTH) {
                     buf[offset + i] = new_value[i];
@@ -2190,8 +3153,11 @@ impl FITS {
             }
 
             if line.contains("CRPIX2  = ") {
-                let new_value = format!("CRPIX2  = {} / modified by fits_web_ql", self.crpix2 - y1)
-                    .into_bytes();
+                let new_value = format!(
+                    "CRPIX2  = {} / modified by fits_web_ql",
+                    binning.crpix(1, self.crpix2, y1)
+                )
+                .into_bytes();
 
                 for i in 0..new_value.len().min(FITS_LINE_LENGTH) {
                     buf[offset + i] = new_value[i];
@@ -2201,7 +3167,7 @@ impl FITS {
             if line.contains("CRPIX3  = ") {
                 let new_value = format!(
                     "CRPIX3  = {} / modified by fits_web_ql",
-                    self.crpix3 - start
+                    binning.crpix(2, self.crpix3, start)
                 )
                 .into_bytes();
 
@@ -2210,6 +3176,13 @@ impl FITS {
                 }
             }
 
+            //binned cutouts carry float32 physical values on a coarser grid
+            if !binning.is_native() {
+                if let Some(new_value) = binned_fits_header_line(line, binning) {
+                    buf[offset..offset + FITS_LINE_LENGTH].copy_from_slice(&new_value);
+                }
+            }
+
             offset = offset + FITS_LINE_LENGTH;
         }
 
@@ -2863,6 +3836,9 @@ impl FITS {
         let mut frame_min = std::f32::MAX;
         let mut frame_max = std::f32::MIN;
 
+        //the valid values for the channel noise statistics
+        let mut valid: Vec<f32> = Vec::with_capacity(len);
+
         match self.bitpix {
             8 => {
                 for i in 0..len {
@@ -2882,6 +3858,8 @@ impl FITS {
 
                         sum += tmp;
                         count += 1;
+
+                        valid.push(tmp);
                     }
                 }
             }
@@ -2906,6 +3884,8 @@ impl FITS {
 
                                 sum += tmp;
                                 count += 1;
+
+                                valid.push(tmp);
                             }
                         }
                         Err(err) => {
@@ -2935,6 +3915,8 @@ impl FITS {
 
                                 sum += tmp;
                                 count += 1;
+
+                                valid.push(tmp);
                             }
                         }
                         Err(err) => {
@@ -2966,6 +3948,8 @@ impl FITS {
 
                                 sum += tmp;
                                 count += 1;
+
+                                valid.push(tmp);
                             }
                         }
                         Err(err) => {
@@ -2995,6 +3979,8 @@ impl FITS {
 
                                 sum += tmp;
                                 count += 1;
+
+                                valid.push(tmp);
                             }
                         }
                         Err(err) => {
@@ -3021,6 +4007,10 @@ impl FITS {
             self.mean_spectrum[frame] = sum / (count as f32);
             self.integrated_spectrum[frame] = sum * cdelt3;
         }
+
+        if let Some(stats) = self.frame_stats.get_mut().get_mut(frame) {
+            *stats = Some(noise::ChannelStats::new(&mut valid, len));
+        }
     }
 
     pub fn make_image_spectrum(
@@ -3353,6 +4343,48 @@ impl FITS {
         Some((pixels, mask, mean_spectrum, integrated_spectrum))
     }
 
+    /// the noise statistics of the channels not covered while loading
+    /// (parallel, zfp and cache reads bypass process_cube_frame)
+    pub fn make_channel_stats(&self) {
+        let missing: Vec<usize> = {
+            let stats = self.frame_stats.read();
+
+            (0..self.depth)
+                .filter(|frame| stats.get(*frame).map_or(true, |x| x.is_none()))
+                .collect()
+        };
+
+        if missing.is_empty() {
+            return;
+        }
+
+        let watch = Instant::now();
+
+        let computed: Vec<(usize, noise::ChannelStats)> = missing
+            .par_iter()
+            .filter_map(|frame| {
+                let values = self.frame_values(*frame)?;
+                Some((*frame, noise::ChannelStats::from_frame(&values)))
+            })
+            .collect();
+
+        {
+            let mut stats = self.frame_stats.write();
+            stats.resize(self.depth, None);
+
+            for (frame, x) in computed {
+                stats[frame] = Some(x);
+            }
+        }
+
+        println!(
+            "{}: noise statistics of {} channel(s), elapsed time: {:?}",
+            self.dataset_id,
+            missing.len(),
+            watch.elapsed()
+        );
+    }
+
     pub fn make_data_histogram(&self) {
         println!("global dmin = {}, dmax = {}", self.dmin, self.dmax);
 
@@ -4107,6 +5139,11 @@ impl FITS {
             _ => String::from("legacy"),
         };
 
+        //the additional stretches for histogram shapes not covered by the regression
+        if let Some(stretch) = stretch::classify(&slot, self.is_optical) {
+            self.flux = stretch.to_flux();
+        }
+
         println!("histogram classifier elapsed time {:?}", watch.elapsed());
     }
 
@@ -4975,6 +6012,28 @@ impl FITS {
         flux: &String,
         pool: &Option<rayon::ThreadPool>,
     ) -> Option<Vec<u8>> {
+        //the additional stretches work on physical values
+        if stretch::Stretch::from_flux(flux).is_some() {
+            let pixels = self.frame_pixels(frame)?;
+            let (median, black, white, sensitivity, ratio_sensitivity) = self.frame_tone_mapping();
+
+            return Some(self.pixels_to_luminance(
+                &pixels,
+                &self.mask,
+                self.dmin,
+                self.dmax,
+                self.lmin,
+                self.lmax,
+                black,
+                white,
+                median,
+                sensitivity,
+                ratio_sensitivity,
+                flux,
+                pool,
+            ));
+        }
+
         match self.bitpix {
             8 => self.data_to_luminance_u8(frame, flux, pool),
             16 => self.data_to_luminance_i16(frame, flux, pool),
@@ -4988,7 +6047,333 @@ impl FITS {
         }
     }
 
-    pub fn pixels_to_luminance(
+    //the physical pixel values of a single frame
+    fn frame_pixels(&self, frame: usize) -> Option<Vec<f32>> {
+        let pixels: Vec<f32> = match self.bitpix {
+            8 => self
+                .data_u8
+                .get(frame)?
+                .par_iter()
+                .map(|x| self.bzero + self.bscale * (*x as f32))
+                .collect(),
+            16 => self
+                .data_i16
+                .get(frame)?
+                .par_iter()
+                .map(|x| self.bzero + self.bscale * (*x as f32))
+                .collect(),
+            32 => self
+                .data_i32
+                .get(frame)?
+                .par_iter()
+                .map(|x| self.bzero + self.bscale * (*x as f32))
+                .collect(),
+            -32 => self
+                .data_f16
+                .get(frame)?
+                .par_iter()
+                .map(|x| self.bzero + self.bscale * (*x).to_f32())
+                .collect(),
+            -64 => self
+                .data_f64
+                .get(frame)?
+                .par_iter()
+                .map(|x| self.bzero + self.bscale * (*x as f32))
+                .collect(),
+            _ => {
+                println!("unsupported bitpix: {}", self.bitpix);
+                return None;
+            }
+        };
+
+        if pixels.len() == 0 {
+            return None;
+        };
+
+        Some(pixels)
+    }
+
+    /// the physical values of a single frame with the invalid pixels
+    /// (outside DATAMIN..DATAMAX or not above IGNRVAL) set to NaN
+    pub fn frame_values(&self, frame: usize) -> Option<Vec<f32>> {
+        let mut pixels = self.frame_pixels(frame)?;
+
+        let datamin = self.datamin;
+        let datamax = self.datamax;
+        let ignrval = self.ignrval;
+
+        pixels.par_iter_mut().for_each(|x| {
+            if !(x.is_finite() && *x >= datamin && *x <= datamax && *x > ignrval) {
+                *x = f32::NAN;
+            }
+        });
+
+        Some(pixels)
+    }
+
+    /// frame_values restricted to a box (0-based, inclusive bounds), row by row
+    pub fn frame_box_values(
+        &self,
+        frame: usize,
+        (x1, y1, x2, y2): (usize, usize, usize, usize),
+    ) -> Option<Vec<f32>> {
+        let width = self.width;
+
+        let offsets = || (y1..=y2).flat_map(move |y| (x1..=x2).map(move |x| y * width + x));
+
+        let raw: Vec<f32> = match self.bitpix {
+            8 => {
+                let data = self.data_u8.get(frame)?;
+                offsets()
+                    .map(|i| data.get(i).map_or(f32::NAN, |x| *x as f32))
+                    .collect()
+            }
+            16 => {
+                let data = self.data_i16.get(frame)?;
+                offsets()
+                    .map(|i| data.get(i).map_or(f32::NAN, |x| *x as f32))
+                    .collect()
+            }
+            32 => {
+                let data = self.data_i32.get(frame)?;
+                offsets()
+                    .map(|i| data.get(i).map_or(f32::NAN, |x| *x as f32))
+                    .collect()
+            }
+            -32 => {
+                let data = self.data_f16.get(frame)?;
+                offsets()
+                    .map(|i| data.get(i).map_or(f32::NAN, |x| x.to_f32()))
+                    .collect()
+            }
+            -64 => {
+                let data = self.data_f64.get(frame)?;
+                offsets()
+                    .map(|i| data.get(i).map_or(f32::NAN, |x| *x as f32))
+                    .collect()
+            }
+            _ => {
+                println!("unsupported bitpix: {}", self.bitpix);
+                return None;
+            }
+        };
+
+        let (datamin, datamax, ignrval) = (self.datamin, self.datamax, self.ignrval);
+
+        Some(
+            raw.into_iter()
+                .map(|x| self.bzero + self.bscale * x)
+                .map(|x| {
+                    if x.is_finite() && x >= datamin && x <= datamax && x > ignrval {
+                        x
+                    } else {
+                        f32::NAN
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    //black, white and sensitivity for single frames (as in data_to_luminance_*)
+    fn frame_tone_mapping(&self) -> (f32, f32, f32, f32, f32) {
+        let u = 7.5_f32;
+
+        let median = *self.data_median.read();
+        let mut black = self
+            .dmin
+            .max((*self.data_median.read()) - u * (*self.data_mad_n.read()));
+        let mut white = self
+            .dmax
+            .min((*self.data_median.read()) + u * (*self.data_mad_p.read()));
+        let mut sensitivity = 1.0 / (white - black);
+        let mut ratio_sensitivity = sensitivity;
+
+        //SubaruWebQL-style
+        if self.is_optical {
+            let u = 0.5_f32;
+            let v = 15.0_f32;
+            black = self
+                .dmin
+                .max((*self.data_median.read()) - u * (*self.data_mad.read()));
+            white = self
+                .dmax
+                .min((*self.data_median.read()) + u * (*self.data_mad.read()));
+            sensitivity = 1.0 / (v * (*self.data_mad.read()));
+
+            // re-use the auto-brightness factor
+            let factor = self.ratio_sensitivity / self.sensitivity;
+            ratio_sensitivity = sensitivity * factor;
+        };
+
+        (median, black, white, sensitivity, ratio_sensitivity)
+    }
+
+    /// data_to_luminance with 10/12-bit output samples
+    fn data_to_luminance_hbd(
+        &self,
+        frame: usize,
+        flux: &String,
+        bit_depth: u32,
+        pool: &Option<rayon::ThreadPool>,
+    ) -> Option<Vec<u16>> {
+        let pixels = self.frame_pixels(frame)?;
+        let (median, black, white, sensitivity, ratio_sensitivity) = self.frame_tone_mapping();
+
+        Some(self.pixels_to_luminance_hbd(
+            &pixels,
+            &self.mask,
+            self.dmin,
+            self.dmax,
+            self.lmin,
+            self.lmax,
+            black,
+            white,
+            median,
+            sensitivity,
+            ratio_sensitivity,
+            flux,
+            bit_depth,
+            pool,
+        ))
+    }
+
+    /// the additional stretches with their data-dependent parts (the zscale limits, the histeq CDF)
+    /// taken from the whole image once, so that the frames, viewports and colourbars all agree
+    pub fn stretch_function(
+        &self,
+        stretch: stretch::Stretch,
+        black: f32,
+        white: f32,
+    ) -> std::sync::Arc<stretch::StretchFunction> {
+        if !stretch.is_data_dependent() {
+            return std::sync::Arc::new(stretch::StretchFunction::new(
+                stretch,
+                &[],
+                &[],
+                black,
+                white,
+            ));
+        }
+
+        //the image is not complete yet, do not cache partial statistics
+        if !self.has_data {
+            return std::sync::Arc::new(stretch::StretchFunction::new(
+                stretch,
+                &self.pixels,
+                &self.mask,
+                black,
+                white,
+            ));
+        }
+
+        let key = stretch.to_flux();
+
+        if let Some(function) = self.stretches.read().get(&key) {
+            return function.clone();
+        }
+
+        let function = std::sync::Arc::new(stretch::StretchFunction::new(
+            stretch,
+            &self.pixels,
+            &self.mask,
+            black,
+            white,
+        ));
+
+        self.stretches.write().insert(key, function.clone());
+
+        function
+    }
+
+    /// apply a ready-made stretch function, masked pixels are black
+    pub fn stretch_to_luminance(
+        &self,
+        function: &stretch::StretchFunction,
+        pixels: &[f32],
+        mask: &[u8],
+        pool: &Option<rayon::ThreadPool>,
+    ) -> Vec<u8> {
+        quantise_luminance(
+            pixels,
+            mask,
+            |x| stretch::quantise_u8(num::clamp(function.apply(x), 0.0, 1.0)),
+            pool,
+        )
+    }
+
+    /// the [0, 1] pixel mapping shared by the 8-bit and the 10/12-bit luma paths
+    pub fn luminance(
+        &self,
+        pmin: f32,
+        pmax: f32,
+        lmin: f32,
+        lmax: f32,
+        black: f32,
+        white: f32,
+        median: f32,
+        sensitivity: f32,
+        ratio_sensitivity: f32,
+        flux: &String,
+    ) -> stretch::Luminance {
+        if let Some(stretch) = stretch::Stretch::from_flux(flux) {
+            return stretch::Luminance::Stretch(self.stretch_function(stretch, black, white));
+        }
+
+        stretch::Luminance::Flux(
+            stretch::Flux::from_flux(flux),
+            stretch::FluxParams {
+                pmin,
+                pmax,
+                lmin,
+                lmax,
+                black,
+                white,
+                median,
+                sensitivity,
+                ratio_sensitivity,
+            },
+        )
+    }
+
+    pub fn pixels_to_luminance(
+        &self,
+        pixels: &Vec<f32>,
+        mask: &Vec<u8>,
+        pmin: f32,
+        pmax: f32,
+        lmin: f32,
+        lmax: f32,
+        black: f32,
+        white: f32,
+        median: f32,
+        sensitivity: f32,
+        ratio_sensitivity: f32,
+        flux: &String,
+        pool: &Option<rayon::ThreadPool>,
+    ) -> Vec<u8> {
+        let luminance = self.luminance(
+            pmin,
+            pmax,
+            lmin,
+            lmax,
+            black,
+            white,
+            median,
+            sensitivity,
+            ratio_sensitivity,
+            flux,
+        );
+
+        quantise_luminance(
+            pixels,
+            mask,
+            |x| stretch::quantise_u8(luminance.apply(x)),
+            pool,
+        )
+    }
+
+    /// pixels_to_luminance with 10/12-bit output samples, avoiding the posterisation of faint emission
+    pub fn pixels_to_luminance_hbd(
         &self,
         pixels: &Vec<f32>,
         mask: &Vec<u8>,
@@ -5002,196 +6387,28 @@ impl FITS {
         sensitivity: f32,
         ratio_sensitivity: f32,
         flux: &String,
+        bit_depth: u32,
         pool: &Option<rayon::ThreadPool>,
-    ) -> Vec<u8> {
-        match flux.as_ref() {
-            "linear" => {
-                let slope = 1.0 / (white - black);
-
-                match pool {
-                    Some(pool) => pool.install(|| {
-                        pixels
-                            .par_iter()
-                            .zip(mask.par_iter())
-                            .map(|(x, m)| {
-                                if *m > 0 {
-                                    let pixel = num::clamp((x - black) * slope, 0.0, 1.0);
-                                    (255.0 * pixel) as u8
-                                } else {
-                                    0
-                                }
-                            })
-                            .collect()
-                    }),
-                    None => pixels
-                        .par_iter()
-                        .zip(mask.par_iter())
-                        .map(|(x, m)| {
-                            if *m > 0 {
-                                let pixel = num::clamp((x - black) * slope, 0.0, 1.0);
-                                (255.0 * pixel) as u8
-                            } else {
-                                0
-                            }
-                        })
-                        .collect(),
-                }
-            }
-            "logistic" => match pool {
-                Some(pool) => pool.install(|| {
-                    pixels
-                        .par_iter()
-                        .zip(mask.par_iter())
-                        .map(|(x, m)| {
-                            if *m > 0 {
-                                let pixel = num::clamp(
-                                    1.0 / (1.0 + (-6.0 * (x - median) * sensitivity).exp()),
-                                    0.0,
-                                    1.0,
-                                );
-                                (255.0 * pixel) as u8
-                            } else {
-                                0
-                            }
-                        })
-                        .collect()
-                }),
-                None => pixels
-                    .par_iter()
-                    .zip(mask.par_iter())
-                    .map(|(x, m)| {
-                        if *m > 0 {
-                            let pixel = num::clamp(
-                                1.0 / (1.0 + (-6.0 * (x - median) * sensitivity).exp()),
-                                0.0,
-                                1.0,
-                            );
-                            (255.0 * pixel) as u8
-                        } else {
-                            0
-                        }
-                    })
-                    .collect(),
-            },
-            "ratio" => match pool {
-                Some(pool) => pool.install(|| {
-                    pixels
-                        .par_iter()
-                        .zip(mask.par_iter())
-                        .map(|(x, m)| {
-                            if *m > 0 {
-                                let pixel = 5.0 * (x - black) * ratio_sensitivity;
-
-                                if pixel > 0.0 {
-                                    (255.0 * pixel / (1.0 + pixel)) as u8
-                                } else {
-                                    0
-                                }
-                            } else {
-                                0
-                            }
-                        })
-                        .collect()
-                }),
-                None => pixels
-                    .par_iter()
-                    .zip(mask.par_iter())
-                    .map(|(x, m)| {
-                        if *m > 0 {
-                            let pixel = 5.0 * (x - black) * ratio_sensitivity;
-
-                            if pixel > 0.0 {
-                                (255.0 * pixel / (1.0 + pixel)) as u8
-                            } else {
-                                0
-                            }
-                        } else {
-                            0
-                        }
-                    })
-                    .collect(),
-            },
-            "square" => match pool {
-                Some(pool) => pool.install(|| {
-                    pixels
-                        .par_iter()
-                        .zip(mask.par_iter())
-                        .map(|(x, m)| {
-                            if *m > 0 {
-                                let pixel = (x - black) * sensitivity;
-
-                                if pixel > 0.0 {
-                                    (255.0 * num::clamp(pixel * pixel, 0.0, 1.0)) as u8
-                                } else {
-                                    0
-                                }
-                            } else {
-                                0
-                            }
-                        })
-                        .collect()
-                }),
-                None => pixels
-                    .par_iter()
-                    .zip(mask.par_iter())
-                    .map(|(x, m)| {
-                        if *m > 0 {
-                            let pixel = (x - black) * sensitivity;
-
-                            if pixel > 0.0 {
-                                (255.0 * num::clamp(pixel * pixel, 0.0, 1.0)) as u8
-                            } else {
-                                0
-                            }
-                        } else {
-                            0
-                        }
-                    })
-                    .collect(),
-            },
-            //by default assume "legacy"
-            _ => match pool {
-                Some(pool) => pool.install(|| {
-                    pixels
-                        .par_iter()
-                        .zip(mask.par_iter())
-                        .map(|(x, m)| {
-                            if *m > 0 {
-                                let pixel = 0.5 + (x - pmin) / (pmax - pmin);
-
-                                if pixel > 0.0 {
-                                    (255.0
-                                        * num::clamp((pixel.ln() - lmin) / (lmax - lmin), 0.0, 1.0))
-                                        as u8
-                                } else {
-                                    0
-                                }
-                            } else {
-                                0
-                            }
-                        })
-                        .collect()
-                }),
-                None => pixels
-                    .par_iter()
-                    .zip(mask.par_iter())
-                    .map(|(x, m)| {
-                        if *m > 0 {
-                            let pixel = 0.5 + (x - pmin) / (pmax - pmin);
+    ) -> Vec<u16> {
+        let luminance = self.luminance(
+            pmin,
+            pmax,
+            lmin,
+            lmax,
+            black,
+            white,
+            median,
+            sensitivity,
+            ratio_sensitivity,
+            flux,
+        );
 
-                            if pixel > 0.0 {
-                                (255.0 * num::clamp((pixel.ln() - lmin) / (lmax - lmin), 0.0, 1.0))
-                                    as u8
-                            } else {
-                                0
-                            }
-                        } else {
-                            0
-                        }
-                    })
-                    .collect(),
-            },
-        }
+        quantise_luminance(
+            pixels,
+            mask,
+            |x| stretch::quantise_u16(luminance.apply(x), bit_depth),
+            pool,
+        )
     }
 
     pub fn get_video_frame(
@@ -5220,6 +6437,36 @@ impl FITS {
         Some(dst)
     }
 
+    /// get_video_frame with 10/12-bit luma samples
+    pub fn get_video_frame_hbd(
+        &self,
+        frame: usize,
+        width: u32,
+        height: u32,
+        flux: &String,
+        bit_depth: u32,
+        pool: &Option<rayon::ThreadPool>,
+    ) -> Option<Vec<u16>> {
+        let watch = Instant::now();
+
+        let y: Vec<u16> = match self.data_to_luminance_hbd(frame, flux, bit_depth, pool) {
+            Some(y) => y,
+            None => vec![0; self.width * self.height],
+        };
+
+        //invert and downscale
+        let mut dst = vec![0; (width * height) as usize];
+        self.resize_and_invert_u16(&y, &mut dst, width, height, libyuv_FilterMode_kFilterBox);
+
+        println!(
+            "{}-bit Y video plane preparation time: {:?}",
+            bit_depth,
+            watch.elapsed()
+        );
+
+        Some(dst)
+    }
+
     #[cfg(feature = "vp9")]
     pub fn get_vpx_frame(
         &self,
@@ -5594,19 +6841,46 @@ impl FITS {
         }*/
     }
 
-    fn make_hevc_viewport(&self, dimx: u32, dimy: u32, y: &Vec<u8>) -> Option<Vec<Vec<u8>>> {
-        let param: *mut x265_param = unsafe { x265_param_alloc() };
+    /// resize_and_invert for 16-bit samples (10/12-bit luma)
+    pub fn resize_and_invert_u16(
+        &self,
+        src: &Vec<u16>,
+        dst: &mut Vec<u16>,
+        width: u32,
+        height: u32,
+        filter: u32,
+    ) {
+        unsafe {
+            libyuv_ScalePlane_16(
+                src.as_ptr(),
+                self.width as i32,
+                self.width as i32,
+                -(self.height as i32),
+                dst.as_mut_ptr(),
+                width as i32,
+                width as i32,
+                height as i32,
+                filter,
+            );
+        };
+    }
+
+    /// an HEVC still viewport, Main10/Main12 with 16-bit luma samples
+    fn make_hevc_viewport(
+        &self,
+        dimx: u32,
+        dimy: u32,
+        mut y: hevc::Plane,
+        bit_depth: u32,
+    ) -> Option<Vec<Vec<u8>>> {
+        let api = hevc::Api::get(bit_depth)?;
+        let param: *mut x265_param = api.param_alloc("superfast", "zerolatency");
 
         if param.is_null() {
             return None;
         }
 
-        let preset = CString::new("superfast").unwrap();
-        let tune = CString::new("zerolatency").unwrap();
-
         unsafe {
-            x265_param_default_preset(param, preset.as_ptr(), tune.as_ptr());
-
             (*param).fpsNum = 10;
             (*param).fpsDenom = 1;
         };
@@ -5615,7 +6889,6 @@ impl FITS {
         unsafe {
             (*param).bRepeatHeaders = 1;
             (*param).internalCsp = X265_CSP_I400 as i32;
-            (*param).internalBitDepth = 8;
             (*param).sourceWidth = dimx as i32;
             (*param).sourceHeight = dimy as i32;
 
@@ -5624,27 +6897,25 @@ impl FITS {
             (*param).rc.qp = 31;
         };
 
-        let pic: *mut x265_picture = unsafe { x265_picture_alloc() };
-        let enc: *mut x265_encoder = unsafe { x265_encoder_open(param) };
-        unsafe { x265_picture_init(param, pic) };
+        let pic: *mut x265_picture = unsafe { api.picture_alloc() };
+        let enc: *mut x265_encoder = unsafe { api.encoder_open(param) };
+        unsafe { api.picture_init(param, pic) };
 
         //HEVC-encode a still viewport
         let watch = Instant::now();
 
-        unsafe {
-            (*pic).stride[0] = dimx as i32;
-            (*pic).planes[0] = y.as_ptr() as *mut std::os::raw::c_void;
-        }
+        unsafe { y.attach(pic, dimx) };
 
         let mut nal_count: u32 = 0;
         let mut p_nal: *mut x265_nal = ptr::null_mut();
         let p_out: *mut x265_picture = ptr::null_mut();
 
         //encode
-        let ret = unsafe { x265_encoder_encode(enc, &mut p_nal, &mut nal_count, pic, p_out) };
+        let ret = unsafe { api.encoder_encode(enc, &mut p_nal, &mut nal_count, pic, p_out) };
 
         println!(
-            "x265 hevc viewport encode time: {:?}, speed {} frames per second, ret = {}, nal_count = {}",
+            "x265 {}-bit hevc viewport encode time: {:?}, speed {} frames per second, ret = {}, nal_count = {}",
+            api.bit_depth(),
             watch.elapsed(),
             1000000000 / watch.elapsed().as_nanos(),
             ret,
@@ -5652,10 +6923,7 @@ impl FITS {
         );
 
         //y falls out of scope
-        unsafe {
-            (*pic).stride[0] = 0 as i32;
-            (*pic).planes[0] = ptr::null_mut();
-        }
+        unsafe { hevc::Plane::detach(pic) };
 
         let mut frames: Vec<Vec<u8>> = Vec::new();
 
@@ -5676,7 +6944,7 @@ impl FITS {
         //flush the encoder to signal the end
         loop {
             let ret = unsafe {
-                x265_encoder_encode(enc, &mut p_nal, &mut nal_count, ptr::null_mut(), p_out)
+                api.encoder_encode(enc, &mut p_nal, &mut nal_count, ptr::null_mut(), p_out)
             };
 
             if ret > 0 {
@@ -5701,15 +6969,15 @@ impl FITS {
         //release memory
         unsafe {
             if !param.is_null() {
-                x265_param_free(param);
+                api.param_free(param);
             }
 
             if !enc.is_null() {
-                x265_encoder_close(enc);
+                api.encoder_close(enc);
             }
 
             if !pic.is_null() {
-                x265_picture_free(pic);
+                api.picture_free(pic);
             }
         }
 
@@ -6008,20 +7276,8 @@ impl FITS {
         sum / (num_threads as f32)
     }
 
-    fn make_vpx_image(&mut self) {
-        //check if the .img binary image file is already in the IMAGECACHE
-
-        let filename = format!("{}/{}.img", IMAGECACHE, self.dataset_id.replace("/", "_"));
-        let filepath = std::path::Path::new(&filename);
-
-        if filepath.exists() {
-            return;
-        }
-
-        let watch = Instant::now();
-
-        let mut image_frame: Vec<u8> = Vec::new();
-
+    /// the (downscaled) dimensions of the still image
+    pub fn get_image_dimensions(&self) -> (u32, u32) {
         let mut w = self.width as u32;
         let mut h = self.height as u32;
         let pixel_count = (w as u64) * (h as u64);
@@ -6091,6 +7347,211 @@ impl FITS {
             }
         }
 
+        (w, h)
+    }
+
+    //write a codec-compressed still image to the IMAGECACHE via a temporary file
+    fn save_image_frame(&self, filepath: &std::path::Path, image_frame: FITSImage) {
+        let tmp_filename = format!("{}.tmp", filepath.display());
+        let tmp_filepath = std::path::Path::new(&tmp_filename);
+
+        let mut buffer = match File::create(tmp_filepath) {
+            Ok(f) => f,
+            Err(err) => {
+                println!("{}", err);
+                return;
+            }
+        };
+
+        // remove the preallocation limit
+        let config = Configuration::default().disable_preallocation_size_limit();
+        match wincode::config::serialize(&image_frame, config) {
+            Ok(bin) => {
+                println!("FITSImage binary length: {}", bin.len());
+
+                match buffer.write_all(&bin) {
+                    Ok(()) => {
+                        //remove (rename) the temporary file
+                        let _ = std::fs::rename(tmp_filepath, filepath);
+                    }
+                    Err(err) => {
+                        println!(
+                            "image cache write error: {}, removing the temporary file",
+                            err
+                        );
+                        let _ = std::fs::remove_file(tmp_filepath);
+                    }
+                }
+            }
+            Err(err) => println!("error serializing a FITSImage structure: {}", err),
+        }
+    }
+
+    /// an AV1-compressed alternative to the VP9 still image, stored alongside it in the IMAGECACHE
+    #[cfg(feature = "av1")]
+    pub fn make_av1_image(&self, bit_depth: u32) {
+        let filename = av1_image_filename(&self.dataset_id, bit_depth);
+        let filepath = std::path::Path::new(&filename);
+
+        if filepath.exists() || !self.has_data {
+            return;
+        }
+
+        let (w, h) = self.get_image_dimensions();
+
+        //invert/downscale the mask (alpha channel) without interpolation
+        let mut alpha = vec![0; (w as usize) * (h as usize)];
+        self.resize_and_invert(&self.mask, &mut alpha, w, h, libyuv_FilterMode_kFilterNone);
+
+        if bit_depth > 8 {
+            let y: Vec<u16> = self.pixels_to_luminance_hbd(
+                &self.pixels,
+                &self.mask,
+                self.pmin,
+                self.pmax,
+                self.lmin,
+                self.lmax,
+                self.black,
+                self.white,
+                self.median,
+                self.sensitivity,
+                self.ratio_sensitivity,
+                &self.flux,
+                bit_depth,
+                &None,
+            );
+
+            let mut dst = vec![0; (w as usize) * (h as usize)];
+            self.resize_and_invert_u16(&y, &mut dst, w, h, libyuv_FilterMode_kFilterBox);
+
+            if let Some(image_frame) = av1::encode_still_hbd(w, h, &dst, bit_depth) {
+                self.save_image_frame(
+                    &filepath,
+                    FITSImage {
+                        identifier: String::from("AV1"),
+                        width: w,
+                        height: h,
+                        image: image_frame,
+                        alpha: lz4_compress::compress(&alpha),
+                    },
+                );
+            }
+
+            return;
+        }
+
+        let y: Vec<u8> = self.pixels_to_luminance(
+            &self.pixels,
+            &self.mask,
+            self.pmin,
+            self.pmax,
+            self.lmin,
+            self.lmax,
+            self.black,
+            self.white,
+            self.median,
+            self.sensitivity,
+            self.ratio_sensitivity,
+            &self.flux,
+            &None,
+        );
+
+        let mut dst = vec![0; (w as usize) * (h as usize)];
+        self.resize_and_invert(&y, &mut dst, w, h, libyuv_FilterMode_kFilterBox);
+
+        let image_frame = match av1::encode_still(w, h, &dst) {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        self.save_image_frame(
+            &filepath,
+            FITSImage {
+                identifier: String::from("AV1"),
+                width: w,
+                height: h,
+                image: image_frame,
+                alpha: lz4_compress::compress(&alpha),
+            },
+        );
+    }
+
+    #[cfg(feature = "av1")]
+    fn make_av1_viewport(&self, dimx: u32, dimy: u32, y: &Vec<u8>) -> Option<Vec<Vec<u8>>> {
+        match av1::encode_still(dimx, dimy, y) {
+            Some(frame) => Some(vec![frame; 1]),
+            None => None,
+        }
+    }
+
+    /// a 10/12-bit (VP9 profile 2) alternative to the 8-bit VP9 still image, stored alongside it in the IMAGECACHE
+    pub fn make_vpx_image_hbd(&self, bit_depth: u32) {
+        let filename = vpx_image_filename(&self.dataset_id, bit_depth);
+        let filepath = std::path::Path::new(&filename);
+
+        if filepath.exists() || !self.has_data {
+            return;
+        }
+
+        let (w, h) = self.get_image_dimensions();
+
+        let y: Vec<u16> = self.pixels_to_luminance_hbd(
+            &self.pixels,
+            &self.mask,
+            self.pmin,
+            self.pmax,
+            self.lmin,
+            self.lmax,
+            self.black,
+            self.white,
+            self.median,
+            self.sensitivity,
+            self.ratio_sensitivity,
+            &self.flux,
+            bit_depth,
+            &None,
+        );
+
+        let mut dst = vec![0; (w as usize) * (h as usize)];
+        self.resize_and_invert_u16(&y, &mut dst, w, h, libyuv_FilterMode_kFilterBox);
+
+        let image_frame = match encode_vpx_still_hbd(w, h, &dst, bit_depth) {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        //invert/downscale the mask (alpha channel) without interpolation
+        let mut alpha = vec![0; (w as usize) * (h as usize)];
+        self.resize_and_invert(&self.mask, &mut alpha, w, h, libyuv_FilterMode_kFilterNone);
+
+        self.save_image_frame(
+            &filepath,
+            FITSImage {
+                identifier: String::from("VP9"),
+                width: w,
+                height: h,
+                image: image_frame,
+                alpha: lz4_compress::compress(&alpha),
+            },
+        );
+    }
+
+    fn make_vpx_image(&mut self) {
+        //check if the .img binary image file is already in the IMAGECACHE
+
+        let filename = format!("{}/{}.img", IMAGECACHE, self.dataset_id.replace("/", "_"));
+        let filepath = std::path::Path::new(&filename);
+
+        if filepath.exists() {
+            return;
+        }
+
+        let watch = Instant::now();
+
+        let mut image_frame: Vec<u8> = Vec::new();
+
+        let (w, h) = self.get_image_dimensions();
+
         let mut raw: vpx_image = vpx_image::default();
         let mut ctx = vpx_codec_ctx_t {
             name: ptr::null(),
@@ -6331,51 +7792,604 @@ impl FITS {
         unsafe { vpx_img_free(&mut raw) };
         unsafe { vpx_codec_destroy(&mut ctx) };
 
-        let tmp_filename = format!(
-            "{}/{}.img.tmp",
-            IMAGECACHE,
-            self.dataset_id.replace("/", "_")
+        self.save_image_frame(
+            &filepath,
+            FITSImage {
+                identifier: String::from("VP9"),
+                width: w,
+                height: h,
+                image: image_frame,
+                alpha: alpha_frame,
+            },
         );
-        let tmp_filepath = std::path::Path::new(&tmp_filename);
+    }
 
-        let mut buffer = match File::create(tmp_filepath) {
-            Ok(f) => f,
-            Err(err) => {
-                println!("{}", err);
-                return;
+    //the tone-mapping statistics of an image computed on the fly
+    fn owned_range_image(&self, pixels: Vec<f32>, mask: Vec<u8>) -> Option<RangeImage<'static>> {
+        let mut ord_pixels: Vec<f32> = pixels
+            .par_iter()
+            .zip(mask.par_iter())
+            .map(|(x, m)| if *m > 0 { *x } else { std::f32::NAN })
+            .collect();
+
+        //NaNs go last
+        ord_pixels.par_sort_unstable_by(|a, b| match (a.is_finite(), b.is_finite()) {
+            (true, true) => a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal),
+            (true, false) => std::cmp::Ordering::Less,
+            (false, true) => std::cmp::Ordering::Greater,
+            (false, false) => std::cmp::Ordering::Equal,
+        });
+
+        let (_, pmin, pmax, black, white, median, sensitivity, ratio_sensitivity) =
+            self.get_image_histogram(&ord_pixels, &pixels, &mask)?;
+
+        Some(RangeImage {
+            pixels: std::borrow::Cow::Owned(pixels),
+            mask: std::borrow::Cow::Owned(mask),
+            pmin: pmin,
+            pmax: pmax,
+            black: black,
+            white: white,
+            median: median,
+            sensitivity: sensitivity,
+            ratio_sensitivity: ratio_sensitivity,
+        })
+    }
+
+    //the image integrated over a frame range (the whole cube by default) with its statistics
+    fn get_range_image(
+        &self,
+        frame_start: Option<f64>,
+        frame_end: Option<f64>,
+        ref_freq: f64,
+    ) -> Option<RangeImage<'_>> {
+        let (start, end) = match (frame_start, frame_end) {
+            (Some(frame_start), Some(frame_end)) => {
+                self.get_spectrum_range(frame_start, frame_end, ref_freq)?
             }
+            _ => (0, self.depth - 1),
         };
 
-        let image_frame = FITSImage {
-            identifier: String::from("VP9"),
-            width: w,
-            height: h,
-            image: image_frame,
-            alpha: alpha_frame,
+        //an image integrated over a different channel range needs new pixels and percentiles
+        let custom_range = start != 0 || end != self.depth - 1;
+
+        if custom_range {
+            let (pixels, mask, _, _) = self.make_image_spectrum(start, end)?;
+            self.owned_range_image(pixels, mask)
+        } else {
+            Some(RangeImage {
+                pixels: std::borrow::Cow::Borrowed(&self.pixels),
+                mask: std::borrow::Cow::Borrowed(&self.mask),
+                pmin: self.pmin,
+                pmax: self.pmax,
+                black: self.black,
+                white: self.white,
+                median: self.median,
+                sensitivity: self.sensitivity,
+                ratio_sensitivity: self.ratio_sensitivity,
+            })
+        }
+    }
+
+    //the noise of a sum over channels start..=end: the channel RMS added in quadrature
+    fn get_sum_noise(&self, start: usize, end: usize) -> Option<f32> {
+        let stats = self.frame_stats.read();
+
+        let variance: f32 = (start..=end)
+            .filter_map(|frame| stats.get(frame).copied().flatten())
+            .filter(|x| x.rms.is_finite())
+            .map(|x| x.rms * x.rms)
+            .sum();
+
+        if variance > 0.0 {
+            Some(variance.sqrt())
+        } else {
+            None
+        }
+    }
+
+    //the integrated intensity divided by its propagated noise (a signal-to-noise ratio map)
+    fn get_snr_image(
+        &self,
+        frame_start: Option<f64>,
+        frame_end: Option<f64>,
+        ref_freq: f64,
+    ) -> Option<RangeImage<'static>> {
+        let (start, end) = match (frame_start, frame_end) {
+            (Some(frame_start), Some(frame_end)) => {
+                self.get_spectrum_range(frame_start, frame_end, ref_freq)?
+            }
+            _ => (0, self.depth - 1),
         };
 
-        // remove the preallocation limit
-        let config = Configuration::default().disable_preallocation_size_limit();
-        match wincode::config::serialize(&image_frame, config) {
-            Ok(bin) => {
-                println!("FITSImage binary length: {}", bin.len());
+        let (pixels, mask) = if start != 0 || end != self.depth - 1 {
+            let (pixels, mask, _, _) = self.make_image_spectrum(start, end)?;
+            (pixels, mask)
+        } else {
+            (self.pixels.clone(), self.mask.clone())
+        };
 
-                match buffer.write_all(&bin) {
-                    Ok(()) => {
-                        //remove (rename) the temporary file
-                        let _ = std::fs::rename(tmp_filepath, filepath);
-                    }
-                    Err(err) => {
-                        println!(
-                            "image cache write error: {}, removing the temporary file",
-                            err
-                        );
-                        let _ = std::fs::remove_file(tmp_filepath);
-                    }
+        //the channel width the sums have been multiplied by (as in make_image_spectrum)
+        let cdelt3 = {
+            if self.has_velocity && self.depth > 1 {
+                self.cdelt3 * self.frame_multiplier / 1000.0
+            } else {
+                1.0
+            }
+        } as f32;
+
+        let noise = cdelt3 * self.get_sum_noise(start, end)?;
+
+        let snr: Vec<f32> = pixels.par_iter().map(|x| x / noise).collect();
+
+        self.owned_range_image(snr, mask)
+    }
+
+    /// a publication-quality PNG of the (stretched) image, see snapshot::render
+    pub fn get_snapshot(&self, params: &snapshot::SnapshotParams) -> Option<Vec<u8>> {
+        if !self.has_data || self.width == 0 || self.height == 0 {
+            return None;
+        }
+
+        let range = if params.snr {
+            self.get_snr_image(params.frame_start, params.frame_end, params.ref_freq)?
+        } else {
+            self.get_range_image(params.frame_start, params.frame_end, params.ref_freq)?
+        };
+
+        //SNR maps have their own black and white points
+        let (black, white, median) = if params.snr {
+            (range.black, range.white, range.median)
+        } else {
+            (self.black, self.white, self.median)
+        };
+
+        let (pixels, mask) = (range.pixels, range.mask);
+        let (pmin, pmax) = (range.pmin, range.pmax);
+        let (sensitivity, ratio_sensitivity) = (range.sensitivity, range.ratio_sensitivity);
+
+        //the stretch, as in the [image] WebSocket request
+        let flux = params.flux.clone().unwrap_or_else(|| self.flux.clone());
+        let black = params.black.unwrap_or(black);
+        let white = params.white.unwrap_or(white);
+        let median = params.median.unwrap_or(median);
+        let sensitivity = params.noise * sensitivity;
+        let ratio_sensitivity = params.noise * ratio_sensitivity;
+
+        let (pmin, pmax, lmin, lmax) = if flux == "legacy" {
+            let xmin = 0.01f32;
+            let xmax = 100.0f32;
+            let p = 0.001f32 + (0.5f32 - 0.001f32) * (params.noise - xmin) / (xmax - xmin);
+
+            (black, white, p.ln(), (p + 1.0).ln())
+        } else {
+            (pmin, pmax, self.lmin, self.lmax)
+        };
+
+        //an additional stretch is built once from the whole range image and applied to the
+        //image as well as to the colourbar ramp and its ticks
+        let function = stretch::Stretch::from_flux(&flux)
+            .map(|x| stretch::StretchFunction::new(x, &pixels, &mask, black, white));
+
+        let to_luminance = |pixels: &Vec<f32>, mask: &Vec<u8>| match function {
+            Some(ref function) => self.stretch_to_luminance(function, pixels, mask, &None),
+            None => self.pixels_to_luminance(
+                pixels,
+                mask,
+                pmin,
+                pmax,
+                lmin,
+                lmax,
+                black,
+                white,
+                median,
+                sensitivity,
+                ratio_sensitivity,
+                &flux,
+                &None,
+            ),
+        };
+
+        let y = to_luminance(&*pixels, &*mask);
+
+        //the region (0-based, inclusive), flipped so that the top row comes first
+        let (x1, y1, x2, y2) =
+            params
+                .region
+                .unwrap_or((0, 0, self.width as i32 - 1, self.height as i32 - 1));
+
+        let x1 = x1.clamp(0, self.width as i32 - 1) as usize;
+        let x2 = x2.clamp(0, self.width as i32 - 1) as usize;
+        let y1 = y1.clamp(0, self.height as i32 - 1) as usize;
+        let y2 = y2.clamp(0, self.height as i32 - 1) as usize;
+        let (x1, x2) = (x1.min(x2), x1.max(x2));
+        let (y1, y2) = (y1.min(y2), y1.max(y2));
+
+        let (dimx, dimy) = (x2 - x1 + 1, y2 - y1 + 1);
+        let mut luminance: Vec<u8> = Vec::with_capacity(dimx * dimy);
+        let mut alpha: Vec<u8> = Vec::with_capacity(dimx * dimy);
+
+        for row in (y1..=y2).rev() {
+            let offset = row * self.width;
+            luminance.extend_from_slice(&y[offset + x1..=offset + x2]);
+            alpha.extend_from_slice(&mask[offset + x1..=offset + x2]);
+        }
+
+        //colourbar ticks: sample the stretch over the data range
+        let lo = black.min(pmin) as f64;
+        let hi = white.max(pmax) as f64;
+        const NO_SAMPLES: usize = 1024;
+
+        let samples: Vec<f32> = (0..NO_SAMPLES)
+            .map(|i| (lo + (hi - lo) * (i as f64) / ((NO_SAMPLES - 1) as f64)) as f32)
+            .collect();
+        let samples_lum = to_luminance(&samples, &vec![255; NO_SAMPLES]);
+
+        //the values spanning the unsaturated part of the colourbar
+        let first = samples_lum.iter().rposition(|l| *l == 0).unwrap_or(0);
+        let last = samples_lum
+            .iter()
+            .position(|l| *l == 255)
+            .unwrap_or(NO_SAMPLES - 1);
+
+        let ticks = if first < last {
+            let (values, step) =
+                snapshot::nice_ticks(samples[first] as f64, samples[last] as f64, 8);
+            let values_f32: Vec<f32> = values.iter().map(|x| *x as f32).collect();
+            let values_lum = to_luminance(&values_f32, &vec![255; values_f32.len()]);
+
+            values
+                .iter()
+                .zip(values_lum.iter())
+                .map(|(v, l)| snapshot::ColourbarTick {
+                    luminance: *l,
+                    label: snapshot::format_tick(*v, step),
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        //the grid lines follow the full celestial WCS (rotation, projection)
+        let wcs = self.get_celestial_wcs();
+
+        let image = snapshot::SnapshotImage {
+            luminance: &luminance,
+            mask: &alpha,
+            width: dimx as u32,
+            height: dimy as u32,
+            x1: x1 as f64,
+            y1: y1 as f64,
+        };
+
+        snapshot::render(&image, wcs.as_ref(), &ticks, &self.beam_unit, params)
+    }
+
+    /// a stretched composite channel, see composite::render
+    pub fn get_composite_channel(
+        &self,
+        channel: &composite::Channel,
+    ) -> Option<composite::ChannelImage> {
+        if !self.has_data || self.width == 0 || self.height == 0 {
+            return None;
+        }
+
+        let range =
+            self.get_range_image(channel.frame_start, channel.frame_end, channel.ref_freq)?;
+
+        //each channel gets its own stretch, tuned to its own frame range
+        let flux = channel.flux.clone().unwrap_or_else(|| self.flux.clone());
+
+        let luminance = self.pixels_to_luminance(
+            &range.pixels,
+            &range.mask,
+            range.pmin,
+            range.pmax,
+            self.lmin,
+            self.lmax,
+            range.black,
+            range.white,
+            range.median,
+            range.sensitivity,
+            range.ratio_sensitivity,
+            &flux,
+            &None,
+        );
+
+        Some(composite::ChannelImage {
+            luminance: luminance,
+            mask: range.mask.into_owned(),
+            width: self.width,
+            height: self.height,
+            wcs: self.get_celestial_wcs(),
+        })
+    }
+
+    //the celestial WCS with the full CD matrix (or CDELT when there is none)
+    pub fn get_celestial_wcs(&self) -> Option<hips::CelestialWcs> {
+        let or_zero = |x: f64| if x.is_finite() { x } else { 0.0 };
+
+        let cd = if self.cd1_1.is_finite() && self.cd2_2.is_finite() {
+            [
+                [self.cd1_1, or_zero(self.cd1_2)],
+                [or_zero(self.cd2_1), self.cd2_2],
+            ]
+        } else {
+            [[self.cdelt1, 0.0], [0.0, self.cdelt2]]
+        };
+
+        hips::CelestialWcs::new(
+            &self.ctype1,
+            &self.ctype2,
+            (self.crval1, self.crval2),
+            (self.crpix1, self.crpix2),
+            cd,
+        )
+    }
+
+    //the frame label burnt into exported videos
+    fn frame_label(&self, frame: usize, ref_freq: f64) -> String {
+        let (f, v) = self.get_frame2freq_vel(frame, ref_freq, 0.0, false);
+
+        let mut label = format!("FRAME {}", frame + 1);
+
+        if f.is_finite() {
+            label.push_str(&format!("  {:.6} GHZ", f));
+        }
+
+        if v.is_finite() {
+            label.push_str(&format!("  {:.2} KM/S", v));
+        }
+
+        label
+    }
+
+    /// render a range of frames into a WebM (VP9) or MP4 (HEVC) file
+    pub fn export_video(&self, job: &str, params: &export::ExportParams) -> Result<(), String> {
+        if !self.has_data || self.width == 0 || self.height == 0 {
+            return Err(String::from("no image data"));
+        }
+
+        if self.depth < 2 {
+            return Err(String::from("not a data cube"));
+        }
+
+        let (start, end) = if params.frame_start.is_finite() && params.frame_end.is_finite() {
+            match self.get_spectrum_range(params.frame_start, params.frame_end, params.ref_freq) {
+                Some(range) => range,
+                None => return Err(String::from("invalid frame range")),
+            }
+        } else {
+            (0, self.depth - 1)
+        };
+
+        let no_frames = end - start + 1;
+
+        if no_frames > export::EXPORT_MAX_FRAMES {
+            return Err(format!(
+                "too many frames: {} > {}",
+                no_frames,
+                export::EXPORT_MAX_FRAMES
+            ));
+        }
+
+        //downscale large images, both dimensions need to be even for 4:2:0 chroma
+        let pixel_count = (self.width as u64) * (self.height as u64);
+        let scale = if pixel_count > export::EXPORT_PIXEL_COUNT_LIMIT {
+            ((export::EXPORT_PIXEL_COUNT_LIMIT as f64) / (pixel_count as f64)).sqrt()
+        } else {
+            1.0
+        };
+
+        let width = ((((self.width as f64) * scale) as u32).max(32) + 1) & !1;
+        let height = ((((self.height as f64) * scale) as u32).max(32) + 1) & !1;
+
+        let fps = params.fps.max(1).min(60);
+        let flux = match params.flux {
+            Some(ref flux) => flux.clone(),
+            None => self.flux.clone(),
+        };
+
+        let mut encoder: Box<dyn export::VideoFileEncoder> = match params.format {
+            export::ExportFormat::WebM => {
+                Box::new(export::Vp9FileEncoder::new(width, height, fps)?)
+            }
+            export::ExportFormat::Mp4 => {
+                Box::new(export::HevcFileEncoder::new(width, height, fps)?)
+            }
+        };
+
+        //scale the label with the video
+        let font = ((height as i64) / 240).max(1);
+        let watch = Instant::now();
+
+        let mut frames: Vec<export::EncodedFrame> = Vec::with_capacity(no_frames);
+
+        for (index, frame) in (start..end + 1).enumerate() {
+            let y: Vec<u8> = match self.data_to_luminance(frame, &flux, &None) {
+                Some(y) => y,
+                None => vec![0; (self.width as usize) * (self.height as usize)],
+            };
+
+            //invert and downscale
+            let mut dst = vec![0; (width * height) as usize];
+            self.resize_and_invert(&y, &mut dst, width, height, libyuv_FilterMode_kFilterBox);
+
+            if params.label {
+                snapshot::label_luma(
+                    &mut dst,
+                    width,
+                    height,
+                    2 * font,
+                    height as i64 - snapshot::label_height(font) - 3 * font,
+                    &self.frame_label(frame, params.ref_freq),
+                    font,
+                );
+            }
+
+            if let Some(packet) = encoder.encode(&dst, index as u32)? {
+                frames.push(packet);
+            }
+
+            export::set_progress(job, ((index + 1) as f32) / (no_frames as f32));
+        }
+
+        frames.extend(encoder.flush()?);
+
+        if frames.is_empty() {
+            return Err(String::from("no frames encoded"));
+        }
+
+        let file = encoder.finish(&frames, fps)?;
+
+        let dir = export::export_directory(&self.dataset_id);
+        std::fs::create_dir_all(&dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+
+        //write to a temporary file first so that a partial video is never served
+        let path = export::export_path(&self.dataset_id, job, params.format);
+        let tmp = path.with_extension("tmp");
+
+        std::fs::write(&tmp, &file)
+            .and_then(|_| std::fs::rename(&tmp, &path))
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+        println!(
+            "{} frames [{}-{}] exported as a {}x{} {} video ({} bytes) in {:?}",
+            frames.len(),
+            start + 1,
+            end + 1,
+            width,
+            height,
+            params.format.extension(),
+            file.len(),
+            watch.elapsed()
+        );
+
+        Ok(())
+    }
+
+    /// reproject the image (using the default stretch) onto a HiPS tile set in HIPSCACHE
+    pub fn make_hips(&self) -> Result<u32, String> {
+        if !self.has_data || self.width == 0 || self.height == 0 {
+            return Err(String::from("no image data"));
+        }
+
+        let wcs = match self.get_celestial_wcs() {
+            Some(wcs) => wcs,
+            None => {
+                return Err(format!(
+                    "unsupported WCS: CTYPE1 = {}, CTYPE2 = {}",
+                    self.ctype1, self.ctype2
+                ));
+            }
+        };
+
+        let luminance: Vec<u8> = self.pixels_to_luminance(
+            &self.pixels,
+            &self.mask,
+            self.pmin,
+            self.pmax,
+            self.lmin,
+            self.lmax,
+            self.black,
+            self.white,
+            self.median,
+            self.sensitivity,
+            self.ratio_sensitivity,
+            &self.flux,
+            &None,
+        );
+
+        let title = if self.obj_name.is_empty() {
+            self.dataset_id.clone()
+        } else {
+            self.obj_name.clone()
+        };
+
+        let source = hips::HipsSource {
+            luminance: &luminance,
+            mask: &self.mask,
+            width: self.width,
+            height: self.height,
+            wcs: wcs,
+            title: title,
+        };
+
+        //write into a temporary directory first so that an interrupted job never looks complete
+        let dir = hips::hips_directory(&self.dataset_id);
+        let tmp = std::path::PathBuf::from(format!("{}.tmp", dir.display()));
+        let _ = std::fs::remove_dir_all(&tmp);
+
+        let max_order = hips::generate(&source, &tmp)?;
+
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::rename(&tmp, &dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+
+        Ok(max_order)
+    }
+
+    /// a deep-zoom tile pyramid (the default stretch) for 2-D images too large to be served in full by get_image
+    pub fn make_tile_pyramid(&self) {
+        let pixel_count = (self.width as u64) * (self.height as u64);
+
+        if !self.has_data || self.depth != 1 || pixel_count <= IMAGE_PIXEL_COUNT_LIMIT {
+            return;
+        }
+
+        if pyramid::status(&self.dataset_id) != pyramid::PyramidStatus::Missing
+            || !pyramid::start_job(&self.dataset_id)
+        {
+            return;
+        }
+
+        let mut luminance: Vec<u8> = self.pixels_to_luminance(
+            &self.pixels,
+            &self.mask,
+            self.pmin,
+            self.pmax,
+            self.lmin,
+            self.lmax,
+            self.black,
+            self.white,
+            self.median,
+            self.sensitivity,
+            self.ratio_sensitivity,
+            &self.flux,
+            &None,
+        );
+
+        //tiles are indexed from the top of the image
+        let mut alpha: Vec<u8> = Vec::with_capacity(self.mask.len());
+
+        for row in self.mask.chunks(self.width).rev() {
+            alpha.extend_from_slice(row);
+        }
+
+        for i in 0..self.height / 2 {
+            let (upper, lower) = luminance.split_at_mut((self.height - 1 - i) * self.width);
+            upper[i * self.width..(i + 1) * self.width].swap_with_slice(&mut lower[..self.width]);
+        }
+
+        //write into a temporary directory first so that an interrupted job never looks complete
+        let dir = pyramid::pyramid_directory(&self.dataset_id);
+        let tmp = std::path::PathBuf::from(format!("{}.tmp", dir.display()));
+        let _ = std::fs::remove_dir_all(&tmp);
+
+        match pyramid::generate(luminance, alpha, self.width, self.height, &tmp) {
+            Ok(_) => {
+                let _ = std::fs::remove_dir_all(&dir);
+
+                if let Err(err) = std::fs::rename(&tmp, &dir) {
+                    println!("{}: {}", dir.display(), err);
                 }
             }
-            Err(err) => println!("error serializing a FITSImage structure: {}", err),
+            Err(err) => {
+                println!("{}: tile pyramid error: {}", self.dataset_id, err);
+                let _ = std::fs::remove_dir_all(&tmp);
+            }
         }
+
+        pyramid::end_job(&self.dataset_id);
     }
 
     pub fn get_viewport(
@@ -6386,6 +8400,8 @@ impl FITS {
         y2: i32,
         user: &Option<UserParams>,
         wasm: bool,
+        codec: Option<Codec>,
+        bit_depth: u32,
         pool: &Option<rayon::ThreadPool>,
     ) -> Option<(u32, u32, Vec<Vec<u8>>, Vec<u8>, String)> {
         //spatial range checks
@@ -6429,19 +8445,112 @@ impl FITS {
         let mut pixels: Vec<f32> = vec![0.0; (dimx as usize) * (dimy as usize)];
         let mut mask: Vec<u8> = vec![0; (dimx as usize) * (dimy as usize)];
 
-        for j in y1..y2 + 1 {
-            let src_offset = (j * width) as usize;
-            let mut dst_offset = (((dimy - 1) - (j - y1)) * dimx) as usize;
+        for j in y1..y2 + 1 {
+            let src_offset = (j * width) as usize;
+            let mut dst_offset = (((dimy - 1) - (j - y1)) * dimx) as usize;
+
+            for i in x1..x2 + 1 {
+                let valid_pixel = (i >= 0) && (i < width) && (j >= 0) && (j < height);
+
+                if valid_pixel {
+                    pixels[dst_offset] = master_pixels[src_offset + i as usize];
+                    mask[dst_offset] = master_mask[src_offset + i as usize];
+                }
+
+                dst_offset = dst_offset + 1;
+            }
+        }
+
+        //x265 can only work with dimensions >= 32; in addition libxpv seems more efficient compression-size-wise for small images...
+        let method = if let Some(Codec::VPX) = codec {
+            Codec::VPX
+        } else if !wasm {
+            println!("wasm unsupported, switching over to VP9");
+            fits::Codec::VPX
+        } else {
+            if dimx < 128 || dimy < 128 {
+                println!(
+                    "viewport too small ({}x{}), switching over to VP9",
+                    dimx, dimy
+                );
+                Codec::VPX
+            } else {
+                println!("wasm supported, using HEVC");
+                fits::Codec::HEVC
+            }
+        };
+
+        //AV1 is decoded natively by the browser, no need for wasm
+        #[cfg(feature = "av1")]
+        let method = match codec {
+            Some(Codec::AV1) => Codec::AV1,
+            _ => method,
+        };
+
+        //10/12-bit luma: HEVC Main10/Main12, VP9 profile 2 or AV1, otherwise 8 bits
+        if bit_depth > 8 {
+            let y = match user {
+                Some(params) => self.pixels_to_luminance_hbd(
+                    &pixels,
+                    &mask,
+                    params.pmin,
+                    params.pmax,
+                    params.lmin,
+                    params.lmax,
+                    params.black,
+                    params.white,
+                    params.median,
+                    params.sensitivity,
+                    params.ratio_sensitivity,
+                    &params.flux,
+                    bit_depth,
+                    pool,
+                ),
+                None => self.pixels_to_luminance_hbd(
+                    &pixels,
+                    &mask,
+                    self.pmin,
+                    self.pmax,
+                    self.lmin,
+                    self.lmax,
+                    self.black,
+                    self.white,
+                    self.median,
+                    self.sensitivity,
+                    self.ratio_sensitivity,
+                    &self.flux,
+                    bit_depth,
+                    pool,
+                ),
+            };
+
+            let frame = match method {
+                Codec::VPX => encode_vpx_still_hbd(dimx as u32, dimy as u32, &y, bit_depth)
+                    .map(|frame| (vec![frame; 1], "VP9")),
+                Codec::HEVC => self
+                    .make_hevc_viewport(dimx as u32, dimy as u32, hevc::Plane::U16(y), bit_depth)
+                    .map(|frame| (frame, "HEVC")),
+                #[cfg(feature = "av1")]
+                Codec::AV1 => av1::encode_still_hbd(dimx as u32, dimy as u32, &y, bit_depth)
+                    .map(|frame| (vec![frame; 1], "AV1")),
+            };
 
-            for i in x1..x2 + 1 {
-                let valid_pixel = (i >= 0) && (i < width) && (j >= 0) && (j < height);
+            match frame {
+                Some((frame, identifier)) => {
+                    let alpha = lz4_compress::compress(&mask);
 
-                if valid_pixel {
-                    pixels[dst_offset] = master_pixels[src_offset + i as usize];
-                    mask[dst_offset] = master_mask[src_offset + i as usize];
+                    return Some((
+                        dimx as u32,
+                        dimy as u32,
+                        frame,
+                        alpha,
+                        String::from(identifier),
+                    ));
                 }
-
-                dst_offset = dst_offset + 1;
+                None => println!(
+                    "no {}-bit {:?} viewport, falling back to 8 bits",
+                    bit_depth, method
+                ),
             }
         }
 
@@ -6478,23 +8587,6 @@ impl FITS {
             ),
         };
 
-        //x265 can only work with dimensions >= 32; in addition libxpv seems more efficient compression-size-wise for small images...
-        let method = if !wasm {
-            println!("wasm unsupported, switching over to VP9");
-            fits::Codec::VPX
-        } else {
-            if dimx < 128 || dimy < 128 {
-                println!(
-                    "viewport too small ({}x{}), switching over to VP9",
-                    dimx, dimy
-                );
-                Codec::VPX
-            } else {
-                println!("wasm supported, using HEVC");
-                fits::Codec::HEVC
-            }
-        };
-
         let alpha = lz4_compress::compress(&mask);
 
         match method {
@@ -6502,8 +8594,17 @@ impl FITS {
                 Some(frame) => Some((dimx as u32, dimy as u32, frame, alpha, String::from("VP9"))),
                 None => None,
             },
-            Codec::HEVC => match self.make_hevc_viewport(dimx as u32, dimy as u32, &y) {
-                Some(frame) => Some((dimx as u32, dimy as u32, frame, alpha, String::from("HEVC"))),
+            Codec::HEVC => {
+                match self.make_hevc_viewport(dimx as u32, dimy as u32, hevc::Plane::U8(y), 8) {
+                    Some(frame) => {
+                        Some((dimx as u32, dimy as u32, frame, alpha, String::from("HEVC")))
+                    }
+                    None => None,
+                }
+            }
+            #[cfg(feature = "av1")]
+            Codec::AV1 => match self.make_av1_viewport(dimx as u32, dimy as u32, &y) {
+                Some(frame) => Some((dimx as u32, dimy as u32, frame, alpha, String::from("AV1"))),
                 None => None,
             },
         }
@@ -6543,35 +8644,17 @@ impl FITS {
             return None;
         }
 
-        // viewport dimensions
-        let dimx = (x2 - x1 + 1).abs();
-        let dimy = (y2 - y1 + 1).abs();
-
-        let cx = 1 + (x1 + x2) >> 1;
-        let cy = 1 + (y1 + y2) >> 1;
-
-        let rx = (x2 - x1).abs() >> 1;
-        let ry = (y2 - y1).abs() >> 1;
-
-        let (start, end) = match self.get_spectrum_range(frame_start, frame_end, ref_freq) {
-            Some(frame) => frame,
-            None => {
-                println!("error: an invalid spectrum range");
-                return None;
-            }
-        };
-
-        let (lng_value, lat_value) = self.pix_to_world(cx, cy);
-        let (ra1, dec1) = self.pix_to_world(cx - rx, cy - ry);
-        let (ra2, dec2) = self.pix_to_world(cx + rx, cy + ry);
-
-        let beam_width = (ra2 - ra1).abs(); // [deg]
-        let beam_height = (dec2 - dec1).abs(); // [deg]
-
-        println!(
-            "first channel: {}, last channel: {}, ra {} [deg], dec {} [deg], beam width [deg]: {}, beam height [deg]: {}",
-            start, end, lng_value, lat_value, beam_width, beam_height
-        );
+        let SpectrumRegion {
+            start,
+            cx,
+            cy,
+            dimx,
+            dimy,
+            lng: lng_value,
+            lat: lat_value,
+            width: beam_width,
+            height: beam_height,
+        } = self.get_spectrum_region(x1, y1, x2, y2, frame_start, frame_end, ref_freq)?;
 
         let mut intensity_column = format!("intensity [{}", self.beam_unit);
 
@@ -6726,62 +8809,574 @@ impl FITS {
                         continue;
                     }
 
-                    if v != std::f64::NAN {
-                        // write the CSV header
-                        if !has_header {
-                            let _ = stream.write(b"\"channel\",");
-                            let _ = stream.write(b"\"velocity [km/s]\",");
-                            let _ = stream.write(format!("\"{}\"\n", intensity_column).as_bytes());
+                    if v != std::f64::NAN {
+                        // write the CSV header
+                        if !has_header {
+                            let _ = stream.write(b"\"channel\",");
+                            let _ = stream.write(b"\"velocity [km/s]\",");
+                            let _ = stream.write(format!("\"{}\"\n", intensity_column).as_bytes());
+
+                            has_header = true;
+                        }
+
+                        // write out CSV values
+                        let _ = stream.write(format!("{},", frame).as_bytes());
+                        let _ = stream.write(format!("{},", v).as_bytes());
+                        let _ = stream.write(format!("{}\n", spectrum[i]).as_bytes());
+
+                        continue;
+                    }
+
+                    if f != std::f64::NAN {
+                        // write the CSV header
+                        if !has_header {
+                            let _ = stream.write(b"\"channel\",");
+                            let _ = stream.write(format!("\"{}\",", frequency_column).as_bytes());
+                            let _ = stream.write(format!("\"{}\"\n", intensity_column).as_bytes());
+
+                            has_header = true;
+                        }
+
+                        // write out CSV values
+                        let _ = stream.write(format!("{},", frame).as_bytes());
+                        let _ = stream.write(format!("{},", f).as_bytes());
+                        let _ = stream.write(format!("{}\n", spectrum[i]).as_bytes());
+
+                        continue;
+                    }
+                }
+
+                // flush the CSV stream
+                let _ = stream.flush();
+
+                match stream.into_inner() {
+                    Ok(w) => match String::from_utf8(w) {
+                        Ok(csv) => Some(csv),
+                        Err(err) => {
+                            println!("csv2utf8 conversion error: {}", err);
+                            None
+                        }
+                    },
+                    Err(err) => {
+                        println!("CSV into_inner() error: {}", err);
+                        None
+                    }
+                }
+            }
+            None => None,
+        }
+    }
+
+    //the pixel area [deg^2]
+    fn pixel_area(&self) -> f64 {
+        match self.get_celestial_wcs() {
+            Some(wcs) => wcs.pixel_scale() * wcs.pixel_scale(),
+            None => (self.cdelt1 * self.cdelt2).abs(),
+        }
+    }
+
+    /// region photometry (flux density, peak, mean, RMS, area) of a 2D image or of every
+    /// channel of a cube within the frame range, with the per-beam units divided by the
+    /// beam area (BMAJ/BMIN) and an optional background annulus subtracted
+    pub fn get_photometry(
+        &self,
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        beam: Beam,
+        annulus: Option<photometry::Annulus>,
+        frame_start: f64,
+        frame_end: f64,
+        ref_freq: f64,
+    ) -> Option<photometry::Photometry> {
+        if !self.has_data || self.width == 0 || self.height == 0 {
+            return None;
+        }
+
+        //spatial range checks
+        let x1 = num::clamp(x1, 0, self.width as i32 - 1) as usize;
+        let y1 = num::clamp(y1, 0, self.height as i32 - 1) as usize;
+
+        let x2 = num::clamp(x2, 0, self.width as i32 - 1) as usize;
+        let y2 = num::clamp(y2, 0, self.height as i32 - 1) as usize;
+
+        let aperture = photometry::Aperture {
+            x1: x1.min(x2),
+            y1: y1.min(y2),
+            x2: x1.max(x2),
+            y2: y1.max(y2),
+            circle: match beam {
+                Beam::Circle => true,
+                Beam::Square => false,
+            },
+        };
+
+        let (start, end) = if self.depth > 1 {
+            self.get_spectrum_range(frame_start, frame_end, ref_freq)?
+        } else {
+            (0, 0)
+        };
+
+        let pixel_area = self.pixel_area(); // [deg^2]
+
+        //the beam area in pixels, only for per-beam units (i.e. Jy/beam)
+        let per_beam = self.beam_unit.to_ascii_lowercase().contains("/beam");
+
+        let beam_area = if per_beam && self.bmaj > 0.0 && self.bmin > 0.0 && pixel_area > 0.0 {
+            Some(std::f64::consts::PI * self.bmaj * self.bmin / (4.0 * 2.0_f64.ln()) / pixel_area)
+        } else {
+            None
+        };
+
+        if per_beam && beam_area.is_none() {
+            println!(
+                "{}: {} without BMAJ/BMIN, the flux is not corrected for the beam area",
+                self.dataset_id, self.beam_unit
+            );
+        }
+
+        let flux_unit = match (beam_area, self.beam_unit.to_ascii_lowercase().find("/beam")) {
+            (Some(_), Some(pos)) => {
+                let mut unit = self.beam_unit.clone();
+                unit.replace_range(pos..pos + 5, "");
+                unit
+            }
+            _ => self.beam_unit.clone(),
+        };
+
+        let channel_width = if self.has_velocity && self.depth > 1 {
+            (self.cdelt3 * self.frame_multiplier / 1000.0).abs()
+        } else {
+            0.0
+        };
+
+        //only the bounding box of the aperture and of the annulus is read
+        let (bx1, by1, bx2, by2) = match annulus {
+            Some(annulus) => {
+                let (cx, cy) = aperture.centre();
+                let (cx, cy) = (cx as f64, cy as f64);
+                let r = annulus.outer;
+
+                let x1 = (cx - r).floor().max(0.0) as usize;
+                let y1 = (cy - r).floor().max(0.0) as usize;
+                let x2 = ((cx + r).ceil() as usize).min(self.width - 1);
+                let y2 = ((cy + r).ceil() as usize).min(self.height - 1);
+
+                (
+                    aperture.x1.min(x1),
+                    aperture.y1.min(y1),
+                    aperture.x2.max(x2),
+                    aperture.y2.max(y2),
+                )
+            }
+            None => (aperture.x1, aperture.y1, aperture.x2, aperture.y2),
+        };
+
+        //the aperture relative to the box
+        let boxed = photometry::Aperture {
+            x1: aperture.x1 - bx1,
+            y1: aperture.y1 - by1,
+            x2: aperture.x2 - bx1,
+            y2: aperture.y2 - by1,
+            circle: aperture.circle,
+        };
+
+        let watch = Instant::now();
+
+        let rows: Vec<photometry::PhotometryRow> = (start..=end)
+            .into_par_iter()
+            .filter_map(|frame| {
+                let pixels = self.frame_box_values(frame, (bx1, by1, bx2, by2))?;
+
+                //the channel noise, used without a background annulus
+                let noise = self
+                    .frame_stats
+                    .read()
+                    .get(frame)
+                    .copied()
+                    .flatten()
+                    .map_or(f32::NAN, |x| x.rms);
+
+                let (f, v) = if self.depth > 1 {
+                    self.get_frame2freq_vel(frame + 1, ref_freq, 0.0, false)
+                } else {
+                    (std::f64::NAN, std::f64::NAN)
+                };
+
+                Some(photometry::PhotometryRow {
+                    channel: frame + 1,
+                    frequency: f,
+                    velocity: v,
+                    measurement: photometry::measure(
+                        &pixels,
+                        bx2 - bx1 + 1,
+                        by2 - by1 + 1,
+                        &boxed,
+                        annulus,
+                        beam_area,
+                        noise,
+                    ),
+                })
+            })
+            .collect();
+
+        println!(
+            "{}: photometry of {} channel(s), elapsed time: {:?}",
+            self.dataset_id,
+            rows.len(),
+            watch.elapsed()
+        );
+
+        if rows.is_empty() {
+            return None;
+        }
+
+        let (cx, cy) = aperture.centre();
+        let (lng, lat) = self.pix_to_world(cx as i32 + 1, cy as i32 + 1);
+
+        Some(photometry::Photometry {
+            dataset_id: self.dataset_id.clone(),
+            aperture: aperture,
+            annulus: annulus,
+            lng: lng,
+            lat: lat,
+            unit: self.beam_unit.clone(),
+            flux_unit: flux_unit,
+            beam: beam_area,
+            pixel_area: pixel_area * 3600.0 * 3600.0,
+            channel_width: channel_width,
+            rows: rows,
+        })
+    }
+
+    //the channel range and the centre and size of a spectrum export region
+    fn get_spectrum_region(
+        &self,
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        frame_start: f64,
+        frame_end: f64,
+        ref_freq: f64,
+    ) -> Option<SpectrumRegion> {
+        // viewport dimensions
+        let dimx = (x2 - x1 + 1).abs();
+        let dimy = (y2 - y1 + 1).abs();
+
+        let cx = 1 + (x1 + x2) >> 1;
+        let cy = 1 + (y1 + y2) >> 1;
+
+        let rx = (x2 - x1).abs() >> 1;
+        let ry = (y2 - y1).abs() >> 1;
+
+        let (start, end) = match self.get_spectrum_range(frame_start, frame_end, ref_freq) {
+            Some(frame) => frame,
+            None => {
+                println!("error: an invalid spectrum range");
+                return None;
+            }
+        };
+
+        let (lng, lat) = self.pix_to_world(cx, cy);
+        let (ra1, dec1) = self.pix_to_world(cx - rx, cy - ry);
+        let (ra2, dec2) = self.pix_to_world(cx + rx, cy + ry);
+
+        let width = (ra2 - ra1).abs(); // [deg]
+        let height = (dec2 - dec1).abs(); // [deg]
+
+        println!(
+            "first channel: {}, last channel: {}, ra {} [deg], dec {} [deg], beam width [deg]: {}, beam height [deg]: {}",
+            start, end, lng, lat, width, height
+        );
+
+        Some(SpectrumRegion {
+            start: start,
+            cx: cx,
+            cy: cy,
+            dimx: dimx,
+            dimy: dimy,
+            lng: lng,
+            lat: lat,
+            width: width,
+            height: height,
+        })
+    }
+
+    /// the spectrum of a region with its metadata for the VOTable and FITS BINTABLE exports
+    pub fn get_spectrum_table(
+        &self,
+        ra: &String,
+        dec: &String,
+        x1: i32,
+        y1: i32,
+        x2: i32,
+        y2: i32,
+        beam: Beam,
+        intensity: Intensity,
+        frame_start: f64,
+        frame_end: f64,
+        ref_freq: f64,
+        delta_v: f64,
+        rest: bool,
+        pool: &Option<rayon::ThreadPool>,
+    ) -> Option<table::SpectrumTable> {
+        if self.depth <= 1 {
+            return None;
+        }
+
+        let region = self.get_spectrum_region(x1, y1, x2, y2, frame_start, frame_end, ref_freq)?;
+
+        let spectrum = self.get_spectrum(
+            x1,
+            y1,
+            x2,
+            y2,
+            beam.clone(),
+            intensity,
+            frame_start,
+            frame_end,
+            ref_freq,
+            pool,
+        )?;
+
+        let (ra_suffix, ra_value) = FITS::split_wcs(ra);
+        let (dec_suffix, dec_value) = FITS::split_wcs(dec);
+
+        let mut params = vec![
+            table::Param::text(
+                "ra",
+                "RA",
+                "pos.eq.ra",
+                &format!("region centre ra ({})", ra_suffix),
+                &ra_value,
+            ),
+            table::Param::text(
+                "dec",
+                "DEC",
+                "pos.eq.dec",
+                &format!("region centre dec ({})", dec_suffix),
+                &dec_value,
+            ),
+            table::Param::number(
+                "lng",
+                "LNG",
+                "deg",
+                "pos.eq.ra;meta.main",
+                "region centre wcs.lng",
+                region.lng,
+            ),
+            table::Param::number(
+                "lat",
+                "LAT",
+                "deg",
+                "pos.eq.dec;meta.main",
+                "region centre wcs.lat",
+                region.lat,
+            ),
+            table::Param::text(
+                "region_type",
+                "REGION",
+                "meta.code",
+                "region type",
+                match beam {
+                    Beam::Circle => "circle",
+                    Beam::Square => "square",
+                },
+            ),
+            table::Param::number(
+                "region_x",
+                "REGX",
+                "pix",
+                "pos.cartesian.x",
+                "region centre (x)",
+                region.cx as f64,
+            ),
+            table::Param::number(
+                "region_y",
+                "REGY",
+                "pix",
+                "pos.cartesian.y",
+                "region centre (y)",
+                region.cy as f64,
+            ),
+        ];
+
+        match beam {
+            Beam::Circle => {
+                params.push(table::Param::number(
+                    "region_diameter",
+                    "REGDIAM",
+                    "deg",
+                    "phys.angSize",
+                    "region diameter",
+                    region.width,
+                ));
+                params.push(table::Param::number(
+                    "region_diameter_px",
+                    "REGDIAMP",
+                    "pix",
+                    "phys.angSize",
+                    "region diameter",
+                    region.dimx as f64,
+                ));
+            }
+            Beam::Square => {
+                params.push(table::Param::number(
+                    "region_width",
+                    "REGWIDTH",
+                    "deg",
+                    "phys.angSize",
+                    "region width",
+                    region.width,
+                ));
+                params.push(table::Param::number(
+                    "region_height",
+                    "REGHEIGH",
+                    "deg",
+                    "phys.angSize",
+                    "region height",
+                    region.height,
+                ));
+                params.push(table::Param::number(
+                    "region_width_px",
+                    "REGWIDTP",
+                    "pix",
+                    "phys.angSize",
+                    "region width",
+                    region.dimx as f64,
+                ));
+                params.push(table::Param::number(
+                    "region_height_px",
+                    "REGHEIGP",
+                    "pix",
+                    "phys.angSize",
+                    "region height",
+                    region.dimy as f64,
+                ));
+            }
+        };
+
+        //the restoring beam, if any
+        if self.bmaj > 0.0 && self.bmin > 0.0 {
+            params.push(table::Param::number(
+                "bmaj",
+                "BMAJ",
+                "deg",
+                "instr.beam;phys.angSize",
+                "beam major axis",
+                self.bmaj,
+            ));
+            params.push(table::Param::number(
+                "bmin",
+                "BMIN",
+                "deg",
+                "instr.beam;phys.angSize",
+                "beam minor axis",
+                self.bmin,
+            ));
+            params.push(table::Param::number(
+                "bpa",
+                "BPA",
+                "deg",
+                "instr.beam;pos.posAng",
+                "beam position angle",
+                self.bpa,
+            ));
+        }
 
-                            has_header = true;
-                        }
+        params.push(table::Param::text(
+            "specsys",
+            "SPECSYS",
+            "meta.code",
+            "spectral reference frame",
+            &self.specsys,
+        ));
 
-                        // write out CSV values
-                        let _ = stream.write(format!("{},", frame).as_bytes());
-                        let _ = stream.write(format!("{},", v).as_bytes());
-                        let _ = stream.write(format!("{}\n", spectrum[i]).as_bytes());
+        params.push(table::Param::number(
+            "source_velocity",
+            "VSOURCE",
+            "km/s",
+            "spect.dopplerVeloc",
+            "source velocity",
+            delta_v / 1000.0,
+        ));
 
-                        continue;
-                    }
+        if ref_freq > 0.0 {
+            params.push(table::Param::number(
+                "rest_frequency",
+                "RESTFRQ",
+                "Hz",
+                "em.freq;meta.ref",
+                "reference frequency",
+                ref_freq,
+            ));
+        }
 
-                    if f != std::f64::NAN {
-                        // write the CSV header
-                        if !has_header {
-                            let _ = stream.write(b"\"channel\",");
-                            let _ = stream.write(format!("\"{}\",", frequency_column).as_bytes());
-                            let _ = stream.write(format!("\"{}\"\n", intensity_column).as_bytes());
+        let mut channels: Vec<i32> = Vec::with_capacity(spectrum.len());
+        let mut frequencies: Vec<f64> = Vec::with_capacity(spectrum.len());
+        let mut velocities: Vec<f64> = Vec::with_capacity(spectrum.len());
 
-                            has_header = true;
-                        }
+        for i in 0..spectrum.len() {
+            let frame = region.start + i + 1;
+            let (f, v) = self.get_frame2freq_vel(frame, ref_freq, delta_v, rest);
 
-                        // write out CSV values
-                        let _ = stream.write(format!("{},", frame).as_bytes());
-                        let _ = stream.write(format!("{},", f).as_bytes());
-                        let _ = stream.write(format!("{}\n", spectrum[i]).as_bytes());
+            channels.push(frame as i32);
+            frequencies.push(f);
+            velocities.push(v);
+        }
 
-                        continue;
-                    }
-                }
+        let mut intensity_unit = self.beam_unit.clone();
 
-                // flush the CSV stream
-                let _ = stream.flush();
+        let intensity_description = match intensity {
+            Intensity::Mean => "mean intensity",
+            Intensity::Integrated => {
+                if self.has_velocity {
+                    intensity_unit = format!("{}.km/s", intensity_unit);
+                };
 
-                match stream.into_inner() {
-                    Ok(w) => match String::from_utf8(w) {
-                        Ok(csv) => Some(csv),
-                        Err(err) => {
-                            println!("csv2utf8 conversion error: {}", err);
-                            None
-                        }
-                    },
-                    Err(err) => {
-                        println!("CSV into_inner() error: {}", err);
-                        None
-                    }
-                }
+                "integrated intensity"
             }
-            None => None,
-        }
+        };
+
+        let columns = vec![
+            table::Column {
+                name: String::from("channel"),
+                unit: String::new(),
+                ucd: "meta.number",
+                description: String::from("channel (1-based)"),
+                data: table::ColumnData::Int(channels),
+            },
+            table::Column {
+                name: String::from("frequency"),
+                unit: String::from("GHz"),
+                ucd: "em.freq",
+                description: String::from(if rest { "rest frequency" } else { "frequency" }),
+                data: table::ColumnData::Double(frequencies),
+            },
+            table::Column {
+                name: String::from("velocity"),
+                unit: String::from("km/s"),
+                ucd: "spect.dopplerVeloc",
+                description: String::from("velocity"),
+                data: table::ColumnData::Double(velocities),
+            },
+            table::Column {
+                name: String::from("intensity"),
+                unit: intensity_unit,
+                ucd: "phot.flux.density",
+                description: String::from(intensity_description),
+                data: table::ColumnData::Double(spectrum.iter().map(|x| *x as f64).collect()),
+            },
+        ];
+
+        Some(table::SpectrumTable {
+            name: self.dataset_id.clone(),
+            description: format!("{} spectrum exported by fits_web_ql", self.dataset_id),
+            params: params,
+            columns: columns,
+        })
     }
 
     pub fn get_spectrum(
@@ -7385,6 +9980,75 @@ impl FITS {
         return (std::f64::NAN, std::f64::NAN);
     }
 
+    /// the velocity [km/s] of a frame (0-based), None without a velocity axis
+    /// or a rest frequency
+    pub fn frame_velocity(&self, frame: usize) -> Option<f64> {
+        if self.depth <= 1 {
+            return None;
+        }
+
+        let (_, v) = self.get_frame2freq_vel(frame + 1, self.restfrq, 0.0, false);
+
+        if v.is_finite() { Some(v) } else { None }
+    }
+
+    /// the per-channel noise statistics with the channel frequencies [GHz] and
+    /// velocities [km/s], None until every channel has been measured
+    pub fn get_noise_table(&self) -> Option<Vec<noise::NoiseRow>> {
+        let stats = self.frame_stats.read();
+
+        if self.depth == 0 || stats.len() < self.depth {
+            return None;
+        }
+
+        stats
+            .iter()
+            .take(self.depth)
+            .enumerate()
+            .map(|(frame, x)| {
+                let (f, v) = if self.depth > 1 {
+                    self.get_frame2freq_vel(frame + 1, self.restfrq, 0.0, false)
+                } else {
+                    (std::f64::NAN, std::f64::NAN)
+                };
+
+                Some(noise::NoiseRow {
+                    channel: frame + 1,
+                    frequency: f,
+                    velocity: v,
+                    stats: (*x)?,
+                })
+            })
+            .collect()
+    }
+
+    /// the noise table as CSV or JSON
+    pub fn get_noise_stats(&self, csv: bool) -> Option<String> {
+        let rows = self.get_noise_table()?;
+
+        if csv {
+            Some(noise::to_csv(&rows, &self.beam_unit))
+        } else {
+            Some(noise::to_json(&self.dataset_id, &rows, &self.beam_unit))
+        }
+    }
+
+    //the RMS-vs-channel spectrum, null for channels not measured (yet)
+    fn rms_spectrum(&self) -> Vec<Option<f32>> {
+        let stats = self.frame_stats.read();
+
+        (0..self.depth)
+            .map(|frame| {
+                stats
+                    .get(frame)
+                    .copied()
+                    .flatten()
+                    .map(|x| x.rms)
+                    .filter(|x| x.is_finite())
+            })
+            .collect()
+    }
+
     fn pix_to_world(&self, x: i32, y: i32) -> (f64, f64) {
         let ra = if self.ctype1.contains("RA")
             || self.ctype1.contains("GLON")
@@ -7610,6 +10274,71 @@ impl FITS {
         (fmin / 1000000000.0, fmax / 1000000000.0)
     }
 
+    pub fn get_header(&self) -> &str {
+        &self.header
+    }
+
+    /// the header fields recorded in the catalogue
+    pub fn header_summary(&self) -> catalogue::HeaderSummary {
+        //the equatorial coordinates of the image centre and half its diagonal
+        let (ra, dec, radius) = match self.get_celestial_wcs() {
+            Some(wcs) => {
+                let centre = wcs.pixel_to_world(
+                    (self.width as f64 - 1.0) / 2.0,
+                    (self.height as f64 - 1.0) / 2.0,
+                );
+
+                let radius = 0.5
+                    * wcs.pixel_scale()
+                    * ((self.width * self.width + self.height * self.height) as f64).sqrt();
+
+                //galactic positions are catalogued in equatorial coordinates too
+                match centre {
+                    Some((lon, lat)) => {
+                        let (ra, dec) =
+                            regrid::convert(lon, lat, wcs.frame, hips::Frame::Equatorial);
+                        (Some(ra), Some(dec), radius)
+                    }
+                    None => (None, None, radius),
+                }
+            }
+            None => (None, None, 0.0),
+        };
+
+        let (fmin, fmax) = self.get_frequency_range();
+        let positive = |x: f64| {
+            if x.is_finite() && x > 0.0 {
+                Some(x)
+            } else {
+                None
+            }
+        };
+
+        catalogue::HeaderSummary {
+            object: self.obj_name.clone(),
+            telescope: self.telescope.clone(),
+            line: self.line.clone(),
+            bunit: self.beam_unit.clone(),
+            obs_date: self.obs_date.clone(),
+            ra: ra,
+            dec: dec,
+            radius: radius,
+            freq_start: positive(fmin),
+            freq_end: positive(fmax),
+            bmaj: positive(self.bmaj),
+            bmin: positive(self.bmin),
+            bpa: if self.bpa.is_finite() && self.bmaj > 0.0 {
+                Some(self.bpa)
+            } else {
+                None
+            },
+            width: self.width,
+            height: self.height,
+            depth: self.depth,
+            polarisation: self.polarisation,
+        }
+    }
+
     pub fn to_json(&self) -> String {
         let value = json!({
                 "HEADER" : self.header,
@@ -7655,6 +10384,7 @@ impl FITS {
                 "FILTER" : self.filter,
                 "mean_spectrum" : &self.mean_spectrum,
                 "integrated_spectrum" : &self.integrated_spectrum,
+                "rms_spectrum" : self.rms_spectrum(),
                 /* the histogram part, pixel min, max etc... */
                 "min" : self.pmin,
                 "max" : self.pmax,
@@ -7672,6 +10402,78 @@ impl FITS {
         value.to_string()
     }
 
+    //the number of bytes held in RAM by the data vectors and the derived image/spectra
+    pub fn get_memory_footprint(&self) -> usize {
+        let data: usize = self.data_u8.iter().map(|v| v.len()).sum::<usize>()
+            + self
+                .data_i16
+                .iter()
+                .map(|v| v.len() * mem::size_of::<i16>())
+                .sum::<usize>()
+            + self
+                .data_i32
+                .iter()
+                .map(|v| v.len() * mem::size_of::<i32>())
+                .sum::<usize>()
+            + self
+                .data_f16
+                .iter()
+                .map(|v| v.len() * mem::size_of::<f16>())
+                .sum::<usize>()
+            + self
+                .data_f64
+                .iter()
+                .map(|v| v.len() * mem::size_of::<f64>())
+                .sum::<usize>();
+
+        let image = self.mask.len() + self.pixels.len() * mem::size_of::<f32>();
+
+        let spectra = (self.mean_spectrum.len()
+            + self.integrated_spectrum.len()
+            + self.frame_min.len()
+            + self.frame_max.len())
+            * mem::size_of::<f32>();
+
+        data + image + spectra + self.header.len()
+    }
+
+    //None for native-resolution FITS cutouts, copied verbatim from the FITS file;
+    //the array formats always carry physical float32 values
+    fn get_frame_binner(
+        &self,
+        binning: &cutout::Binning,
+        physical: bool,
+        x1: i32,
+        y1: i32,
+        width: usize,
+        height: usize,
+    ) -> Option<cutout::FrameBinner> {
+        if binning.is_native() && !physical {
+            return None;
+        }
+
+        let blank = cutout::header_attributes(self.header.as_bytes())
+            .get("BLANK")
+            .and_then(|x| x.as_i64());
+
+        let format = cutout::SampleFormat {
+            bitpix: self.bitpix,
+            bscale: self.bscale,
+            bzero: self.bzero,
+            datamin: self.datamin,
+            datamax: self.datamax,
+            ignrval: self.ignrval,
+            blank: blank,
+        };
+
+        Some(cutout::FrameBinner::new(
+            *binning,
+            format,
+            self.width,
+            (x1 as usize, y1 as usize, width, height),
+        ))
+    }
+
     pub fn get_cutout_data(
         &self,
         x1: i32,
@@ -7681,6 +10483,7 @@ impl FITS {
         frame_start: f64,
         frame_end: f64,
         ref_freq: f64,
+        binning: &cutout::Binning,
     ) -> Option<Vec<u8>> {
         //spatial range checks
         let x1 = num::clamp(x1, 0, self.width as i32 - 1);
@@ -7704,8 +10507,18 @@ impl FITS {
         let partial_height = (y2 - y1).abs() as usize;
         let partial_depth = end - start + 1;
 
-        let partial_data_size =
-            partial_height * partial_width * partial_depth * ((self.bitpix.abs() / 8) as usize);
+        //the binned dimensions, binned samples are stored as float32
+        let frames = binning.frames(start, end);
+        let no_frames = frames.len();
+        let (binned_width, binned_height, binned_depth) =
+            binning.dims(partial_width, partial_height, partial_depth);
+        let sample_size = if binning.is_native() {
+            (self.bitpix.abs() / 8) as usize
+        } else {
+            4
+        };
+
+        let partial_data_size = binned_height * binned_width * binned_depth * sample_size;
         let mut no_units = partial_data_size / FITS_CHUNK_LENGTH;
 
         if partial_data_size % FITS_CHUNK_LENGTH > 0 {
@@ -7715,7 +10528,7 @@ impl FITS {
         let partial_capacity = self.header.len() + no_units * FITS_CHUNK_LENGTH;
         let mut partial_fits = Vec::with_capacity(partial_capacity);
 
-        let naxes = [partial_width, partial_height, partial_depth, 1];
+        let naxes = [binned_width, binned_height, binned_depth, 1];
 
         //open the original FITS file
         let filename = format!("{}/{}.fits", FITSCACHE, self.dataset_id.replace("/", "_"));
@@ -7769,6 +10582,7 @@ impl FITS {
                         x1 as f64,
                         y1 as f64,
                         start as f64,
+                        binning,
                     );
                     partial_fits.extend_from_slice(&chunk);
                 }
@@ -7785,7 +10599,7 @@ impl FITS {
         let (tx, rx) = mpsc::channel();
 
         thread::spawn(move || {
-            for frame in start..end + 1 {
+            for frame in frames {
                 let offset = header_size + frame * frame_size;
 
                 if let Err(err) = f.seek(SeekFrom::Start(offset as u64)) {
@@ -7816,6 +10630,8 @@ impl FITS {
             }
         });
 
+        let mut binner =
+            self.get_frame_binner(binning, false, x1, y1, partial_width, partial_height);
         let mut frame: usize = 0;
 
         for data in rx {
@@ -7823,6 +10639,14 @@ impl FITS {
 
             //println!("read {}/{} FITS cube frames", frame, partial_depth);
 
+            if let Some(ref mut binner) = binner {
+                if let Some(binned) = binner.push(&data) {
+                    partial_fits.extend_from_slice(&binned);
+                }
+
+                continue;
+            }
+
             for y in y1..y2 {
                 let src_offset = ((y as usize) * self.width + (x1 as usize))
                     * ((self.bitpix.abs() / 8) as usize);
@@ -7833,10 +10657,15 @@ impl FITS {
             }
         }
 
-        if frame != partial_depth {
+        //the last partial spectral bin
+        if let Some(binned) = binner.as_mut().and_then(|binner| binner.finish()) {
+            partial_fits.extend_from_slice(&binned);
+        }
+
+        if frame != no_frames {
             println!(
                 "CRITICAL ERROR not all FITS cube frames have been read: {}/{}",
-                frame, partial_depth
+                frame, no_frames
             );
             return None;
         }
@@ -7872,7 +10701,9 @@ impl FITS {
         frame_start: f64,
         frame_end: f64,
         ref_freq: f64,
-    ) -> Option<mpsc::Receiver<Vec<u8>>> {
+        binning: &cutout::Binning,
+        format: cutout::CutoutFormat,
+    ) -> Option<(mpsc::Receiver<Vec<u8>>, usize)> {
         //spatial range checks
         let x1 = num::clamp(x1, 0, self.width as i32 - 1);
         let y1 = num::clamp(y1, 0, self.height as i32 - 1);
@@ -7898,8 +10729,19 @@ impl FITS {
         let partial_height = (y2 - y1).abs() as usize;
         let partial_depth = end - start + 1;
 
-        let partial_data_size =
-            partial_height * partial_width * partial_depth * ((self.bitpix.abs() / 8) as usize);
+        //the binned dimensions, binned samples (and all the array formats) are stored as float32
+        let frames = binning.frames(start, end);
+        let no_frames = frames.len();
+        let (binned_width, binned_height, binned_depth) =
+            binning.dims(partial_width, partial_height, partial_depth);
+        let physical = format != cutout::CutoutFormat::Fits;
+        let sample_size = if binning.is_native() && !physical {
+            (self.bitpix.abs() / 8) as usize
+        } else {
+            4
+        };
+
+        let partial_data_size = binned_height * binned_width * binned_depth * sample_size;
         let mut no_units = partial_data_size / FITS_CHUNK_LENGTH;
 
         if partial_data_size % FITS_CHUNK_LENGTH > 0 {
@@ -7909,7 +10751,7 @@ impl FITS {
         let partial_capacity = self.header.len() + no_units * FITS_CHUNK_LENGTH;
         let mut partial_size = 0;
 
-        let naxes = [partial_width, partial_height, partial_depth, 1];
+        let naxes = [binned_width, binned_height, binned_depth, 1];
 
         //open the original FITS file
         let filename = format!("{}/{}.fits", FITSCACHE, self.dataset_id.replace("/", "_"));
@@ -7949,6 +10791,7 @@ impl FITS {
         }
 
         let mut header_end: bool = false;
+        let mut header: Vec<u8> = Vec::new();
 
         while !header_end {
             //read a FITS chunk
@@ -7963,18 +10806,10 @@ impl FITS {
                         x1 as f64,
                         y1 as f64,
                         start as f64,
+                        binning,
                     );
 
-                    partial_size += chunk.len();
-
-                    let stream = stream_tx.clone();
-                    match stream.send(chunk.to_vec()) {
-                        Ok(()) => {}
-                        Err(err) => {
-                            println!("CRITICAL ERROR sending partial_fits: {}", err);
-                            return None;
-                        }
-                    }
+                    header.extend_from_slice(&chunk);
                 }
                 Err(err) => {
                     println!("CRITICAL ERROR reading FITS header: {}", err);
@@ -7983,13 +10818,42 @@ impl FITS {
             };
         }
 
+        //the array formats carry the cut-out header cards as attributes
+        let mut writer = cutout::array_writer(
+            format,
+            cutout::ArrayInfo {
+                name: String::from("data"),
+                depth: binned_depth,
+                height: binned_height,
+                width: binned_width,
+                attributes: cutout::header_attributes(&header),
+            },
+        );
+
+        let capacity = match writer {
+            Some(ref writer) => writer.length(),
+            None => partial_capacity,
+        };
+
+        let prologue = match writer {
+            Some(ref mut writer) => writer.begin(),
+            None => header,
+        };
+
+        partial_size += prologue.len();
+
+        if let Err(err) = stream_tx.send(prologue) {
+            println!("CRITICAL ERROR sending partial_fits: {}", err);
+            return None;
+        }
+
         let frame_size = self.width * self.height * ((self.bitpix.abs() / 8) as usize);
         let header_size = self.header.len();
 
         let (tx, rx) = mpsc::channel();
 
         thread::spawn(move || {
-            for frame in start..end + 1 {
+            for frame in frames {
                 let offset = header_size + frame * frame_size;
 
                 if let Err(err) = f.seek(SeekFrom::Start(offset as u64)) {
@@ -8022,6 +10886,8 @@ impl FITS {
 
         let fits_width = self.width;
         let fits_bitpix = self.bitpix;
+        let mut binner =
+            self.get_frame_binner(binning, physical, x1, y1, partial_width, partial_height);
 
         thread::spawn(move || {
             let mut frame: usize = 0;
@@ -8031,6 +10897,24 @@ impl FITS {
 
                 //println!("read {}/{} FITS cube frames", frame, partial_depth);
 
+                if let Some(ref mut binner) = binner {
+                    if let Some(binned) = binner.push(&data) {
+                        let binned = match writer {
+                            Some(ref mut writer) => writer.frame(&binned),
+                            None => binned,
+                        };
+
+                        partial_size += binned.len();
+
+                        if let Err(err) = stream_tx.send(binned) {
+                            println!("CRITICAL ERROR sending partial_fits: {}", err);
+                            return;
+                        }
+                    }
+
+                    continue;
+                }
+
                 for y in y1..y2 {
                     let src_offset = ((y as usize) * fits_width + (x1 as usize))
                         * ((fits_bitpix.abs() / 8) as usize);
@@ -8051,11 +10935,43 @@ impl FITS {
                 }
             }
 
-            if frame != partial_depth {
+            //the last partial spectral bin
+            if let Some(binned) = binner.as_mut().and_then(|binner| binner.finish()) {
+                let binned = match writer {
+                    Some(ref mut writer) => writer.frame(&binned),
+                    None => binned,
+                };
+
+                partial_size += binned.len();
+
+                if let Err(err) = stream_tx.send(binned) {
+                    println!("CRITICAL ERROR sending partial_fits: {}", err);
+                    return;
+                }
+            }
+
+            if frame != no_frames {
                 println!(
                     "CRITICAL ERROR not all FITS cube frames have been read: {}/{}",
-                    frame, partial_depth
+                    frame, no_frames
+                );
+                return;
+            }
+
+            if let Some(ref mut writer) = writer {
+                let epilogue = writer.end();
+                partial_size += epilogue.len();
+
+                if let Err(err) = stream_tx.send(epilogue) {
+                    println!("CRITICAL ERROR sending partial_fits: {}", err);
+                    return;
+                }
+
+                println!(
+                    "{:?} cut-out length: {}, capacity: {}",
+                    format, partial_size, capacity
                 );
+
                 return;
             }
 
@@ -8087,7 +11003,7 @@ impl FITS {
             }
         });
 
-        Some(stream_rx)
+        Some((stream_rx, capacity))
     }
 
     pub fn get_full_stream(&self) -> Option<mpsc::Receiver<Vec<u8>>> {
@@ -8857,6 +11773,107 @@ impl FITS {
             }
         }
     }
+
+    /// the header of a float32 copy of the data (i.e. a virtual dataset) followed by
+    /// HISTORY cards, padded to whole FITS chunks; the celestial (and spectral) axes
+    /// can be taken over from the dataset whose grid the data has been resampled onto
+    pub fn float_fits_header(
+        &self,
+        grid: Option<&FITS>,
+        spectral: bool,
+        history: &[String],
+    ) -> Vec<u8> {
+        let replaced = |keyword: &str| {
+            grid.is_some()
+                && (is_celestial_keyword(keyword) || (spectral && is_spectral_keyword(keyword)))
+        };
+
+        //the NAXISn cards must stay together, the other WCS cards follow them
+        let grid_cards = |axes: bool| -> Vec<u8> {
+            let grid = match grid {
+                Some(x) => x,
+                None => return Vec::new(),
+            };
+
+            grid.header
+                .as_bytes()
+                .chunks(FITS_LINE_LENGTH)
+                .take_while(|card| !card.starts_with(b"END     "))
+                .filter(|card| {
+                    let keyword = String::from_utf8_lossy(&card[0..8.min(card.len())]);
+                    let keyword = keyword.trim();
+
+                    replaced(keyword) && keyword.starts_with("NAXIS") == axes
+                })
+                .flatten()
+                .cloned()
+                .collect()
+        };
+
+        let mut header: Vec<u8> = Vec::with_capacity(self.header.len() + FITS_CHUNK_LENGTH);
+        let mut axes_done = false;
+        let mut wcs_done = false;
+
+        for card in self.header.as_bytes().chunks(FITS_LINE_LENGTH) {
+            let line = String::from_utf8_lossy(card);
+            let keyword = line.get(0..8).unwrap_or("").trim();
+
+            if keyword == "END" {
+                break;
+            }
+
+            if replaced(keyword) {
+                if keyword.starts_with("NAXIS") && !axes_done {
+                    header.extend_from_slice(&grid_cards(true));
+                    axes_done = true;
+                } else if !keyword.starts_with("NAXIS") && !wcs_done {
+                    header.extend_from_slice(&grid_cards(false));
+                    wcs_done = true;
+                }
+
+                continue;
+            }
+
+            match keyword {
+                //the data becomes a primary HDU
+                "XTENSION" => header.extend_from_slice(&fits_header_card("SIMPLE", "T")),
+                "BITPIX" => header.extend_from_slice(&fits_header_card(keyword, "-32")),
+                "BSCALE" => header.extend_from_slice(&fits_header_card(keyword, "1.0")),
+                "BZERO" => header.extend_from_slice(&fits_header_card(keyword, "0.0")),
+                //no longer valid for the float32 values
+                "BLANK" | "DATAMIN" | "DATAMAX" | "PCOUNT" | "GCOUNT" => {
+                    let comment = format!("COMMENT {} removed by fits_web_ql", keyword);
+                    header.extend_from_slice(format!("{:<80}", comment).as_bytes());
+                }
+                _ => header.extend_from_slice(card),
+            }
+        }
+
+        //a source header without any WCS cards
+        if grid.is_some() && !wcs_done {
+            header.extend_from_slice(&grid_cards(false));
+        }
+
+        for entry in history {
+            //at most 72 characters per HISTORY card
+            let text: Vec<char> = entry.chars().filter(|c| c.is_ascii()).collect();
+
+            for chunk in text.chunks(FITS_LINE_LENGTH - 8) {
+                let line: String = chunk.iter().collect();
+                header.extend_from_slice(format!("HISTORY {:<72}", line).as_bytes());
+            }
+        }
+
+        header.extend_from_slice(format!("{:<80}", "END").as_bytes());
+
+        let remainder = header.len() % FITS_CHUNK_LENGTH;
+
+        if remainder > 0 {
+            header.extend(std::iter::repeat_n(b' ', FITS_CHUNK_LENGTH - remainder));
+        }
+
+        header
+    }
 }
 
 impl Clone for FITS {
@@ -8911,6 +11928,135 @@ impl Drop for FITS {
     }
 }
 
+//the header keywords of the celestial axes (1 and 2)
+fn is_celestial_keyword(keyword: &str) -> bool {
+    matches!(
+        keyword,
+        "NAXIS1"
+            | "NAXIS2"
+            | "CTYPE1"
+            | "CTYPE2"
+            | "CUNIT1"
+            | "CUNIT2"
+            | "CRVAL1"
+            | "CRVAL2"
+            | "CRPIX1"
+            | "CRPIX2"
+            | "CDELT1"
+            | "CDELT2"
+            | "CROTA1"
+            | "CROTA2"
+            | "CD1_1"
+            | "CD1_2"
+            | "CD2_1"
+            | "CD2_2"
+            | "PC1_1"
+            | "PC1_2"
+            | "PC2_1"
+            | "PC2_2"
+            | "PC001001"
+            | "PC001002"
+            | "PC002001"
+            | "PC002002"
+            | "LONPOLE"
+            | "LATPOLE"
+            | "RADESYS"
+            | "RADECSYS"
+            | "EQUINOX"
+            | "EPOCH"
+    ) || keyword.starts_with("PV1_")
+        || keyword.starts_with("PV2_")
+}
+
+//the header keywords of the spectral axis (3)
+fn is_spectral_keyword(keyword: &str) -> bool {
+    matches!(
+        keyword,
+        "NAXIS3"
+            | "CTYPE3"
+            | "CUNIT3"
+            | "CRVAL3"
+            | "CRPIX3"
+            | "CDELT3"
+            | "CROTA3"
+            | "CD3_3"
+            | "PC3_3"
+            | "SPECSYS"
+            | "SSYSOBS"
+            | "RESTFRQ"
+            | "RESTFREQ"
+            | "VELREF"
+            | "ALTRVAL"
+            | "ALTRPIX"
+    )
+}
+
+//a fixed-format header card padded to FITS_LINE_LENGTH
+fn fits_header_card(keyword: &str, value: &str) -> [u8; FITS_LINE_LENGTH] {
+    let card = format!("{:<8}= {:>20} / modified by fits_web_ql", keyword, value);
+    let mut line = [b' '; FITS_LINE_LENGTH];
+
+    for (dst, src) in line.iter_mut().zip(card.bytes()) {
+        *dst = src;
+    }
+
+    line
+}
+
+//the header cards changed by binning: BITPIX, BSCALE/BZERO/BLANK, CDELTn and CDi_j
+fn binned_fits_header_line(
+    line: &str,
+    binning: &cutout::Binning,
+) -> Option<[u8; FITS_LINE_LENGTH]> {
+    let keyword = line.get(0..8)?.trim();
+
+    let value = || -> Option<f64> {
+        line.get(10..)?
+            .split('/')
+            .next()?
+            .trim()
+            .replace('D', "E")
+            .parse::<f64>()
+            .ok()
+    };
+
+    match keyword {
+        //the binned values are float32 physical values
+        "BITPIX" => Some(fits_header_card(keyword, "-32")),
+        "BSCALE" => Some(fits_header_card(keyword, "1.0")),
+        "BZERO" => Some(fits_header_card(keyword, "0.0")),
+        "BLANK" => {
+            let mut card = [b' '; FITS_LINE_LENGTH];
+            let comment = b"COMMENT BLANK removed by fits_web_ql (binned float32 data)";
+            card[..comment.len()].copy_from_slice(comment);
+            Some(card)
+        }
+        "CDELT1" | "CDELT2" | "CDELT3" => {
+            let axis = (keyword.as_bytes()[5] - b'1') as usize;
+            let cdelt = value()? * binning.factor(axis);
+            Some(fits_header_card(keyword, &format!("{:.12E}", cdelt)))
+        }
+        "CD1_1" | "CD1_2" | "CD2_1" | "CD2_2" | "CD3_3" => {
+            //the column j scales with the pixel size along the axis j
+            let axis = (keyword.as_bytes()[4] - b'1') as usize;
+            let cd = value()? * binning.factor(axis);
+            Some(fits_header_card(keyword, &format!("{:.12E}", cd)))
+        }
+        _ => None,
+    }
+}
+
+//a (transparently decompressed) reader of a FITS file, and whether it is compressed
+fn open_fits_reader(mut file: File) -> (Box<dyn Read>, bool) {
+    if is_gzip_compressed(&mut file) {
+        (Box::new(GzDecoder::new(file)), true)
+    } else if is_bzip2_compressed(&mut file) {
+        (Box::new(BzDecoder::new(file)), true)
+    } else {
+        (Box::new(file), false)
+    }
+}
+
 fn is_gzip_compressed(f: &mut File) -> bool {
     let mut header = [0; 10];
     match f.read_exact(&mut header) {
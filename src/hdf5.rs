@@ -0,0 +1,359 @@
+//a minimal HDF5 writer: a version 2 superblock, a root group with one contiguous dataset
+//and scalar attributes, followed by the raw dataset samples; enough for h5py and the HDF5 library
+
+//an undefined HDF5 address
+const UNDEFINED: u64 = u64::MAX;
+
+const SUPERBLOCK_LENGTH: u64 = 48;
+
+//object header message types
+const MSG_DATASPACE: u8 = 0x01;
+const MSG_LINK_INFO: u8 = 0x02;
+const MSG_DATATYPE: u8 = 0x03;
+const MSG_FILL_VALUE: u8 = 0x05;
+const MSG_LINK: u8 = 0x06;
+const MSG_LAYOUT: u8 = 0x08;
+const MSG_GROUP_INFO: u8 = 0x0A;
+const MSG_ATTRIBUTE: u8 = 0x0C;
+
+/// a scalar attribute value
+#[derive(Debug, Clone)]
+pub enum Attribute {
+    Number(f64),
+    Text(String),
+}
+
+//IEEE 754 with an implied leading mantissa bit
+fn floating_point(size: u32, big_endian: bool) -> Vec<u8> {
+    let (exponent_location, exponent_size, mantissa_size, bias): (u8, u8, u8, u32) = match size {
+        4 => (23, 8, 23, 127),
+        _ => (52, 11, 52, 1023),
+    };
+
+    let mut buf = vec![
+        0x11,
+        0x20 | if big_endian { 0x01 } else { 0x00 },
+        (8 * size - 1) as u8, //the sign bit location
+        0,
+    ];
+    buf.extend_from_slice(&size.to_le_bytes());
+    buf.extend_from_slice(&0u16.to_le_bytes());
+    buf.extend_from_slice(&((8 * size) as u16).to_le_bytes());
+    buf.extend_from_slice(&[exponent_location, exponent_size, 0, mantissa_size]);
+    buf.extend_from_slice(&bias.to_le_bytes());
+    buf
+}
+
+//a null-padded ASCII string
+fn fixed_string(size: u32) -> Vec<u8> {
+    let mut buf = vec![0x13, 0x01, 0, 0];
+    buf.extend_from_slice(&size.to_le_bytes());
+    buf
+}
+
+fn dataspace(shape: &[u64]) -> Vec<u8> {
+    //a rank 0 dataspace is a scalar
+    let kind = if shape.is_empty() { 0 } else { 1 };
+    let mut buf = vec![2, shape.len() as u8, 0, kind];
+
+    for dim in shape {
+        buf.extend_from_slice(&dim.to_le_bytes());
+    }
+
+    buf
+}
+
+fn attribute(name: &str, value: &Attribute) -> Vec<u8> {
+    let (datatype, data) = match value {
+        Attribute::Number(x) => (floating_point(8, false), x.to_le_bytes().to_vec()),
+        Attribute::Text(s) => {
+            let mut data = s.as_bytes().to_vec();
+
+            //zero-length strings are not allowed
+            if data.is_empty() {
+                data.push(0);
+            }
+
+            (fixed_string(data.len() as u32), data)
+        }
+    };
+
+    let space = dataspace(&[]);
+
+    let mut buf = vec![3, 0];
+    buf.extend_from_slice(&((name.len() + 1) as u16).to_le_bytes());
+    buf.extend_from_slice(&(datatype.len() as u16).to_le_bytes());
+    buf.extend_from_slice(&(space.len() as u16).to_le_bytes());
+    buf.push(0); //ASCII
+    buf.extend_from_slice(name.as_bytes());
+    buf.push(0);
+    buf.extend_from_slice(&datatype);
+    buf.extend_from_slice(&space);
+    buf.extend_from_slice(&data);
+    buf
+}
+
+//a version 2 object header with a 4-byte chunk size
+fn object_header(messages: &[(u8, Vec<u8>)]) -> Vec<u8> {
+    let mut body: Vec<u8> = Vec::new();
+
+    for (kind, data) in messages {
+        body.push(*kind);
+        body.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        body.push(0); //message flags
+        body.extend_from_slice(data);
+    }
+
+    let mut buf = b"OHDR".to_vec();
+    buf.push(2);
+    buf.push(0x02);
+    buf.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    buf.extend_from_slice(&body);
+
+    let checksum = lookup3(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+fn root_group(name: &str, address: u64) -> Vec<u8> {
+    let mut link_info = vec![0, 0];
+    link_info.extend_from_slice(&UNDEFINED.to_le_bytes()); //no fractal heap
+    link_info.extend_from_slice(&UNDEFINED.to_le_bytes()); //no name index
+
+    //a compact hard link
+    let mut link = vec![1, 0, name.len() as u8];
+    link.extend_from_slice(name.as_bytes());
+    link.extend_from_slice(&address.to_le_bytes());
+
+    object_header(&[
+        (MSG_LINK_INFO, link_info),
+        (MSG_GROUP_INFO, vec![0, 0]),
+        (MSG_LINK, link),
+    ])
+}
+
+fn dataset(shape: &[u64], attributes: &[(String, Attribute)], address: u64, size: u64) -> Vec<u8> {
+    let mut layout = vec![3, 1]; //contiguous
+    layout.extend_from_slice(&address.to_le_bytes());
+    layout.extend_from_slice(&size.to_le_bytes());
+
+    //late allocation, written if set, no fill value
+    let fill_value = vec![3, 0x0A];
+
+    let mut messages = vec![
+        (MSG_DATASPACE, dataspace(shape)),
+        (MSG_DATATYPE, floating_point(4, true)),
+        (MSG_FILL_VALUE, fill_value),
+        (MSG_LAYOUT, layout),
+    ];
+
+    for (name, value) in attributes {
+        messages.push((MSG_ATTRIBUTE, attribute(name, value)));
+    }
+
+    object_header(&messages)
+}
+
+/// everything preceding the samples of a file holding a single dataset of the given shape
+/// (slowest axis first); the big-endian float32 samples follow immediately
+pub fn file_header(name: &str, shape: &[u64], attributes: &[(String, Attribute)]) -> Vec<u8> {
+    let size = shape.iter().product::<u64>() * 4;
+
+    //the object header lengths do not depend on the addresses within
+    let root_length = root_group(name, 0).len() as u64;
+    let dataset_address = SUPERBLOCK_LENGTH + root_length;
+    let dataset_length = dataset(shape, attributes, 0, size).len() as u64;
+    let data_address = dataset_address + dataset_length;
+
+    let mut buf = b"\x89HDF\r\n\x1a\n".to_vec();
+    buf.extend_from_slice(&[2, 8, 8, 0]);
+    buf.extend_from_slice(&0u64.to_le_bytes()); //base address
+    buf.extend_from_slice(&UNDEFINED.to_le_bytes()); //no superblock extension
+    buf.extend_from_slice(&(data_address + size).to_le_bytes()); //end of file
+    buf.extend_from_slice(&SUPERBLOCK_LENGTH.to_le_bytes()); //the root group
+
+    let checksum = lookup3(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+
+    buf.extend_from_slice(&root_group(name, dataset_address));
+    buf.extend_from_slice(&dataset(shape, attributes, data_address, size));
+
+    buf
+}
+
+//Bob Jenkins' lookup3 hashlittle() with a zero seed, the HDF5 metadata checksum
+fn lookup3(key: &[u8]) -> u32 {
+    let mut a: u32 = 0xdeadbeef_u32.wrapping_add(key.len() as u32);
+    let mut b = a;
+    let mut c = a;
+
+    let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+    let mut rest = key;
+
+    while rest.len() > 12 {
+        a = a.wrapping_add(word(&rest[0..4]));
+        b = b.wrapping_add(word(&rest[4..8]));
+        c = c.wrapping_add(word(&rest[8..12]));
+
+        a = a.wrapping_sub(c);
+        a ^= c.rotate_left(4);
+        c = c.wrapping_add(b);
+        b = b.wrapping_sub(a);
+        b ^= a.rotate_left(6);
+        a = a.wrapping_add(c);
+        c = c.wrapping_sub(b);
+        c ^= b.rotate_left(8);
+        b = b.wrapping_add(a);
+        a = a.wrapping_sub(c);
+        a ^= c.rotate_left(16);
+        c = c.wrapping_add(b);
+        b = b.wrapping_sub(a);
+        b ^= a.rotate_left(19);
+        a = a.wrapping_add(c);
+        c = c.wrapping_sub(b);
+        c ^= b.rotate_left(4);
+        b = b.wrapping_add(a);
+
+        rest = &rest[12..];
+    }
+
+    if rest.is_empty() {
+        return c;
+    }
+
+    //the zero-padded tail
+    let mut tail = [0u8; 12];
+    tail[..rest.len()].copy_from_slice(rest);
+
+    a = a.wrapping_add(word(&tail[0..4]));
+    b = b.wrapping_add(word(&tail[4..8]));
+    c = c.wrapping_add(word(&tail[8..12]));
+
+    c ^= b;
+    c = c.wrapping_sub(b.rotate_left(14));
+    a ^= c;
+    a = a.wrapping_sub(c.rotate_left(11));
+    b ^= a;
+    b = b.wrapping_sub(a.rotate_left(25));
+    c ^= b;
+    c = c.wrapping_sub(b.rotate_left(16));
+    a ^= c;
+    a = a.wrapping_sub(c.rotate_left(4));
+    b ^= a;
+    b = b.wrapping_sub(a.rotate_left(14));
+    c ^= b;
+    c = c.wrapping_sub(b.rotate_left(24));
+
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(buf: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+    }
+
+    //the messages of a checksummed object header at <offset>, and its length
+    fn messages(buf: &[u8], offset: usize) -> (Vec<(u8, Vec<u8>)>, usize) {
+        assert_eq!(&buf[offset..offset + 4], b"OHDR");
+        assert_eq!(buf[offset + 4], 2);
+
+        let body = u32_at(buf, offset + 6) as usize;
+        let end = offset + 10 + body;
+        assert_eq!(lookup3(&buf[offset..end]), u32_at(buf, end));
+
+        let mut messages = Vec::new();
+        let mut pos = offset + 10;
+
+        while pos < end {
+            let kind = buf[pos];
+            let len = u16::from_le_bytes([buf[pos + 1], buf[pos + 2]]) as usize;
+            messages.push((kind, buf[pos + 4..pos + 4 + len].to_vec()));
+            pos += 4 + len;
+        }
+
+        assert_eq!(pos, end);
+
+        (messages, end + 4 - offset)
+    }
+
+    #[test]
+    fn lookup3_vectors() {
+        assert_eq!(lookup3(b""), 0xdeadbeef);
+        assert_eq!(lookup3(b"Four score and seven years ago"), 0x17770551);
+    }
+
+    #[test]
+    fn file_layout() {
+        let attributes = vec![
+            (
+                String::from("BUNIT"),
+                Attribute::Text(String::from("Jy/beam")),
+            ),
+            (String::from("EMPTY"), Attribute::Text(String::new())),
+            (String::from("CDELT1"), Attribute::Number(-1.5e-4)),
+        ];
+
+        let header = file_header("data", &[2, 3, 4], &attributes);
+
+        //the superblock
+        assert_eq!(&header[0..8], b"\x89HDF\r\n\x1a\n");
+        assert_eq!(header[8], 2);
+        assert_eq!(lookup3(&header[0..44]), u32_at(&header, 44));
+        assert_eq!(u64_at(&header, 28), header.len() as u64 + 2 * 3 * 4 * 4);
+        assert_eq!(u64_at(&header, 36), SUPERBLOCK_LENGTH);
+
+        //the root group links to the dataset
+        let (root, root_length) = messages(&header, SUPERBLOCK_LENGTH as usize);
+        let (_, link) = root.iter().find(|(kind, _)| *kind == MSG_LINK).unwrap();
+        assert_eq!(&link[3..7], b"data");
+
+        let dataset_address = SUPERBLOCK_LENGTH as usize + root_length;
+        assert_eq!(u64_at(link, 7), dataset_address as u64);
+
+        //the dataset: its shape, float32 samples following the header and the attributes
+        let (dataset, dataset_length) = messages(&header, dataset_address);
+        assert_eq!(dataset_address + dataset_length, header.len());
+
+        let (_, space) = dataset
+            .iter()
+            .find(|(kind, _)| *kind == MSG_DATASPACE)
+            .unwrap();
+        assert_eq!(space[1], 3);
+        assert_eq!(u64_at(space, 4), 2);
+        assert_eq!(u64_at(space, 12), 3);
+        assert_eq!(u64_at(space, 20), 4);
+
+        let (_, datatype) = dataset
+            .iter()
+            .find(|(kind, _)| *kind == MSG_DATATYPE)
+            .unwrap();
+        assert_eq!(datatype[0], 0x11);
+        assert_eq!(datatype[1] & 0x01, 0x01);
+        assert_eq!(u32_at(datatype, 4), 4);
+
+        let (_, layout) = dataset
+            .iter()
+            .find(|(kind, _)| *kind == MSG_LAYOUT)
+            .unwrap();
+        assert_eq!(u64_at(layout, 2), header.len() as u64);
+        assert_eq!(u64_at(layout, 10), 2 * 3 * 4 * 4);
+
+        let names: Vec<&[u8]> = dataset
+            .iter()
+            .filter(|(kind, _)| *kind == MSG_ATTRIBUTE)
+            .map(|(_, attribute)| {
+                let len = u16::from_le_bytes([attribute[2], attribute[3]]) as usize;
+                &attribute[9..9 + len]
+            })
+            .collect();
+        assert_eq!(names, vec![&b"BUNIT\0"[..], b"EMPTY\0", b"CDELT1\0"]);
+    }
+}
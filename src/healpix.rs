@@ -0,0 +1,202 @@
+//HEALPix NESTED scheme pixel <-> sky position conversions (Gorski et al. 2005)
+use std::f64::consts::{FRAC_PI_2, PI};
+
+//the maximum order supported with 64-bit pixel indices
+pub const MAX_ORDER: u32 = 29;
+
+//the base pixel ring and longitude indices
+const JRLL: [i64; 12] = [2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4];
+const JPLL: [i64; 12] = [1, 3, 5, 7, 0, 2, 4, 6, 1, 3, 5, 7];
+
+//interleave the bits of x with zeros: abc -> 0a0b0c
+pub fn spread_bits(x: u64) -> u64 {
+    let mut x = x & 0xffff_ffff;
+
+    x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+    x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    x = (x | (x << 1)) & 0x5555_5555_5555_5555;
+
+    x
+}
+
+//the inverse of spread_bits, the odd bits are discarded
+pub fn compress_bits(x: u64) -> u64 {
+    let mut x = x & 0x5555_5555_5555_5555;
+
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    x = (x | (x >> 16)) & 0x0000_0000_ffff_ffff;
+
+    x
+}
+
+pub fn npix(order: u32) -> u64 {
+    12u64 << (2 * order)
+}
+
+/// the NESTED pixel index containing (lon, lat) [deg]
+pub fn ang2pix(order: u32, lon: f64, lat: f64) -> u64 {
+    let nside = 1i64 << order;
+    let z = lat.to_radians().sin();
+    let za = z.abs();
+    let tt = lon.to_radians().rem_euclid(2.0 * PI) / FRAC_PI_2; //[0,4)
+
+    let (face, ix, iy) = if za <= 2.0 / 3.0 {
+        //the equatorial region
+        let temp1 = nside as f64 * (0.5 + tt);
+        let temp2 = nside as f64 * (z * 0.75);
+        let jp = (temp1 - temp2) as i64; //the ascending edge line index
+        let jm = (temp1 + temp2) as i64; //the descending edge line index
+        let ifp = jp >> order;
+        let ifm = jm >> order;
+
+        let face = if ifp == ifm {
+            (ifp & 3) + 4
+        } else if ifp < ifm {
+            ifp & 3
+        } else {
+            (ifm & 3) + 8
+        };
+
+        (face, jm & (nside - 1), nside - (jp & (nside - 1)) - 1)
+    } else {
+        //the polar caps
+        let ntt = (tt as i64).min(3);
+        let tp = tt - ntt as f64;
+        let tmp = nside as f64 * (3.0 * (1.0 - za)).sqrt();
+
+        let jp = ((tp * tmp) as i64).min(nside - 1);
+        let jm = (((1.0 - tp) * tmp) as i64).min(nside - 1);
+
+        if z >= 0.0 {
+            (ntt, nside - jm - 1, nside - jp - 1)
+        } else {
+            (ntt + 8, jp, jm)
+        }
+    };
+
+    ((face as u64) << (2 * order)) + spread_bits(ix as u64) + (spread_bits(iy as u64) << 1)
+}
+
+/// the centre (lon, lat) [deg] of a NESTED pixel
+pub fn pix2ang(order: u32, ipix: u64) -> (f64, f64) {
+    let nside = 1i64 << order;
+    let npface = (nside * nside) as u64;
+    let nl4 = 4 * nside;
+
+    let face = (ipix / npface) as usize;
+    let ipf = ipix % npface;
+    let ix = compress_bits(ipf) as i64;
+    let iy = compress_bits(ipf >> 1) as i64;
+
+    let jr = JRLL[face] * nside - ix - iy - 1;
+
+    let (nr, z, kshift) = if jr < nside {
+        let nr = jr;
+        (nr, 1.0 - (nr * nr) as f64 / (3 * npface) as f64, 0)
+    } else if jr > 3 * nside {
+        let nr = nl4 - jr;
+        (nr, (nr * nr) as f64 / (3 * npface) as f64 - 1.0, 0)
+    } else {
+        (
+            nside,
+            (2 * nside - jr) as f64 * 2.0 / (3 * nside) as f64,
+            (jr - nside) & 1,
+        )
+    };
+
+    let mut jp = (JPLL[face] * nr + ix - iy + 1 + kshift) / 2;
+
+    if jp > nl4 {
+        jp -= nl4;
+    }
+
+    if jp < 1 {
+        jp += nl4;
+    }
+
+    let phi = (jp as f64 - (kshift + 1) as f64 * 0.5) * (FRAC_PI_2 / nr as f64);
+
+    (phi.to_degrees(), z.asin().to_degrees())
+}
+
+/// the mean pixel size at a given order [deg]
+pub fn pixel_size(order: u32) -> f64 {
+    (4.0 * PI / npix(order) as f64).sqrt().to_degrees()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spread_and_compress_bits() {
+        assert_eq!(spread_bits(0b111), 0b010101);
+        assert_eq!(spread_bits(0b101), 0b010001);
+        assert_eq!(compress_bits(0b010101), 0b111);
+
+        //the odd bits belong to the other coordinate
+        assert_eq!(compress_bits(0b101010), 0);
+
+        for x in [0u64, 1, 2, 0x1234, 0xffff_ffff] {
+            assert_eq!(compress_bits(spread_bits(x)), x);
+        }
+    }
+
+    #[test]
+    fn base_pixel_centres() {
+        let cap = (2.0f64 / 3.0).asin().to_degrees();
+
+        //the northern faces 0..3, the equatorial faces 4..7, the southern faces 8..11
+        for face in 0..4u64 {
+            let (lon, lat) = pix2ang(0, face);
+            assert!((lon - (45.0 + 90.0 * face as f64)).abs() < 1e-9);
+            assert!((lat - cap).abs() < 1e-9);
+
+            let (lon, lat) = pix2ang(0, face + 4);
+            assert!((lon - 90.0 * face as f64).abs() < 1e-9);
+            assert!(lat.abs() < 1e-9);
+
+            let (lon, lat) = pix2ang(0, face + 8);
+            assert!((lon - (45.0 + 90.0 * face as f64)).abs() < 1e-9);
+            assert!((lat + cap).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn pixel_centres_round_trip() {
+        for order in 0..5 {
+            for ipix in 0..npix(order) {
+                let (lon, lat) = pix2ang(order, ipix);
+                assert_eq!(ang2pix(order, lon, lat), ipix, "order {}", order);
+            }
+        }
+
+        //a sample of a deep order
+        let order = 12;
+        for ipix in (0..npix(order)).step_by(9973) {
+            let (lon, lat) = pix2ang(order, ipix);
+            assert_eq!(ang2pix(order, lon, lat), ipix);
+        }
+    }
+
+    #[test]
+    fn poles_and_longitude_wrap() {
+        assert!(ang2pix(3, 0.0, 90.0) < 4 * npix(3) / 12);
+        assert!(ang2pix(3, 0.0, -90.0) >= 8 * npix(3) / 12);
+        assert_eq!(ang2pix(5, -30.0, 12.0), ang2pix(5, 330.0, 12.0));
+        assert_eq!(ang2pix(5, 360.0, 12.0), ang2pix(5, 0.0, 12.0));
+    }
+
+    #[test]
+    fn pixel_counts_and_sizes() {
+        assert_eq!(npix(0), 12);
+        assert_eq!(npix(MAX_ORDER), 12 << 58);
+        assert!((pixel_size(0) - 58.6323).abs() < 1e-4);
+        assert!((pixel_size(1) - pixel_size(0) / 2.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,147 @@
+use std::ffi::CString;
+use std::os::raw::c_void;
+use std::ptr;
+
+use crate::*; //the x265 bindings
+
+/// an x265 library for one internal bit depth: the default 8-bit encoder,
+/// or Main10/Main12 from a libx265 built with multilib support
+#[derive(Clone, Copy)]
+pub struct Api {
+    api: &'static x265_api,
+}
+
+//the function table is static and immutable
+unsafe impl Send for Api {}
+unsafe impl Sync for Api {}
+
+impl Default for Api {
+    fn default() -> Api {
+        Api::new(8)
+    }
+}
+
+impl Api {
+    /// the encoder for a bit depth (0 - the default one), None if the linked libx265 lacks it
+    pub fn get(bit_depth: u32) -> Option<Api> {
+        let api = unsafe { x265_api_get(bit_depth as i32) };
+
+        if api.is_null() {
+            return None;
+        }
+
+        let api = unsafe { &*api };
+
+        if bit_depth > 0 && api.bit_depth as u32 != bit_depth {
+            return None;
+        }
+
+        Some(Api { api })
+    }
+
+    /// the encoder for a bit depth, falling back to the default (8-bit) one
+    pub fn new(bit_depth: u32) -> Api {
+        match Api::get(bit_depth) {
+            Some(api) => api,
+            None => {
+                println!(
+                    "x265: no {}-bit encoder in this libx265, falling back to the default",
+                    bit_depth
+                );
+
+                Api::get(0).expect("x265: cannot get the default encoder")
+            }
+        }
+    }
+
+    /// the internal (output) bit depth
+    pub fn bit_depth(&self) -> u32 {
+        self.api.bit_depth as u32
+    }
+
+    /// a parameter set with a preset/tune and the matching internal bit depth
+    pub fn param_alloc(&self, preset: &str, tune: &str) -> *mut x265_param {
+        let param = unsafe { (self.api.param_alloc.unwrap())() };
+
+        if !param.is_null() {
+            let preset = CString::new(preset).unwrap();
+            let tune = CString::new(tune).unwrap();
+
+            unsafe {
+                (self.api.param_default_preset.unwrap())(param, preset.as_ptr(), tune.as_ptr());
+                (*param).internalBitDepth = self.api.bit_depth;
+            }
+        }
+
+        param
+    }
+
+    pub unsafe fn param_free(&self, param: *mut x265_param) {
+        unsafe { (self.api.param_free.unwrap())(param) }
+    }
+
+    pub unsafe fn picture_alloc(&self) -> *mut x265_picture {
+        unsafe { (self.api.picture_alloc.unwrap())() }
+    }
+
+    pub unsafe fn picture_init(&self, param: *mut x265_param, pic: *mut x265_picture) {
+        unsafe { (self.api.picture_init.unwrap())(param, pic) }
+    }
+
+    pub unsafe fn picture_free(&self, pic: *mut x265_picture) {
+        unsafe { (self.api.picture_free.unwrap())(pic) }
+    }
+
+    pub unsafe fn encoder_open(&self, param: *mut x265_param) -> *mut x265_encoder {
+        unsafe { (self.api.encoder_open.unwrap())(param) }
+    }
+
+    pub unsafe fn encoder_reconfig(&self, enc: *mut x265_encoder, param: *mut x265_param) -> i32 {
+        unsafe { (self.api.encoder_reconfig.unwrap())(enc, param) }
+    }
+
+    pub unsafe fn encoder_encode(
+        &self,
+        enc: *mut x265_encoder,
+        pp_nal: *mut *mut x265_nal,
+        pi_nal: *mut u32,
+        pic_in: *mut x265_picture,
+        pic_out: *mut x265_picture,
+    ) -> i32 {
+        unsafe { (self.api.encoder_encode.unwrap())(enc, pp_nal, pi_nal, pic_in, pic_out) }
+    }
+
+    pub unsafe fn encoder_close(&self, enc: *mut x265_encoder) {
+        unsafe { (self.api.encoder_close.unwrap())(enc) }
+    }
+}
+
+/// a luma plane, with 16-bit samples for the 10/12-bit encoders
+pub enum Plane {
+    U8(Vec<u8>),
+    U16(Vec<u16>),
+}
+
+impl Plane {
+    /// point the luma of an x265 picture at the samples (the stride is in bytes),
+    /// the plane must outlive the encoding
+    pub unsafe fn attach(&mut self, pic: *mut x265_picture, width: u32) {
+        let (planes, stride) = match self {
+            Plane::U8(y) => (y.as_mut_ptr() as *mut c_void, width),
+            Plane::U16(y) => (y.as_mut_ptr() as *mut c_void, 2 * width),
+        };
+
+        unsafe {
+            (*pic).stride[0] = stride as i32;
+            (*pic).planes[0] = planes;
+        }
+    }
+
+    /// the plane falls out of scope
+    pub unsafe fn detach(pic: *mut x265_picture) {
+        unsafe {
+            (*pic).stride[0] = 0;
+            (*pic).planes[0] = ptr::null_mut();
+        }
+    }
+}
@@ -20,9 +20,16 @@ extern AVCodec ff_hevc_decoder;
 // extern AVCodecParser ff_hevc_parser;
 
 void hevc_init(int va_count);
+int hevc_max_bit_depth(void);
 void hevc_destroy(int va_count);
 double hevc_decode_nal_unit(int index, const unsigned char *data, size_t data_len, unsigned char *canvas, unsigned int _w, unsigned int _h, const unsigned char *alpha, unsigned char *bytes, const char *colourmap);
 
+// the highest luma bit depth handled, Main10/Main12 frames are rounded to 8 bits for the colourmaps
+int hevc_max_bit_depth(void)
+{
+    return 12;
+}
+
 void hevc_init(int va_count)
 {
     // the "standard" way
@@ -189,6 +196,33 @@ double hevc_decode_nal_unit(int index, const unsigned char *data, size_t data_le
             int h = avframe[index]->height;
             int stride = avframe[index]->linesize[0];
             const unsigned char *luma = avframe[index]->data[0];
+            unsigned char *luma8 = NULL;
+
+            // Main10/Main12: round the 16-bit samples to 8 bits for the colourmaps
+            if ((format == AV_PIX_FMT_GRAY10 || format == AV_PIX_FMT_GRAY12) && w == _w && h == _h)
+            {
+                int shift = (format == AV_PIX_FMT_GRAY10) ? 2 : 4;
+                int round = 1 << (shift - 1);
+
+                luma8 = malloc(w * h);
+
+                if (luma8 != NULL)
+                {
+                    for (int j = 0; j < h; j++)
+                    {
+                        const uint16_t *src = (const uint16_t *)(luma + j * stride);
+
+                        for (int i = 0; i < w; i++)
+                        {
+                            int sample = (src[i] + round) >> shift;
+                            luma8[j * w + i] = sample > 255 ? 255 : sample;
+                        }
+                    }
+
+                    luma = luma8;
+                    stride = w;
+                }
+            }
 
             if (w == _w && h == _h)
             {
@@ -267,6 +301,9 @@ double hevc_decode_nal_unit(int index, const unsigned char *data, size_t data_le
             }
             else
                 printf("[wasm hevc] canvas image dimensions %d x %d do not match the decoded image size, doing nothing\n", _w, _h);
+
+            if (luma8 != NULL)
+                free(luma8);
         }
 
         av_frame_unref(avframe[index]);
@@ -0,0 +1,544 @@
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::healpix;
+
+//HiPS tile sets are written into HIPSCACHE/<dataset id>/
+pub static HIPSCACHE: &'static str = "HIPSCACHE";
+
+//512x512 tiles
+const TILE_ORDER: u32 = 9;
+const TILE_WIDTH: usize = 1 << TILE_ORDER;
+
+//HiPS viewers expect the tiles to start at order 3
+const MIN_ORDER: u32 = 3;
+
+lazy_static! {
+    static ref HIPS_JOBS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    static ref HIPS_ERRORS: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HipsStatus {
+    Ready,
+    Running,
+    Failed(String),
+    Missing,
+}
+
+pub fn hips_directory(dataset_id: &str) -> PathBuf {
+    Path::new(HIPSCACHE).join(dataset_id.replace("/", "_"))
+}
+
+pub fn status(dataset_id: &str) -> HipsStatus {
+    if HIPS_JOBS.lock().contains(dataset_id) {
+        HipsStatus::Running
+    } else if hips_directory(dataset_id).join("properties").exists() {
+        HipsStatus::Ready
+    } else if let Some(err) = HIPS_ERRORS.lock().get(dataset_id) {
+        HipsStatus::Failed(err.clone())
+    } else {
+        HipsStatus::Missing
+    }
+}
+
+/// register a tile generation job, false if one is already running
+pub fn start_job(dataset_id: &str) -> bool {
+    HIPS_ERRORS.lock().remove(dataset_id);
+    HIPS_JOBS.lock().insert(dataset_id.to_string())
+}
+
+pub fn end_job(dataset_id: &str, result: Result<u32, String>) {
+    if let Err(err) = result {
+        println!("HiPS {}: {}", dataset_id, err);
+        HIPS_ERRORS.lock().insert(dataset_id.to_string(), err);
+    }
+
+    HIPS_JOBS.lock().remove(dataset_id);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Tan,
+    Sin,
+    Linear, //CAR or no projection code, treated as a plate carree around CRVAL
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frame {
+    Equatorial,
+    Galactic,
+}
+
+impl Frame {
+    fn name(&self) -> &'static str {
+        match self {
+            Frame::Equatorial => "equatorial",
+            Frame::Galactic => "galactic",
+        }
+    }
+}
+
+/// a celestial WCS (CRVAL/CRPIX + the CD matrix) of a 2-D image
+#[derive(Debug, Clone)]
+pub struct CelestialWcs {
+    pub crval1: f64,
+    pub crval2: f64,
+    pub crpix1: f64,
+    pub crpix2: f64,
+    pub cd: [[f64; 2]; 2],
+    pub projection: Projection,
+    pub frame: Frame,
+}
+
+impl CelestialWcs {
+    pub fn new(
+        ctype1: &str,
+        ctype2: &str,
+        crval: (f64, f64),
+        crpix: (f64, f64),
+        cd: [[f64; 2]; 2],
+    ) -> Option<CelestialWcs> {
+        let frame = if ctype1.starts_with("RA") && ctype2.starts_with("DEC") {
+            Frame::Equatorial
+        } else if ctype1.starts_with("GLON") && ctype2.starts_with("GLAT") {
+            Frame::Galactic
+        } else {
+            return None;
+        };
+
+        let projection = if ctype1.ends_with("-TAN") {
+            Projection::Tan
+        } else if ctype1.ends_with("-SIN") {
+            Projection::Sin
+        } else {
+            Projection::Linear
+        };
+
+        let det = cd[0][0] * cd[1][1] - cd[0][1] * cd[1][0];
+
+        if !det.is_finite() || det == 0.0 || !crval.0.is_finite() || !crval.1.is_finite() {
+            return None;
+        }
+
+        Some(CelestialWcs {
+            crval1: crval.0,
+            crval2: crval.1,
+            crpix1: crpix.0,
+            crpix2: crpix.1,
+            cd: cd,
+            projection: projection,
+            frame: frame,
+        })
+    }
+
+    /// the mean pixel size [deg]
+    pub fn pixel_scale(&self) -> f64 {
+        (self.cd[0][0] * self.cd[1][1] - self.cd[0][1] * self.cd[1][0])
+            .abs()
+            .sqrt()
+    }
+
+    /// 0-based image pixel -> (lon, lat) [deg]
+    pub fn pixel_to_world(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let dx = x + 1.0 - self.crpix1;
+        let dy = y + 1.0 - self.crpix2;
+
+        //intermediate world coordinates [deg]
+        let u = self.cd[0][0] * dx + self.cd[0][1] * dy;
+        let v = self.cd[1][0] * dx + self.cd[1][1] * dy;
+
+        if self.projection == Projection::Linear {
+            let lat = self.crval2 + v;
+
+            if lat.abs() > 90.0 {
+                return None;
+            }
+
+            return Some(((self.crval1 + u).rem_euclid(360.0), lat));
+        }
+
+        let (u, v) = (u.to_radians(), v.to_radians());
+        let r = (u * u + v * v).sqrt();
+
+        //the native latitude
+        let theta = match self.projection {
+            Projection::Tan => (1.0 / r).atan(),
+            _ => {
+                if r > 1.0 {
+                    return None;
+                }
+
+                r.acos()
+            }
+        };
+
+        //the native longitude (LONPOLE = 180 deg)
+        let phi = u.atan2(-v);
+
+        let (a0, d0) = (self.crval1.to_radians(), self.crval2.to_radians());
+
+        let sin_d = theta.sin() * d0.sin() - theta.cos() * d0.cos() * phi.cos();
+        let dec = sin_d.clamp(-1.0, 1.0).asin();
+
+        let ra = a0
+            + (theta.cos() * phi.sin())
+                .atan2(theta.sin() * d0.cos() + theta.cos() * d0.sin() * phi.cos());
+
+        Some((ra.to_degrees().rem_euclid(360.0), dec.to_degrees()))
+    }
+
+    /// (lon, lat) [deg] -> 0-based image pixel
+    pub fn world_to_pixel(&self, lon: f64, lat: f64) -> Option<(f64, f64)> {
+        let (u, v) = match self.projection {
+            Projection::Linear => {
+                let mut du = lon - self.crval1;
+
+                if du > 180.0 {
+                    du -= 360.0;
+                } else if du < -180.0 {
+                    du += 360.0;
+                }
+
+                (du, lat - self.crval2)
+            }
+            _ => {
+                let (a, d) = (lon.to_radians(), lat.to_radians());
+                let (a0, d0) = (self.crval1.to_radians(), self.crval2.to_radians());
+                let da = a - a0;
+
+                //the cosine of the angular distance from the reference point
+                let cos_c = d.sin() * d0.sin() + d.cos() * d0.cos() * da.cos();
+
+                //only the hemisphere facing the reference point is projected
+                if cos_c <= 0.0 {
+                    return None;
+                }
+
+                let x = d.cos() * da.sin();
+                let y = d.sin() * d0.cos() - d.cos() * d0.sin() * da.cos();
+
+                let scale = if self.projection == Projection::Tan {
+                    1.0 / cos_c
+                } else {
+                    1.0
+                };
+
+                ((x * scale).to_degrees(), (y * scale).to_degrees())
+            }
+        };
+
+        //invert the CD matrix
+        let det = self.cd[0][0] * self.cd[1][1] - self.cd[0][1] * self.cd[1][0];
+        let dx = (self.cd[1][1] * u - self.cd[0][1] * v) / det;
+        let dy = (-self.cd[1][0] * u + self.cd[0][0] * v) / det;
+
+        Some((dx + self.crpix1 - 1.0, dy + self.crpix2 - 1.0))
+    }
+}
+
+/// a stretched 2-D image (FITS orientation, the first row at the bottom)
+pub struct HipsSource<'a> {
+    pub luminance: &'a [u8],
+    pub mask: &'a [u8],
+    pub width: usize,
+    pub height: usize,
+    pub wcs: CelestialWcs,
+    pub title: String,
+}
+
+impl<'a> HipsSource<'a> {
+    fn is_valid(&self, x: i64, y: i64) -> bool {
+        x >= 0
+            && y >= 0
+            && (x as usize) < self.width
+            && (y as usize) < self.height
+            && self.mask[(y as usize) * self.width + (x as usize)] > 0
+    }
+
+    fn value(&self, x: i64, y: i64) -> f32 {
+        self.luminance[(y as usize) * self.width + (x as usize)] as f32
+    }
+
+    //bilinear interpolation, falling back on the nearest neighbour next to the masked pixels
+    fn sample(&self, x: f64, y: f64) -> Option<u8> {
+        let x0 = x.floor() as i64;
+        let y0 = y.floor() as i64;
+        let fx = (x - x0 as f64) as f32;
+        let fy = (y - y0 as f64) as f32;
+
+        if self.is_valid(x0, y0)
+            && self.is_valid(x0 + 1, y0)
+            && self.is_valid(x0, y0 + 1)
+            && self.is_valid(x0 + 1, y0 + 1)
+        {
+            let v = (1.0 - fx) * (1.0 - fy) * self.value(x0, y0)
+                + fx * (1.0 - fy) * self.value(x0 + 1, y0)
+                + (1.0 - fx) * fy * self.value(x0, y0 + 1)
+                + fx * fy * self.value(x0 + 1, y0 + 1);
+
+            return Some(v.round().clamp(0.0, 255.0) as u8);
+        }
+
+        let (xn, yn) = (x.round() as i64, y.round() as i64);
+
+        if self.is_valid(xn, yn) {
+            Some(self.luminance[(yn as usize) * self.width + (xn as usize)])
+        } else {
+            None
+        }
+    }
+}
+
+//a grey + alpha tile, the first row at the top
+type Tile = Vec<u8>;
+
+//the tile pixel (row, column) -> the sub-pixel index within a tile (Aladin convention:
+//the South corner at the top-left, the East one at the bottom-left)
+fn tile_subpixel(row: usize, col: usize) -> u64 {
+    healpix::spread_bits(row as u64) | (healpix::spread_bits(col as u64) << 1)
+}
+
+fn render_tile(source: &HipsSource, order: u32, tile: u64) -> Option<Tile> {
+    let mut data: Tile = vec![0; 2 * TILE_WIDTH * TILE_WIDTH];
+    let mut empty = true;
+
+    for row in 0..TILE_WIDTH {
+        for col in 0..TILE_WIDTH {
+            let ipix = (tile << (2 * TILE_ORDER)) | tile_subpixel(row, col);
+            let (lon, lat) = healpix::pix2ang(order + TILE_ORDER, ipix);
+
+            if let Some((x, y)) = source.wcs.world_to_pixel(lon, lat) {
+                if let Some(value) = source.sample(x, y) {
+                    let offset = 2 * (row * TILE_WIDTH + col);
+                    data[offset] = value;
+                    data[offset + 1] = 255;
+                    empty = false;
+                }
+            }
+        }
+    }
+
+    if empty { None } else { Some(data) }
+}
+
+//a parent tile is the 2x2 average of its four children
+fn merge_tiles(children: [Option<&Tile>; 4]) -> Option<Tile> {
+    let half = TILE_WIDTH / 2;
+    let mut data: Tile = vec![0; 2 * TILE_WIDTH * TILE_WIDTH];
+    let mut empty = true;
+
+    for row in 0..TILE_WIDTH {
+        for col in 0..TILE_WIDTH {
+            //the child quadrant, as in tile_subpixel
+            let child = match children[(row / half) | ((col / half) << 1)] {
+                Some(x) => x,
+                None => continue,
+            };
+
+            let (r, c) = (2 * (row % half), 2 * (col % half));
+            let mut sum = 0u32;
+            let mut count = 0u32;
+
+            for (dr, dc) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+                let offset = 2 * ((r + dr) * TILE_WIDTH + c + dc);
+
+                if child[offset + 1] > 0 {
+                    sum += child[offset] as u32;
+                    count += 1;
+                }
+            }
+
+            if count > 0 {
+                let offset = 2 * (row * TILE_WIDTH + col);
+                data[offset] = ((sum + count / 2) / count) as u8;
+                data[offset + 1] = 255;
+                empty = false;
+            }
+        }
+    }
+
+    if empty { None } else { Some(data) }
+}
+
+fn tile_path(dir: &Path, order: u32, tile: u64) -> PathBuf {
+    dir.join(format!("Norder{}", order))
+        .join(format!("Dir{}", (tile / 10000) * 10000))
+        .join(format!("Npix{}.png", tile))
+}
+
+fn write_tile(dir: &Path, order: u32, tile: u64, data: &Tile) -> Result<(), String> {
+    let path = tile_path(dir, order, tile);
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|err| format!("{}: {}", parent.display(), err))?;
+    }
+
+    let file =
+        std::fs::File::create(&path).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    let mut encoder = png::Encoder::new(
+        std::io::BufWriter::new(file),
+        TILE_WIDTH as u32,
+        TILE_WIDTH as u32,
+    );
+    encoder.set_color(png::ColorType::GrayscaleAlpha);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| {
+            writer.write_image_data(data)?;
+            writer.finish()
+        })
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+//the HiPS metadata read by Aladin-style viewers
+fn write_properties(
+    dir: &Path,
+    source: &HipsSource,
+    max_order: u32,
+    min_order: u32,
+) -> Result<(), String> {
+    let (ra, dec) = source
+        .wcs
+        .pixel_to_world(
+            (source.width as f64 - 1.0) / 2.0,
+            (source.height as f64 - 1.0) / 2.0,
+        )
+        .unwrap_or((source.wcs.crval1, source.wcs.crval2));
+
+    let fov = source.wcs.pixel_scale() * (source.width.max(source.height) as f64);
+
+    let properties = format!(
+        "creator_did = ivo://fitswebql/{}\n\
+         obs_title = {}\n\
+         dataproduct_type = image\n\
+         hips_version = 1.4\n\
+         hips_builder = fits_web_ql\n\
+         hips_release_date = {}\n\
+         hips_status = public master unclonable\n\
+         hips_frame = {}\n\
+         hips_order = {}\n\
+         hips_order_min = {}\n\
+         hips_tile_width = {}\n\
+         hips_tile_format = png\n\
+         hips_initial_ra = {}\n\
+         hips_initial_dec = {}\n\
+         hips_initial_fov = {}\n",
+        source.title.replace(" ", "_"),
+        source.title,
+        chrono::Utc::now().format("%Y-%m-%dT%H:%MZ"),
+        source.wcs.frame.name(),
+        max_order,
+        min_order,
+        TILE_WIDTH,
+        ra,
+        dec,
+        fov
+    );
+
+    let path = dir.join("properties");
+
+    std::fs::File::create(&path)
+        .and_then(|mut file| file.write_all(properties.as_bytes()))
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// reproject an image onto HEALPix tiles, writing a HiPS tile set into dir
+pub fn generate(source: &HipsSource, dir: &Path) -> Result<u32, String> {
+    let watch = Instant::now();
+
+    //the deepest order with tile pixels no larger than the image ones
+    let scale = source.wcs.pixel_scale();
+    let max_order = (MIN_ORDER..=healpix::MAX_ORDER - TILE_ORDER)
+        .find(|order| healpix::pixel_size(order + TILE_ORDER) <= scale)
+        .unwrap_or(healpix::MAX_ORDER - TILE_ORDER);
+
+    //find the tiles covered by the image, sampling it at a fraction of the tile size
+    let tile_size = healpix::pixel_size(max_order) / scale; //[image pixels]
+    let step = ((tile_size / 4.0) as usize).max(1);
+
+    let xs: Vec<usize> = (0..source.width)
+        .step_by(step)
+        .chain(std::iter::once(source.width - 1))
+        .collect();
+
+    let tiles: HashSet<u64> = (0..source.height)
+        .step_by(step)
+        .chain(std::iter::once(source.height - 1))
+        .collect::<Vec<usize>>()
+        .par_iter()
+        .flat_map_iter(|&y| {
+            xs.iter()
+                .filter(move |&&x| source.mask[y * source.width + x] > 0)
+                .filter_map(move |&x| source.wcs.pixel_to_world(x as f64, y as f64))
+                .map(|(lon, lat)| healpix::ang2pix(max_order, lon, lat))
+                .collect::<Vec<u64>>()
+        })
+        .collect();
+
+    println!(
+        "HiPS {}: pixel scale {:.3e} deg, max order {}, {} tile(s)",
+        source.title,
+        scale,
+        max_order,
+        tiles.len()
+    );
+
+    if tiles.is_empty() {
+        return Err(String::from("the image does not contain any valid pixels"));
+    }
+
+    std::fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+
+    //the deepest order: reproject the image
+    let mut current: HashMap<u64, Tile> = tiles
+        .into_par_iter()
+        .filter_map(|tile| render_tile(source, max_order, tile).map(|data| (tile, data)))
+        .collect();
+
+    //then go up the hierarchy
+    let mut order = max_order;
+
+    loop {
+        current
+            .par_iter()
+            .map(|(tile, data)| write_tile(dir, order, *tile, data))
+            .collect::<Result<Vec<()>, String>>()?;
+
+        if order == MIN_ORDER {
+            break;
+        }
+
+        let parents: HashSet<u64> = current.keys().map(|tile| tile >> 2).collect();
+
+        current = parents
+            .into_par_iter()
+            .filter_map(|parent| {
+                let children = [0, 1, 2, 3].map(|k| current.get(&((parent << 2) | k)));
+                merge_tiles(children).map(|data| (parent, data))
+            })
+            .collect();
+
+        order -= 1;
+    }
+
+    write_properties(dir, source, max_order, MIN_ORDER)?;
+
+    println!(
+        "HiPS {}: orders {}-{} written to {} in {:?}",
+        source.title,
+        MIN_ORDER,
+        max_order,
+        dir.display(),
+        watch.elapsed()
+    );
+
+    Ok(max_order)
+}
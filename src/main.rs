@@ -70,7 +70,7 @@ use actix_web_actors::ws;
 #[cfg(feature = "jvo")]
 use flexi_logger::FileSpec;
 
-use percent_encoding::percent_decode;
+use percent_encoding::{NON_ALPHANUMERIC, percent_decode, utf8_percent_encode};
 use tar::{Builder, Header};
 use uuid::Uuid;
 
@@ -97,10 +97,36 @@ use std::collections::{HashMap, HashSet};
 
 use parking_lot::RwLock;
 
+mod abr;
+mod arithmetic;
+#[cfg(feature = "av1")]
+mod av1;
+mod catalogue;
+mod colourmap;
+mod composite;
+mod cutout;
+mod download;
+mod export;
 mod fits;
+mod hdf5;
+mod healpix;
+mod hevc;
+mod hips;
 mod kalman;
 mod molecule;
+mod noise;
+mod photometry;
+mod pyramid;
+mod registry;
+mod regrid;
+mod s3;
 mod server;
+mod snapshot;
+mod stretch;
+mod table;
+mod thumbnail;
+mod views;
+mod zipstream;
 
 use crate::kalman::KalmanFilter;
 use crate::molecule::Molecule;
@@ -185,6 +211,7 @@ pub struct WsViewport {
 struct WsSessionState {
     addr: Addr<server::SessionServer>,
     home_dir: Option<std::path::PathBuf>,
+    admin_token: Option<String>,
 }
 
 pub struct UserParams {
@@ -292,6 +319,7 @@ struct UserSession {
     param: *mut x265_param,   //HEVC param
     enc: *mut x265_encoder,   //HEVC context
     pic: *mut x265_picture,   //HEVC picture
+    x265: hevc::Api,          //the HEVC encoder for the luma bit depth
     //config: EncoderConfig,
     width: u32,
     height: u32,
@@ -304,6 +332,12 @@ struct UserSession {
     video_timestamp: std::time::Instant,
     bitrate: i32,
     kf: KalmanFilter,
+    abr: abr::RateController,        //adaptive bitrate control
+    codec: Option<fits::Codec>,      //the client codec preference
+    bit_depth: u32,                  //the luma bit depth (8, 10 or 12)
+    composite: Vec<composite::Tint>, //composite video channel colours (RGB by default)
+    #[cfg(feature = "av1")]
+    av1: Option<av1::VideoEncoder>, //AV1 video encoder
 }
 
 impl UserSession {
@@ -369,6 +403,7 @@ impl UserSession {
             param: ptr::null_mut(),
             enc: ptr::null_mut(),
             pic: ptr::null_mut(),
+            x265: hevc::Api::default(),
             //config: EncoderConfig::default(),
             width: 0,
             height: 0,
@@ -381,6 +416,12 @@ impl UserSession {
             video_timestamp: std::time::Instant::now(),
             bitrate: 1000,
             kf: KalmanFilter::default(),
+            abr: abr::RateController::default(),
+            codec: None,
+            bit_depth: 8,
+            composite: Vec::new(),
+            #[cfg(feature = "av1")]
+            av1: None,
         };
 
         println!("allocating a new websocket session for {}", id[0]);
@@ -397,15 +438,15 @@ impl Drop for UserSession {
 
         unsafe {
             if !self.param.is_null() {
-                x265_param_free(self.param);
+                self.x265.param_free(self.param);
             }
 
             if !self.enc.is_null() {
-                x265_encoder_close(self.enc);
+                self.x265.encoder_close(self.enc);
             }
 
             if !self.pic.is_null() {
-                x265_picture_free(self.pic);
+                self.x265.picture_free(self.pic);
             }
         }
     }
@@ -421,7 +462,9 @@ impl Actor for UserSession {
         let addr = ctx.address();
 
         self.addr.do_send(server::Connect {
-            addr: addr.recipient(),
+            addr: addr.clone().recipient(),
+            kick: addr.clone().recipient(),
+            relay: addr.recipient(),
             dataset_id: self.dataset_id[0].clone(),
             id: self.session_id,
         });
@@ -488,6 +531,172 @@ impl Handler<server::WsMessage> for UserSession {
     }
 }
 
+/// close the websocket on request from the SessionServer (i.e. an administrator)
+impl Handler<server::Kick> for UserSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: server::Kick, ctx: &mut Self::Context) {
+        println!(
+            "closing a websocket session for {}/{}: {}",
+            self.dataset_id[0], self.session_id, msg.reason
+        );
+
+        ctx.text("[close]");
+        ctx.stop();
+    }
+}
+
+/// pass "follow me" notifications and the presenter's view state to the websocket
+impl Handler<server::Relay> for UserSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: server::Relay, ctx: &mut Self::Context) {
+        ctx.text(msg.text);
+    }
+}
+
+impl UserSession {
+    //the first of the session's datasets that is still queued or loading
+    fn loading_dataset(&self) -> Option<String> {
+        self.dataset_id
+            .iter()
+            .find(|id| match registry::get_state(id) {
+                Some(state) => !state.is_final(),
+                None => false,
+            })
+            .cloned()
+    }
+
+    /// [follow] action=present|join|leave|handover&to=<session id>
+    fn follow_request(&self, text: &str) {
+        let (action, to) = scan_fmt_some!(
+            &text.replace("&", " "),
+            "[follow] action={} to={}",
+            String,
+            String
+        );
+
+        let action = match action.as_deref() {
+            Some("present") => server::FollowAction::Present,
+            Some("join") => server::FollowAction::Join,
+            Some("leave") => server::FollowAction::Leave,
+            Some("handover") => match to.and_then(|x| Uuid::parse_str(&x).ok()) {
+                Some(to) => server::FollowAction::HandOver(to),
+                None => {
+                    println!("[follow] handover: a missing or invalid session id");
+                    return;
+                }
+            },
+            _ => {
+                println!("[follow] unknown action: {}", text);
+                return;
+            }
+        };
+
+        self.addr.do_send(server::Follow {
+            id: self.session_id,
+            dataset_id: self.dataset_id[0].clone(),
+            action: action,
+        });
+    }
+
+    /// [follow_state] <JSON view state> or [follow_cursor] <JSON cursor position> from the presenter
+    fn follow_relay(&self, text: &str, prefix: &str, msg_type: &str) {
+        let payload: serde_json::Value = match serde_json::from_str(text[prefix.len()..].trim()) {
+            Ok(x) => x,
+            Err(err) => {
+                println!("{} invalid JSON: {}", prefix, err);
+                return;
+            }
+        };
+
+        let msg = json!({
+            "type" : msg_type,
+            "session" : self.session_id.to_string(),
+            "data" : payload,
+        })
+        .to_string();
+
+        self.addr.do_send(server::FollowRelay {
+            id: self.session_id,
+            dataset_id: self.dataset_id[0].clone(),
+            message: msg,
+            retain: msg_type == "follow_state",
+        });
+    }
+
+    /// [codec] name=av1|hevc|vp9|auto&bits=8|10|12, replies with the codec and the luma bit depth actually in use
+    fn codec_request(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let (name, bits) = scan_fmt_some!(
+            &text.replace("&", " "),
+            "[codec] name={} bits={}",
+            String,
+            u32
+        );
+
+        self.codec = match name.as_deref() {
+            #[cfg(feature = "av1")]
+            Some("av1") => Some(fits::Codec::AV1),
+            Some("hevc") => Some(fits::Codec::HEVC),
+            Some("vp9") => Some(fits::Codec::VPX),
+            _ => None,
+        };
+
+        let codec = match self.codec {
+            #[cfg(feature = "av1")]
+            Some(fits::Codec::AV1) => "av1",
+            Some(fits::Codec::HEVC) => "hevc",
+            Some(fits::Codec::VPX) => "vp9",
+            None => "auto",
+        };
+
+        //10/12-bit luma: HEVC Main10/Main12, VP9 profile 2 or AV1
+        self.bit_depth = fits::supported_bit_depth(bits.unwrap_or(8));
+
+        println!(
+            "[codec] requested: {:?}, using: {} ({}-bit)",
+            name, codec, self.bit_depth
+        );
+
+        let msg = json!({
+            "type" : "codec",
+            "codec" : codec,
+            "bits" : self.bit_depth,
+        });
+
+        ctx.text(msg.to_string());
+    }
+
+    /// [composite] channels=[{"colour":"#ff0000","weight":1.0}, ...], the composite video channel colours
+    fn composite_request(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let channels = text["[composite]".len()..]
+            .trim()
+            .trim_start_matches("channels=");
+
+        let msg = match composite::parse_tints(channels) {
+            Ok(tints) => {
+                println!("[composite] {} channel colour(s)", tints.len());
+                self.composite = tints;
+
+                json!({
+                    "type" : "composite",
+                    "channels" : self.composite.len(),
+                })
+            }
+            Err(err) => {
+                println!("[composite] {}", err);
+
+                json!({
+                    "type" : "composite",
+                    "error" : err,
+                })
+            }
+        };
+
+        ctx.text(msg.to_string());
+    }
+}
+
 // Handler for ws::Message messages
 impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
     fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
@@ -529,18 +738,81 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                     };
                 }
 
+                //"follow me" collaborative sessions
+                if text.starts_with("[follow]") {
+                    self.follow_request(&text);
+                    return;
+                }
+
+                if text.starts_with("[follow_state]") {
+                    self.follow_relay(&text, "[follow_state]", "follow_state");
+                    return;
+                }
+
+                if text.starts_with("[follow_cursor]") {
+                    self.follow_relay(&text, "[follow_cursor]", "follow_cursor");
+                    return;
+                }
+
+                if text.starts_with("[codec]") {
+                    self.codec_request(&text, ctx);
+                    return;
+                }
+
+                if text.starts_with("[composite]") {
+                    self.composite_request(&text, ctx);
+                    return;
+                }
+
+                if text.starts_with("[video_ack]") {
+                    let (seq_id, decode) = scan_fmt_some!(
+                        &text.replace("&", " "),
+                        "[video_ack] seq_id={} decode={}",
+                        i32,
+                        f64
+                    );
+
+                    if let Some(seq_id) = seq_id {
+                        self.abr.ack(seq_id, decode.unwrap_or(0.0));
+                    }
+
+                    return;
+                }
+
+                //the dataset requests wait (asynchronously) for a load in progress, then get re-handled
+                if !(&text).contains("[heartbeat]") {
+                    if let Some(dataset_id) = self.loading_dataset() {
+                        ctx.spawn(
+                            async move {
+                                registry::wait_ready(
+                                    &dataset_id,
+                                    std::time::Duration::from_millis(LONG_POLL_TIMEOUT),
+                                )
+                                .await
+                            }
+                            .into_actor(self)
+                            .map(move |_, act, ctx| {
+                                StreamHandler::handle(act, Ok(ws::Message::Text(text)), ctx);
+                            }),
+                        );
+
+                        return;
+                    }
+                }
+
                 if (&text).contains("[init_video]") {
                     //println!("{}", text.replace("&", " "));
-                    let (frame, view, ref_freq, fps, seq_id, target_bitrate, timestamp) = scan_fmt_some!(
+                    let (frame, view, ref_freq, fps, seq_id, target_bitrate, timestamp, scale) = scan_fmt_some!(
                         &text.replace("&", " "),
-                        "[init_video] frame={} view={} ref_freq={} fps={} seq_id={} bitrate={} timestamp={}",
+                        "[init_video] frame={} view={} ref_freq={} fps={} seq_id={} bitrate={} timestamp={} scale={}",
                         String,
                         String,
                         String,
                         String,
                         i32,
                         i32,
-                        String
+                        String,
+                        f32
                     );
 
                     let frame = match frame {
@@ -585,7 +857,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                     };
 
                     let target_bitrate = match target_bitrate {
-                        Some(x) => num::clamp(x, 100, 10000),
+                        Some(x) => num::clamp(x, abr::MIN_BITRATE, abr::MAX_BITRATE),
                         _ => 1000,
                     };
 
@@ -597,12 +869,26 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                         _ => 0.0,
                     };
 
+                    //a reduced resolution requested by the adaptive bitrate control
+                    let scale = match scale {
+                        Some(x) => num::clamp(x, 0.25, 1.0),
+                        _ => 1.0,
+                    };
+
                     println!(
-                        "[init_video] frame:{} is_composite:{} ref_freq:{} fps:{} seq_id:{} target_bitrate:{} timestamp:{}",
-                        frame, is_composite, ref_freq, fps, seq_id, target_bitrate, timestamp
+                        "[init_video] frame:{} is_composite:{} ref_freq:{} fps:{} seq_id:{} target_bitrate:{} timestamp:{} scale:{}",
+                        frame,
+                        is_composite,
+                        ref_freq,
+                        fps,
+                        seq_id,
+                        target_bitrate,
+                        timestamp,
+                        scale
                     );
 
                     self.kf = KalmanFilter::new(frame);
+                    self.abr = abr::RateController::new(target_bitrate, scale);
 
                     self.video_frame = frame;
                     self.video_ref_freq = ref_freq;
@@ -638,37 +924,35 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
 
                     //alloc HEVC params
                     if self.param.is_null() {
-                        self.param = unsafe { x265_param_alloc() };
-                        unsafe {
-                            //x265_param_default_preset(self.param, CString::new("ultrafast").unwrap().as_ptr(), CString::new("fastdecode").unwrap().as_ptr());
+                        //Main10/Main12 for the luma, composite (I444) videos stay 8-bit
+                        self.x265 = if self.dataset_id.len() == 1 || !is_composite {
+                            hevc::Api::new(self.bit_depth)
+                        } else {
+                            hevc::Api::new(8)
+                        };
 
-                            let tune = CString::new("zerolatency").unwrap();
+                        //x265_param_default_preset(self.param, CString::new("ultrafast").unwrap().as_ptr(), CString::new("fastdecode").unwrap().as_ptr());
 
-                            /*let tune = if fits.telescope.contains("kiso") {
-                                CString::new("grain").unwrap()
-                            } else {
-                                CString::new("zerolatency").unwrap()
-                            };*/
-
-                            if self.dataset_id.len() == 1 || !is_composite {
-                                let preset = CString::new("superfast").unwrap();
-                                x265_param_default_preset(
-                                    self.param,
-                                    preset.as_ptr(),
-                                    tune.as_ptr(),
-                                );
-                            } else {
-                                let preset = CString::new("ultrafast").unwrap();
-                                x265_param_default_preset(
-                                    self.param,
-                                    preset.as_ptr(),
-                                    tune.as_ptr(),
-                                );
-                            }
+                        let tune = "zerolatency";
 
-                            (*self.param).fpsNum = fps as u32;
-                            (*self.param).fpsDenom = 1;
+                        /*let tune = if fits.telescope.contains("kiso") {
+                            "grain"
+                        } else {
+                            "zerolatency"
+                        };*/
+
+                        self.param = if self.dataset_id.len() == 1 || !is_composite {
+                            self.x265.param_alloc("superfast", tune)
+                        } else {
+                            self.x265.param_alloc("ultrafast", tune)
                         };
+
+                        if !self.param.is_null() {
+                            unsafe {
+                                (*self.param).fpsNum = fps as u32;
+                                (*self.param).fpsDenom = 1;
+                            };
+                        }
                     }
 
                     let mut ret = unsafe {
@@ -748,6 +1032,16 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                             }
                         }
 
+                        if scale < 1.0 {
+                            w = (((w as f32) * scale) as u32).max(32);
+                            h = (((h as f32) * scale) as u32).max(32);
+
+                            println!(
+                                "adaptive bitrate: downscaling the video to {}x{} (x{})",
+                                w, h, scale
+                            );
+                        }
+
                         //get the alpha channel
                         let alpha_frame = {
                             let watch = Instant::now();
@@ -775,12 +1069,38 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                             compressed_alpha
                         };
 
+                        //AV1 only handles the luma, composite videos stay with HEVC
+                        #[cfg(feature = "av1")]
+                        {
+                            self.av1 = match self.codec {
+                                Some(fits::Codec::AV1)
+                                    if self.dataset_id.len() == 1 || !is_composite =>
+                                {
+                                    av1::VideoEncoder::new(
+                                        w,
+                                        h,
+                                        fps,
+                                        target_bitrate,
+                                        self.bit_depth,
+                                    )
+                                }
+                                _ => None,
+                            };
+                        }
+
+                        #[cfg(feature = "av1")]
+                        let video_codec = if self.av1.is_some() { "AV1" } else { "HEVC" };
+
+                        #[cfg(not(feature = "av1"))]
+                        let video_codec = "HEVC";
+
                         //send the video size + alpha channel as JSON
                         let resolution = json!({
                             "type" : "init_video",
                             "width" : w,
                             "height" : h,
                             "alpha" : alpha_frame,
+                            "codec" : video_codec,
                         });
 
                         ctx.text(resolution.to_string());
@@ -795,7 +1115,6 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                 (*self.param).internalCsp = X265_CSP_I400 as i32;
                             }
 
-                            (*self.param).internalBitDepth = 8;
                             (*self.param).sourceWidth = w as i32;
                             (*self.param).sourceHeight = h as i32;
 
@@ -805,12 +1124,12 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                         };
 
                         if self.pic.is_null() {
-                            self.pic = unsafe { x265_picture_alloc() };
+                            self.pic = unsafe { self.x265.picture_alloc() };
                         }
 
                         if self.enc.is_null() {
-                            self.enc = unsafe { x265_encoder_open(self.param) }; //x265_encoder_open_160 for x265 2.8
-                            unsafe { x265_picture_init(self.param, self.pic) };
+                            self.enc = unsafe { self.x265.encoder_open(self.param) }; //x265_encoder_open_160 for x265 2.8
+                            unsafe { self.x265.picture_init(self.param, self.pic) };
                         }
 
                         self.width = w;
@@ -897,21 +1216,26 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
 
                     self.streaming = false;
 
+                    #[cfg(feature = "av1")]
+                    {
+                        self.av1 = None;
+                    }
+
                     unsafe { vpx_codec_destroy(&mut self.ctx) };
 
                     unsafe {
                         if !self.param.is_null() {
-                            x265_param_free(self.param);
+                            self.x265.param_free(self.param);
                             self.param = ptr::null_mut();
                         }
 
                         if !self.enc.is_null() {
-                            x265_encoder_close(self.enc);
+                            self.x265.encoder_close(self.enc);
                             self.enc = ptr::null_mut();
                         }
 
                         if !self.pic.is_null() {
-                            x265_picture_free(self.pic);
+                            self.x265.picture_free(self.pic);
                             self.pic = ptr::null_mut();
                         }
                     }
@@ -1037,8 +1361,15 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                 _ => 0.0,
                             };
 
+                            //csv (default), votable or fits
+                            let format = match msg["format"].as_str() {
+                                Some(s) => table::SpectrumFormat::from_name(s)
+                                    .unwrap_or(table::SpectrumFormat::Csv),
+                                _ => table::SpectrumFormat::Csv,
+                            };
+
                             println!(
-                                "type: {}, ra: {}, dec: {}, x1: {}, x2: {}, y1: {}, y2: {}, frame_start: {}, frame_end: {}, ref_freq: {}, beam: {:?}, intensity: {:?}, rest: {}, Δv: {}",
+                                "type: {}, ra: {}, dec: {}, x1: {}, x2: {}, y1: {}, y2: {}, frame_start: {}, frame_end: {}, ref_freq: {}, beam: {:?}, intensity: {:?}, rest: {}, Δv: {}, format: {:?}",
                                 msg_type,
                                 ra,
                                 dec,
@@ -1052,42 +1383,71 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                 beam,
                                 intensity,
                                 rest,
-                                delta_v
+                                delta_v,
+                                format
                             );
 
                             if fits.has_data {
-                                match fits.get_csv_spectrum(
-                                    &ra,
-                                    &dec,
-                                    x1 as i32,
-                                    y1 as i32,
-                                    x2 as i32,
-                                    y2 as i32,
-                                    beam,
-                                    intensity,
-                                    frame_start,
-                                    frame_end,
-                                    ref_freq,
-                                    delta_v,
-                                    rest,
-                                    &self.pool,
-                                ) {
-                                    Some(csv) => {
-                                        let data = csv.as_bytes();
+                                let export = match format {
+                                    table::SpectrumFormat::Csv => fits
+                                        .get_csv_spectrum(
+                                            &ra,
+                                            &dec,
+                                            x1 as i32,
+                                            y1 as i32,
+                                            x2 as i32,
+                                            y2 as i32,
+                                            beam,
+                                            intensity,
+                                            frame_start,
+                                            frame_end,
+                                            ref_freq,
+                                            delta_v,
+                                            rest,
+                                            &self.pool,
+                                        )
+                                        .map(|csv| csv.into_bytes()),
+                                    _ => fits
+                                        .get_spectrum_table(
+                                            &ra,
+                                            &dec,
+                                            x1 as i32,
+                                            y1 as i32,
+                                            x2 as i32,
+                                            y2 as i32,
+                                            beam,
+                                            intensity,
+                                            frame_start,
+                                            frame_end,
+                                            ref_freq,
+                                            delta_v,
+                                            rest,
+                                            &self.pool,
+                                        )
+                                        .map(|spectrum| match format {
+                                            table::SpectrumFormat::VoTable => {
+                                                spectrum.to_votable().into_bytes()
+                                            }
+                                            _ => spectrum.to_fits(),
+                                        }),
+                                };
+
+                                match export {
+                                    Some(data) => {
                                         let original_size = data.len();
 
                                         let compressed_csv = lz4_compress::compress(&data);
                                         let compressed_size = compressed_csv.len();
 
                                         println!(
-                                            "CSV UTF-8 length: {} bytes; after LZ4 compression: {} bytes",
-                                            original_size, compressed_size
+                                            "{:?} spectrum length: {} bytes; after LZ4 compression: {} bytes",
+                                            format, original_size, compressed_size
                                         );
 
                                         let ws_csv = WsCSV {
                                             ts: timestamp as f32,
                                             seq_id: 0,
-                                            msg_type: 6,
+                                            msg_type: format.msg_type(),
                                             original_size: original_size as u32,
                                             csv: compressed_csv,
                                         };
@@ -1117,6 +1477,134 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                     }
                 }
 
+                //region photometry, {"type":"photometry", x1, y1, x2, y2, beam, annulus_inner,
+                //annulus_outer, frame_start, frame_end, ref_freq, export}
+                if (&text).contains("\"photometry\"") {
+                    let msg: serde_json::Value = match serde_json::from_str(&text) {
+                        Ok(x) => x,
+                        Err(err) => {
+                            println!("{}", err);
+                            return;
+                        }
+                    };
+
+                    let unavailable = json!({
+                        "type" : "photometry",
+                        "message" : "unavailable",
+                    });
+
+                    let dataset = match DATASETS.read().get(&self.dataset_id[0]) {
+                        Some(x) => x.clone(),
+                        None => {
+                            ctx.text(unavailable.to_string());
+                            return;
+                        }
+                    };
+
+                    let (width, height) = match dataset.try_read() {
+                        Some(fits) if !fits.is_dummy => {
+                            *fits.timestamp.write() = SystemTime::now();
+                            (fits.width, fits.height)
+                        }
+                        _ => {
+                            ctx.text(unavailable.to_string());
+                            return;
+                        }
+                    };
+
+                    let timestamp = msg["timestamp"].as_f64().unwrap_or(0.0);
+
+                    let x1 = msg["x1"].as_i64().unwrap_or(0) as i32;
+                    let y1 = msg["y1"].as_i64().unwrap_or(0) as i32;
+                    let x2 = msg["x2"].as_i64().unwrap_or(width as i64 - 1) as i32;
+                    let y2 = msg["y2"].as_i64().unwrap_or(height as i64 - 1) as i32;
+
+                    let beam = match msg["beam"].as_str() {
+                        Some("circle") => fits::Beam::Circle,
+                        _ => fits::Beam::Square,
+                    };
+
+                    //the background annulus radii [px]
+                    let inner = msg["annulus_inner"].as_f64();
+                    let outer = msg["annulus_outer"].as_f64();
+
+                    let annulus = match (inner, outer) {
+                        (Some(inner), Some(outer)) if inner >= 0.0 && outer > inner => {
+                            Some(photometry::Annulus {
+                                inner: inner,
+                                outer: outer,
+                            })
+                        }
+                        _ => None,
+                    };
+
+                    let frame_start = msg["frame_start"].as_f64().unwrap_or(0.0);
+                    let frame_end = msg["frame_end"].as_f64().unwrap_or(0.0);
+                    let ref_freq = msg["ref_freq"].as_f64().unwrap_or(0.0);
+                    let export = msg["export"].as_bool().unwrap_or(false);
+
+                    println!(
+                        "[photometry] x1: {}, y1: {}, x2: {}, y2: {}, beam: {:?}, annulus: {:?}, frame_start: {}, frame_end: {}, ref_freq: {}, export: {}",
+                        x1, y1, x2, y2, beam, annulus, frame_start, frame_end, ref_freq, export
+                    );
+
+                    //measure every channel in a worker thread, the session keeps handling messages
+                    ctx.spawn(
+                        async move {
+                            web::block(move || {
+                                dataset.read().get_photometry(
+                                    x1,
+                                    y1,
+                                    x2,
+                                    y2,
+                                    beam,
+                                    annulus,
+                                    frame_start,
+                                    frame_end,
+                                    ref_freq,
+                                )
+                            })
+                            .await
+                        }
+                        .into_actor(self)
+                        .map(move |result, _, ctx| {
+                            let result = match result {
+                                Ok(Some(x)) => x,
+                                _ => {
+                                    ctx.text(unavailable.to_string());
+                                    return;
+                                }
+                            };
+
+                            ctx.text(result.to_json());
+
+                            //the CSV export
+                            if export {
+                                let data = result.to_csv().into_bytes();
+                                let original_size = data.len();
+
+                                let ws_csv = WsCSV {
+                                    ts: timestamp as f32,
+                                    seq_id: 0,
+                                    msg_type: 10,
+                                    original_size: original_size as u32,
+                                    csv: lz4_compress::compress(&data),
+                                };
+
+                                let config =
+                                    Configuration::default().disable_preallocation_size_limit();
+                                match wincode::config::serialize(&ws_csv, config) {
+                                    Ok(bin) => ctx.binary(bin),
+                                    Err(err) => println!(
+                                        "error serializing a WebSocket photometry export response: {}",
+                                        err
+                                    ),
+                                }
+                            }
+                        }),
+                    );
+                }
+
                 if (&text).contains("[spectrum]") {
                     //println!("{}", text.replace("&", " "));
                     let (
@@ -1349,9 +1837,17 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                         }
 
                         if image {
-                            match fits
-                                .get_viewport(x1, y1, x2, y2, &self.user, self.wasm, &self.pool)
-                            {
+                            match fits.get_viewport(
+                                x1,
+                                y1,
+                                x2,
+                                y2,
+                                &self.user,
+                                self.wasm,
+                                self.codec,
+                                self.bit_depth,
+                                &self.pool,
+                            ) {
                                 Some((width, height, frame, alpha, identifier)) => {
                                     //send a binary response message (serialize a structure to a binary stream)
                                     let ws_viewport = WsViewport {
@@ -1951,38 +2447,130 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                     compressed_alpha
                                 };
 
-                                //I420
-                                let stride_u = raw.stride[1];
-                                let stride_v = raw.stride[2];
-                                let count = stride_u * stride_v;
-
-                                let u: &[u8] = &vec![128; count as usize];
-                                let v: &[u8] = &vec![128; count as usize];
+                                //10/12-bit luma (VP9 profile 2 or AV1) or an AV1 image, otherwise the 8-bit VP9 below
+                                let mut still: Option<(Vec<u8>, &str)> = None;
 
-                                raw.planes[0] = unsafe { mem::transmute(y.as_ptr()) };
-                                raw.planes[1] = unsafe { mem::transmute(u.as_ptr()) };
-                                raw.planes[2] = unsafe { mem::transmute(v.as_ptr()) };
+                                if self.bit_depth > 8 {
+                                    //re-do the luma at 10/12 bits
+                                    let y = fits.pixels_to_luminance_hbd(
+                                        &user.pixels,
+                                        &user.mask,
+                                        user.pmin,
+                                        user.pmax,
+                                        user.lmin,
+                                        user.lmax,
+                                        user.black,
+                                        user.white,
+                                        user.median,
+                                        user.sensitivity,
+                                        user.ratio_sensitivity,
+                                        &user.flux,
+                                        self.bit_depth,
+                                        &self.pool,
+                                    );
 
-                                //a workaround around a bug in libvpx triggered when h > w
-                                raw.stride[0] = if w > h { w as i32 } else { h as i32 };
+                                    let mut dst = vec![0; (w as usize) * (h as usize)];
+                                    fits.resize_and_invert_u16(
+                                        &y,
+                                        &mut dst,
+                                        w,
+                                        h,
+                                        libyuv_FilterMode_kFilterBox,
+                                    );
 
-                                //let mut cfg = vpx_codec_enc_cfg::default();
-                                let mut cfg = vpx_codec_enc_config_init();
-                                let mut ret = unsafe {
-                                    vpx_codec_enc_config_default(vpx_codec_vp9_cx(), &mut cfg, 0)
-                                };
+                                    still = match self.codec {
+                                        #[cfg(feature = "av1")]
+                                        Some(fits::Codec::AV1) => {
+                                            av1::encode_still_hbd(w, h, &dst, self.bit_depth)
+                                                .map(|frame| (frame, "AV1"))
+                                        }
+                                        //still images are VP9 with HEVC too
+                                        Some(fits::Codec::HEVC) | Some(fits::Codec::VPX) | None => {
+                                            fits::encode_vpx_still_hbd(w, h, &dst, self.bit_depth)
+                                                .map(|frame| (frame, "VP9"))
+                                        }
+                                    };
 
-                                if ret != VPX_CODEC_OK {
-                                    println!("VP9 image frame error: default Configuration failed");
+                                    if still.is_none() {
+                                        println!(
+                                            "{}-bit image frame error, falling back to 8 bits",
+                                            self.bit_depth
+                                        );
+                                    }
+                                }
 
-                                    //release the image
-                                    unsafe { vpx_img_free(&mut raw) };
+                                #[cfg(feature = "av1")]
+                                if still.is_none() && self.codec == Some(fits::Codec::AV1) {
+                                    still = av1::encode_still(w, h, &y).map(|frame| (frame, "AV1"));
 
-                                    return;
+                                    if still.is_none() {
+                                        println!("AV1 image frame error, falling back to VP9");
+                                    }
                                 }
 
-                                //a workaround around a bug in libvpx triggered when h > w
-                                if w > h {
+                                if let Some((image_frame, identifier)) = still {
+                                    unsafe { vpx_img_free(&mut raw) };
+
+                                    let ws_image = WsImage {
+                                        ts: timestamp as f32,
+                                        seq_id: 0,
+                                        msg_type: 2,
+                                        identifier: String::from(identifier),
+                                        width: w,
+                                        height: h,
+                                        image: image_frame,
+                                        alpha: alpha_frame,
+                                    };
+
+                                    // remove the preallocation limit
+                                    let config =
+                                        Configuration::default().disable_preallocation_size_limit();
+                                    match wincode::config::serialize(&ws_image, config) {
+                                        Ok(bin) => {
+                                            println!("binary length: {}", bin.len());
+                                            ctx.binary(bin);
+                                        }
+                                        Err(err) => println!(
+                                            "error serializing a WebSocket image response: {}",
+                                            err
+                                        ),
+                                    }
+
+                                    return;
+                                }
+
+                                //I420
+                                let stride_u = raw.stride[1];
+                                let stride_v = raw.stride[2];
+                                let count = stride_u * stride_v;
+
+                                let u: &[u8] = &vec![128; count as usize];
+                                let v: &[u8] = &vec![128; count as usize];
+
+                                raw.planes[0] = unsafe { mem::transmute(y.as_ptr()) };
+                                raw.planes[1] = unsafe { mem::transmute(u.as_ptr()) };
+                                raw.planes[2] = unsafe { mem::transmute(v.as_ptr()) };
+
+                                //a workaround around a bug in libvpx triggered when h > w
+                                raw.stride[0] = if w > h { w as i32 } else { h as i32 };
+
+                                //let mut cfg = vpx_codec_enc_cfg::default();
+                                let mut cfg = vpx_codec_enc_config_init();
+                                let mut ret = unsafe {
+                                    vpx_codec_enc_config_default(vpx_codec_vp9_cx(), &mut cfg, 0)
+                                };
+
+                                if ret != VPX_CODEC_OK {
+                                    println!("VP9 image frame error: default Configuration failed");
+
+                                    //release the image
+                                    unsafe { vpx_img_free(&mut raw) };
+
+                                    return;
+                                }
+
+                                //a workaround around a bug in libvpx triggered when h > w
+                                if w > h {
                                     cfg.g_w = w;
                                     cfg.g_h = h;
                                 } else {
@@ -2175,7 +2763,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                     };
 
                     let target_bitrate = match target_bitrate {
-                        Some(x) => num::clamp(x, 100, 10000),
+                        Some(x) => num::clamp(x, abr::MIN_BITRATE, abr::MAX_BITRATE),
                         _ => 1000,
                     };
 
@@ -2220,6 +2808,28 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                     self.video_fps = fps;
                     self.video_seq_id = seq_id;
                     self.video_timestamp = std::time::Instant::now(); //timestamp;
+
+                    //adapt the client bitrate to the send queue depth and the client decode time
+                    let decision = self.abr.next_frame(target_bitrate, fps, keyframe);
+
+                    if decision.skip {
+                        println!("adaptive bitrate: congestion, skipping a video frame");
+                        return;
+                    }
+
+                    //composite videos keep their resolution
+                    if let Some(scale) = decision.rescale {
+                        if self.dataset_id.len() == 1 || !is_composite {
+                            let msg = json!({
+                                "type" : "video_rescale",
+                                "scale" : scale,
+                            });
+
+                            ctx.text(msg.to_string());
+                        }
+                    }
+
+                    let target_bitrate = decision.bitrate;
                     self.bitrate = target_bitrate;
 
                     let frame = predicted_frame;
@@ -2281,27 +2891,104 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                 None => fits.flux.clone(),
                             };
 
-                            //HEVC (x265)
+                            //AV1 (rav1e)
+                            #[cfg(feature = "av1")]
+                            if let Some(ref mut encoder) = self.av1 {
+                                //the bitrate chosen by the adaptive bitrate control
+                                let keyframe = encoder.set_bitrate(target_bitrate) || keyframe;
+
+                                let packets = if encoder.bit_depth() > 8 {
+                                    fits.get_video_frame_hbd(
+                                        frame_index,
+                                        self.width,
+                                        self.height,
+                                        &flux,
+                                        encoder.bit_depth(),
+                                        &self.pool,
+                                    )
+                                    .map(|y| encoder.encode_hbd(&y, keyframe))
+                                } else {
+                                    fits.get_video_frame(
+                                        frame_index,
+                                        self.width,
+                                        self.height,
+                                        &flux,
+                                        &self.pool,
+                                    )
+                                    .map(|y| encoder.encode(&y, keyframe))
+                                };
+
+                                if let Some(packets) = packets {
+                                    println!(
+                                        "AV1 video frame prepare/encode time: {:?}, {} packet(s)",
+                                        watch.elapsed(),
+                                        packets.len()
+                                    );
+
+                                    for packet in packets {
+                                        let ws_frame = WsFrame {
+                                            ts: timestamp as f32,
+                                            seq_id: seq_id as u32,
+                                            msg_type: 8, //an AV1 video frame
+                                            elapsed: watch.elapsed().as_millis() as f32,
+                                            frame: packet,
+                                        };
+
+                                        // remove the preallocation limit
+                                        let config = Configuration::default()
+                                            .disable_preallocation_size_limit();
+                                        match wincode::config::serialize(&ws_frame, config) {
+                                            Ok(bin) => {
+                                                println!("WsFrame binary length: {}", bin.len());
+                                                self.abr.sent(seq_id, bin.len());
+                                                ctx.binary(bin);
+                                            }
+                                            Err(err) => println!(
+                                                "error serializing a WebSocket video frame response: {}",
+                                                err
+                                            ),
+                                        }
+                                    }
+                                }
+
+                                return;
+                            }
+
+                            //HEVC (x265), 16-bit luma samples with Main10/Main12
                             #[cfg(feature = "hevc")]
-                            match fits.get_video_frame(
-                                frame_index,
-                                self.width,
-                                self.height,
-                                &flux,
-                                &self.pool,
-                            ) {
+                            let frame = if self.x265.bit_depth() > 8 {
+                                fits.get_video_frame_hbd(
+                                    frame_index,
+                                    self.width,
+                                    self.height,
+                                    &flux,
+                                    self.x265.bit_depth(),
+                                    &self.pool,
+                                )
+                                .map(hevc::Plane::U16)
+                            } else {
+                                fits.get_video_frame(
+                                    frame_index,
+                                    self.width,
+                                    self.height,
+                                    &flux,
+                                    &self.pool,
+                                )
+                                .map(hevc::Plane::U8)
+                            };
+
+                            #[cfg(feature = "hevc")]
+                            match frame {
                                 Some(mut y) => {
                                     unsafe {
-                                        (*self.pic).stride[0] = self.width as i32;
-                                        (*self.pic).planes[0] =
-                                            y.as_mut_ptr() as *mut std::os::raw::c_void;
+                                        y.attach(self.pic, self.width);
 
                                         //adaptive bitrate
                                         (*self.param).rc.bitrate = target_bitrate;
                                     }
 
                                     let ret =
-                                        unsafe { x265_encoder_reconfig(self.enc, self.param) };
+                                        unsafe { self.x265.encoder_reconfig(self.enc, self.param) };
 
                                     if ret < 0 {
                                         println!("x265: error changing the bitrate");
@@ -2313,7 +3000,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
 
                                     //encode
                                     let ret = unsafe {
-                                        x265_encoder_encode(
+                                        self.x265.encoder_encode(
                                             self.enc,
                                             &mut p_nal,
                                             &mut nal_count,
@@ -2331,10 +3018,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                     );
 
                                     //y falls out of scope
-                                    unsafe {
-                                        (*self.pic).stride[0] = 0 as i32;
-                                        (*self.pic).planes[0] = ptr::null_mut();
-                                    }
+                                    unsafe { hevc::Plane::detach(self.pic) };
 
                                     //process all NAL units one by one
                                     if nal_count > 0 {
@@ -2374,6 +3058,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                                         bin.len()
                                                     );
                                                     //println!("{}", bin);
+                                                    self.abr.sent(seq_id, bin.len());
                                                     ctx.binary(bin);
                                                 }
                                                 Err(err) => println!(
@@ -2395,7 +3080,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                     //flush the encoder to signal the end
                                     loop {
                                         let ret = unsafe {
-                                            x265_encoder_encode(
+                                            self.x265.encoder_encode(
                                                 self.enc,
                                                 &mut p_nal,
                                                 &mut nal_count,
@@ -2443,6 +3128,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                                     Ok(bin) => {
                                                     println!("WsFrame binary length: {}", bin.len());
                                                     //println!("{}", bin);
+                                                    self.abr.sent(seq_id, bin.len());
                                                     ctx.binary(bin);
                                                     },
                                                     Err(err) => println!("error serializing a WebSocket video frame response: {}", err)
@@ -2554,6 +3240,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                             Ok(bin) => {
                                                 println!("WsFrame binary length: {}", bin.len());
                                                 //println!("{}", bin);
+                                                self.abr.sent(seq_id, bin.len());
                                                 ctx.binary(bin);
                                             }
                                             Err(err) => println!(
@@ -2637,6 +3324,11 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                             })
                             .collect();
 
+                        //more than three channels or custom colours: mix them into R, G and B
+                        if planes.len() > 3 || !self.composite.is_empty() {
+                            planes = Vec::from(composite::mix_planes(&planes, &self.composite));
+                        }
+
                         //HEVC (x265)
                         #[cfg(feature = "hevc")]
                         {
@@ -2671,7 +3363,8 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                     (*self.param).rc.bitrate = target_bitrate;
                                 }
 
-                                let ret = unsafe { x265_encoder_reconfig(self.enc, self.param) };
+                                let ret =
+                                    unsafe { self.x265.encoder_reconfig(self.enc, self.param) };
 
                                 if ret < 0 {
                                     println!("x265: error changing the bitrate");
@@ -2683,7 +3376,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
 
                                 //encode
                                 let ret = unsafe {
-                                    x265_encoder_encode(
+                                    self.x265.encoder_encode(
                                         self.enc,
                                         &mut p_nal,
                                         &mut nal_count,
@@ -2742,6 +3435,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                         match wincode::config::serialize(&ws_frame, config) {
                                             Ok(bin) => {
                                                 println!("WsFrame binary length: {}", bin.len());
+                                                self.abr.sent(seq_id, bin.len());
                                                 //println!("{}", bin);
                                                 ctx.binary(bin);
                                             }
@@ -2764,7 +3458,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                 //flush the encoder to signal the end
                                 loop {
                                     let ret = unsafe {
-                                        x265_encoder_encode(
+                                        self.x265.encoder_encode(
                                             self.enc,
                                             &mut p_nal,
                                             &mut nal_count,
@@ -2812,6 +3506,7 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
                                                 Ok(bin) => {
                                                 println!("WsFrame binary length: {}", bin.len());
                                                 //println!("{}", bin);
+                                                self.abr.sent(seq_id, bin.len());
                                                 ctx.binary(bin);
                                                 },
                                                 Err(err) => println!("error serializing a WebSocket video frame response: {}", err)
@@ -2844,6 +3539,9 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for UserSession {
 lazy_static! {
     static ref DATASETS: Arc<RwLock<HashMap<String, Arc<RwLock<Box<fits::FITS>>>>>> =
         Arc::new(RwLock::new(HashMap::new()));
+
+    //the definitions of the derived datasets made since the start-up, by datasetId
+    static ref DERIVED_DEFINITIONS: RwLock<HashMap<String, String>> = RwLock::new(HashMap::new());
 }
 
 #[cfg(feature = "jvo")]
@@ -2877,7 +3575,7 @@ const SERVER_PATH: &'static str = "fitswebql";
 
 const WEBSOCKET_TIMEOUT: u64 = 60 * 60; //[s]; a websocket inactivity timeout
 
-//const LONG_POLL_TIMEOUT: u64 = 100;//[ms]; keep it short, long intervals will block the actix event loop
+const LONG_POLL_TIMEOUT: u64 = 1000; //[ms]; an asynchronous wait for a dataset to finish loading, it does not block the actix event loop
 
 fn fpzip_compress(src: &Vec<f32>, high_quality: bool) -> Option<Vec<u8>> {
     let prec = if high_quality { 24 } else { 16 };
@@ -3071,9 +3769,9 @@ fn remove_symlinks(server_path: Option<String>) {
     }
 }
 
-fn get_home_directory(home_dir: &Option<std::path::PathBuf>) -> HttpResponse {
+async fn get_home_directory(home_dir: &Option<std::path::PathBuf>) -> HttpResponse {
     match home_dir {
-        Some(path_buf) => get_directory(path_buf.to_path_buf()),
+        Some(path_buf) => get_directory(path_buf.to_path_buf()).await,
         None => HttpResponse::NotFound()
             .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
             .append_header(("Pragma", "no-cache"))
@@ -3085,10 +3783,33 @@ fn get_home_directory(home_dir: &Option<std::path::PathBuf>) -> HttpResponse {
     }
 }
 
-fn get_directory(path: std::path::PathBuf) -> HttpResponse {
+async fn get_directory(path: std::path::PathBuf) -> HttpResponse {
+    //the header summaries and thumbnail checks hit the disks, keep them off the actix workers
+    match web::block(move || list_directory(path)).await {
+        Ok(body) => HttpResponse::Ok()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("application/json")
+            .body(body),
+        Err(err) => HttpResponse::InternalServerError()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Critical Error</b>: directory listing failed: {}</p>",
+                err
+            )),
+    }
+}
+
+//the JSON listing of <path>: sub-directories and FITS files with their header summaries
+fn list_directory(path: std::path::PathBuf) -> String {
     println!("scanning directory: {:?}", path);
 
     let mut ordered_entries = BTreeMap::new();
+    let mut fits_files = Vec::new();
 
     match path.read_dir() {
         Ok(entries) => {
@@ -3159,6 +3880,7 @@ fn get_directory(path: std::path::PathBuf) -> HttpResponse {
 
                                 println!("{}", file_entry.to_string());
                                 ordered_entries.insert(entry.file_name(), file_entry);
+                                fits_files.push((entry.file_name(), path));
                             }
                         }
                     }
@@ -3168,6 +3890,37 @@ fn get_directory(path: std::path::PathBuf) -> HttpResponse {
         Err(err) => println!("read_dir call failed: {}", err),
     }
 
+    //add the FITS header summaries (from the catalogue or the headers) and thumbnail URLs
+    let summaries = catalogue::header_summaries(
+        &fits_files
+            .iter()
+            .map(|(_, filepath)| filepath.clone())
+            .collect::<Vec<std::path::PathBuf>>(),
+    );
+
+    for ((key, filepath), summary) in fits_files.iter().zip(summaries) {
+        if let Some(file_entry) = ordered_entries.get_mut(key) {
+            match summary {
+                Some(summary) if summary.width > 0 && summary.height > 0 => {
+                    let dataset_id = thumbnail_dataset_id(filepath);
+
+                    file_entry["fits"] = summary.to_json();
+                    file_entry["thumbnail"] = json!(format!(
+                        "get_thumbnail?dir={}&name={}",
+                        utf8_percent_encode(&path.to_string_lossy(), NON_ALPHANUMERIC),
+                        utf8_percent_encode(&key.to_string_lossy(), NON_ALPHANUMERIC)
+                    ));
+                    file_entry["thumbnail_cached"] =
+                        json!(thumbnail::cached(filepath, &dataset_id).is_some());
+                }
+                _ => {
+                    file_entry["fits"] = serde_json::Value::Null;
+                    file_entry["thumbnail"] = serde_json::Value::Null;
+                }
+            }
+        }
+    }
+
     //println!("{:?}", ordered_entries);
 
     let mut contents = String::from("[");
@@ -3184,16 +3937,90 @@ fn get_directory(path: std::path::PathBuf) -> HttpResponse {
 
     contents.push(']');
 
-    HttpResponse::Ok()
-        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-        .append_header(("Pragma", "no-cache"))
-        .append_header(("Expires", "0"))
-        .content_type("application/json")
-        .body(format!(
-            "{{\"location\": \"{}\", \"contents\": {} }}",
-            path.display(),
-            contents
-        ))
+    format!(
+        "{{\"location\": \"{}\", \"contents\": {} }}",
+        path.display(),
+        contents
+    )
+}
+
+//the FITSWebQL.html dataset id of a local file, i.e. "cube" for cube.fits, "cube.fits" for cube.fits.gz
+fn thumbnail_dataset_id(filepath: &std::path::Path) -> String {
+    let name = match filepath.file_name() {
+        Some(x) => x.to_string_lossy().into_owned(),
+        None => String::from(""),
+    };
+
+    match name.rsplit_once('.') {
+        Some((stem, _)) => stem.to_string(),
+        None => name,
+    }
+}
+
+async fn get_thumbnail(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    let filepath = match (query.get("dir"), query.get("name")) {
+        (Some(dir), Some(name)) if !name.contains('/') && name != ".." => {
+            std::path::Path::new(dir).join(name)
+        }
+        _ => {
+            return HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: get_thumbnail/dir or name parameter not found</p>"
+                ));
+        }
+    };
+
+    if !filepath.is_file() || !catalogue::is_fits_file(&filepath) {
+        return HttpResponse::NotFound()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Critical Error</b>: {} is not a FITS file</p>",
+                filepath.display()
+            ));
+    }
+
+    let dataset_id = thumbnail_dataset_id(&filepath);
+
+    //either from IMAGECACHE or generated from the FITS file
+    let thumbnail = match web::block(move || thumbnail::get(&filepath, &dataset_id)).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(err)) => {
+            println!("cannot make a thumbnail: {}", err);
+            return HttpResponse::NoContent()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .finish();
+        }
+        Err(err) => {
+            println!("cannot make a thumbnail: {}", err);
+            return HttpResponse::InternalServerError()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!("<p><b>Critical Error</b>: internal error</p>"));
+        }
+    };
+
+    match fs::NamedFile::open(thumbnail) {
+        Ok(file) => file.respond_to(&req),
+        Err(_) => HttpResponse::NoContent()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .finish(),
+    }
 }
 
 async fn directory_handler(
@@ -3201,12 +4028,12 @@ async fn directory_handler(
     query: web::Query<HashMap<String, String>>,
 ) -> HttpResponse {
     match query.get("dir") {
-        Some(x) => get_directory(std::path::PathBuf::from(x)),
+        Some(x) => get_directory(std::path::PathBuf::from(x)).await,
         None => {
             let home_dir = &state.home_dir;
 
             //default location
-            get_home_directory(home_dir)
+            get_home_directory(home_dir).await
         }
     }
 }
@@ -3242,6 +4069,12 @@ async fn websocket_entry(
         id, user_agent
     );
 
+    for entry in &id {
+        if let Some(response) = rejected_response(entry) {
+            return Ok(response);
+        }
+    }
+
     ws::start(UserSession::new(state.addr.clone(), &id), &req, stream)
 }
 
@@ -3382,7 +4215,7 @@ async fn fitswebql_entry(
             valid_values.insert(String::from("square"));
             valid_values.insert(String::from("legacy"));
 
-            if valid_values.contains(value) {
+            if valid_values.contains(value) || stretch::Stretch::from_flux(value).is_some() {
                 flux = value;
             };
         }
@@ -3432,6 +4265,113 @@ async fn fitswebql_entry(
     ));
 }
 
+//long-poll the registry; a response is returned only while the dataset is still queued or loading
+//a load rejected by the memory budget
+fn rejected_response(dataset_id: &str) -> Option<HttpResponse> {
+    match registry::get_state(dataset_id) {
+        Some(registry::LoadState::Failed {
+            status_code: 503, ..
+        }) => Some(
+            HttpResponse::ServiceUnavailable()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "INSUFFICIENT SERVER MEMORY, PLEASE TRY AGAIN LATER"
+                )),
+        ),
+        _ => None,
+    }
+}
+
+async fn wait_for_dataset(dataset_id: &str) -> Option<HttpResponse> {
+    let state = registry::wait_ready(
+        dataset_id,
+        std::time::Duration::from_millis(LONG_POLL_TIMEOUT),
+    )
+    .await;
+
+    match state {
+        Some(state) => {
+            if state.is_final() {
+                rejected_response(dataset_id)
+            } else {
+                Some(
+                    HttpResponse::Accepted()
+                        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                        .append_header(("Pragma", "no-cache"))
+                        .append_header(("Expires", "0"))
+                        .content_type("application/json")
+                        .body(state.to_json().to_string()),
+                )
+            }
+        }
+        None => None,
+    }
+}
+
+//an alternative still image (AV1, 10/12-bit VP9) made on first request once the dataset is ready,
+//Some(response) unless it could be made
+async fn get_cached_image(
+    req: &HttpRequest,
+    dataset_id: &str,
+    filename: &str,
+    make: impl FnOnce(&fits::FITS) + Send + 'static,
+) -> Option<HttpResponse> {
+    if !std::path::Path::new(filename).exists() {
+        if let Some(response) = wait_for_dataset(dataset_id).await {
+            return Some(response);
+        }
+
+        let fits = match DATASETS.read().get(dataset_id) {
+            Some(x) => x.clone(),
+            None => {
+                return Some(
+                    HttpResponse::NotFound()
+                        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                        .append_header(("Pragma", "no-cache"))
+                        .append_header(("Expires", "0"))
+                        .content_type("text/html")
+                        .body(format!("<p><b>Critical Error</b>: dataset not found</p>")),
+                );
+            }
+        };
+
+        //encoding takes a while, keep it off the async workers
+        let _ = web::block(move || make(&fits.read())).await;
+    }
+
+    match fs::NamedFile::open(filename) {
+        Ok(file) => Some(file.respond_to(req)),
+        Err(_) => None,
+    }
+}
+
+//an AV1 still image
+#[cfg(feature = "av1")]
+async fn get_av1_image(
+    req: &HttpRequest,
+    dataset_id: &str,
+    bit_depth: u32,
+) -> Result<HttpResponse, Error> {
+    let filename = fits::av1_image_filename(dataset_id, bit_depth);
+
+    match get_cached_image(req, dataset_id, &filename, move |fits| {
+        fits.make_av1_image(bit_depth)
+    })
+    .await
+    {
+        Some(response) => Ok(response),
+        None => Ok(HttpResponse::InternalServerError()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!("<p><b>Critical Error</b>: AV1 image not found</p>"))),
+    }
+}
+
 async fn get_image(req: HttpRequest) -> Result<HttpResponse, Error> {
     let query = match web::Query::<HashMap<String, String>>::extract(&req).await {
         Ok(x) => x,
@@ -3454,21 +4394,66 @@ async fn get_image(req: HttpRequest) -> Result<HttpResponse, Error> {
 
     //println!("[get_image] http request for {}", dataset_id);
 
-    //check the IMAGECACHE first
-    let filename = format!("{}/{}.img", fits::IMAGECACHE, dataset_id.replace("/", "_"));
-    let filepath = std::path::Path::new(&filename);
-
-    if filepath.exists() {
-        return Ok(fs::NamedFile::open(filepath).unwrap().respond_to(&req));
+    let bit_depth = match query.get("bits") {
+        Some(x) => fits::supported_bit_depth(x.parse::<u32>().unwrap_or(8)),
+        None => 8,
     };
 
-    let datasets = DATASETS.read();
+    //the still image codec: the default VP9 (IMAGECACHE/<id>.img) or AV1 when compiled in
+    match query.get("codec").map(|x| x.as_str()) {
+        None | Some("vp9") => {
+            //VP9 profile 2, the 8-bit image below unless libvpx supports a high bit depth
+            if bit_depth > 8 {
+                let filename = fits::vpx_image_filename(dataset_id, bit_depth);
+
+                if let Some(response) = get_cached_image(&req, dataset_id, &filename, move |fits| {
+                    fits.make_vpx_image_hbd(bit_depth)
+                })
+                .await
+                {
+                    return Ok(response);
+                }
 
-    let fits = match datasets.get(dataset_id) {
-        Some(x) => x,
-        None => {
-            return Ok(HttpResponse::NotFound()
-                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                println!(
+                    "[get_image] no {}-bit VP9 image, falling back to 8 bits",
+                    bit_depth
+                );
+            }
+        }
+        #[cfg(feature = "av1")]
+        Some("av1") => return get_av1_image(&req, dataset_id, bit_depth).await,
+        Some(codec) => {
+            return Ok(HttpResponse::BadRequest()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: unsupported image codec '{}'</p>",
+                    codec
+                )));
+        }
+    }
+
+    //check the IMAGECACHE first
+    let filename = format!("{}/{}.img", fits::IMAGECACHE, dataset_id.replace("/", "_"));
+    let filepath = std::path::Path::new(&filename);
+
+    if filepath.exists() {
+        return Ok(fs::NamedFile::open(filepath).unwrap().respond_to(&req));
+    };
+
+    if let Some(response) = wait_for_dataset(dataset_id).await {
+        return Ok(response);
+    }
+
+    let datasets = DATASETS.read();
+
+    let fits = match datasets.get(dataset_id) {
+        Some(x) => x,
+        None => {
+            return Ok(HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
                 .append_header(("Pragma", "no-cache"))
                 .append_header(("Expires", "0"))
                 .content_type("text/html")
@@ -3540,6 +4525,24 @@ async fn get_image(req: HttpRequest) -> Result<HttpResponse, Error> {
                 .append_header(("Expires", "0"))
                 .content_type("text/html")
                 .body(format!("CRITICAL ERROR"))),
+            503 => Ok(HttpResponse::ServiceUnavailable()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!("INSUFFICIENT SERVER MEMORY, PLEASE TRY AGAIN LATER"))),
+            401 | 403 => Ok(HttpResponseBuilder::new(StatusCode::FORBIDDEN)
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!("ACCESS DENIED BY THE REMOTE SITE/SERVER"))),
+            504 => Ok(HttpResponse::GatewayTimeout()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!("DOWNLOAD FAILED, PLEASE RELOAD THE PAGE"))),
             _ => Ok(HttpResponse::NotFound()
                 .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
                 .append_header(("Pragma", "no-cache"))
@@ -3567,6 +4570,10 @@ async fn get_spectrum(query: web::Query<HashMap<String, String>>) -> HttpRespons
 
     //println!("[get_spectrum] http request for {}", dataset_id);
 
+    if let Some(response) = wait_for_dataset(dataset_id).await {
+        return response;
+    }
+
     let datasets = DATASETS.read();
 
     let fits = match datasets.get(dataset_id) {
@@ -3629,72 +4636,147 @@ async fn get_spectrum(query: web::Query<HashMap<String, String>>) -> HttpRespons
     }
 }
 
-struct MoleculeStream {
-    rx: mpsc::Receiver<Molecule>,
-    first: bool,
-    end_transmission: bool,
+fn get_snapshot_params(query: &HashMap<String, String>) -> snapshot::SnapshotParams {
+    let get_f64 = |key: &str| query.get(key).and_then(|x| x.parse::<f64>().ok());
+    let get_f32 = |key: &str| query.get(key).and_then(|x| x.parse::<f32>().ok());
+    let get_i32 = |key: &str| query.get(key).and_then(|x| x.parse::<i32>().ok());
+    let get_bool = |key: &str| match query.get(key) {
+        Some(x) => x != "false" && x != "0",
+        None => true,
+    };
+
+    let region = match (get_i32("x1"), get_i32("y1"), get_i32("x2"), get_i32("y2")) {
+        (Some(x1), Some(y1), Some(x2), Some(y2)) => Some((x1, y1, x2, y2)),
+        _ => None,
+    };
+
+    snapshot::SnapshotParams {
+        frame_start: get_f64("frame_start"),
+        frame_end: get_f64("frame_end"),
+        ref_freq: get_f64("ref_freq").unwrap_or(0.0),
+        flux: query.get("flux").cloned(),
+        black: get_f32("black"),
+        white: get_f32("white"),
+        median: get_f32("median"),
+        noise: query
+            .get("noise")
+            .and_then(|x| x.replace("x", "").parse::<f32>().ok())
+            .unwrap_or(1.0),
+        region: region,
+        width: query.get("width").and_then(|x| x.parse::<u32>().ok()),
+        colourmap: match query.get("colourmap") {
+            Some(x) => x.clone(),
+            None => String::from("green"),
+        },
+        contours: query
+            .get("contours")
+            .and_then(|x| x.parse::<usize>().ok())
+            .unwrap_or(0)
+            .min(100),
+        grid: get_bool("grid"),
+        colourbar: get_bool("colourbar"),
+        snr: query.get("mode").map_or(false, |x| x == "snr"),
+    }
 }
 
-impl MoleculeStream {
-    pub fn new(rx: mpsc::Receiver<Molecule>) -> MoleculeStream {
-        MoleculeStream {
-            rx: rx,
-            first: true,
-            end_transmission: false,
+async fn get_snapshot(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+    let dataset_id = match query.get("datasetId") {
+        Some(x) => x.clone(),
+        None => {
+            return HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: get_snapshot/datasetId parameter not found</p>"
+                ));
         }
+    };
+
+    if let Some(response) = wait_for_dataset(&dataset_id).await {
+        return response;
     }
-}
 
-impl Stream for MoleculeStream {
-    type Item = Bytes;
+    let params = get_snapshot_params(&query);
+    println!("[get_snapshot] {}: {:?}", dataset_id, params);
 
-    fn poll_next(
-        mut self: std::pin::Pin<&mut Self>,
-        _cx: &mut futures::task::Context<'_>,
-    ) -> Poll<Option<Self::Item>> {
-        match self.rx.recv() {
-            Ok(molecule) => {
-                //println!("{:?}", molecule);
+    let id = dataset_id.clone();
 
-                if self.first {
-                    self.first = false;
+    //rendering a large PNG takes a while, keep it off the event loop
+    let result = web::block(move || {
+        let fits = match DATASETS.read().get(&id) {
+            Some(x) => x.clone(),
+            None => return Err(StatusCode::NOT_FOUND),
+        };
 
-                    //the first molecule
-                    Poll::Ready(Some(Bytes::from(format!(
-                        "{{\"molecules\" : [{}",
-                        molecule.to_json().to_string()
-                    ))))
-                } else {
-                    //subsequent molecules
-                    Poll::Ready(Some(Bytes::from(format!(
-                        ",{}",
-                        molecule.to_json().to_string()
-                    ))))
-                }
-            }
-            Err(err) => {
-                if self.end_transmission {
-                    println!("MoleculeStream: {}, terminating transmission", err);
-                    Poll::Ready(None)
-                } else {
-                    self.end_transmission = true;
+        let fits = match fits.try_read() {
+            Some(x) => x,
+            None => return Err(StatusCode::ACCEPTED),
+        };
 
-                    if self.first {
-                        //no molecules received; send an empty JSON array
-                        Poll::Ready(Some(Bytes::from("{\"molecules\" : []}")))
-                    } else {
-                        //end a JSON array
-                        Poll::Ready(Some(Bytes::from("]}")))
-                    }
-                }
-            }
+        {
+            *fits.timestamp.write() = SystemTime::now();
         }
-    }
+
+        if fits.is_dummy {
+            return Err(StatusCode::ACCEPTED);
+        }
+
+        match fits.get_snapshot(&params) {
+            Some(png) => Ok(png),
+            None => Err(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    })
+    .await;
+
+    let status = match result {
+        Ok(Ok(png)) => {
+            return HttpResponse::Ok()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .append_header((
+                    "Content-Disposition",
+                    format!(
+                        "attachment; filename={}.png",
+                        dataset_id.replace("/", "_").replace("\"", "")
+                    ),
+                ))
+                .content_type("image/png")
+                .body(png);
+        }
+        Ok(Err(status)) => status,
+        Err(err) => {
+            println!("[get_snapshot] {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+
+    let msg = match status {
+        StatusCode::ACCEPTED => format!(
+            "<p><b>RwLock timeout</b>: {} not available yet</p>",
+            dataset_id
+        ),
+        StatusCode::NOT_FOUND => format!("<p><b>Critical Error</b>: dataset not found</p>"),
+        _ => format!(
+            "<p><b>Critical Error</b>: cannot produce a snapshot of {}</p>",
+            dataset_id
+        ),
+    };
+
+    HttpResponseBuilder::new(status)
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("text/html")
+        .body(msg)
 }
 
-async fn get_molecules(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+//per-channel noise statistics (median, MAD-based RMS, NaN fraction, min/max), format=json|csv
+async fn get_noise_stats(query: web::Query<HashMap<String, String>>) -> HttpResponse {
     let dataset_id = match query.get("datasetId") {
-        Some(x) => x,
+        Some(x) => x.clone(),
         None => {
             return HttpResponse::NotFound()
                 .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
@@ -3702,135 +4784,1644 @@ async fn get_molecules(query: web::Query<HashMap<String, String>>) -> HttpRespon
                 .append_header(("Expires", "0"))
                 .content_type("text/html")
                 .body(format!(
-                    "<p><b>Critical Error</b>: get_molecules/datasetId parameter not found</p>"
+                    "<p><b>Critical Error</b>: get_noise_stats/datasetId parameter not found</p>"
                 ));
         }
     };
 
-    //freq_start
-    let freq_start = match query.get("freq_start") {
-        Some(x) => x,
-        None => {
-            return HttpResponse::NotFound()
+    let csv = match query.get("format").map(|x| x.to_lowercase()) {
+        None => false,
+        Some(x) if x == "json" => false,
+        Some(x) if x == "csv" => true,
+        Some(x) => {
+            return HttpResponse::BadRequest()
                 .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
                 .append_header(("Pragma", "no-cache"))
                 .append_header(("Expires", "0"))
                 .content_type("text/html")
                 .body(format!(
-                    "<p><b>Critical Error</b>: get_molecules/freq_start parameter not found</p>"
+                    "<p><b>Critical Error</b>: unsupported format '{}', use json or csv</p>",
+                    x
                 ));
         }
     };
 
-    let freq_start = match freq_start.parse::<f64>() {
-        Ok(x) => x / 1e9, //[Hz -> GHz]
-        Err(_) => 0.0,
-    };
+    if let Some(response) = wait_for_dataset(&dataset_id).await {
+        return response;
+    }
 
-    //freq_end
-    let freq_end = match query.get("freq_end") {
-        Some(x) => x,
+    let fits = match DATASETS.read().get(&dataset_id) {
+        Some(x) => x.clone(),
         None => {
             return HttpResponse::NotFound()
                 .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
                 .append_header(("Pragma", "no-cache"))
                 .append_header(("Expires", "0"))
                 .content_type("text/html")
-                .body(format!(
-                    "<p><b>Critical Error</b>: get_molecules/freq_end parameter not found</p>"
-                ));
+                .body(format!("<p><b>Critical Error</b>: dataset not found</p>"));
         }
     };
 
-    let freq_end = match freq_end.parse::<f64>() {
-        Ok(x) => x / 1e9, //[Hz -> GHz]
-        Err(_) => 0.0,
+    let table = match fits.try_read() {
+        Some(fits) if !fits.is_dummy => {
+            *fits.timestamp.write() = SystemTime::now();
+            fits.get_noise_stats(csv)
+        }
+        _ => None,
     };
 
-    println!(
-        "[get_molecules] http request for {}: freq_start={}, freq_end={} [GHz]",
-        dataset_id, freq_start, freq_end
-    );
-
-    if freq_start == 0.0 || freq_end == 0.0 {
-        let datasets = DATASETS.read();
-
-        let fits = match datasets.get(dataset_id) {
-            Some(x) => x,
-            None => {
-                return HttpResponse::NotFound()
-                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                    .append_header(("Pragma", "no-cache"))
-                    .append_header(("Expires", "0"))
-                    .content_type("text/html")
-                    .body(format!("<p><b>Critical Error</b>: dataset not found</p>"));
-            }
-        };
-
-        let fits = match fits.try_read() {
-            Some(x) => x,
-            None => {
-                return HttpResponse::Accepted()
-                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                    .append_header(("Pragma", "no-cache"))
-                    .append_header(("Expires", "0"))
-                    .content_type("text/html")
-                    .body(format!(
-                        "<p><b>RwLock timeout</b>: {} not available yet</p>",
-                        dataset_id
-                    ));
-            }
-        };
+    match table {
+        Some(table) => {
+            let mut response = HttpResponse::Ok();
 
-        if !fits.has_header {
-            if fits.is_dummy {
-                HttpResponse::Accepted()
-                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                    .append_header(("Pragma", "no-cache"))
-                    .append_header(("Expires", "0"))
-                    .content_type("text/html")
-                    .body(format!(
-                        "<p><b>spectral lines for {} not available yet</p>",
-                        dataset_id
+            response
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"));
+
+            if csv {
+                response
+                    .append_header((
+                        "Content-Disposition",
+                        format!(
+                            "attachment; filename={}_noise.csv",
+                            dataset_id.replace("/", "_").replace("\"", "")
+                        ),
                     ))
+                    .content_type("text/csv")
+                    .body(table)
             } else {
-                HttpResponse::NotFound()
-                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                    .append_header(("Pragma", "no-cache"))
-                    .append_header(("Expires", "0"))
-                    .content_type("text/html")
-                    .body(format!(
-                        "<p><b>Critical Error</b>: spectral lines not found</p>"
-                    ))
+                response.content_type("application/json").body(table)
             }
-        } else {
-            if fits.is_optical {
-                HttpResponse::NotFound()
-                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                    .append_header(("Pragma", "no-cache"))
-                    .append_header(("Expires", "0"))
-                    .content_type("text/html")
-                    .body(format!(
-                        "<p><b>Critical Error</b>: spectral lines not found</p>"
-                    ))
-            } else {
-                let (freq_start, freq_end) = fits.get_frequency_range();
+        }
+        //the statistics of some loads are computed in the background
+        None => HttpResponse::Accepted()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Please wait</b>: the noise statistics of {} are not available yet</p>",
+                dataset_id
+            )),
+    }
+}
 
-                //stream molecules from sqlite
-                match stream_molecules(freq_start, freq_end) {
-                    Some(rx) => {
-                        let molecules_stream = MoleculeStream::new(rx);
+async fn search_catalogue(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+    let status = catalogue::status();
 
-                        HttpResponse::Ok()
-                            .content_type("application/json")
-                            .streaming(molecules_stream.map(|x| Ok(x) as Result<Bytes, Error>))
-                    }
-                    None => HttpResponse::Ok()
-                        .content_type("application/json")
-                        .body(format!("{{\"molecules\" : []}}")),
-                }
-            }
-        }
+    if status.roots.is_empty() {
+        return HttpResponse::NotFound()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Critical Error</b>: the FITS catalogue is disabled, use '--catalogue /a/path/to/your/FITS/files'</p>"
+            ));
+    }
+
+    let number = |key: &str| {
+        query
+            .get(key)
+            .and_then(|x| x.parse::<f64>().ok())
+            .filter(|x| x.is_finite())
+    };
+
+    let text = |key: &str| {
+        query
+            .get(key)
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+    };
+
+    //a cone search needs both ra and dec [deg]
+    let cone = match (number("ra"), number("dec")) {
+        (Some(ra), Some(dec)) => Some((
+            ra,
+            dec,
+            number("radius")
+                .unwrap_or(catalogue::DEFAULT_SEARCH_RADIUS)
+                .abs(),
+        )),
+        _ => None,
+    };
+
+    //the frequency coverage must overlap [freq_start, freq_end] (a single frequency also works)
+    let freq = match (number("freq_start"), number("freq_end")) {
+        (Some(a), Some(b)) => Some((a.min(b), a.max(b))),
+        (Some(a), None) | (None, Some(a)) => Some((a, a)),
+        _ => None,
+    };
+
+    let limit = match query.get("limit").and_then(|x| x.parse::<usize>().ok()) {
+        Some(x) => x.clamp(1, catalogue::MAX_SEARCH_LIMIT),
+        None => catalogue::DEFAULT_SEARCH_LIMIT,
+    };
+
+    let search = catalogue::Query {
+        cone: cone,
+        freq: freq,
+        object: text("object"),
+        telescope: text("telescope"),
+        line: text("line"),
+        header: text("header"),
+        min_depth: query.get("min_depth").and_then(|x| x.parse::<usize>().ok()),
+        limit: limit,
+    };
+
+    let entries = match web::block(move || catalogue::search(&search)).await {
+        Ok(Ok(x)) => x,
+        Ok(Err(err)) => {
+            println!("error searching the FITS catalogue: {}", err);
+            return HttpResponse::InternalServerError()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!("<p><b>Critical Error</b>: database error</p>"));
+        }
+        Err(err) => {
+            println!("error searching the FITS catalogue: {}", err);
+            return HttpResponse::InternalServerError()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!("<p><b>Critical Error</b>: internal error</p>"));
+        }
+    };
+
+    HttpResponse::Ok()
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("application/json")
+        .body(
+            json!({
+                "catalogue" : status.to_json(),
+                "count" : entries.len(),
+                "results" : entries.iter().map(|x| x.to_json()).collect::<Vec<serde_json::Value>>(),
+            })
+            .to_string(),
+        )
+}
+
+//an N-channel colour composite PNG, channels=[{"datasetId":...,"frame_start":...,"colour":...}, ...]
+async fn get_composite(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+    let channels = match query.get("channels").map(|x| composite::parse_channels(x)) {
+        Some(Ok(channels)) => channels,
+        Some(Err(err)) => {
+            return HttpResponse::BadRequest()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: get_composite: {}</p>",
+                    err
+                ));
+        }
+        None => {
+            return HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: get_composite/channels parameter not found</p>"
+                ));
+        }
+    };
+
+    for channel in &channels {
+        if let Some(response) = wait_for_dataset(&channel.dataset_id).await {
+            return response;
+        }
+    }
+
+    let width = query.get("width").and_then(|x| x.parse::<u32>().ok());
+    println!("[get_composite] {:?}, width: {:?}", channels, width);
+
+    //regridding and mixing the channels takes a while, keep it off the event loop
+    let result = web::block(move || {
+        let mut images = Vec::with_capacity(channels.len());
+
+        for channel in &channels {
+            let fits = match DATASETS.read().get(&channel.dataset_id) {
+                Some(x) => x.clone(),
+                None => return Err((StatusCode::NOT_FOUND, channel.dataset_id.clone())),
+            };
+
+            let fits = match fits.try_read() {
+                Some(x) => x,
+                None => return Err((StatusCode::ACCEPTED, channel.dataset_id.clone())),
+            };
+
+            {
+                *fits.timestamp.write() = SystemTime::now();
+            }
+
+            if fits.is_dummy {
+                return Err((StatusCode::ACCEPTED, channel.dataset_id.clone()));
+            }
+
+            match fits.get_composite_channel(channel) {
+                Some(image) => images.push(image),
+                None => {
+                    return Err((
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("cannot stretch {}", channel.dataset_id),
+                    ));
+                }
+            }
+        }
+
+        composite::render(&channels, &images, width)
+            .map_err(|err| (StatusCode::UNPROCESSABLE_ENTITY, err))
+    })
+    .await;
+
+    let (status, detail) = match result {
+        Ok(Ok(png)) => {
+            return HttpResponse::Ok()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .append_header(("Content-Disposition", "attachment; filename=composite.png"))
+                .content_type("image/png")
+                .body(png);
+        }
+        Ok(Err(err)) => err,
+        Err(err) => {
+            println!("[get_composite] {}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+        }
+    };
+
+    let msg = match status {
+        StatusCode::ACCEPTED => {
+            format!("<p><b>RwLock timeout</b>: {} not available yet</p>", detail)
+        }
+        StatusCode::NOT_FOUND => format!("<p><b>Critical Error</b>: {} not found</p>", detail),
+        _ => format!(
+            "<p><b>Critical Error</b>: cannot produce a composite: {}</p>",
+            detail
+        ),
+    };
+
+    HttpResponseBuilder::new(status)
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("text/html")
+        .body(msg)
+}
+
+//an error creating a dataset derived from loaded ones (make_virtual, make_regrid)
+fn derived_dataset_error(endpoint: &str, status: StatusCode, msg: String) -> HttpResponse {
+    println!("[{}] {}", endpoint, msg);
+
+    HttpResponseBuilder::new(status)
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("text/html")
+        .body(format!(
+            "<p><b>Critical Error</b>: {}: {}</p>",
+            endpoint, msg
+        ))
+}
+
+//the datasetId of a derived dataset and the URL to view it
+fn derived_dataset_response(req: &HttpRequest, dataset_id: &str) -> HttpResponse {
+    let fitswebql_path = req.match_info().get("path").unwrap_or("fitswebql");
+    let url = format!(
+        "/{}/FITSWebQL.html?datasetId={}",
+        fitswebql_path,
+        utf8_percent_encode(dataset_id, NON_ALPHANUMERIC)
+    );
+
+    HttpResponse::Ok()
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("application/json")
+        .body(json!({ "datasetId" : dataset_id, "url" : url }).to_string())
+}
+
+//the definition of a derived dataset as a URL, the v3 UUID of which is its default datasetId
+fn derived_definition(endpoint: &str, params: &[(&str, &str)]) -> String {
+    let query: Vec<String> = params
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, utf8_percent_encode(value, NON_ALPHANUMERIC)))
+        .collect();
+
+    format!("{}?{}", endpoint, query.join("&"))
+}
+
+//a named derived dataset that already exists with a different definition
+fn derived_dataset_conflict(
+    endpoint: &str,
+    dataset_id: &str,
+    definition: &str,
+) -> Option<HttpResponse> {
+    match DERIVED_DEFINITIONS.read().get(dataset_id) {
+        Some(x) if x != definition => Some(derived_dataset_error(
+            endpoint,
+            StatusCode::CONFLICT,
+            format!("{} already exists with a different definition", dataset_id),
+        )),
+        _ => None,
+    }
+}
+
+//an operand that is still being loaded, the client should retry
+fn derived_dataset_pending(dataset_id: &str, message: &str) -> HttpResponse {
+    HttpResponse::Accepted()
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("application/json")
+        .body(
+            json!({ "datasetId" : dataset_id, "state" : "loading", "message" : message })
+                .to_string(),
+        )
+}
+
+//a virtual dataset evaluated from an expression over loaded datasets,
+//expr=(A-B)/B&A=<datasetId>&B=<datasetId>[&name=<name>, the new datasetId is virtual_<name>]
+async fn make_virtual(
+    state: web::Data<WsSessionState>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    let text = match query.get("expr") {
+        Some(x) => x.clone(),
+        None => {
+            return derived_dataset_error(
+                "make_virtual",
+                StatusCode::NOT_FOUND,
+                String::from("expr parameter not found"),
+            );
+        }
+    };
+
+    //the operands A, B, ... up to the first one missing
+    let operands: Vec<String> = (b'A'..b'A' + arithmetic::MAX_OPERANDS as u8)
+        .map_while(|c| query.get(&(c as char).to_string()).cloned())
+        .collect();
+
+    let expr = match arithmetic::parse(&text, operands.len()) {
+        Ok(x) => x,
+        Err(err) => {
+            return derived_dataset_error(
+                "make_virtual",
+                StatusCode::BAD_REQUEST,
+                format!("'{}': {}", text, err),
+            );
+        }
+    };
+
+    let mut used = Vec::new();
+    expr.operands(&mut used);
+
+    if used.is_empty() {
+        return derived_dataset_error(
+            "make_virtual",
+            StatusCode::BAD_REQUEST,
+            format!("'{}' does not refer to any dataset", text),
+        );
+    }
+
+    //the same expression over the same datasets gives the same virtual dataset
+    let letters: Vec<String> = (0..operands.len())
+        .map(|index| ((b'A' + index as u8) as char).to_string())
+        .collect();
+
+    let params: Vec<(&str, &str)> = std::iter::once(("expr", text.as_str()))
+        .chain(
+            letters
+                .iter()
+                .zip(operands.iter())
+                .map(|(letter, operand)| (letter.as_str(), operand.as_str())),
+        )
+        .collect();
+
+    let definition = derived_definition("make_virtual", &params);
+
+    //a user-supplied name is prefixed so that it cannot take over a FITS file in the FITSCACHE
+    let dataset_id = match query.get("name") {
+        Some(x) => format!("virtual_{}", x),
+        None => format!(
+            "virtual_{}",
+            Uuid::new_v3(&Uuid::NAMESPACE_URL, definition.as_bytes())
+        ),
+    };
+
+    if operands.contains(&dataset_id) {
+        return derived_dataset_error(
+            "make_virtual",
+            StatusCode::BAD_REQUEST,
+            format!("{} cannot replace its own operand", dataset_id),
+        );
+    }
+
+    if let Some(response) = derived_dataset_conflict("make_virtual", &dataset_id, &definition) {
+        return response;
+    }
+
+    let response = || derived_dataset_response(&req, &dataset_id);
+
+    if DATASETS.read().contains_key(&dataset_id) {
+        return response();
+    }
+
+    for &index in &used {
+        if let Some(response) = wait_for_dataset(&operands[index]).await {
+            return response;
+        }
+    }
+
+    let mut sources = vec![None; operands.len()];
+
+    //the cube with the most frames defines the grid, 2D images apply to every frame
+    let mut reference: Option<(usize, usize, usize, usize)> = None;
+    let mut flux = String::new();
+
+    for &index in &used {
+        let dataset = match DATASETS.read().get(&operands[index]) {
+            Some(x) => x.clone(),
+            None => {
+                return derived_dataset_error(
+                    "make_virtual",
+                    StatusCode::NOT_FOUND,
+                    format!("{} not found", operands[index]),
+                );
+            }
+        };
+
+        {
+            let fits = match dataset.try_read() {
+                Some(x) => x,
+                None => {
+                    return derived_dataset_pending(&operands[index], "not available yet");
+                }
+            };
+
+            if fits.is_dummy || !fits.has_data {
+                return derived_dataset_pending(&operands[index], "no data yet");
+            }
+
+            match reference {
+                Some((_, _, _, depth)) if depth >= fits.depth => {}
+                _ => {
+                    reference = Some((index, fits.width, fits.height, fits.depth));
+                    flux = fits.flux.clone();
+                }
+            }
+        }
+
+        sources[index] = Some(dataset);
+    }
+
+    let (reference, width, height, depth) = match reference {
+        Some(x) => x,
+        None => {
+            return derived_dataset_error(
+                "make_virtual",
+                StatusCode::NOT_FOUND,
+                String::from("no operands"),
+            );
+        }
+    };
+
+    let reference_wcs = match sources[reference] {
+        Some(ref fits) => fits.read().get_celestial_wcs(),
+        None => None,
+    };
+
+    for &index in &used {
+        let fits = match sources[index] {
+            Some(ref fits) => fits.read(),
+            None => continue,
+        };
+
+        let same_grid = match (&reference_wcs, fits.get_celestial_wcs()) {
+            (Some(a), Some(b)) => composite::same_grid(a, &b),
+            _ => true,
+        };
+
+        if fits.width != width
+            || fits.height != height
+            || (fits.depth != depth && fits.depth != 1)
+            || !same_grid
+        {
+            return derived_dataset_error(
+                "make_virtual",
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "{} ({}x{}x{}) is not on the grid of {} ({}x{}x{}), regrid it first",
+                    operands[index],
+                    fits.width,
+                    fits.height,
+                    fits.depth,
+                    operands[reference],
+                    width,
+                    height,
+                    depth
+                ),
+            );
+        }
+    }
+
+    //checked and inserted atomically, a concurrent request gets the same dataset
+    {
+        let mut datasets = DATASETS.write();
+
+        if let Some(response) = derived_dataset_conflict("make_virtual", &dataset_id, &definition) {
+            return response;
+        }
+
+        if datasets.contains_key(&dataset_id) {
+            return response();
+        }
+
+        datasets.insert(
+            dataset_id.clone(),
+            Arc::new(RwLock::new(Box::new(fits::FITS::new(
+                &dataset_id,
+                &"".to_owned(),
+                &flux,
+            )))),
+        );
+
+        DERIVED_DEFINITIONS
+            .write()
+            .insert(dataset_id.clone(), definition);
+    }
+
+    println!(
+        "[make_virtual] {} = {} over {:?}",
+        dataset_id, text, operands
+    );
+
+    registry::queue(&dataset_id);
+
+    let server = state.addr.clone();
+    let my_data_id = dataset_id.clone();
+
+    //evaluate the expression and load the result in a new thread
+    thread::spawn(move || {
+        let filename = format!("{}/{}.fits", fits::FITSCACHE, my_data_id.replace("/", "_"));
+        let bytes = width * height * depth * std::mem::size_of::<f32>();
+
+        let fits = load_dataset(&my_data_id, &"".to_owned(), &flux, bytes, &server, || {
+            let history = std::iter::once(format!("fits_web_ql virtual dataset: {}", text))
+                .chain(used.iter().map(|&index| {
+                    format!("{} = {}", (b'A' + index as u8) as char, operands[index])
+                }))
+                .collect::<Vec<String>>();
+
+            let result = write_virtual_fits(&expr, &sources, reference, &history, &filename);
+
+            if let Err(err) = result {
+                println!("{}: cannot evaluate '{}': {}", my_data_id, text, err);
+
+                let mut fits = fits::FITS::new(&my_data_id, &"".to_owned(), &flux);
+                fits.is_dummy = false;
+                fits.status_code = 500;
+                return fits;
+            }
+
+            fits::FITS::from_path(
+                &my_data_id,
+                &flux,
+                std::path::Path::new(&filename),
+                &"".to_owned(),
+                &server,
+            )
+        });
+
+        publish_dataset(&my_data_id, fits);
+    });
+
+    response()
+}
+
+//write a float32 FITS file via a temporary file (the FITSCACHE entry must be complete),
+//<data> writes the big-endian samples and returns their length
+fn write_float_fits<F>(filename: &str, header: &[u8], data: F) -> std::io::Result<()>
+where
+    F: FnOnce(&mut std::io::BufWriter<File>) -> std::io::Result<usize>,
+{
+    //a unique temporary file, the writers of the same dataset never share one
+    let tmp = format!("{}.{}.tmp", filename, Uuid::new_v4());
+
+    let write = || -> std::io::Result<()> {
+        let mut f = std::io::BufWriter::new(File::create(&tmp)?);
+
+        f.write_all(header)?;
+
+        let written = data(&mut f)?;
+        let remainder = written % fits::FITS_CHUNK_LENGTH;
+
+        if remainder > 0 {
+            f.write_all(&vec![0; fits::FITS_CHUNK_LENGTH - remainder])?;
+        }
+
+        f.flush()
+    };
+
+    let result = write().and_then(|_| std::fs::rename(&tmp, filename));
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+
+    result
+}
+
+//evaluate a virtual dataset into a float32 FITS file on the grid of <sources[reference]>
+fn write_virtual_fits(
+    expr: &arithmetic::Expr,
+    sources: &[Option<Arc<RwLock<Box<fits::FITS>>>>],
+    reference: usize,
+    history: &[String],
+    filename: &str,
+) -> std::io::Result<()> {
+    let datasets: Vec<_> = sources
+        .iter()
+        .map(|x| x.as_ref().map(|x| x.read()))
+        .collect();
+
+    let (header, size, depth) = match datasets[reference] {
+        Some(ref fits) => (
+            fits.float_fits_header(None, false, history),
+            fits.width * fits.height,
+            fits.depth,
+        ),
+        None => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "the reference dataset is missing",
+            ));
+        }
+    };
+
+    let depths: Vec<usize> = datasets
+        .iter()
+        .map(|x| x.as_ref().map(|x| x.depth).unwrap_or(1))
+        .collect();
+
+    write_float_fits(filename, &header, |f| {
+        arithmetic::evaluate(
+            expr,
+            &depths,
+            size,
+            depth,
+            |index, frame| datasets[index].as_ref()?.frame_values(frame),
+            f,
+        )
+    })
+}
+
+//resample a dataset onto the pixel grid (and optionally the velocity axis) of another one,
+//datasetId=<source>&reference=<datasetId>[&method=nearest|bilinear|flux][&spectral=true]
+//[&name=<name>, the new datasetId is regrid_<name>]
+async fn make_regrid(
+    state: web::Data<WsSessionState>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    let source_id = match query.get("datasetId") {
+        Some(x) => x.clone(),
+        None => {
+            return derived_dataset_error(
+                "make_regrid",
+                StatusCode::NOT_FOUND,
+                String::from("datasetId parameter not found"),
+            );
+        }
+    };
+
+    let reference_id = match query.get("reference") {
+        Some(x) => x.clone(),
+        None => {
+            return derived_dataset_error(
+                "make_regrid",
+                StatusCode::NOT_FOUND,
+                String::from("reference parameter not found"),
+            );
+        }
+    };
+
+    let method = match query.get("method") {
+        Some(x) => match regrid::Method::from_name(x) {
+            Some(method) => method,
+            None => {
+                return derived_dataset_error(
+                    "make_regrid",
+                    StatusCode::BAD_REQUEST,
+                    format!("unknown method '{}' (nearest, bilinear or flux)", x),
+                );
+            }
+        },
+        None => regrid::Method::Bilinear,
+    };
+
+    let spectral = match query.get("spectral") {
+        Some(x) => x == "true" || x == "velocity" || x == "1",
+        None => false,
+    };
+
+    let definition = derived_definition(
+        "make_regrid",
+        &[
+            ("datasetId", &source_id),
+            ("reference", &reference_id),
+            ("method", method.name()),
+            ("spectral", if spectral { "true" } else { "false" }),
+        ],
+    );
+
+    //a user-supplied name is prefixed so that it cannot take over a FITS file in the FITSCACHE
+    let dataset_id = match query.get("name") {
+        Some(x) => format!("regrid_{}", x),
+        None => format!(
+            "regrid_{}",
+            Uuid::new_v3(&Uuid::NAMESPACE_URL, definition.as_bytes())
+        ),
+    };
+
+    if dataset_id == source_id || dataset_id == reference_id {
+        return derived_dataset_error(
+            "make_regrid",
+            StatusCode::BAD_REQUEST,
+            format!("{} cannot replace its own source", dataset_id),
+        );
+    }
+
+    if let Some(response) = derived_dataset_conflict("make_regrid", &dataset_id, &definition) {
+        return response;
+    }
+
+    if DATASETS.read().contains_key(&dataset_id) {
+        return derived_dataset_response(&req, &dataset_id);
+    }
+
+    for id in [&source_id, &reference_id] {
+        if let Some(response) = wait_for_dataset(id).await {
+            return response;
+        }
+    }
+
+    let mut datasets = Vec::with_capacity(2);
+
+    for id in [&source_id, &reference_id] {
+        let dataset = match DATASETS.read().get(id) {
+            Some(x) => x.clone(),
+            None => {
+                return derived_dataset_error(
+                    "make_regrid",
+                    StatusCode::NOT_FOUND,
+                    format!("{} not found", id),
+                );
+            }
+        };
+
+        let ready = match dataset.try_read() {
+            Some(fits) => !fits.is_dummy && fits.has_data,
+            None => false,
+        };
+
+        if !ready {
+            return derived_dataset_pending(id, "not available yet");
+        }
+
+        datasets.push(dataset);
+    }
+
+    let (source, reference) = (datasets[0].clone(), datasets[1].clone());
+
+    //the spectral frames of the source making up each output frame
+    let (channels, flux, width, height) = {
+        let source = source.read();
+        let reference = reference.read();
+
+        for (id, fits) in [(&source_id, &source), (&reference_id, &reference)] {
+            if fits.get_celestial_wcs().is_none() {
+                return derived_dataset_error(
+                    "make_regrid",
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    format!("{} has no celestial WCS", id),
+                );
+            }
+        }
+
+        let channels = if spectral {
+            let velocities = |fits: &fits::FITS| -> Option<Vec<f64>> {
+                if fits.depth <= 1 {
+                    return None;
+                }
+
+                (0..fits.depth)
+                    .map(|frame| fits.frame_velocity(frame))
+                    .collect()
+            };
+
+            match (velocities(&source), velocities(&reference)) {
+                (Some(from), Some(to)) => regrid::spectral_map(&to, &from, method),
+                _ => {
+                    return derived_dataset_error(
+                        "make_regrid",
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        String::from("both datasets need a velocity axis (or a rest frequency)"),
+                    );
+                }
+            }
+        } else {
+            (0..source.depth).map(|frame| vec![(frame, 1.0)]).collect()
+        };
+
+        if channels.iter().all(|frames| frames.is_empty()) {
+            return derived_dataset_error(
+                "make_regrid",
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!(
+                    "{} and {} do not overlap in velocity",
+                    source_id, reference_id
+                ),
+            );
+        }
+
+        (
+            channels,
+            source.flux.clone(),
+            reference.width,
+            reference.height,
+        )
+    };
+
+    //checked and inserted atomically, a concurrent request gets the same dataset
+    {
+        let mut datasets = DATASETS.write();
+
+        if let Some(response) = derived_dataset_conflict("make_regrid", &dataset_id, &definition) {
+            return response;
+        }
+
+        if datasets.contains_key(&dataset_id) {
+            return derived_dataset_response(&req, &dataset_id);
+        }
+
+        datasets.insert(
+            dataset_id.clone(),
+            Arc::new(RwLock::new(Box::new(fits::FITS::new(
+                &dataset_id,
+                &"".to_owned(),
+                &flux,
+            )))),
+        );
+
+        DERIVED_DEFINITIONS
+            .write()
+            .insert(dataset_id.clone(), definition);
+    }
+
+    println!(
+        "[make_regrid] {} = {} on the grid of {} ({}, spectral: {})",
+        dataset_id,
+        source_id,
+        reference_id,
+        method.name(),
+        spectral
+    );
+
+    registry::queue(&dataset_id);
+
+    let server = state.addr.clone();
+    let my_data_id = dataset_id.clone();
+
+    //resample the source and load the result in a new thread
+    thread::spawn(move || {
+        let filename = format!("{}/{}.fits", fits::FITSCACHE, my_data_id.replace("/", "_"));
+        let bytes = width * height * channels.len() * std::mem::size_of::<f32>();
+
+        let fits = load_dataset(&my_data_id, &"".to_owned(), &flux, bytes, &server, || {
+            let history = vec![
+                format!(
+                    "fits_web_ql: {} resampled onto the grid of {}",
+                    source_id, reference_id
+                ),
+                format!(
+                    "method: {}, spectral regridding: {}",
+                    method.name(),
+                    spectral
+                ),
+            ];
+
+            let result = write_regridded_fits(
+                &source, &reference, method, spectral, &channels, &history, &filename,
+            );
+
+            if let Err(err) = result {
+                println!("{}: cannot regrid {}: {}", my_data_id, source_id, err);
+
+                let mut fits = fits::FITS::new(&my_data_id, &"".to_owned(), &flux);
+                fits.is_dummy = false;
+                fits.status_code = 500;
+                return fits;
+            }
+
+            fits::FITS::from_path(
+                &my_data_id,
+                &flux,
+                std::path::Path::new(&filename),
+                &"".to_owned(),
+                &server,
+            )
+        });
+
+        publish_dataset(&my_data_id, fits);
+    });
+
+    derived_dataset_response(&req, &dataset_id)
+}
+
+//resample <source> into a float32 FITS file on the grid of <reference>,
+//<channels> lists the weighted source frames of each output frame
+fn write_regridded_fits(
+    source: &Arc<RwLock<Box<fits::FITS>>>,
+    reference: &Arc<RwLock<Box<fits::FITS>>>,
+    method: regrid::Method,
+    spectral: bool,
+    channels: &[Vec<(usize, f32)>],
+    history: &[String],
+    filename: &str,
+) -> std::io::Result<()> {
+    let source = source.read();
+
+    let (map, header, size) = {
+        let reference = reference.read();
+
+        let (source_wcs, reference_wcs) =
+            match (source.get_celestial_wcs(), reference.get_celestial_wcs()) {
+                (Some(a), Some(b)) => (a, b),
+                _ => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "no celestial WCS",
+                    ));
+                }
+            };
+
+        let map = regrid::SpatialMap::new(
+            &reference_wcs,
+            reference.width,
+            reference.height,
+            &source_wcs,
+            source.width,
+            source.height,
+            method,
+        );
+
+        let grid: &fits::FITS = &reference;
+
+        (
+            map,
+            source.float_fits_header(Some(grid), spectral, history),
+            reference.width * reference.height,
+        )
+    };
+
+    write_float_fits(filename, &header, |f| {
+        let mut written = 0;
+
+        for frames in channels {
+            let mut planes = Vec::with_capacity(frames.len());
+
+            for &(frame, weight) in frames {
+                match source.frame_values(frame) {
+                    Some(pixels) => planes.push((pixels, weight)),
+                    None => {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("frame {} is not available", frame),
+                        ));
+                    }
+                }
+            }
+
+            //beyond the source velocity range
+            let plane = if planes.is_empty() {
+                vec![f32::NAN; size]
+            } else {
+                map.apply(&regrid::combine(&planes, source.width * source.height))
+            };
+
+            let bytes: Vec<u8> = plane.iter().flat_map(|x| x.to_be_bytes()).collect();
+            f.write_all(&bytes)?;
+            written += bytes.len();
+        }
+
+        Ok(written)
+    })
+}
+
+//the deep-zoom tile pyramid description (202 while it is being built)
+async fn get_tile_pyramid(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    let dataset_id = match query.get("datasetId") {
+        Some(x) => x,
+        None => {
+            return HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: get_tile_pyramid/datasetId parameter not found</p>"
+                ));
+        }
+    };
+
+    let status = match pyramid::status(dataset_id) {
+        pyramid::PyramidStatus::Ready => {
+            return match fs::NamedFile::open(pyramid::info_path(dataset_id)) {
+                Ok(file) => file
+                    .set_content_type("application/json".parse().unwrap())
+                    .respond_to(&req),
+                Err(_) => HttpResponse::NotFound().finish(),
+            };
+        }
+        pyramid::PyramidStatus::Running => StatusCode::ACCEPTED,
+        pyramid::PyramidStatus::Missing => StatusCode::NOT_FOUND,
+    };
+
+    HttpResponseBuilder::new(status)
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("application/json")
+        .body(
+            json!({
+                "status" : if status == StatusCode::ACCEPTED { "running" } else { "unavailable" },
+            })
+            .to_string(),
+        )
+}
+
+//a single deep-zoom tile: ?datasetId=..&level=..&x=..&y=..
+async fn get_tile(req: HttpRequest, query: web::Query<HashMap<String, String>>) -> HttpResponse {
+    let param = |key: &str| query.get(key).and_then(|x| x.parse::<u32>().ok());
+
+    let (dataset_id, level, x, y) = match (
+        query.get("datasetId"),
+        param("level"),
+        param("x"),
+        param("y"),
+    ) {
+        (Some(dataset_id), Some(level), Some(x), Some(y)) if !dataset_id.contains("..") => {
+            (dataset_id, level, x, y)
+        }
+        _ => {
+            return HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: get_tile/datasetId, level, x or y parameter not found</p>"
+                ));
+        }
+    };
+
+    //fully transparent tiles are not stored
+    match fs::NamedFile::open(pyramid::tile_path(dataset_id, level, x, y)) {
+        Ok(file) => file.respond_to(&req),
+        Err(_) => HttpResponse::NoContent()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .finish(),
+    }
+}
+
+//start (or poll) the generation of a HiPS tile set from a 2-D image
+async fn make_hips(req: HttpRequest, query: web::Query<HashMap<String, String>>) -> HttpResponse {
+    let dataset_id = match query.get("datasetId") {
+        Some(x) => x.clone(),
+        None => {
+            return HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: make_hips/datasetId parameter not found</p>"
+                ));
+        }
+    };
+
+    let fitswebql_path = req.match_info().get("path").unwrap_or("fitswebql");
+
+    //the HiPS base URL to be given to a viewer
+    let url = format!(
+        "/{}/hips/{}/",
+        fitswebql_path,
+        utf8_percent_encode(&dataset_id.replace("/", "_"), NON_ALPHANUMERIC)
+    );
+
+    let status = match hips::status(&dataset_id) {
+        hips::HipsStatus::Ready => {
+            json!({ "status" : "ready", "url" : url })
+        }
+        hips::HipsStatus::Running => {
+            json!({ "status" : "running", "url" : url })
+        }
+        hips::HipsStatus::Failed(err) if !query.contains_key("retry") => {
+            json!({ "status" : "failed", "message" : err })
+        }
+        _ => {
+            if let Some(response) = wait_for_dataset(&dataset_id).await {
+                return response;
+            }
+
+            if hips::start_job(&dataset_id) {
+                let id = dataset_id.clone();
+
+                //reprojecting a large image takes a while, run it in the background
+                thread::spawn(move || {
+                    let fits = match DATASETS.read().get(&id) {
+                        Some(x) => x.clone(),
+                        None => {
+                            hips::end_job(&id, Err(String::from("dataset not found")));
+                            return;
+                        }
+                    };
+
+                    let fits = fits.read();
+
+                    {
+                        *fits.timestamp.write() = SystemTime::now();
+                    }
+
+                    hips::end_job(&id, fits.make_hips());
+                });
+            }
+
+            json!({ "status" : "running", "url" : url })
+        }
+    };
+
+    let code = match status["status"].as_str() {
+        Some("ready") => StatusCode::OK,
+        Some("failed") => StatusCode::UNPROCESSABLE_ENTITY,
+        _ => StatusCode::ACCEPTED,
+    };
+
+    HttpResponseBuilder::new(code)
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("application/json")
+        .body(status.to_string())
+}
+
+//serve the HiPS tiles and metadata from HIPSCACHE (readable from other origins, i.e. Aladin Lite)
+async fn get_hips_file(req: HttpRequest) -> HttpResponse {
+    let id = req.match_info().get("id").unwrap_or("");
+    let tail = req.match_info().get("tail").unwrap_or("");
+
+    let tail = if tail.is_empty() { "properties" } else { tail };
+
+    let is_safe = |x: &str| {
+        !x.is_empty()
+            && !x.contains("..")
+            && !x.starts_with('/')
+            && x.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "/_-.+".contains(c))
+    };
+
+    let filepath = hips::hips_directory(id).join(tail);
+
+    if !is_safe(tail) || id.contains("..") || !filepath.is_file() {
+        return HttpResponse::NotFound()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .append_header(("Access-Control-Allow-Origin", "*"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Critical Error</b>: HiPS file {}/{} not found</p>",
+                id, tail
+            ));
+    }
+
+    match fs::NamedFile::open(&filepath) {
+        Ok(file) => {
+            let mut response = file.respond_to(&req);
+            response.headers_mut().insert(
+                actix_web::http::header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                HeaderValue::from_static("*"),
+            );
+            response
+        }
+        Err(err) => HttpResponse::InternalServerError()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!("<p><b>Critical Error</b>: {}</p>", err)),
+    }
+}
+
+//render a spectral-cube flythrough into a video file in the background
+async fn export_video(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+    let dataset_id = match query.get("datasetId") {
+        Some(x) => x.clone(),
+        None => {
+            return HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: export_video/datasetId parameter not found</p>"
+                ));
+        }
+    };
+
+    let format = match export::ExportFormat::from_name(
+        query.get("format").map(|x| x.as_str()).unwrap_or("webm"),
+    ) {
+        Some(format) => format,
+        None => {
+            return HttpResponse::BadRequest()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: export_video: unsupported format (webm or mp4)</p>"
+                ));
+        }
+    };
+
+    let get_f64 = |key: &str| -> f64 {
+        query
+            .get(key)
+            .and_then(|x| x.parse::<f64>().ok())
+            .unwrap_or(std::f64::NAN)
+    };
+
+    let params = export::ExportParams {
+        frame_start: get_f64("frame_start"),
+        frame_end: get_f64("frame_end"),
+        ref_freq: get_f64("ref_freq").max(0.0),
+        fps: query
+            .get("fps")
+            .and_then(|x| x.parse::<u32>().ok())
+            .unwrap_or(10),
+        flux: query.get("flux").cloned(),
+        label: query.get("label").map(|x| x != "false").unwrap_or(true),
+        format: format,
+    };
+
+    if let Some(response) = wait_for_dataset(&dataset_id).await {
+        return response;
+    }
+
+    let job = export::start_job(&dataset_id, format);
+
+    {
+        let id = dataset_id.clone();
+        let job = job.clone();
+
+        //encoding hundreds of frames takes a while
+        thread::spawn(move || {
+            let fits = match DATASETS.read().get(&id) {
+                Some(x) => x.clone(),
+                None => {
+                    export::end_job(&job, Err(String::from("dataset not found")));
+                    return;
+                }
+            };
+
+            let fits = fits.read();
+
+            {
+                *fits.timestamp.write() = SystemTime::now();
+            }
+
+            export::end_job(&job, fits.export_video(&job, &params));
+        });
+    }
+
+    let status = json!({ "status" : "running", "job" : job, "progress" : 0.0 });
+
+    HttpResponseBuilder::new(StatusCode::ACCEPTED)
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("application/json")
+        .body(status.to_string())
+}
+
+async fn export_video_status(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    let job = query.get("job").map(|x| x.as_str()).unwrap_or("");
+
+    let fitswebql_path = req.match_info().get("path").unwrap_or("fitswebql");
+    let url = format!("/{}/get_video_export?job={}", fitswebql_path, job);
+
+    let status = match export::status(job) {
+        export::ExportStatus::Running(progress) => {
+            json!({ "status" : "running", "job" : job, "progress" : progress })
+        }
+        export::ExportStatus::Ready => {
+            json!({ "status" : "ready", "job" : job, "url" : url })
+        }
+        export::ExportStatus::Failed(err) => {
+            json!({ "status" : "failed", "job" : job, "message" : err })
+        }
+        export::ExportStatus::Missing => {
+            json!({ "status" : "missing", "job" : job })
+        }
+    };
+
+    let code = match status["status"].as_str() {
+        Some("ready") => StatusCode::OK,
+        Some("running") => StatusCode::ACCEPTED,
+        Some("failed") => StatusCode::UNPROCESSABLE_ENTITY,
+        _ => StatusCode::NOT_FOUND,
+    };
+
+    HttpResponseBuilder::new(code)
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("application/json")
+        .body(status.to_string())
+}
+
+async fn get_video_export(
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    let job = query.get("job").map(|x| x.as_str()).unwrap_or("");
+
+    let (filepath, format, dataset_id) = match export::job_file(job) {
+        Some(x) => x,
+        None => {
+            return HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: video export {} not found</p>",
+                    job
+                ));
+        }
+    };
+
+    match fs::NamedFile::open(&filepath) {
+        Ok(file) => {
+            let mut response = file.respond_to(&req);
+            let headers = response.headers_mut();
+
+            headers.insert(
+                actix_web::http::header::CONTENT_TYPE,
+                HeaderValue::from_static(format.mime_type()),
+            );
+
+            if let Ok(value) = HeaderValue::from_str(&format!(
+                "attachment; filename={}.{}",
+                dataset_id.replace("/", "_").replace("\"", ""),
+                format.extension()
+            )) {
+                headers.insert(actix_web::http::header::CONTENT_DISPOSITION, value);
+            }
+
+            response
+        }
+        Err(err) => HttpResponse::InternalServerError()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!("<p><b>Critical Error</b>: {}</p>", err)),
+    }
+}
+
+struct MoleculeStream {
+    rx: mpsc::Receiver<Molecule>,
+    first: bool,
+    end_transmission: bool,
+}
+
+impl MoleculeStream {
+    pub fn new(rx: mpsc::Receiver<Molecule>) -> MoleculeStream {
+        MoleculeStream {
+            rx: rx,
+            first: true,
+            end_transmission: false,
+        }
+    }
+}
+
+impl Stream for MoleculeStream {
+    type Item = Bytes;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        _cx: &mut futures::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        match self.rx.recv() {
+            Ok(molecule) => {
+                //println!("{:?}", molecule);
+
+                if self.first {
+                    self.first = false;
+
+                    //the first molecule
+                    Poll::Ready(Some(Bytes::from(format!(
+                        "{{\"molecules\" : [{}",
+                        molecule.to_json().to_string()
+                    ))))
+                } else {
+                    //subsequent molecules
+                    Poll::Ready(Some(Bytes::from(format!(
+                        ",{}",
+                        molecule.to_json().to_string()
+                    ))))
+                }
+            }
+            Err(err) => {
+                if self.end_transmission {
+                    println!("MoleculeStream: {}, terminating transmission", err);
+                    Poll::Ready(None)
+                } else {
+                    self.end_transmission = true;
+
+                    if self.first {
+                        //no molecules received; send an empty JSON array
+                        Poll::Ready(Some(Bytes::from("{\"molecules\" : []}")))
+                    } else {
+                        //end a JSON array
+                        Poll::Ready(Some(Bytes::from("]}")))
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn get_molecules(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+    let dataset_id = match query.get("datasetId") {
+        Some(x) => x,
+        None => {
+            return HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: get_molecules/datasetId parameter not found</p>"
+                ));
+        }
+    };
+
+    //freq_start
+    let freq_start = match query.get("freq_start") {
+        Some(x) => x,
+        None => {
+            return HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: get_molecules/freq_start parameter not found</p>"
+                ));
+        }
+    };
+
+    let freq_start = match freq_start.parse::<f64>() {
+        Ok(x) => x / 1e9, //[Hz -> GHz]
+        Err(_) => 0.0,
+    };
+
+    //freq_end
+    let freq_end = match query.get("freq_end") {
+        Some(x) => x,
+        None => {
+            return HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: get_molecules/freq_end parameter not found</p>"
+                ));
+        }
+    };
+
+    let freq_end = match freq_end.parse::<f64>() {
+        Ok(x) => x / 1e9, //[Hz -> GHz]
+        Err(_) => 0.0,
+    };
+
+    println!(
+        "[get_molecules] http request for {}: freq_start={}, freq_end={} [GHz]",
+        dataset_id, freq_start, freq_end
+    );
+
+    if freq_start == 0.0 || freq_end == 0.0 {
+        let datasets = DATASETS.read();
+
+        let fits = match datasets.get(dataset_id) {
+            Some(x) => x,
+            None => {
+                return HttpResponse::NotFound()
+                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                    .append_header(("Pragma", "no-cache"))
+                    .append_header(("Expires", "0"))
+                    .content_type("text/html")
+                    .body(format!("<p><b>Critical Error</b>: dataset not found</p>"));
+            }
+        };
+
+        let fits = match fits.try_read() {
+            Some(x) => x,
+            None => {
+                return HttpResponse::Accepted()
+                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                    .append_header(("Pragma", "no-cache"))
+                    .append_header(("Expires", "0"))
+                    .content_type("text/html")
+                    .body(format!(
+                        "<p><b>RwLock timeout</b>: {} not available yet</p>",
+                        dataset_id
+                    ));
+            }
+        };
+
+        if !fits.has_header {
+            if fits.is_dummy {
+                HttpResponse::Accepted()
+                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                    .append_header(("Pragma", "no-cache"))
+                    .append_header(("Expires", "0"))
+                    .content_type("text/html")
+                    .body(format!(
+                        "<p><b>spectral lines for {} not available yet</p>",
+                        dataset_id
+                    ))
+            } else {
+                HttpResponse::NotFound()
+                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                    .append_header(("Pragma", "no-cache"))
+                    .append_header(("Expires", "0"))
+                    .content_type("text/html")
+                    .body(format!(
+                        "<p><b>Critical Error</b>: spectral lines not found</p>"
+                    ))
+            }
+        } else {
+            if fits.is_optical {
+                HttpResponse::NotFound()
+                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                    .append_header(("Pragma", "no-cache"))
+                    .append_header(("Expires", "0"))
+                    .content_type("text/html")
+                    .body(format!(
+                        "<p><b>Critical Error</b>: spectral lines not found</p>"
+                    ))
+            } else {
+                let (freq_start, freq_end) = fits.get_frequency_range();
+
+                //stream molecules from sqlite
+                match stream_molecules(freq_start, freq_end) {
+                    Some(rx) => {
+                        let molecules_stream = MoleculeStream::new(rx);
+
+                        HttpResponse::Ok()
+                            .content_type("application/json")
+                            .streaming(molecules_stream.map(|x| Ok(x) as Result<Bytes, Error>))
+                    }
+                    None => HttpResponse::Ok()
+                        .content_type("application/json")
+                        .body(format!("{{\"molecules\" : []}}")),
+                }
+            }
+        }
     } else {
         //stream molecules from sqlite without waiting for a FITS header
         match stream_molecules(freq_start, freq_end) {
@@ -3838,518 +6429,1202 @@ async fn get_molecules(query: web::Query<HashMap<String, String>>) -> HttpRespon
                 let molecules_stream = MoleculeStream::new(rx);
 
                 HttpResponse::Ok()
-                    .content_type("application/json")
-                    .streaming(molecules_stream.map(|x| Ok(x) as Result<Bytes, Error>))
+                    .content_type("application/json")
+                    .streaming(molecules_stream.map(|x| Ok(x) as Result<Bytes, Error>))
+            }
+            None => HttpResponse::Ok()
+                .content_type("application/json")
+                .body(format!("{{\"molecules\" : []}}")),
+        }
+    }
+}
+
+struct FITSDataStream {
+    rx: mpsc::Receiver<Vec<u8>>,
+    length: usize,   //the expected number of bytes (0 if unknown)
+    sent: usize,     //bytes streamed so far
+    progress: usize, //the last reported progress [%]
+}
+
+impl FITSDataStream {
+    pub fn new(rx: mpsc::Receiver<Vec<u8>>) -> FITSDataStream {
+        FITSDataStream {
+            rx: rx,
+            length: 0,
+            sent: 0,
+            progress: 0,
+        }
+    }
+
+    //a stream of a known length reports its progress
+    pub fn with_length(rx: mpsc::Receiver<Vec<u8>>, length: usize) -> FITSDataStream {
+        FITSDataStream {
+            rx: rx,
+            length: length,
+            sent: 0,
+            progress: 0,
+        }
+    }
+}
+
+impl Stream for FITSDataStream {
+    type Item = Bytes;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        _cx: &mut futures::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        match self.rx.recv() {
+            Ok(v) => {
+                //print!("partial FITS chunk length: {}", v.len());
+                self.sent += v.len();
+
+                if self.length > 0 {
+                    let progress = (100 * self.sent / self.length).min(100);
+
+                    //every 10%
+                    if progress / 10 > self.progress / 10 {
+                        println!(
+                            "FITSDataStream: {}% ({}/{} bytes)",
+                            progress, self.sent, self.length
+                        );
+                        self.progress = progress;
+                    }
+                }
+
+                Poll::Ready(Some(Bytes::from(v)))
+            }
+            Err(err) => {
+                println!("FITSDataStream: {}, terminating transmission", err);
+
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+async fn get_fits(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+    /*#[cfg(not(feature = "jvo"))]
+    let dataset = "filename";
+
+    #[cfg(feature = "jvo")]*/
+    let dataset = "datasetId"; //JavaScript get_fits? always uses datasetId
+    let mut full_download = false;
+
+    let dataset_id = match query.get(dataset) {
+        Some(x) => vec![x.as_str()],
+        None => {
+            //try to read multiple datasets or filename,
+            //i.e. dataset1,dataset2,... or filename1,filename2,...
+            let mut v: Vec<&str> = Vec::new();
+            let mut count: u32 = 1;
+
+            loop {
+                let pattern = format!("{}{}", dataset, count);
+                count = count + 1;
+
+                match query.get(&pattern) {
+                    Some(x) => {
+                        v.push(x);
+                    }
+                    None => {
+                        break;
+                    }
+                };
+            }
+
+            //the last resort
+            if v.is_empty() {
+                return HttpResponse::NotFound()
+                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                    .append_header(("Pragma", "no-cache"))
+                    .append_header(("Expires", "0"))
+                    .content_type("text/html")
+                    .body(format!(
+                        "<p><b>Critical Error</b>: get_fits/{} parameter not found</p>",
+                        dataset
+                    ));
+            };
+
+            v
+        }
+    };
+
+    //x1
+    let x1 = match query.get("x1") {
+        Some(x) => x,
+        None => {
+            full_download = true;
+            "-1"
+            /*return HttpResponse::NotFound()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Critical Error</b>: get_fits/x1 parameter not found</p>"
+            ));*/
+        }
+    };
+
+    let x1 = match x1.parse::<i32>() {
+        Ok(x) => x,
+        Err(_) => 0,
+    };
+
+    //x2
+    let x2 = match query.get("x2") {
+        Some(x) => x,
+        None => {
+            full_download = true;
+            "-1"
+            /*return HttpResponse::NotFound()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Critical Error</b>: get_fits/x2 parameter not found</p>"
+            ));*/
+        }
+    };
+
+    let x2 = match x2.parse::<i32>() {
+        Ok(x) => x,
+        Err(_) => 0,
+    };
+
+    //y1
+    let y1 = match query.get("y1") {
+        Some(x) => x,
+        None => {
+            full_download = true;
+            "-1"
+            /*return HttpResponse::NotFound()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Critical Error</b>: get_fits/y1 parameter not found</p>"
+            ));*/
+        }
+    };
+
+    let y1 = match y1.parse::<i32>() {
+        Ok(x) => x,
+        Err(_) => 0,
+    };
+
+    //y2
+    let y2 = match query.get("y2") {
+        Some(x) => x,
+        None => {
+            full_download = true;
+            "-1"
+            /*return HttpResponse::NotFound()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Critical Error</b>: get_fits/y2 parameter not found</p>"
+            ));*/
+        }
+    };
+
+    let y2 = match y2.parse::<i32>() {
+        Ok(x) => x,
+        Err(_) => 0,
+    };
+
+    //frame_start
+    let frame_start = match query.get("frame_start") {
+        Some(x) => x,
+        None => {
+            full_download = true;
+            "0.0"
+            /*return HttpResponse::NotFound()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Critical Error</b>: get_fits/frame_start parameter not found</p>"
+            ));*/
+        }
+    };
+
+    let frame_start = match frame_start.parse::<f64>() {
+        Ok(x) => x,
+        Err(_) => 0.0,
+    };
+
+    //frame_end
+    let frame_end = match query.get("frame_end") {
+        Some(x) => x,
+        None => {
+            full_download = true;
+            "0.0"
+            /*return HttpResponse::NotFound()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Critical Error</b>: get_fits/frame_end parameter not found</p>"
+            ));*/
+        }
+    };
+
+    let frame_end = match frame_end.parse::<f64>() {
+        Ok(x) => x,
+        Err(_) => 0.0,
+    };
+
+    //ref_freq
+    let ref_freq = match query.get("ref_freq") {
+        Some(x) => x,
+        None => {
+            full_download = true;
+            "0.0"
+            /*return HttpResponse::NotFound()
+            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+            .append_header(("Pragma", "no-cache"))
+            .append_header(("Expires", "0"))
+            .content_type("text/html")
+            .body(format!(
+                "<p><b>Critical Error</b>: get_fits/ref_freq parameter not found</p>"
+            ));*/
+        }
+    };
+
+    let ref_freq = match ref_freq.parse::<f64>() {
+        Ok(x) => x,
+        Err(_) => 0.0,
+    };
+
+    //optional downsampling: bin (or bin_x and bin_y), spectral_bin and spectral_mode=mean|decimate
+    let get_factor = |key: &str| query.get(key).and_then(|x| x.parse::<usize>().ok());
+    let bin = get_factor("bin").unwrap_or(1);
+
+    let binning = cutout::Binning::new(
+        get_factor("bin_x").unwrap_or(bin),
+        get_factor("bin_y").unwrap_or(bin),
+        get_factor("spectral_bin").unwrap_or(1),
+        match query.get("spectral_mode").map(|x| x.as_str()) {
+            Some("decimate") => cutout::SpectralBinning::Decimate,
+            _ => cutout::SpectralBinning::Mean,
+        },
+    );
+
+    //the cut-out file format: fits (default), hdf5, zarr or npy
+    let format = match query.get("format") {
+        Some(x) => match cutout::CutoutFormat::from_name(x) {
+            Some(format) => format,
+            None => {
+                return HttpResponse::BadRequest()
+                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                    .append_header(("Pragma", "no-cache"))
+                    .append_header(("Expires", "0"))
+                    .content_type("text/html")
+                    .body(format!(
+                        "<p><b>Critical Error</b>: get_fits: unsupported format '{}'</p>",
+                        x
+                    ));
+            }
+        },
+        None => cutout::CutoutFormat::Fits,
+    };
+
+    for entry in &dataset_id {
+        if let Some(response) = rejected_response(entry) {
+            return response;
+        }
+    }
+
+    println!(
+        "[get_fits] http request for {:?}: x1={}, y1={}, x2={}, y2={}, frame_start={}, frame_end={}, ref_freq={}, binning={:?}, format={:?}",
+        dataset_id, x1, y1, x2, y2, frame_start, frame_end, ref_freq, binning, format
+    );
+
+    if dataset_id.len() > 1 && !full_download {
+        let mut ar = Builder::new(Vec::new());
+
+        //dataset_id.iter().for_each(|entry| {//not used for now; problems accessing inner data of Arc<RwLock<Builder>> later on
+
+        //for each dataset append it to the archives
+        for entry in dataset_id {
+            let datasets = DATASETS.read();
+
+            let fits = match datasets.get(entry) {
+                Some(x) => x,
+                None => {
+                    return HttpResponse::NotFound()
+                        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                        .append_header(("Pragma", "no-cache"))
+                        .append_header(("Expires", "0"))
+                        .content_type("text/html")
+                        .body(format!("<p><b>Critical Error</b>: dataset not found</p>"));
+                }
+            };
+
+            let fits = match fits.try_read() {
+                Some(x) => x,
+                None => {
+                    println!("[get_fits] error getting {} from DATASETS; aborting", entry);
+
+                    return HttpResponse::NotFound()
+                        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                        .append_header(("Pragma", "no-cache"))
+                        .append_header(("Expires", "0"))
+                        .content_type("text/html")
+                        .body(format!(
+                            "<p><b>Critical Error</b>: get_fits/{} not found in DATASETS</p>",
+                            entry
+                        ));
+                }
+            };
+
+            {
+                *fits.timestamp.write() = SystemTime::now();
+            }
+
+            if fits.has_data {
+                let region = match format {
+                    cutout::CutoutFormat::Fits => fits.get_cutout_data(
+                        x1,
+                        y1,
+                        x2,
+                        y2,
+                        frame_start,
+                        frame_end,
+                        ref_freq,
+                        &binning,
+                    ),
+                    //collect the streamed array formats
+                    _ => fits
+                        .get_cutout_stream(
+                            x1,
+                            y1,
+                            x2,
+                            y2,
+                            frame_start,
+                            frame_end,
+                            ref_freq,
+                            &binning,
+                            format,
+                        )
+                        .map(|(rx, _)| rx.iter().collect::<Vec<Vec<u8>>>().concat()),
+                };
+
+                match region {
+                    Some(region) => {
+                        let mut header = Header::new_gnu();
+                        if let Err(err) = header.set_path(format!(
+                            "{}-subregion.{}",
+                            entry.replace("/", "_"),
+                            format.extension()
+                        )) {
+                            println!("Critical Error: get_fits/tar/set_path error: {}", err);
+
+                            return HttpResponse::NotFound()
+                                .append_header((
+                                    "Cache-Control",
+                                    "no-cache, no-store, must-revalidate",
+                                ))
+                                .append_header(("Pragma", "no-cache"))
+                                .append_header(("Expires", "0"))
+                                .content_type("text/html")
+                                .body(format!(
+                                    "<p><b>Critical Error</b>: get_fits/tar/set_path error: {}</p>",
+                                    err
+                                ));
+                        }
+
+                        header.set_mode(420);
+
+                        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+                            Ok(n) => header.set_mtime(n.as_secs()),
+                            Err(_) => println!("SystemTime before UNIX EPOCH!"),
+                        };
+
+                        header.set_size(region.len() as u64);
+                        header.set_cksum();
+
+                        if let Err(err) = ar.append(&header, region.as_slice()) {
+                            println!("Critical Error: get_fits/tar/append error: {}", err);
+                            return HttpResponse::NotFound()
+                                .append_header((
+                                    "Cache-Control",
+                                    "no-cache, no-store, must-revalidate",
+                                ))
+                                .append_header(("Pragma", "no-cache"))
+                                .append_header(("Expires", "0"))
+                                .content_type("text/html")
+                                .body(format!(
+                                    "<p><b>Critical Error</b>: get_fits/tar/append error: {}</p>",
+                                    err
+                                ));
+                        }
+                    }
+                    None => println!(
+                        "partial FITS cut-out for {} did not produce any data",
+                        entry
+                    ),
+                }
+            }
+        }
+
+        match ar.into_inner() {
+            Ok(tarball) => {
+                let timestamp = Local::now();
+                let disposition_filename = format!(
+                    "attachment; filename=fits_web_ql_{}.tar",
+                    timestamp.format("%Y-%m-%d_%H-%M-%S")
+                );
+
+                HttpResponse::Ok()
+                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                    .append_header(("Pragma", "no-cache"))
+                    .append_header(("Expires", "0"))
+                    .content_type("application/force-download")
+                    .append_header(("Content-Encoding", "identity")) // disable compression
+                    .append_header(("Content-Disposition", disposition_filename))
+                    .append_header(("Content-Transfer-Encoding", "binary"))
+                    .append_header(("Accept-Ranges", "bytes"))
+                    .body(tarball)
             }
-            None => HttpResponse::Ok()
-                .content_type("application/json")
-                .body(format!("{{\"molecules\" : []}}")),
+            Err(err) => HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: get_fits tarball creation error: {}</p>",
+                    err
+                )),
+        }
+    } else {
+        //only one dataset, no need to use tarball, stream the data instead
+        let entry = dataset_id[0];
+
+        // get the URL filename, if there is any, otherwise use the dataset name
+        let filename = match query.get("filename") {
+            Some(f) => {
+                full_download = true;
+                // check if f is not an emptry string
+                if f.len() > 0 {
+                    String::from(f)
+                } else {
+                    format!("{}.fits", entry.replace("/", "_"))
+                }
+            }
+            None => {
+                full_download = false;
+                format!("{}.fits", entry.replace("/", "_"))
+            }
+        };
+
+        if full_download {
+            println!("full FITS download: as '{}'", filename);
+        }
+
+        let datasets = DATASETS.read();
+
+        let fits = match datasets.get(entry) {
+            Some(x) => x,
+            None => {
+                return HttpResponse::NotFound()
+                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                    .append_header(("Pragma", "no-cache"))
+                    .append_header(("Expires", "0"))
+                    .content_type("text/html")
+                    .body(format!("<p><b>Critical Error</b>: dataset not found</p>"));
+            }
+        };
+
+        let fits = match fits.try_read() {
+            Some(x) => x,
+            None => {
+                println!("[get_fits] error getting {} from DATASETS; aborting", entry);
+
+                return HttpResponse::NotFound()
+                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                    .append_header(("Pragma", "no-cache"))
+                    .append_header(("Expires", "0"))
+                    .content_type("text/html")
+                    .body(format!(
+                        "<p><b>Critical Error</b>: get_fits/{} not found in DATASETS</p>",
+                        entry
+                    ));
+            }
+        };
+
+        {
+            *fits.timestamp.write() = SystemTime::now();
+        }
+
+        if fits.has_data {
+            //streaming version (an immediate response, low memory footprint)
+            if !full_download {
+                match fits.get_cutout_stream(
+                    x1,
+                    y1,
+                    x2,
+                    y2,
+                    frame_start,
+                    frame_end,
+                    ref_freq,
+                    &binning,
+                    format,
+                ) {
+                    Some((rx, length)) => {
+                        let fits_stream = FITSDataStream::with_length(rx, length);
+
+                        let disposition_filename = format!(
+                            "attachment; filename={}-subregion.{}",
+                            entry.replace("/", "_"),
+                            format.extension()
+                        );
+
+                        return HttpResponse::Ok()
+                            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                            .append_header(("Pragma", "no-cache"))
+                            .append_header(("Expires", "0"))
+                            .content_type("application/force-download")
+                            .append_header(("Content-Encoding", "identity")) // disable compression
+                            .append_header(("Content-Disposition", disposition_filename))
+                            .append_header(("Content-Transfer-Encoding", "binary"))
+                            .append_header(("Accept-Ranges", "bytes"))
+                            //a known length lets the browser display the download progress
+                            .no_chunking(length as u64)
+                            .streaming(fits_stream.map(|x| Ok(x) as Result<Bytes, Error>));
+                    }
+                    None => {
+                        return HttpResponse::NotFound()
+                            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                            .append_header(("Pragma", "no-cache"))
+                            .append_header(("Expires", "0"))
+                            .content_type("text/html")
+                            .body(format!(
+                                "<p><b>Critical Error</b>: get_fits: {} contains no data</p>",
+                                entry
+                            ));
+                    }
+                }
+            } else {
+                match fits.get_full_stream() {
+                    Some(rx) => {
+                        let fits_stream = FITSDataStream::new(rx);
+
+                        let disposition_filename = format!("attachment; filename={}", filename);
+
+                        return HttpResponse::Ok()
+                            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                            .append_header(("Pragma", "no-cache"))
+                            .append_header(("Expires", "0"))
+                            .content_type("application/force-download")
+                            .append_header(("Content-Encoding", "identity")) // disable compression
+                            .append_header(("Content-Disposition", disposition_filename))
+                            .append_header(("Content-Transfer-Encoding", "binary"))
+                            .append_header(("Accept-Ranges", "bytes"))
+                            .streaming(fits_stream.map(|x| Ok(x) as Result<Bytes, Error>));
+                    }
+                    None => {
+                        return HttpResponse::NotFound()
+                            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                            .append_header(("Pragma", "no-cache"))
+                            .append_header(("Expires", "0"))
+                            .content_type("text/html")
+                            .body(format!(
+                                "<p><b>Critical Error</b>: get_fits: {} contains no data</p>",
+                                entry
+                            ));
+                    }
+                }
+            }
+        } else {
+            HttpResponse::NotFound()
+                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+                .append_header(("Pragma", "no-cache"))
+                .append_header(("Expires", "0"))
+                .content_type("text/html")
+                .body(format!(
+                    "<p><b>Critical Error</b>: get_fits: {} contains no data</p>",
+                    entry
+                ))
         }
     }
 }
 
-struct FITSDataStream {
-    rx: mpsc::Receiver<Vec<u8>>,
+fn view_error(status: StatusCode, msg: &str) -> HttpResponse {
+    HttpResponseBuilder::new(status)
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("text/html")
+        .body(format!("<p><b>Critical Error</b>: {}</p>", msg))
 }
 
-impl FITSDataStream {
-    pub fn new(rx: mpsc::Receiver<Vec<u8>>) -> FITSDataStream {
-        FITSDataStream { rx: rx }
+fn get_view_datasets(state: &serde_json::Value) -> Option<Vec<String>> {
+    let datasets: Vec<String> = state["datasets"]
+        .as_array()?
+        .iter()
+        .filter_map(|x| x.as_str())
+        .map(|x| x.to_string())
+        .collect();
+
+    if datasets.is_empty() {
+        None
+    } else {
+        Some(datasets)
     }
 }
 
-impl Stream for FITSDataStream {
-    type Item = Bytes;
+//the FITSWebQL.html query string re-opening the dataset(s) of a saved view
+fn get_view_query(id: &str, state: &serde_json::Value) -> Option<String> {
+    let datasets = get_view_datasets(state)?;
 
-    fn poll_next(
-        self: std::pin::Pin<&mut Self>,
-        _cx: &mut futures::task::Context<'_>,
-    ) -> Poll<Option<Self::Item>> {
-        match self.rx.recv() {
-            Ok(v) => {
-                //print!("partial FITS chunk length: {}", v.len());
-                Poll::Ready(Some(Bytes::from(v)))
-            }
-            Err(err) => {
-                println!("FITSDataStream: {}, terminating transmission", err);
+    let encode = |x: &str| utf8_percent_encode(x, NON_ALPHANUMERIC).to_string();
 
-                Poll::Ready(None)
-            }
+    //a dataset downloaded from an external URL
+    if let Some(x) = state["url"].as_str() {
+        return Some(format!("url={}&view_id={}", encode(x), id));
+    }
+
+    let mut query = String::new();
+
+    #[cfg(feature = "jvo")]
+    let dataset = "datasetId";
+
+    #[cfg(feature = "jvo")]
+    for key in &["db", "table"] {
+        if let Some(x) = state[*key].as_str() {
+            query.push_str(&format!("{}={}&", key, encode(x)));
         }
     }
-}
 
-async fn get_fits(query: web::Query<HashMap<String, String>>) -> HttpResponse {
-    /*#[cfg(not(feature = "jvo"))]
+    #[cfg(not(feature = "jvo"))]
     let dataset = "filename";
 
-    #[cfg(feature = "jvo")]*/
-    let dataset = "datasetId"; //JavaScript get_fits? always uses datasetId
-    let mut full_download = false;
+    #[cfg(not(feature = "jvo"))]
+    for key in &["dir", "ext"] {
+        if let Some(x) = state[*key].as_str() {
+            query.push_str(&format!("{}={}&", key, encode(x)));
+        }
+    }
 
-    let dataset_id = match query.get(dataset) {
-        Some(x) => vec![x.as_str()],
-        None => {
-            //try to read multiple datasets or filename,
-            //i.e. dataset1,dataset2,... or filename1,filename2,...
-            let mut v: Vec<&str> = Vec::new();
-            let mut count: u32 = 1;
+    if datasets.len() == 1 {
+        query.push_str(&format!("{}={}&", dataset, encode(&datasets[0])));
+    } else {
+        for (i, x) in datasets.iter().enumerate() {
+            query.push_str(&format!("{}{}={}&", dataset, i + 1, encode(x)));
+        }
+    }
 
-            loop {
-                let pattern = format!("{}{}", dataset, count);
-                count = count + 1;
+    if state["composite"].as_bool().unwrap_or(false) {
+        query.push_str("view=composite&");
+    }
 
-                match query.get(&pattern) {
-                    Some(x) => {
-                        v.push(x);
-                    }
-                    None => {
-                        break;
-                    }
-                };
-            }
+    if let Some(x) = state["flux"].as_str() {
+        query.push_str(&format!("flux={}&", encode(x)));
+    }
 
-            //the last resort
-            if v.is_empty() {
-                return HttpResponse::NotFound()
-                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                    .append_header(("Pragma", "no-cache"))
-                    .append_header(("Expires", "0"))
-                    .content_type("text/html")
-                    .body(format!(
-                        "<p><b>Critical Error</b>: get_fits/{} parameter not found</p>",
-                        dataset
-                    ));
-            };
+    query.push_str(&format!("view_id={}", id));
 
-            v
-        }
+    Some(query)
+}
+
+async fn save_view(req: HttpRequest, body: Bytes) -> HttpResponse {
+    let fitswebql_path = match req.match_info().get("path") {
+        Some(x) => x.to_string(),
+        None => return view_error(StatusCode::BAD_REQUEST, "save_view: no path"),
     };
 
-    //x1
-    let x1 = match query.get("x1") {
-        Some(x) => x,
-        None => {
-            full_download = true;
-            "-1"
-            /*return HttpResponse::NotFound()
-            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-            .append_header(("Pragma", "no-cache"))
-            .append_header(("Expires", "0"))
-            .content_type("text/html")
-            .body(format!(
-                "<p><b>Critical Error</b>: get_fits/x1 parameter not found</p>"
-            ));*/
+    let state: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(x) => x,
+        Err(err) => {
+            return view_error(
+                StatusCode::BAD_REQUEST,
+                &format!("save_view: invalid JSON: {}", err),
+            );
         }
     };
 
-    let x1 = match x1.parse::<i32>() {
-        Ok(x) => x,
-        Err(_) => 0,
+    if !state.is_object() || get_view_datasets(&state).is_none() {
+        return view_error(
+            StatusCode::BAD_REQUEST,
+            "save_view: a view must name at least one dataset",
+        );
+    }
+
+    let name = match state["name"].as_str() {
+        Some(x) => x.to_string(),
+        None => String::from(""),
     };
 
-    //x2
-    let x2 = match query.get("x2") {
-        Some(x) => x,
-        None => {
-            full_download = true;
-            "-1"
-            /*return HttpResponse::NotFound()
-            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-            .append_header(("Pragma", "no-cache"))
-            .append_header(("Expires", "0"))
-            .content_type("text/html")
-            .body(format!(
-                "<p><b>Critical Error</b>: get_fits/x2 parameter not found</p>"
-            ));*/
+    let id = match web::block(move || views::save_view(&name, &state)).await {
+        Ok(Ok(id)) => id,
+        Ok(Err(err)) => {
+            println!("error saving a view: {}", err);
+            return view_error(StatusCode::INTERNAL_SERVER_ERROR, "save_view: database error");
+        }
+        Err(err) => {
+            println!("error saving a view: {}", err);
+            return view_error(StatusCode::INTERNAL_SERVER_ERROR, "save_view: internal error");
         }
     };
 
-    let x2 = match x2.parse::<i32>() {
-        Ok(x) => x,
-        Err(_) => 0,
-    };
+    println!("saved view {}", id);
 
-    //y1
-    let y1 = match query.get("y1") {
-        Some(x) => x,
-        None => {
-            full_download = true;
-            "-1"
-            /*return HttpResponse::NotFound()
-            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-            .append_header(("Pragma", "no-cache"))
-            .append_header(("Expires", "0"))
-            .content_type("text/html")
-            .body(format!(
-                "<p><b>Critical Error</b>: get_fits/y1 parameter not found</p>"
-            ));*/
+    HttpResponse::Ok()
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("application/json")
+        .body(
+            json!({
+                "id" : id,
+                "url" : format!("/{}/view/{}", fitswebql_path, id),
+            })
+            .to_string(),
+        )
+}
+
+async fn fetch_view(id: String) -> Result<views::SavedView, HttpResponse> {
+    if !views::is_valid_id(&id) {
+        return Err(view_error(StatusCode::BAD_REQUEST, "invalid view id"));
+    }
+
+    let view_id = id.clone();
+
+    match web::block(move || views::load_view(&view_id)).await {
+        Ok(Ok(Some(view))) => Ok(view),
+        Ok(Ok(None)) => Err(view_error(
+            StatusCode::NOT_FOUND,
+            &format!("view {} not found", id),
+        )),
+        Ok(Err(err)) => {
+            println!("error loading view {}: {}", id, err);
+            Err(view_error(StatusCode::INTERNAL_SERVER_ERROR, "database error"))
         }
-    };
+        Err(err) => {
+            println!("error loading view {}: {}", id, err);
+            Err(view_error(StatusCode::INTERNAL_SERVER_ERROR, "internal error"))
+        }
+    }
+}
 
-    let y1 = match y1.parse::<i32>() {
-        Ok(x) => x,
-        Err(_) => 0,
+async fn get_view(query: web::Query<HashMap<String, String>>) -> HttpResponse {
+    let id = match query.get("id") {
+        Some(x) => x.to_string(),
+        None => return view_error(StatusCode::NOT_FOUND, "get_view/id parameter not found"),
     };
 
-    //y2
-    let y2 = match query.get("y2") {
-        Some(x) => x,
-        None => {
-            full_download = true;
-            "-1"
-            /*return HttpResponse::NotFound()
+    match fetch_view(id).await {
+        Ok(view) => HttpResponse::Ok()
             .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
             .append_header(("Pragma", "no-cache"))
             .append_header(("Expires", "0"))
-            .content_type("text/html")
-            .body(format!(
-                "<p><b>Critical Error</b>: get_fits/y2 parameter not found</p>"
-            ));*/
-        }
-    };
+            .content_type("application/json")
+            .body(view.to_json().to_string()),
+        Err(resp) => resp,
+    }
+}
 
-    let y2 = match y2.parse::<i32>() {
-        Ok(x) => x,
-        Err(_) => 0,
+//a permalink: redirect to FITSWebQL.html, which then restores the view state via get_view
+async fn view_permalink(req: HttpRequest) -> HttpResponse {
+    let fitswebql_path = match req.match_info().get("path") {
+        Some(x) => x.to_string(),
+        None => return view_error(StatusCode::BAD_REQUEST, "view: no path"),
     };
 
-    //frame_start
-    let frame_start = match query.get("frame_start") {
-        Some(x) => x,
-        None => {
-            full_download = true;
-            "0.0"
-            /*return HttpResponse::NotFound()
-            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-            .append_header(("Pragma", "no-cache"))
-            .append_header(("Expires", "0"))
-            .content_type("text/html")
-            .body(format!(
-                "<p><b>Critical Error</b>: get_fits/frame_start parameter not found</p>"
-            ));*/
-        }
+    let id = match req.match_info().get("id") {
+        Some(x) => x.to_string(),
+        None => return view_error(StatusCode::BAD_REQUEST, "view: no id"),
     };
 
-    let frame_start = match frame_start.parse::<f64>() {
+    let view = match fetch_view(id).await {
         Ok(x) => x,
-        Err(_) => 0.0,
+        Err(resp) => return resp,
     };
 
-    //frame_end
-    let frame_end = match query.get("frame_end") {
-        Some(x) => x,
-        None => {
-            full_download = true;
-            "0.0"
-            /*return HttpResponse::NotFound()
-            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-            .append_header(("Pragma", "no-cache"))
-            .append_header(("Expires", "0"))
-            .content_type("text/html")
-            .body(format!(
-                "<p><b>Critical Error</b>: get_fits/frame_end parameter not found</p>"
-            ));*/
-        }
+    match get_view_query(&view.id, &view.state) {
+        Some(query) => HttpResponse::Found()
+            .append_header((
+                "Location",
+                format!("/{}/FITSWebQL.html?{}", fitswebql_path, query),
+            ))
+            .finish(),
+        None => view_error(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &format!("view {} contains no datasets", view.id),
+        ),
+    }
+}
+
+fn is_admin(state: &WsSessionState, req: &HttpRequest) -> bool {
+    let admin_token = match state.admin_token {
+        Some(ref x) => x,
+        None => return false,
+    };
+
+    //only "Authorization: Bearer <token>", a URL parameter would end up in the access logs
+    let token = match req
+        .headers()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(x) => x.trim(),
+        None => return false,
     };
 
-    let frame_end = match frame_end.parse::<f64>() {
-        Ok(x) => x,
-        Err(_) => 0.0,
-    };
+    //a constant-time comparison
+    token.len() == admin_token.len()
+        && token
+            .bytes()
+            .zip(admin_token.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn admin_forbidden() -> HttpResponse {
+    HttpResponse::Forbidden()
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("text/html")
+        .body(format!(
+            "<p><b>Critical Error</b>: admin access denied</p>"
+        ))
+}
+
+fn admin_response(value: serde_json::Value) -> HttpResponse {
+    HttpResponse::Ok()
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("application/json")
+        .body(value.to_string())
+}
+
+fn admin_bad_request(what: &str) -> HttpResponse {
+    HttpResponse::BadRequest()
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("text/html")
+        .body(format!("<p><b>Critical Error</b>: {}</p>", what))
+}
+
+//JSON rather than HTML, the id comes from the caller
+fn admin_not_found(what: &str, id: &str) -> HttpResponse {
+    HttpResponse::NotFound()
+        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
+        .append_header(("Pragma", "no-cache"))
+        .append_header(("Expires", "0"))
+        .content_type("application/json")
+        .body(
+            json!({
+                "error" : format!("{} not found", what),
+                "id" : id,
+            })
+            .to_string(),
+        )
+}
+
+fn unix_timestamp(ts: SystemTime) -> u64 {
+    match ts.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(x) => x.as_secs(),
+        Err(_) => 0,
+    }
+}
+
+async fn admin_list_sessions(state: web::Data<WsSessionState>, req: HttpRequest) -> HttpResponse {
+    if !is_admin(&state, &req) {
+        return admin_forbidden();
+    }
 
-    //ref_freq
-    let ref_freq = match query.get("ref_freq") {
-        Some(x) => x,
-        None => {
-            full_download = true;
-            "0.0"
-            /*return HttpResponse::NotFound()
-            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-            .append_header(("Pragma", "no-cache"))
-            .append_header(("Expires", "0"))
-            .content_type("text/html")
-            .body(format!(
-                "<p><b>Critical Error</b>: get_fits/ref_freq parameter not found</p>"
-            ));*/
+    let sessions = match state.addr.send(server::ListSessions).await {
+        Ok(x) => x,
+        Err(err) => {
+            println!("[admin] error listing sessions: {}", err);
+            Vec::new()
         }
     };
 
-    let ref_freq = match ref_freq.parse::<f64>() {
+    let sessions: Vec<serde_json::Value> = sessions
+        .iter()
+        .map(|session| {
+            json!({
+                "sessionId" : session.id.to_string(),
+                "datasetId" : session.dataset_id,
+                "connected" : unix_timestamp(session.connected),
+            })
+        })
+        .collect();
+
+    admin_response(json!({ "sessions": sessions }))
+}
+
+async fn admin_list_datasets(state: web::Data<WsSessionState>, req: HttpRequest) -> HttpResponse {
+    if !is_admin(&state, &req) {
+        return admin_forbidden();
+    }
+
+    let sessions = match state.addr.send(server::ListSessions).await {
         Ok(x) => x,
-        Err(_) => 0.0,
+        Err(err) => {
+            println!("[admin] error listing sessions: {}", err);
+            Vec::new()
+        }
     };
 
-    println!(
-        "[get_fits] http request for {:?}: x1={}, y1={}, x2={}, y2={}, frame_start={}, frame_end={}, ref_freq={}",
-        dataset_id, x1, y1, x2, y2, frame_start, frame_end, ref_freq
-    );
+    let mut connected: HashMap<String, Vec<String>> = HashMap::new();
 
-    if dataset_id.len() > 1 && !full_download {
-        let mut ar = Builder::new(Vec::new());
+    for session in &sessions {
+        connected
+            .entry(session.dataset_id.clone())
+            .or_insert(Vec::new())
+            .push(session.id.to_string());
+    }
 
-        //dataset_id.iter().for_each(|entry| {//not used for now; problems accessing inner data of Arc<RwLock<Builder>> later on
+    let memory_usage = server::get_memory_usage();
 
-        //for each dataset append it to the archives
-        for entry in dataset_id {
-            let datasets = DATASETS.read();
+    let datasets = DATASETS.read();
+    let mut entries: Vec<serde_json::Value> = Vec::new();
 
-            let fits = match datasets.get(entry) {
-                Some(x) => x,
-                None => {
-                    return HttpResponse::NotFound()
-                        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                        .append_header(("Pragma", "no-cache"))
-                        .append_header(("Expires", "0"))
-                        .content_type("text/html")
-                        .body(format!("<p><b>Critical Error</b>: dataset not found</p>"));
-                }
-            };
+    for (key, value) in datasets.iter() {
+        let sessions = match connected.get(key) {
+            Some(x) => x.clone(),
+            None => Vec::new(),
+        };
 
-            let fits = match fits.try_read() {
-                Some(x) => x,
-                None => {
-                    println!("[get_fits] error getting {} from DATASETS; aborting", entry);
+        //do not block on datasets that are being written to
+        let entry = match value.try_read() {
+            Some(fits) => json!({
+                "datasetId" : key,
+                "state" : match registry::get_state(key) {
+                    Some(state) => state.to_json(),
+                    None => serde_json::Value::Null,
+                },
+                "memory" : fits.get_memory_footprint(),
+                "status_code" : fits.status_code,
+                "has_data" : fits.has_data,
+                "is_dummy" : fits.is_dummy,
+                "last_access" : unix_timestamp(*fits.timestamp.read()),
+                "sessions" : sessions,
+            }),
+            None => json!({
+                "datasetId" : key,
+                "state" : match registry::get_state(key) {
+                    Some(state) => state.to_json(),
+                    None => serde_json::Value::Null,
+                },
+                "locked" : true,
+                "sessions" : sessions,
+            }),
+        };
 
-                    return HttpResponse::NotFound()
-                        .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                        .append_header(("Pragma", "no-cache"))
-                        .append_header(("Expires", "0"))
-                        .content_type("text/html")
-                        .body(format!(
-                            "<p><b>Critical Error</b>: get_fits/{} not found in DATASETS</p>",
-                            entry
-                        ));
-                }
-            };
+        entries.push(entry);
+    }
 
-            {
-                *fits.timestamp.write() = SystemTime::now();
-            }
+    admin_response(json!({
+        "memory_budget" : server::MEMORY_BUDGET.load(std::sync::atomic::Ordering::SeqCst),
+        "memory_usage" : memory_usage,
+        "datasets" : entries,
+    }))
+}
 
-            if fits.has_data {
-                match fits.get_cutout_data(x1, y1, x2, y2, frame_start, frame_end, ref_freq) {
-                    Some(region) => {
-                        let mut header = Header::new_gnu();
-                        if let Err(err) =
-                            header.set_path(format!("{}-subregion.fits", entry.replace("/", "_")))
-                        {
-                            println!("Critical Error: get_fits/tar/set_path error: {}", err);
+async fn admin_evict_dataset(
+    state: web::Data<WsSessionState>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    if !is_admin(&state, &req) {
+        return admin_forbidden();
+    }
 
-                            return HttpResponse::NotFound()
-                                .append_header((
-                                    "Cache-Control",
-                                    "no-cache, no-store, must-revalidate",
-                                ))
-                                .append_header(("Pragma", "no-cache"))
-                                .append_header(("Expires", "0"))
-                                .content_type("text/html")
-                                .body(format!(
-                                    "<p><b>Critical Error</b>: get_fits/tar/set_path error: {}</p>",
-                                    err
-                                ));
-                        }
+    let dataset_id = match query.get("datasetId") {
+        Some(x) => x,
+        None => return admin_bad_request("datasetId parameter not found"),
+    };
 
-                        header.set_mode(420);
+    println!("[admin] evicting {}", dataset_id);
 
-                        match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                            Ok(n) => header.set_mtime(n.as_secs()),
-                            Err(_) => println!("SystemTime before UNIX EPOCH!"),
-                        };
+    if server::evict_dataset(dataset_id) {
+        admin_response(json!({ "datasetId": dataset_id, "evicted": true }))
+    } else {
+        admin_not_found("dataset", dataset_id)
+    }
+}
 
-                        header.set_size(region.len() as u64);
-                        header.set_cksum();
+async fn admin_drop_to_cache(
+    state: web::Data<WsSessionState>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    if !is_admin(&state, &req) {
+        return admin_forbidden();
+    }
 
-                        if let Err(err) = ar.append(&header, region.as_slice()) {
-                            println!("Critical Error: get_fits/tar/append error: {}", err);
-                            return HttpResponse::NotFound()
-                                .append_header((
-                                    "Cache-Control",
-                                    "no-cache, no-store, must-revalidate",
-                                ))
-                                .append_header(("Pragma", "no-cache"))
-                                .append_header(("Expires", "0"))
-                                .content_type("text/html")
-                                .body(format!(
-                                    "<p><b>Critical Error</b>: get_fits/tar/append error: {}</p>",
-                                    err
-                                ));
-                        }
-                    }
-                    None => println!(
-                        "partial FITS cut-out for {} did not produce any data",
-                        entry
-                    ),
-                }
-            }
-        }
+    let dataset_id = match query.get("datasetId") {
+        Some(x) => x,
+        None => return admin_bad_request("datasetId parameter not found"),
+    };
 
-        match ar.into_inner() {
-            Ok(tarball) => {
-                let timestamp = Local::now();
-                let disposition_filename = format!(
-                    "attachment; filename=fits_web_ql_{}.tar",
-                    timestamp.format("%Y-%m-%d_%H-%M-%S")
-                );
+    let fits = match DATASETS.read().get(dataset_id) {
+        Some(x) => x.clone(),
+        None => return admin_not_found("dataset", dataset_id),
+    };
 
-                HttpResponse::Ok()
-                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                    .append_header(("Pragma", "no-cache"))
-                    .append_header(("Expires", "0"))
-                    .content_type("application/force-download")
-                    .append_header(("Content-Encoding", "identity")) // disable compression
-                    .append_header(("Content-Disposition", disposition_filename))
-                    .append_header(("Content-Transfer-Encoding", "binary"))
-                    .append_header(("Accept-Ranges", "bytes"))
-                    .body(tarball)
-            }
-            Err(err) => HttpResponse::NotFound()
-                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                .append_header(("Pragma", "no-cache"))
-                .append_header(("Expires", "0"))
-                .content_type("text/html")
-                .body(format!(
-                    "<p><b>Critical Error</b>: get_fits tarball creation error: {}</p>",
-                    err
-                )),
-        }
-    } else {
-        //only one dataset, no need to use tarball, stream the data instead
-        let entry = dataset_id[0];
+    println!("[admin] writing {} to the cache", dataset_id);
 
-        // get the URL filename, if there is any, otherwise use the dataset name
-        let filename = match query.get("filename") {
-            Some(f) => {
-                full_download = true;
-                // check if f is not an emptry string
-                if f.len() > 0 {
-                    String::from(f)
-                } else {
-                    format!("{}.fits", entry.replace("/", "_"))
-                }
-            }
-            None => {
-                full_download = false;
-                format!("{}.fits", entry.replace("/", "_"))
-            }
-        };
+    //keep the dataset in memory, only persist it in FITSCACHE
+    thread::spawn(move || {
+        fits.read().drop_to_cache();
+    });
 
-        if full_download {
-            println!("full FITS download: as '{}'", filename);
-        }
+    admin_response(json!({ "datasetId": dataset_id, "cached": true }))
+}
+
+async fn admin_reload_dataset(
+    state: web::Data<WsSessionState>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    if !is_admin(&state, &req) {
+        return admin_forbidden();
+    }
+
+    let dataset_id = match query.get("datasetId") {
+        Some(x) => x.clone(),
+        None => return admin_bad_request("datasetId parameter not found"),
+    };
 
+    let (path, url, flux) = {
         let datasets = DATASETS.read();
 
-        let fits = match datasets.get(entry) {
+        let fits = match datasets.get(&dataset_id) {
             Some(x) => x,
-            None => {
-                return HttpResponse::NotFound()
-                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                    .append_header(("Pragma", "no-cache"))
-                    .append_header(("Expires", "0"))
-                    .content_type("text/html")
-                    .body(format!("<p><b>Critical Error</b>: dataset not found</p>"));
-            }
+            None => return admin_not_found("dataset", &dataset_id),
         };
 
-        let fits = match fits.try_read() {
-            Some(x) => x,
+        match fits.try_read() {
+            Some(fits) => (fits.path.clone(), fits.url.clone(), fits.flux.clone()),
             None => {
-                println!("[get_fits] error getting {} from DATASETS; aborting", entry);
-
-                return HttpResponse::NotFound()
-                    .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                    .append_header(("Pragma", "no-cache"))
-                    .append_header(("Expires", "0"))
+                return HttpResponse::Conflict()
                     .content_type("text/html")
-                    .body(format!(
-                        "<p><b>Critical Error</b>: get_fits/{} not found in DATASETS</p>",
-                        entry
-                    ));
+                    .body(format!("<p>{} is busy, try again later</p>", dataset_id));
             }
+        }
+    };
+
+    //the dataset is still loading (dummy) or its source is unknown
+    if path.is_empty() && url.is_empty() {
+        return HttpResponse::Conflict()
+            .content_type("text/html")
+            .body(format!("<p>{} cannot be reloaded</p>", dataset_id));
+    }
+
+    println!("[admin] reloading {} from '{}' '{}'", dataset_id, path, url);
+
+    server::evict_dataset(&dataset_id);
+
+    DATASETS.write().insert(
+        dataset_id.clone(),
+        Arc::new(RwLock::new(Box::new(fits::FITS::new(
+            &dataset_id,
+            &url,
+            &flux,
+        )))),
+    );
+
+    registry::queue(&dataset_id);
+
+    let server = state.addr.clone();
+    let my_data_id = dataset_id.clone();
+
+    //load FITS data in a new thread
+    thread::spawn(move || {
+        let bytes = if !path.is_empty() {
+            estimate_memory_footprint(std::path::Path::new(&path))
+        } else {
+            estimate_url_footprint(&url)
         };
 
-        {
-            *fits.timestamp.write() = SystemTime::now();
-        }
+        let fits = load_dataset(&my_data_id, &url, &flux, bytes, &server, || {
+            if !path.is_empty() {
+                fits::FITS::from_path(
+                    &my_data_id,
+                    &flux,
+                    std::path::Path::new(&path),
+                    &url,
+                    &server,
+                )
+            } else {
+                fits::FITS::from_url(&my_data_id, &flux, &url, &server)
+            }
+        });
+
+        publish_dataset(&my_data_id, fits);
+    });
+
+    admin_response(json!({ "datasetId": dataset_id, "reloading": true }))
+}
+
+async fn admin_cancel_load(
+    state: web::Data<WsSessionState>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    if !is_admin(&state, &req) {
+        return admin_forbidden();
+    }
+
+    let dataset_id = match query.get("datasetId") {
+        Some(x) => x,
+        None => return admin_bad_request("datasetId parameter not found"),
+    };
 
-        if fits.has_data {
-            //streaming version (an immediate response, low memory footprint)
-            if !full_download {
-                match fits.get_cutout_stream(x1, y1, x2, y2, frame_start, frame_end, ref_freq) {
-                    Some(rx) => {
-                        let fits_stream = FITSDataStream::new(rx);
+    println!("[admin] cancelling the load of {}", dataset_id);
 
-                        let disposition_filename = format!(
-                            "attachment; filename={}-subregion.fits",
-                            entry.replace("/", "_")
-                        );
+    if registry::cancel(dataset_id) {
+        admin_response(json!({ "datasetId": dataset_id, "cancelled": true }))
+    } else {
+        HttpResponse::Conflict()
+            .content_type("text/html")
+            .body(format!("<p>{} is not being loaded</p>", dataset_id))
+    }
+}
 
-                        return HttpResponse::Ok()
-                            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                            .append_header(("Pragma", "no-cache"))
-                            .append_header(("Expires", "0"))
-                            .content_type("application/force-download")
-                            .append_header(("Content-Encoding", "identity")) // disable compression
-                            .append_header(("Content-Disposition", disposition_filename))
-                            .append_header(("Content-Transfer-Encoding", "binary"))
-                            .append_header(("Accept-Ranges", "bytes"))
-                            .streaming(fits_stream.map(|x| Ok(x) as Result<Bytes, Error>));
-                    }
-                    None => {
-                        return HttpResponse::NotFound()
-                            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                            .append_header(("Pragma", "no-cache"))
-                            .append_header(("Expires", "0"))
-                            .content_type("text/html")
-                            .body(format!(
-                                "<p><b>Critical Error</b>: get_fits: {} contains no data</p>",
-                                entry
-                            ));
-                    }
-                }
-            } else {
-                match fits.get_full_stream() {
-                    Some(rx) => {
-                        let fits_stream = FITSDataStream::new(rx);
+async fn admin_disconnect_session(
+    state: web::Data<WsSessionState>,
+    req: HttpRequest,
+    query: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    if !is_admin(&state, &req) {
+        return admin_forbidden();
+    }
 
-                        let disposition_filename = format!("attachment; filename={}", filename);
+    let session_id = match query.get("sessionId") {
+        Some(x) => x,
+        None => return admin_bad_request("sessionId parameter not found"),
+    };
 
-                        return HttpResponse::Ok()
-                            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                            .append_header(("Pragma", "no-cache"))
-                            .append_header(("Expires", "0"))
-                            .content_type("application/force-download")
-                            .append_header(("Content-Encoding", "identity")) // disable compression
-                            .append_header(("Content-Disposition", disposition_filename))
-                            .append_header(("Content-Transfer-Encoding", "binary"))
-                            .append_header(("Accept-Ranges", "bytes"))
-                            .streaming(fits_stream.map(|x| Ok(x) as Result<Bytes, Error>));
-                    }
-                    None => {
-                        return HttpResponse::NotFound()
-                            .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                            .append_header(("Pragma", "no-cache"))
-                            .append_header(("Expires", "0"))
-                            .content_type("text/html")
-                            .body(format!(
-                                "<p><b>Critical Error</b>: get_fits: {} contains no data</p>",
-                                entry
-                            ));
-                    }
-                }
-            }
-        } else {
-            HttpResponse::NotFound()
-                .append_header(("Cache-Control", "no-cache, no-store, must-revalidate"))
-                .append_header(("Pragma", "no-cache"))
-                .append_header(("Expires", "0"))
-                .content_type("text/html")
-                .body(format!(
-                    "<p><b>Critical Error</b>: get_fits: {} contains no data</p>",
-                    entry
-                ))
-        }
+    let id = match Uuid::parse_str(session_id) {
+        Ok(x) => x,
+        Err(_) => return admin_bad_request("invalid sessionId"),
+    };
+
+    match state.addr.send(server::DisconnectSession { id: id }).await {
+        Ok(true) => admin_response(json!({ "sessionId": session_id, "disconnected": true })),
+        Ok(false) => admin_not_found("session", session_id),
+        Err(err) => HttpResponse::InternalServerError()
+            .content_type("text/html")
+            .body(format!("<p><b>Critical Error</b>: {}</p>", err)),
     }
 }
 
@@ -4433,6 +7708,176 @@ fn get_jvo_path(dataset_id: &String, db: &str, table: &str) -> Option<std::path:
     return None;
 }
 
+//a rough upper bound on the memory needed to hold a FITS file
+fn estimate_memory_footprint(filepath: &std::path::Path) -> usize {
+    match filepath.metadata() {
+        Ok(metadata) => metadata.len() as usize,
+        Err(_) => 0,
+    }
+}
+
+//the same for a dataset not in the FITSCACHE yet: the size of the remote file
+fn estimate_url_footprint(url: &str) -> usize {
+    let size = if s3::is_s3_url(url) {
+        s3::S3Object::open(url).ok().map(|object| object.size)
+    } else {
+        download::content_length(url)
+    };
+
+    match size {
+        Some(x) => x as usize,
+        None => {
+            println!("{}: unknown remote file size, no memory reserved", url);
+            0
+        }
+    }
+}
+
+//wait (queue) until the memory budget admits a new dataset; false means the load has been rejected
+fn admit_dataset(dataset_id: &str, bytes: usize, server: &Addr<server::SessionServer>) -> bool {
+    if server::MEMORY_BUDGET.load(std::sync::atomic::Ordering::SeqCst) == 0 {
+        return true;
+    }
+
+    let start = Instant::now();
+
+    loop {
+        match futures::executor::block_on(server.send(server::Admit {
+            dataset_id: dataset_id.to_string(),
+            bytes: bytes,
+        })) {
+            Ok(true) => return true,
+            Ok(false) => {}
+            Err(err) => {
+                println!("[admission control] {}: {}", dataset_id, err);
+                return true;
+            }
+        }
+
+        if start.elapsed() > std::time::Duration::from_secs(server::ADMISSION_TIMEOUT) {
+            println!(
+                "[admission control] {} ({} bytes) does not fit within the memory budget, rejecting",
+                dataset_id, bytes
+            );
+            return false;
+        }
+
+        server.do_send(server::WsMessage {
+            notification: String::from("queued: waiting for free memory"),
+            total: 1,
+            running: 0,
+            elapsed: start.elapsed(),
+            dataset_id: dataset_id.to_string(),
+        });
+
+        thread::sleep(std::time::Duration::from_secs(
+            server::ADMISSION_RETRY_INTERVAL,
+        ));
+    }
+}
+
+//a placeholder for a dataset that could not be admitted
+fn rejected_fits(dataset_id: &String, url: &String, flux: &String) -> fits::FITS {
+    let mut fits = fits::FITS::new(dataset_id, url, flux);
+    fits.is_dummy = false;
+    fits.status_code = 503;
+
+    fits
+}
+
+//fails a load that never completes (i.e. it panicked), releasing its memory reservation
+struct LoadGuard {
+    dataset_id: String,
+    completed: bool,
+}
+
+impl Drop for LoadGuard {
+    fn drop(&mut self) {
+        if self.completed {
+            return;
+        }
+
+        server::release_reservation(&self.dataset_id);
+        registry::finish(
+            &self.dataset_id,
+            registry::LoadState::Failed {
+                status_code: 500,
+                reason: String::from("critical error reading the FITS file"),
+            },
+        );
+    }
+}
+
+//run a dataset load through the registry: memory admission, a concurrent load slot, then <load>
+fn load_dataset<F>(
+    dataset_id: &String,
+    url: &String,
+    flux: &String,
+    bytes: usize,
+    server: &Addr<server::SessionServer>,
+    load: F,
+) -> fits::FITS
+where
+    F: FnOnce() -> fits::FITS,
+{
+    if !admit_dataset(dataset_id, bytes, server) {
+        return rejected_fits(dataset_id, url, flux);
+    }
+
+    let slot = match registry::acquire_slot(dataset_id) {
+        Some(x) => x,
+        None => {
+            println!("{}: the queued load has been cancelled", dataset_id);
+            return fits::FITS::new(dataset_id, url, flux);
+        }
+    };
+
+    //a panicking load still releases its slot and its memory reservation
+    let mut guard = LoadGuard {
+        dataset_id: dataset_id.clone(),
+        completed: false,
+    };
+
+    let fits = load();
+    guard.completed = true;
+
+    drop(slot);
+
+    fits
+}
+
+//make a loaded dataset available and wake up anyone waiting for it
+fn publish_dataset(dataset_id: &String, fits: fits::FITS) {
+    if registry::is_cancelled(dataset_id) {
+        println!("{}: the load has been cancelled, discarding the data", dataset_id);
+
+        let entry = DATASETS.write().remove(dataset_id);
+        server::release_reservation(dataset_id);
+        registry::finish(dataset_id, registry::LoadState::cancelled());
+
+        //drop the partial dataset outside of the DATASETS lock
+        drop(entry);
+        return;
+    }
+
+    let state = registry::LoadState::from_fits(&fits);
+    let fits = Arc::new(RwLock::new(Box::new(fits)));
+
+    DATASETS.write().insert(dataset_id.clone(), fits.clone());
+
+    //from now on the actual footprint counts against the memory budget
+    server::release_reservation(dataset_id);
+    registry::finish(dataset_id, state);
+
+    if fits.read().has_data {
+        thread::spawn(move || {
+            fits.read().make_data_histogram();
+            fits.read().make_channel_stats();
+            fits.read().make_tile_pyramid();
+        });
+    };
+}
+
 fn external_fits(
     fitswebql_path: &String,
     url: &str,
@@ -4441,12 +7886,42 @@ fn external_fits(
 ) -> HttpResponse {
     let mut has_fits: bool = true;
 
-    //does the entry exist in the datasets hash map?
-    let has_entry = {
-        let datasets = DATASETS.read();
-        datasets.contains_key(dataset_id)
+    //does the entry exist in the datasets hash map? (checked and inserted atomically so that
+    //concurrent requests for the same URL start only one download)
+    let (has_entry, failed) = {
+        let mut datasets = DATASETS.write();
+
+        //a failed download is retried (resumed) on the next request
+        let retry = match registry::get_state(dataset_id) {
+            Some(registry::LoadState::Failed { status_code, .. }) => status_code != 415,
+            _ => false,
+        };
+
+        if datasets.contains_key(dataset_id) && !retry {
+            (true, None)
+        } else {
+            if retry {
+                println!("{}: retrying a failed load", dataset_id);
+            }
+
+            let failed = datasets.insert(
+                dataset_id.to_string(),
+                Arc::new(RwLock::new(Box::new(fits::FITS::new(
+                    &dataset_id.to_string(),
+                    &url.to_string(),
+                    &"".to_owned(),
+                )))),
+            );
+
+            registry::queue(dataset_id);
+
+            (false, failed)
+        }
     };
 
+    //drop the failed dataset outside of the DATASETS lock
+    drop(failed);
+
     //if it does not exist set has_fits to false and load the FITS data
     if !has_entry {
         has_fits = false;
@@ -4455,50 +7930,48 @@ fn external_fits(
         let my_data_id = dataset_id.to_string();
         let my_server = server.clone();
 
-        DATASETS.write().insert(
-            my_data_id.clone(),
-            Arc::new(RwLock::new(Box::new(fits::FITS::new(
-                &my_data_id,
-                &my_url,
-                &"".to_owned(),
-            )))),
-        );
-
         //load FITS data in a new thread
         thread::spawn(move || {
             let filepath =
                 std::path::PathBuf::from(&format!("{}/{}.fits", fits::FITSCACHE, my_data_id));
 
-            let fits = if filepath.exists() {
-                fits::FITS::from_path(
-                    &my_data_id.clone(),
-                    &"".to_owned(),
-                    filepath.as_path(),
-                    &my_url.clone(),
-                    &my_server,
-                )
+            let bytes = if filepath.exists() {
+                estimate_memory_footprint(filepath.as_path())
             } else {
-                println!(
-                    "no cachefile found: {:?}, will download from the URL",
-                    filepath
-                );
-                fits::FITS::from_url(
-                    &my_data_id.clone(),
-                    &"".to_owned(),
-                    &my_url.clone(),
-                    &my_server,
-                )
+                estimate_url_footprint(&my_url)
             };
 
-            let fits = Arc::new(RwLock::new(Box::new(fits)));
-
-            DATASETS.write().insert(my_data_id.clone(), fits.clone());
+            let fits = load_dataset(
+                &my_data_id,
+                &my_url,
+                &"".to_owned(),
+                bytes,
+                &my_server,
+                || {
+                    if filepath.exists() {
+                        fits::FITS::from_path(
+                            &my_data_id.clone(),
+                            &"".to_owned(),
+                            filepath.as_path(),
+                            &my_url.clone(),
+                            &my_server,
+                        )
+                    } else {
+                        println!(
+                            "no cachefile found: {:?}, will download from the URL",
+                            filepath
+                        );
+                        fits::FITS::from_url(
+                            &my_data_id.clone(),
+                            &"".to_owned(),
+                            &my_url.clone(),
+                            &my_server,
+                        )
+                    }
+                },
+            );
 
-            if fits.read().has_data {
-                thread::spawn(move || {
-                    fits.read().make_data_histogram();
-                });
-            };
+            publish_dataset(&my_data_id, fits);
         });
     } else {
         //update the timestamp
@@ -4568,6 +8041,8 @@ fn internal_fits(
                 )))),
             );
 
+            registry::queue(&my_data_id);
+
             //load FITS data in a new thread
             thread::spawn(move || {
                 #[cfg(not(feature = "jvo"))]
@@ -4588,25 +8063,45 @@ fn internal_fits(
                     }
                 };
 
+                //virtual datasets (cube arithmetic) only exist in the FITSCACHE
+                let filepath = if filepath.exists() {
+                    filepath
+                } else {
+                    let cachefile = std::path::PathBuf::from(&format!(
+                        "{}/{}.fits",
+                        fits::FITSCACHE,
+                        my_data_id.replace("/", "_")
+                    ));
+
+                    if cachefile.exists() {
+                        cachefile
+                    } else {
+                        filepath
+                    }
+                };
+
                 println!("loading FITS data from {:?}", filepath);
 
-                let fits = fits::FITS::from_path(
-                    &my_data_id.clone(),
-                    &my_flux.clone(),
-                    filepath.as_path(),
+                let bytes = estimate_memory_footprint(filepath.as_path());
+
+                let fits = load_dataset(
+                    &my_data_id,
                     &"".to_owned(),
+                    &my_flux,
+                    bytes,
                     &my_server,
-                ); //from_path or from_path_mmap
-
-                let fits = Arc::new(RwLock::new(Box::new(fits)));
-
-                DATASETS.write().insert(my_data_id.clone(), fits.clone());
+                    || {
+                        fits::FITS::from_path(
+                            &my_data_id.clone(),
+                            &my_flux.clone(),
+                            filepath.as_path(),
+                            &"".to_owned(),
+                            &my_server,
+                        ) //from_path or from_path_mmap
+                    },
+                );
 
-                if fits.read().has_data {
-                    thread::spawn(move || {
-                        fits.read().make_data_histogram();
-                    });
-                };
+                publish_dataset(&my_data_id, fits);
             });
         } else {
             //update the timestamp
@@ -4823,12 +8318,12 @@ fn http_fits_response(
             html.push_str(&format!("data-datasetId{}='{}' ", i + 1, dataset_id[i]));
         }
 
-        if composite && dataset_id.len() <= 3 {
+        if composite && dataset_id.len() <= composite::MAX_CHANNELS {
             html.push_str("data-composite='1' ");
         }
     }
 
-    html.push_str(&format!("data-root-path='/{}/' data-server-version='{}' data-server-string='{}' data-server-mode='{}' data-has-fits='{}'></div>\n", fitswebql_path, VERSION_STRING, SERVER_STRING, SERVER_MODE, has_fits));
+    html.push_str(&format!("data-root-path='/{}/' data-server-version='{}' data-server-string='{}' data-server-mode='{}' data-has-fits='{}' data-has-av1='{}'></div>\n", fitswebql_path, VERSION_STRING, SERVER_STRING, SERVER_MODE, has_fits, cfg!(feature = "av1")));
 
     // scrollIntoView with ZenScroll (the original one does not work in Safari)
     html.push_str("<script src=\"https://cdn.jsdelivr.net/gh/jvo203/fits_web_ql/htdocs/fitswebql/zenscroll-min.js\"></script>\n");
@@ -5054,6 +8549,15 @@ async fn main() {
     let mut server_path = String::from(SERVER_PATH);
     let mut server_address = String::from(SERVER_ADDRESS);
     let mut home_dir = dirs::home_dir();
+    let mut admin_token = env::var("FITSWEBQL_ADMIN_TOKEN").ok();
+    let mut catalogue_roots: Vec<std::path::PathBuf> = match env::var("FITSWEBQL_CATALOGUE") {
+        Ok(x) => x
+            .split(':')
+            .filter(|x| !x.is_empty())
+            .map(std::path::PathBuf::from)
+            .collect(),
+        Err(_) => Vec::new(),
+    };
     let args: Vec<String> = env::args().collect();
 
     if args.len() > 2 {
@@ -5081,6 +8585,45 @@ async fn main() {
                 server_address = value.clone();
             }
 
+            if key == "--memory-budget" {
+                match value.parse::<usize>() {
+                    Ok(budget) => server::MEMORY_BUDGET
+                        .store(budget * 1024 * 1024, std::sync::atomic::Ordering::SeqCst),
+                    Err(err) => println!(
+                        "error parsing the memory budget [MB]: {}, defaulting to unlimited",
+                        err
+                    ),
+                }
+            }
+
+            if key == "--max-loads" {
+                match value.parse::<usize>() {
+                    Ok(count) => registry::CONCURRENT_LOADS
+                        .store(count.max(1), std::sync::atomic::Ordering::SeqCst),
+                    Err(err) => println!(
+                        "error parsing the number of concurrent loads: {}, defaulting to {}",
+                        err,
+                        registry::MAX_CONCURRENT_LOADS
+                    ),
+                }
+            }
+
+            if key == "--admin-token" {
+                admin_token = Some(value.clone());
+            }
+
+            if key == "--catalogue" {
+                catalogue_roots.push(std::path::PathBuf::from(value));
+            }
+
+            if key == "--s3-endpoint" {
+                s3::configure(Some(value.clone()), None);
+            }
+
+            if key == "--s3-region" {
+                s3::configure(None, Some(value.clone()));
+            }
+
             if key == "--home" {
                 let path = std::path::PathBuf::from(value);
 
@@ -5101,6 +8644,48 @@ async fn main() {
         server_address, server_port, server_path
     );
 
+    match server::MEMORY_BUDGET.load(std::sync::atomic::Ordering::SeqCst) {
+        0 => println!("dataset memory budget: unlimited"),
+        budget => println!("dataset memory budget: {} MB", budget / (1024 * 1024)),
+    }
+
+    match admin_token {
+        Some(_) => println!("the administrative interface /admin/* is enabled"),
+        None => println!(
+            "the administrative interface is disabled, use '--admin-token XXXX' to enable it"
+        ),
+    }
+
+    catalogue_roots.retain(|root| {
+        if root.is_dir() {
+            println!("FITS catalogue root: {:?}", root);
+            true
+        } else {
+            println!(
+                "the FITS catalogue root {:?} is not a directory, ignoring it",
+                root
+            );
+            false
+        }
+    });
+
+    catalogue::start(catalogue_roots);
+
+    {
+        let s3 = s3::config();
+
+        println!(
+            "object storage (s3://) endpoint: {}, region: {}, credentials: {}",
+            s3.endpoint,
+            s3.region,
+            if s3.access_key.is_some() && s3.secret_key.is_some() {
+                "yes"
+            } else {
+                "none (anonymous)"
+            }
+        );
+    }
+
     remove_symlinks(None);
 
     //splatalogue sqlite db integration
@@ -5127,6 +8712,7 @@ async fn main() {
             let state = Data::new(WsSessionState {
                 addr: server.clone(),
                 home_dir: home_dir.clone(),
+                admin_token: admin_token.clone(),
             });
 
             App::new()
@@ -5144,10 +8730,34 @@ async fn main() {
                 .route("/{path}/FITSWebQL.html", web::get().to(fitswebql_entry))
                 .service(web::resource("/{path}/websocket/{id}").to(websocket_entry))                
                 .route("/get_directory", web::get().to(directory_handler))
+                .route("/get_thumbnail", web::get().to(get_thumbnail))
                 .route("/{path}/get_image", web::get().to(get_image))
                 .route("/{path}/get_spectrum", web::get().to(get_spectrum))
                 .route("/{path}/get_molecules", web::get().to(get_molecules))
                 .route("/{path}/get_fits", web::get().to(get_fits))
+                .route("/{path}/get_snapshot", web::get().to(get_snapshot))
+                .route("/{path}/get_noise_stats", web::get().to(get_noise_stats))
+                .route("/{path}/search_catalogue", web::get().to(search_catalogue))
+                .route("/{path}/get_composite", web::get().to(get_composite))
+                .route("/{path}/make_hips", web::get().to(make_hips))
+                .route("/{path}/make_virtual", web::get().to(make_virtual))
+                .route("/{path}/make_regrid", web::get().to(make_regrid))
+                .route("/{path}/export_video", web::get().to(export_video))
+                .route("/{path}/export_video_status", web::get().to(export_video_status))
+                .route("/{path}/get_video_export", web::get().to(get_video_export))
+                .route("/{path}/get_tile_pyramid", web::get().to(get_tile_pyramid))
+                .route("/{path}/get_tile", web::get().to(get_tile))
+                .route("/{path}/hips/{id}/{tail:.*}", web::get().to(get_hips_file))
+                .route("/{path}/save_view", web::post().to(save_view))
+                .route("/{path}/get_view", web::get().to(get_view))
+                .route("/{path}/view/{id}", web::get().to(view_permalink))
+                .route("/admin/datasets", web::get().to(admin_list_datasets))
+                .route("/admin/sessions", web::get().to(admin_list_sessions))
+                .route("/admin/evict", web::post().to(admin_evict_dataset))
+                .route("/admin/drop_to_cache", web::post().to(admin_drop_to_cache))
+                .route("/admin/reload", web::post().to(admin_reload_dataset))
+                .route("/admin/cancel", web::post().to(admin_cancel_load))
+                .route("/admin/disconnect", web::post().to(admin_disconnect_session))
                 .service(fs::Files::new("/", "htdocs").index_file(index_file))
         })
         .workers(num_workers)
@@ -0,0 +1,247 @@
+//per-channel robust noise statistics: median, MAD-based RMS, blanked fraction and the data range
+
+//the MAD -> standard deviation conversion factor of Gaussian noise
+const MAD_TO_RMS: f32 = 1.4826;
+
+/// the robust statistics of a single channel (frame), in the data units (BUNIT)
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelStats {
+    pub median: f32,
+    pub rms: f32,          //1.4826 x the median absolute deviation
+    pub nan_fraction: f32, //the fraction of invalid (blanked) pixels
+    pub min: f32,
+    pub max: f32,
+    pub count: usize, //the number of valid pixels
+}
+
+//the median of finite values, reorders the slice
+fn select_median(values: &mut [f32]) -> f32 {
+    let len = values.len();
+
+    if len == 0 {
+        return f32::NAN;
+    }
+
+    let (lower, middle, _) = values.select_nth_unstable_by(len / 2, |a, b| a.total_cmp(b));
+    let middle = *middle;
+
+    if len % 2 == 1 {
+        middle
+    } else {
+        //the largest value of the lower half
+        let below = lower.iter().fold(f32::MIN, |acc, x| acc.max(*x));
+        (below + middle) / 2.0
+    }
+}
+
+impl ChannelStats {
+    /// the statistics of the valid values of a frame with <total> pixels, reuses the buffer
+    pub fn new(values: &mut Vec<f32>, total: usize) -> ChannelStats {
+        let count = values.len();
+
+        let nan_fraction = if total > 0 {
+            1.0 - (count as f32) / (total as f32)
+        } else {
+            1.0
+        };
+
+        if count == 0 {
+            return ChannelStats {
+                median: f32::NAN,
+                rms: f32::NAN,
+                nan_fraction: nan_fraction,
+                min: f32::NAN,
+                max: f32::NAN,
+                count: 0,
+            };
+        }
+
+        let (min, max) = values
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), x| (lo.min(*x), hi.max(*x)));
+
+        let median = select_median(values);
+
+        //the absolute deviations overwrite the values
+        values.iter_mut().for_each(|x| *x = (*x - median).abs());
+        let mad = select_median(values);
+
+        ChannelStats {
+            median: median,
+            rms: MAD_TO_RMS * mad,
+            nan_fraction: nan_fraction,
+            min: min,
+            max: max,
+            count: count,
+        }
+    }
+
+    /// the statistics of a frame with invalid pixels set to NaN
+    pub fn from_frame(frame: &[f32]) -> ChannelStats {
+        let mut values: Vec<f32> = frame.iter().filter(|x| x.is_finite()).copied().collect();
+        ChannelStats::new(&mut values, frame.len())
+    }
+}
+
+/// a row of the noise table: the channel (1-based), its frequency [GHz] and velocity [km/s]
+pub struct NoiseRow {
+    pub channel: usize,
+    pub frequency: f64,
+    pub velocity: f64,
+    pub stats: ChannelStats,
+}
+
+//an empty CSV field for NaN
+fn csv_field<T: std::fmt::Display>(x: T, finite: bool) -> String {
+    if finite {
+        format!("{}", x)
+    } else {
+        String::new()
+    }
+}
+
+/// the noise table as CSV, one line per channel
+pub fn to_csv(rows: &[NoiseRow], bunit: &str) -> String {
+    let unit = if bunit.is_empty() {
+        String::new()
+    } else {
+        format!(" [{}]", bunit)
+    };
+
+    let mut csv = format!(
+        "channel,frequency [GHz],velocity [km/s],median{0},rms{0},nan_fraction,min{0},max{0},count\n",
+        unit
+    );
+
+    for row in rows {
+        let s = &row.stats;
+
+        csv.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{}\n",
+            row.channel,
+            csv_field(row.frequency, row.frequency.is_finite()),
+            csv_field(row.velocity, row.velocity.is_finite()),
+            csv_field(s.median, s.median.is_finite()),
+            csv_field(s.rms, s.rms.is_finite()),
+            s.nan_fraction,
+            csv_field(s.min, s.min.is_finite()),
+            csv_field(s.max, s.max.is_finite()),
+            s.count
+        ));
+    }
+
+    csv
+}
+
+//NaN is not valid JSON
+fn json_number(x: f64) -> serde_json::Value {
+    if x.is_finite() {
+        json!(x)
+    } else {
+        serde_json::Value::Null
+    }
+}
+
+/// the noise table as JSON: the column arrays, null for NaN
+pub fn to_json(dataset_id: &str, rows: &[NoiseRow], bunit: &str) -> String {
+    let column = |f: &dyn Fn(&NoiseRow) -> f64| -> Vec<serde_json::Value> {
+        rows.iter().map(|row| json_number(f(row))).collect()
+    };
+
+    let value = json!({
+        "datasetId" : dataset_id,
+        "BUNIT" : bunit,
+        "channel" : rows.iter().map(|row| row.channel).collect::<Vec<usize>>(),
+        "frequency" : column(&|row| row.frequency),
+        "velocity" : column(&|row| row.velocity),
+        "median" : column(&|row| row.stats.median as f64),
+        "rms" : column(&|row| row.stats.rms as f64),
+        "nan_fraction" : column(&|row| row.stats.nan_fraction as f64),
+        "min" : column(&|row| row.stats.min as f64),
+        "max" : column(&|row| row.stats.max as f64),
+        "count" : rows.iter().map(|row| row.stats.count).collect::<Vec<usize>>(),
+    });
+
+    value.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(channel: usize, frame: &[f32]) -> NoiseRow {
+        NoiseRow {
+            channel: channel,
+            frequency: 100.0 + channel as f64,
+            velocity: f64::NAN,
+            stats: ChannelStats::from_frame(frame),
+        }
+    }
+
+    #[test]
+    fn medians() {
+        assert!(select_median(&mut []).is_nan());
+        assert_eq!(select_median(&mut [3.0]), 3.0);
+        assert_eq!(select_median(&mut [5.0, 1.0, 3.0]), 3.0);
+        assert_eq!(select_median(&mut [4.0, 1.0, 3.0, 2.0]), 2.5);
+        assert_eq!(select_median(&mut [-1.0, -1.0, 7.0, -1.0]), -1.0);
+    }
+
+    #[test]
+    fn robust_statistics() {
+        //one outlier does not affect the median and the MAD
+        let frame = [
+            1.0,
+            2.0,
+            3.0,
+            4.0,
+            1000.0,
+            f32::NAN,
+            f32::INFINITY,
+            f32::NAN,
+        ];
+        let stats = ChannelStats::from_frame(&frame);
+
+        assert_eq!(stats.count, 5);
+        assert_eq!(stats.median, 3.0);
+        assert_eq!(stats.rms, MAD_TO_RMS);
+        assert_eq!(stats.nan_fraction, 3.0 / 8.0);
+        assert_eq!((stats.min, stats.max), (1.0, 1000.0));
+
+        let stats = ChannelStats::from_frame(&[f32::NAN; 4]);
+        assert_eq!(stats.count, 0);
+        assert_eq!(stats.nan_fraction, 1.0);
+        assert!(stats.median.is_nan() && stats.rms.is_nan());
+
+        assert_eq!(ChannelStats::from_frame(&[]).nan_fraction, 1.0);
+    }
+
+    #[test]
+    fn csv_table() {
+        let rows = [row(1, &[1.0, 3.0]), row(2, &[f32::NAN])];
+        let csv = to_csv(&rows, "Jy/beam");
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(
+            lines[0],
+            "channel,frequency [GHz],velocity [km/s],median [Jy/beam],rms [Jy/beam],nan_fraction,min [Jy/beam],max [Jy/beam],count"
+        );
+        assert_eq!(lines[1], "1,101,,2,1.4826,0,1,3,2");
+        assert_eq!(lines[2], "2,102,,,,1,,,0");
+        assert!(to_csv(&[], "").starts_with("channel,frequency [GHz],velocity [km/s],median,rms,"));
+    }
+
+    #[test]
+    fn json_table() {
+        let rows = [row(1, &[1.0, 3.0]), row(2, &[f32::NAN])];
+        let json: serde_json::Value = serde_json::from_str(&to_json("cube", &rows, "K")).unwrap();
+
+        assert_eq!(json["datasetId"], "cube");
+        assert_eq!(json["BUNIT"], "K");
+        assert_eq!(json["channel"], json!([1, 2]));
+        assert_eq!(json["frequency"], json!([101.0, 102.0]));
+        assert_eq!(json["velocity"], json!([null, null]));
+        assert_eq!(json["median"], json!([2.0, null]));
+        assert_eq!(json["count"], json!([2, 0]));
+    }
+}
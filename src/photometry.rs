@@ -0,0 +1,560 @@
+//region photometry: aperture sums with beam-area and background-annulus corrections
+
+use crate::noise::ChannelStats;
+
+/// a circular or rectangular aperture in 0-based image pixels (inclusive bounds)
+#[derive(Debug, Clone, Copy)]
+pub struct Aperture {
+    pub x1: usize,
+    pub y1: usize,
+    pub x2: usize,
+    pub y2: usize,
+    pub circle: bool,
+}
+
+impl Aperture {
+    /// the centre and the radius of a circle, as in FITS::get_spectrum
+    pub fn centre(&self) -> (usize, usize) {
+        ((self.x1 + self.x2) >> 1, (self.y1 + self.y2) >> 1)
+    }
+
+    pub fn radius(&self) -> usize {
+        ((self.x2 - self.x1) >> 1).min((self.y2 - self.y1) >> 1)
+    }
+
+    fn contains(&self, x: usize, y: usize) -> bool {
+        if !self.circle {
+            return true;
+        }
+
+        let (cx, cy) = self.centre();
+        let r = self.radius();
+
+        let dx = x.abs_diff(cx);
+        let dy = y.abs_diff(cy);
+
+        dx * dx + dy * dy <= r * r
+    }
+}
+
+/// a background annulus around the aperture centre, the radii in pixels
+#[derive(Debug, Clone, Copy)]
+pub struct Annulus {
+    pub inner: f64,
+    pub outer: f64,
+}
+
+/// the photometry of a single image (frame)
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub sum: f64,        //the background-subtracted sum of the pixel values [BUNIT]
+    pub flux: f64,       //the total flux density (the sum divided by the beam area)
+    pub flux_error: f64, //the noise of the flux density, NaN when unknown
+    pub peak: f32,
+    pub mean: f32,
+    pub rms: f32,            //the standard deviation of the pixels within the aperture
+    pub pixels: usize,       //the number of valid pixels within the aperture
+    pub background: f32,     //the annulus median per pixel, 0 without an annulus
+    pub background_rms: f32, //the robust RMS of the annulus
+}
+
+/// measure an image (invalid pixels are NaN); <beam> is the beam area in pixels for
+/// per-beam units and <noise> the per-pixel noise used when there is no annulus
+pub fn measure(
+    pixels: &[f32],
+    width: usize,
+    height: usize,
+    aperture: &Aperture,
+    annulus: Option<Annulus>,
+    beam: Option<f64>,
+    noise: f32,
+) -> Measurement {
+    let (background, background_rms) = match annulus {
+        Some(annulus) => {
+            let (cx, cy) = aperture.centre();
+            let (cx, cy) = (cx as f64, cy as f64);
+
+            let x1 = (cx - annulus.outer).floor().max(0.0) as usize;
+            let y1 = (cy - annulus.outer).floor().max(0.0) as usize;
+            let x2 = ((cx + annulus.outer).ceil() as usize).min(width - 1);
+            let y2 = ((cy + annulus.outer).ceil() as usize).min(height - 1);
+
+            let mut values: Vec<f32> = Vec::new();
+            let mut total = 0;
+
+            for y in y1..=y2 {
+                for x in x1..=x2 {
+                    let r = ((x as f64 - cx).powi(2) + (y as f64 - cy).powi(2)).sqrt();
+
+                    if r >= annulus.inner && r <= annulus.outer {
+                        total += 1;
+
+                        let value = pixels[y * width + x];
+
+                        if value.is_finite() {
+                            values.push(value);
+                        }
+                    }
+                }
+            }
+
+            let stats = ChannelStats::new(&mut values, total);
+
+            if stats.count > 0 {
+                (stats.median, stats.rms)
+            } else {
+                (0.0, f32::NAN)
+            }
+        }
+        None => (0.0, noise),
+    };
+
+    let mut sum = 0.0_f64;
+    let mut sum2 = 0.0_f64;
+    let mut peak = f32::NAN;
+    let mut count = 0;
+
+    for y in aperture.y1..=aperture.y2 {
+        for x in aperture.x1..=aperture.x2 {
+            let value = pixels[y * width + x];
+
+            if value.is_finite() && aperture.contains(x, y) {
+                let value = value - background;
+
+                sum += value as f64;
+                sum2 += (value as f64) * (value as f64);
+                peak = peak.max(value); //NaN at first
+                count += 1;
+            }
+        }
+    }
+
+    let (mean, rms) = if count > 0 {
+        let mean = sum / count as f64;
+        let variance = (sum2 / count as f64 - mean * mean).max(0.0);
+
+        (mean as f32, variance.sqrt() as f32)
+    } else {
+        (f32::NAN, f32::NAN)
+    };
+
+    //the noise of a sum of N pixels correlated over the beam: sigma * sqrt(N * beam)
+    let (flux, flux_error) = match beam {
+        _ if count == 0 => (f64::NAN, f64::NAN),
+        Some(beam) => (
+            sum / beam,
+            (background_rms as f64) * (count as f64 * beam).sqrt() / beam,
+        ),
+        None => (sum, (background_rms as f64) * (count as f64).sqrt()),
+    };
+
+    Measurement {
+        sum: sum,
+        flux: flux,
+        flux_error: flux_error,
+        peak: peak,
+        mean: mean,
+        rms: rms,
+        pixels: count,
+        background: background,
+        background_rms: background_rms,
+    }
+}
+
+/// a row of the photometry table: the channel (1-based), its frequency [GHz] and velocity [km/s]
+pub struct PhotometryRow {
+    pub channel: usize,
+    pub frequency: f64,
+    pub velocity: f64,
+    pub measurement: Measurement,
+}
+
+/// the photometry of a region, one row per channel (a single row for 2D images)
+pub struct Photometry {
+    pub dataset_id: String,
+    pub aperture: Aperture,
+    pub annulus: Option<Annulus>,
+    pub lng: f64, //the aperture centre [deg]
+    pub lat: f64,
+    pub unit: String,       //BUNIT
+    pub flux_unit: String,  //BUNIT without the "/beam"
+    pub beam: Option<f64>,  //the beam area [pixels]
+    pub pixel_area: f64,    //[arcsec^2]
+    pub channel_width: f64, //[km/s], 0 without a velocity axis
+    pub rows: Vec<PhotometryRow>,
+}
+
+//an empty CSV field for NaN
+fn csv_field<T: Into<f64> + std::fmt::Display + Copy>(x: T) -> String {
+    if x.into().is_finite() {
+        format!("{}", x)
+    } else {
+        String::new()
+    }
+}
+
+impl Photometry {
+    /// the line flux: the flux density integrated over the channels [flux_unit km/s]
+    /// with its error, None for 2D images or without a velocity axis
+    pub fn integrated_flux(&self) -> Option<(f64, f64)> {
+        if self.rows.len() < 2 || self.channel_width <= 0.0 {
+            return None;
+        }
+
+        let (flux, variance) = self
+            .rows
+            .iter()
+            .map(|row| &row.measurement)
+            .filter(|m| m.flux.is_finite())
+            .fold((0.0, 0.0), |(flux, variance), m| {
+                let error = if m.flux_error.is_finite() {
+                    m.flux_error
+                } else {
+                    0.0
+                };
+
+                (flux + m.flux, variance + error * error)
+            });
+
+        Some((
+            flux * self.channel_width,
+            variance.sqrt() * self.channel_width,
+        ))
+    }
+
+    /// the JSON response, NaN becomes null
+    pub fn to_json(&self) -> String {
+        let rows: Vec<serde_json::Value> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let m = &row.measurement;
+
+                json!({
+                    "channel" : row.channel,
+                    "frequency" : row.frequency,
+                    "velocity" : row.velocity,
+                    "flux" : m.flux,
+                    "flux_error" : m.flux_error,
+                    "sum" : m.sum,
+                    "peak" : m.peak,
+                    "mean" : m.mean,
+                    "rms" : m.rms,
+                    "pixels" : m.pixels,
+                    "area" : m.pixels as f64 * self.pixel_area,
+                    "background" : m.background,
+                    "background_rms" : m.background_rms,
+                })
+            })
+            .collect();
+
+        let integrated = self.integrated_flux();
+
+        let value = json!({
+            "type" : "photometry",
+            "datasetId" : self.dataset_id,
+            "region" : if self.aperture.circle { "circle" } else { "square" },
+            "x1" : self.aperture.x1,
+            "y1" : self.aperture.y1,
+            "x2" : self.aperture.x2,
+            "y2" : self.aperture.y2,
+            "lng" : self.lng,
+            "lat" : self.lat,
+            "annulus" : self.annulus.map(|x| vec![x.inner, x.outer]),
+            "BUNIT" : self.unit,
+            "flux_unit" : self.flux_unit,
+            "beam_area" : self.beam,
+            "pixel_area" : self.pixel_area,
+            "integrated_flux" : integrated.map(|x| x.0),
+            "integrated_flux_error" : integrated.map(|x| x.1),
+            "rows" : rows,
+        });
+
+        value.to_string()
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::new();
+
+        //a '# comment' header with the information common to all rows
+        csv.push_str(&format!("# dataset: {}\n", self.dataset_id));
+        csv.push_str(&format!(
+            "# region type: {}\n",
+            if self.aperture.circle {
+                "circle"
+            } else {
+                "square/rect."
+            }
+        ));
+
+        let (cx, cy) = self.aperture.centre();
+        csv.push_str(&format!("# region centre (x) [px]: {}\n", cx));
+        csv.push_str(&format!("# region centre (y) [px]: {}\n", cy));
+
+        if self.aperture.circle {
+            csv.push_str(&format!(
+                "# region radius [px]: {}\n",
+                self.aperture.radius()
+            ));
+        } else {
+            csv.push_str(&format!(
+                "# region width [px]: {}\n",
+                self.aperture.x2 - self.aperture.x1 + 1
+            ));
+            csv.push_str(&format!(
+                "# region height [px]: {}\n",
+                self.aperture.y2 - self.aperture.y1 + 1
+            ));
+        }
+
+        csv.push_str(&format!("# wcs.lng [deg]: {}\n", csv_field(self.lng)));
+        csv.push_str(&format!("# wcs.lat [deg]: {}\n", csv_field(self.lat)));
+
+        match self.annulus {
+            Some(annulus) => csv.push_str(&format!(
+                "# background annulus [px]: {} - {}\n",
+                annulus.inner, annulus.outer
+            )),
+            None => csv.push_str("# background annulus: none\n"),
+        }
+
+        match self.beam {
+            Some(beam) => csv.push_str(&format!("# beam area [px]: {}\n", beam)),
+            None => csv.push_str("# beam area: not applied\n"),
+        }
+
+        csv.push_str(&format!(
+            "# pixel area [arcsec^2]: {}\n",
+            csv_field(self.pixel_area)
+        ));
+
+        if let Some((flux, error)) = self.integrated_flux() {
+            csv.push_str(&format!(
+                "# integrated flux [{} km/s]: {} +/- {}\n",
+                self.flux_unit,
+                csv_field(flux),
+                csv_field(error)
+            ));
+        }
+
+        csv.push_str(&format!(
+            "\"channel\",\"frequency [GHz]\",\"velocity [km/s]\",\"flux [{0}]\",\"flux error [{0}]\",\"peak [{1}]\",\"mean [{1}]\",\"rms [{1}]\",\"pixels\",\"area [arcsec^2]\",\"background [{1}]\",\"background rms [{1}]\"\n",
+            self.flux_unit, self.unit
+        ));
+
+        for row in &self.rows {
+            let m = &row.measurement;
+
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{},{},{}\n",
+                row.channel,
+                csv_field(row.frequency),
+                csv_field(row.velocity),
+                csv_field(m.flux),
+                csv_field(m.flux_error),
+                csv_field(m.peak),
+                csv_field(m.mean),
+                csv_field(m.rms),
+                m.pixels,
+                csv_field(m.pixels as f64 * self.pixel_area),
+                csv_field(m.background),
+                csv_field(m.background_rms)
+            ));
+        }
+
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(x1: usize, y1: usize, x2: usize, y2: usize) -> Aperture {
+        Aperture {
+            x1: x1,
+            y1: y1,
+            x2: x2,
+            y2: y2,
+            circle: false,
+        }
+    }
+
+    //an 11x11 image of 1.0 with a 3x3 source of 4.0 centred at (5, 5)
+    fn source_image() -> Vec<f32> {
+        (0..121)
+            .map(|i| {
+                let (x, y) = (i % 11, i / 11);
+                if (4..=6).contains(&x) && (4..=6).contains(&y) {
+                    4.0
+                } else {
+                    1.0
+                }
+            })
+            .collect()
+    }
+
+    fn photometry(rows: Vec<Measurement>, channel_width: f64) -> Photometry {
+        Photometry {
+            dataset_id: String::from("cube"),
+            aperture: square(4, 4, 6, 6),
+            annulus: None,
+            lng: 150.0,
+            lat: f64::NAN,
+            unit: String::from("Jy/beam"),
+            flux_unit: String::from("Jy"),
+            beam: Some(2.0),
+            pixel_area: 0.25,
+            channel_width: channel_width,
+            rows: rows
+                .into_iter()
+                .enumerate()
+                .map(|(i, m)| PhotometryRow {
+                    channel: i + 1,
+                    frequency: 100.0,
+                    velocity: f64::NAN,
+                    measurement: m,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn circular_apertures() {
+        let circle = Aperture {
+            circle: true,
+            ..square(3, 3, 7, 7)
+        };
+
+        assert_eq!(circle.centre(), (5, 5));
+        assert_eq!(circle.radius(), 2);
+        assert!(circle.contains(5, 3) && circle.contains(6, 6));
+        assert!(!circle.contains(7, 6) && !circle.contains(3, 3));
+        assert!(square(3, 3, 7, 7).contains(3, 3));
+
+        //13 pixels within a radius of 2
+        let m = measure(&vec![1.0; 121], 11, 11, &circle, None, None, 0.0);
+        assert_eq!(m.pixels, 13);
+        assert_eq!(m.sum, 13.0);
+    }
+
+    #[test]
+    fn aperture_sums() {
+        let mut pixels = source_image();
+        pixels[5 * 11 + 5] = f32::NAN;
+
+        let m = measure(&pixels, 11, 11, &square(4, 4, 6, 6), None, None, 0.5);
+
+        assert_eq!(m.pixels, 8);
+        assert_eq!(m.sum, 32.0);
+        assert_eq!(m.flux, 32.0);
+        assert_eq!((m.peak, m.mean, m.rms), (4.0, 4.0, 0.0));
+        assert!((m.flux_error - 0.5 * 8f64.sqrt()).abs() < 1e-12);
+        assert_eq!((m.background, m.background_rms), (0.0, 0.5));
+
+        //per-beam units
+        let m = measure(&pixels, 11, 11, &square(4, 4, 6, 6), None, Some(4.0), 0.5);
+        assert_eq!(m.flux, 8.0);
+        assert!((m.flux_error - 0.5 * 32f64.sqrt() / 4.0).abs() < 1e-12);
+
+        //nothing valid
+        let m = measure(&[f32::NAN; 4], 2, 2, &square(0, 0, 1, 1), None, None, 0.5);
+        assert_eq!(m.pixels, 0);
+        assert!(m.flux.is_nan() && m.mean.is_nan() && m.peak.is_nan());
+    }
+
+    #[test]
+    fn background_annulus() {
+        let annulus = Annulus {
+            inner: 3.0,
+            outer: 5.0,
+        };
+
+        let m = measure(
+            &source_image(),
+            11,
+            11,
+            &square(4, 4, 6, 6),
+            Some(annulus),
+            None,
+            0.5,
+        );
+
+        assert_eq!((m.background, m.background_rms), (1.0, 0.0));
+        assert_eq!(m.sum, 27.0);
+        assert_eq!(m.peak, 3.0);
+
+        //an annulus without valid pixels
+        let mut pixels = vec![f32::NAN; 121];
+        pixels[5 * 11 + 5] = 2.0;
+
+        let m = measure(
+            &pixels,
+            11,
+            11,
+            &square(5, 5, 5, 5),
+            Some(annulus),
+            None,
+            0.5,
+        );
+        assert_eq!(m.background, 0.0);
+        assert!(m.background_rms.is_nan() && m.flux_error.is_nan());
+        assert_eq!(m.sum, 2.0);
+    }
+
+    #[test]
+    fn line_flux() {
+        let m = measure(
+            &source_image(),
+            11,
+            11,
+            &square(4, 4, 6, 6),
+            None,
+            Some(2.0),
+            0.5,
+        );
+        let blank = measure(&[f32::NAN], 1, 1, &square(0, 0, 0, 0), None, Some(2.0), 0.5);
+
+        let (flux, error) = photometry(vec![m, m, blank], 0.5)
+            .integrated_flux()
+            .unwrap();
+        assert_eq!(flux, 2.0 * 18.0 * 0.5);
+        assert!((error - (2.0 * m.flux_error * m.flux_error).sqrt() * 0.5).abs() < 1e-12);
+
+        assert!(photometry(vec![m], 0.5).integrated_flux().is_none());
+        assert!(photometry(vec![m, m], 0.0).integrated_flux().is_none());
+    }
+
+    #[test]
+    fn tables() {
+        let m = measure(
+            &source_image(),
+            11,
+            11,
+            &square(4, 4, 6, 6),
+            None,
+            Some(2.0),
+            0.5,
+        );
+        let table = photometry(vec![m, m], 1.0);
+
+        let json: serde_json::Value = serde_json::from_str(&table.to_json()).unwrap();
+        assert_eq!(json["region"], "square");
+        assert_eq!(json["lat"], serde_json::Value::Null);
+        assert_eq!(json["annulus"], serde_json::Value::Null);
+        assert_eq!(json["integrated_flux"], json!(36.0));
+        assert_eq!(json["rows"][1]["channel"], json!(2));
+        assert_eq!(json["rows"][0]["area"], json!(2.25));
+        assert_eq!(json["rows"][0]["velocity"], serde_json::Value::Null);
+
+        let csv = table.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert!(lines.contains(&"# region width [px]: 3"));
+        assert!(lines.contains(&"# wcs.lat [deg]: "));
+        assert!(lines.contains(&"# background annulus: none"));
+        assert!(lines.contains(&"# beam area [px]: 2"));
+        assert!(csv.contains("\"flux [Jy]\",\"flux error [Jy]\",\"peak [Jy/beam]\""));
+        assert_eq!(lines.len(), 13 + 2);
+        assert!(lines[14].starts_with("2,100,,18,"));
+    }
+}
@@ -0,0 +1,343 @@
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::fits::IMAGECACHE;
+
+//256x256 PNG tiles (greyscale luminance + alpha), the last row/column of tiles may be smaller
+pub const TILE_SIZE: usize = 256;
+
+//the pyramid description, written last so that its presence marks a complete pyramid
+static PYRAMID_INFO: &'static str = "pyramid.json";
+
+lazy_static! {
+    static ref PYRAMID_JOBS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+#[derive(Debug, PartialEq)]
+pub enum PyramidStatus {
+    Ready,
+    Running,
+    Missing,
+}
+
+/// IMAGECACHE/<dataset id>.tiles/<level>/<x>_<y>.png
+pub fn pyramid_directory(dataset_id: &str) -> PathBuf {
+    Path::new(IMAGECACHE).join(format!("{}.tiles", dataset_id.replace("/", "_")))
+}
+
+pub fn info_path(dataset_id: &str) -> PathBuf {
+    pyramid_directory(dataset_id).join(PYRAMID_INFO)
+}
+
+pub fn tile_path(dataset_id: &str, level: u32, x: u32, y: u32) -> PathBuf {
+    pyramid_directory(dataset_id)
+        .join(level.to_string())
+        .join(format!("{}_{}.png", x, y))
+}
+
+pub fn status(dataset_id: &str) -> PyramidStatus {
+    if PYRAMID_JOBS.lock().contains(dataset_id) {
+        PyramidStatus::Running
+    } else if info_path(dataset_id).exists() {
+        PyramidStatus::Ready
+    } else {
+        PyramidStatus::Missing
+    }
+}
+
+/// register a pyramid job, false if one is already running
+pub fn start_job(dataset_id: &str) -> bool {
+    PYRAMID_JOBS.lock().insert(dataset_id.to_string())
+}
+
+pub fn end_job(dataset_id: &str) {
+    PYRAMID_JOBS.lock().remove(dataset_id);
+}
+
+//the number of levels needed for the whole image to fit into a single tile at level 0
+fn no_levels(width: usize, height: usize) -> u32 {
+    let mut levels = 1;
+    let mut size = width.max(height);
+
+    while size > TILE_SIZE {
+        size = (size + 1) / 2;
+        levels += 1;
+    }
+
+    levels
+}
+
+//halve the resolution, averaging the valid pixels of each 2x2 block
+fn downsample(
+    luminance: &[u8],
+    alpha: &[u8],
+    width: usize,
+    height: usize,
+) -> (Vec<u8>, Vec<u8>, usize, usize) {
+    let w = (width + 1) / 2;
+    let h = (height + 1) / 2;
+
+    let rows: Vec<(Vec<u8>, Vec<u8>)> = (0..h)
+        .into_par_iter()
+        .map(|row| {
+            let mut lum = vec![0u8; w];
+            let mut mask = vec![0u8; w];
+
+            for col in 0..w {
+                let mut sum = 0u32;
+                let mut count = 0u32;
+
+                for y in (2 * row)..(2 * row + 2).min(height) {
+                    for x in (2 * col)..(2 * col + 2).min(width) {
+                        let offset = y * width + x;
+
+                        if alpha[offset] > 0 {
+                            sum += luminance[offset] as u32;
+                            count += 1;
+                        }
+                    }
+                }
+
+                if count > 0 {
+                    lum[col] = ((sum + count / 2) / count) as u8;
+                    mask[col] = 255;
+                }
+            }
+
+            (lum, mask)
+        })
+        .collect();
+
+    let mut lum = Vec::with_capacity(w * h);
+    let mut mask = Vec::with_capacity(w * h);
+
+    for (l, m) in rows {
+        lum.extend_from_slice(&l);
+        mask.extend_from_slice(&m);
+    }
+
+    (lum, mask, w, h)
+}
+
+/// a greyscale + alpha PNG image (interleaved 8-bit samples)
+pub fn write_tile(path: &Path, width: usize, height: usize, data: &[u8]) -> Result<(), String> {
+    let file = std::fs::File::create(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    let mut encoder = png::Encoder::new(std::io::BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::GrayscaleAlpha);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    encoder
+        .write_header()
+        .and_then(|mut writer| {
+            writer.write_image_data(data)?;
+            writer.finish()
+        })
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn write_level(
+    dir: &Path,
+    level: u32,
+    luminance: &[u8],
+    alpha: &[u8],
+    width: usize,
+    height: usize,
+) -> Result<(), String> {
+    let level_dir = dir.join(level.to_string());
+    std::fs::create_dir_all(&level_dir)
+        .map_err(|err| format!("{}: {}", level_dir.display(), err))?;
+
+    let nx = (width + TILE_SIZE - 1) / TILE_SIZE;
+    let ny = (height + TILE_SIZE - 1) / TILE_SIZE;
+
+    (0..nx * ny).into_par_iter().try_for_each(|index| {
+        let (tx, ty) = (index % nx, index / nx);
+        let x0 = tx * TILE_SIZE;
+        let y0 = ty * TILE_SIZE;
+        let w = TILE_SIZE.min(width - x0);
+        let h = TILE_SIZE.min(height - y0);
+
+        //skip fully transparent tiles, the client treats missing tiles as empty
+        let mut data: Vec<u8> = Vec::with_capacity(2 * w * h);
+        let mut empty = true;
+
+        for y in y0..y0 + h {
+            for x in x0..x0 + w {
+                let offset = y * width + x;
+                data.push(luminance[offset]);
+                data.push(alpha[offset]);
+                empty = empty && alpha[offset] == 0;
+            }
+        }
+
+        if empty {
+            return Ok(());
+        }
+
+        write_tile(&level_dir.join(format!("{}_{}.png", tx, ty)), w, h, &data)
+    })
+}
+
+/// build a multi-resolution tile pyramid from a stretched image (the first row at the top);
+/// the last level holds the native resolution, level 0 fits into a single tile
+pub fn generate(
+    luminance: Vec<u8>,
+    alpha: Vec<u8>,
+    width: usize,
+    height: usize,
+    dir: &Path,
+) -> Result<u32, String> {
+    let watch = Instant::now();
+    let levels = no_levels(width, height);
+
+    let mut luminance = luminance;
+    let mut alpha = alpha;
+    let (mut w, mut h) = (width, height);
+
+    for level in (0..levels).rev() {
+        write_level(dir, level, &luminance, &alpha, w, h)?;
+
+        if level > 0 {
+            let (lum, mask, w2, h2) = downsample(&luminance, &alpha, w, h);
+            luminance = lum;
+            alpha = mask;
+            w = w2;
+            h = h2;
+        }
+    }
+
+    let info = json!({
+        "width" : width,
+        "height" : height,
+        "tile_size" : TILE_SIZE,
+        "levels" : levels,
+        "format" : "png",
+    });
+
+    let path = dir.join(PYRAMID_INFO);
+
+    std::fs::File::create(&path)
+        .and_then(|mut file| file.write_all(info.to_string().as_bytes()))
+        .map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    println!(
+        "{}x{} image tile pyramid with {} levels written to {} in {:?}",
+        width,
+        height,
+        levels,
+        dir.display(),
+        watch.elapsed()
+    );
+
+    Ok(levels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_size(path: &Path) -> (u32, u32) {
+        let png = std::fs::read(path).unwrap();
+        let reader = png::Decoder::new(std::io::Cursor::new(png))
+            .read_info()
+            .unwrap();
+        let info = reader.info();
+
+        assert_eq!(info.color_type, png::ColorType::GrayscaleAlpha);
+        (info.width, info.height)
+    }
+
+    #[test]
+    fn level_counts() {
+        assert_eq!(no_levels(1, 1), 1);
+        assert_eq!(no_levels(TILE_SIZE, TILE_SIZE), 1);
+        assert_eq!(no_levels(TILE_SIZE + 1, 1), 2);
+        assert_eq!(no_levels(2 * TILE_SIZE, 2 * TILE_SIZE), 2);
+        assert_eq!(no_levels(1, 4 * TILE_SIZE), 3);
+        //odd sizes round up when halved: 513 -> 257 -> 129
+        assert_eq!(no_levels(2 * TILE_SIZE + 1, 3), 3);
+        assert_eq!(no_levels(1000, 3), 3);
+    }
+
+    #[test]
+    fn masked_pixels_are_ignored() {
+        //3x3, the middle column of the top two rows is masked
+        let luminance = [10, 20, 30, 40, 50, 60, 70, 80, 90];
+        let alpha = [255, 0, 255, 255, 0, 255, 255, 255, 255];
+
+        let (lum, mask, w, h) = downsample(&luminance, &alpha, 3, 3);
+
+        assert_eq!((w, h), (2, 2));
+        assert_eq!(lum, vec![25, 45, 75, 90]);
+        assert_eq!(mask, vec![255; 4]);
+
+        //the average is rounded
+        let (lum, _, _, _) = downsample(&[1, 2], &[255, 255], 2, 1);
+        assert_eq!(lum, vec![2]);
+    }
+
+    #[test]
+    fn transparency_propagates() {
+        //4x2, the right 2x2 block is fully masked
+        let luminance = [10, 20, 200, 200, 30, 40, 200, 200];
+        let alpha = [255, 255, 0, 0, 255, 255, 0, 0];
+
+        let (lum, mask, w, h) = downsample(&luminance, &alpha, 4, 2);
+
+        assert_eq!((w, h), (2, 1));
+        assert_eq!(lum, vec![25, 0]);
+        assert_eq!(mask, vec![255, 0]);
+    }
+
+    #[test]
+    fn edge_tiles() {
+        let dir = std::env::temp_dir().join(format!("pyramid-{}", std::process::id()));
+        let (width, height) = (TILE_SIZE + 44, 40);
+
+        //the right half of the second tile is transparent
+        let luminance = vec![128u8; width * height];
+        let alpha: Vec<u8> = (0..width * height)
+            .map(|i| if i % width < TILE_SIZE + 20 { 255 } else { 0 })
+            .collect();
+
+        assert_eq!(generate(luminance, alpha, width, height, &dir), Ok(2));
+
+        //the native resolution, the last tile is narrower
+        assert_eq!(png_size(&dir.join("1/0_0.png")), (TILE_SIZE as u32, 40));
+        assert_eq!(png_size(&dir.join("1/1_0.png")), (44, 40));
+
+        //a single tile at level 0
+        assert_eq!(png_size(&dir.join("0/0_0.png")), (150, 20));
+        assert!(!dir.join("0/1_0.png").exists());
+        assert!(dir.join(PYRAMID_INFO).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn transparent_tiles_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("pyramid-empty-{}", std::process::id()));
+        let (width, height) = (2 * TILE_SIZE, 10);
+
+        //only the first tile has valid pixels
+        let alpha: Vec<u8> = (0..width * height)
+            .map(|i| if i % width < TILE_SIZE { 255 } else { 0 })
+            .collect();
+
+        assert_eq!(
+            generate(vec![0; width * height], alpha, width, height, &dir),
+            Ok(2)
+        );
+
+        assert!(dir.join("1/0_0.png").exists());
+        assert!(!dir.join("1/1_0.png").exists());
+        assert_eq!(png_size(&dir.join("0/0_0.png")), (TILE_SIZE as u32, 5));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
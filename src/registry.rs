@@ -0,0 +1,486 @@
+use futures::channel::oneshot;
+use parking_lot::{Condvar, Mutex};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::fits::FITS;
+
+pub const MAX_CONCURRENT_LOADS: usize = 4;
+
+const SLOT_POLL_INTERVAL: u64 = 1; //[s]; re-check cancellation while waiting for a load slot
+
+/// the maximum number of datasets being loaded at the same time
+pub static CONCURRENT_LOADS: AtomicUsize = AtomicUsize::new(MAX_CONCURRENT_LOADS);
+
+/// the life cycle of a dataset: Queued -> Loading -> Ready | Failed
+#[derive(Debug, Clone)]
+pub enum LoadState {
+    Queued,
+    Loading {
+        message: String,
+        total: i32,
+        running: i32,
+    },
+    Ready,
+    Failed {
+        status_code: u16,
+        reason: String,
+    },
+}
+
+impl LoadState {
+    pub fn from_fits(fits: &FITS) -> LoadState {
+        if fits.has_data {
+            return LoadState::Ready;
+        }
+
+        let reason = match fits.status_code {
+            415 => "unsupported media type",
+            500 => "critical error reading the FITS file",
+            503 => "insufficient server memory",
+            504 => "the download failed or could not be verified, a new request resumes it",
+            _ => "data not found on the remote site/server",
+        };
+
+        LoadState::Failed {
+            status_code: fits.status_code,
+            reason: String::from(reason),
+        }
+    }
+
+    pub fn cancelled() -> LoadState {
+        LoadState::Failed {
+            status_code: 499,
+            reason: String::from("loading has been cancelled"),
+        }
+    }
+
+    pub fn is_final(&self) -> bool {
+        match self {
+            LoadState::Ready | LoadState::Failed { .. } => true,
+            _ => false,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            LoadState::Queued => json!({ "state" : "queued" }),
+            LoadState::Loading {
+                message,
+                total,
+                running,
+            } => json!({
+                "state" : "loading",
+                "message" : message,
+                "total" : total,
+                "running" : running,
+            }),
+            LoadState::Ready => json!({ "state" : "ready" }),
+            LoadState::Failed {
+                status_code,
+                reason,
+            } => json!({
+                "state" : "failed",
+                "status_code" : status_code,
+                "reason" : reason,
+            }),
+        }
+    }
+}
+
+struct Entry {
+    state: LoadState,
+    cancelled: Arc<AtomicBool>,
+    waiters: Vec<oneshot::Sender<LoadState>>,
+}
+
+struct Registry {
+    entries: Mutex<HashMap<String, Entry>>,
+    active_loads: Mutex<usize>,
+    slot_released: Condvar,
+}
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry {
+        entries: Mutex::new(HashMap::new()),
+        active_loads: Mutex::new(0),
+        slot_released: Condvar::new(),
+    };
+}
+
+/// register a new load, keeping any waiters from a previous (i.e. reloaded) entry
+pub fn queue(dataset_id: &str) {
+    let mut entries = REGISTRY.entries.lock();
+
+    let waiters = match entries.remove(dataset_id) {
+        Some(entry) => entry.waiters,
+        None => Vec::new(),
+    };
+
+    entries.insert(
+        dataset_id.to_string(),
+        Entry {
+            state: LoadState::Queued,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            waiters: waiters,
+        },
+    );
+}
+
+pub fn get_state(dataset_id: &str) -> Option<LoadState> {
+    match REGISTRY.entries.lock().get(dataset_id) {
+        Some(entry) => Some(entry.state.clone()),
+        None => None,
+    }
+}
+
+pub fn update_progress(dataset_id: &str, message: &str, total: i32, running: i32) {
+    if let Some(entry) = REGISTRY.entries.lock().get_mut(dataset_id) {
+        if let LoadState::Loading { .. } = entry.state {
+            entry.state = LoadState::Loading {
+                message: String::from(message),
+                total: total,
+                running: running,
+            };
+        }
+    }
+}
+
+pub fn is_cancelled(dataset_id: &str) -> bool {
+    match REGISTRY.entries.lock().get(dataset_id) {
+        Some(entry) => entry.cancelled.load(Ordering::SeqCst),
+        None => false,
+    }
+}
+
+/// the cancellation flag of a load, checked cheaply (i.e. for every frame) without the registry lock
+pub fn cancel_flag(dataset_id: &str) -> Arc<AtomicBool> {
+    match REGISTRY.entries.lock().get(dataset_id) {
+        Some(entry) => entry.cancelled.clone(),
+        None => Arc::new(AtomicBool::new(false)),
+    }
+}
+
+/// request cancellation of a queued or in-progress load; false if there is nothing to cancel
+pub fn cancel(dataset_id: &str) -> bool {
+    let cancelled = match REGISTRY.entries.lock().get(dataset_id) {
+        Some(entry) => {
+            if entry.state.is_final() {
+                false
+            } else {
+                entry.cancelled.store(true, Ordering::SeqCst);
+                true
+            }
+        }
+        None => false,
+    };
+
+    //wake up loads waiting for a slot
+    REGISTRY.slot_released.notify_all();
+
+    cancelled
+}
+
+/// block until a concurrent load slot becomes available; None if the load got cancelled meanwhile
+pub fn acquire_slot(dataset_id: &str) -> Option<LoadSlot> {
+    let mut active = REGISTRY.active_loads.lock();
+
+    loop {
+        if is_cancelled(dataset_id) {
+            return None;
+        }
+
+        if *active < CONCURRENT_LOADS.load(Ordering::SeqCst).max(1) {
+            *active += 1;
+
+            if let Some(entry) = REGISTRY.entries.lock().get_mut(dataset_id) {
+                entry.state = LoadState::Loading {
+                    message: String::from("loading FITS"),
+                    total: 0,
+                    running: 0,
+                };
+            }
+
+            return Some(LoadSlot);
+        }
+
+        REGISTRY
+            .slot_released
+            .wait_for(&mut active, Duration::from_secs(SLOT_POLL_INTERVAL));
+    }
+}
+
+fn release_slot() {
+    let mut active = REGISTRY.active_loads.lock();
+    *active = active.saturating_sub(1);
+
+    REGISTRY.slot_released.notify_all();
+}
+
+/// a concurrent load slot, released when dropped (also if the load panics)
+pub struct LoadSlot;
+
+impl Drop for LoadSlot {
+    fn drop(&mut self) {
+        release_slot();
+    }
+}
+
+/// set the final state and resolve all the waiters
+pub fn finish(dataset_id: &str, state: LoadState) {
+    let waiters = match REGISTRY.entries.lock().get_mut(dataset_id) {
+        Some(entry) => {
+            entry.state = state.clone();
+            std::mem::replace(&mut entry.waiters, Vec::new())
+        }
+        None => Vec::new(),
+    };
+
+    for waiter in waiters {
+        let _ = waiter.send(state.clone());
+    }
+}
+
+/// forget an evicted dataset, failing any outstanding waiters
+pub fn remove(dataset_id: &str) {
+    let entry = REGISTRY.entries.lock().remove(dataset_id);
+
+    if let Some(entry) = entry {
+        entry.cancelled.store(true, Ordering::SeqCst);
+
+        for waiter in entry.waiters {
+            let _ = waiter.send(LoadState::Failed {
+                status_code: 404,
+                reason: String::from("dataset has been evicted"),
+            });
+        }
+    }
+}
+
+fn subscribe(dataset_id: &str) -> Option<oneshot::Receiver<LoadState>> {
+    let mut entries = REGISTRY.entries.lock();
+
+    let entry = match entries.get_mut(dataset_id) {
+        Some(x) => x,
+        None => return None,
+    };
+
+    let (tx, rx) = oneshot::channel();
+
+    if entry.state.is_final() {
+        let _ = tx.send(entry.state.clone());
+    } else {
+        entry.waiters.push(tx);
+    }
+
+    Some(rx)
+}
+
+/// wait (asynchronously) up to <timeout> for a dataset to become ready or fail;
+/// returns the latest state, or None for datasets unknown to the registry
+pub async fn wait_ready(dataset_id: &str, timeout: Duration) -> Option<LoadState> {
+    let rx = match subscribe(dataset_id) {
+        Some(rx) => rx,
+        None => return None,
+    };
+
+    match actix_web::rt::time::timeout(timeout, rx).await {
+        Ok(Ok(state)) => Some(state),
+        _ => get_state(dataset_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::sync::mpsc;
+
+    //the registry is global, every test uses its own dataset ids
+
+    fn status_code(state: Option<LoadState>) -> Option<u16> {
+        match state {
+            Some(LoadState::Failed { status_code, .. }) => Some(status_code),
+            _ => None,
+        }
+    }
+
+    fn active_loads() -> usize {
+        *REGISTRY.active_loads.lock()
+    }
+
+    #[test]
+    fn queue_finish_remove() {
+        let id = "registry-test-lifecycle";
+
+        assert!(get_state(id).is_none());
+
+        queue(id);
+        assert!(matches!(get_state(id), Some(LoadState::Queued)));
+
+        //the progress only applies to a load that has started
+        update_progress(id, "downloading FITS", 100, 50);
+        assert!(matches!(get_state(id), Some(LoadState::Queued)));
+
+        finish(id, LoadState::Ready);
+        assert!(matches!(get_state(id), Some(LoadState::Ready)));
+
+        remove(id);
+        assert!(get_state(id).is_none());
+    }
+
+    #[test]
+    fn waiters_are_resolved() {
+        let id = "registry-test-waiters";
+
+        assert!(subscribe(id).is_none());
+
+        //a reload keeps the waiters of the previous entry
+        queue(id);
+        let rx = subscribe(id).unwrap();
+        queue(id);
+
+        finish(id, LoadState::cancelled());
+        assert_eq!(status_code(block_on(rx).ok()), Some(499));
+
+        //a final state resolves straight away
+        assert_eq!(
+            status_code(block_on(subscribe(id).unwrap()).ok()),
+            Some(499)
+        );
+
+        //an eviction fails the outstanding waiters
+        queue(id);
+        let rx = subscribe(id).unwrap();
+        remove(id);
+        assert_eq!(status_code(block_on(rx).ok()), Some(404));
+    }
+
+    #[actix_web::test]
+    async fn wait_ready_times_out() {
+        let id = "registry-test-wait";
+        let timeout = Duration::from_millis(10);
+
+        assert!(wait_ready(id, timeout).await.is_none());
+
+        //the latest state after a timeout
+        queue(id);
+        assert!(matches!(
+            wait_ready(id, timeout).await,
+            Some(LoadState::Queued)
+        ));
+
+        finish(id, LoadState::Ready);
+        assert!(matches!(
+            wait_ready(id, timeout).await,
+            Some(LoadState::Ready)
+        ));
+
+        remove(id);
+    }
+
+    #[test]
+    fn cancel_pending_loads_only() {
+        let id = "registry-test-cancel";
+
+        assert!(!cancel(id));
+
+        queue(id);
+        let flag = cancel_flag(id);
+        assert!(!flag.load(Ordering::SeqCst));
+
+        assert!(cancel(id));
+        assert!(is_cancelled(id));
+        assert!(flag.load(Ordering::SeqCst));
+
+        //a reload starts with a new flag
+        queue(id);
+        assert!(!is_cancelled(id));
+
+        finish(id, LoadState::Ready);
+        assert!(!cancel(id));
+        assert!(!is_cancelled(id));
+
+        remove(id);
+    }
+
+    //the only test taking load slots, the counter is global
+    #[test]
+    fn load_slots() {
+        let ids = [
+            "registry-test-slot-0",
+            "registry-test-slot-1",
+            "registry-test-slot-2",
+        ];
+
+        for id in ids {
+            queue(id);
+        }
+
+        CONCURRENT_LOADS.store(2, Ordering::SeqCst);
+
+        let first = acquire_slot(ids[0]).unwrap();
+        assert!(matches!(get_state(ids[0]), Some(LoadState::Loading { .. })));
+
+        update_progress(ids[0], "downloading FITS", 100, 50);
+        match get_state(ids[0]) {
+            Some(LoadState::Loading {
+                message, running, ..
+            }) => {
+                assert_eq!(message, "downloading FITS");
+                assert_eq!(running, 50);
+            }
+            state => panic!("unexpected {:?}", state),
+        }
+
+        let second = acquire_slot(ids[1]).unwrap();
+        assert_eq!(active_loads(), 2);
+
+        //over the limit a load waits for a slot to be released
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || tx.send(acquire_slot(ids[2])).unwrap());
+
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+        assert!(matches!(get_state(ids[2]), Some(LoadState::Queued)));
+
+        drop(first);
+        let third = rx.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+        assert_eq!(active_loads(), 2);
+
+        //cancelling a queued load wakes it up before the next poll
+        queue(ids[0]);
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || tx.send(acquire_slot(ids[0])).unwrap());
+
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+        assert!(cancel(ids[0]));
+        assert!(
+            rx.recv_timeout(Duration::from_millis(500))
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(active_loads(), 2);
+
+        drop(second);
+        drop(third);
+        assert_eq!(active_loads(), 0);
+
+        //a slot is also released by a load that panics
+        let result = std::thread::spawn(move || {
+            let _slot = acquire_slot(ids[1]).unwrap();
+            panic!("a failed load");
+        })
+        .join();
+
+        assert!(result.is_err());
+        assert_eq!(active_loads(), 0);
+
+        CONCURRENT_LOADS.store(MAX_CONCURRENT_LOADS, Ordering::SeqCst);
+
+        for id in ids {
+            remove(id);
+        }
+    }
+}
@@ -0,0 +1,413 @@
+//reprojection of a dataset onto the celestial WCS of another one, optionally onto its velocity axis
+use rayon::prelude::*;
+
+use crate::hips::{CelestialWcs, Frame};
+
+//the largest supersampling factor per axis of the flux-conserving resampling
+const MAX_SUPERSAMPLING: usize = 16;
+
+//the smallest fraction of a target pixel covered by valid source pixels
+const MIN_COVERAGE: f32 = 0.5;
+
+/// the resampling method
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Method {
+    Nearest,
+    Bilinear,
+    Flux, //area-weighted averages of the source pixels, conserving the surface brightness
+}
+
+impl Method {
+    pub fn from_name(name: &str) -> Option<Method> {
+        match name.to_lowercase().as_str() {
+            "nearest" => Some(Method::Nearest),
+            "bilinear" | "linear" => Some(Method::Bilinear),
+            "flux" | "exact" | "area" => Some(Method::Flux),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::Nearest => "nearest",
+            Method::Bilinear => "bilinear",
+            Method::Flux => "flux-conserving",
+        }
+    }
+}
+
+//the J2000 equatorial -> galactic rotation matrix
+const GALACTIC: [[f64; 3]; 3] = [
+    [-0.0548755604, -0.8734370902, -0.4838350155],
+    [0.4941094279, -0.4448296300, 0.7469822445],
+    [-0.8676661490, -0.1980763734, 0.4559837762],
+];
+
+/// (lon, lat) [deg] from one celestial frame to another
+pub fn convert(lon: f64, lat: f64, from: Frame, to: Frame) -> (f64, f64) {
+    if from == to {
+        return (lon, lat);
+    }
+
+    let (lon, lat) = (lon.to_radians(), lat.to_radians());
+    let v = [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()];
+
+    //the inverse rotation is the transpose
+    let r = |i: usize, j: usize| match to {
+        Frame::Galactic => GALACTIC[i][j],
+        Frame::Equatorial => GALACTIC[j][i],
+    };
+
+    let w: Vec<f64> = (0..3)
+        .map(|i| r(i, 0) * v[0] + r(i, 1) * v[1] + r(i, 2) * v[2])
+        .collect();
+
+    (
+        w[1].atan2(w[0]).to_degrees().rem_euclid(360.0),
+        w[2].clamp(-1.0, 1.0).asin().to_degrees(),
+    )
+}
+
+/// the source pixels (indices and weights) contributing to each target pixel
+pub struct SpatialMap {
+    weights: Vec<Vec<(u32, f32)>>,
+}
+
+//the bilinear (or nearest) weights of a fractional source pixel, lower weight for pixels off the image
+fn sample(
+    x: f64,
+    y: f64,
+    width: usize,
+    height: usize,
+    method: Method,
+    weight: f32,
+) -> Vec<(u32, f32)> {
+    let inside = |x: i64, y: i64| x >= 0 && y >= 0 && (x as usize) < width && (y as usize) < height;
+    let index = |x: i64, y: i64| (y as usize * width + x as usize) as u32;
+
+    if method != Method::Bilinear {
+        let (x, y) = (x.round() as i64, y.round() as i64);
+
+        return if inside(x, y) {
+            vec![(index(x, y), weight)]
+        } else {
+            Vec::new()
+        };
+    }
+
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = ((x - x0) as f32, (y - y0) as f32);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    [
+        (x0, y0, (1.0 - fx) * (1.0 - fy)),
+        (x0 + 1, y0, fx * (1.0 - fy)),
+        (x0, y0 + 1, (1.0 - fx) * fy),
+        (x0 + 1, y0 + 1, fx * fy),
+    ]
+    .iter()
+    .filter(|(x, y, w)| *w > 0.0 && inside(*x, *y))
+    .map(|(x, y, w)| (index(*x, *y), weight * w))
+    .collect()
+}
+
+impl SpatialMap {
+    /// map the pixels of a (width x height) target image onto a (src_width x src_height) source
+    pub fn new(
+        target: &CelestialWcs,
+        width: usize,
+        height: usize,
+        source: &CelestialWcs,
+        src_width: usize,
+        src_height: usize,
+        method: Method,
+    ) -> SpatialMap {
+        //sub-samples per axis: at least two per source pixel covered by a target pixel
+        let n = match method {
+            Method::Flux => ((2.0 * target.pixel_scale() / source.pixel_scale()).ceil() as usize)
+                .clamp(2, MAX_SUPERSAMPLING),
+            _ => 1,
+        };
+
+        let weight = 1.0 / (n * n) as f32;
+
+        let weights = (0..width * height)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = ((i % width) as f64, (i / width) as f64);
+                let mut pixel: Vec<(u32, f32)> = Vec::new();
+
+                for k in 0..n * n {
+                    //the sub-sample centres within the target pixel
+                    let dx = ((k % n) as f64 + 0.5) / n as f64 - 0.5;
+                    let dy = ((k / n) as f64 + 0.5) / n as f64 - 0.5;
+
+                    let (lon, lat) = match target.pixel_to_world(x + dx, y + dy) {
+                        Some(x) => convert(x.0, x.1, target.frame, source.frame),
+                        None => continue,
+                    };
+
+                    let (sx, sy) = match source.world_to_pixel(lon, lat) {
+                        Some(x) => x,
+                        None => continue,
+                    };
+
+                    for (index, w) in sample(sx, sy, src_width, src_height, method, weight) {
+                        match pixel.iter_mut().find(|(j, _)| *j == index) {
+                            Some(entry) => entry.1 += w,
+                            None => pixel.push((index, w)),
+                        }
+                    }
+                }
+
+                pixel
+            })
+            .collect();
+
+        SpatialMap { weights: weights }
+    }
+
+    /// resample a source frame, NaN where less than half of a target pixel has valid data
+    pub fn apply(&self, frame: &[f32]) -> Vec<f32> {
+        self.weights
+            .par_iter()
+            .map(|pixel| {
+                let mut sum = 0.0;
+                let mut coverage = 0.0;
+
+                for &(index, w) in pixel {
+                    let x = frame[index as usize];
+
+                    if x.is_finite() {
+                        sum += w * x;
+                        coverage += w;
+                    }
+                }
+
+                if coverage >= MIN_COVERAGE {
+                    sum / coverage
+                } else {
+                    f32::NAN
+                }
+            })
+            .collect()
+    }
+}
+
+/// the source frames (indices and weights) of each target channel, given the channel
+/// velocities of both cubes; frames off the source velocity range are left empty
+pub fn spectral_map(target: &[f64], source: &[f64], method: Method) -> Vec<Vec<(usize, f32)>> {
+    //the fractional source channel of a velocity (the axis is monotonic)
+    let position = |v: f64| -> Option<f64> {
+        (0..source.len().saturating_sub(1)).find_map(|i| {
+            let (a, b) = (source[i], source[i + 1]);
+
+            if (v - a) * (v - b) <= 0.0 && a != b {
+                Some(i as f64 + (v - a) / (b - a))
+            } else {
+                None
+            }
+        })
+    };
+
+    target
+        .iter()
+        .enumerate()
+        .map(|(k, &v)| {
+            //the target channel width
+            let dv = match (target.get(k + 1), k.checked_sub(1).map(|j| target[j])) {
+                (Some(next), _) => (next - v).abs(),
+                (None, Some(previous)) => (v - previous).abs(),
+                _ => 0.0,
+            };
+
+            if method == Method::Flux && dv > 0.0 {
+                //the mean of the source channels within the target channel
+                let inside: Vec<usize> = (0..source.len())
+                    .filter(|&i| (source[i] - v).abs() <= 0.5 * dv)
+                    .collect();
+
+                if !inside.is_empty() {
+                    let w = 1.0 / inside.len() as f32;
+                    return inside.into_iter().map(|i| (i, w)).collect();
+                }
+            }
+
+            let x = match position(v) {
+                Some(x) => x,
+                None => return Vec::new(),
+            };
+
+            if method == Method::Nearest {
+                return vec![(x.round() as usize, 1.0)];
+            }
+
+            let i = x.floor() as usize;
+            let f = (x - i as f64) as f32;
+
+            if f > 0.0 {
+                vec![(i, 1.0 - f), (i + 1, f)]
+            } else {
+                vec![(i, 1.0)]
+            }
+        })
+        .collect()
+}
+
+/// the weighted mean of several frames, pixels invalid in some frames use the others
+pub fn combine(frames: &[(Vec<f32>, f32)], size: usize) -> Vec<f32> {
+    if frames.len() == 1 {
+        return frames[0].0.clone();
+    }
+
+    (0..size)
+        .into_par_iter()
+        .map(|i| {
+            let mut sum = 0.0;
+            let mut total = 0.0;
+
+            for (frame, w) in frames {
+                if frame[i].is_finite() {
+                    sum += w * frame[i];
+                    total += w;
+                }
+            }
+
+            if total > 0.0 { sum / total } else { f32::NAN }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tan(crpix: (f64, f64), scale: f64) -> CelestialWcs {
+        let cd = [[-scale, 0.0], [0.0, scale]];
+        CelestialWcs::new("RA---TAN", "DEC--TAN", (150.0, 2.0), crpix, cd).unwrap()
+    }
+
+    //longitudes compared modulo 360 degrees
+    fn close(a: (f64, f64), b: (f64, f64), eps: f64) -> bool {
+        ((a.0 - b.0 + 180.0).rem_euclid(360.0) - 180.0).abs() < eps && (a.1 - b.1).abs() < eps
+    }
+
+    #[test]
+    fn method_names() {
+        assert_eq!(Method::from_name("Linear"), Some(Method::Bilinear));
+        assert_eq!(Method::from_name("area"), Some(Method::Flux));
+        assert_eq!(Method::from_name("cubic"), None);
+        assert_eq!(Method::Flux.name(), "flux-conserving");
+    }
+
+    #[test]
+    fn celestial_frames() {
+        let (eq, gal) = (Frame::Equatorial, Frame::Galactic);
+
+        //the galactic centre and the north galactic pole (J2000)
+        assert!(close(
+            convert(266.404996, -28.936172, eq, gal),
+            (0.0, 0.0),
+            1e-4
+        ));
+        assert!(close(
+            convert(0.0, 90.0, gal, eq),
+            (192.85948, 27.12825),
+            1e-6
+        ));
+
+        //the 10-digit matrix is orthogonal to about 1e-11, i.e. a milliarcsecond near the poles
+        for (lon, lat) in [(0.0, 0.0), (83.6, 22.0), (201.4, -43.0), (359.9, 89.0)] {
+            let (l, b) = convert(lon, lat, eq, gal);
+            assert!(close(convert(l, b, gal, eq), (lon, lat), 1e-6));
+            assert_eq!(convert(lon, lat, eq, eq), (lon, lat));
+        }
+    }
+
+    #[test]
+    fn sample_weights() {
+        let total = |weights: &[(u32, f32)]| weights.iter().map(|(_, w)| w).sum::<f32>();
+
+        let weights = sample(1.25, 2.5, 4, 4, Method::Bilinear, 1.0);
+        assert_eq!(weights.len(), 4);
+        assert!((total(&weights) - 1.0).abs() < 1e-6);
+        assert!(weights.contains(&(9, 0.375)));
+
+        //an exact pixel position, and the image edge
+        assert_eq!(
+            sample(2.0, 1.0, 4, 4, Method::Bilinear, 0.5),
+            vec![(6, 0.5)]
+        );
+        assert!((total(&sample(3.5, 0.0, 4, 4, Method::Bilinear, 1.0)) - 0.5).abs() < 1e-6);
+
+        assert_eq!(
+            sample(1.4, 2.6, 4, 4, Method::Nearest, 1.0),
+            vec![(13, 1.0)]
+        );
+        assert!(sample(-0.6, 0.0, 4, 4, Method::Nearest, 1.0).is_empty());
+    }
+
+    #[test]
+    fn spatial_resampling() {
+        let frame: Vec<f32> = (0..16).map(|x| x as f32).collect();
+        let wcs = tan((2.0, 2.0), 1e-3);
+
+        //the same grid
+        for method in [Method::Nearest, Method::Bilinear] {
+            let map = SpatialMap::new(&wcs, 4, 4, &wcs, 4, 4, method);
+            let out = map.apply(&frame);
+
+            for (a, b) in out.iter().zip(frame.iter()) {
+                assert!((a - b).abs() < 1e-3, "{:?}", method);
+            }
+        }
+
+        //a 2x2 target with twice the pixel size averages 2x2 blocks
+        let coarse = tan((1.25, 1.25), 2e-3);
+        let map = SpatialMap::new(&coarse, 2, 2, &wcs, 4, 4, Method::Flux);
+        let out = map.apply(&frame);
+
+        for (value, expected) in out.iter().zip([2.5, 4.5, 10.5, 12.5]) {
+            assert!((value - expected).abs() < 0.05, "{} vs {}", value, expected);
+        }
+
+        //not enough valid data
+        let mut blank = frame.clone();
+        blank[0..2].fill(f32::NAN);
+        blank[4..6].fill(f32::NAN);
+        assert!(map.apply(&blank)[0].is_nan());
+    }
+
+    #[test]
+    fn velocity_axes() {
+        let source = [0.0, 1.0, 2.0, 3.0];
+
+        let map = spectral_map(&[0.5, 2.0, 5.0], &source, Method::Bilinear);
+        assert_eq!(map, vec![vec![(0, 0.5), (1, 0.5)], vec![(2, 1.0)], vec![]]);
+
+        //a descending source axis
+        let map = spectral_map(&[0.75], &[3.0, 2.0, 1.0, 0.0], Method::Nearest);
+        assert_eq!(map, vec![vec![(2, 1.0)]]);
+
+        //the source channels within each target channel
+        let map = spectral_map(&[0.5, 2.5], &source, Method::Flux);
+        assert_eq!(
+            map,
+            vec![vec![(0, 0.5), (1, 0.5)], vec![(2, 0.5), (3, 0.5)]]
+        );
+    }
+
+    #[test]
+    fn weighted_frames() {
+        let frames = vec![
+            (vec![1.0, f32::NAN, f32::NAN], 0.25),
+            (vec![3.0, 2.0, f32::NAN], 0.75),
+        ];
+
+        let out = combine(&frames, 3);
+        assert_eq!(out[0..2], [2.5, 2.0]);
+        assert!(out[2].is_nan());
+
+        assert_eq!(combine(&frames[0..1], 3)[0], 1.0);
+    }
+}
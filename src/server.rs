@@ -1,6 +1,7 @@
-use parking_lot::RwLock;
+use parking_lot::{Mutex, RwLock};
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use chrono;
 use std;
@@ -15,8 +16,15 @@ use thread_priority::*;
 use uuid::Uuid;
 
 use crate::DATASETS;
+use crate::registry;
 use crate::fits::FITSCACHE;
 use crate::fits::IMAGECACHE;
+use crate::fits::av1_image_filename;
+use crate::fits::vpx_image_filename;
+use crate::export;
+use crate::hips;
+use crate::pyramid;
+use crate::thumbnail;
 
 #[cfg(feature = "jvo")]
 const GARBAGE_COLLECTION_TIMEOUT: i64 = 60 * 60; //[s]; a dataset inactivity timeout//was 60
@@ -30,12 +38,31 @@ const DUMMY_DATASET_TIMEOUT: u64 = 24 * 60 * 60; //[s]; 24 hours, plenty of time
 
 const CACHE_DATASET_TIMEOUT: u64 = 30 * 24 * 60 * 60; //[s]; 30 days
 
+const MEMORY_CHECK_INTERVAL: i64 = 10; //[s]; how often to enforce the memory budget
+
+pub const ADMISSION_RETRY_INTERVAL: u64 = 1; //[s]; a queued dataset load re-tries admission this often
+
+pub const ADMISSION_TIMEOUT: u64 = 5 * 60; //[s]; give up (reject) a queued dataset load after this time
+
+/// the memory budget for all loaded datasets in bytes; 0 means unlimited
+pub static MEMORY_BUDGET: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    //the estimated footprints of the admitted datasets, held until they have been loaded (or failed)
+    static ref RESERVATIONS: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+
+    //the footprints of the evicted datasets, held until their asynchronous drop has finished
+    static ref DRAINING: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+}
+
 /// the server sends these messages to session
 /// New session is created
 #[derive(Message)]
 #[rtype(String)]
 pub struct Connect {
     pub addr: Recipient<WsMessage>,
+    pub kick: Recipient<Kick>,
+    pub relay: Recipient<Relay>,
     pub dataset_id: String,
     pub id: Uuid,
 }
@@ -55,6 +82,78 @@ pub struct Remove {
     pub dataset_id: String,
 }
 
+/// list all the registered sessions (admin)
+#[derive(Message)]
+#[rtype(result = "Vec<SessionInfo>")]
+pub struct ListSessions;
+
+/// forcibly close a WebSocket session (admin)
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct DisconnectSession {
+    pub id: Uuid,
+}
+
+/// the server asks a session to close its WebSocket connection
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Kick {
+    pub reason: String,
+}
+
+/// ask for <bytes> of the memory budget prior to loading a dataset
+#[derive(Message)]
+#[rtype(result = "bool")]
+pub struct Admit {
+    pub dataset_id: String,
+    pub bytes: usize,
+}
+
+/// "follow me" actions: become a presenter, follow the presenter, stop presenting/following
+/// or hand the control over to a follower
+#[derive(Debug, Clone, PartialEq)]
+pub enum FollowAction {
+    Present,
+    Join,
+    Leave,
+    HandOver(Uuid),
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Follow {
+    pub id: Uuid,
+    pub dataset_id: String,
+    pub action: FollowAction,
+}
+
+/// the presenter's view state or cursor position, mirrored by all the followers
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct FollowRelay {
+    pub id: Uuid,
+    pub dataset_id: String,
+    /// a JSON-encoded WebSocket text message
+    pub message: String,
+    /// keep the latest message for the late joiners
+    pub retain: bool,
+}
+
+/// a text message pushed to a single session
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Relay {
+    pub text: String,
+}
+
+/// a snapshot of a registered session
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub dataset_id: String,
+    pub connected: SystemTime,
+}
+
 /// broadcast a message to a dataset
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -68,12 +167,188 @@ pub struct WsMessage {
     pub dataset_id: String,
 }
 
+struct Session {
+    addr: Recipient<WsMessage>,
+    kick: Recipient<Kick>,
+    relay: Recipient<Relay>,
+    dataset_id: String,
+    connected: SystemTime,
+}
+
+/// a presenter and the sessions following it, one group per dataset
+struct FollowGroup {
+    presenter: Uuid,
+    followers: HashSet<Uuid>,
+    last_state: Option<String>,
+}
+
 /// `SessionServer` manages sending messages from the FITSWebQL host server to WebSocket clients
 pub struct SessionServer {
-    sessions: HashMap<Uuid, Recipient<WsMessage>>,
+    sessions: HashMap<Uuid, Session>,
     datasets: Arc<RwLock<HashMap<String, HashSet<Uuid>>>>,
+    groups: HashMap<String, FollowGroup>,
     timer: timer::Timer,
     _guard: timer::Guard,
+    _memory_guard: timer::Guard,
+}
+
+/// give back the memory reserved by Admit, once a dataset has been loaded, failed or evicted
+pub fn release_reservation(dataset_id: &str) {
+    RESERVATIONS.lock().remove(dataset_id);
+}
+
+/// remove a dataset from memory, persisting it in FITSCACHE in a low-priority thread
+pub fn evict_dataset(dataset_id: &str) -> bool {
+    let entry = DATASETS.write().remove(dataset_id);
+    registry::remove(dataset_id);
+
+    //a dataset still being loaded only has its reservation
+    let reserved = RESERVATIONS.lock().remove(dataset_id);
+
+    match entry {
+        Some(value) => {
+            let size = match value.try_read() {
+                Some(fits) => fits.get_memory_footprint(),
+                None => reserved.unwrap_or(0),
+            };
+
+            //still counted against the budget until the memory has actually been freed
+            let key = dataset_id.to_string();
+            *DRAINING.lock().entry(key.clone()).or_insert(0) += size;
+
+            std::thread::spawn(move || {
+                #[cfg(target_os = "linux")]
+                {
+                    match set_current_thread_priority(ThreadPriority::Min) {
+                        Ok(_) => println!("successfully lowered priority for the dataset drop thread"),
+                        Err(err) => println!("error changing the thread priority: {:?}", err),
+                    }
+                };
+
+                {
+                    let fits = value.read();
+                    println!("non-blocking drop for {}", fits.dataset_id);
+                    fits.drop_to_cache();
+                }
+
+                //free the data (unless still referenced elsewhere) before releasing the budget
+                drop(value);
+
+                let mut draining = DRAINING.lock();
+
+                if let Some(x) = draining.get_mut(&key) {
+                    *x = x.saturating_sub(size);
+
+                    if *x == 0 {
+                        draining.remove(&key);
+                    }
+                }
+            });
+
+            true
+        }
+        None => false,
+    }
+}
+
+//the footprint of a dataset: its reservation while loading, otherwise the actual data
+fn dataset_footprint(
+    dataset_id: &str,
+    value: &RwLock<Box<crate::fits::FITS>>,
+    reservations: &HashMap<String, usize>,
+) -> usize {
+    match reservations.get(dataset_id) {
+        Some(reserved) => *reserved,
+        None => match value.try_read() {
+            Some(fits) => fits.get_memory_footprint(),
+            None => 0,
+        },
+    }
+}
+
+/// the total memory footprint of all datasets, including those still being loaded
+/// (by their reservations) or being dropped
+pub fn get_memory_usage() -> usize {
+    let reservations = RESERVATIONS.lock().clone();
+    let draining: usize = DRAINING.lock().values().sum();
+
+    //admitted datasets not (yet) in DATASETS
+    let datasets = DATASETS.read();
+    let pending: usize = reservations
+        .iter()
+        .filter(|(key, _)| !datasets.contains_key(*key))
+        .map(|(_, size)| size)
+        .sum();
+
+    datasets
+        .iter()
+        .map(|(key, value)| dataset_footprint(key, value, &reservations))
+        .sum::<usize>()
+        + pending
+        + draining
+}
+
+/// evict least-recently used datasets without active sessions until <required> extra bytes fit within the budget
+fn enforce_memory_budget(
+    active: &HashMap<String, HashSet<Uuid>>,
+    exclude: &str,
+    required: usize,
+) -> bool {
+    let budget = MEMORY_BUDGET.load(Ordering::SeqCst);
+
+    if budget == 0 {
+        return true;
+    }
+
+    let mut usage = get_memory_usage();
+
+    //(last access, memory footprint, dataset_id) of the loaded datasets
+    let mut candidates: Vec<(SystemTime, usize, String)> = {
+        let reservations = RESERVATIONS.lock();
+        let datasets = DATASETS.read();
+
+        datasets
+            .iter()
+            .filter(|(key, _)| !reservations.contains_key(*key))
+            .filter_map(|(key, value)| match value.try_read() {
+                Some(fits) => Some((
+                    *fits.timestamp.read(),
+                    fits.get_memory_footprint(),
+                    key.clone(),
+                )),
+                None => None,
+            })
+            .collect()
+    };
+
+    if usage + required <= budget {
+        return true;
+    }
+
+    //the oldest first
+    candidates.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (_, size, key) in candidates {
+        if usage + required <= budget {
+            break;
+        }
+
+        if size == 0 || key == exclude || active.contains_key(&key) {
+            continue;
+        }
+
+        println!(
+            "[memory budget]: usage {} + required {} > budget {} bytes, evicting {} ({} bytes)",
+            usage, required, budget, key, size
+        );
+
+        if evict_dataset(&key) {
+            usage = usage.saturating_sub(size);
+        }
+    }
+
+    //the evicted datasets only count as freed once their drop has finished
+    get_memory_usage() + required <= budget
 }
 
 impl Default for SessionServer {
@@ -140,27 +415,11 @@ impl Default for SessionServer {
                 match key {
                     Some(key) => {
                         //println!("[orphaned dataset cleanup]: no active sessions found, {} will be expunged from memory", key);                    
-                        let entry = DATASETS.write().remove(&key);
-                        match entry {
-                            Some(value) => {
-                                std::thread::spawn(move || {
-                                    #[cfg(target_os = "linux")]
-                                    {
-                                        match set_current_thread_priority(ThreadPriority::Min) {
-                                            Ok(_) => println!("successfully lowered priority for the dataset drop thread"),
-                                            Err(err) => println!("error changing the thread priority: {:?}", err),
-                                        }
-                                    };
-
-                                    let fits = value.read();
-                                    println!("non-blocking drop for {}", fits.dataset_id);
-                                    fits.drop_to_cache();
-                                });
-
-                                println!("resuming the actix server thread");
-                            },
-                            None => println!("{} not found in the DATASETS", &key),
-                        };
+                        if evict_dataset(&key) {
+                            println!("resuming the actix server thread");
+                        } else {
+                            println!("{} not found in the DATASETS", &key);
+                        }
 
                         println!("[orphaned dataset cleanup]: {} has been expunged from memory", key);
                     },
@@ -241,6 +500,21 @@ impl Default for SessionServer {
                                                         let imagename = format!("{}/{}.img", IMAGECACHE, key);
                                                         let imagepath = std::path::Path::new(&imagename);
                                                         let _ = std::fs::remove_file(imagepath);
+
+                                                        // and its AV1 (8, 10 and 12-bit) and VP9 (10 and 12-bit) counterparts
+                                                        for bit_depth in [8, 10, 12] {
+                                                            let _ = std::fs::remove_file(av1_image_filename(&key, bit_depth));
+                                                            let _ = std::fs::remove_file(vpx_image_filename(&key, bit_depth));
+                                                        }
+
+                                                        // and any HiPS tiles
+                                                        let _ = std::fs::remove_dir_all(hips::hips_directory(&key));
+
+                                                        // and the deep-zoom tile pyramid
+                                                        let _ = std::fs::remove_dir_all(pyramid::pyramid_directory(&key));
+
+                                                        // and any exported videos
+                                                        let _ = std::fs::remove_dir_all(export::export_directory(&key));
                                                     });
                                                 }
                                             }
@@ -294,6 +568,21 @@ impl Default for SessionServer {
                                                         let imagename = format!("{}/{}.img", IMAGECACHE, key);
                                                         let imagepath = std::path::Path::new(&imagename);
                                                         let _ = std::fs::remove_file(imagepath);
+
+                                                        // and its AV1 (8, 10 and 12-bit) and VP9 (10 and 12-bit) counterparts
+                                                        for bit_depth in [8, 10, 12] {
+                                                            let _ = std::fs::remove_file(av1_image_filename(&key, bit_depth));
+                                                            let _ = std::fs::remove_file(vpx_image_filename(&key, bit_depth));
+                                                        }
+
+                                                        // and any HiPS tiles
+                                                        let _ = std::fs::remove_dir_all(hips::hips_directory(&key));
+
+                                                        // and the deep-zoom tile pyramid
+                                                        let _ = std::fs::remove_dir_all(pyramid::pyramid_directory(&key));
+
+                                                        // and any exported videos
+                                                        let _ = std::fs::remove_dir_all(export::export_directory(&key));
                                                 }
                                             }
                                         } else {
@@ -311,13 +600,114 @@ impl Default for SessionServer {
                 }
             }
         }
+
+            // the file browser thumbnails are keyed by path rather than by dataset, expire them by age
+            thumbnail::cleanup(timeout);
     });
 
+        let datasets_copy = datasets.clone();
+
+        let memory_guard = timer.schedule_repeating(chrono::Duration::try_seconds(MEMORY_CHECK_INTERVAL).expect("a valid number of seconds"), move || {
+            if MEMORY_BUDGET.load(Ordering::SeqCst) > 0 {
+                let active = datasets_copy.read().clone();
+                enforce_memory_budget(&active, "", 0);
+            }
+        });
+
         SessionServer {
             sessions: HashMap::new(),
             datasets: datasets,
+            groups: HashMap::new(),
             timer: timer,
             _guard: guard,
+            _memory_guard: memory_guard,
+        }
+    }
+}
+
+impl SessionServer {
+    fn relay(&self, id: &Uuid, text: &str) {
+        if let Some(session) = self.sessions.get(id) {
+            let _ = session.relay.do_send(Relay {
+                text: text.to_string(),
+            });
+        }
+    }
+
+    /// tell every session of a dataset about a change in its "follow me" group
+    fn notify_follow(&self, dataset_id: &str, event: &str, session: &Uuid) {
+        let sessions = match self.datasets.read().get(dataset_id) {
+            Some(dataset) => dataset.clone(),
+            None => return,
+        };
+
+        let group = self.groups.get(dataset_id);
+
+        let (presenter, followers): (Option<String>, Vec<String>) = match group {
+            Some(group) => (
+                Some(group.presenter.to_string()),
+                group.followers.iter().map(|x| x.to_string()).collect(),
+            ),
+            None => (None, Vec::new()),
+        };
+
+        for id in sessions {
+            let role = match group {
+                Some(group) if group.presenter == id => "presenter",
+                Some(group) if group.followers.contains(&id) => "follower",
+                _ => "none",
+            };
+
+            let msg = json!({
+                "type" : "follow",
+                "event" : event,
+                "session" : session.to_string(),
+                "presenter" : presenter,
+                "followers" : followers,
+                "role" : role,
+            })
+            .to_string();
+
+            self.relay(&id, &msg);
+        }
+    }
+
+    /// reply to the originator only
+    fn reject_follow(&self, id: &Uuid, reason: &str) {
+        let msg = json!({
+            "type" : "follow",
+            "event" : "rejected",
+            "reason" : reason,
+        })
+        .to_string();
+
+        self.relay(id, &msg);
+    }
+
+    fn leave_follow(&mut self, dataset_id: &str, id: &Uuid) {
+        let is_presenter = match self.groups.get_mut(dataset_id) {
+            Some(group) => {
+                if group.presenter == *id {
+                    true
+                } else if group.followers.remove(id) {
+                    false
+                } else {
+                    return;
+                }
+            }
+            None => return,
+        };
+
+        if is_presenter {
+            self.groups.remove(dataset_id);
+
+            println!("[SessionServer]: {}/{} stopped presenting", dataset_id, id);
+
+            self.notify_follow(dataset_id, "end", id);
+        } else {
+            println!("[SessionServer]: {}/{} stopped following", dataset_id, id);
+
+            self.notify_follow(dataset_id, "leave", id);
         }
     }
 }
@@ -339,7 +729,16 @@ impl Handler<Connect> for SessionServer {
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) -> Self::Result {
         // register a new session
         let id = msg.id;
-        self.sessions.insert(id, msg.addr);
+        self.sessions.insert(
+            id,
+            Session {
+                addr: msg.addr,
+                kick: msg.kick,
+                relay: msg.relay,
+                dataset_id: msg.dataset_id.clone(),
+                connected: SystemTime::now(),
+            },
+        );
 
         println!(
             "[SessionServer]: registering a new session {}/{}",
@@ -365,6 +764,9 @@ impl Handler<Disconnect> for SessionServer {
         //let id = Uuid::parse_str(&msg.id).unwrap();
         let id = msg.id;
 
+        //hand the presentation back (or end it) while the session can still be notified
+        self.leave_follow(&msg.dataset_id, &id);
+
         if self.sessions.remove(&id).is_some() {
             println!(
                 "[SessionServer]: removing a session {}/{}",
@@ -423,27 +825,11 @@ impl Handler<Disconnect> for SessionServer {
                                     //do not remove dummy datasets (loading progress etc)
                                     //they will be cleaned in a separate garbage collection thread
                                     if !is_dummy {
-                                        let entry = DATASETS.write().remove(&msg.dataset_id) ;
-                                        match entry {
-                                            Some(value) => {
-                                                std::thread::spawn(move || {
-                                                    #[cfg(target_os = "linux")]
-                                                    {
-                                                        match set_current_thread_priority(ThreadPriority::Min) {
-                                                            Ok(_) => println!("successfully lowered priority for the dataset drop thread"),
-                                                            Err(err) => println!("error changing the thread priority: {:?}", err),
-                                                        }
-                                                    };
-
-                                                    let fits = value.read();
-                                                    println!("non-blocking drop for {}", fits.dataset_id);
-                                                    fits.drop_to_cache();
-                                                });
-
-                                                println!("resuming the actix server thread");
-                                            },
-                                            None => println!("{} not found in the DATASETS", &msg.dataset_id),
-                                        };
+                                        if evict_dataset(&msg.dataset_id) {
+                                            println!("resuming the actix server thread");
+                                        } else {
+                                            println!("{} not found in the DATASETS", &msg.dataset_id);
+                                        }
                                     }
                                 }
                             };
@@ -470,6 +856,191 @@ impl Handler<Remove> for SessionServer {
     }
 }
 
+/// admission control for a new dataset load
+impl Handler<Admit> for SessionServer {
+    type Result = bool;
+
+    fn handle(&mut self, msg: Admit, _: &mut Context<Self>) -> Self::Result {
+        let active = self.datasets.read().clone();
+
+        //a re-admission (i.e. a reload) replaces the previous reservation
+        release_reservation(&msg.dataset_id);
+
+        let admitted = enforce_memory_budget(&active, &msg.dataset_id, msg.bytes);
+
+        //Admit messages are handled one at a time, so concurrent loads see each other's reservations
+        if admitted {
+            RESERVATIONS
+                .lock()
+                .insert(msg.dataset_id.clone(), msg.bytes);
+        }
+
+        println!(
+            "[SessionServer]: admission of {} ({} bytes): {}",
+            msg.dataset_id, msg.bytes, admitted
+        );
+
+        admitted
+    }
+}
+
+/// list all the sessions
+impl Handler<ListSessions> for SessionServer {
+    type Result = MessageResult<ListSessions>;
+
+    fn handle(&mut self, _: ListSessions, _: &mut Context<Self>) -> Self::Result {
+        let sessions = self
+            .sessions
+            .iter()
+            .map(|(id, session)| SessionInfo {
+                id: *id,
+                dataset_id: session.dataset_id.clone(),
+                connected: session.connected,
+            })
+            .collect();
+
+        MessageResult(sessions)
+    }
+}
+
+/// ask a session to close; the session will unregister itself via Disconnect
+impl Handler<DisconnectSession> for SessionServer {
+    type Result = bool;
+
+    fn handle(&mut self, msg: DisconnectSession, _: &mut Context<Self>) -> Self::Result {
+        match self.sessions.get(&msg.id) {
+            Some(session) => {
+                println!(
+                    "[SessionServer]: disconnecting a session {}/{}",
+                    session.dataset_id, msg.id
+                );
+
+                session.kick.do_send(Kick {
+                    reason: String::from("disconnected by the administrator"),
+                });
+
+                true
+            }
+            None => {
+                println!("[SessionServer]: session {} not found", msg.id);
+                false
+            }
+        }
+    }
+}
+
+/// "follow me" group management
+impl Handler<Follow> for SessionServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Follow, _: &mut Context<Self>) {
+        let id = msg.id;
+        let dataset_id = msg.dataset_id;
+
+        println!(
+            "[SessionServer]: {:?} request from {}/{}",
+            msg.action, dataset_id, id
+        );
+
+        match msg.action {
+            FollowAction::Present => {
+                if let Some(group) = self.groups.get(&dataset_id) {
+                    if group.presenter != id {
+                        self.reject_follow(&id, "another session is already presenting");
+                    }
+
+                    return;
+                }
+
+                self.groups.insert(
+                    dataset_id.clone(),
+                    FollowGroup {
+                        presenter: id,
+                        followers: HashSet::new(),
+                        last_state: None,
+                    },
+                );
+
+                self.notify_follow(&dataset_id, "present", &id);
+            }
+            FollowAction::Join => {
+                let last_state = match self.groups.get_mut(&dataset_id) {
+                    Some(group) => {
+                        if group.presenter == id || !group.followers.insert(id) {
+                            return;
+                        }
+
+                        group.last_state.clone()
+                    }
+                    None => {
+                        self.reject_follow(&id, "nobody is presenting this dataset");
+                        return;
+                    }
+                };
+
+                self.notify_follow(&dataset_id, "join", &id);
+
+                //bring the new follower up to date
+                if let Some(state) = last_state {
+                    self.relay(&id, &state);
+                }
+            }
+            FollowAction::Leave => self.leave_follow(&dataset_id, &id),
+            FollowAction::HandOver(to) => {
+                match self.groups.get_mut(&dataset_id) {
+                    Some(group) => {
+                        if group.presenter != id {
+                            self.reject_follow(&id, "only the presenter can hand over the control");
+                            return;
+                        }
+
+                        if !group.followers.remove(&to) {
+                            self.reject_follow(&id, "the new presenter must be a follower");
+                            return;
+                        }
+
+                        group.followers.insert(id);
+                        group.presenter = to;
+                    }
+                    None => {
+                        self.reject_follow(&id, "nobody is presenting this dataset");
+                        return;
+                    }
+                };
+
+                self.notify_follow(&dataset_id, "handover", &to);
+            }
+        }
+    }
+}
+
+/// mirror the presenter's messages to the followers
+impl Handler<FollowRelay> for SessionServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: FollowRelay, _: &mut Context<Self>) {
+        let followers: Vec<Uuid> = match self.groups.get_mut(&msg.dataset_id) {
+            Some(group) => {
+                //ignore the followers' own changes
+                if group.presenter != msg.id {
+                    return;
+                }
+
+                if msg.retain {
+                    group.last_state = Some(msg.message.clone());
+                }
+
+                group.followers.iter().cloned().collect()
+            }
+            None => return,
+        };
+
+        for id in followers {
+            self.relay(&id, &msg.message);
+        }
+    }
+}
+
 /// Handler for WsMessage message.
 impl Handler<WsMessage> for SessionServer {
     type Result = ();
@@ -482,8 +1053,8 @@ impl Handler<WsMessage> for SessionServer {
                 //progress interval checking has been moved to the websocket actor
                 //simply pass through all progress notifications
                 for id in dataset {
-                    if let Some(addr) = self.sessions.get(id) {
-                        let _ = addr.do_send(WsMessage {
+                    if let Some(session) = self.sessions.get(id) {
+                        let _ = session.addr.do_send(WsMessage {
                             notification: msg.notification.clone(),
                             total: msg.total,
                             running: msg.running,
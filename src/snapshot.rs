@@ -0,0 +1,1140 @@
+use crate::colourmap::colourmap_rgb;
+use crate::hips::{CelestialWcs, Frame};
+use std::time::Instant;
+
+//the largest PNG snapshot width (height) in pixels
+pub const SNAPSHOT_MAX_SIZE: u32 = 8192;
+
+const BACKGROUND: [u8; 3] = [255, 255, 255];
+const FOREGROUND: [u8; 3] = [0, 0, 0];
+const GRID_COLOUR: [u8; 3] = [255, 255, 255];
+const GRID_OPACITY: f32 = 0.5;
+const CONTOUR_COLOUR: [u8; 3] = [255, 255, 255];
+
+//the bitmap font cell: 5x7 glyphs plus spacing
+const GLYPH_WIDTH: i64 = 5;
+const GLYPH_HEIGHT: i64 = 7;
+const GLYPH_ADVANCE: i64 = 6;
+
+//the world coordinate range is estimated on a (GRID_RANGE_SAMPLES + 1)^2 lattice of pixels,
+//each grid line is traced through GRID_LINE_SAMPLES + 1 points
+const GRID_RANGE_SAMPLES: usize = 32;
+const GRID_LINE_SAMPLES: usize = 256;
+
+#[derive(Debug)]
+pub struct SnapshotParams {
+    pub frame_start: Option<f64>,
+    pub frame_end: Option<f64>,
+    pub ref_freq: f64,
+    pub flux: Option<String>,
+    pub black: Option<f32>,
+    pub white: Option<f32>,
+    pub median: Option<f32>,
+    pub noise: f32,
+    pub region: Option<(i32, i32, i32, i32)>, //x1, y1, x2, y2 (0-based image pixels)
+    pub width: Option<u32>,
+    pub colourmap: String,
+    pub contours: usize,
+    pub grid: bool,
+    pub colourbar: bool,
+    pub snr: bool, //a signal-to-noise ratio map instead of the integrated intensity
+}
+
+//the axis titles
+fn axis_names(frame: Frame) -> (&'static str, &'static str) {
+    match frame {
+        Frame::Equatorial => ("RA", "DEC"),
+        Frame::Galactic => ("GLON", "GLAT"),
+    }
+}
+
+/// the (already stretched) luminance of the snapshot region, top row first
+pub struct SnapshotImage<'a> {
+    pub luminance: &'a [u8],
+    pub mask: &'a [u8],
+    pub width: u32,
+    pub height: u32,
+    pub x1: f64, //the region origin in image pixels
+    pub y1: f64,
+}
+
+/// a labelled colourbar tick at a given luminance
+pub struct ColourbarTick {
+    pub luminance: u8,
+    pub label: String,
+}
+
+struct Canvas {
+    width: i64,
+    height: i64,
+    data: Vec<u8>,                      //RGB
+    clip: Option<(i64, i64, i64, i64)>, //x, y, width, height
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> Canvas {
+        let mut data = vec![0; 3 * (width as usize) * (height as usize)];
+
+        for pixel in data.chunks_mut(3) {
+            pixel.copy_from_slice(&BACKGROUND);
+        }
+
+        Canvas {
+            width: width as i64,
+            height: height as i64,
+            data: data,
+            clip: None,
+        }
+    }
+
+    fn blend(&mut self, x: i64, y: i64, colour: [u8; 3], opacity: f32) {
+        if x < 0 || y < 0 || x >= self.width || y >= self.height {
+            return;
+        }
+
+        if let Some((cx, cy, cw, ch)) = self.clip {
+            if x < cx || y < cy || x >= cx + cw || y >= cy + ch {
+                return;
+            }
+        }
+
+        let offset = 3 * (y * self.width + x) as usize;
+
+        for c in 0..3 {
+            let src = colour[c] as f32;
+            let dst = self.data[offset + c] as f32;
+            self.data[offset + c] = (opacity * src + (1.0 - opacity) * dst).round() as u8;
+        }
+    }
+
+    fn put(&mut self, x: i64, y: i64, colour: [u8; 3]) {
+        self.blend(x, y, colour, 1.0);
+    }
+
+    fn fill(&mut self, x: i64, y: i64, w: i64, h: i64, colour: [u8; 3]) {
+        for j in y..y + h {
+            for i in x..x + w {
+                self.put(i, j, colour);
+            }
+        }
+    }
+
+    //Bresenham, thickened to <thickness> pixels
+    fn line(
+        &mut self,
+        (x0, y0): (i64, i64),
+        (x1, y1): (i64, i64),
+        colour: [u8; 3],
+        opacity: f32,
+        thickness: i64,
+    ) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let sx = if x0 < x1 { 1 } else { -1 };
+        let sy = if y0 < y1 { 1 } else { -1 };
+
+        let mut err = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+
+        loop {
+            for t in 0..thickness {
+                if dx > -dy {
+                    self.blend(x, y + t, colour, opacity);
+                } else {
+                    self.blend(x + t, y, colour, opacity);
+                }
+            }
+
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let e2 = 2 * err;
+
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    fn rectangle(&mut self, x: i64, y: i64, w: i64, h: i64, colour: [u8; 3], thickness: i64) {
+        self.fill(x, y, w, thickness, colour);
+        self.fill(x, y + h - thickness, w, thickness, colour);
+        self.fill(x, y, thickness, h, colour);
+        self.fill(x + w - thickness, y, thickness, h, colour);
+    }
+
+    fn text(&mut self, x: i64, y: i64, text: &str, scale: i64, colour: [u8; 3]) {
+        let mut pen = x;
+
+        for c in text.chars() {
+            let glyph = glyph(c);
+
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (0x10 >> col) != 0 {
+                        self.fill(
+                            pen + col * scale,
+                            y + (row as i64) * scale,
+                            scale,
+                            scale,
+                            colour,
+                        );
+                    }
+                }
+            }
+
+            pen += GLYPH_ADVANCE * scale;
+        }
+    }
+
+    fn to_png(&self) -> Option<Vec<u8>> {
+        let mut buffer: Vec<u8> = Vec::new();
+
+        let mut encoder = png::Encoder::new(&mut buffer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let result = encoder.write_header().and_then(|mut writer| {
+            writer.write_image_data(&self.data)?;
+            writer.finish()
+        });
+
+        match result {
+            Ok(()) => Some(buffer),
+            Err(err) => {
+                println!("PNG snapshot encoding error: {}", err);
+                None
+            }
+        }
+    }
+}
+
+fn text_width(text: &str, scale: i64) -> i64 {
+    let n = text.chars().count() as i64;
+
+    if n > 0 {
+        (n * GLYPH_ADVANCE - 1) * scale
+    } else {
+        0
+    }
+}
+
+/// burn a text label into an 8-bit luma plane (white with a dark shadow), e.g. video frames
+pub fn label_luma(
+    plane: &mut [u8],
+    width: u32,
+    height: u32,
+    x: i64,
+    y: i64,
+    text: &str,
+    scale: i64,
+) {
+    let (width, height) = (width as i64, height as i64);
+
+    for (dx, value) in [(scale.max(1) / 2 + 1, 16u8), (0, 235u8)] {
+        let mut pen = x + dx;
+
+        for c in text.chars() {
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (0x10 >> col) == 0 {
+                        continue;
+                    }
+
+                    let top = y + dx + (row as i64) * scale;
+
+                    for py in top.max(0)..(top + scale).min(height) {
+                        for px in (pen + col * scale).max(0)..(pen + (col + 1) * scale).min(width) {
+                            plane[(py * width + px) as usize] = value;
+                        }
+                    }
+                }
+            }
+
+            pen += GLYPH_ADVANCE * scale;
+        }
+    }
+}
+
+/// the label font height in pixels
+pub fn label_height(scale: i64) -> i64 {
+    GLYPH_HEIGHT * scale
+}
+
+//an upper-case 5x7 bitmap font, lower-case letters are drawn as capitals
+fn glyph(c: char) -> [u8; 7] {
+    match c.to_ascii_uppercase() {
+        '0' => [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E],
+        '1' => [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        '2' => [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F],
+        '3' => [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E],
+        '4' => [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02],
+        '5' => [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E],
+        '6' => [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E],
+        '7' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E],
+        '9' => [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C],
+        'A' => [0x0E, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'B' => [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E],
+        'C' => [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E],
+        'D' => [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C],
+        'E' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F],
+        'F' => [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10],
+        'G' => [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F],
+        'H' => [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11],
+        'I' => [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E],
+        'J' => [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C],
+        'K' => [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F],
+        'M' => [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11],
+        'N' => [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11],
+        'O' => [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'P' => [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10],
+        'Q' => [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D],
+        'R' => [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11],
+        'S' => [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E],
+        'T' => [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        'U' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E],
+        'V' => [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04],
+        'W' => [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A],
+        'X' => [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11],
+        'Y' => [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04],
+        'Z' => [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F],
+        '+' => [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00],
+        '-' => [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00],
+        '=' => [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C],
+        ',' => [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08],
+        ':' => [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00],
+        '/' => [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00],
+        '*' => [0x00, 0x04, 0x15, 0x0E, 0x15, 0x04, 0x00],
+        '^' => [0x04, 0x0A, 0x11, 0x00, 0x00, 0x00, 0x00],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F],
+        '(' => [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02],
+        ')' => [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08],
+        '\'' => [0x04, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00],
+        '"' => [0x0A, 0x0A, 0x14, 0x00, 0x00, 0x00, 0x00],
+        '°' => [0x0C, 0x12, 0x12, 0x0C, 0x00, 0x00, 0x00],
+        _ => [0x00; 7],
+    }
+}
+
+/// "nice" (1, 2, 5 x 10^n) tick values covering [lo, hi]
+pub fn nice_ticks(lo: f64, hi: f64, max_ticks: usize) -> (Vec<f64>, f64) {
+    if !lo.is_finite() || !hi.is_finite() || hi <= lo || max_ticks < 2 {
+        return (Vec::new(), 0.0);
+    }
+
+    let raw_step = (hi - lo) / ((max_ticks - 1) as f64);
+    let magnitude = 10f64.powf(raw_step.log10().floor());
+
+    let step = [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .find(|s| *s >= raw_step)
+        .unwrap_or(10.0 * magnitude);
+
+    let mut ticks = Vec::new();
+    let mut k = (lo / step).ceil();
+
+    while k * step <= hi + 1e-9 * step {
+        //avoid printing "-0"
+        let tick = k * step;
+        ticks.push(if tick.abs() < 1e-12 * step { 0.0 } else { tick });
+        k += 1.0;
+    }
+
+    (ticks, step)
+}
+
+//the decimal places telling apart the multiples of a step (0.5 needs one, 0.05 two)
+fn step_decimals(step: f64) -> u32 {
+    if step >= 1.0 || !step.is_finite() {
+        0
+    } else {
+        ((-step.log10() - 1.0e-9).ceil() as u32).min(6)
+    }
+}
+
+pub fn format_tick(value: f64, step: f64) -> String {
+    if value == 0.0 {
+        return String::from("0");
+    }
+
+    if value.abs() >= 1.0e5 || step < 1.0e-4 {
+        return format!("{:.2E}", value);
+    }
+
+    let text = format!("{:.*}", step_decimals(step) as usize, value);
+
+    //a rounding error around zero, not "-0.0"
+    if text.chars().all(|c| c == '-' || c == '0' || c == '.') {
+        return String::from("0");
+    }
+
+    text
+}
+
+//the grid step [seconds of time or arcseconds], aiming at a few grid lines across the span
+fn sexagesimal_step(span: f64, max_lines: usize) -> f64 {
+    let steps = [
+        0.01, 0.02, 0.05, 0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 15.0, 20.0, 30.0, 60.0, 120.0, 300.0,
+        600.0, 900.0, 1200.0, 1800.0, 3600.0, 7200.0, 18000.0, 36000.0, 72000.0,
+    ];
+
+    steps
+        .iter()
+        .cloned()
+        .find(|s| span / s <= max_lines as f64)
+        .unwrap_or(144000.0)
+}
+
+//seconds (in units of 10^-decimals) with the decimal places, if any
+fn format_seconds(s: i64, decimals: u32) -> String {
+    let scale = 10i64.pow(decimals);
+
+    if decimals == 0 {
+        format!("{:02}", s)
+    } else {
+        format!(
+            "{:02}.{:0width$}",
+            s / scale,
+            s % scale,
+            width = decimals as usize
+        )
+    }
+}
+
+//the value rounded to the last digit shown, in units of 10^-decimals seconds
+//(rounding before splitting turns 59.99 into the next minute instead of ":60")
+fn sexagesimal_units(value: f64, step: f64, decimals: u32) -> i64 {
+    let scale = 10i64.pow(decimals);
+
+    let unit = if step >= 3600.0 {
+        3600
+    } else if step >= 60.0 {
+        60
+    } else {
+        1
+    };
+
+    if unit > 1 {
+        (value / unit as f64).round() as i64 * unit * scale
+    } else {
+        (value * scale as f64).round() as i64
+    }
+}
+
+//[seconds of time] -> hh:mm:ss(.s)
+fn format_ra(seconds: f64, step: f64) -> String {
+    let decimals = step_decimals(step);
+    let scale = 10i64.pow(decimals);
+
+    //the minutes are shown for steps of an hour too
+    let units = sexagesimal_units(seconds, step.min(60.0), decimals).rem_euclid(86400 * scale);
+
+    let h = units / (3600 * scale);
+    let m = (units / (60 * scale)) % 60;
+    let s = units % (60 * scale);
+
+    if step >= 60.0 {
+        format!("{:02}:{:02}", h, m)
+    } else {
+        format!("{:02}:{:02}:{}", h, m, format_seconds(s, decimals))
+    }
+}
+
+//[arcseconds] -> +dd°mm'ss(.s)"
+fn format_dec(arcsec: f64, step: f64) -> String {
+    let decimals = step_decimals(step);
+    let scale = 10i64.pow(decimals);
+    let units = sexagesimal_units(arcsec.abs(), step, decimals);
+
+    //no "-00°00'00"" for a value rounded to zero
+    let sign = if arcsec < 0.0 && units > 0 { "-" } else { "+" };
+
+    let d = units / (3600 * scale);
+    let m = (units / (60 * scale)) % 60;
+    let s = units % (60 * scale);
+
+    if step >= 3600.0 {
+        format!("{}{:02}°", sign, d)
+    } else if step >= 60.0 {
+        format!("{}{:02}°{:02}'", sign, d, m)
+    } else {
+        format!(
+            "{}{:02}°{:02}'{}\"",
+            sign,
+            d,
+            m,
+            format_seconds(s, decimals)
+        )
+    }
+}
+
+/// where a 0-based image pixel lands in the output image of the given size
+fn to_output(image: &SnapshotImage, (out_w, out_h): (i64, i64), px: f64, py: f64) -> (f64, f64) {
+    let sx = (image.width as f64) / (out_w as f64);
+    let sy = (image.height as f64) / (out_h as f64);
+
+    //the rows run from the top of the region down to its bottom
+    let row = (image.height as f64) - 1.0 - (py - image.y1);
+
+    ((px - image.x1 + 0.5) / sx - 0.5, (row + 0.5) / sy - 0.5)
+}
+
+//the longitude and latitude ranges [deg] covered by the region, the longitudes
+//unwrapped around the centre of the region
+fn world_range(wcs: &CelestialWcs, image: &SnapshotImage) -> Option<(f64, f64, f64, f64)> {
+    let (w, h) = (image.width as f64, image.height as f64);
+    let (x0, y0) = (image.x1 - 0.5, image.y1 - 0.5);
+
+    let (lon0, _) = wcs.pixel_to_world(x0 + 0.5 * w, y0 + 0.5 * h)?;
+
+    let (mut lon_lo, mut lon_hi) = (f64::INFINITY, f64::NEG_INFINITY);
+    let (mut lat_lo, mut lat_hi) = (f64::INFINITY, f64::NEG_INFINITY);
+
+    for i in 0..=GRID_RANGE_SAMPLES {
+        for j in 0..=GRID_RANGE_SAMPLES {
+            let px = x0 + w * (i as f64) / (GRID_RANGE_SAMPLES as f64);
+            let py = y0 + h * (j as f64) / (GRID_RANGE_SAMPLES as f64);
+
+            if let Some((lon, lat)) = wcs.pixel_to_world(px, py) {
+                let lon = lon0 + (lon - lon0 + 180.0).rem_euclid(360.0) - 180.0;
+
+                lon_lo = lon_lo.min(lon);
+                lon_hi = lon_hi.max(lon);
+                lat_lo = lat_lo.min(lat);
+                lat_hi = lat_hi.max(lat);
+            }
+        }
+    }
+
+    if !(lon_lo <= lon_hi && lat_lo <= lat_hi) {
+        return None;
+    }
+
+    //a celestial pole inside the region: all the meridians pass through it
+    let inside = |lat: f64| match wcs.world_to_pixel(0.0, lat) {
+        Some((px, py)) => px >= x0 && px <= x0 + w && py >= y0 && py <= y0 + h,
+        None => false,
+    };
+
+    if inside(90.0) {
+        return Some((0.0, 360.0, lat_lo, 90.0));
+    }
+
+    if inside(-90.0) {
+        return Some((0.0, 360.0, -90.0, lat_hi));
+    }
+
+    Some((lon_lo, lon_hi, lat_lo, lat_hi))
+}
+
+//the grid line values within [lo, hi] [deg] with their labels
+fn grid_values(lo: f64, hi: f64, kind: char) -> Vec<(f64, String)> {
+    //world units per degree and labels
+    let (units, step) = match kind {
+        'h' => (240.0, sexagesimal_step((hi - lo) * 240.0, 6)),
+        'd' => (3600.0, sexagesimal_step((hi - lo) * 3600.0, 6)),
+        _ => (1.0, nice_ticks(lo, hi, 6).1),
+    };
+
+    //an unbounded range would never end
+    if !lo.is_finite() || !hi.is_finite() || step.is_nan() || step <= 0.0 {
+        return Vec::new();
+    }
+
+    let mut values = Vec::new();
+    let mut k = (lo * units / step).ceil();
+
+    while k * step <= hi * units {
+        let world = k * step / units;
+
+        let label = match kind {
+            'h' => format_ra(world.rem_euclid(360.0) * units, step),
+            'd' => format_dec(world * units, step),
+            'g' => {
+                //359.99999... is labelled 0, not 360
+                let lon = world.rem_euclid(360.0);
+                let lon = if 360.0 - lon < 1.0e-9 * step {
+                    0.0
+                } else {
+                    lon
+                };
+                format_tick(lon, step)
+            }
+            _ => format_tick(world, step),
+        };
+
+        values.push((world, label));
+        k += 1.0;
+    }
+
+    values
+}
+
+/// a grid line traced through world coordinates, in output pixels (None where not projected)
+fn grid_curve<F>(
+    wcs: &CelestialWcs,
+    image: &SnapshotImage,
+    size: (i64, i64),
+    world: F,
+) -> Vec<Option<(f64, f64)>>
+where
+    F: Fn(f64) -> (f64, f64),
+{
+    (0..=GRID_LINE_SAMPLES)
+        .map(|i| {
+            let (lon, lat) = world((i as f64) / (GRID_LINE_SAMPLES as f64));
+
+            if lat.abs() > 90.0 {
+                return None;
+            }
+
+            let (px, py) = wcs.world_to_pixel(lon, lat)?;
+            Some(to_output(image, size, px, py))
+        })
+        .collect()
+}
+
+//where a grid line crosses an edge (an x = edge or a y = edge line), along the other axis
+fn edge_crossing(curve: &[Option<(f64, f64)>], edge: f64, vertical: bool) -> Option<f64> {
+    let coords = |p: (f64, f64)| if vertical { (p.0, p.1) } else { (p.1, p.0) };
+
+    curve.windows(2).find_map(|pair| {
+        let (a, b) = (coords(pair[0]?), coords(pair[1]?));
+
+        if (a.0 - edge) * (b.0 - edge) > 0.0 || a.0 == b.0 {
+            return None;
+        }
+
+        let t = (edge - a.0) / (b.0 - a.0);
+        Some(a.1 + t * (b.1 - a.1))
+    })
+}
+
+//bilinear resampling of the luminance; masked pixels are marked with -1
+fn resample(image: &SnapshotImage, width: u32, height: u32) -> Vec<i16> {
+    let sx = (image.width as f64) / (width as f64);
+    let sy = (image.height as f64) / (height as f64);
+    let w = image.width as i64;
+    let h = image.height as i64;
+
+    let at = |x: i64, y: i64| -> Option<f64> {
+        let x = x.clamp(0, w - 1);
+        let y = y.clamp(0, h - 1);
+        let offset = (y * w + x) as usize;
+
+        if image.mask[offset] > 0 {
+            Some(image.luminance[offset] as f64)
+        } else {
+            None
+        }
+    };
+
+    let mut dst = vec![-1; (width as usize) * (height as usize)];
+
+    for j in 0..height as usize {
+        let fy = ((j as f64) + 0.5) * sy - 0.5;
+        let y0 = fy.floor();
+        let ty = fy - y0;
+
+        for i in 0..width as usize {
+            let fx = ((i as f64) + 0.5) * sx - 0.5;
+            let x0 = fx.floor();
+            let tx = fx - x0;
+
+            let (x0, y0i) = (x0 as i64, y0 as i64);
+
+            //nearest-neighbour masking
+            if at(fx.round() as i64, fy.round() as i64).is_none() {
+                continue;
+            }
+
+            let corners = [
+                (at(x0, y0i), (1.0 - tx) * (1.0 - ty)),
+                (at(x0 + 1, y0i), tx * (1.0 - ty)),
+                (at(x0, y0i + 1), (1.0 - tx) * ty),
+                (at(x0 + 1, y0i + 1), tx * ty),
+            ];
+
+            let (sum, weight) = corners
+                .iter()
+                .filter_map(|(v, wt)| v.map(|v| (v * wt, *wt)))
+                .fold((0.0, 0.0), |acc, x| (acc.0 + x.0, acc.1 + x.1));
+
+            if weight > 0.0 {
+                dst[j * (width as usize) + i] = (sum / weight).round().clamp(0.0, 255.0) as i16;
+            }
+        }
+    }
+
+    dst
+}
+
+//marching squares over the resampled luminance at evenly spaced levels
+fn draw_contours(
+    canvas: &mut Canvas,
+    lum: &[i16],
+    width: i64,
+    height: i64,
+    (ox, oy): (i64, i64),
+    levels: usize,
+    thickness: i64,
+) {
+    for n in 1..=levels {
+        let level = 256.0 * (n as f64) / ((levels + 1) as f64);
+
+        for j in 0..height - 1 {
+            for i in 0..width - 1 {
+                let v = [
+                    lum[(j * width + i) as usize],
+                    lum[(j * width + i + 1) as usize],
+                    lum[((j + 1) * width + i + 1) as usize],
+                    lum[((j + 1) * width + i) as usize],
+                ];
+
+                if v.iter().any(|x| *x < 0) {
+                    continue;
+                }
+
+                let corners = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+                let mut points: Vec<(f64, f64)> = Vec::with_capacity(4);
+
+                for e in 0..4 {
+                    let (a, b) = (v[e] as f64, v[(e + 1) % 4] as f64);
+
+                    if (a < level) != (b < level) {
+                        let t = (level - a) / (b - a);
+                        let (p, q) = (corners[e], corners[(e + 1) % 4]);
+                        points.push((p.0 + t * (q.0 - p.0), p.1 + t * (q.1 - p.1)));
+                    }
+                }
+
+                for pair in points.chunks(2) {
+                    if pair.len() == 2 {
+                        let to_canvas = |p: (f64, f64)| {
+                            (ox + i + p.0.round() as i64, oy + j + p.1.round() as i64)
+                        };
+
+                        canvas.line(
+                            to_canvas(pair[0]),
+                            to_canvas(pair[1]),
+                            CONTOUR_COLOUR,
+                            1.0,
+                            thickness,
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// render a colourmapped PNG snapshot with an optional colourbar, WCS grid and contours
+pub fn render(
+    image: &SnapshotImage,
+    wcs: Option<&CelestialWcs>,
+    ticks: &[ColourbarTick],
+    unit: &str,
+    params: &SnapshotParams,
+) -> Option<Vec<u8>> {
+    let watch = Instant::now();
+
+    if image.width == 0 || image.height == 0 {
+        return None;
+    }
+
+    //the output image size
+    let out_w = params
+        .width
+        .unwrap_or(image.width)
+        .clamp(16, SNAPSHOT_MAX_SIZE);
+    let out_h = (((out_w as f64) * (image.height as f64) / (image.width as f64)).round() as u32)
+        .clamp(1, SNAPSHOT_MAX_SIZE);
+
+    //the font scale follows the output resolution
+    let font = ((out_w as i64) / 512).clamp(1, 4);
+    let thickness = font.min(2);
+    let grid = match wcs {
+        Some(wcs) if params.grid => world_range(wcs, image).map(|range| (wcs, range)),
+        _ => None,
+    };
+
+    let margin = 10 * font;
+    let label_height = (GLYPH_HEIGHT + 4) * font;
+
+    let dec_labels_width = if grid.is_some() {
+        text_width("+00°00'00.0\"", font) + margin
+    } else {
+        0
+    };
+    let left = margin + dec_labels_width;
+    let top = margin
+        + if grid.is_some() || params.colourbar {
+            label_height
+        } else {
+            0
+        };
+    let bottom = margin + if grid.is_some() { 2 * label_height } else { 0 };
+
+    let bar_width = 16 * font;
+    let tick_labels_width = ticks
+        .iter()
+        .map(|t| text_width(&t.label, font))
+        .max()
+        .unwrap_or(0)
+        .max(text_width(unit, font));
+    let right = margin
+        + if params.colourbar {
+            2 * margin + bar_width + 4 * font + tick_labels_width
+        } else {
+            0
+        };
+
+    let mut canvas = Canvas::new(
+        (left + out_w as i64 + right) as u32,
+        (top + out_h as i64 + bottom) as u32,
+    );
+
+    //the colourmapped image
+    let lum = resample(image, out_w, out_h);
+
+    for j in 0..out_h as i64 {
+        for i in 0..out_w as i64 {
+            let pixel = lum[(j * (out_w as i64) + i) as usize];
+
+            if pixel >= 0 {
+                canvas.put(
+                    left + i,
+                    top + j,
+                    colourmap_rgb(&params.colourmap, pixel as u8),
+                );
+            }
+        }
+    }
+
+    if params.contours > 0 {
+        draw_contours(
+            &mut canvas,
+            &lum,
+            out_w as i64,
+            out_h as i64,
+            (left, top),
+            params.contours,
+            thickness,
+        );
+    }
+
+    //RA/Dec (or galactic) grid lines with labels, traced through the full celestial WCS
+    if let Some((wcs, (lon_lo, lon_hi, lat_lo, lat_hi))) = grid {
+        let size = (out_w as i64, out_h as i64);
+
+        let (kind1, kind2) = match wcs.frame {
+            Frame::Equatorial => ('h', 'd'),
+            Frame::Galactic => ('g', 'l'),
+        };
+
+        let meridians: Vec<(Vec<Option<(f64, f64)>>, String)> = grid_values(lon_lo, lon_hi, kind1)
+            .into_iter()
+            .map(|(lon, label)| {
+                let curve = grid_curve(wcs, image, size, |t| (lon, lat_lo + t * (lat_hi - lat_lo)));
+                (curve, label)
+            })
+            .collect();
+
+        let parallels: Vec<(Vec<Option<(f64, f64)>>, String)> = grid_values(lat_lo, lat_hi, kind2)
+            .into_iter()
+            .map(|(lat, label)| {
+                let curve = grid_curve(wcs, image, size, |t| (lon_lo + t * (lon_hi - lon_lo), lat));
+                (curve, label)
+            })
+            .collect();
+
+        //keep the lines within the image
+        canvas.clip = Some((left, top, out_w as i64, out_h as i64));
+
+        let in_reach = |p: (f64, f64)| {
+            p.0 >= -(out_w as f64)
+                && p.0 <= 2.0 * (out_w as f64)
+                && p.1 >= -(out_h as f64)
+                && p.1 <= 2.0 * (out_h as f64)
+        };
+
+        for (curve, _) in meridians.iter().chain(parallels.iter()) {
+            for pair in curve.windows(2) {
+                if let (Some(a), Some(b)) = (pair[0], pair[1]) {
+                    if in_reach(a) && in_reach(b) {
+                        canvas.line(
+                            (left + a.0.round() as i64, top + a.1.round() as i64),
+                            (left + b.0.round() as i64, top + b.1.round() as i64),
+                            GRID_COLOUR,
+                            GRID_OPACITY,
+                            1,
+                        );
+                    }
+                }
+            }
+        }
+
+        canvas.clip = None;
+
+        //the longitudes along the bottom edge
+        for (curve, label) in &meridians {
+            if let Some(x) = edge_crossing(curve, out_h as f64 - 0.5, false) {
+                if x >= 0.0 && x < out_w as f64 {
+                    let w = text_width(label, font);
+                    canvas.text(
+                        left + x.round() as i64 - w / 2,
+                        top + out_h as i64 + 4 * font,
+                        label,
+                        font,
+                        FOREGROUND,
+                    );
+                }
+            }
+        }
+
+        //the latitudes along the left edge
+        for (curve, label) in &parallels {
+            if let Some(y) = edge_crossing(curve, -0.5, true) {
+                if y >= 0.0 && y < out_h as f64 {
+                    let w = text_width(label, font);
+                    canvas.text(
+                        left - w - 4 * font,
+                        top + y.round() as i64 - GLYPH_HEIGHT * font / 2,
+                        label,
+                        font,
+                        FOREGROUND,
+                    );
+                }
+            }
+        }
+
+        //axis titles
+        let (title1, title2) = axis_names(wcs.frame);
+
+        canvas.text(
+            left + (out_w as i64 - text_width(title1, font)) / 2,
+            top + out_h as i64 + label_height + 4 * font,
+            title1,
+            font,
+            FOREGROUND,
+        );
+
+        canvas.text(
+            left - text_width(title2, font) - 4 * font,
+            top - label_height,
+            title2,
+            font,
+            FOREGROUND,
+        );
+    }
+
+    //the image frame
+    canvas.rectangle(
+        left - thickness,
+        top - thickness,
+        out_w as i64 + 2 * thickness,
+        out_h as i64 + 2 * thickness,
+        FOREGROUND,
+        thickness,
+    );
+
+    //a labelled colourbar
+    if params.colourbar {
+        let bar_x = left + out_w as i64 + 2 * margin;
+        let bar_h = out_h as i64;
+
+        for j in 0..bar_h {
+            let pixel = (255.0 * ((bar_h - 1 - j) as f64) / ((bar_h - 1).max(1) as f64)).round();
+            let colour = colourmap_rgb(&params.colourmap, pixel as u8);
+            canvas.fill(bar_x, top + j, bar_width, 1, colour);
+        }
+
+        canvas.rectangle(
+            bar_x - thickness,
+            top - thickness,
+            bar_width + 2 * thickness,
+            bar_h + 2 * thickness,
+            FOREGROUND,
+            thickness,
+        );
+
+        //skip the labels that would overlap
+        let mut last_y = i64::MAX;
+
+        for tick in ticks {
+            let y = top + bar_h
+                - 1
+                - ((tick.luminance as f64) / 255.0 * ((bar_h - 1) as f64)).round() as i64;
+
+            canvas.fill(
+                bar_x + bar_width - 4 * font,
+                y,
+                4 * font,
+                thickness,
+                FOREGROUND,
+            );
+
+            if (last_y - y).abs() >= label_height {
+                canvas.text(
+                    bar_x + bar_width + 4 * font,
+                    y - GLYPH_HEIGHT * font / 2,
+                    &tick.label,
+                    font,
+                    FOREGROUND,
+                );
+
+                last_y = y;
+            }
+        }
+
+        canvas.text(bar_x, top - label_height, unit, font, FOREGROUND);
+    }
+
+    let png = canvas.to_png();
+
+    println!(
+        "PNG snapshot {}x{} (image {}x{}) render time: {:?}",
+        canvas.width,
+        canvas.height,
+        out_w,
+        out_h,
+        watch.elapsed()
+    );
+
+    png
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(values: &[(f64, String)]) -> Vec<&str> {
+        values.iter().map(|(_, label)| label.as_str()).collect()
+    }
+
+    #[test]
+    fn ticks_across_zero() {
+        let (ticks, step) = nice_ticks(-1.0, 1.0, 5);
+        assert_eq!(step, 0.5);
+        assert_eq!(ticks, vec![-1.0, -0.5, 0.0, 0.5, 1.0]);
+
+        //no negative zero, not even from ceil(-0.1)
+        let (ticks, step) = nice_ticks(-0.1, 1.0, 3);
+        assert_eq!(step, 1.0);
+        assert!(ticks[0] == 0.0 && ticks[0].is_sign_positive());
+
+        let (ticks, step) = nice_ticks(-0.3, 0.7, 6);
+        let labels: Vec<String> = ticks.iter().map(|t| format_tick(*t, step)).collect();
+        assert_eq!(labels, vec!["-0.2", "0", "0.2", "0.4", "0.6"]);
+    }
+
+    #[test]
+    fn degenerate_ticks() {
+        assert_eq!(nice_ticks(1.0, 1.0, 5), (Vec::new(), 0.0));
+        assert_eq!(nice_ticks(2.0, 1.0, 5), (Vec::new(), 0.0));
+        assert_eq!(nice_ticks(f64::NAN, 1.0, 5), (Vec::new(), 0.0));
+        assert_eq!(nice_ticks(0.0, f64::INFINITY, 5), (Vec::new(), 0.0));
+        assert_eq!(nice_ticks(0.0, 1.0, 1), (Vec::new(), 0.0));
+    }
+
+    #[test]
+    fn tick_labels() {
+        assert_eq!(format_tick(0.0, 1.0), "0");
+        assert_eq!(format_tick(-0.0, 0.5), "0");
+        assert_eq!(format_tick(-1.0e-17, 0.1), "0");
+        assert_eq!(format_tick(3.0, 1.0), "3");
+        //a step of 0.5 (or 0.2) needs a decimal place
+        assert_eq!(format_tick(2.5, 0.5), "2.5");
+        assert_eq!(format_tick(0.4, 0.2), "0.4");
+        assert_eq!(format_tick(-0.25, 0.05), "-0.25");
+        assert_eq!(format_tick(0.30000000000000004, 0.1), "0.3");
+        assert_eq!(format_tick(150000.0, 50000.0), "1.50E5");
+        assert_eq!(format_tick(2.0e-5, 1.0e-5), "2.00E-5");
+    }
+
+    #[test]
+    fn sexagesimal_steps() {
+        assert_eq!(sexagesimal_step(0.0, 6), 0.01);
+        assert_eq!(sexagesimal_step(3600.0, 6), 600.0);
+        assert_eq!(sexagesimal_step(3601.0, 6), 900.0);
+        assert_eq!(sexagesimal_step(f64::NAN, 6), 144000.0);
+        assert_eq!(sexagesimal_step(1.0e9, 6), 144000.0);
+    }
+
+    #[test]
+    fn right_ascensions() {
+        assert_eq!(format_ra(0.0, 1.0), "00:00:00");
+        assert_eq!(format_ra(45296.0, 1.0), "12:34:56");
+        assert_eq!(format_ra(45296.3, 0.5), "12:34:56.3");
+        assert_eq!(format_ra(45296.25, 0.05), "12:34:56.25");
+        assert_eq!(format_ra(45296.0, 60.0), "12:35");
+        //rounded up to the next minute, not ":60"
+        assert_eq!(format_ra(3599.9999, 1.0), "01:00:00");
+        assert_eq!(format_ra(3599.99, 0.1), "01:00:00.0");
+        //wrapped at 24h
+        assert_eq!(format_ra(86399.6, 1.0), "00:00:00");
+        assert_eq!(format_ra(86400.0, 3600.0), "00:00");
+        assert_eq!(format_ra(-1.0, 1.0), "23:59:59");
+    }
+
+    #[test]
+    fn declinations() {
+        assert_eq!(format_dec(0.0, 1.0), "+00°00'00\"");
+        assert_eq!(format_dec(324000.0, 3600.0), "+90°");
+        assert_eq!(format_dec(-322200.0, 1800.0), "-89°30'");
+        //between 0 and -1 degree
+        assert_eq!(format_dec(-1800.0, 60.0), "-00°30'");
+        assert_eq!(format_dec(-30.0, 1.0), "-00°00'30\"");
+        assert_eq!(format_dec(-0.02, 0.01), "-00°00'00.02\"");
+        //rounded to zero, no sign
+        assert_eq!(format_dec(-0.004, 0.01), "+00°00'00.00\"");
+        assert_eq!(format_dec(-3599.9999, 1.0), "-01°00'00\"");
+    }
+
+    #[test]
+    fn grid_lines() {
+        //across RA = 0, 10 s apart
+        let values = grid_values(359.9, 360.1, 'h');
+        assert_eq!(
+            labels(&values),
+            vec!["23:59:40", "23:59:50", "00:00:00", "00:00:10", "00:00:20"]
+        );
+
+        //across the equator, 30" apart
+        let values = grid_values(-0.02, 0.02, 'd');
+        assert_eq!(
+            labels(&values),
+            vec![
+                "-00°01'00\"",
+                "-00°00'30\"",
+                "+00°00'00\"",
+                "+00°00'30\"",
+                "+00°01'00\""
+            ]
+        );
+
+        //galactic longitudes across 0
+        let values = grid_values(-1.0, 1.0, 'g');
+        assert_eq!(labels(&values), vec!["359.0", "359.5", "0", "0.5", "1.0"]);
+    }
+
+    #[test]
+    fn degenerate_grids() {
+        assert!(grid_values(10.0, 10.0, 'd').len() <= 1);
+        assert!(grid_values(f64::NAN, 1.0, 'h').is_empty());
+        assert!(grid_values(0.0, f64::NAN, 'g').is_empty());
+        assert!(grid_values(0.0, f64::INFINITY, 'd').is_empty());
+        assert!(grid_values(1.0, 0.0, 'h').is_empty());
+    }
+}
@@ -0,0 +1,683 @@
+use rayon::prelude::*;
+use std::cmp::Ordering::Equal;
+use std::sync::Arc;
+
+//asinh (Lupton et al. 1999) softening as a fraction of the [black, white] range
+const ASINH_SOFTENING: f32 = 0.1;
+
+//the log stretch scale, y = ln(a * x + 1) / ln(a + 1)
+const LOG_SCALE: f32 = 1000.0;
+
+//used by "power" without an explicit exponent
+pub const DEFAULT_POWER_EXPONENT: f32 = 1.5;
+
+//IRAF zscale defaults
+const ZSCALE_SAMPLES: usize = 1000;
+const ZSCALE_CONTRAST: f32 = 0.25;
+const ZSCALE_MAX_REJECT: f32 = 0.5;
+const ZSCALE_MIN_PIXELS: usize = 5;
+const ZSCALE_KREJ: f32 = 2.5;
+const ZSCALE_MAX_ITERATIONS: usize = 5;
+
+//histogram equalisation resolution
+const HISTEQ_BINS: usize = 4096;
+const HISTEQ_MAX_SAMPLES: usize = 1 << 20;
+
+/// the stretch functions beyond legacy, linear, logistic, ratio and square;
+/// selected by the same flux string, i.e. "asinh", "power:2.5" or "zscale"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stretch {
+    Asinh,
+    Sqrt,
+    Log,
+    Power(f32),
+    Zscale,
+    Histeq,
+}
+
+impl Stretch {
+    pub fn from_flux(flux: &str) -> Option<Stretch> {
+        let mut parts = flux.splitn(2, ':');
+
+        match parts.next().unwrap_or("") {
+            "asinh" => Some(Stretch::Asinh),
+            "sqrt" => Some(Stretch::Sqrt),
+            "log" => Some(Stretch::Log),
+            "power" => match parts.next() {
+                Some(exponent) => match exponent.parse::<f32>() {
+                    Ok(x) if x.is_finite() && x > 0.0 => Some(Stretch::Power(x)),
+                    _ => None,
+                },
+                None => Some(Stretch::Power(DEFAULT_POWER_EXPONENT)),
+            },
+            "zscale" => Some(Stretch::Zscale),
+            "histeq" => Some(Stretch::Histeq),
+            _ => None,
+        }
+    }
+
+    pub fn to_flux(&self) -> String {
+        match self {
+            Stretch::Asinh => String::from("asinh"),
+            Stretch::Sqrt => String::from("sqrt"),
+            Stretch::Log => String::from("log"),
+            Stretch::Power(exponent) => format!("power:{}", exponent),
+            Stretch::Zscale => String::from("zscale"),
+            Stretch::Histeq => String::from("histeq"),
+        }
+    }
+
+    /// zscale and histeq depend on the pixel values, not just on the black and white points
+    pub fn is_data_dependent(&self) -> bool {
+        match self {
+            Stretch::Zscale | Stretch::Histeq => true,
+            _ => false,
+        }
+    }
+}
+
+/// a stretch ready to be applied pixel by pixel, with the data-dependent
+/// parts (zscale limits, the equalisation CDF) computed up-front
+pub struct StretchFunction {
+    stretch: Stretch,
+    black: f32,
+    white: f32,
+    cdf: Vec<f32>,
+}
+
+impl StretchFunction {
+    /// black and white are the user-chosen limits, ignored by zscale and histeq
+    pub fn new(stretch: Stretch, pixels: &[f32], mask: &[u8], black: f32, white: f32) -> Self {
+        let (black, white) = match stretch {
+            Stretch::Zscale => {
+                zscale(&sample(pixels, mask, ZSCALE_SAMPLES)).unwrap_or((black, white))
+            }
+            Stretch::Histeq => value_range(pixels, mask).unwrap_or((black, white)),
+            _ => (black, white),
+        };
+
+        let cdf = match stretch {
+            Stretch::Histeq => {
+                histogram_cdf(&sample(pixels, mask, HISTEQ_MAX_SAMPLES), black, white)
+            }
+            _ => Vec::new(),
+        };
+
+        StretchFunction {
+            stretch: stretch,
+            black: black,
+            white: white,
+            cdf: cdf,
+        }
+    }
+
+    /// map a pixel value onto [0, 1]
+    pub fn apply(&self, x: f32) -> f32 {
+        let range = self.white - self.black;
+
+        if range.is_nan() || range <= 0.0 {
+            return if x > self.black { 1.0 } else { 0.0 };
+        }
+
+        let t = ((x - self.black) / range).max(0.0).min(1.0);
+
+        match self.stretch {
+            Stretch::Asinh => (t / ASINH_SOFTENING).asinh() / (1.0 / ASINH_SOFTENING).asinh(),
+            Stretch::Sqrt => t.sqrt(),
+            Stretch::Log => (LOG_SCALE * t + 1.0).ln() / (LOG_SCALE + 1.0).ln(),
+            Stretch::Power(exponent) => t.powf(exponent),
+            Stretch::Zscale => t,
+            Stretch::Histeq => {
+                if self.cdf.is_empty() {
+                    return t;
+                }
+
+                //interpolate the cumulative distribution
+                let pos = t * ((self.cdf.len() - 1) as f32);
+                let i = (pos as usize).min(self.cdf.len() - 2);
+                let frac = pos - (i as f32);
+
+                self.cdf[i] + frac * (self.cdf[i + 1] - self.cdf[i])
+            }
+        }
+    }
+}
+
+/// the original flux functions, "legacy" being the default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flux {
+    Legacy,
+    Linear,
+    Logistic,
+    Ratio,
+    Square,
+}
+
+/// the image statistics parametrising the original flux functions
+#[derive(Debug, Clone, Copy)]
+pub struct FluxParams {
+    pub pmin: f32,
+    pub pmax: f32,
+    pub lmin: f32,
+    pub lmax: f32,
+    pub black: f32,
+    pub white: f32,
+    pub median: f32,
+    pub sensitivity: f32,
+    pub ratio_sensitivity: f32,
+}
+
+impl Flux {
+    pub fn from_flux(flux: &str) -> Flux {
+        match flux {
+            "linear" => Flux::Linear,
+            "logistic" => Flux::Logistic,
+            "ratio" => Flux::Ratio,
+            "square" => Flux::Square,
+            _ => Flux::Legacy,
+        }
+    }
+
+    /// map a pixel value onto [0, 1]
+    pub fn apply(&self, x: f32, p: &FluxParams) -> f32 {
+        let pixel = match self {
+            Flux::Linear => (x - p.black) * (1.0 / (p.white - p.black)),
+            Flux::Logistic => 1.0 / (1.0 + (-6.0 * (x - p.median) * p.sensitivity).exp()),
+            Flux::Ratio => {
+                let pixel = 5.0 * (x - p.black) * p.ratio_sensitivity;
+
+                if pixel > 0.0 {
+                    pixel / (1.0 + pixel)
+                } else {
+                    0.0
+                }
+            }
+            Flux::Square => {
+                let pixel = (x - p.black) * p.sensitivity;
+
+                if pixel > 0.0 { pixel * pixel } else { 0.0 }
+            }
+            Flux::Legacy => {
+                let pixel = 0.5 + (x - p.pmin) / (p.pmax - p.pmin);
+
+                if pixel > 0.0 {
+                    (pixel.ln() - p.lmin) / (p.lmax - p.lmin)
+                } else {
+                    0.0
+                }
+            }
+        };
+
+        //NaN stays NaN (black once quantised)
+        num::clamp(pixel, 0.0, 1.0)
+    }
+}
+
+/// the pixel-to-luminance mapping shared by the 8-bit and the 10/12-bit paths
+pub enum Luminance {
+    Flux(Flux, FluxParams),
+    Stretch(Arc<StretchFunction>),
+}
+
+impl Luminance {
+    /// map a pixel value onto [0, 1]
+    pub fn apply(&self, x: f32) -> f32 {
+        match self {
+            Luminance::Flux(flux, params) => flux.apply(x, params),
+            Luminance::Stretch(function) => num::clamp(function.apply(x), 0.0, 1.0),
+        }
+    }
+}
+
+/// an 8-bit luma sample from a [0, 1] luminance
+pub fn quantise_u8(y: f32) -> u8 {
+    (255.0 * y) as u8
+}
+
+/// a 10/12-bit luma sample from a [0, 1] luminance
+pub fn quantise_u16(y: f32, bit_depth: u32) -> u16 {
+    (((1u32 << bit_depth) - 1) as f32 * y) as u16
+}
+
+//evenly spaced valid pixels
+fn sample(pixels: &[f32], mask: &[u8], max_samples: usize) -> Vec<f32> {
+    let valid = pixels
+        .par_iter()
+        .zip(mask.par_iter())
+        .filter(|(x, m)| **m > 0 && x.is_finite())
+        .count();
+
+    if valid == 0 {
+        return Vec::new();
+    }
+
+    let stride = (valid / max_samples.max(1)).max(1);
+
+    pixels
+        .iter()
+        .zip(mask.iter())
+        .filter(|(x, m)| **m > 0 && x.is_finite())
+        .step_by(stride)
+        .map(|(x, _)| *x)
+        .collect()
+}
+
+fn value_range(pixels: &[f32], mask: &[u8]) -> Option<(f32, f32)> {
+    pixels
+        .par_iter()
+        .zip(mask.par_iter())
+        .filter(|(x, m)| **m > 0 && x.is_finite())
+        .map(|(x, _)| (*x, *x))
+        .reduce_with(|a, b| (a.0.min(b.0), a.1.max(b.1)))
+}
+
+//the normalised cumulative histogram over [lo, hi], HISTEQ_BINS + 1 knots starting at 0
+fn histogram_cdf(values: &[f32], lo: f32, hi: f32) -> Vec<f32> {
+    if values.is_empty() || hi.is_nan() || lo.is_nan() || hi <= lo {
+        return Vec::new();
+    }
+
+    let mut hist = vec![0u64; HISTEQ_BINS];
+    let scale = (HISTEQ_BINS as f32) / (hi - lo);
+
+    for x in values {
+        let bin = (((x - lo) * scale) as usize).min(HISTEQ_BINS - 1);
+        hist[bin] += 1;
+    }
+
+    let total = values.len() as f32;
+    let mut cdf = Vec::with_capacity(HISTEQ_BINS + 1);
+    let mut sum = 0u64;
+
+    cdf.push(0.0);
+
+    for count in hist {
+        sum += count;
+        cdf.push((sum as f32) / total);
+    }
+
+    cdf
+}
+
+/// the IRAF zscale display limits (z1, z2) estimated from a sample of pixel values
+pub fn zscale(samples: &[f32]) -> Option<(f32, f32)> {
+    let mut samples: Vec<f32> = samples.iter().filter(|x| x.is_finite()).cloned().collect();
+
+    if samples.is_empty() {
+        return None;
+    }
+
+    samples.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(Equal));
+
+    let npix = samples.len();
+    let zmin = samples[0];
+    let zmax = samples[npix - 1];
+
+    let center = npix / 2;
+    let median = if npix % 2 == 1 {
+        samples[center]
+    } else {
+        0.5 * (samples[center - 1] + samples[center])
+    };
+
+    let min_npixels = ZSCALE_MIN_PIXELS.max(((npix as f32) * ZSCALE_MAX_REJECT) as usize);
+    let ngrow = 1.max(((npix as f32) * 0.01) as usize);
+
+    let mut badpix = vec![false; npix];
+    let mut ngoodpix = npix;
+    let mut last_ngoodpix = npix + 1;
+    let mut slope = 0.0_f32;
+
+    for _ in 0..ZSCALE_MAX_ITERATIONS {
+        if ngoodpix >= last_ngoodpix || ngoodpix < min_npixels {
+            break;
+        }
+
+        //a least-squares line through the good pixels
+        let (mut sx, mut sy, mut sxx, mut sxy, mut n) =
+            (0.0_f64, 0.0_f64, 0.0_f64, 0.0_f64, 0.0_f64);
+
+        for (i, y) in samples.iter().enumerate() {
+            if !badpix[i] {
+                let (x, y) = (i as f64, *y as f64);
+                sx += x;
+                sy += y;
+                sxx += x * x;
+                sxy += x * y;
+                n += 1.0;
+            }
+        }
+
+        let det = n * sxx - sx * sx;
+
+        if det == 0.0 {
+            break;
+        }
+
+        slope = ((n * sxy - sx * sy) / det) as f32;
+        let intercept = ((sy - (slope as f64) * sx) / n) as f32;
+
+        //reject the outliers from the fit, growing the rejected regions
+        let flat: Vec<f32> = samples
+            .iter()
+            .enumerate()
+            .map(|(i, y)| y - (slope * (i as f32) + intercept))
+            .collect();
+
+        let good: Vec<f32> = flat
+            .iter()
+            .zip(badpix.iter())
+            .filter(|(_, bad)| !**bad)
+            .map(|(x, _)| *x)
+            .collect();
+
+        let mean = good.iter().sum::<f32>() / (good.len() as f32);
+        let variance =
+            good.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / (good.len() as f32);
+        let threshold = ZSCALE_KREJ * variance.sqrt();
+
+        last_ngoodpix = ngoodpix;
+
+        let rejected: Vec<usize> = flat
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| **x < -threshold || **x > threshold)
+            .map(|(i, _)| i)
+            .collect();
+
+        for i in rejected {
+            let lo = i.saturating_sub(ngrow / 2);
+            let hi = (i + ngrow - ngrow / 2).min(npix);
+
+            for bad in &mut badpix[lo..hi] {
+                *bad = true;
+            }
+        }
+
+        ngoodpix = badpix.iter().filter(|x| !**x).count();
+    }
+
+    if ngoodpix < min_npixels {
+        return Some((zmin, zmax));
+    }
+
+    slope /= ZSCALE_CONTRAST;
+
+    let z1 = zmin.max(median - ((center as f32) - 1.0) * slope);
+    let z2 = zmax.min(median + ((npix - center) as f32) * slope);
+
+    Some((z1, z2))
+}
+
+/// pick a stretch from the cumulative image histogram where the classic
+/// tone mappings are known to do poorly; None keeps the classifier's choice
+pub fn classify(cdf: &[f64], is_optical: bool) -> Option<Stretch> {
+    let nbins = cdf.len();
+
+    if nbins < 100 {
+        return None;
+    }
+
+    //the fraction of the histogram range below which a given fraction of the pixels lies
+    let quantile = |q: f64| -> f64 {
+        let index = cdf.iter().position(|x| *x >= q).unwrap_or(nbins - 1);
+        (index as f64) / (nbins as f64)
+    };
+
+    let q50 = quantile(0.5);
+    let q99 = quantile(0.99);
+    let q01 = quantile(0.01);
+
+    //a noise-dominated image with a narrow core and a few bright outliers
+    if is_optical && q99 - q01 < 0.05 {
+        return Some(Stretch::Zscale);
+    }
+
+    //a very long bright tail (stars, compact sources)
+    if q50 < 0.01 && q99 > 0.25 {
+        return Some(if is_optical {
+            Stretch::Asinh
+        } else {
+            Stretch::Log
+        });
+    }
+
+    //a long but moderate tail
+    if q50 < 0.05 && q99 > 0.5 {
+        return Some(Stretch::Sqrt);
+    }
+
+    //pixels spread over separate intensity populations: the central half of the
+    //pixels covering most of the range
+    if quantile(0.75) - quantile(0.25) > 0.5 {
+        return Some(Stretch::Histeq);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Stretch; 6] = [
+        Stretch::Asinh,
+        Stretch::Sqrt,
+        Stretch::Log,
+        Stretch::Power(2.5),
+        Stretch::Zscale,
+        Stretch::Histeq,
+    ];
+
+    #[test]
+    fn flux_names() {
+        for stretch in ALL {
+            assert_eq!(Stretch::from_flux(&stretch.to_flux()), Some(stretch));
+        }
+
+        assert_eq!(
+            Stretch::from_flux("power"),
+            Some(Stretch::Power(DEFAULT_POWER_EXPONENT))
+        );
+        assert_eq!(Stretch::from_flux("power:0"), None);
+        assert_eq!(Stretch::from_flux("power:-1"), None);
+        assert_eq!(Stretch::from_flux("power:x"), None);
+        assert_eq!(Stretch::from_flux("legacy"), None);
+        assert_eq!(Stretch::from_flux(""), None);
+    }
+
+    #[test]
+    fn monotonic_onto_the_unit_interval() {
+        let pixels: Vec<f32> = (0..1000).map(|x| x as f32).collect();
+        let mask = vec![1u8; pixels.len()];
+
+        for stretch in ALL {
+            let function = StretchFunction::new(stretch, &pixels, &mask, 0.0, 999.0);
+
+            assert!(function.apply(-100.0).abs() < 1e-6, "{:?}", stretch);
+            assert!((function.apply(1e6) - 1.0).abs() < 1e-6, "{:?}", stretch);
+
+            let mut last = 0.0;
+            for x in &pixels {
+                let y = function.apply(*x);
+                assert!((0.0..=1.0).contains(&y), "{:?}", stretch);
+                assert!(y >= last, "{:?}", stretch);
+                last = y;
+            }
+        }
+    }
+
+    fn flux_params() -> FluxParams {
+        FluxParams {
+            pmin: -1.0,
+            pmax: 10.0,
+            lmin: 0.5f32.ln(),
+            lmax: 1.5f32.ln(),
+            black: 0.0,
+            white: 8.0,
+            median: 1.0,
+            sensitivity: 0.2,
+            ratio_sensitivity: 0.5,
+        }
+    }
+
+    #[test]
+    fn original_flux_functions() {
+        let params = flux_params();
+
+        assert_eq!(Flux::from_flux("linear"), Flux::Linear);
+        assert_eq!(Flux::from_flux("square"), Flux::Square);
+        assert_eq!(Flux::from_flux("asinh"), Flux::Legacy);
+
+        assert_eq!(Flux::Linear.apply(4.0, &params), 0.5);
+        assert_eq!(Flux::Linear.apply(-4.0, &params), 0.0);
+        assert_eq!(Flux::Linear.apply(40.0, &params), 1.0);
+        assert_eq!(Flux::Logistic.apply(1.0, &params), 0.5);
+        assert_eq!(Flux::Ratio.apply(0.4, &params), 0.5);
+        assert_eq!(Flux::Square.apply(2.5, &params), 0.25);
+        assert_eq!(Flux::Legacy.apply(-1.0, &params), 0.0);
+        assert!((Flux::Legacy.apply(10.0, &params) - 1.0).abs() < 1e-6);
+        assert!(Flux::Linear.apply(f32::NAN, &params).is_nan());
+    }
+
+    #[test]
+    fn luminance_paths_agree() {
+        let params = flux_params();
+        let pixels: Vec<f32> = (0..5000).map(|x| -2.0 + (x as f32) * 0.0025).collect();
+        let mask = vec![1u8; pixels.len()];
+
+        let mut mappings: Vec<Luminance> = [
+            Flux::Legacy,
+            Flux::Linear,
+            Flux::Logistic,
+            Flux::Ratio,
+            Flux::Square,
+        ]
+        .iter()
+        .map(|flux| Luminance::Flux(*flux, params))
+        .collect();
+
+        for stretch in ALL {
+            mappings.push(Luminance::Stretch(Arc::new(StretchFunction::new(
+                stretch, &pixels, &mask, 0.0, 8.0,
+            ))));
+        }
+
+        for luminance in &mappings {
+            for bit_depth in [10, 12] {
+                for x in pixels.iter().chain([f32::NAN, f32::INFINITY].iter()) {
+                    let y = luminance.apply(*x);
+                    let low = quantise_u8(y) as u32;
+                    let high = quantise_u16(y, bit_depth) as u32;
+
+                    //requantised to 8 bits the high-bit-depth sample is never darker and at most one level brighter
+                    let requantised = high >> (bit_depth - 8);
+                    assert!(
+                        requantised == low || requantised == low + 1,
+                        "x = {}, {} vs {}",
+                        x,
+                        low,
+                        high
+                    );
+                }
+            }
+        }
+
+        assert_eq!((quantise_u8(0.0), quantise_u16(0.0, 10)), (0, 0));
+        assert_eq!((quantise_u8(1.0), quantise_u16(1.0, 10)), (255, 1023));
+        assert_eq!(quantise_u16(1.0, 12), 4095);
+        assert_eq!((quantise_u8(f32::NAN), quantise_u16(f32::NAN, 12)), (0, 0));
+    }
+
+    #[test]
+    fn faint_ramps_are_not_posterised() {
+        //a ramp spanning two 8-bit levels
+        let luminance = Luminance::Flux(Flux::Linear, flux_params());
+        let ramp: Vec<f32> = (0..100).map(|i| 1.01 + (i as f32) * 0.0004).collect();
+
+        let levels = |quantise: &dyn Fn(f32) -> u32| -> usize {
+            let mut levels: Vec<u32> = ramp.iter().map(|x| quantise(luminance.apply(*x))).collect();
+            levels.dedup();
+            levels.len()
+        };
+
+        assert!(levels(&|y| quantise_u8(y) as u32) <= 2);
+        assert!(levels(&|y| quantise_u16(y, 10) as u32) >= 5);
+        assert!(levels(&|y| quantise_u16(y, 12) as u32) >= 20);
+    }
+
+    #[test]
+    fn an_empty_range_is_a_step() {
+        let function = StretchFunction::new(Stretch::Sqrt, &[], &[], 1.0, 1.0);
+
+        assert_eq!(function.apply(0.5), 0.0);
+        assert_eq!(function.apply(1.5), 1.0);
+    }
+
+    #[test]
+    fn masked_pixels_are_ignored() {
+        let pixels = [0.0, 1.0, 2.0, 1000.0, f32::NAN];
+        let mask = [1, 1, 1, 0, 1];
+
+        assert_eq!(value_range(&pixels, &mask), Some((0.0, 2.0)));
+        assert_eq!(sample(&pixels, &mask, 10), vec![0.0, 1.0, 2.0]);
+        assert_eq!(sample(&pixels, &[0; 5], 10), Vec::<f32>::new());
+    }
+
+    #[test]
+    fn histeq_of_uniform_values_is_linear() {
+        let pixels: Vec<f32> = (0..10000).map(|x| x as f32).collect();
+        let mask = vec![1u8; pixels.len()];
+        let function = StretchFunction::new(Stretch::Histeq, &pixels, &mask, 0.0, 1.0);
+
+        for x in [0.0, 2500.0, 5000.0, 9999.0] {
+            assert!((function.apply(x) - x / 9999.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn zscale_limits() {
+        assert_eq!(zscale(&[]), None);
+        assert_eq!(zscale(&[f32::NAN]), None);
+
+        //a ramp is fitted exactly, the limits are clipped to the data range
+        let ramp: Vec<f32> = (0..1000).map(|x| x as f32).collect();
+        assert_eq!(zscale(&ramp), Some((0.0, 999.0)));
+
+        //a flat background with a few bright outliers
+        let mut values: Vec<f32> = (0..1000).map(|x| 100.0 + (x % 10) as f32).collect();
+        values.extend_from_slice(&[1e6; 5]);
+
+        let (z1, z2) = zscale(&values).unwrap();
+        assert!((90.0..=110.0).contains(&z1), "z1 = {}", z1);
+        assert!((100.0..=1000.0).contains(&z2), "z2 = {}", z2);
+    }
+
+    #[test]
+    fn classify_needs_a_histogram() {
+        assert_eq!(classify(&[0.5; 10], false), None);
+
+        //most of the pixels in the first bin, a tail up to the middle of the range
+        let cdf: Vec<f64> = (0..1000).map(|i| if i < 500 { 0.9 } else { 1.0 }).collect();
+        assert_eq!(classify(&cdf, false), Some(Stretch::Log));
+        assert_eq!(classify(&cdf, true), Some(Stretch::Asinh));
+
+        //an optical image with all the pixels within a narrow core
+        let cdf: Vec<f64> = (0..1000).map(|i| if i < 20 { 0.5 } else { 1.0 }).collect();
+        assert_eq!(classify(&cdf, true), Some(Stretch::Zscale));
+
+        //three separate populations at the bottom, the middle and the top of the range
+        let cdf: Vec<f64> = (0..1000)
+            .map(|i| match i {
+                0..450 => 0.3,
+                450..950 => 0.7,
+                _ => 1.0,
+            })
+            .collect();
+        assert_eq!(classify(&cdf, false), Some(Stretch::Histeq));
+
+        //a uniform distribution keeps the classifier's choice
+        let cdf: Vec<f64> = (1..=1000).map(|x| x as f64 / 1000.0).collect();
+        assert_eq!(classify(&cdf, false), None);
+    }
+}
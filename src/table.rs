@@ -0,0 +1,479 @@
+//spectrum tables for TOPCAT, Astropy and SPLAT: VOTable 1.4 (TABLEDATA) and FITS BINTABLE
+
+const FITS_CHUNK_LENGTH: usize = 2880;
+const FITS_LINE_LENGTH: usize = 80;
+
+/// the spectrum export file format
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpectrumFormat {
+    Csv,
+    VoTable,
+    Fits, //a BINTABLE extension following an empty primary HDU
+}
+
+impl SpectrumFormat {
+    pub fn from_name(name: &str) -> Option<SpectrumFormat> {
+        match name.to_lowercase().as_str() {
+            "csv" => Some(SpectrumFormat::Csv),
+            "votable" | "vot" | "xml" => Some(SpectrumFormat::VoTable),
+            "fits" | "bintable" => Some(SpectrumFormat::Fits),
+            _ => None,
+        }
+    }
+
+    /// the WebSocket message type of an exported spectrum
+    pub fn msg_type(&self) -> u32 {
+        match self {
+            SpectrumFormat::Csv => 6,
+            SpectrumFormat::VoTable => 7,
+            SpectrumFormat::Fits => 9,
+        }
+    }
+}
+
+/// a single-valued table parameter
+#[derive(Debug, Clone)]
+pub enum ParamValue {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name: String,
+    pub keyword: &'static str, //the FITS header keyword (up to 8 characters)
+    pub unit: &'static str,
+    pub ucd: &'static str,
+    pub description: String,
+    pub value: ParamValue,
+}
+
+impl Param {
+    pub fn number(
+        name: &str,
+        keyword: &'static str,
+        unit: &'static str,
+        ucd: &'static str,
+        description: &str,
+        value: f64,
+    ) -> Param {
+        Param {
+            name: name.to_string(),
+            keyword: keyword,
+            unit: unit,
+            ucd: ucd,
+            description: description.to_string(),
+            value: ParamValue::Number(value),
+        }
+    }
+
+    pub fn text(
+        name: &str,
+        keyword: &'static str,
+        ucd: &'static str,
+        description: &str,
+        value: &str,
+    ) -> Param {
+        Param {
+            name: name.to_string(),
+            keyword: keyword,
+            unit: "",
+            ucd: ucd,
+            description: description.to_string(),
+            value: ParamValue::Text(value.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ColumnData {
+    Int(Vec<i32>),
+    Double(Vec<f64>),
+}
+
+impl ColumnData {
+    fn len(&self) -> usize {
+        match self {
+            ColumnData::Int(x) => x.len(),
+            ColumnData::Double(x) => x.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    pub unit: String,
+    pub ucd: &'static str,
+    pub description: String,
+    pub data: ColumnData,
+}
+
+/// a spectrum with its region, beam and spectral frame metadata
+#[derive(Debug, Clone)]
+pub struct SpectrumTable {
+    pub name: String,
+    pub description: String,
+    pub params: Vec<Param>,
+    pub columns: Vec<Column>,
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+//VOUnits/FITS units use '.' for a product
+fn standard_unit(unit: &str) -> String {
+    unit.replace('•', ".")
+}
+
+//the VOTable representation of special values
+fn votable_double(x: f64) -> String {
+    if x.is_nan() {
+        String::from("NaN")
+    } else if x.is_infinite() {
+        String::from(if x > 0.0 { "+Inf" } else { "-Inf" })
+    } else {
+        x.to_string()
+    }
+}
+
+fn optional_attribute(name: &str, value: &str) -> String {
+    if value.is_empty() {
+        String::new()
+    } else {
+        format!(" {}=\"{}\"", name, xml_escape(value))
+    }
+}
+
+//a fixed-format header card, truncated to FITS_LINE_LENGTH;
+//strings begin in column 11, other values end in column 30
+fn header_card(keyword: &str, value: &str, comment: &str) -> [u8; FITS_LINE_LENGTH] {
+    let value = if value.starts_with('\'') {
+        format!("{:<20}", value)
+    } else {
+        format!("{:>20}", value)
+    };
+
+    let card = if comment.is_empty() {
+        format!("{:<8}= {}", keyword, value)
+    } else {
+        format!("{:<8}= {} / {}", keyword, value, comment)
+    };
+
+    let mut line = [b' '; FITS_LINE_LENGTH];
+
+    for (dst, src) in line.iter_mut().zip(card.bytes().filter(|c| c.is_ascii())) {
+        *dst = src;
+    }
+
+    line
+}
+
+fn header_string(value: &str) -> String {
+    let value: String = value.chars().filter(|c| c.is_ascii()).collect();
+    format!("'{:<8}'", value.replace('\'', "''"))
+}
+
+fn pad(buf: &mut Vec<u8>, fill: u8) {
+    let remainder = buf.len() % FITS_CHUNK_LENGTH;
+
+    if remainder > 0 {
+        buf.extend(std::iter::repeat_n(fill, FITS_CHUNK_LENGTH - remainder));
+    }
+}
+
+impl SpectrumTable {
+    fn rows(&self) -> usize {
+        self.columns.iter().map(|c| c.data.len()).min().unwrap_or(0)
+    }
+
+    pub fn to_votable(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<VOTABLE version=\"1.4\" xmlns=\"http://www.ivoa.net/xml/VOTable/v1.3\">\n");
+        xml.push_str("<RESOURCE type=\"results\">\n");
+        xml.push_str(&format!("<TABLE name=\"{}\">\n", xml_escape(&self.name)));
+        xml.push_str(&format!(
+            "<DESCRIPTION>{}</DESCRIPTION>\n",
+            xml_escape(&self.description)
+        ));
+
+        for param in &self.params {
+            let (datatype, value) = match &param.value {
+                ParamValue::Number(x) => ("datatype=\"double\"", votable_double(*x)),
+                ParamValue::Text(s) => ("datatype=\"char\" arraysize=\"*\"", s.clone()),
+            };
+
+            xml.push_str(&format!(
+                "<PARAM name=\"{}\" {}{}{} value=\"{}\"><DESCRIPTION>{}</DESCRIPTION></PARAM>\n",
+                xml_escape(&param.name),
+                datatype,
+                optional_attribute("unit", &standard_unit(param.unit)),
+                optional_attribute("ucd", param.ucd),
+                xml_escape(&value),
+                xml_escape(&param.description)
+            ));
+        }
+
+        for column in &self.columns {
+            let datatype = match column.data {
+                ColumnData::Int(_) => "int",
+                ColumnData::Double(_) => "double",
+            };
+
+            xml.push_str(&format!(
+                "<FIELD name=\"{}\" datatype=\"{}\"{}{}><DESCRIPTION>{}</DESCRIPTION></FIELD>\n",
+                xml_escape(&column.name),
+                datatype,
+                optional_attribute("unit", &standard_unit(&column.unit)),
+                optional_attribute("ucd", column.ucd),
+                xml_escape(&column.description)
+            ));
+        }
+
+        xml.push_str("<DATA>\n<TABLEDATA>\n");
+
+        for row in 0..self.rows() {
+            xml.push_str("<TR>");
+
+            for column in &self.columns {
+                match &column.data {
+                    ColumnData::Int(x) => xml.push_str(&format!("<TD>{}</TD>", x[row])),
+                    ColumnData::Double(x) => {
+                        xml.push_str(&format!("<TD>{}</TD>", votable_double(x[row])))
+                    }
+                }
+            }
+
+            xml.push_str("</TR>\n");
+        }
+
+        xml.push_str("</TABLEDATA>\n</DATA>\n</TABLE>\n</RESOURCE>\n</VOTABLE>\n");
+
+        xml
+    }
+
+    pub fn to_fits(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = Vec::new();
+
+        //an empty primary HDU
+        buf.extend_from_slice(&header_card("SIMPLE", "T", "conforms to FITS standard"));
+        buf.extend_from_slice(&header_card("BITPIX", "8", ""));
+        buf.extend_from_slice(&header_card("NAXIS", "0", ""));
+        buf.extend_from_slice(&header_card("EXTEND", "T", ""));
+        buf.extend_from_slice(&format!("{:<80}", "END").into_bytes());
+        pad(&mut buf, b' ');
+
+        let rows = self.rows();
+        let row_size: usize = self
+            .columns
+            .iter()
+            .map(|column| match column.data {
+                ColumnData::Int(_) => 4,
+                ColumnData::Double(_) => 8,
+            })
+            .sum();
+
+        buf.extend_from_slice(&header_card("XTENSION", &header_string("BINTABLE"), ""));
+        buf.extend_from_slice(&header_card("BITPIX", "8", ""));
+        buf.extend_from_slice(&header_card("NAXIS", "2", ""));
+        buf.extend_from_slice(&header_card(
+            "NAXIS1",
+            &row_size.to_string(),
+            "bytes per row",
+        ));
+        buf.extend_from_slice(&header_card("NAXIS2", &rows.to_string(), "rows"));
+        buf.extend_from_slice(&header_card("PCOUNT", "0", ""));
+        buf.extend_from_slice(&header_card("GCOUNT", "1", ""));
+        buf.extend_from_slice(&header_card("TFIELDS", &self.columns.len().to_string(), ""));
+
+        for (i, column) in self.columns.iter().enumerate() {
+            let n = i + 1;
+            let form = match column.data {
+                ColumnData::Int(_) => "1J",
+                ColumnData::Double(_) => "1D",
+            };
+
+            buf.extend_from_slice(&header_card(
+                &format!("TTYPE{}", n),
+                &header_string(&column.name),
+                &column.description,
+            ));
+            buf.extend_from_slice(&header_card(
+                &format!("TFORM{}", n),
+                &header_string(form),
+                "",
+            ));
+
+            if !column.unit.is_empty() {
+                buf.extend_from_slice(&header_card(
+                    &format!("TUNIT{}", n),
+                    &header_string(&standard_unit(&column.unit)),
+                    "",
+                ));
+            }
+        }
+
+        buf.extend_from_slice(&header_card("EXTNAME", &header_string("SPECTRUM"), ""));
+
+        for param in &self.params {
+            let value = match &param.value {
+                ParamValue::Number(x) if x.is_finite() => format!("{:E}", x),
+                ParamValue::Number(_) => continue,
+                ParamValue::Text(s) => header_string(s),
+            };
+
+            let comment = if param.unit.is_empty() {
+                param.description.clone()
+            } else {
+                format!("[{}] {}", standard_unit(param.unit), param.description)
+            };
+
+            buf.extend_from_slice(&header_card(param.keyword, &value, &comment));
+        }
+
+        buf.extend_from_slice(&format!("{:<80}", "END").into_bytes());
+        pad(&mut buf, b' ');
+
+        //big-endian rows
+        for row in 0..rows {
+            for column in &self.columns {
+                match &column.data {
+                    ColumnData::Int(x) => buf.extend_from_slice(&x[row].to_be_bytes()),
+                    ColumnData::Double(x) => buf.extend_from_slice(&x[row].to_be_bytes()),
+                }
+            }
+        }
+
+        pad(&mut buf, 0);
+
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_table() -> SpectrumTable {
+        SpectrumTable {
+            name: String::from("M31 <core>"),
+            description: String::from("a \"test\" spectrum"),
+            params: vec![
+                Param::number("beam", "BMAJ", "deg", "instr.beam", "major axis", 1.5e-3),
+                Param::number("rms", "RMS", "Jy•beam-1", "", "noise", f64::NAN),
+                Param::text("frame", "SPECSYS", "", "spectral frame", "LSRK"),
+            ],
+            columns: vec![
+                Column {
+                    name: String::from("channel"),
+                    unit: String::new(),
+                    ucd: "meta.number",
+                    description: String::from("channel number"),
+                    data: ColumnData::Int(vec![1, 2, 3]),
+                },
+                Column {
+                    name: String::from("flux"),
+                    unit: String::from("Jy•beam-1"),
+                    ucd: "phot.flux.density",
+                    description: String::from("mean flux"),
+                    data: ColumnData::Double(vec![0.5, f64::NAN, -f64::INFINITY]),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn format_names() {
+        assert_eq!(
+            SpectrumFormat::from_name("VOT"),
+            Some(SpectrumFormat::VoTable)
+        );
+        assert_eq!(
+            SpectrumFormat::from_name("bintable"),
+            Some(SpectrumFormat::Fits)
+        );
+        assert_eq!(SpectrumFormat::from_name("json"), None);
+    }
+
+    #[test]
+    fn header_cards() {
+        let card = header_card("NAXIS", "2", "");
+        assert_eq!(&card[..30], b"NAXIS   =                    2");
+        assert!(card[30..].iter().all(|c| *c == b' '));
+
+        let card = header_card("TTYPE1", &header_string("O'Neil"), "name");
+        let text = String::from_utf8(card.to_vec()).unwrap();
+        let expected = format!("TTYPE1  = {:<20} / name", "'O''Neil '");
+        assert_eq!(text.trim_end(), expected);
+
+        //non-ASCII characters are dropped, long cards are truncated
+        assert_eq!(header_string("µJy"), "'Jy      '");
+        let card = header_card("COMMENT", "1", &"x".repeat(200));
+        assert_eq!(card.len(), FITS_LINE_LENGTH);
+    }
+
+    #[test]
+    fn votable_document() {
+        let xml = test_table().to_votable();
+
+        assert!(xml.contains("<TABLE name=\"M31 &lt;core&gt;\">"));
+        assert!(xml.contains("<DESCRIPTION>a &quot;test&quot; spectrum</DESCRIPTION>"));
+        assert!(xml.contains(
+            "<PARAM name=\"beam\" datatype=\"double\" unit=\"deg\" ucd=\"instr.beam\" value=\"0.0015\">"
+        ));
+        assert!(
+            xml.contains(
+                "<PARAM name=\"rms\" datatype=\"double\" unit=\"Jy.beam-1\" value=\"NaN\">"
+            )
+        );
+        assert!(xml.contains("datatype=\"char\" arraysize=\"*\""));
+        assert!(xml.contains("<FIELD name=\"channel\" datatype=\"int\" ucd=\"meta.number\">"));
+        assert!(xml.contains("<TR><TD>1</TD><TD>0.5</TD></TR>"));
+        assert!(xml.contains("<TR><TD>2</TD><TD>NaN</TD></TR>"));
+        assert!(xml.contains("<TR><TD>3</TD><TD>-Inf</TD></TR>"));
+        assert!(xml.ends_with("</VOTABLE>\n"));
+    }
+
+    #[test]
+    fn fits_bintable() {
+        let fits = test_table().to_fits();
+
+        assert_eq!(fits.len(), 3 * FITS_CHUNK_LENGTH);
+
+        let cards: Vec<String> = fits[FITS_CHUNK_LENGTH..2 * FITS_CHUNK_LENGTH]
+            .chunks(FITS_LINE_LENGTH)
+            .map(|card| String::from_utf8(card.to_vec()).unwrap())
+            .collect();
+
+        let value = |keyword: &str| -> Option<String> {
+            cards
+                .iter()
+                .find(|card| card[..8].trim() == keyword)
+                .map(|card| card[10..].split(" / ").next().unwrap().trim().to_string())
+        };
+
+        assert_eq!(value("XTENSION").as_deref(), Some("'BINTABLE'"));
+        assert_eq!(value("NAXIS1").as_deref(), Some("12"));
+        assert_eq!(value("NAXIS2").as_deref(), Some("3"));
+        assert_eq!(value("TFORM1").as_deref(), Some("'1J      '"));
+        assert_eq!(value("TFORM2").as_deref(), Some("'1D      '"));
+        assert_eq!(value("TUNIT1"), None);
+        assert_eq!(value("TUNIT2").as_deref(), Some("'Jy.beam-1'"));
+        assert_eq!(value("BMAJ").as_deref(), Some("1.5E-3"));
+        assert_eq!(value("SPECSYS").as_deref(), Some("'LSRK    '"));
+
+        //a non-finite parameter has no FITS representation
+        assert_eq!(value("RMS"), None);
+
+        //the big-endian rows
+        let data = &fits[2 * FITS_CHUNK_LENGTH..];
+        assert_eq!(&data[0..4], &1i32.to_be_bytes());
+        assert_eq!(&data[4..12], &0.5f64.to_be_bytes());
+        assert_eq!(&data[24..36], &[0, 0, 0, 3, 0xff, 0xf0, 0, 0, 0, 0, 0, 0]);
+        assert!(data[36..].iter().all(|x| *x == 0));
+    }
+}
@@ -0,0 +1,128 @@
+//small PNG previews of the local FITS files for the file browser, cached in IMAGECACHE
+
+use parking_lot::Mutex;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use uuid::Uuid;
+
+use crate::fits::{FITS, IMAGECACHE};
+use crate::pyramid;
+
+/// the maximum width/height of a generated thumbnail
+pub const THUMBNAIL_SIZE: usize = 128;
+
+lazy_static! {
+    //one thumbnail at a time so that listing a large directory does not saturate the disks
+    static ref GENERATOR: Mutex<()> = Mutex::new(());
+}
+
+/// IMAGECACHE/thumbnails/<uuid of the full path>.png, files of the same name in
+/// different directories do not collide
+pub fn thumbnail_path(filepath: &Path) -> PathBuf {
+    let id = Uuid::new_v3(&Uuid::NAMESPACE_URL, filepath.to_string_lossy().as_bytes());
+
+    Path::new(IMAGECACHE)
+        .join("thumbnails")
+        .join(format!("{}.png", id))
+}
+
+//is <path> at least as recent as the FITS file?
+fn is_fresh(path: &Path, modified: SystemTime) -> bool {
+    match std::fs::metadata(path).and_then(|x| x.modified()) {
+        Ok(x) => x >= modified,
+        Err(_) => false,
+    }
+}
+
+/// a ready-made preview: a cached thumbnail or the top level of the dataset's tile pyramid
+pub fn cached(filepath: &Path, dataset_id: &str) -> Option<PathBuf> {
+    let modified = std::fs::metadata(filepath)
+        .and_then(|x| x.modified())
+        .ok()?;
+
+    let thumbnail = thumbnail_path(filepath);
+
+    if is_fresh(&thumbnail, modified) {
+        return Some(thumbnail);
+    }
+
+    if pyramid::status(dataset_id) == pyramid::PyramidStatus::Ready {
+        let tile = pyramid::tile_path(dataset_id, 0, 0, 0);
+
+        if is_fresh(&tile, modified) {
+            return Some(tile);
+        }
+    }
+
+    None
+}
+
+/// the preview of a local FITS file, generated on demand from its middle channel
+pub fn get(filepath: &Path, dataset_id: &str) -> Result<PathBuf, String> {
+    if let Some(path) = cached(filepath, dataset_id) {
+        return Ok(path);
+    }
+
+    let _lock = GENERATOR.lock();
+
+    //a concurrent request might have generated it in the meantime
+    if let Some(path) = cached(filepath, dataset_id) {
+        return Ok(path);
+    }
+
+    let watch = Instant::now();
+
+    let (data, width, height) = match FITS::thumbnail_from_path(filepath, THUMBNAIL_SIZE) {
+        Some(x) => x,
+        None => return Err(format!("{}: no image data", filepath.display())),
+    };
+
+    let path = thumbnail_path(filepath);
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|err| format!("{}: {}", dir.display(), err))?;
+    }
+
+    //write into a temporary file first so that an interrupted write is never served
+    let tmp = path.with_extension("png.tmp");
+    pyramid::write_tile(&tmp, width, height, &data)?;
+    std::fs::rename(&tmp, &path).map_err(|err| format!("{}: {}", path.display(), err))?;
+
+    println!(
+        "[thumbnail] {}: {}x{} in {:?}",
+        filepath.display(),
+        width,
+        height,
+        watch.elapsed()
+    );
+
+    Ok(path)
+}
+
+/// removes the thumbnails that have not been (re)generated within <timeout>,
+/// together with any left-over temporary files
+pub fn cleanup(timeout: Duration) {
+    let dir = Path::new(IMAGECACHE).join("thumbnails");
+
+    let entries = match dir.read_dir() {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+
+    let now = SystemTime::now();
+
+    for entry in entries.flatten() {
+        let expired = match entry.metadata().and_then(|x| x.modified()) {
+            Ok(modified) => match now.duration_since(modified) {
+                Ok(elapsed) => elapsed > timeout,
+                Err(_) => false,
+            },
+            Err(_) => false,
+        };
+
+        if expired {
+            println!("[thumbnail cleanup]: removing {:?}", entry.path());
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
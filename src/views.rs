@@ -0,0 +1,199 @@
+use rusqlite::{Connection, OptionalExtension, params};
+use std::time::SystemTime;
+use uuid::Uuid;
+
+//saved views (permalinks) sqlite store, created in the working directory on first use
+pub static VIEWS_DB: &'static str = "views.db";
+
+const VIEW_ID_LENGTH: usize = 8;
+const MAX_ID_ATTEMPTS: usize = 16;
+
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+#[derive(Debug)]
+pub struct SavedView {
+    pub id: String,
+    pub name: String,
+    pub created: i64,
+    pub state: serde_json::Value,
+}
+
+impl SavedView {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "id" : self.id,
+            "name" : self.name,
+            "created" : self.created,
+            "state" : self.state,
+        })
+    }
+}
+
+fn open_db() -> rusqlite::Result<Connection> {
+    let conn = Connection::open(std::path::Path::new(VIEWS_DB))?;
+    create_table(&conn)?;
+
+    Ok(conn)
+}
+
+fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS views (id TEXT PRIMARY KEY, name TEXT NOT NULL, created INTEGER NOT NULL, state TEXT NOT NULL);",
+        [],
+    )?;
+
+    Ok(())
+}
+
+//a short base62 identifier derived from a random v4 uuid
+fn make_view_id() -> String {
+    Uuid::new_v4()
+        .as_bytes()
+        .iter()
+        .take(VIEW_ID_LENGTH)
+        .map(|b| BASE62[(*b as usize) % BASE62.len()] as char)
+        .collect()
+}
+
+//only letters and digits can appear in a view id
+pub fn is_valid_id(id: &str) -> bool {
+    id.len() == VIEW_ID_LENGTH && id.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+pub fn save_view(name: &str, state: &serde_json::Value) -> rusqlite::Result<String> {
+    insert_view(&open_db()?, name, state)
+}
+
+fn insert_view(
+    conn: &Connection,
+    name: &str,
+    state: &serde_json::Value,
+) -> rusqlite::Result<String> {
+    let created = match SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(x) => x.as_secs() as i64,
+        Err(_) => 0,
+    };
+
+    let state = state.to_string();
+
+    //retry on the (unlikely) id collision
+    let mut attempts = 0;
+
+    loop {
+        let id = make_view_id();
+
+        match conn.execute(
+            "INSERT OR IGNORE INTO views (id, name, created, state) VALUES (?1, ?2, ?3, ?4);",
+            params![id, name, created, state],
+        ) {
+            Ok(1) => return Ok(id),
+            Ok(_) => {
+                attempts = attempts + 1;
+
+                if attempts >= MAX_ID_ATTEMPTS {
+                    return Err(rusqlite::Error::SqliteFailure(
+                        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CONSTRAINT),
+                        Some(String::from("unable to allocate a unique view id")),
+                    ));
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+pub fn load_view(id: &str) -> rusqlite::Result<Option<SavedView>> {
+    select_view(&open_db()?, id)
+}
+
+fn select_view(conn: &Connection, id: &str) -> rusqlite::Result<Option<SavedView>> {
+    let row = conn
+        .query_row(
+            "SELECT id, name, created, state FROM views WHERE id = ?1;",
+            params![id],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            },
+        )
+        .optional()?;
+
+    Ok(match row {
+        Some((id, name, created, state)) => Some(SavedView {
+            id: id,
+            name: name,
+            created: created,
+            state: match serde_json::from_str(&state) {
+                Ok(x) => x,
+                Err(_) => serde_json::Value::Null,
+            },
+        }),
+        None => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        create_table(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn view_ids() {
+        for _ in 0..100 {
+            let id = make_view_id();
+            assert_eq!(id.len(), VIEW_ID_LENGTH);
+            assert!(id.bytes().all(|b| BASE62.contains(&b)));
+            assert!(is_valid_id(&id));
+        }
+    }
+
+    #[test]
+    fn malformed_ids() {
+        for id in [
+            "",
+            "abc1234",
+            "abc123456",
+            "abc-1234",
+            "../views",
+            "abc 1234",
+            "abcdé12",
+            "1'OR'1'1",
+        ] {
+            assert!(!is_valid_id(id), "{}", id);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let conn = memory_db();
+        let state = json!({
+            "datasetId" : ["ALMA01234567"],
+            "colourmap" : "viridis",
+            "zoom" : 2.5,
+        });
+
+        let id = insert_view(&conn, "outflow", &state).unwrap();
+        assert!(is_valid_id(&id));
+
+        let view = select_view(&conn, &id).unwrap().unwrap();
+        assert_eq!(view.id, id);
+        assert_eq!(view.name, "outflow");
+        assert_eq!(view.state, state);
+        assert!(view.created > 0);
+
+        //another save gets another id
+        let other = insert_view(&conn, "outflow", &state).unwrap();
+        assert_ne!(other, id);
+
+        assert!(select_view(&conn, "00000000").unwrap().is_none());
+    }
+}
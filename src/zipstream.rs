@@ -0,0 +1,410 @@
+use flate2::Crc;
+
+//the largest size/offset of a plain ZIP field, larger ones go into the ZIP64 extra field
+const ZIP64_LIMIT: u64 = 0xFFFF_FFFF;
+
+//1980-01-01 00:00 in the MS-DOS format
+const DOS_TIME: u16 = 0;
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+//the data descriptor flag: the CRC-32 follows the entry data
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+
+struct Entry {
+    name: String,
+    size: u64,
+    offset: u64,
+    crc: u32,
+}
+
+impl Entry {
+    fn zip64(&self) -> bool {
+        self.size >= ZIP64_LIMIT
+    }
+}
+
+/// a stored (uncompressed) ZIP archive written on the fly, as read by numpy.load and zarr's
+/// ZipStore; the entry sizes are known up-front, the CRC-32 of each entry follows its data
+pub struct ZipStream {
+    entries: Vec<Entry>,
+    offset: u64,
+    crc: Crc,
+}
+
+fn local_header_length(name: &str, size: u64) -> u64 {
+    30 + name.len() as u64 + if size >= ZIP64_LIMIT { 20 } else { 0 }
+}
+
+fn descriptor_length(size: u64) -> u64 {
+    if size >= ZIP64_LIMIT { 24 } else { 16 }
+}
+
+fn central_header_length(name: &str, size: u64, offset: u64) -> u64 {
+    let mut fields = 0;
+
+    if size >= ZIP64_LIMIT {
+        fields += 2;
+    }
+
+    if offset >= ZIP64_LIMIT {
+        fields += 1;
+    }
+
+    let extra = if fields > 0 { 4 + 8 * fields } else { 0 };
+
+    46 + name.len() as u64 + extra
+}
+
+fn end_length(entries: usize, cd_offset: u64, cd_size: u64) -> u64 {
+    if needs_zip64_end(entries, cd_offset, cd_size) {
+        56 + 20 + 22
+    } else {
+        22
+    }
+}
+
+fn needs_zip64_end(entries: usize, cd_offset: u64, cd_size: u64) -> bool {
+    entries >= 0xFFFF || cd_offset >= ZIP64_LIMIT || cd_size >= ZIP64_LIMIT
+}
+
+impl ZipStream {
+    pub fn new() -> Self {
+        ZipStream {
+            entries: Vec::new(),
+            offset: 0,
+            crc: Crc::new(),
+        }
+    }
+
+    /// the length of an archive holding entries of the given names and sizes
+    pub fn archive_length(entries: &[(String, u64)]) -> u64 {
+        let mut offset = 0;
+        let mut cd_size = 0;
+
+        for (name, size) in entries {
+            cd_size += central_header_length(name, *size, offset);
+            offset += local_header_length(name, *size) + size + descriptor_length(*size);
+        }
+
+        offset + cd_size + end_length(entries.len(), offset, cd_size)
+    }
+
+    /// the local header of a new entry
+    pub fn begin_entry(&mut self, name: &str, size: u64) -> Vec<u8> {
+        let entry = Entry {
+            name: name.to_string(),
+            size: size,
+            offset: self.offset,
+            crc: 0,
+        };
+
+        let mut buf = Vec::with_capacity(local_header_length(name, size) as usize);
+
+        buf.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        buf.extend_from_slice(&(if entry.zip64() { 45u16 } else { 20u16 }).to_le_bytes());
+        buf.extend_from_slice(&FLAG_DATA_DESCRIPTOR.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes()); //stored
+        buf.extend_from_slice(&DOS_TIME.to_le_bytes());
+        buf.extend_from_slice(&DOS_DATE.to_le_bytes());
+        buf.extend_from_slice(&0u32.to_le_bytes()); //the CRC-32 goes into the data descriptor
+
+        let sizes = if entry.zip64() { 0xFFFF_FFFFu32 } else { 0 };
+        buf.extend_from_slice(&sizes.to_le_bytes());
+        buf.extend_from_slice(&sizes.to_le_bytes());
+
+        buf.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        buf.extend_from_slice(&(if entry.zip64() { 20u16 } else { 0u16 }).to_le_bytes());
+        buf.extend_from_slice(name.as_bytes());
+
+        if entry.zip64() {
+            buf.extend_from_slice(&0x0001u16.to_le_bytes());
+            buf.extend_from_slice(&16u16.to_le_bytes());
+            buf.extend_from_slice(&0u64.to_le_bytes());
+            buf.extend_from_slice(&0u64.to_le_bytes());
+        }
+
+        self.offset += buf.len() as u64;
+        self.crc.reset();
+        self.entries.push(entry);
+
+        buf
+    }
+
+    /// the entry data, checksummed on its way into the archive
+    pub fn data(&mut self, bytes: &[u8]) {
+        self.crc.update(bytes);
+        self.offset += bytes.len() as u64;
+    }
+
+    /// the data descriptor closing the current entry
+    pub fn end_entry(&mut self) -> Vec<u8> {
+        let crc = self.crc.sum();
+
+        let entry = match self.entries.last_mut() {
+            Some(entry) => entry,
+            None => return Vec::new(),
+        };
+
+        entry.crc = crc;
+
+        let mut buf = Vec::with_capacity(descriptor_length(entry.size) as usize);
+
+        buf.extend_from_slice(&0x08074b50u32.to_le_bytes());
+        buf.extend_from_slice(&crc.to_le_bytes());
+
+        if entry.zip64() {
+            buf.extend_from_slice(&entry.size.to_le_bytes());
+            buf.extend_from_slice(&entry.size.to_le_bytes());
+        } else {
+            buf.extend_from_slice(&(entry.size as u32).to_le_bytes());
+            buf.extend_from_slice(&(entry.size as u32).to_le_bytes());
+        }
+
+        self.offset += buf.len() as u64;
+
+        buf
+    }
+
+    /// a complete entry: the local header, the data and the data descriptor
+    pub fn entry(&mut self, name: &str, bytes: &[u8]) -> Vec<u8> {
+        let mut buf = self.begin_entry(name, bytes.len() as u64);
+        self.data(bytes);
+        buf.extend_from_slice(bytes);
+        buf.extend_from_slice(&self.end_entry());
+        buf
+    }
+
+    /// the central directory and the end of the archive
+    pub fn finish(&mut self) -> Vec<u8> {
+        let cd_offset = self.offset;
+        let mut buf = Vec::new();
+
+        for entry in &self.entries {
+            let mut extra: Vec<u8> = Vec::new();
+
+            if entry.zip64() {
+                extra.extend_from_slice(&entry.size.to_le_bytes());
+                extra.extend_from_slice(&entry.size.to_le_bytes());
+            }
+
+            if entry.offset >= ZIP64_LIMIT {
+                extra.extend_from_slice(&entry.offset.to_le_bytes());
+            }
+
+            let zip64 = !extra.is_empty();
+            let size = if entry.zip64() {
+                0xFFFF_FFFF
+            } else {
+                entry.size as u32
+            };
+            let offset = entry.offset.min(ZIP64_LIMIT) as u32;
+            let version: u16 = if zip64 { 45 } else { 20 };
+
+            buf.extend_from_slice(&0x02014b50u32.to_le_bytes());
+            buf.extend_from_slice(&version.to_le_bytes()); //made by
+            buf.extend_from_slice(&version.to_le_bytes()); //needed to extract
+            buf.extend_from_slice(&FLAG_DATA_DESCRIPTOR.to_le_bytes());
+            buf.extend_from_slice(&0u16.to_le_bytes());
+            buf.extend_from_slice(&DOS_TIME.to_le_bytes());
+            buf.extend_from_slice(&DOS_DATE.to_le_bytes());
+            buf.extend_from_slice(&entry.crc.to_le_bytes());
+            buf.extend_from_slice(&size.to_le_bytes());
+            buf.extend_from_slice(&size.to_le_bytes());
+            buf.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            buf.extend_from_slice(&(if zip64 { 4 + extra.len() as u16 } else { 0 }).to_le_bytes());
+            buf.extend_from_slice(&0u16.to_le_bytes()); //comment
+            buf.extend_from_slice(&0u16.to_le_bytes()); //disk
+            buf.extend_from_slice(&0u16.to_le_bytes()); //internal attributes
+            buf.extend_from_slice(&0u32.to_le_bytes()); //external attributes
+            buf.extend_from_slice(&offset.to_le_bytes());
+            buf.extend_from_slice(entry.name.as_bytes());
+
+            if zip64 {
+                buf.extend_from_slice(&0x0001u16.to_le_bytes());
+                buf.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+                buf.extend_from_slice(&extra);
+            }
+        }
+
+        let cd_size = buf.len() as u64;
+        let count = self.entries.len();
+
+        if needs_zip64_end(count, cd_offset, cd_size) {
+            let end64_offset = cd_offset + cd_size;
+
+            buf.extend_from_slice(&0x06064b50u32.to_le_bytes());
+            buf.extend_from_slice(&44u64.to_le_bytes());
+            buf.extend_from_slice(&45u16.to_le_bytes());
+            buf.extend_from_slice(&45u16.to_le_bytes());
+            buf.extend_from_slice(&0u32.to_le_bytes());
+            buf.extend_from_slice(&0u32.to_le_bytes());
+            buf.extend_from_slice(&(count as u64).to_le_bytes());
+            buf.extend_from_slice(&(count as u64).to_le_bytes());
+            buf.extend_from_slice(&cd_size.to_le_bytes());
+            buf.extend_from_slice(&cd_offset.to_le_bytes());
+
+            buf.extend_from_slice(&0x07064b50u32.to_le_bytes());
+            buf.extend_from_slice(&0u32.to_le_bytes());
+            buf.extend_from_slice(&end64_offset.to_le_bytes());
+            buf.extend_from_slice(&1u32.to_le_bytes());
+        }
+
+        buf.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+        buf.extend_from_slice(&(count.min(0xFFFF) as u16).to_le_bytes());
+        buf.extend_from_slice(&(count.min(0xFFFF) as u16).to_le_bytes());
+        buf.extend_from_slice(&(cd_size.min(ZIP64_LIMIT) as u32).to_le_bytes());
+        buf.extend_from_slice(&(cd_offset.min(ZIP64_LIMIT) as u32).to_le_bytes());
+        buf.extend_from_slice(&0u16.to_le_bytes());
+
+        self.offset += buf.len() as u64;
+
+        buf
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(buf: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([buf[offset], buf[offset + 1]])
+    }
+
+    fn u32_at(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(buf: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = Crc::new();
+        crc.update(bytes);
+        crc.sum()
+    }
+
+    #[test]
+    fn stored_archive() {
+        let mut zip = ZipStream::new();
+
+        let mut archive = zip.entry("a.txt", b"hello");
+
+        //an entry streamed in parts
+        archive.extend_from_slice(&zip.begin_entry("b.bin", 6));
+        for part in [&b"wor"[..], b"ld!"] {
+            zip.data(part);
+            archive.extend_from_slice(part);
+        }
+        archive.extend_from_slice(&zip.end_entry());
+
+        let cd_offset = archive.len();
+        archive.extend_from_slice(&zip.finish());
+
+        let entries = [(String::from("a.txt"), 5), (String::from("b.bin"), 6)];
+        assert_eq!(archive.len() as u64, ZipStream::archive_length(&entries));
+
+        //the first local header and its data descriptor
+        assert_eq!(u32_at(&archive, 0), 0x04034b50);
+        assert_eq!(u16_at(&archive, 26), 5);
+        assert_eq!(&archive[30..35], b"a.txt");
+        assert_eq!(&archive[35..40], b"hello");
+        assert_eq!(u32_at(&archive, 40), 0x08074b50);
+        assert_eq!(u32_at(&archive, 44), 0x3610a686);
+        assert_eq!(u32_at(&archive, 48), 5);
+
+        //the central directory
+        assert_eq!(u32_at(&archive, cd_offset), 0x02014b50);
+        assert_eq!(u32_at(&archive, cd_offset + 16), 0x3610a686);
+        assert_eq!(u32_at(&archive, cd_offset + 42), 0);
+
+        let second = cd_offset + 46 + 5;
+        assert_eq!(u32_at(&archive, second), 0x02014b50);
+        assert_eq!(u32_at(&archive, second + 16), crc32(b"world!"));
+        assert_eq!(u32_at(&archive, second + 42), 56);
+
+        //the end of the central directory
+        let end = archive.len() - 22;
+        assert_eq!(u32_at(&archive, end), 0x06054b50);
+        assert_eq!(u16_at(&archive, end + 10), 2);
+        assert_eq!(u32_at(&archive, end + 12) as usize, end - cd_offset);
+        assert_eq!(u32_at(&archive, end + 16) as usize, cd_offset);
+    }
+
+    #[test]
+    fn zip64_entries() {
+        let size = 5 * (1u64 << 30);
+        let mut zip = ZipStream::new();
+
+        let header = zip.begin_entry("data.npy", size);
+        assert_eq!(header.len() as u64, local_header_length("data.npy", size));
+        assert_eq!(u16_at(&header, 4), 45);
+        assert_eq!(u32_at(&header, 18), 0xFFFF_FFFF);
+        assert_eq!(u16_at(&header, 28), 20);
+
+        //skip writing the data itself
+        zip.offset += size;
+
+        let descriptor = zip.end_entry();
+        assert_eq!(descriptor.len(), 24);
+        assert_eq!(u64_at(&descriptor, 8), size);
+        assert_eq!(u64_at(&descriptor, 16), size);
+
+        //an entry beyond 4 GiB needs a ZIP64 offset
+        let small = zip.entry("header.npy", b"{}");
+        let cd_offset = zip.offset;
+        let end = zip.finish();
+
+        let entries = [
+            (String::from("data.npy"), size),
+            (String::from("header.npy"), 2),
+        ];
+        let length = header.len() as u64 + size + 24 + small.len() as u64 + end.len() as u64;
+        assert_eq!(ZipStream::archive_length(&entries), length);
+
+        //the central directory records: sizes in the first, the offset in the second
+        assert_eq!(u32_at(&end, 20), 0xFFFF_FFFF);
+        assert_eq!(u16_at(&end, 30), 20);
+        assert_eq!(u64_at(&end, 46 + 8 + 4), size);
+
+        let second = 46 + 8 + 20;
+        assert_eq!(u32_at(&end, second + 42), 0xFFFF_FFFF);
+        assert_eq!(u16_at(&end, second + 30), 12);
+        assert_eq!(
+            u64_at(&end, second + 46 + 10 + 4),
+            header.len() as u64 + size + 24
+        );
+
+        //the ZIP64 end record, its locator and the classic end record
+        let cd_size = (second + 46 + 10 + 12) as u64;
+        let end64 = cd_size as usize;
+        assert_eq!(u32_at(&end, end64), 0x06064b50);
+        assert_eq!(u64_at(&end, end64 + 40), cd_size);
+        assert_eq!(u64_at(&end, end64 + 48), cd_offset);
+        assert_eq!(u32_at(&end, end64 + 56), 0x07064b50);
+        assert_eq!(u64_at(&end, end64 + 64), cd_offset + cd_size);
+        assert_eq!(u32_at(&end, end64 + 76), 0x06054b50);
+        assert_eq!(u32_at(&end, end64 + 92), 0xFFFF_FFFF);
+        assert_eq!(end.len(), end64 + 98);
+    }
+
+    #[test]
+    fn zip64_entry_count() {
+        let entries: Vec<(String, u64)> = (0..0x10000).map(|i| (format!("{}", i), 0)).collect();
+        let mut zip = ZipStream::new();
+        let mut length = 0;
+
+        for (name, _) in &entries {
+            length += zip.entry(name, b"").len();
+        }
+
+        let end = zip.finish();
+        length += end.len();
+
+        assert_eq!(ZipStream::archive_length(&entries), length as u64);
+        assert_eq!(u32_at(&end, end.len() - 98), 0x06064b50);
+        assert_eq!(u16_at(&end, end.len() - 12), 0xFFFF);
+    }
+}